@@ -898,6 +898,22 @@ if __name__ == "__main__":
       dest="features",
       help=("Compiles the OpenSK application with support for nfc."),
   )
+  main_parser.add_argument(
+      "--secure-element",
+      action="append_const",
+      const="with_secure_element",
+      dest="features",
+      help=("Compiles the OpenSK application with the attestation key "
+            "generated and used inside an I2C secure element."),
+  )
+  main_parser.add_argument(
+      "--external-flash",
+      action="append_const",
+      const="with_external_flash",
+      dest="features",
+      help=("Compiles the OpenSK application with the resident credentials "
+            "stored in an external SPI flash, when the board has one."),
+  )
   main_parser.add_argument(
       "--regen-keys",
       action="store_true",
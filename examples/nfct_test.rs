@@ -21,11 +21,11 @@ mod example {
 mod example {
     use super::Console;
     use super::Write;
-    use libtock_core::result::CommandError;
+    use libtock_drivers::nfc::NfcEmulationGuard;
+    use libtock_drivers::nfc::NfcError;
     use libtock_drivers::nfc::NfcTag;
     use libtock_drivers::nfc::RecvOp;
     use libtock_drivers::result::FlexUnwrap;
-    use libtock_drivers::result::TockError;
     use libtock_drivers::timer;
     use libtock_drivers::timer::Timer;
     use libtock_drivers::timer::Timestamp;
@@ -89,10 +89,7 @@ mod example {
         let start = Timestamp::<f64>::from_clock_value(timer.get_current_clock().flex_unwrap());
         match NfcTag::transmit(&mut buf, amount) {
             Ok(_) => (),
-            Err(TockError::Command(CommandError {
-                return_code: -8, /* ECANCEL: No Field*/
-                ..
-            })) => return ReturnCode::ECANCEL,
+            Err(NfcError::FieldLost) => return ReturnCode::ECANCEL,
             Err(_) => writeln!(Console::new(), " -- tx error!").unwrap(),
         }
         let end = Timestamp::<f64>::from_clock_value(timer.get_current_clock().flex_unwrap());
@@ -110,18 +107,20 @@ mod example {
         ReturnCode::SUCCESS
     }
 
-    fn receive_packet(console: &mut Console, mut buf: &mut [u8; 256]) -> ReturnCode {
-        match NfcTag::receive(&mut buf) {
+    fn receive_packet(console: &mut Console, buf: &mut [u8]) -> ReturnCode {
+        let max_frame_size = buf.len();
+        match NfcTag::receive(buf, max_frame_size) {
             Ok(RecvOp {
                 recv_amount: amount,
                 ..
-            }) => {
-                if amount <= buf.len() {
-                    print_rx_buffer(&mut buf[..amount]);
-                }
-            }
-            Err(TockError::Command(CommandError { return_code, .. })) => return return_code.into(),
-            Err(_) => {
+            }) => print_rx_buffer(&mut buf[..amount]),
+            Err(NfcError::EmulationDisabled) => return ReturnCode::EOFF,
+            Err(NfcError::FieldLost) => return ReturnCode::ECANCEL,
+            Err(NfcError::Busy) => return ReturnCode::EBUSY,
+            Err(NfcError::BufferTooSmall) => return ReturnCode::ENOMEM,
+            Err(NfcError::InvalidArgument) => return ReturnCode::EINVAL,
+            Err(NfcError::Kernel(return_code)) => return return_code.into(),
+            Err(NfcError::DriverMissing) | Err(NfcError::Timeout) | Err(NfcError::Aborted) => {
                 writeln!(console, " -- RX Err").unwrap();
                 return ReturnCode::ECANCEL;
             }
@@ -201,17 +200,21 @@ mod example {
         .unwrap();
 
         let mut state_change_counter = 0;
+        let mut emulation = None;
         loop {
             let mut rx_buf = [0; 256];
             match receive_packet(&mut console, &mut rx_buf) {
                 ReturnCode::EOFF => {
                     // Not configured
-                    while !NfcTag::enable_emulation() {}
+                    emulation = None;
+                    while emulation.is_none() {
+                        emulation = NfcEmulationGuard::new().ok();
+                    }
                     // Configure Type 4 tag
-                    while !NfcTag::configure(4) {}
+                    while NfcTag::configure(4).is_err() {}
                 }
                 ReturnCode::ECANCEL /* field lost */ => {
-                    NfcTag::disable_emulation();
+                    emulation = None;
                 }
                 ReturnCode::EBUSY /* awaiting select*/ => (),
                 ReturnCode::ENOMEM => {
@@ -224,7 +227,7 @@ mod example {
                     // If the reader restarts the communication then disable the tag.
                     match transmit_reply(&mut console, &timer, &rx_buf) {
                         ReturnCode::ECANCEL | ReturnCode::EOFF => {
-                            if NfcTag::disable_emulation() {
+                            if emulation.take().is_some() {
                                 writeln!(console, " -- TAG DISABLED").unwrap();
                             }
                             state_change_counter += 1;
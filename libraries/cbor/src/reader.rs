@@ -98,6 +98,15 @@ impl<'a> Reader<'a> {
         }
     }
 
+    // Lengths are 64 bit in CBOR, and must not be truncated on platforms with a shorter usize.
+    fn read_content_bytes(&mut self, size_value: u64) -> Option<&[u8]> {
+        if size_value > self.remaining_cbor.len() as u64 {
+            None
+        } else {
+            self.read_bytes(size_value as usize)
+        }
+    }
+
     fn read_variadic_length_integer(&mut self, additional_info: u8) -> Result<u64, DecoderError> {
         let additional_bytes_num = match additional_info {
             0..=Constants::ADDITIONAL_INFORMATION_MAX_INT => return Ok(additional_info as u64),
@@ -140,14 +149,14 @@ impl<'a> Reader<'a> {
     }
 
     fn read_byte_string_content(&mut self, size_value: u64) -> Result<Value, DecoderError> {
-        match self.read_bytes(size_value as usize) {
+        match self.read_content_bytes(size_value) {
             Some(bytes) => Ok(cbor_bytes_lit!(bytes)),
             None => Err(DecoderError::IncompleteCborData),
         }
     }
 
     fn read_text_string_content(&mut self, size_value: u64) -> Result<Value, DecoderError> {
-        match self.read_bytes(size_value as usize) {
+        match self.read_content_bytes(size_value) {
             Some(bytes) => match str::from_utf8(bytes) {
                 Ok(s) => Ok(cbor_text!(s)),
                 Err(_) => Err(DecoderError::InvalidUtf8),
@@ -792,12 +801,57 @@ mod test {
         let cases = vec![
             vec![0x9B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
             vec![0xBB, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+            // The lengths would be 4 if truncated to 32 bit.
+            vec![
+                0x5B, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x04, 0x01, 0x02, 0x03, 0x04,
+            ],
+            vec![
+                0x7B, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x04, 0x49, 0x45, 0x54, 0x46,
+            ],
         ];
         for cbor in cases {
             assert_eq!(read(&cbor), Err(DecoderError::IncompleteCborData));
         }
     }
 
+    #[test]
+    fn test_read_indefinite_length_error() {
+        let cases = vec![
+            // Byte string with a single chunk and a break.
+            vec![0x5F, 0x41, 0x01, 0xFF],
+            // Text string with a single chunk and a break.
+            vec![0x7F, 0x61, 0x61, 0xFF],
+            // Array containing 1 and a break.
+            vec![0x9F, 0x01, 0xFF],
+            // Map from 1 to 2 and a break.
+            vec![0xBF, 0x01, 0x02, 0xFF],
+        ];
+        for cbor in cases {
+            assert_eq!(read(&cbor), Err(DecoderError::UnknownAdditionalInfo));
+        }
+    }
+
+    #[test]
+    fn test_read_non_minimal_length_error() {
+        let cases = vec![
+            // Byte string of length 1 with a 1 byte length.
+            vec![0x58, 0x01, 0x01],
+            // Text string of length 1 with a 2 byte length.
+            vec![0x79, 0x00, 0x01, 0x61],
+            // Array of length 1 with a 4 byte length.
+            vec![0x9A, 0x00, 0x00, 0x00, 0x01, 0x01],
+            // Map of length 1 with an 8 byte length.
+            vec![
+                0xBB, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0x02,
+            ],
+            // Simple value true with a 1 byte value.
+            vec![0xF8, 0x15],
+        ];
+        for cbor in cases {
+            assert_eq!(read(&cbor), Err(DecoderError::NonMinimalCborEncoding));
+        }
+    }
+
     #[test]
     fn test_read_unsupported_major_type() {
         let cases = vec![
@@ -0,0 +1,133 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Dispatches primitives to a hardware crypto accelerator, if the kernel has a driver for one.
+//
+// Each function returns whether the accelerator computed the result. Otherwise, the caller falls
+// back to its software implementation. Tests on the desktop always use the software.
+
+use core::num::Wrapping;
+use core::sync::atomic::{AtomicU8, Ordering};
+#[cfg(not(feature = "std"))]
+use libtock_drivers::crypto_cell as driver;
+
+const UNKNOWN: u8 = 0;
+const AVAILABLE: u8 = 1;
+const MISSING: u8 = 2;
+
+// The kernel is asked only once whether it has a driver.
+static AVAILABILITY: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+pub fn is_available() -> bool {
+    match AVAILABILITY.load(Ordering::Relaxed) {
+        AVAILABLE => true,
+        MISSING => false,
+        _ => {
+            let available = driver::is_available();
+            let availability = if available { AVAILABLE } else { MISSING };
+            AVAILABILITY.store(availability, Ordering::Relaxed);
+            available
+        }
+    }
+}
+
+pub fn sha256_block(state: &mut [Wrapping<u32>; 8], block: &[u8; 64]) -> bool {
+    if !is_available() {
+        return false;
+    }
+    let mut words = [0; 8];
+    for (word, state_word) in words.iter_mut().zip(state.iter()) {
+        *word = state_word.0;
+    }
+    if !driver::sha256_compress(&mut words, block) {
+        return false;
+    }
+    for (state_word, word) in state.iter_mut().zip(words.iter()) {
+        *state_word = Wrapping(*word);
+    }
+    true
+}
+
+pub fn aes256_encrypt_block(key: &[u8; 32], block: &mut [u8; 16]) -> bool {
+    is_available() && driver::aes256_encrypt_block(key, block)
+}
+
+pub fn aes256_decrypt_block(key: &[u8; 32], block: &mut [u8; 16]) -> bool {
+    is_available() && driver::aes256_decrypt_block(key, block)
+}
+
+// Computes the multiplication of the base point, or of the given point, by the scalar. Points are
+// their big endian affine coordinates x and y. The caller must check that the result is on the
+// curve, so that a faulty accelerator can't produce invalid keys.
+pub fn p256_mul(scalar: &[u8; 32], base: Option<&[u8; 64]>, point: &mut [u8; 64]) -> bool {
+    if !is_available() {
+        return false;
+    }
+    match base {
+        None => driver::p256_base_point_mul(scalar, point),
+        Some(base) => {
+            point.copy_from_slice(base);
+            driver::p256_point_mul(scalar, point)
+        }
+    }
+}
+
+// Desktop builds have no kernel, and therefore no accelerator.
+#[cfg(feature = "std")]
+mod driver {
+    pub fn is_available() -> bool {
+        false
+    }
+
+    pub fn sha256_compress(_state: &mut [u32; 8], _block: &[u8; 64]) -> bool {
+        false
+    }
+
+    pub fn aes256_encrypt_block(_key: &[u8; 32], _block: &mut [u8; 16]) -> bool {
+        false
+    }
+
+    pub fn aes256_decrypt_block(_key: &[u8; 32], _block: &mut [u8; 16]) -> bool {
+        false
+    }
+
+    pub fn p256_base_point_mul(_scalar: &[u8; 32], _point: &mut [u8; 64]) -> bool {
+        false
+    }
+
+    pub fn p256_point_mul(_scalar: &[u8; 32], _point: &mut [u8; 64]) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_software_fallback() {
+        assert!(!is_available());
+        let mut state = [Wrapping(0x55); 8];
+        assert!(!sha256_block(&mut state, &[0xAA; 64]));
+        // The state is unchanged, for the software implementation to compute.
+        assert_eq!(state, [Wrapping(0x55); 8]);
+        let mut block = [0xAA; 16];
+        assert!(!aes256_encrypt_block(&[0x55; 32], &mut block));
+        assert!(!aes256_decrypt_block(&[0x55; 32], &mut block));
+        assert_eq!(block, [0xAA; 16]);
+        let mut point = [0; 64];
+        assert!(!p256_mul(&[0x55; 32], None, &mut point));
+        assert!(!p256_mul(&[0x55; 32], Some(&[0xAA; 64]), &mut point));
+    }
+}
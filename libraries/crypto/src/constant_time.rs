@@ -0,0 +1,42 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use subtle::ConstantTimeEq;
+
+// Compares secret byte strings, like MACs or PIN hashes, without exiting at the first differing
+// byte. The time only depends on the lengths, which are not secret: slices of different lengths
+// are unequal.
+pub fn bytes_eq(a: &[u8], b: &[u8]) -> bool {
+    bool::from(a.ct_eq(b))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bytes_eq() {
+        assert!(bytes_eq(&[], &[]));
+        assert!(bytes_eq(&[0x01, 0x02, 0x03], &[0x01, 0x02, 0x03]));
+        assert!(!bytes_eq(&[0x01, 0x02, 0x03], &[0x01, 0x02, 0x04]));
+        assert!(!bytes_eq(&[0x01, 0x02, 0x03], &[0xFF, 0x02, 0x03]));
+    }
+
+    #[test]
+    fn test_bytes_eq_different_lengths() {
+        assert!(!bytes_eq(&[0x01, 0x02], &[0x01, 0x02, 0x03]));
+        assert!(!bytes_eq(&[0x01, 0x02, 0x03], &[0x01, 0x02]));
+        assert!(!bytes_eq(&[], &[0x00]));
+    }
+}
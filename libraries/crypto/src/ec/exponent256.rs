@@ -13,8 +13,9 @@
 // limitations under the License.
 
 use super::super::rng256::Rng256;
+use super::super::zeroize::Zeroize;
 use super::int256::{Digit, Int256};
-use core::ops::Mul;
+use core::ops::{Mul, Sub};
 use subtle::{self, Choice, ConditionallySelectable, CtOption};
 
 // An exponent on the elliptic curve, that is an element modulo the curve order N.
@@ -35,6 +36,12 @@ impl ConditionallySelectable for ExponentP256 {
     }
 }
 
+impl Zeroize for ExponentP256 {
+    fn zeroize(&mut self) {
+        self.int.zeroize();
+    }
+}
+
 impl ExponentP256 {
     /** Constructors **/
     pub fn from_int_checked(int: Int256) -> CtOption<ExponentP256> {
@@ -91,6 +98,19 @@ impl Mul for &ExponentP256 {
     }
 }
 
+impl Sub for &ExponentP256 {
+    type Output = ExponentP256;
+
+    // Constant-time: N is added back whether or not the subtraction borrowed.
+    fn sub(self, other: &ExponentP256) -> ExponentP256 {
+        let (difference, borrow) = &self.int - &other.int;
+        let (wrapped, _) = &difference + &Int256::N;
+        ExponentP256 {
+            int: Int256::conditional_select(&difference, &wrapped, Choice::from(borrow as u8 & 1)),
+        }
+    }
+}
+
 // A non-zero exponent on the elliptic curve.
 #[derive(Clone, Copy, PartialEq, Eq)]
 // TODO: remove this Default once https://github.com/dalek-cryptography/subtle/issues/63 is
@@ -109,6 +129,12 @@ impl ConditionallySelectable for NonZeroExponentP256 {
     }
 }
 
+impl Zeroize for NonZeroExponentP256 {
+    fn zeroize(&mut self) {
+        self.e.zeroize();
+    }
+}
+
 impl NonZeroExponentP256 {
     /** RNG **/
     // Generates a uniformly distributed element 0 < k < N
@@ -296,6 +322,20 @@ pub mod test {
         assert_eq!(N_MIN_1.inv(), N_MIN_1);
     }
 
+    /** Arithmetic operations: subtraction **/
+    #[test]
+    fn test_sub() {
+        let values = get_test_values();
+        for x in &values {
+            assert_eq!(x - x, ZERO);
+            assert_eq!(&ZERO - x, ExponentP256::modn((&Int256::N - &x.int).0));
+            for y in &values {
+                assert_eq!(&(x - y) - &(x - &ZERO), &ZERO - y);
+            }
+        }
+        assert_eq!(&ZERO - ONE.as_exponent(), *N_MIN_1.as_exponent());
+    }
+
     /** RNG **/
     // Mock rng that samples through a list of values, then panics.
     struct StressTestingRng {
@@ -0,0 +1,156 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Arithmetic in GF(2^255 - 19), the field of Curve25519, shared by Ed25519 and X25519.
+//
+// The representation follows TweetNaCl. All operations are constant-time, except the ones
+// suffixed with _vartime.
+
+use arrayref::array_ref;
+
+// An element of GF(2^255 - 19), as 16 signed limbs of 16 bits in little-endian order.
+#[derive(Clone, Copy)]
+pub struct Fe(pub [i64; 16]);
+
+impl Fe {
+    pub const ZERO: Fe = Fe([0; 16]);
+    pub const ONE: Fe = Fe([1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+    pub fn from_bytes(bytes: &[u8; 32]) -> Fe {
+        let mut limbs = [0; 16];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb = bytes[2 * i] as i64 + ((bytes[2 * i + 1] as i64) << 8);
+        }
+        limbs[15] &= 0x7fff;
+        Fe(limbs)
+    }
+
+    // Returns the canonical encoding, fully reduced modulo 2^255 - 19.
+    pub fn to_bytes(self) -> [u8; 32] {
+        let mut t = self;
+        t.carry();
+        t.carry();
+        t.carry();
+        let mut m = Fe::ZERO;
+        for _ in 0..2 {
+            m.0[0] = t.0[0] - 0xffed;
+            for i in 1..15 {
+                m.0[i] = t.0[i] - 0xffff - ((m.0[i - 1] >> 16) & 1);
+                m.0[i - 1] &= 0xffff;
+            }
+            m.0[15] = t.0[15] - 0x7fff - ((m.0[14] >> 16) & 1);
+            let borrow = (m.0[15] >> 16) & 1;
+            m.0[14] &= 0xffff;
+            Fe::cswap(&mut t, &mut m, 1 - borrow);
+        }
+        let mut bytes = [0; 32];
+        for i in 0..16 {
+            bytes[2 * i] = t.0[i] as u8;
+            bytes[2 * i + 1] = (t.0[i] >> 8) as u8;
+        }
+        bytes
+    }
+
+    // Propagates the carries so that each limb fits in 16 bits.
+    pub fn carry(&mut self) {
+        for i in 0..16 {
+            self.0[i] += 1 << 16;
+            let c = self.0[i] >> 16;
+            if i < 15 {
+                self.0[i + 1] += c - 1;
+            } else {
+                self.0[0] += 38 * (c - 1);
+            }
+            self.0[i] -= c << 16;
+        }
+    }
+
+    // Swaps a and b in constant time if bit is 1, and leaves them unchanged if bit is 0.
+    pub fn cswap(a: &mut Fe, b: &mut Fe, bit: i64) {
+        let mask = !(bit - 1);
+        for i in 0..16 {
+            let t = mask & (a.0[i] ^ b.0[i]);
+            a.0[i] ^= t;
+            b.0[i] ^= t;
+        }
+    }
+
+    pub fn add(&self, other: &Fe) -> Fe {
+        let mut result = Fe::ZERO;
+        for i in 0..16 {
+            result.0[i] = self.0[i] + other.0[i];
+        }
+        result
+    }
+
+    pub fn sub(&self, other: &Fe) -> Fe {
+        let mut result = Fe::ZERO;
+        for i in 0..16 {
+            result.0[i] = self.0[i] - other.0[i];
+        }
+        result
+    }
+
+    pub fn mul(&self, other: &Fe) -> Fe {
+        let mut t = [0i64; 31];
+        for i in 0..16 {
+            for j in 0..16 {
+                t[i + j] += self.0[i] * other.0[j];
+            }
+        }
+        for i in 0..15 {
+            t[i] += 38 * t[i + 16];
+        }
+        let mut result = Fe(*array_ref!(t, 0, 16));
+        result.carry();
+        result.carry();
+        result
+    }
+
+    pub fn square(&self) -> Fe {
+        self.mul(self)
+    }
+
+    // Computes the inverse as self^(p - 2).
+    pub fn invert(&self) -> Fe {
+        let mut c = *self;
+        for i in (0..254).rev() {
+            c = c.square();
+            if i != 2 && i != 4 {
+                c = c.mul(self);
+            }
+        }
+        c
+    }
+
+    // Computes self^((p - 5) / 8), used to compute square roots.
+    pub fn pow2523(&self) -> Fe {
+        let mut c = *self;
+        for i in (0..251).rev() {
+            c = c.square();
+            if i != 1 {
+                c = c.mul(self);
+            }
+        }
+        c
+    }
+
+    pub fn parity(&self) -> u8 {
+        self.to_bytes()[0] & 1
+    }
+
+    pub fn equals_vartime(&self, other: &Fe) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+}
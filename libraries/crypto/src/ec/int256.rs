@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use super::super::rng256::Rng256;
+use super::super::zeroize::Zeroize;
 use alloc::vec;
 use alloc::vec::Vec;
 use arrayref::{array_mut_ref, array_ref};
@@ -50,6 +51,12 @@ impl ConditionallySelectable for Int256 {
 /** Arithmetic operations on the secp256r1 field, where elements are represented as 8 digits of
  * 32 bits. **/
 #[allow(clippy::unreadable_literal)]
+impl Zeroize for Int256 {
+    fn zeroize(&mut self) {
+        self.digits.zeroize();
+    }
+}
+
 impl Int256 {
     /** Constants for the secp256r1 curve. **/
     // Curve order (prime)
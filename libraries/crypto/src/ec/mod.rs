@@ -13,8 +13,10 @@
 // limitations under the License.
 
 pub mod exponent256;
+pub mod gf25519;
 mod gfp256;
 pub mod int256;
 mod montgomery;
+pub mod p384;
 pub mod point;
 mod precomputed;
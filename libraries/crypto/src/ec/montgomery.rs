@@ -105,9 +105,9 @@ impl Montgomery {
         Montgomery { limbs }
     }
 
-    // This contains two tables of 15 points, each represented by its x and y coordinates in
+    // This contains four tables of 15 points, each represented by its x and y coordinates in
     // Montgomery form.
-    pub const PRECOMPUTED: [[[Montgomery; 2]; 15]; 2] = precomputed::PRECOMPUTED;
+    pub const PRECOMPUTED: [[[Montgomery; 2]; 15]; 4] = precomputed::PRECOMPUTED;
 
     /** Conversion to/from Montgomery form **/
     pub fn field_to_montgomery(gf: &GFP256) -> Montgomery {
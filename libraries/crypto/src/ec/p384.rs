@@ -0,0 +1,680 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Arithmetic on the secp384r1 curve.
+//
+// Both the field elements and the scalars use 6 little-endian limbs of 64 bits in Montgomery form,
+// with R = 2^384. Points use projective coordinates with the complete addition formulas of Renes,
+// Costello and Batina (https://eprint.iacr.org/2015/1060, algorithm 4), so that additions and
+// doublings run the same code without exceptional cases.
+
+use arrayref::{array_mut_ref, array_ref};
+use byteorder::{BigEndian, ByteOrder};
+
+pub const NBYTES: usize = 48;
+const NLIMBS: usize = 6;
+
+type Limbs = [u64; NLIMBS];
+
+// A prime modulus, with the constants used for Montgomery multiplication.
+struct Modulus {
+    m: Limbs,
+    // -m^-1 mod 2^64
+    m_inv: u64,
+    // R^2 mod m, to convert into the Montgomery domain.
+    r2: Limbs,
+    // m - 2, the exponent for inversion with Fermat's little theorem.
+    m_minus_2: Limbs,
+}
+
+#[allow(clippy::unreadable_literal)]
+const P: Modulus = Modulus {
+    m: [
+        0x00000000ffffffff,
+        0xffffffff00000000,
+        0xfffffffffffffffe,
+        0xffffffffffffffff,
+        0xffffffffffffffff,
+        0xffffffffffffffff,
+    ],
+    m_inv: 0x0000000100000001,
+    r2: [
+        0xfffffffe00000001,
+        0x0000000200000000,
+        0xfffffffe00000000,
+        0x0000000200000000,
+        0x0000000000000001,
+        0x0000000000000000,
+    ],
+    m_minus_2: [
+        0x00000000fffffffd,
+        0xffffffff00000000,
+        0xfffffffffffffffe,
+        0xffffffffffffffff,
+        0xffffffffffffffff,
+        0xffffffffffffffff,
+    ],
+};
+
+#[allow(clippy::unreadable_literal)]
+const N: Modulus = Modulus {
+    m: [
+        0xecec196accc52973,
+        0x581a0db248b0a77a,
+        0xc7634d81f4372ddf,
+        0xffffffffffffffff,
+        0xffffffffffffffff,
+        0xffffffffffffffff,
+    ],
+    m_inv: 0x6ed46089e88fdc45,
+    r2: [
+        0x2d319b2419b409a9,
+        0xff3d81e5df1aa419,
+        0xbc3e483afcb82947,
+        0xd40d49174aab1cc5,
+        0x3fb05b7a28266895,
+        0x0c84ee012b39bf21,
+    ],
+    m_minus_2: [
+        0xecec196accc52971,
+        0x581a0db248b0a77a,
+        0xc7634d81f4372ddf,
+        0xffffffffffffffff,
+        0xffffffffffffffff,
+        0xffffffffffffffff,
+    ],
+};
+
+// Returns a + b + carry, and the new carry.
+fn adc(a: u64, b: u64, carry: u64) -> (u64, u64) {
+    let t = a as u128 + b as u128 + carry as u128;
+    (t as u64, (t >> 64) as u64)
+}
+
+// Returns a - b - borrow, and the new borrow (0 or 1).
+fn sbb(a: u64, b: u64, borrow: u64) -> (u64, u64) {
+    let t = (a as u128).wrapping_sub(b as u128 + borrow as u128);
+    (t as u64, (t >> 127) as u64)
+}
+
+// Returns a + b * c + carry, and the new carry.
+fn mac(a: u64, b: u64, c: u64, carry: u64) -> (u64, u64) {
+    let t = a as u128 + (b as u128) * (c as u128) + carry as u128;
+    (t as u64, (t >> 64) as u64)
+}
+
+// Returns a if choice is 1 and b if choice is 0, in constant time.
+fn select(choice: u64, a: &Limbs, b: &Limbs) -> Limbs {
+    let mask = 0u64.wrapping_sub(choice);
+    let mut result = [0; NLIMBS];
+    for i in 0..NLIMBS {
+        result[i] = (a[i] & mask) | (b[i] & !mask);
+    }
+    result
+}
+
+// Subtracts the modulus if the value (with an extra high carry) is not smaller than it.
+fn reduce_once(a: &Limbs, carry: u64, modulus: &Modulus) -> Limbs {
+    let mut reduced = [0; NLIMBS];
+    let mut borrow = 0;
+    for i in 0..NLIMBS {
+        let (limb, b) = sbb(a[i], modulus.m[i], borrow);
+        reduced[i] = limb;
+        borrow = b;
+    }
+    // Keep a if the subtraction underflowed without the carry.
+    select(borrow & !carry & 1, a, &reduced)
+}
+
+fn add_mod(a: &Limbs, b: &Limbs, modulus: &Modulus) -> Limbs {
+    let mut sum = [0; NLIMBS];
+    let mut carry = 0;
+    for i in 0..NLIMBS {
+        let (limb, c) = adc(a[i], b[i], carry);
+        sum[i] = limb;
+        carry = c;
+    }
+    reduce_once(&sum, carry, modulus)
+}
+
+fn sub_mod(a: &Limbs, b: &Limbs, modulus: &Modulus) -> Limbs {
+    let mut difference = [0; NLIMBS];
+    let mut borrow = 0;
+    for i in 0..NLIMBS {
+        let (limb, b) = sbb(a[i], b[i], borrow);
+        difference[i] = limb;
+        borrow = b;
+    }
+    // Add the modulus back if the subtraction underflowed.
+    let mask = 0u64.wrapping_sub(borrow);
+    let mut carry = 0;
+    for (limb, m) in difference.iter_mut().zip(modulus.m.iter()) {
+        let (sum, c) = adc(*limb, m & mask, carry);
+        *limb = sum;
+        carry = c;
+    }
+    difference
+}
+
+// Computes a * b / R mod m, with the CIOS method.
+fn mont_mul(a: &Limbs, b: &Limbs, modulus: &Modulus) -> Limbs {
+    let mut t = [0u64; NLIMBS + 2];
+    for b_limb in b.iter() {
+        let mut carry = 0;
+        for j in 0..NLIMBS {
+            let (limb, c) = mac(t[j], a[j], *b_limb, carry);
+            t[j] = limb;
+            carry = c;
+        }
+        let (limb, c) = adc(t[NLIMBS], carry, 0);
+        t[NLIMBS] = limb;
+        t[NLIMBS + 1] = c;
+
+        let u = t[0].wrapping_mul(modulus.m_inv);
+        let (_, mut carry) = mac(t[0], u, modulus.m[0], 0);
+        for j in 1..NLIMBS {
+            let (limb, c) = mac(t[j], u, modulus.m[j], carry);
+            t[j - 1] = limb;
+            carry = c;
+        }
+        let (limb, c) = adc(t[NLIMBS], carry, 0);
+        t[NLIMBS - 1] = limb;
+        t[NLIMBS] = t[NLIMBS + 1] + c;
+    }
+    reduce_once(array_ref![t, 0, NLIMBS], t[NLIMBS], modulus)
+}
+
+// Computes a^exponent in the Montgomery domain. The exponent is public, so the running time may
+// depend on it.
+fn mont_pow(a: &Limbs, one: &Limbs, exponent: &Limbs, modulus: &Modulus) -> Limbs {
+    let mut result = *one;
+    for i in (0..NLIMBS * 64).rev() {
+        result = mont_mul(&result, &result, modulus);
+        if (exponent[i / 64] >> (i % 64)) & 1 == 1 {
+            result = mont_mul(&result, a, modulus);
+        }
+    }
+    result
+}
+
+fn limbs_from_bytes(bytes: &[u8; NBYTES]) -> Limbs {
+    let mut limbs = [0; NLIMBS];
+    for (i, limb) in limbs.iter_mut().enumerate() {
+        *limb = BigEndian::read_u64(array_ref![bytes, NBYTES - 8 * (i + 1), 8]);
+    }
+    limbs
+}
+
+fn limbs_to_bytes(limbs: &Limbs, bytes: &mut [u8; NBYTES]) {
+    for (i, limb) in limbs.iter().enumerate() {
+        BigEndian::write_u64(array_mut_ref![bytes, NBYTES - 8 * (i + 1), 8], *limb);
+    }
+}
+
+// Returns whether a < b, in constant time.
+fn limbs_less_than(a: &Limbs, b: &Limbs) -> bool {
+    let mut borrow = 0;
+    for i in 0..NLIMBS {
+        borrow = sbb(a[i], b[i], borrow).1;
+    }
+    borrow == 1
+}
+
+fn limbs_is_zero(a: &Limbs) -> bool {
+    a.iter().fold(0, |acc, limb| acc | limb) == 0
+}
+
+/** Element of the base field, in Montgomery form **/
+#[derive(Clone, Copy)]
+pub struct FieldElement(Limbs);
+
+#[allow(clippy::unreadable_literal)]
+impl FieldElement {
+    pub const ZERO: FieldElement = FieldElement([0; NLIMBS]);
+    // R mod p.
+    pub const ONE: FieldElement = FieldElement([
+        0xffffffff00000001,
+        0x00000000ffffffff,
+        0x0000000000000001,
+        0x0000000000000000,
+        0x0000000000000000,
+        0x0000000000000000,
+    ]);
+    const B: FieldElement = FieldElement([
+        0x081188719d412dcc,
+        0xf729add87a4c32ec,
+        0x77f2209b1920022e,
+        0xe3374bee94938ae2,
+        0xb62b21f41f022094,
+        0xcd08114b604fbff9,
+    ]);
+    const GX: FieldElement = FieldElement([
+        0x3dd0756649c0b528,
+        0x20e378e2a0d6ce38,
+        0x879c3afc541b4d6e,
+        0x6454868459a30eff,
+        0x812ff723614ede2b,
+        0x4d3aadc2299e1513,
+    ]);
+    const GY: FieldElement = FieldElement([
+        0x23043dad4b03a4fe,
+        0xa1bfa8bf7bb4a9ac,
+        0x8bade7562e83b050,
+        0xc6c3521968f4ffd9,
+        0xdd8002263969a840,
+        0x2b78abc25a15c5e9,
+    ]);
+
+    // Returns None if the value is not reduced modulo p.
+    pub fn from_bytes(bytes: &[u8; NBYTES]) -> Option<FieldElement> {
+        let limbs = limbs_from_bytes(bytes);
+        if !limbs_less_than(&limbs, &P.m) {
+            return None;
+        }
+        Some(FieldElement(mont_mul(&limbs, &P.r2, &P)))
+    }
+
+    pub fn to_bytes(&self, bytes: &mut [u8; NBYTES]) {
+        let mut one = [0; NLIMBS];
+        one[0] = 1;
+        limbs_to_bytes(&mont_mul(&self.0, &one, &P), bytes);
+    }
+
+    pub fn add(&self, other: &FieldElement) -> FieldElement {
+        FieldElement(add_mod(&self.0, &other.0, &P))
+    }
+
+    pub fn sub(&self, other: &FieldElement) -> FieldElement {
+        FieldElement(sub_mod(&self.0, &other.0, &P))
+    }
+
+    pub fn mul(&self, other: &FieldElement) -> FieldElement {
+        FieldElement(mont_mul(&self.0, &other.0, &P))
+    }
+
+    pub fn square(&self) -> FieldElement {
+        self.mul(self)
+    }
+
+    // The inverse of zero is zero.
+    pub fn invert(&self) -> FieldElement {
+        FieldElement(mont_pow(&self.0, &FieldElement::ONE.0, &P.m_minus_2, &P))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        limbs_is_zero(&self.0)
+    }
+
+    pub fn equals(&self, other: &FieldElement) -> bool {
+        self.sub(other).is_zero()
+    }
+
+    fn select(choice: u64, a: &FieldElement, b: &FieldElement) -> FieldElement {
+        FieldElement(select(choice, &a.0, &b.0))
+    }
+}
+
+/** Scalar modulo the order of the curve, in Montgomery form **/
+#[derive(Clone, Copy)]
+pub struct Scalar(Limbs);
+
+impl Scalar {
+    // Returns None if the value is zero or not reduced modulo n.
+    pub fn from_bytes_checked(bytes: &[u8; NBYTES]) -> Option<Scalar> {
+        let limbs = limbs_from_bytes(bytes);
+        if limbs_is_zero(&limbs) || !limbs_less_than(&limbs, &N.m) {
+            return None;
+        }
+        Some(Scalar(mont_mul(&limbs, &N.r2, &N)))
+    }
+
+    // Reduces any 384-bit value modulo n, as done for message hashes in ECDSA.
+    pub fn from_bytes_reduced(bytes: &[u8; NBYTES]) -> Scalar {
+        // The input is smaller than R, so the Montgomery reduction outputs a reduced value.
+        Scalar(mont_mul(&limbs_from_bytes(bytes), &N.r2, &N))
+    }
+
+    // Reduces a 512-bit value modulo n. The bias of the output is negligible for random inputs.
+    pub fn from_wide_bytes(bytes: &[u8; 64]) -> Scalar {
+        let mut high = [0; NBYTES];
+        high[NBYTES - 16..].copy_from_slice(&bytes[..16]);
+        let low = Scalar::from_bytes_reduced(array_ref![bytes, 16, NBYTES]);
+        let high = Scalar::from_bytes_reduced(&high);
+        // high * 2^384 + low, where multiplying by R^2 in Montgomery form multiplies by R.
+        Scalar(add_mod(&mont_mul(&high.0, &N.r2, &N), &low.0, &N))
+    }
+
+    pub fn to_bytes(&self, bytes: &mut [u8; NBYTES]) {
+        let mut one = [0; NLIMBS];
+        one[0] = 1;
+        limbs_to_bytes(&mont_mul(&self.0, &one, &N), bytes);
+    }
+
+    pub fn add(&self, other: &Scalar) -> Scalar {
+        Scalar(add_mod(&self.0, &other.0, &N))
+    }
+
+    pub fn mul(&self, other: &Scalar) -> Scalar {
+        Scalar(mont_mul(&self.0, &other.0, &N))
+    }
+
+    pub fn invert(&self) -> Scalar {
+        let mut one = [0; NLIMBS];
+        one[0] = 1;
+        let r = mont_mul(&one, &N.r2, &N);
+        Scalar(mont_pow(&self.0, &r, &N.m_minus_2, &N))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        limbs_is_zero(&self.0)
+    }
+
+    // Interprets an x coordinate as a scalar, reducing it modulo n.
+    pub fn from_field_element(element: &FieldElement) -> Scalar {
+        let mut bytes = [0; NBYTES];
+        element.to_bytes(&mut bytes);
+        Scalar::from_bytes_reduced(&bytes)
+    }
+
+    pub fn equals(&self, other: &Scalar) -> bool {
+        self.0 == other.0
+    }
+}
+
+/** Point on the curve, in projective coordinates **/
+#[derive(Clone, Copy)]
+pub struct Point {
+    x: FieldElement,
+    y: FieldElement,
+    z: FieldElement,
+}
+
+impl Point {
+    pub const IDENTITY: Point = Point {
+        x: FieldElement::ZERO,
+        y: FieldElement::ONE,
+        z: FieldElement::ZERO,
+    };
+
+    pub fn base_point() -> Point {
+        Point {
+            x: FieldElement::GX,
+            y: FieldElement::GY,
+            z: FieldElement::ONE,
+        }
+    }
+
+    // Returns None if the coordinates are not on the curve.
+    pub fn from_affine_vartime(x: &[u8; NBYTES], y: &[u8; NBYTES]) -> Option<Point> {
+        let x = FieldElement::from_bytes(x)?;
+        let y = FieldElement::from_bytes(y)?;
+        // y^2 = x^3 - 3x + b
+        let three_x = x.add(&x).add(&x);
+        let rhs = x.square().mul(&x).sub(&three_x).add(&FieldElement::B);
+        if !y.square().equals(&rhs) {
+            return None;
+        }
+        Some(Point {
+            x,
+            y,
+            z: FieldElement::ONE,
+        })
+    }
+
+    // Returns the affine coordinates x and y. The identity is mapped to (0, 0), which is not on
+    // the curve.
+    pub fn to_affine(&self, x: &mut [u8; NBYTES], y: &mut [u8; NBYTES]) {
+        let z_inv = self.z.invert();
+        self.x.mul(&z_inv).to_bytes(x);
+        self.y.mul(&z_inv).to_bytes(y);
+    }
+
+    pub fn affine_x(&self) -> FieldElement {
+        self.x.mul(&self.z.invert())
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.z.is_zero()
+    }
+
+    #[allow(clippy::many_single_char_names)]
+    pub fn add(&self, other: &Point) -> Point {
+        let (x1, y1, z1) = (&self.x, &self.y, &self.z);
+        let (x2, y2, z2) = (&other.x, &other.y, &other.z);
+        let b = &FieldElement::B;
+
+        let t0 = x1.mul(x2);
+        let t1 = y1.mul(y2);
+        let t2 = z1.mul(z2);
+        let t3 = x1.add(y1).mul(&x2.add(y2));
+        let t4 = t0.add(&t1);
+        let t3 = t3.sub(&t4);
+        let t4 = y1.add(z1).mul(&y2.add(z2));
+        let x3 = t1.add(&t2);
+        let t4 = t4.sub(&x3);
+        let x3 = x1.add(z1).mul(&x2.add(z2));
+        let y3 = t0.add(&t2);
+        let y3 = x3.sub(&y3);
+        let z3 = b.mul(&t2);
+        let x3 = y3.sub(&z3);
+        let z3 = x3.add(&x3);
+        let x3 = x3.add(&z3);
+        let z3 = t1.sub(&x3);
+        let x3 = t1.add(&x3);
+        let y3 = b.mul(&y3);
+        let t1 = t2.add(&t2);
+        let t2 = t1.add(&t2);
+        let y3 = y3.sub(&t2);
+        let y3 = y3.sub(&t0);
+        let t1 = y3.add(&y3);
+        let y3 = t1.add(&y3);
+        let t1 = t0.add(&t0);
+        let t0 = t1.add(&t0);
+        let t0 = t0.sub(&t2);
+        let t1 = t4.mul(&y3);
+        let t2 = t0.mul(&y3);
+        let y3 = x3.mul(&z3);
+        let y3 = y3.add(&t2);
+        let x3 = t3.mul(&x3);
+        let x3 = x3.sub(&t1);
+        let z3 = t4.mul(&z3);
+        let t1 = t3.mul(&t0);
+        let z3 = z3.add(&t1);
+        Point {
+            x: x3,
+            y: y3,
+            z: z3,
+        }
+    }
+
+    // Computes scalar * self, with a double-and-add-always loop over the big-endian scalar.
+    pub fn mul(&self, scalar: &Scalar) -> Point {
+        let mut bytes = [0; NBYTES];
+        scalar.to_bytes(&mut bytes);
+        let mut result = Point::IDENTITY;
+        for byte in bytes.iter() {
+            for i in (0..8).rev() {
+                result = result.add(&result);
+                let sum = result.add(self);
+                result = Point::select(((byte >> i) & 1) as u64, &sum, &result);
+            }
+        }
+        result
+    }
+
+    fn select(choice: u64, a: &Point, b: &Point) -> Point {
+        Point {
+            x: FieldElement::select(choice, &a.x, &b.x),
+            y: FieldElement::select(choice, &a.y, &b.y),
+            z: FieldElement::select(choice, &a.z, &b.z),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn scalar_from_u64(value: u64) -> Scalar {
+        let mut bytes = [0; NBYTES];
+        BigEndian::write_u64(array_mut_ref![bytes, NBYTES - 8, 8], value);
+        Scalar::from_bytes_checked(&bytes).unwrap()
+    }
+
+    fn affine_bytes(point: &Point) -> ([u8; NBYTES], [u8; NBYTES]) {
+        let mut x = [0; NBYTES];
+        let mut y = [0; NBYTES];
+        point.to_affine(&mut x, &mut y);
+        (x, y)
+    }
+
+    /** Field arithmetic **/
+    #[test]
+    fn test_field_element_bytes_round_trip() {
+        let mut bytes = [0; NBYTES];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let element = FieldElement::from_bytes(&bytes).unwrap();
+        let mut output = [0; NBYTES];
+        element.to_bytes(&mut output);
+        assert_eq!(output, bytes);
+    }
+
+    #[test]
+    fn test_field_element_from_bytes_not_reduced() {
+        assert!(FieldElement::from_bytes(&[0xFF; NBYTES]).is_none());
+        let mut p = [0; NBYTES];
+        limbs_to_bytes(&P.m, &mut p);
+        assert!(FieldElement::from_bytes(&p).is_none());
+    }
+
+    #[test]
+    fn test_field_element_invert() {
+        let mut bytes = [0x5A; NBYTES];
+        bytes[0] = 0x12;
+        let element = FieldElement::from_bytes(&bytes).unwrap();
+        assert!(element.mul(&element.invert()).equals(&FieldElement::ONE));
+        assert!(FieldElement::ZERO.invert().is_zero());
+    }
+
+    #[test]
+    fn test_field_element_sub_wraps() {
+        let one = FieldElement::ONE;
+        let minus_one = FieldElement::ZERO.sub(&one);
+        assert!(minus_one.add(&one).is_zero());
+        let mut bytes = [0; NBYTES];
+        minus_one.to_bytes(&mut bytes);
+        let mut expected = [0; NBYTES];
+        limbs_to_bytes(&P.m, &mut expected);
+        expected[NBYTES - 1] -= 1;
+        assert_eq!(bytes, expected);
+    }
+
+    /** Scalar arithmetic **/
+    #[test]
+    fn test_scalar_from_bytes_checked() {
+        assert!(Scalar::from_bytes_checked(&[0; NBYTES]).is_none());
+        let mut n = [0; NBYTES];
+        limbs_to_bytes(&N.m, &mut n);
+        assert!(Scalar::from_bytes_checked(&n).is_none());
+        n[NBYTES - 1] -= 1;
+        assert!(Scalar::from_bytes_checked(&n).is_some());
+    }
+
+    #[test]
+    fn test_scalar_from_bytes_reduced() {
+        let mut n_plus_one = [0; NBYTES];
+        limbs_to_bytes(&N.m, &mut n_plus_one);
+        n_plus_one[NBYTES - 1] += 1;
+        let reduced = Scalar::from_bytes_reduced(&n_plus_one);
+        assert!(reduced.equals(&scalar_from_u64(1)));
+    }
+
+    #[test]
+    fn test_scalar_from_wide_bytes() {
+        // 2^384 + 5
+        let mut bytes = [0; 64];
+        bytes[15] = 1;
+        bytes[63] = 5;
+        let mut r_mod_n = [0; NBYTES];
+        let one = scalar_from_u64(1);
+        // R mod n is the Montgomery form of one.
+        limbs_to_bytes(&one.0, &mut r_mod_n);
+        let expected = Scalar::from_bytes_reduced(&r_mod_n).add(&scalar_from_u64(5));
+        assert!(Scalar::from_wide_bytes(&bytes).equals(&expected));
+    }
+
+    #[test]
+    fn test_scalar_invert() {
+        let scalar = scalar_from_u64(0x1234_5678_9ABC_DEF0);
+        assert!(scalar.mul(&scalar.invert()).equals(&scalar_from_u64(1)));
+    }
+
+    /** Point arithmetic **/
+    #[test]
+    fn test_base_point_is_on_curve() {
+        let (x, y) = affine_bytes(&Point::base_point());
+        assert!(Point::from_affine_vartime(&x, &y).is_some());
+    }
+
+    #[test]
+    fn test_identity() {
+        let g = Point::base_point();
+        assert_eq!(affine_bytes(&g.add(&Point::IDENTITY)), affine_bytes(&g));
+        assert!(Point::IDENTITY.add(&Point::IDENTITY).is_identity());
+        // Multiplying by n - 1 and adding the base point gives the identity.
+        let mut n_minus_one = [0; NBYTES];
+        limbs_to_bytes(&N.m, &mut n_minus_one);
+        n_minus_one[NBYTES - 1] -= 1;
+        let n_minus_one = Scalar::from_bytes_checked(&n_minus_one).unwrap();
+        assert!(g.mul(&n_minus_one).add(&g).is_identity());
+    }
+
+    #[test]
+    fn test_double_matches_mul() {
+        let g = Point::base_point();
+        let doubled = g.add(&g);
+        assert_eq!(
+            affine_bytes(&doubled),
+            affine_bytes(&g.mul(&scalar_from_u64(2)))
+        );
+        assert_eq!(
+            affine_bytes(&doubled.add(&g)),
+            affine_bytes(&g.mul(&scalar_from_u64(3)))
+        );
+    }
+
+    #[test]
+    fn test_mul_known_answer() {
+        // 2G, from the Python cryptography package.
+        let (x, y) = affine_bytes(&Point::base_point().mul(&scalar_from_u64(2)));
+        assert_eq!(
+            x[..],
+            hex::decode(
+                "08d999057ba3d2d969260045c55b97f089025959a6f434d651d207d19fb96e9e\
+                 4fe0e86ebe0e64f85b96a9c75295df61"
+            )
+            .unwrap()[..]
+        );
+        assert_eq!(
+            y[..],
+            hex::decode(
+                "8e80f1fa5b1b3cedb7bfe8dffd6dba74b275d875bc6cc43e904e505f256ab425\
+                 5ffd43e94d39e22d61501e700a940e80"
+            )
+            .unwrap()[..]
+        );
+    }
+}
@@ -12,14 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::super::accelerator;
+use super::super::hmac::hmac_256;
+use super::super::sha256::Sha256;
+use super::super::zeroize::Zeroize;
 use super::exponent256::ExponentP256;
 use super::gfp256::GFP256;
-use super::int256::Int256;
+use super::int256::{Int256, NBYTES};
 use super::montgomery::Montgomery;
-#[cfg(test)]
-use arrayref::array_mut_ref;
-#[cfg(feature = "std")]
-use arrayref::array_ref;
+use arrayref::{array_mut_ref, array_ref};
 use core::ops::Add;
 use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
 
@@ -108,29 +109,130 @@ impl PointP256 {
     }
 
     /** Arithmetic **/
+    // The software multiplications are blinded, see Blinding.
     pub fn base_point_mul(n: &ExponentP256) -> PointP256 {
-        let point = PointProjective::scalar_base_mul(n);
+        if let Some(point) = PointP256::mul_accelerated(None, n) {
+            return point;
+        }
+        let blinding = Blinding::new(n, None);
+        let point = PointProjective::scalar_base_mul(&blinding.shares, &blinding.z);
         PointP256::from_projective(&point)
     }
 
     pub fn mul(&self, n: &ExponentP256) -> PointP256 {
+        if let Some(point) = PointP256::mul_accelerated(Some(self), n) {
+            return point;
+        }
+        let blinding = Blinding::new(n, Some(self));
         let p = self.to_affine();
-        let point = p.scalar_mul(n);
+        let point = p.scalar_mul(&blinding.shares, &blinding.z);
         PointP256::from_projective(&point)
     }
 
+    // Computes n*G, or n*base, with the hardware accelerator if there is one. The result is checked
+    // to be on the curve, and None is returned on failure, in which case the software computes it.
+    fn mul_accelerated(base: Option<&PointP256>, n: &ExponentP256) -> Option<PointP256> {
+        if !accelerator::is_available() {
+            return None;
+        }
+        let mut scalar = [0; NBYTES];
+        n.to_int().to_bin(&mut scalar);
+        let base = base.map(|base| {
+            let mut coordinates = [0; 2 * NBYTES];
+            base.x
+                .to_int()
+                .to_bin(array_mut_ref![coordinates, 0, NBYTES]);
+            base.y
+                .to_int()
+                .to_bin(array_mut_ref![coordinates, NBYTES, NBYTES]);
+            coordinates
+        });
+        let mut point = [0; 2 * NBYTES];
+        if !accelerator::p256_mul(&scalar, base.as_ref(), &mut point) {
+            return None;
+        }
+        PointP256::new_checked_vartime(
+            Int256::from_bin(array_ref![point, 0, NBYTES]),
+            Int256::from_bin(array_ref![point, NBYTES, NBYTES]),
+        )
+    }
+
     // Computes n1*G + n2*self
-    #[cfg(feature = "std")]
+    // This is only used to verify signatures, so the scalars are public and not blinded.
     pub fn points_mul(&self, n1: &ExponentP256, n2: &ExponentP256) -> PointP256 {
         let p = self.to_affine();
-        let p1 = PointProjective::scalar_base_mul(n1);
-        let p2 = p.scalar_mul(n2);
+        let p1 = PointProjective::scalar_base_mul(&[*n1], &Montgomery::ONE);
+        let p2 = p.scalar_mul(&[*n2], &Montgomery::ONE);
 
         let point = &p1 + &p2;
         PointP256::from_projective(&point)
     }
 }
 
+// The number of precomputed tables of the base point, and the number of bits between them.
+const COMB_TABLES: usize = 4;
+const COMB_SPACING: usize = 64 / COMB_TABLES;
+
+// Masks the secret scalar and the intermediate points of a software multiplication, so that the
+// power consumption and the EM emanations don't correlate with them.
+//
+// The scalar n is split into two random shares that add up to n modulo N, and the multiplication
+// computes the sum of the shares times the base point. The first point added to the accumulator
+// is also scaled to random projective coordinates (X*z, Y*z, z), which randomizes all the
+// following intermediate values.
+//
+// The blinding values are derived from the scalar and the base point with HMAC-SHA256. They are
+// unpredictable without the scalar, and differ for every base point an attacker chooses, without
+// requiring an RNG in the callers.
+struct Blinding {
+    shares: [ExponentP256; 2],
+    z: Montgomery,
+}
+
+impl Drop for Blinding {
+    fn drop(&mut self) {
+        for share in self.shares.iter_mut() {
+            share.zeroize();
+        }
+    }
+}
+
+impl Blinding {
+    const SHARE_LABEL: u8 = 0x00;
+    const Z_LABEL: u8 = 0x01;
+
+    fn new(n: &ExponentP256, base: Option<&PointP256>) -> Blinding {
+        let mut key = [0; NBYTES];
+        n.to_int().to_bin(&mut key);
+        let mut message = [0; 1 + 2 * NBYTES];
+        if let Some(base) = base {
+            base.x.to_int().to_bin(array_mut_ref![message, 1, NBYTES]);
+            base.y
+                .to_int()
+                .to_bin(array_mut_ref![message, 1 + NBYTES, NBYTES]);
+        }
+
+        message[0] = Blinding::SHARE_LABEL;
+        let mut share = hmac_256::<Sha256>(&key, &message);
+        let first_share = ExponentP256::modn(Int256::from_bin(&share));
+        share.zeroize();
+
+        message[0] = Blinding::Z_LABEL;
+        let z = Int256::from_bin(&hmac_256::<Sha256>(&key, &message)).modd(&Int256::P);
+        key.zeroize();
+        // The reduced value is always below P. It is zero with negligible probability, and then
+        // replaced with one so that the point stays valid.
+        let z_is_zero = z.is_zero();
+        let z = Montgomery::field_to_montgomery(&GFP256::from_int_checked(z).unwrap());
+        let z = Montgomery::conditional_select(&z, &Montgomery::ONE, z_is_zero);
+
+        Blinding {
+            shares: [first_share, n - &first_share],
+            z,
+        }
+    }
+}
+
 // A point on the elliptic curve in projective form.
 // This uses Montgomery representation for field elements.
 // This is in projective coordinates, i.e. it represents the point { x: x / z, y: y / z }.
@@ -169,6 +271,7 @@ impl PointProjective {
     };
 
     /** Constructors **/
+    #[cfg(test)]
     pub fn from_affine(point: &PointAffine) -> PointProjective {
         PointProjective {
             x: point.x,
@@ -177,7 +280,6 @@ impl PointProjective {
         }
     }
 
-    #[cfg(test)]
     // Construct a point in projective coordinates, with a given z value.
     // This point is equivalent to { x, y, z: 1 }
     pub fn from_affine_shuffled(point: &PointAffine, z: Montgomery) -> PointProjective {
@@ -312,8 +414,13 @@ impl PointProjective {
         PointProjective { x, y, z }
     }
 
-    // Compute scalar*G
-    fn scalar_base_mul(scalar: &ExponentP256) -> PointProjective {
+    // Compute the sum of scalars*G
+    // The first point added to the accumulator gets the projective coordinate z.
+    //
+    // This uses the comb method: the scalar is split into 4 blocks of 64 bits, and each block into
+    // 4 combs of 16 bits. Table t contains the sums of the points 2^(16t + 64k) * G, so that one
+    // lookup per table adds 16 bits of the scalar, and only 15 doublings are needed in total.
+    fn scalar_base_mul(scalars: &[ExponentP256], z: &Montgomery) -> PointProjective {
         let mut n = PointProjective {
             x: Montgomery::ZERO,
             y: Montgomery::ZERO,
@@ -321,28 +428,34 @@ impl PointProjective {
         };
         let mut choice_n_is_inf = Choice::from(1u8);
 
-        for i in 0..32 {
+        for i in 0..COMB_SPACING {
             if i != 0 {
                 n = n.double();
             }
 
-            for table_offset in 0..2 {
-                let j = 32 * table_offset;
-                let bit0 = scalar.bit(31 - i + j);
-                let bit1 = scalar.bit(95 - i + j);
-                let bit2 = scalar.bit(159 - i + j);
-                let bit3 = scalar.bit(223 - i + j);
-                let index = bit0 | (bit1 << 1) | (bit2 << 2) | (bit3 << 3);
+            for scalar in scalars {
+                for table_offset in 0..COMB_TABLES {
+                    let j = COMB_SPACING * table_offset + COMB_SPACING - 1 - i;
+                    let bit0 = scalar.bit(j);
+                    let bit1 = scalar.bit(j + 64);
+                    let bit2 = scalar.bit(j + 128);
+                    let bit3 = scalar.bit(j + 192);
+                    let index = bit0 | (bit1 << 1) | (bit2 << 2) | (bit3 << 3);
 
-                let p = PointAffine::select_point(&Montgomery::PRECOMPUTED[table_offset], index);
-                let t = n.add_mixed(&p);
+                    let p =
+                        PointAffine::select_point(&Montgomery::PRECOMPUTED[table_offset], index);
+                    let t = n.add_mixed(&p);
 
-                n.conditional_assign(&PointProjective::from_affine(&p), choice_n_is_inf);
+                    n.conditional_assign(
+                        &PointProjective::from_affine_shuffled(&p, *z),
+                        choice_n_is_inf,
+                    );
 
-                let choice_p_is_inf = index.ct_eq(&0);
-                n.conditional_assign(&t, !(choice_p_is_inf | choice_n_is_inf));
+                    let choice_p_is_inf = index.ct_eq(&0);
+                    n.conditional_assign(&t, !(choice_p_is_inf | choice_n_is_inf));
 
-                choice_n_is_inf &= choice_p_is_inf;
+                    choice_n_is_inf &= choice_p_is_inf;
+                }
             }
         }
 
@@ -428,14 +541,16 @@ impl PointAffine {
     }
 
     /** Arithmetic **/
-    fn scalar_mul(&self, scalar: &ExponentP256) -> PointProjective {
+    // Compute the sum of scalars*self
+    // The precomputed multiples of self get the projective coordinate z.
+    fn scalar_mul(&self, scalars: &[ExponentP256], z: &Montgomery) -> PointProjective {
         let mut precomp = [PointProjective {
             x: Montgomery::ZERO,
             y: Montgomery::ZERO,
             z: Montgomery::ZERO,
         }; 15];
 
-        precomp[0] = PointProjective::from_affine(self);
+        precomp[0] = PointProjective::from_affine_shuffled(self, *z);
 
         for i in (1..15).step_by(2) {
             precomp[i] = precomp[i >> 1].double();
@@ -456,20 +571,22 @@ impl PointAffine {
                 n = n.double();
                 n = n.double();
             }
-            let index = scalar.bit(255 - i) << 3
-                | scalar.bit(255 - i - 1) << 2
-                | scalar.bit(255 - i - 2) << 1
-                | scalar.bit(255 - i - 3);
+            for scalar in scalars {
+                let index = scalar.bit(255 - i) << 3
+                    | scalar.bit(255 - i - 1) << 2
+                    | scalar.bit(255 - i - 2) << 1
+                    | scalar.bit(255 - i - 3);
 
-            let p = PointProjective::select_point(&precomp, index);
-            let t = n.add(&p);
+                let p = PointProjective::select_point(&precomp, index);
+                let t = n.add(&p);
 
-            n.conditional_assign(&p, choice_n_is_inf);
+                n.conditional_assign(&p, choice_n_is_inf);
 
-            let choice_p_is_inf = index.ct_eq(&0);
-            n.conditional_assign(&t, !(choice_p_is_inf | choice_n_is_inf));
+                let choice_p_is_inf = index.ct_eq(&0);
+                n.conditional_assign(&t, !(choice_p_is_inf | choice_n_is_inf));
 
-            choice_n_is_inf &= choice_p_is_inf;
+                choice_n_is_inf &= choice_p_is_inf;
+            }
         }
 
         n
@@ -999,7 +1116,7 @@ pub mod test {
         // block to construct an exponent of N.
         let order = unsafe { ExponentP256::from_int_unchecked(Int256::N) };
         assert_eq!(
-            PointP256::from_projective(&gen.scalar_mul(&order)),
+            PointP256::from_projective(&gen.scalar_mul(&[order], &Montgomery::ONE)),
             PointP256::INFINITY
         );
     }
@@ -1010,8 +1127,8 @@ pub mod test {
         // TODO: more scalars
         for scalar in &super::super::exponent256::test::get_test_values() {
             assert_eq!(
-                PointProjective::scalar_base_mul(scalar),
-                gen.scalar_mul(scalar)
+                PointProjective::scalar_base_mul(&[*scalar], &Montgomery::ONE),
+                gen.scalar_mul(&[*scalar], &Montgomery::ONE)
             );
         }
     }
@@ -1028,6 +1145,40 @@ pub mod test {
         }
     }
 
+    #[test]
+    fn test_blinded_mul_is_unblinded_mul() {
+        let gen = precomputed(0, 0);
+        let base = PointP256::from_affine(&precomputed(1, 3));
+        for scalar in &super::super::exponent256::test::get_test_values() {
+            let blinding = Blinding::new(scalar, None);
+            assert_eq!(
+                PointProjective::scalar_base_mul(&blinding.shares, &blinding.z),
+                gen.scalar_mul(&[*scalar], &Montgomery::ONE)
+            );
+            let blinding = Blinding::new(scalar, Some(&base));
+            assert_eq!(
+                base.to_affine().scalar_mul(&blinding.shares, &blinding.z),
+                base.to_affine().scalar_mul(&[*scalar], &Montgomery::ONE)
+            );
+        }
+    }
+
+    #[test]
+    fn test_blinding_depends_on_scalar_and_base() {
+        let values = super::super::exponent256::test::get_test_values();
+        let base = PointP256::from_affine(&precomputed(1, 3));
+        let blinding = Blinding::new(&values[1], None);
+        let other_scalar = Blinding::new(&values[2], None);
+        let other_base = Blinding::new(&values[1], Some(&base));
+        assert!(blinding.shares[0] != other_scalar.shares[0]);
+        assert!(blinding.shares[0] != other_base.shares[0]);
+        assert!(blinding.z != other_base.z);
+        assert!(blinding.z != Montgomery::ONE);
+        // The accumulator doesn't start in affine coordinates.
+        let point = PointProjective::scalar_base_mul(&blinding.shares, &blinding.z);
+        assert!(point.z != Montgomery::ONE);
+    }
+
     // Helper function to compute the point 2^power * p.
     pub fn power_of_two(mut p: PointProjective, power: usize) -> PointProjective {
         for _ in 0..power {
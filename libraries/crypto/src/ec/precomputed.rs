@@ -14,7 +14,8 @@
 
 use super::montgomery::{Montgomery, NLIMBS};
 
-pub const PRECOMPUTED: [[[Montgomery; 2]; 15]; 2] = [
+// Table t contains the 15 non-trivial sums of the points 2^(16t + 64k) * G, for k in 0..4.
+pub const PRECOMPUTED: [[[Montgomery; 2]; 15]; 4] = [
     [
         [
             Montgomery::new(PRECOMPUTED_LIMBS[0]),
@@ -139,11 +140,135 @@ pub const PRECOMPUTED: [[[Montgomery; 2]; 15]; 2] = [
             Montgomery::new(PRECOMPUTED_LIMBS[59]),
         ],
     ],
+    [
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[60]),
+            Montgomery::new(PRECOMPUTED_LIMBS[61]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[62]),
+            Montgomery::new(PRECOMPUTED_LIMBS[63]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[64]),
+            Montgomery::new(PRECOMPUTED_LIMBS[65]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[66]),
+            Montgomery::new(PRECOMPUTED_LIMBS[67]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[68]),
+            Montgomery::new(PRECOMPUTED_LIMBS[69]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[70]),
+            Montgomery::new(PRECOMPUTED_LIMBS[71]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[72]),
+            Montgomery::new(PRECOMPUTED_LIMBS[73]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[74]),
+            Montgomery::new(PRECOMPUTED_LIMBS[75]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[76]),
+            Montgomery::new(PRECOMPUTED_LIMBS[77]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[78]),
+            Montgomery::new(PRECOMPUTED_LIMBS[79]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[80]),
+            Montgomery::new(PRECOMPUTED_LIMBS[81]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[82]),
+            Montgomery::new(PRECOMPUTED_LIMBS[83]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[84]),
+            Montgomery::new(PRECOMPUTED_LIMBS[85]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[86]),
+            Montgomery::new(PRECOMPUTED_LIMBS[87]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[88]),
+            Montgomery::new(PRECOMPUTED_LIMBS[89]),
+        ],
+    ],
+    [
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[90]),
+            Montgomery::new(PRECOMPUTED_LIMBS[91]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[92]),
+            Montgomery::new(PRECOMPUTED_LIMBS[93]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[94]),
+            Montgomery::new(PRECOMPUTED_LIMBS[95]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[96]),
+            Montgomery::new(PRECOMPUTED_LIMBS[97]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[98]),
+            Montgomery::new(PRECOMPUTED_LIMBS[99]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[100]),
+            Montgomery::new(PRECOMPUTED_LIMBS[101]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[102]),
+            Montgomery::new(PRECOMPUTED_LIMBS[103]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[104]),
+            Montgomery::new(PRECOMPUTED_LIMBS[105]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[106]),
+            Montgomery::new(PRECOMPUTED_LIMBS[107]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[108]),
+            Montgomery::new(PRECOMPUTED_LIMBS[109]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[110]),
+            Montgomery::new(PRECOMPUTED_LIMBS[111]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[112]),
+            Montgomery::new(PRECOMPUTED_LIMBS[113]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[114]),
+            Montgomery::new(PRECOMPUTED_LIMBS[115]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[116]),
+            Montgomery::new(PRECOMPUTED_LIMBS[117]),
+        ],
+        [
+            Montgomery::new(PRECOMPUTED_LIMBS[118]),
+            Montgomery::new(PRECOMPUTED_LIMBS[119]),
+        ],
+    ],
 ];
 
 #[rustfmt::skip]
 #[allow(clippy::unreadable_literal)]
-const PRECOMPUTED_LIMBS: [[u32; NLIMBS]; 60] = [
+const PRECOMPUTED_LIMBS: [[u32; NLIMBS]; 120] = [
 [0x11522878, 0x0e730d41, 0x0db60179, 0x04afe2ff, 0x12883add, 0x0caddd88, 0x119e7edc, 0x0d4a6eab, 0x03120bee],
 [0x1d2aac15, 0x0f25357c, 0x19e45cdd, 0x05c721d0, 0x1992c5a5, 0x0a237487, 0x0154ba21, 0x014b10bb, 0x00ae3fe3],
 [0x0d41a576, 0x0922fc51, 0x0234994f, 0x060b60d3, 0x164586ae, 0x0ce95f18, 0x1fe49073, 0x03fa36cc, 0x05ebcd2c],
@@ -174,6 +299,36 @@ const PRECOMPUTED_LIMBS: [[u32; NLIMBS]; 60] = [
 [0x1128ae0b, 0x0d095d05, 0x0b85c0c2, 0x01ecb2ef, 0x024ddc84, 0x0e35e901, 0x18411a4a, 0x0f5ddc3d, 0x03786689],
 [0x052260e8, 0x05ae3564, 0x0542b10d, 0x08d93a45, 0x19952aa4, 0x0996cc41, 0x1051a729, 0x04be3499, 0x052b23aa],
 [0x109f307e, 0x06f5b6bb, 0x1f84e1e7, 0x077a0cfa, 0x10c4df3f, 0x025a02ea, 0x0b048035, 0x0e31de66, 0x0c6ecaa3],
+[0x06ef3dc6, 0x00165fce, 0x095d9e0f, 0x03fcedea, 0x073d7007, 0x0e8880a1, 0x0647e93b, 0x0c8f58c4, 0x08ac975b],
+[0x08e11d1c, 0x0e6312ac, 0x1e9adf90, 0x092fdd38, 0x05cfa7ab, 0x06b8f5a1, 0x057a57d5, 0x023d2968, 0x08fef996],
+[0x0386cf95, 0x0050f1cf, 0x0fbc7de4, 0x02d4da48, 0x08194de4, 0x028b8684, 0x0a48dcf1, 0x005a0727, 0x06c84657],
+[0x0cfb5a52, 0x0577eb01, 0x178ab2f5, 0x07980ca5, 0x1a458b02, 0x02bfa0a4, 0x1082eaa5, 0x0a82ca86, 0x0eff7b1b],
+[0x0cacb36c, 0x04b5ee5b, 0x0651def7, 0x0390306f, 0x0a912c69, 0x04f1618d, 0x14e681be, 0x096a36e2, 0x02e83b90],
+[0x1c7639e5, 0x04e2f485, 0x086a41d7, 0x05474b94, 0x0c79b8fb, 0x01c80d36, 0x0963dc66, 0x00794513, 0x0dcf25c7],
+[0x0c7892c5, 0x0531fe1c, 0x1fdb2580, 0x00f448c0, 0x15b5a82a, 0x0687f530, 0x129704a4, 0x004dbe9f, 0x080d2743],
+[0x022fe407, 0x0c4604e0, 0x19b8d0b2, 0x08cb1454, 0x1622122f, 0x02ab1884, 0x0a0bb708, 0x0da41ea5, 0x063da9ca],
+[0x0ce7b0ea, 0x0a036629, 0x15f1669d, 0x0e739199, 0x0616a3e2, 0x0b8d5396, 0x00093a24, 0x027108b4, 0x00e451e0],
+[0x1f10ab4f, 0x09f34a22, 0x0307fcff, 0x06902a27, 0x1dc9fbeb, 0x0788d156, 0x13cf3509, 0x09db2c82, 0x0af97605],
+[0x1cd6aab7, 0x0b3edb45, 0x18da5619, 0x03cbf3e8, 0x0939cac6, 0x0d53a63e, 0x1628b74c, 0x091396a9, 0x00842446],
+[0x14c80575, 0x09ca71d7, 0x18f1dee3, 0x0bc3e09b, 0x13aec116, 0x0b40afaa, 0x0e8abe14, 0x09a21f86, 0x0b15ee56],
+[0x107e8057, 0x0de6bd85, 0x081fdbe4, 0x0a1c199a, 0x071af6f4, 0x0b5d2342, 0x13049015, 0x04d57a5d, 0x01c6920d],
+[0x01ace6e9, 0x033363ce, 0x0c05eb7b, 0x00ca256c, 0x1d053872, 0x0cd60b4a, 0x19cde296, 0x02c00ba7, 0x00921b06],
+[0x1bef999f, 0x0d9bc15a, 0x01e1b075, 0x0f2eb2fd, 0x00bf40d1, 0x0110926f, 0x10c30e79, 0x061d4ffa, 0x0e6fb996],
+[0x00ff7425, 0x06548739, 0x172904da, 0x0536ce51, 0x13629aed, 0x0f198a0e, 0x0fa15933, 0x0d95272b, 0x0a13f49e],
+[0x0894e7ed, 0x02c19d84, 0x0ee88a7b, 0x0a671f7f, 0x0c696447, 0x09c350c7, 0x124687de, 0x048364ae, 0x05c08423],
+[0x0b73bbe3, 0x07b64ce4, 0x1f927b46, 0x09007f44, 0x15b222ef, 0x09c40744, 0x1f921d31, 0x0ca9bf2b, 0x08959449],
+[0x06464b8f, 0x060550e7, 0x1e0994c6, 0x09340b1f, 0x05be6a68, 0x03b7b3f6, 0x0d1ff3fa, 0x0b611ccb, 0x00b16a44],
+[0x182c4847, 0x023f8b4d, 0x071a6b95, 0x03d3fd21, 0x0c62c4a8, 0x0bd99635, 0x1b4d34ba, 0x0273840b, 0x001cf745],
+[0x18a5d773, 0x0c063953, 0x0f1e4346, 0x0ea84a81, 0x128fb999, 0x07f6de72, 0x1cab5ad0, 0x0b4702f4, 0x06767a3b],
+[0x19f2ed49, 0x0deddb41, 0x17bac81f, 0x07e078bd, 0x0c0b25f0, 0x0f469e13, 0x01e798e3, 0x02c8737e, 0x0f49d5ec],
+[0x1f94dfe2, 0x0b0c4ac5, 0x0070378c, 0x0836125b, 0x0b9a4ed2, 0x0aac36c9, 0x0c11ba24, 0x0dbff663, 0x022bfb1b],
+[0x121d555c, 0x0135992c, 0x1f8578d9, 0x07553775, 0x0e02da0f, 0x0879e8e2, 0x01f2c92f, 0x0f63012b, 0x0532e3aa],
+[0x031de418, 0x08a14ba4, 0x0ba43d07, 0x06bc0412, 0x1643b526, 0x0de3110a, 0x0cb9d11d, 0x05b17744, 0x0388e559],
+[0x0846f111, 0x05c362f4, 0x0f1c00b4, 0x00b3b027, 0x0c2e3d96, 0x02651400, 0x0ce7a7cf, 0x029f627b, 0x00245fba],
+[0x1dfe25c2, 0x06e5c936, 0x031583e9, 0x060ed12f, 0x1afc855e, 0x03337257, 0x13f2113a, 0x06603e4f, 0x0b510d63],
+[0x0f670be1, 0x05d7086b, 0x09023884, 0x032c5c28, 0x0b05c68e, 0x0a98b01e, 0x0213746c, 0x0adc2a2b, 0x0df205b9],
+[0x1fd523ca, 0x0ff4f350, 0x11bda685, 0x0a34157c, 0x17e5194a, 0x0cf63baa, 0x06ff87c5, 0x065b482c, 0x0b75b56c],
+[0x1f09ccb3, 0x007bc51b, 0x06caa021, 0x0434c16c, 0x1f077a1b, 0x03d1ba7e, 0x14412b63, 0x0c68b44a, 0x0efe2695],
 [0x028ea335, 0x02886024, 0x1372f020, 0x00f55d35, 0x15e4684c, 0x0f2a9e17, 0x1a4a7529, 0x0cb7beb1, 0x0b2a78a1],
 [0x1ab21f1f, 0x06361ccf, 0x06c9179d, 0x0b135627, 0x1267b974, 0x04408bad, 0x1cbff658, 0x0e3d6511, 0x00c7d76f],
 [0x01cc7a69, 0x0e7ee31b, 0x054fab4f, 0x002b914f, 0x1ad27a30, 0x0cd3579e, 0x0c50124c, 0x050daa90, 0x00b13f72],
@@ -204,6 +359,36 @@ const PRECOMPUTED_LIMBS: [[u32; NLIMBS]; 60] = [
 [0x16152289, 0x0ac78ec2, 0x1a59c5a2, 0x02028b97, 0x071c2d01, 0x0295851f, 0x0404747b, 0x0878558d, 0x07d29aa4],
 [0x13d8341f, 0x08daefd7, 0x139c972d, 0x06b7ea75, 0x0d4a9dde, 0x0ff163d8, 0x081d55d7, 0x0a5bef68, 0x0b7b30d8],
 [0x0be73d6f, 0x0aa88141, 0x0d976c81, 0x07e7a9cc, 0x18beb771, 0x0d773cbd, 0x13f51951, 0x09d0c177, 0x01c49a78],
+[0x14ea181e, 0x07a64880, 0x148e83cc, 0x067f1a72, 0x07829cdd, 0x01d83044, 0x020f5063, 0x0963e22b, 0x07c9b549],
+[0x1ff8d3fe, 0x033e7b55, 0x05312727, 0x0a3de490, 0x0fca2378, 0x00d35c8b, 0x1f2216c9, 0x0bcdf8d6, 0x0d4889ec],
+[0x06c1c4b5, 0x0e9b6bfc, 0x1a1a788c, 0x028ca43a, 0x197a7321, 0x0aa1205c, 0x1e8771d5, 0x0fa8656a, 0x00780881],
+[0x0e40503e, 0x070b3f1d, 0x15e051c6, 0x0c887689, 0x15f39102, 0x0c3b6c14, 0x0e77b452, 0x0f8ad1d4, 0x0d2f67a4],
+[0x0f103916, 0x016b5b8b, 0x1825b445, 0x0a1e34d2, 0x181267f3, 0x0c1b0938, 0x098f56e2, 0x079f1926, 0x06383482],
+[0x0c655c77, 0x06eee84b, 0x148b145e, 0x0f5b555e, 0x10f4efdb, 0x04a669c9, 0x0efa9945, 0x0c54d226, 0x032d66b1],
+[0x0f3e724a, 0x01d783e9, 0x0c89a7b8, 0x0898557a, 0x1bfd0f7e, 0x0513f46f, 0x07949708, 0x08364cb5, 0x09652d20],
+[0x13b6cb6d, 0x08e9634b, 0x199d8aed, 0x01718c1a, 0x11f99ae4, 0x053d00d5, 0x0ad6a9e9, 0x0b045871, 0x03be1115],
+[0x004b5402, 0x06b8d047, 0x1e959539, 0x09674f11, 0x14fa54c5, 0x0f2082ec, 0x0965da79, 0x064034a3, 0x046ef1d7],
+[0x13702148, 0x03ff433a, 0x1269a765, 0x0b9ee337, 0x140000bb, 0x0a084a6b, 0x1aef38ab, 0x02a262fa, 0x06468a2a],
+[0x0cf533f4, 0x0a3bd51f, 0x12c87f39, 0x00fbe5d3, 0x12e531a2, 0x0dbdd144, 0x1e330ff1, 0x0d38d0da, 0x0ce1c018],
+[0x004efada, 0x05c29f9a, 0x043178a3, 0x0d6e79f2, 0x170b03c5, 0x04fd7471, 0x13c72807, 0x03f91269, 0x046ea393],
+[0x1848b932, 0x07c4f80e, 0x07312890, 0x01e998b1, 0x1d70d44a, 0x0b2cb88c, 0x15a26138, 0x04f298c4, 0x0cab49bb],
+[0x1cdc292e, 0x0e95ce7e, 0x1d337688, 0x0c93f894, 0x1ff9cbbf, 0x01e95a28, 0x1849d61e, 0x0fb87979, 0x017bed09],
+[0x1e12ac2b, 0x083e2ea1, 0x068c3310, 0x056bb4a7, 0x0c5f0514, 0x0948f620, 0x133aff00, 0x06e6b0a1, 0x06b0a227],
+[0x01bf1543, 0x0935f0b3, 0x17e3ac7d, 0x0d03de55, 0x0065d6ee, 0x0a6554bc, 0x1b04b0d0, 0x0d5213be, 0x0867da3b],
+[0x01f65365, 0x02c4a8a1, 0x17eb3622, 0x032c2d85, 0x0c50aa84, 0x00247ba2, 0x1768b457, 0x0c0a8121, 0x0694e1a8],
+[0x1ec3f7b8, 0x0e7a3896, 0x1348c43b, 0x03157fe9, 0x0aa953e9, 0x00a799b5, 0x15a5a0bb, 0x04299771, 0x0bde2efd],
+[0x1d5f6ca1, 0x067f7937, 0x12a0cb30, 0x0fdf9ddf, 0x041271be, 0x066a30eb, 0x12d9ae38, 0x0040bd16, 0x01892618],
+[0x0b9b6f78, 0x09126764, 0x0a892cd6, 0x0e72b82f, 0x0b87e75e, 0x000e0054, 0x197c0315, 0x08f6cb47, 0x05c25bf9],
+[0x18becfa1, 0x0dfc7a96, 0x0471a79b, 0x025f00c3, 0x1654b252, 0x0c16dc86, 0x003fc4b1, 0x0aaec1ae, 0x0985ea47],
+[0x08dfd7e4, 0x0bb9288b, 0x1964e649, 0x0384c9ba, 0x1c97b510, 0x02641081, 0x1a1b2929, 0x0a717953, 0x04311825],
+[0x1dca1e3c, 0x057f5c48, 0x0578e438, 0x000e904d, 0x0b877c7c, 0x07f7c83c, 0x0a6f9b22, 0x0f579e5f, 0x0b741165],
+[0x10de79f8, 0x0803b774, 0x1883ea86, 0x0497bce7, 0x15ab4235, 0x09d91d3f, 0x06afed49, 0x01a4a42c, 0x090624f6],
+[0x14cb1856, 0x0288cfe6, 0x1d9e9118, 0x07796859, 0x1a7974f5, 0x08fe6b91, 0x1c163c80, 0x0379e682, 0x017b5c96],
+[0x030c3a24, 0x0725d7bb, 0x0725bb9b, 0x027ba05a, 0x1e0c95e0, 0x01f34abb, 0x03b67f8b, 0x0cc21703, 0x0bd30b76],
+[0x09331c96, 0x0b1117cb, 0x0b2e3258, 0x0629cf71, 0x1fb05166, 0x0fa049fc, 0x0757440c, 0x07778972, 0x0d2b1247],
+[0x015426db, 0x077a0cf7, 0x1bbf55a0, 0x0443a720, 0x1898697d, 0x06a8ca0d, 0x0fbd349c, 0x016783af, 0x0fd13820],
+[0x0446fd03, 0x0e088053, 0x0dfaee8d, 0x0427603a, 0x1b19fa1b, 0x0ae622fb, 0x045f6e89, 0x09ba5539, 0x05853280],
+[0x12ccb3c2, 0x0a6a5a0a, 0x1ec31aee, 0x02aaee92, 0x124173a5, 0x03c75f56, 0x192198ee, 0x0e36b08b, 0x0a16921a],
 ];
 
 #[cfg(test)]
@@ -229,96 +414,32 @@ mod test {
     #[test]
     fn test_precomputed_powers_of_g_are_correct() {
         let gen = PointProjective::from_affine(&precomputed(0, 0));
-        let g32 = power_of_two(gen, 32);
-        let g64 = power_of_two(gen, 64);
-        let g96 = power_of_two(gen, 96);
-        let g128 = power_of_two(gen, 128);
-        let g160 = power_of_two(gen, 160);
-        let g192 = power_of_two(gen, 192);
-        let g224 = power_of_two(gen, 224);
-
-        assert_eq!(
-            PointProjective::from_affine(&precomputed(0, 0b0001 - 1)),
-            gen
-        );
-        assert_eq!(
-            PointProjective::from_affine(&precomputed(0, 0b0010 - 1)),
-            g64
-        );
-        assert_eq!(
-            PointProjective::from_affine(&precomputed(0, 0b0100 - 1)),
-            g128
-        );
-        assert_eq!(
-            PointProjective::from_affine(&precomputed(0, 0b1000 - 1)),
-            g192
-        );
-        assert_eq!(
-            PointProjective::from_affine(&precomputed(1, 0b0001 - 1)),
-            g32
-        );
-        assert_eq!(
-            PointProjective::from_affine(&precomputed(1, 0b0010 - 1)),
-            g96
-        );
-        assert_eq!(
-            PointProjective::from_affine(&precomputed(1, 0b0100 - 1)),
-            g160
-        );
-        assert_eq!(
-            PointProjective::from_affine(&precomputed(1, 0b1000 - 1)),
-            g224
-        );
-    }
-
-    #[test]
-    fn test_precomputed_table_0_is_correct() {
-        let gen = PointProjective::from_affine(&precomputed(0, 0));
-        let g64 = power_of_two(gen, 64);
-        let g128 = power_of_two(gen, 128);
-        let g192 = power_of_two(gen, 192);
-
-        for i in 1..16 {
-            let mut x = PointProjective::INFINITY;
-            if i & 1 != 0 {
-                x = &x + &gen;
-            }
-            if i & 2 != 0 {
-                x = &x + &g64;
-            }
-            if i & 4 != 0 {
-                x = &x + &g128;
+        for table in 0..4 {
+            for k in 0..4 {
+                assert_eq!(
+                    PointProjective::from_affine(&precomputed(table, (1 << k) - 1)),
+                    power_of_two(gen, 16 * table + 64 * k)
+                );
             }
-            if i & 8 != 0 {
-                x = &x + &g192;
-            }
-            assert_eq!(PointProjective::from_affine(&precomputed(0, i - 1)), x);
         }
     }
 
     #[test]
-    fn test_precomputed_table_1_is_correct() {
+    fn test_precomputed_tables_are_correct() {
         let gen = PointProjective::from_affine(&precomputed(0, 0));
-        let g32 = power_of_two(gen, 32);
-        let g96 = power_of_two(gen, 96);
-        let g160 = power_of_two(gen, 160);
-        let g224 = power_of_two(gen, 224);
-
-        for i in 1..16 {
-            let mut x = PointProjective::INFINITY;
-            if i & 1 != 0 {
-                x = &x + &g32;
-            }
-            if i & 2 != 0 {
-                x = &x + &g96;
-            }
-            if i & 4 != 0 {
-                x = &x + &g160;
-            }
-            if i & 8 != 0 {
-                x = &x + &g224;
+        for table in 0..4 {
+            let powers: Vec<PointProjective> = (0..4)
+                .map(|k| power_of_two(gen, 16 * table + 64 * k))
+                .collect();
+            for i in 1..16 {
+                let mut x = PointProjective::INFINITY;
+                for (k, power) in powers.iter().enumerate() {
+                    if i & (1 << k) != 0 {
+                        x = &x + power;
+                    }
+                }
+                assert_eq!(PointProjective::from_affine(&precomputed(table, i - 1)), x);
             }
-            assert_eq!(PointProjective::from_affine(&precomputed(1, i - 1)), x);
         }
     }
 }
@@ -18,10 +18,49 @@ use super::ec::int256::Int256;
 use super::ec::point::PointP256;
 use super::rng256::Rng256;
 use super::sha256::Sha256;
+use super::zeroize::Zeroize;
 use super::Hash256;
 
 pub const NBYTES: usize = int256::NBYTES;
 
+// Elliptic curve Diffie-Hellman key agreement, independent of the curve.
+//
+// Protocols that are generic over this trait, such as the key agreement of PIN/UV auth protocols,
+// support new curves without duplicating the handling of the platform key.
+pub trait Ecdh {
+    type SecKey;
+    type PubKey;
+
+    fn gensk<R: Rng256>(rng: &mut R) -> Self::SecKey;
+    fn genpk(sk: &Self::SecKey) -> Self::PubKey;
+
+    // Returns the raw shared secret, or None if the public key of the peer makes it degenerate.
+    // The shared secret is not uniformly random, and must go through a key derivation function.
+    fn exchange(sk: &Self::SecKey, pk: &Self::PubKey) -> Option<[u8; 32]>;
+}
+
+// ECDH on the NIST P-256 curve, whose shared secret is the x-coordinate of the shared point.
+pub struct P256;
+
+impl Ecdh for P256 {
+    type SecKey = SecKey;
+    type PubKey = PubKey;
+
+    fn gensk<R: Rng256>(rng: &mut R) -> SecKey {
+        SecKey::gensk(rng)
+    }
+
+    fn genpk(sk: &SecKey) -> PubKey {
+        sk.genpk()
+    }
+
+    // The public key is a valid point and the curve has a prime order, so the shared point is
+    // never infinite.
+    fn exchange(sk: &SecKey, pk: &PubKey) -> Option<[u8; 32]> {
+        Some(sk.exchange_x(pk))
+    }
+}
+
 pub struct SecKey {
     a: NonZeroExponentP256,
 }
@@ -31,6 +70,12 @@ pub struct PubKey {
     p: PointP256,
 }
 
+impl Drop for SecKey {
+    fn drop(&mut self) {
+        self.a.zeroize();
+    }
+}
+
 impl SecKey {
     pub fn gensk<R>(rng: &mut R) -> SecKey
     where
@@ -65,10 +110,16 @@ impl SecKey {
     // DH key agreement method defined in the FIDO2 specification, Section 5.5.4. "Getting
     // sharedSecret from Authenticator"
     pub fn exchange_x_sha256(&self, other: &PubKey) -> [u8; 32] {
+        Sha256::hash(&self.exchange_x(other))
+    }
+
+    // DH key agreement that outputs the x-coordinate of the shared point, as used as input key
+    // material by the PIN/UV auth protocol two.
+    pub fn exchange_x(&self, other: &PubKey) -> [u8; 32] {
         let p = self.exchange_raw(other);
         let mut x: [u8; 32] = [Default::default(); 32];
         p.getx().to_int().to_bin(&mut x);
-        Sha256::hash(&x)
+        x
     }
 }
 
@@ -150,5 +201,35 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_exchange_x_is_symmetric() {
+        let mut rng = ThreadRng256 {};
+
+        for _ in 0..ITERATIONS {
+            let sk_a = SecKey::gensk(&mut rng);
+            let pk_a = sk_a.genpk();
+            let sk_b = SecKey::gensk(&mut rng);
+            let pk_b = sk_b.genpk();
+            assert_eq!(sk_a.exchange_x(&pk_b), sk_b.exchange_x(&pk_a));
+            assert_eq!(
+                Sha256::hash(&sk_a.exchange_x(&pk_b)),
+                sk_a.exchange_x_sha256(&pk_b)
+            );
+        }
+    }
+
+    #[test]
+    fn test_ecdh_trait_matches_exchange_x() {
+        let mut rng = ThreadRng256 {};
+        let sk_a = P256::gensk(&mut rng);
+        let sk_b = P256::gensk(&mut rng);
+        let pk_b = P256::genpk(&sk_b);
+        assert_eq!(P256::exchange(&sk_a, &pk_b), Some(sk_a.exchange_x(&pk_b)));
+        assert_eq!(
+            P256::exchange(&sk_a, &pk_b),
+            P256::exchange(&sk_b, &P256::genpk(&sk_a))
+        );
+    }
+
     // TODO: tests with invalid public shares.
 }
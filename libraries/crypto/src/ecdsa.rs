@@ -18,10 +18,10 @@ use super::ec::int256::Int256;
 use super::ec::point::PointP256;
 use super::hmac::hmac_256;
 use super::rng256::Rng256;
+use super::zeroize::Zeroize;
 use super::{Hash256, HashBlockSize64Bytes};
 use alloc::vec;
 use alloc::vec::Vec;
-#[cfg(feature = "std")]
 use arrayref::array_ref;
 use arrayref::{array_mut_ref, mut_array_refs};
 use cbor::{cbor_bytes, cbor_map_options};
@@ -42,6 +42,12 @@ pub struct PubKey {
     p: PointP256,
 }
 
+impl Drop for SecKey {
+    fn drop(&mut self) {
+        self.k.zeroize();
+    }
+}
+
 impl SecKey {
     pub fn gensk<R>(rng: &mut R) -> SecKey
     where
@@ -83,9 +89,40 @@ impl SecKey {
     where
         H: Hash256 + HashBlockSize64Bytes,
     {
-        let m = ExponentP256::modn(Int256::from_bin(&H::hash(msg)));
+        self.sign_rfc6979_with_data::<H>(&H::hash(msg), &[])
+    }
+
+    // ECDSA signature with the randomization parameter of RFC 6979, mixed with fresh output of the
+    // RNG as additional data k' (section 3.6).
+    // Unlike sign_rng, this method stays secure with a weak or stuck RNG: the nonce is then at
+    // worst deterministic, and never repeats for different messages. And unlike sign_rfc6979, the
+    // nonce is unpredictable to an attacker who doesn't know the RNG output, which makes fault
+    // attacks on repeated signatures harder.
+    pub fn sign_hedged<H, R>(&self, msg: &[u8], rng: &mut R) -> Signature
+    where
+        H: Hash256 + HashBlockSize64Bytes,
+        R: Rng256,
+    {
+        self.sign_hedged_digest::<H, R>(&H::hash(msg), rng)
+    }
+
+    // Same as sign_hedged, for a message that the caller already hashed with H, e.g. by streaming
+    // it in parts.
+    pub fn sign_hedged_digest<H, R>(&self, digest: &[u8; 32], rng: &mut R) -> Signature
+    where
+        H: Hash256 + HashBlockSize64Bytes,
+        R: Rng256,
+    {
+        self.sign_rfc6979_with_data::<H>(digest, &rng.gen_uniform_u8x32())
+    }
+
+    fn sign_rfc6979_with_data<H>(&self, digest: &[u8; 32], additional_data: &[u8]) -> Signature
+    where
+        H: Hash256 + HashBlockSize64Bytes,
+    {
+        let m = ExponentP256::modn(Int256::from_bin(digest));
 
-        let mut rfc_6979 = Rfc6979::<H>::new(self, &msg);
+        let mut rfc_6979 = Rfc6979::<H>::new(self, digest, additional_data);
         loop {
             let k = NonZeroExponentP256::from_int_checked(rfc_6979.next());
             // The branching here is fine. By design the algorithm of RFC 6976 has a running time
@@ -126,13 +163,13 @@ impl SecKey {
     }
 
     #[cfg(test)]
-    pub fn get_k_rfc6979<H>(&self, msg: &[u8]) -> NonZeroExponentP256
+    pub fn get_k_rfc6979<H>(&self, msg: &[u8], additional_data: &[u8]) -> NonZeroExponentP256
     where
         H: Hash256 + HashBlockSize64Bytes,
     {
         let m = ExponentP256::modn(Int256::from_bin(&H::hash(msg)));
 
-        let mut rfc_6979 = Rfc6979::<H>::new(self, &msg);
+        let mut rfc_6979 = Rfc6979::<H>::new(self, &H::hash(msg), additional_data);
         loop {
             let k = NonZeroExponentP256::from_int_checked(rfc_6979.next());
             if bool::from(k.is_none()) {
@@ -188,7 +225,7 @@ impl Signature {
         encoding
     }
 
-    #[cfg(feature = "std")]
+    // Decodes the big endian integers r and s, as 64 bytes.
     pub fn from_bytes(bytes: &[u8]) -> Option<Signature> {
         if bytes.len() != 64 {
             None
@@ -243,6 +280,16 @@ impl PubKey {
     }
 
     // Encodes the key according to CBOR Object Signing and Encryption, defined in RFC 8152.
+    pub fn from_coordinates(x: &[u8; int256::NBYTES], y: &[u8; int256::NBYTES]) -> Option<PubKey> {
+        PointP256::new_checked_vartime(Int256::from_bin(x), Int256::from_bin(y))
+            .map(|p| PubKey { p })
+    }
+
+    pub fn to_coordinates(&self, x: &mut [u8; int256::NBYTES], y: &mut [u8; int256::NBYTES]) {
+        self.p.getx().to_int().to_bin(x);
+        self.p.gety().to_int().to_bin(y);
+    }
+
     pub fn to_cose_key(&self) -> Option<Vec<u8>> {
         const EC2_KEY_TYPE: i64 = 2;
         const P_256_CURVE: i64 = 1;
@@ -273,12 +320,16 @@ impl PubKey {
         }
     }
 
-    #[cfg(feature = "std")]
     pub fn verify_vartime<H>(&self, msg: &[u8], sign: &Signature) -> bool
     where
         H: Hash256,
     {
-        let m = ExponentP256::modn(Int256::from_bin(&H::hash(msg)));
+        self.verify_digest_vartime(&H::hash(msg), sign)
+    }
+
+    // Verifies a signature over a digest, e.g. of a message that was hashed in parts.
+    pub fn verify_digest_vartime(&self, digest: &[u8; 32], sign: &Signature) -> bool {
+        let m = ExponentP256::modn(Int256::from_bin(digest));
 
         let v = sign.s.inv();
         let u = &m * v.as_exponent();
@@ -299,32 +350,45 @@ where
     hash_marker: PhantomData<H>,
 }
 
+// The state determines the next nonces.
+impl<H> Drop for Rfc6979<H>
+where
+    H: Hash256 + HashBlockSize64Bytes,
+{
+    fn drop(&mut self) {
+        self.k.zeroize();
+        self.v.zeroize();
+    }
+}
+
 impl<H> Rfc6979<H>
 where
     H: Hash256 + HashBlockSize64Bytes,
 {
-    pub fn new(sk: &SecKey, msg: &[u8]) -> Rfc6979<H> {
-        let h1 = H::hash(msg);
+    // The additional data is appended to the private key and the hash in both updates of K, as in
+    // section 3.6. It is empty for the deterministic variant of section 3.2.
+    pub fn new(sk: &SecKey, h1: &[u8; 32], additional_data: &[u8]) -> Rfc6979<H> {
         let v = [0x01; 32];
         let k = [0x00; 32];
 
-        let mut contents = [0; 3 * 32 + 1];
+        let mut contents = vec![0; 3 * 32 + 1];
         let (contents_v, marker, contents_k, contents_h1) =
-            mut_array_refs![&mut contents, 32, 1, 32, 32];
+            mut_array_refs![array_mut_ref![contents, 0, 3 * 32 + 1], 32, 1, 32, 32];
         contents_v.copy_from_slice(&v);
         marker[0] = 0x00;
         Int256::to_bin(&sk.k.to_int(), contents_k);
-        Int256::to_bin(&Int256::from_bin(&h1).modd(&Int256::N), contents_h1);
+        Int256::to_bin(&Int256::from_bin(h1).modd(&Int256::N), contents_h1);
+        contents.extend_from_slice(additional_data);
 
         let k = hmac_256::<H>(&k, &contents);
         let v = hmac_256::<H>(&k, &v);
 
-        let (contents_v, marker, _) = mut_array_refs![&mut contents, 32, 1, 64];
-        contents_v.copy_from_slice(&v);
-        marker[0] = 0x01;
+        contents[..32].copy_from_slice(&v);
+        contents[32] = 0x01;
 
         let k = hmac_256::<H>(&k, &contents);
         let v = hmac_256::<H>(&k, &v);
+        contents.zeroize();
 
         Rfc6979 {
             k,
@@ -410,6 +474,22 @@ mod test {
         assert!(sk.is_none());
     }
 
+    #[test]
+    fn test_pubkey_to_coordinates_from_coordinates() {
+        let mut rng = ThreadRng256 {};
+
+        for _ in 0..ITERATIONS {
+            let pk = SecKey::gensk(&mut rng).genpk();
+            let mut x = [0; 32];
+            let mut y = [0; 32];
+            pk.to_coordinates(&mut x, &mut y);
+            let decoded_pk = PubKey::from_coordinates(&x, &y).unwrap();
+            assert_eq!(decoded_pk.to_cose_key(), pk.to_cose_key());
+        }
+        // The point (0, 0) is not on the curve.
+        assert!(PubKey::from_coordinates(&[0; 32], &[0; 32]).is_none());
+    }
+
     /** Test vectors from RFC6979 **/
     fn int256_from_hex(x: &str) -> Int256 {
         let bytes = hex::decode(x).unwrap();
@@ -437,7 +517,7 @@ mod test {
             k: NonZeroExponentP256::from_int_checked(int256_from_hex(RFC6979_X)).unwrap(),
         };
         assert_eq!(
-            sk.get_k_rfc6979::<Sha256>(msg.as_bytes()).to_int(),
+            sk.get_k_rfc6979::<Sha256>(msg.as_bytes(), &[]).to_int(),
             int256_from_hex(k)
         );
         let sign = sk.sign_rfc6979::<Sha256>(msg.as_bytes());
@@ -478,6 +558,73 @@ mod test {
         }
     }
 
+    // Test that signed messages are correctly verified.
+    #[test]
+    fn test_sign_hedged_verify_random() {
+        let mut rng = ThreadRng256 {};
+
+        for _ in 0..ITERATIONS {
+            let msg = rng.gen_uniform_u8x32();
+            let sk = SecKey::gensk(&mut rng);
+            let pk = sk.genpk();
+            let sign = sk.sign_hedged::<Sha256, _>(&msg, &mut rng);
+            assert!(pk.verify_vartime::<Sha256>(&msg, &sign));
+        }
+    }
+
+    // An RNG that always returns the same output, as a broken TRNG would.
+    struct StuckRng256 {}
+
+    impl Rng256 for StuckRng256 {
+        fn gen_uniform_u8x32(&mut self) -> [u8; 32] {
+            [0x55; 32]
+        }
+    }
+
+    #[test]
+    fn test_sign_hedged_digest_verify() {
+        let mut rng = ThreadRng256 {};
+        let sk = SecKey::gensk(&mut rng);
+        let pk = sk.genpk();
+        let mut hasher = Sha256::new();
+        hasher.update(b"sam");
+        hasher.update(b"ple");
+        let sign = sk.sign_hedged_digest::<Sha256, _>(&hasher.finalize(), &mut rng);
+        assert!(pk.verify_vartime::<Sha256>(b"sample", &sign));
+    }
+
+    #[test]
+    fn test_sign_hedged_stuck_rng() {
+        let mut rng = ThreadRng256 {};
+        let sk = SecKey::gensk(&mut rng);
+        let pk = sk.genpk();
+        let msg1 = rng.gen_uniform_u8x32();
+        let msg2 = rng.gen_uniform_u8x32();
+        let sign1 = sk.sign_hedged::<Sha256, _>(&msg1, &mut StuckRng256 {});
+        let sign2 = sk.sign_hedged::<Sha256, _>(&msg2, &mut StuckRng256 {});
+        assert!(pk.verify_vartime::<Sha256>(&msg1, &sign1));
+        assert!(pk.verify_vartime::<Sha256>(&msg2, &sign2));
+        // Even with the same RNG output, different messages use different nonces.
+        assert_ne!(sign1.r.to_int(), sign2.r.to_int());
+    }
+
+    #[test]
+    fn test_hedged_nonce_depends_on_additional_data() {
+        let sk = SecKey {
+            k: NonZeroExponentP256::from_int_checked(int256_from_hex(RFC6979_X)).unwrap(),
+        };
+        let deterministic_k = sk.get_k_rfc6979::<Sha256>(b"sample", &[]).to_int();
+        let hedged_k = sk.get_k_rfc6979::<Sha256>(b"sample", &[0x55; 32]).to_int();
+        let other_hedged_k = sk.get_k_rfc6979::<Sha256>(b"sample", &[0xAA; 32]).to_int();
+        assert_ne!(hedged_k, deterministic_k);
+        assert_ne!(hedged_k, other_hedged_k);
+        // The nonce is still a function of its inputs.
+        assert_eq!(
+            sk.get_k_rfc6979::<Sha256>(b"sample", &[0x55; 32]).to_int(),
+            hedged_k
+        );
+    }
+
     // Test that signed messages are correctly verified.
     #[test]
     fn test_sign_verify_random() {
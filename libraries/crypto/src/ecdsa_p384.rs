@@ -0,0 +1,546 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// ECDSA with SHA-384 on the P-384 curve, also known as ES384.
+
+use super::ec::p384;
+use super::ec::p384::{Point, Scalar};
+use super::hmac::hmac_384;
+use super::rng256::Rng256;
+use super::sha512::{Sha384, Sha512};
+use super::zeroize::Zeroize;
+use alloc::vec;
+use alloc::vec::Vec;
+use arrayref::{array_mut_ref, array_ref, mut_array_refs};
+use cbor::{cbor_bytes, cbor_map_options};
+
+pub const NBYTES: usize = p384::NBYTES;
+
+// Secret keys are stored as a 32-byte seed from which the secret scalar is derived. This way, they
+// have the same size as the keys of the other algorithms.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "derive_debug", derive(Debug))]
+pub struct SecKey {
+    seed: [u8; 32],
+}
+
+pub struct Signature {
+    r: Scalar,
+    s: Scalar,
+}
+
+pub struct PubKey {
+    p: Point,
+}
+
+impl Drop for SecKey {
+    fn drop(&mut self) {
+        self.seed.zeroize();
+    }
+}
+
+impl SecKey {
+    pub fn gensk<R>(rng: &mut R) -> SecKey
+    where
+        R: Rng256,
+    {
+        loop {
+            if let Some(sk) = SecKey::from_bytes(&rng.gen_uniform_u8x32()) {
+                return sk;
+            }
+        }
+    }
+
+    // Returns None if the seed derives a zero scalar, which happens with negligible probability.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Option<SecKey> {
+        let sk = SecKey { seed: *bytes };
+        if sk.scalar().is_zero() {
+            None
+        } else {
+            Some(sk)
+        }
+    }
+
+    pub fn to_bytes(&self, bytes: &mut [u8; 32]) {
+        bytes.copy_from_slice(&self.seed);
+    }
+
+    pub fn genpk(&self) -> PubKey {
+        PubKey {
+            p: Point::base_point().mul(&self.scalar()),
+        }
+    }
+
+    // Deterministic signature, with the nonce derived as in RFC 6979 with HMAC-SHA384.
+    pub fn sign_rfc6979(&self, msg: &[u8]) -> Signature {
+        sign_rfc6979_with_scalar(&self.scalar(), msg, &[])
+    }
+
+    // Hedged signature, with the RFC 6979 nonce mixed with fresh output of the RNG. A weak or stuck
+    // RNG can't lead to nonce reuse across different messages.
+    pub fn sign_hedged<R>(&self, msg: &[u8], rng: &mut R) -> Signature
+    where
+        R: Rng256,
+    {
+        self.sign_hedged_digest(&Sha384::hash(msg), rng)
+    }
+
+    // Same as sign_hedged, for a message that the caller already hashed with SHA-384, e.g. by
+    // streaming it in parts.
+    pub fn sign_hedged_digest<R>(&self, digest: &[u8; NBYTES], rng: &mut R) -> Signature
+    where
+        R: Rng256,
+    {
+        sign_rfc6979_with_digest(&self.scalar(), digest, &rng.gen_uniform_u8x32())
+    }
+
+    fn scalar(&self) -> Scalar {
+        Scalar::from_wide_bytes(&Sha512::hash(&self.seed))
+    }
+}
+
+fn sign_rfc6979_with_scalar(x: &Scalar, msg: &[u8], additional_data: &[u8]) -> Signature {
+    sign_rfc6979_with_digest(x, &Sha384::hash(msg), additional_data)
+}
+
+fn sign_rfc6979_with_digest(
+    x: &Scalar,
+    digest: &[u8; NBYTES],
+    additional_data: &[u8],
+) -> Signature {
+    let e = Scalar::from_bytes_reduced(digest);
+    let mut rfc_6979 = Rfc6979::new(x, &e, additional_data);
+    loop {
+        let k = match rfc_6979.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let r = Scalar::from_field_element(&Point::base_point().mul(&k).affine_x());
+        if r.is_zero() {
+            continue;
+        }
+        let s = k.invert().mul(&e.add(&r.mul(x)));
+        if s.is_zero() {
+            continue;
+        }
+        return Signature { r, s };
+    }
+}
+
+impl Signature {
+    pub fn to_asn1_der(&self) -> Vec<u8> {
+        const DER_INTEGER_TYPE: u8 = 0x02;
+        const DER_DEF_LENGTH_SEQUENCE: u8 = 0x30;
+
+        let r_encoding = Signature::to_minimal_encoding(&self.r);
+        let s_encoding = Signature::to_minimal_encoding(&self.s);
+        // Each integer takes at most 49 bytes, so the sequence length fits into 7 bits.
+        let mut encoding = vec![
+            DER_DEF_LENGTH_SEQUENCE,
+            (r_encoding.len() + s_encoding.len() + 4) as u8,
+        ];
+        encoding.push(DER_INTEGER_TYPE);
+        encoding.push(r_encoding.len() as u8);
+        encoding.extend(r_encoding);
+        encoding.push(DER_INTEGER_TYPE);
+        encoding.push(s_encoding.len() as u8);
+        encoding.extend(s_encoding);
+        encoding
+    }
+
+    // Returns None if r or s is not in the range [1, n - 1].
+    pub fn from_bytes(bytes: &[u8; 2 * NBYTES]) -> Option<Signature> {
+        let r = Scalar::from_bytes_checked(array_ref![bytes, 0, NBYTES])?;
+        let s = Scalar::from_bytes_checked(array_ref![bytes, NBYTES, NBYTES])?;
+        Some(Signature { r, s })
+    }
+
+    pub fn to_bytes(&self, bytes: &mut [u8; 2 * NBYTES]) {
+        self.r.to_bytes(array_mut_ref![bytes, 0, NBYTES]);
+        self.s.to_bytes(array_mut_ref![bytes, NBYTES, NBYTES]);
+    }
+
+    // Big-endian encoding without leading zeros, except one if the top bit is set.
+    fn to_minimal_encoding(scalar: &Scalar) -> Vec<u8> {
+        let mut bytes = [0; NBYTES];
+        scalar.to_bytes(&mut bytes);
+        let leading_zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+        let mut encoding = Vec::with_capacity(NBYTES + 1);
+        if bytes[leading_zeros] & 0x80 != 0 {
+            encoding.push(0x00);
+        }
+        encoding.extend_from_slice(&bytes[leading_zeros..]);
+        encoding
+    }
+}
+
+impl PubKey {
+    pub const ES384_ALGORITHM: i64 = -35;
+
+    // Returns None if the point is not on the curve.
+    pub fn from_coordinates(x: &[u8; NBYTES], y: &[u8; NBYTES]) -> Option<PubKey> {
+        Point::from_affine_vartime(x, y).map(|p| PubKey { p })
+    }
+
+    pub fn to_coordinates(&self, x: &mut [u8; NBYTES], y: &mut [u8; NBYTES]) {
+        self.p.to_affine(x, y);
+    }
+
+    pub fn to_cose_key(&self) -> Option<Vec<u8>> {
+        const EC2_KEY_TYPE: i64 = 2;
+        const P_384_CURVE: i64 = 2;
+        let mut x_bytes = [0; NBYTES];
+        let mut y_bytes = [0; NBYTES];
+        self.to_coordinates(&mut x_bytes, &mut y_bytes);
+        let x_byte_cbor: cbor::Value = cbor_bytes!(x_bytes.to_vec());
+        let y_byte_cbor: cbor::Value = cbor_bytes!(y_bytes.to_vec());
+        let cbor_value = cbor_map_options! {
+            1 => EC2_KEY_TYPE,
+            3 => PubKey::ES384_ALGORITHM,
+            -1 => P_384_CURVE,
+            -2 => x_byte_cbor,
+            -3 => y_byte_cbor,
+        };
+        let mut encoded_key = Vec::new();
+        if cbor::write(cbor_value, &mut encoded_key) {
+            Some(encoded_key)
+        } else {
+            None
+        }
+    }
+
+    pub fn verify_vartime(&self, msg: &[u8], sign: &Signature) -> bool {
+        self.verify_digest_vartime(&Sha384::hash(msg), sign)
+    }
+
+    // Verifies a signature over a digest, e.g. of a message that was hashed in parts.
+    pub fn verify_digest_vartime(&self, digest: &[u8; NBYTES], sign: &Signature) -> bool {
+        let e = Scalar::from_bytes_reduced(digest);
+        let w = sign.s.invert();
+        let u1 = e.mul(&w);
+        let u2 = sign.r.mul(&w);
+        let point = Point::base_point().mul(&u1).add(&self.p.mul(&u2));
+        if point.is_identity() {
+            return false;
+        }
+        Scalar::from_field_element(&point.affine_x()).equals(&sign.r)
+    }
+}
+
+// Deterministic nonce generation from RFC 6979, section 3.2. The private key, the hash and the
+// nonces are all 384 bits long, which simplifies the conversions. Non-empty additional data is
+// mixed into the key as in section 3.6.
+struct Rfc6979 {
+    k: [u8; NBYTES],
+    v: [u8; NBYTES],
+}
+
+// The state determines the next nonces.
+impl Drop for Rfc6979 {
+    fn drop(&mut self) {
+        self.k.zeroize();
+        self.v.zeroize();
+    }
+}
+
+impl Rfc6979 {
+    fn new(x: &Scalar, e: &Scalar, additional_data: &[u8]) -> Rfc6979 {
+        let v = [0x01; NBYTES];
+        let k = [0x00; NBYTES];
+
+        let mut contents = vec![0; 3 * NBYTES + 1];
+        let (contents_v, marker, contents_x, contents_e) = mut_array_refs![
+            array_mut_ref![contents, 0, 3 * NBYTES + 1],
+            NBYTES,
+            1,
+            NBYTES,
+            NBYTES
+        ];
+        contents_v.copy_from_slice(&v);
+        marker[0] = 0x00;
+        x.to_bytes(contents_x);
+        e.to_bytes(contents_e);
+        contents.extend_from_slice(additional_data);
+
+        let k = hmac_384(&k, &contents);
+        let v = hmac_384(&k, &v);
+
+        contents[..NBYTES].copy_from_slice(&v);
+        contents[NBYTES] = 0x01;
+
+        let k = hmac_384(&k, &contents);
+        let v = hmac_384(&k, &v);
+        contents.zeroize();
+
+        Rfc6979 { k, v }
+    }
+
+    // Returns the next candidate nonce, or None if it is out of range.
+    fn next(&mut self) -> Option<Scalar> {
+        self.v = hmac_384(&self.k, &self.v);
+        let result = Scalar::from_bytes_checked(&self.v);
+
+        // Prepare the state in case this nonce is rejected.
+        let mut v1 = [0; NBYTES + 1];
+        v1[..NBYTES].copy_from_slice(&self.v);
+        v1[NBYTES] = 0x00;
+        self.k = hmac_384(&self.k, &v1);
+        self.v = hmac_384(&self.k, &self.v);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::rng256::ThreadRng256;
+    use super::*;
+
+    // Run more test iterations in release mode, as the code should be faster.
+    #[cfg(not(debug_assertions))]
+    const ITERATIONS: u32 = 100;
+    #[cfg(debug_assertions)]
+    const ITERATIONS: u32 = 5;
+
+    fn scalar_from_hex(hex: &str) -> Scalar {
+        let bytes = hex::decode(hex).unwrap();
+        Scalar::from_bytes_checked(array_ref!(bytes, 0, NBYTES)).unwrap()
+    }
+
+    fn signature_to_hex(sign: &Signature) -> (Vec<u8>, Vec<u8>) {
+        let mut bytes = [0; 2 * NBYTES];
+        sign.to_bytes(&mut bytes);
+        (bytes[..NBYTES].to_vec(), bytes[NBYTES..].to_vec())
+    }
+
+    /** RFC 6979 test vectors, section A.2.6 **/
+    const RFC6979_X: &str = "6b9d3dad2e1b8c1c05b19875b6659f4de23c3b667bf297ba\
+                             9aa47740787137d896d5724e4c70a825f872c9ea60d2edf5";
+
+    #[test]
+    fn test_rfc6979_public_key() {
+        let x = scalar_from_hex(RFC6979_X);
+        let pk = PubKey {
+            p: Point::base_point().mul(&x),
+        };
+        let mut x_bytes = [0; NBYTES];
+        let mut y_bytes = [0; NBYTES];
+        pk.to_coordinates(&mut x_bytes, &mut y_bytes);
+        assert_eq!(
+            x_bytes[..],
+            hex::decode(
+                "ec3a4e415b4e19a4568618029f427fa5da9a8bc4ae92e02e\
+                 06aae5286b300c64def8f0ea9055866064a254515480bc13"
+            )
+            .unwrap()[..]
+        );
+        assert_eq!(
+            y_bytes[..],
+            hex::decode(
+                "8015d9b72d7d57244ea8ef9ac0c621896708a59367f9dfb9\
+                 f54ca84b3f1c9db1288b231c3ae0d4fe7344fd2533264720"
+            )
+            .unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn test_rfc6979_sample() {
+        let x = scalar_from_hex(RFC6979_X);
+        let sign = sign_rfc6979_with_scalar(&x, b"sample", &[]);
+        let (r, s) = signature_to_hex(&sign);
+        assert_eq!(
+            r,
+            hex::decode(
+                "94edbb92a5ecb8aad4736e56c691916b3f88140666ce9fa7\
+                 3d64c4ea95ad133c81a648152e44acf96e36dd1e80fabe46"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            s,
+            hex::decode(
+                "99ef4aeb15f178cea1fe40db2603138f130e740a19624526\
+                 203b6351d0a3a94fa329c145786e679e7b82c71a38628ac8"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_rfc6979_test() {
+        let x = scalar_from_hex(RFC6979_X);
+        let sign = sign_rfc6979_with_scalar(&x, b"test", &[]);
+        let (r, s) = signature_to_hex(&sign);
+        assert_eq!(
+            r,
+            hex::decode(
+                "8203b63d3c853e8d77227fb377bcf7b7b772e97892a80f36\
+                 ab775d509d7a5feb0542a7f0812998da8f1dd3ca3cf023db"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            s,
+            hex::decode(
+                "ddd0760448d42d8a43af45af836fce4de8be06b485e9b61b\
+                 827c2f13173923e06a739f040649a667bf3b828246baa5a5"
+            )
+            .unwrap()
+        );
+    }
+
+    /** Key derivation from the seed **/
+    const SEED_PK_X: &str = "7b7f6f74e11633224c43747d874f3b228de79a007e2c93b2\
+                             9517e1f62cc0e4e62223ecce838148276bc42af2d998dfb0";
+    const SEED_PK_Y: &str = "10adad9764a5f16293b809828f8589f8f2b9de3e9a7369fb\
+                             ecfb62d134445d97c7a0874f8fe916900ba39b28c864bdd0";
+
+    #[test]
+    fn test_seckey_from_seed() {
+        // Expected values computed with a Python reference implementation, with the scalar
+        // int.from_bytes(hashlib.sha512(b'\x55' * 32).digest(), 'big') % n.
+        let sk = SecKey::from_bytes(&[0x55; 32]).unwrap();
+        let mut x_bytes = [0; NBYTES];
+        let mut y_bytes = [0; NBYTES];
+        sk.genpk().to_coordinates(&mut x_bytes, &mut y_bytes);
+        assert_eq!(x_bytes[..], hex::decode(SEED_PK_X).unwrap()[..]);
+        assert_eq!(y_bytes[..], hex::decode(SEED_PK_Y).unwrap()[..]);
+    }
+
+    #[test]
+    fn test_seckey_to_bytes_from_bytes() {
+        let mut rng = ThreadRng256 {};
+        let sk = SecKey::gensk(&mut rng);
+        let mut bytes = [0; 32];
+        sk.to_bytes(&mut bytes);
+        assert_eq!(SecKey::from_bytes(&bytes), Some(sk));
+    }
+
+    /** Signature and verification **/
+    #[test]
+    fn test_sign_verify_random() {
+        let mut rng = ThreadRng256 {};
+
+        for _ in 0..ITERATIONS {
+            let sk = SecKey::gensk(&mut rng);
+            let pk = sk.genpk();
+            let msg = rng.gen_uniform_u8x32();
+            let sign = sk.sign_rfc6979(&msg);
+            assert!(pk.verify_vartime(&msg, &sign));
+        }
+    }
+
+    #[test]
+    fn test_sign_hedged_verify_random() {
+        let mut rng = ThreadRng256 {};
+
+        for _ in 0..ITERATIONS {
+            let sk = SecKey::gensk(&mut rng);
+            let pk = sk.genpk();
+            let msg = rng.gen_uniform_u8x32();
+            let sign = sk.sign_hedged(&msg, &mut rng);
+            assert!(pk.verify_vartime(&msg, &sign));
+        }
+    }
+
+    #[test]
+    fn test_sign_hedged_digest_verify() {
+        let mut rng = ThreadRng256 {};
+        let sk = SecKey::gensk(&mut rng);
+        let pk = sk.genpk();
+        let mut hasher = Sha384::new();
+        hasher.update(b"sam");
+        hasher.update(b"ple");
+        let sign = sk.sign_hedged_digest(&hasher.finalize(), &mut rng);
+        assert!(pk.verify_vartime(b"sample", &sign));
+    }
+
+    #[test]
+    fn test_sign_hedged_differs_from_deterministic() {
+        let x = scalar_from_hex(RFC6979_X);
+        let deterministic = signature_to_hex(&sign_rfc6979_with_scalar(&x, b"sample", &[]));
+        let hedged = signature_to_hex(&sign_rfc6979_with_scalar(&x, b"sample", &[0x55; 32]));
+        let other_hedged = signature_to_hex(&sign_rfc6979_with_scalar(&x, b"sample", &[0xAA; 32]));
+        assert_ne!(hedged.0, deterministic.0);
+        assert_ne!(hedged.0, other_hedged.0);
+    }
+
+    #[test]
+    fn test_verify_wrong_message() {
+        let sk = SecKey::from_bytes(&[0x55; 32]).unwrap();
+        let pk = sk.genpk();
+        let sign = sk.sign_rfc6979(b"message");
+        assert!(!pk.verify_vartime(b"massage", &sign));
+    }
+
+    #[test]
+    fn test_verify_wrong_key() {
+        let sk = SecKey::from_bytes(&[0x55; 32]).unwrap();
+        let other_pk = SecKey::from_bytes(&[0xAA; 32]).unwrap().genpk();
+        let sign = sk.sign_rfc6979(b"message");
+        assert!(!other_pk.verify_vartime(b"message", &sign));
+    }
+
+    /** Serialization **/
+    #[test]
+    fn test_signature_to_asn1_der() {
+        let x = scalar_from_hex(RFC6979_X);
+        let sign = sign_rfc6979_with_scalar(&x, b"sample", &[]);
+        let der = sign.to_asn1_der();
+        // Both r and s have their top bit set, so they are prefixed with a zero byte.
+        assert_eq!(der[..5], [0x30, 0x66, 0x02, 0x31, 0x00]);
+        assert_eq!(der[53..56], [0x02, 0x31, 0x00]);
+        assert_eq!(der.len(), 0x68);
+    }
+
+    #[test]
+    fn test_signature_from_bytes() {
+        assert!(Signature::from_bytes(&[0; 2 * NBYTES]).is_none());
+        assert!(Signature::from_bytes(&[0xFF; 2 * NBYTES]).is_none());
+        let x = scalar_from_hex(RFC6979_X);
+        let sign = sign_rfc6979_with_scalar(&x, b"sample", &[]);
+        let mut bytes = [0; 2 * NBYTES];
+        sign.to_bytes(&mut bytes);
+        let mut decoded_bytes = [0; 2 * NBYTES];
+        Signature::from_bytes(&bytes)
+            .unwrap()
+            .to_bytes(&mut decoded_bytes);
+        assert_eq!(decoded_bytes[..], bytes[..]);
+    }
+
+    #[test]
+    fn test_from_coordinates() {
+        let pk = SecKey::from_bytes(&[0x55; 32]).unwrap().genpk();
+        let mut x_bytes = [0; NBYTES];
+        let mut y_bytes = [0; NBYTES];
+        pk.to_coordinates(&mut x_bytes, &mut y_bytes);
+        assert!(PubKey::from_coordinates(&x_bytes, &y_bytes).is_some());
+        y_bytes[NBYTES - 1] ^= 0x01;
+        assert!(PubKey::from_coordinates(&x_bytes, &y_bytes).is_none());
+    }
+
+    #[test]
+    fn test_to_cose_key() {
+        let pk = SecKey::from_bytes(&[0x55; 32]).unwrap().genpk();
+        let cose_key = pk.to_cose_key().unwrap();
+        let mut expected = vec![
+            0xA5, 0x01, 0x02, 0x03, 0x38, 0x22, 0x20, 0x02, 0x21, 0x58, 0x30,
+        ];
+        expected.extend(hex::decode(SEED_PK_X).unwrap());
+        expected.extend(&[0x22, 0x58, 0x30]);
+        expected.extend(hex::decode(SEED_PK_Y).unwrap());
+        assert_eq!(cose_key, expected);
+    }
+}
@@ -0,0 +1,497 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Ed25519 signatures as specified in RFC 8032.
+//
+// The arithmetic follows the TweetNaCl implementation: field elements are represented as 16 limbs
+// of 16 bits, and scalar multiplication uses a constant-time Montgomery ladder over extended
+// twisted Edwards coordinates. Only verification uses variable-time code.
+
+use super::ec::gf25519::Fe;
+use super::rng256::Rng256;
+use super::sha512::Sha512;
+use super::zeroize::Zeroize;
+use alloc::vec::Vec;
+use arrayref::{array_mut_ref, array_ref};
+use cbor::{cbor_bytes, cbor_map_options};
+
+// The curve constant d = -121665/121666.
+const D: Fe = Fe([
+    0x78a3, 0x1359, 0x4dca, 0x75eb, 0xd8ab, 0x4141, 0x0a4d, 0x0070, 0xe898, 0x7779, 0x4079, 0x8cc7,
+    0xfe73, 0x2b6f, 0x6cee, 0x5203,
+]);
+// 2 * d.
+const D2: Fe = Fe([
+    0xf159, 0x26b2, 0x9b94, 0xebd6, 0xb156, 0x8283, 0x149a, 0x00e0, 0xd130, 0xeef3, 0x80f2, 0x198e,
+    0xfce7, 0x56df, 0xd9dc, 0x2406,
+]);
+// Coordinates of the base point.
+const X: Fe = Fe([
+    0xd51a, 0x8f25, 0x2d60, 0xc956, 0xa7b2, 0x9525, 0xc760, 0x692c, 0xdc5c, 0xfdd6, 0xe231, 0xc0a4,
+    0x53fe, 0xcd6e, 0x36d3, 0x2169,
+]);
+const Y: Fe = Fe([
+    0x6658, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666,
+    0x6666, 0x6666, 0x6666, 0x6666,
+]);
+// A square root of -1.
+const I: Fe = Fe([
+    0xa0b0, 0x4a0e, 0x1b27, 0xc4ee, 0xe478, 0xad2f, 0x1806, 0x2f43, 0xd7a7, 0x3dfb, 0x0099, 0x2b4d,
+    0xdf0b, 0x4fc1, 0x2480, 0x2b83,
+]);
+
+// The order of the base point, in little-endian bytes.
+const L: [i64; 32] = [
+    0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+];
+
+// A point on the curve in extended twisted Edwards coordinates.
+#[derive(Clone, Copy)]
+struct Point {
+    x: Fe,
+    y: Fe,
+    z: Fe,
+    t: Fe,
+}
+
+impl Point {
+    const IDENTITY: Point = Point {
+        x: Fe::ZERO,
+        y: Fe::ONE,
+        z: Fe::ONE,
+        t: Fe::ZERO,
+    };
+
+    fn base() -> Point {
+        Point {
+            x: X,
+            y: Y,
+            z: Fe::ONE,
+            t: X.mul(&Y),
+        }
+    }
+
+    fn add(&self, other: &Point) -> Point {
+        let a = self.y.sub(&self.x).mul(&other.y.sub(&other.x));
+        let b = self.x.add(&self.y).mul(&other.x.add(&other.y));
+        let c = self.t.mul(&other.t).mul(&D2);
+        let d = self.z.mul(&other.z);
+        let d = d.add(&d);
+        let e = b.sub(&a);
+        let f = d.sub(&c);
+        let g = d.add(&c);
+        let h = b.add(&a);
+        Point {
+            x: e.mul(&f),
+            y: h.mul(&g),
+            z: g.mul(&f),
+            t: e.mul(&h),
+        }
+    }
+
+    fn cswap(a: &mut Point, b: &mut Point, bit: i64) {
+        Fe::cswap(&mut a.x, &mut b.x, bit);
+        Fe::cswap(&mut a.y, &mut b.y, bit);
+        Fe::cswap(&mut a.z, &mut b.z, bit);
+        Fe::cswap(&mut a.t, &mut b.t, bit);
+    }
+
+    // Computes scalar * self with a constant-time Montgomery ladder.
+    fn mul(&self, scalar: &[u8; 32]) -> Point {
+        let mut p = Point::IDENTITY;
+        let mut q = *self;
+        for i in (0..256).rev() {
+            let bit = ((scalar[i / 8] >> (i & 7)) & 1) as i64;
+            Point::cswap(&mut p, &mut q, bit);
+            q = q.add(&p);
+            p = p.add(&p);
+            Point::cswap(&mut p, &mut q, bit);
+        }
+        p
+    }
+
+    fn to_bytes(self) -> [u8; 32] {
+        let zi = self.z.invert();
+        let x = self.x.mul(&zi);
+        let y = self.y.mul(&zi);
+        let mut bytes = y.to_bytes();
+        bytes[31] ^= x.parity() << 7;
+        bytes
+    }
+
+    // Decodes a point and returns its negation, or None if the encoding is not a valid point.
+    fn from_bytes_negate_vartime(bytes: &[u8; 32]) -> Option<Point> {
+        let y = Fe::from_bytes(bytes);
+        let y2 = y.square();
+        let num = y2.sub(&Fe::ONE);
+        let den = y2.mul(&D).add(&Fe::ONE);
+        let den2 = den.square();
+        let den4 = den2.square();
+        let den6 = den4.mul(&den2);
+
+        let mut x = den6.mul(&num).mul(&den).pow2523();
+        x = x.mul(&num).mul(&den).mul(&den2);
+        if !x.square().mul(&den).equals_vartime(&num) {
+            x = x.mul(&I);
+        }
+        if !x.square().mul(&den).equals_vartime(&num) {
+            return None;
+        }
+        if x.parity() == bytes[31] >> 7 {
+            x = Fe::ZERO.sub(&x);
+        }
+        Some(Point {
+            x,
+            y,
+            z: Fe::ONE,
+            t: x.mul(&y),
+        })
+    }
+}
+
+// Reduces a little-endian number of 64 limbs modulo L.
+fn mod_l(x: &mut [i64; 64]) -> [u8; 32] {
+    for i in (32..64).rev() {
+        let mut carry = 0;
+        for j in (i - 32)..(i - 12) {
+            x[j] += carry - 16 * x[i] * L[j - (i - 32)];
+            carry = (x[j] + 128) >> 8;
+            x[j] -= carry << 8;
+        }
+        x[i - 12] += carry;
+        x[i] = 0;
+    }
+    let mut carry = 0;
+    for j in 0..32 {
+        x[j] += carry - (x[31] >> 4) * L[j];
+        carry = x[j] >> 8;
+        x[j] &= 0xff;
+    }
+    for j in 0..32 {
+        x[j] -= carry * L[j];
+    }
+    let mut result = [0; 32];
+    for i in 0..32 {
+        x[i + 1] += x[i] >> 8;
+        result[i] = (x[i] & 0xff) as u8;
+    }
+    result
+}
+
+fn reduce(hash: &[u8; 64]) -> [u8; 32] {
+    let mut x = [0; 64];
+    for (limb, byte) in x.iter_mut().zip(hash.iter()) {
+        *limb = *byte as i64;
+    }
+    mod_l(&mut x)
+}
+
+// Checks that the scalar is strictly smaller than L, to reject malleable signatures.
+fn is_canonical_scalar(scalar: &[u8; 32]) -> bool {
+    for i in (0..32).rev() {
+        let limb = L[i] as u8;
+        if scalar[i] < limb {
+            return true;
+        }
+        if scalar[i] > limb {
+            return false;
+        }
+    }
+    false
+}
+
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "derive_debug", derive(Debug))]
+pub struct SecKey {
+    seed: [u8; 32],
+}
+
+pub struct Signature {
+    bytes: [u8; 64],
+}
+
+pub struct PubKey {
+    bytes: [u8; 32],
+}
+
+impl Drop for SecKey {
+    fn drop(&mut self) {
+        self.seed.zeroize();
+    }
+}
+
+impl SecKey {
+    pub fn gensk<R>(rng: &mut R) -> SecKey
+    where
+        R: Rng256,
+    {
+        SecKey {
+            seed: rng.gen_uniform_u8x32(),
+        }
+    }
+
+    // Every 32-byte string is a valid seed.
+    pub fn from_bytes(bytes: &[u8; 32]) -> SecKey {
+        SecKey { seed: *bytes }
+    }
+
+    pub fn to_bytes(&self, bytes: &mut [u8; 32]) {
+        bytes.copy_from_slice(&self.seed);
+    }
+
+    pub fn genpk(&self) -> PubKey {
+        let (mut scalar, mut prefix) = self.expand();
+        let bytes = Point::base().mul(&scalar).to_bytes();
+        scalar.zeroize();
+        prefix.zeroize();
+        PubKey { bytes }
+    }
+
+    // Deterministic signature, as per RFC 8032 section 5.1.6.
+    pub fn sign(&self, msg: &[u8]) -> Signature {
+        let (mut scalar, mut prefix) = self.expand();
+        let public_key = Point::base().mul(&scalar).to_bytes();
+
+        let mut hasher = Sha512::new();
+        hasher.update(&prefix);
+        hasher.update(msg);
+        let mut r = reduce(&hasher.finalize());
+        let big_r = Point::base().mul(&r).to_bytes();
+
+        let mut hasher = Sha512::new();
+        hasher.update(&big_r);
+        hasher.update(&public_key);
+        hasher.update(msg);
+        let k = reduce(&hasher.finalize());
+
+        let mut x = [0; 64];
+        for i in 0..32 {
+            x[i] = r[i] as i64;
+        }
+        for i in 0..32 {
+            for j in 0..32 {
+                x[i + j] += k[i] as i64 * scalar[j] as i64;
+            }
+        }
+        let s = mod_l(&mut x);
+        // The nonce reveals the key, with the signature.
+        scalar.zeroize();
+        prefix.zeroize();
+        r.zeroize();
+        x.zeroize();
+
+        let mut bytes = [0; 64];
+        array_mut_ref![bytes, 0, 32].copy_from_slice(&big_r);
+        array_mut_ref![bytes, 32, 32].copy_from_slice(&s);
+        Signature { bytes }
+    }
+
+    // Returns the clamped secret scalar and the nonce prefix derived from the seed.
+    fn expand(&self) -> ([u8; 32], [u8; 32]) {
+        let mut hash = Sha512::hash(&self.seed);
+        let mut scalar = *array_ref!(hash, 0, 32);
+        scalar[0] &= 0xf8;
+        scalar[31] &= 0x7f;
+        scalar[31] |= 0x40;
+        let prefix = *array_ref!(hash, 32, 32);
+        hash.zeroize();
+        (scalar, prefix)
+    }
+}
+
+impl Signature {
+    pub const LENGTH: usize = 64;
+
+    pub fn from_bytes(bytes: &[u8; 64]) -> Signature {
+        Signature { bytes: *bytes }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 64] {
+        self.bytes
+    }
+}
+
+impl PubKey {
+    pub const EDDSA_ALGORITHM: i64 = -8;
+
+    // The encoding is only validated when verifying signatures.
+    pub fn from_bytes(bytes: &[u8; 32]) -> PubKey {
+        PubKey { bytes: *bytes }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.bytes
+    }
+
+    // Encodes the key as a COSE_Key of type OKP, see RFC 8152 section 13.2.
+    pub fn to_cose_key(&self) -> Option<Vec<u8>> {
+        const OKP_KEY_TYPE: i64 = 1;
+        const ED25519_CURVE: i64 = 6;
+        let x_byte_cbor: cbor::Value = cbor_bytes!(self.bytes.to_vec());
+        let cbor_value = cbor_map_options! {
+            1 => OKP_KEY_TYPE,
+            3 => PubKey::EDDSA_ALGORITHM,
+            -1 => ED25519_CURVE,
+            -2 => x_byte_cbor,
+        };
+        let mut encoded_key = Vec::new();
+        if cbor::write(cbor_value, &mut encoded_key) {
+            Some(encoded_key)
+        } else {
+            None
+        }
+    }
+
+    pub fn verify_vartime(&self, msg: &[u8], sign: &Signature) -> bool {
+        let s = array_ref!(sign.bytes, 32, 32);
+        if !is_canonical_scalar(s) {
+            return false;
+        }
+        let negated_key = match Point::from_bytes_negate_vartime(&self.bytes) {
+            Some(point) => point,
+            None => return false,
+        };
+
+        let mut hasher = Sha512::new();
+        hasher.update(array_ref!(sign.bytes, 0, 32));
+        hasher.update(&self.bytes);
+        hasher.update(msg);
+        let k = reduce(&hasher.finalize());
+
+        let big_r = negated_key.mul(&k).add(&Point::base().mul(s));
+        big_r.to_bytes() == *array_ref!(sign.bytes, 0, 32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::rng256::ThreadRng256;
+    use super::*;
+
+    // Run more test iterations in release mode, as the code should be faster.
+    #[cfg(not(debug_assertions))]
+    const ITERATIONS: u32 = 1000;
+    #[cfg(debug_assertions)]
+    const ITERATIONS: u32 = 50;
+
+    fn check_rfc8032_vector(seed: &str, public_key: &str, msg: &str, signature: &str) {
+        let seed = hex::decode(seed).unwrap();
+        let sk = SecKey::from_bytes(array_ref!(seed, 0, 32));
+        let pk = sk.genpk();
+        assert_eq!(pk.to_bytes()[..], hex::decode(public_key).unwrap()[..]);
+        let msg = hex::decode(msg).unwrap();
+        let sign = sk.sign(&msg);
+        assert_eq!(sign.to_bytes()[..], hex::decode(signature).unwrap()[..]);
+        assert!(pk.verify_vartime(&msg, &sign));
+    }
+
+    /** RFC 8032 test vectors, section 7.1 **/
+    #[test]
+    fn test_rfc8032_empty_message() {
+        check_rfc8032_vector(
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+            "",
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        );
+    }
+
+    #[test]
+    fn test_rfc8032_one_byte_message() {
+        check_rfc8032_vector(
+            "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+            "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+            "72",
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+        );
+    }
+
+    #[test]
+    fn test_rfc8032_two_byte_message() {
+        check_rfc8032_vector(
+            "c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+            "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+            "af82",
+            "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+        );
+    }
+
+    /** Serialization **/
+    #[test]
+    fn test_seckey_to_bytes_from_bytes() {
+        let mut rng = ThreadRng256 {};
+
+        for _ in 0..ITERATIONS {
+            let sk = SecKey::gensk(&mut rng);
+            let mut bytes = [0; 32];
+            sk.to_bytes(&mut bytes);
+            assert_eq!(SecKey::from_bytes(&bytes), sk);
+        }
+    }
+
+    #[test]
+    fn test_to_cose_key() {
+        let sk = SecKey::from_bytes(&[0x55; 32]);
+        let pk = sk.genpk();
+        let mut expected = vec![0xA4, 0x01, 0x01, 0x03, 0x27, 0x20, 0x06, 0x21, 0x58, 0x20];
+        expected.extend_from_slice(&pk.to_bytes());
+        assert_eq!(pk.to_cose_key(), Some(expected));
+    }
+
+    /** Signature and verification **/
+    #[test]
+    fn test_sign_verify_random() {
+        let mut rng = ThreadRng256 {};
+
+        for _ in 0..ITERATIONS {
+            let sk = SecKey::gensk(&mut rng);
+            let pk = sk.genpk();
+            let msg = rng.gen_uniform_u8x32();
+            let sign = sk.sign(&msg);
+            assert!(pk.verify_vartime(&msg, &sign));
+        }
+    }
+
+    #[test]
+    fn test_verify_wrong_message() {
+        let sk = SecKey::from_bytes(&[0x55; 32]);
+        let pk = sk.genpk();
+        let sign = sk.sign(b"message");
+        assert!(!pk.verify_vartime(b"massage", &sign));
+    }
+
+    #[test]
+    fn test_verify_wrong_key() {
+        let sk = SecKey::from_bytes(&[0x55; 32]);
+        let other_pk = SecKey::from_bytes(&[0xAA; 32]).genpk();
+        let sign = sk.sign(b"message");
+        assert!(!other_pk.verify_vartime(b"message", &sign));
+    }
+
+    #[test]
+    fn test_verify_rejects_non_canonical_scalar() {
+        let sk = SecKey::from_bytes(&[0x55; 32]);
+        let pk = sk.genpk();
+        let mut bytes = sk.sign(b"message").to_bytes();
+        // Adding L to S gives an equivalent but non-canonical signature.
+        let mut carry = 0;
+        for i in 0..32 {
+            let sum = bytes[32 + i] as i64 + L[i] + carry;
+            bytes[32 + i] = sum as u8;
+            carry = sum >> 8;
+        }
+        assert!(!pk.verify_vartime(b"message", &Signature::from_bytes(&bytes)));
+    }
+}
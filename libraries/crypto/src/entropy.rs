@@ -0,0 +1,342 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Entropy pool over a hardware noise source, with the health tests of NIST SP 800-90B.
+//
+// Raw samples pass the continuous health tests of section 4.4 before they are conditioned into the
+// pool with HMAC-SHA256. A failed test is permanent: the pool never produces output again, since
+// keys generated from a degraded source would be predictable. Additional input, such as a seed
+// persisted by the previous boot, is mixed into the pool but never replaces fresh samples.
+
+use super::hmac::hmac_256;
+use super::rng256::Rng256;
+use super::sha256::Sha256;
+use super::zeroize::Zeroize;
+use alloc::vec::Vec;
+use libtock_drivers::rng;
+
+// The min-entropy per sample byte that the tests and the conditioning assume, in bits.
+const MIN_ENTROPY_BITS: usize = 4;
+// Section 3.1.5.1.2: the conditioned input has 64 bits of entropy more than the output.
+const SAMPLES_PER_OUTPUT: usize = (256 + 64) / MIN_ENTROPY_BITS;
+// Section 4.3: at startup, 1024 samples are tested and discarded.
+const STARTUP_SAMPLES: usize = 1024;
+// A source that can't deliver samples is retried this many times before failing.
+const MAX_FILL_ATTEMPTS: usize = 3;
+
+// Section 4.4.1, for a false positive probability of 2^-20: 1 + ceil(20 / H).
+const REPETITION_COUNT_CUTOFF: usize = 6;
+// Section 4.4.2, for non-binary samples and a false positive probability of 2^-20:
+// 1 + CRITBINOM(512, 2^-H, 1 - 2^-20).
+const ADAPTIVE_PROPORTION_WINDOW: usize = 512;
+const ADAPTIVE_PROPORTION_CUTOFF: usize = 62;
+
+// Domain separation of the inputs and outputs of the pool.
+const SAMPLES_LABEL: u8 = 0x00;
+const ADDITIONAL_INPUT_LABEL: u8 = 0x01;
+const OUTPUT_LABEL: u8 = 0x02;
+const NEXT_STATE_LABEL: u8 = 0x03;
+
+pub trait EntropySource {
+    // Fills the buffer with raw samples. Returns false if the source failed to do so.
+    fn fill(&mut self, samples: &mut [u8]) -> bool;
+}
+
+// The noise source behind the TockOS rng driver.
+pub struct TockEntropySource {}
+
+impl EntropySource for TockEntropySource {
+    fn fill(&mut self, samples: &mut [u8]) -> bool {
+        rng::fill_buffer(samples)
+    }
+}
+
+// The repetition count and adaptive proportion tests, run on every sample.
+struct HealthTests {
+    last_sample: u8,
+    repetition_count: usize,
+    window_sample: u8,
+    window_count: usize,
+    window_position: usize,
+    failed: bool,
+}
+
+impl HealthTests {
+    fn new() -> HealthTests {
+        HealthTests {
+            last_sample: 0,
+            repetition_count: 0,
+            window_sample: 0,
+            window_count: 0,
+            window_position: 0,
+            failed: false,
+        }
+    }
+
+    // Returns whether all samples so far passed the tests.
+    fn test(&mut self, samples: &[u8]) -> bool {
+        for &sample in samples {
+            if self.repetition_count > 0 && sample == self.last_sample {
+                self.repetition_count += 1;
+            } else {
+                self.last_sample = sample;
+                self.repetition_count = 1;
+            }
+            if self.repetition_count >= REPETITION_COUNT_CUTOFF {
+                self.failed = true;
+            }
+
+            if self.window_position == 0 {
+                self.window_sample = sample;
+                self.window_count = 1;
+            } else if sample == self.window_sample {
+                self.window_count += 1;
+            }
+            if self.window_count >= ADAPTIVE_PROPORTION_CUTOFF {
+                self.failed = true;
+            }
+            self.window_position = (self.window_position + 1) % ADAPTIVE_PROPORTION_WINDOW;
+        }
+        !self.failed
+    }
+}
+
+pub struct EntropyPool<S: EntropySource> {
+    source: S,
+    tests: HealthTests,
+    state: [u8; 32],
+}
+
+impl<S: EntropySource> Drop for EntropyPool<S> {
+    fn drop(&mut self) {
+        self.state.zeroize();
+    }
+}
+
+impl<S: EntropySource> EntropyPool<S> {
+    // Runs the startup tests on the source. The pool is unhealthy if they fail.
+    pub fn new(source: S) -> EntropyPool<S> {
+        let mut pool = EntropyPool {
+            source,
+            tests: HealthTests::new(),
+            state: [0; 32],
+        };
+        let mut samples = [0; STARTUP_SAMPLES];
+        if pool.sample(&mut samples) {
+            pool.absorb(SAMPLES_LABEL, &samples);
+        }
+        samples.zeroize();
+        pool
+    }
+
+    // Returns 32 bytes conditioned from fresh samples, or None if the source is unhealthy.
+    pub fn try_gen_uniform_u8x32(&mut self) -> Option<[u8; 32]> {
+        let mut samples = [0; SAMPLES_PER_OUTPUT];
+        let healthy = self.sample(&mut samples);
+        if healthy {
+            self.absorb(SAMPLES_LABEL, &samples);
+        }
+        samples.zeroize();
+        if !healthy {
+            return None;
+        }
+        let output = hmac_256::<Sha256>(&self.state, &[OUTPUT_LABEL]);
+        // Outputs can't be used to recover earlier outputs.
+        self.state = hmac_256::<Sha256>(&self.state, &[NEXT_STATE_LABEL]);
+        Some(output)
+    }
+
+    // Fills the buffer with samples that passed the health tests.
+    fn sample(&mut self, samples: &mut [u8]) -> bool {
+        if self.tests.failed {
+            return false;
+        }
+        let filled = (0..MAX_FILL_ATTEMPTS).any(|_| self.source.fill(samples));
+        if !filled {
+            self.tests.failed = true;
+            return false;
+        }
+        self.tests.test(samples)
+    }
+
+    fn absorb(&mut self, label: u8, input: &[u8]) {
+        let mut message = Vec::with_capacity(1 + input.len());
+        message.push(label);
+        message.extend_from_slice(input);
+        self.state = hmac_256::<Sha256>(&self.state, &message);
+        message.zeroize();
+    }
+}
+
+impl<S: EntropySource> Rng256 for EntropyPool<S> {
+    // Halts rather than returning output that may be biased.
+    fn gen_uniform_u8x32(&mut self) -> [u8; 32] {
+        match self.try_gen_uniform_u8x32() {
+            Some(output) => output,
+            None => panic!("Entropy source failed its health tests"),
+        }
+    }
+
+    // The input is never credited with any entropy.
+    fn add_input(&mut self, input: &[u8]) {
+        self.absorb(ADDITIONAL_INPUT_LABEL, input);
+    }
+
+    fn is_healthy(&self) -> bool {
+        !self.tests.failed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::rng256::ThreadRng256;
+    use super::*;
+
+    // Full entropy samples.
+    struct ThreadSource {}
+
+    impl EntropySource for ThreadSource {
+        fn fill(&mut self, samples: &mut [u8]) -> bool {
+            let mut rng = ThreadRng256 {};
+            for chunk in samples.chunks_mut(32) {
+                chunk.copy_from_slice(&rng.gen_uniform_u8x32()[..chunk.len()]);
+            }
+            true
+        }
+    }
+
+    // Replays the same samples, starting after the given number of good samples.
+    struct FaultySource {
+        good_samples: usize,
+        fault: fn(usize) -> u8,
+        position: usize,
+    }
+
+    impl EntropySource for FaultySource {
+        fn fill(&mut self, samples: &mut [u8]) -> bool {
+            ThreadSource {}.fill(samples);
+            for sample in samples.iter_mut() {
+                if self.position >= self.good_samples {
+                    *sample = (self.fault)(self.position);
+                }
+                self.position += 1;
+            }
+            true
+        }
+    }
+
+    // Deterministic samples, so that outputs only differ by the additional input.
+    struct ConstantSource {}
+
+    impl EntropySource for ConstantSource {
+        fn fill(&mut self, samples: &mut [u8]) -> bool {
+            for (i, sample) in samples.iter_mut().enumerate() {
+                *sample = i as u8;
+            }
+            true
+        }
+    }
+
+    #[test]
+    fn test_healthy_source() {
+        let mut pool = EntropyPool::new(ThreadSource {});
+        assert!(pool.is_healthy());
+        let first = pool.try_gen_uniform_u8x32().unwrap();
+        let second = pool.gen_uniform_u8x32();
+        assert_ne!(first, second);
+        assert!(pool.is_healthy());
+    }
+
+    #[test]
+    fn test_stuck_source_fails_repetition_count() {
+        let source = FaultySource {
+            good_samples: STARTUP_SAMPLES + 10,
+            fault: |_| 0x55,
+            position: 0,
+        };
+        let mut pool = EntropyPool::new(source);
+        assert!(pool.is_healthy());
+        assert_eq!(pool.try_gen_uniform_u8x32(), None);
+        assert!(!pool.is_healthy());
+        // The failure is permanent.
+        pool.source.good_samples = usize::MAX;
+        assert_eq!(pool.try_gen_uniform_u8x32(), None);
+    }
+
+    #[test]
+    fn test_biased_source_fails_adaptive_proportion() {
+        // One sample in four is the same value, but never twice in a row.
+        let source = FaultySource {
+            good_samples: 0,
+            fault: |position| {
+                if position % 4 == 0 {
+                    0x55
+                } else {
+                    position as u8 | 0x80
+                }
+            },
+            position: 0,
+        };
+        let pool = EntropyPool::new(source);
+        assert!(pool.tests.repetition_count < REPETITION_COUNT_CUTOFF);
+        assert!(!pool.is_healthy());
+    }
+
+    #[test]
+    fn test_failing_source() {
+        struct FailingSource {}
+        impl EntropySource for FailingSource {
+            fn fill(&mut self, _samples: &mut [u8]) -> bool {
+                false
+            }
+        }
+        let mut pool = EntropyPool::new(FailingSource {});
+        assert!(!pool.is_healthy());
+        assert_eq!(pool.try_gen_uniform_u8x32(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_unhealthy_pool_halts() {
+        let source = FaultySource {
+            good_samples: 0,
+            fault: |_| 0x00,
+            position: 0,
+        };
+        EntropyPool::new(source).gen_uniform_u8x32();
+    }
+
+    #[test]
+    fn test_additional_input_changes_output() {
+        let mut pool = EntropyPool {
+            source: ConstantSource {},
+            tests: HealthTests::new(),
+            state: [0; 32],
+        };
+        let mut seeded_pool = EntropyPool {
+            source: ConstantSource {},
+            tests: HealthTests::new(),
+            state: [0; 32],
+        };
+        seeded_pool.add_input(&[0x55; 32]);
+        let output = pool.try_gen_uniform_u8x32().unwrap();
+        let seeded_output = seeded_pool.try_gen_uniform_u8x32().unwrap();
+        assert_ne!(output, seeded_output);
+        seeded_pool.state = pool.state;
+        assert_eq!(
+            pool.try_gen_uniform_u8x32(),
+            seeded_pool.try_gen_uniform_u8x32()
+        );
+    }
+}
@@ -0,0 +1,275 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Galois/Counter Mode from NIST SP 800-38D, restricted to 96-bit nonces and 128-bit tags.
+
+use super::constant_time::bytes_eq;
+use super::util::{xor_block_16, Block16};
+use super::Encrypt16BytesBlock;
+use arrayref::array_ref;
+
+pub const NONCE_SIZE: usize = 12;
+pub const TAG_SIZE: usize = 16;
+
+// Encrypts the data in place, and returns the tag that authenticates it with the additional data.
+// A nonce must never be reused with the same key.
+pub fn gcm_encrypt<K>(key: &K, nonce: &[u8; NONCE_SIZE], aad: &[u8], data: &mut [u8]) -> Block16
+where
+    K: Encrypt16BytesBlock,
+{
+    let gcm = Gcm::new(key, nonce);
+    gcm.apply_keystream(data);
+    gcm.tag(aad, data)
+}
+
+// Checks the tag, then decrypts the data in place. If the tag doesn't match, returns false and
+// leaves the data untouched.
+pub fn gcm_decrypt<K>(
+    key: &K,
+    nonce: &[u8; NONCE_SIZE],
+    aad: &[u8],
+    data: &mut [u8],
+    tag: &Block16,
+) -> bool
+where
+    K: Encrypt16BytesBlock,
+{
+    let gcm = Gcm::new(key, nonce);
+    if !bytes_eq(&gcm.tag(aad, data), tag) {
+        return false;
+    }
+    gcm.apply_keystream(data);
+    true
+}
+
+struct Gcm<'a, K> {
+    key: &'a K,
+    // The hash subkey H, as a polynomial. Bits are in the order of the specification, with the
+    // first bit of the block as most significant bit.
+    hash_key: u128,
+    // The pre-counter block J0.
+    counter0: Block16,
+}
+
+impl<'a, K> Gcm<'a, K>
+where
+    K: Encrypt16BytesBlock,
+{
+    fn new(key: &'a K, nonce: &[u8; NONCE_SIZE]) -> Gcm<'a, K> {
+        let mut hash_key = [0; 16];
+        key.encrypt_block(&mut hash_key);
+        let mut counter0 = [0; 16];
+        counter0[..NONCE_SIZE].copy_from_slice(nonce);
+        counter0[15] = 0x01;
+        Gcm {
+            key,
+            hash_key: u128::from_be_bytes(hash_key),
+            counter0,
+        }
+    }
+
+    // Encryption and decryption are the same XOR with the keystream, starting at inc32(J0).
+    fn apply_keystream(&self, data: &mut [u8]) {
+        let mut counter = self.counter0;
+        for chunk in data.chunks_mut(16) {
+            increment_counter(&mut counter);
+            let mut keystream = counter;
+            self.key.encrypt_block(&mut keystream);
+            for (byte, key_byte) in chunk.iter_mut().zip(keystream.iter()) {
+                *byte ^= key_byte;
+            }
+        }
+    }
+
+    fn tag(&self, aad: &[u8], ciphertext: &[u8]) -> Block16 {
+        let mut hash = 0;
+        self.ghash_padded(&mut hash, aad);
+        self.ghash_padded(&mut hash, ciphertext);
+        let lengths = ((aad.len() as u128 * 8) << 64) | (ciphertext.len() as u128 * 8);
+        hash = gf128_mul(hash ^ lengths, self.hash_key);
+
+        let mut tag = hash.to_be_bytes();
+        let mut mask = self.counter0;
+        self.key.encrypt_block(&mut mask);
+        xor_block_16(&mut tag, &mask);
+        tag
+    }
+
+    // Absorbs the data, padded with zeros to a multiple of the block size.
+    fn ghash_padded(&self, hash: &mut u128, data: &[u8]) {
+        for chunk in data.chunks(16) {
+            let mut block = [0; 16];
+            block[..chunk.len()].copy_from_slice(chunk);
+            *hash = gf128_mul(*hash ^ u128::from_be_bytes(block), self.hash_key);
+        }
+    }
+}
+
+// Increments the last 32 bits of the block, modulo 2^32.
+fn increment_counter(counter: &mut Block16) {
+    let value = u32::from_be_bytes(*array_ref![counter, 12, 4]).wrapping_add(1);
+    counter[12..].copy_from_slice(&value.to_be_bytes());
+}
+
+// Multiplication in GF(2^128), as in algorithm 1 of the specification. The running time doesn't
+// depend on the operands, since the hash key is secret.
+fn gf128_mul(x: u128, y: u128) -> u128 {
+    const R: u128 = 0xE1 << 120;
+    let mut z = 0;
+    let mut v = y;
+    for i in (0..128).rev() {
+        let x_bit = 0u128.wrapping_sub((x >> i) & 1);
+        z ^= v & x_bit;
+        let v_bit = 0u128.wrapping_sub(v & 1);
+        v = (v >> 1) ^ (R & v_bit);
+    }
+    z
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::aes256;
+    use super::*;
+
+    // (key, nonce, plaintext, aad, ciphertext, tag)
+    type TestVector = (
+        &'static str,
+        &'static str,
+        &'static str,
+        &'static str,
+        &'static str,
+        &'static str,
+    );
+
+    // Test cases 13 to 16 of "The Galois/Counter Mode of Operation (GCM)", McGrew and Viega.
+    const TEST_VECTORS: [TestVector; 4] = [
+        (
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "000000000000000000000000",
+            "",
+            "",
+            "",
+            "530f8afbc74536b9a963b4f1c4cb738b",
+        ),
+        (
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "000000000000000000000000",
+            "00000000000000000000000000000000",
+            "",
+            "cea7403d4d606b6e074ec5d3baf39d18",
+            "d0d1c8a799996bf0265b98b5d48ab919",
+        ),
+        (
+            "feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308",
+            "cafebabefacedbaddecaf888",
+            "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+             1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b391aafd255",
+            "",
+            "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa\
+             8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662898015ad",
+            "b094dac5d93471bdec1a502270e3cc6c",
+        ),
+        (
+            "feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308",
+            "cafebabefacedbaddecaf888",
+            "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+             1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39",
+            "feedfacedeadbeeffeedfacedeadbeefabaddad2",
+            "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa\
+             8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662",
+            "76fc6ece0f4e1768cddf8853bb2d551b",
+        ),
+    ];
+
+    fn key_from_hex(key: &str) -> aes256::EncryptionKey {
+        let key = hex::decode(key).unwrap();
+        aes256::EncryptionKey::new(array_ref!(key, 0, 32))
+    }
+
+    fn nonce_from_hex(nonce: &str) -> [u8; NONCE_SIZE] {
+        let nonce = hex::decode(nonce).unwrap();
+        *array_ref!(nonce, 0, NONCE_SIZE)
+    }
+
+    #[test]
+    fn test_gcm_encrypt_vectors() {
+        for (key, nonce, plaintext, aad, ciphertext, tag) in TEST_VECTORS.iter() {
+            let mut data = hex::decode(plaintext).unwrap();
+            let computed_tag = gcm_encrypt(
+                &key_from_hex(key),
+                &nonce_from_hex(nonce),
+                &hex::decode(aad).unwrap(),
+                &mut data,
+            );
+            assert_eq!(data, hex::decode(ciphertext).unwrap());
+            assert_eq!(computed_tag.to_vec(), hex::decode(tag).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_gcm_decrypt_vectors() {
+        for (key, nonce, plaintext, aad, ciphertext, tag) in TEST_VECTORS.iter() {
+            let mut data = hex::decode(ciphertext).unwrap();
+            let tag = hex::decode(tag).unwrap();
+            assert!(gcm_decrypt(
+                &key_from_hex(key),
+                &nonce_from_hex(nonce),
+                &hex::decode(aad).unwrap(),
+                &mut data,
+                array_ref!(tag, 0, TAG_SIZE),
+            ));
+            assert_eq!(data, hex::decode(plaintext).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_gcm_decrypt_rejects_modifications() {
+        let key = aes256::EncryptionKey::new(&[0x55; 32]);
+        let nonce = [0xAA; NONCE_SIZE];
+        let mut ciphertext = b"plaintext of several blocks, not aligned".to_vec();
+        let tag = gcm_encrypt(&key, &nonce, b"aad", &mut ciphertext);
+
+        let mut data = ciphertext.clone();
+        data[20] ^= 0x01;
+        assert!(!gcm_decrypt(&key, &nonce, b"aad", &mut data, &tag));
+        // The data is left untouched on failure.
+        assert_eq!(data[21..], ciphertext[21..]);
+
+        let mut data = ciphertext.clone();
+        assert!(!gcm_decrypt(&key, &nonce, b"aae", &mut data, &tag));
+        assert!(!gcm_decrypt(
+            &key,
+            &[0xAB; NONCE_SIZE],
+            b"aad",
+            &mut data,
+            &tag
+        ));
+        let mut wrong_tag = tag;
+        wrong_tag[15] ^= 0x80;
+        assert!(!gcm_decrypt(&key, &nonce, b"aad", &mut data, &wrong_tag));
+        assert_eq!(data, ciphertext);
+
+        assert!(gcm_decrypt(&key, &nonce, b"aad", &mut data, &tag));
+        assert_eq!(data, b"plaintext of several blocks, not aligned".to_vec());
+    }
+
+    #[test]
+    fn test_increment_counter_wraps() {
+        let mut counter = [0xFF; 16];
+        increment_counter(&mut counter);
+        let mut expected = [0xFF; 16];
+        expected[12..].copy_from_slice(&[0x00; 4]);
+        assert_eq!(counter, expected);
+    }
+}
@@ -0,0 +1,265 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::hmac::hmac_256;
+use super::zeroize::Zeroize;
+use super::{Hash256, HashBlockSize64Bytes};
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+const HASH_SIZE: usize = 32;
+// RFC 5869 section 2.3: the block counter is a single byte, starting at 1.
+pub const MAX_OUTPUT_LENGTH: usize = 255 * HASH_SIZE;
+
+// Computes the pseudorandom key of RFC 5869 section 2.2.
+//
+// An empty salt is equivalent to a salt of HASH_SIZE zero bytes, since HMAC pads its key with
+// zeros.
+pub fn hkdf_extract<H>(salt: &[u8], ikm: &[u8]) -> [u8; HASH_SIZE]
+where
+    H: Hash256 + HashBlockSize64Bytes,
+{
+    hmac_256::<H>(salt, ikm)
+}
+
+// Streams the output keying material of RFC 5869 section 2.3.
+//
+// Callers read as many bytes as they need, in as many calls as they like, without buffering the
+// whole output. Consecutive reads return consecutive bytes of the output.
+pub struct HkdfExpand<'a, H> {
+    prk: [u8; HASH_SIZE],
+    info: &'a [u8],
+    // The last block T(counter), of which the bytes from offset on were not read yet.
+    block: [u8; HASH_SIZE],
+    counter: u8,
+    offset: usize,
+    // The number of bytes read so far.
+    length: usize,
+    hash: PhantomData<H>,
+}
+
+impl<'a, H> Drop for HkdfExpand<'a, H> {
+    fn drop(&mut self) {
+        self.prk.zeroize();
+        self.block.zeroize();
+    }
+}
+
+impl<'a, H> HkdfExpand<'a, H>
+where
+    H: Hash256 + HashBlockSize64Bytes,
+{
+    pub fn new(prk: &[u8; HASH_SIZE], info: &'a [u8]) -> HkdfExpand<'a, H> {
+        HkdfExpand {
+            prk: *prk,
+            info,
+            block: [0; HASH_SIZE],
+            counter: 0,
+            offset: HASH_SIZE,
+            length: 0,
+            hash: PhantomData,
+        }
+    }
+
+    // Fills the buffer with the next bytes of output. Returns false without writing anything if
+    // the total output would exceed MAX_OUTPUT_LENGTH.
+    pub fn fill(&mut self, okm: &mut [u8]) -> bool {
+        if okm.len() > MAX_OUTPUT_LENGTH - self.length {
+            return false;
+        }
+        let mut written = 0;
+        while written < okm.len() {
+            if self.offset == HASH_SIZE {
+                self.next_block();
+            }
+            let chunk_length = core::cmp::min(HASH_SIZE - self.offset, okm.len() - written);
+            okm[written..written + chunk_length]
+                .copy_from_slice(&self.block[self.offset..self.offset + chunk_length]);
+            self.offset += chunk_length;
+            written += chunk_length;
+        }
+        self.length += okm.len();
+        true
+    }
+
+    // Computes T(counter + 1) = HMAC(PRK, T(counter) | info | counter + 1), with an empty T(0).
+    fn next_block(&mut self) {
+        let mut message = Vec::with_capacity(HASH_SIZE + self.info.len() + 1);
+        if self.counter > 0 {
+            message.extend_from_slice(&self.block);
+        }
+        message.extend_from_slice(self.info);
+        self.counter += 1;
+        message.push(self.counter);
+        self.block = hmac_256::<H>(&self.prk, &message);
+        self.offset = 0;
+    }
+}
+
+// Fills the buffer with the output of the expand step. Returns false if the buffer is longer than
+// MAX_OUTPUT_LENGTH.
+pub fn hkdf_expand<H>(prk: &[u8; HASH_SIZE], info: &[u8], okm: &mut [u8]) -> bool
+where
+    H: Hash256 + HashBlockSize64Bytes,
+{
+    HkdfExpand::<H>::new(prk, info).fill(okm)
+}
+
+// Computes the full HKDF, extract and expand, into the buffer. Returns false if the buffer is
+// longer than MAX_OUTPUT_LENGTH.
+pub fn hkdf_256<H>(salt: &[u8], ikm: &[u8], info: &[u8], okm: &mut [u8]) -> bool
+where
+    H: Hash256 + HashBlockSize64Bytes,
+{
+    hkdf_expand::<H>(&hkdf_extract::<H>(salt, ikm), info, okm)
+}
+
+// Computes the HKDF with an empty salt and an output length of one hash block.
+//
+// This is the derivation of FIDO2's PIN/UV auth protocol two.
+pub fn hkdf_empty_salt_256<H>(ikm: &[u8], info: &[u8]) -> [u8; HASH_SIZE]
+where
+    H: Hash256 + HashBlockSize64Bytes,
+{
+    let mut okm = [0; HASH_SIZE];
+    hkdf_256::<H>(&[], ikm, info, &mut okm);
+    okm
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::sha256::Sha256;
+    use super::*;
+    use arrayref::array_ref;
+
+    // (IKM, salt, info, PRK, OKM)
+    type TestVector = (Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>);
+
+    // Test cases 1 to 3 of RFC 5869 appendix A.
+    fn rfc5869_vectors() -> Vec<TestVector> {
+        vec![
+            (
+                vec![0x0b; 22],
+                (0x00..=0x0c).collect(),
+                (0xf0..=0xf9).collect(),
+                hex("077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5"),
+                hex(concat!(
+                    "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf",
+                    "34007208d5b887185865"
+                )),
+            ),
+            (
+                (0x00..=0x4f).collect(),
+                (0x60..=0xaf).collect(),
+                (0xb0..=0xff).collect(),
+                hex("06a6b88c5853361a06104c9ceb35b45cef760014904671014a193f40c15fc244"),
+                hex(concat!(
+                    "b11e398dc80327a1c8e7f78c596a49344f012eda2d4efad8a050cc4c19afa97c",
+                    "59045a99cac7827271cb41c65e590e09da3275600c2f09b8367793a9aca3db71",
+                    "cc30c58179ec3e87c14c01d5c1f3434f1d87"
+                )),
+            ),
+            (
+                vec![0x0b; 22],
+                vec![],
+                vec![],
+                hex("19ef24a32c717b167f33a91d6f648bdf96596776afdb6377ac434c1c293ccb04"),
+                hex(concat!(
+                    "8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d",
+                    "9d201395faa4b61a96c8"
+                )),
+            ),
+        ]
+    }
+
+    fn hex(string: &str) -> Vec<u8> {
+        (0..string.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&string[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_hkdf_sha256_vectors() {
+        for (ikm, salt, info, prk, okm) in rfc5869_vectors() {
+            assert_eq!(hkdf_extract::<Sha256>(&salt, &ikm).to_vec(), prk);
+            let mut output = vec![0; okm.len()];
+            assert!(hkdf_256::<Sha256>(&salt, &ikm, &info, &mut output));
+            assert_eq!(output, okm);
+        }
+    }
+
+    #[test]
+    fn test_hkdf_expand_streaming() {
+        for (_, _, info, prk, okm) in rfc5869_vectors() {
+            let mut expand = HkdfExpand::<Sha256>::new(array_ref!(prk, 0, HASH_SIZE), &info);
+            let mut output = Vec::new();
+            // Reads of varying length cross the block boundaries at different offsets.
+            for length in [1, 7, 32, 3].iter().cycle() {
+                let length = core::cmp::min(*length, okm.len() - output.len());
+                if length == 0 {
+                    break;
+                }
+                let mut chunk = vec![0; length];
+                assert!(expand.fill(&mut chunk));
+                output.extend(chunk);
+            }
+            assert_eq!(output, okm);
+        }
+    }
+
+    #[test]
+    fn test_hkdf_expand_max_length() {
+        let prk = [0x55; HASH_SIZE];
+        let mut okm = vec![0; MAX_OUTPUT_LENGTH + 1];
+        assert!(!hkdf_expand::<Sha256>(&prk, &[], &mut okm));
+        assert!(hkdf_expand::<Sha256>(
+            &prk,
+            &[],
+            &mut okm[..MAX_OUTPUT_LENGTH]
+        ));
+
+        let mut expand = HkdfExpand::<Sha256>::new(&prk, &[]);
+        assert!(expand.fill(&mut okm[..MAX_OUTPUT_LENGTH - 1]));
+        assert!(!expand.fill(&mut [0; 2]));
+        assert!(expand.fill(&mut [0; 1]));
+        assert!(!expand.fill(&mut [0; 1]));
+    }
+
+    #[test]
+    fn test_hkdf_empty_salt_256_sha256_vectors() {
+        // Test vectors from RFC 5869, truncated to the first 32 bytes of output.
+        let ikm = [0x0b; 22];
+        let okm = hkdf_empty_salt_256::<Sha256>(&ikm, &[]);
+        assert_eq!(
+            okm,
+            [
+                0x8d, 0xa4, 0xe7, 0x75, 0xa5, 0x63, 0xc1, 0x8f, 0x71, 0x5f, 0x80, 0x2a, 0x06, 0x3c,
+                0x5a, 0x31, 0xb8, 0xa1, 0x1f, 0x5c, 0x5e, 0xe1, 0x87, 0x9e, 0xc3, 0x45, 0x4e, 0x5f,
+                0x3c, 0x73, 0x8d, 0x2d,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hkdf_empty_salt_256_matches_explicit_zero_salt() {
+        let ikm = [0x33; 32];
+        let prk = hmac_256::<Sha256>(&[0; HASH_SIZE], &ikm);
+        let expected = hmac_256::<Sha256>(&prk, b"CTAP2 AES key\x01");
+        assert_eq!(
+            hkdf_empty_salt_256::<Sha256>(&ikm, b"CTAP2 AES key"),
+            expected
+        );
+    }
+}
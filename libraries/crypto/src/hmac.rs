@@ -12,9 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::constant_time::bytes_eq;
+use super::sha512::Sha384;
+use super::zeroize::Zeroize;
 use super::{Hash256, HashBlockSize64Bytes};
-use arrayref::array_ref;
-use subtle::ConstantTimeEq;
 
 const BLOCK_SIZE: usize = 64;
 const HASH_SIZE: usize = 32;
@@ -24,7 +25,7 @@ where
     H: Hash256 + HashBlockSize64Bytes,
 {
     let expected_mac = hmac_256::<H>(key, contents);
-    bool::from(expected_mac.ct_eq(mac))
+    bytes_eq(&expected_mac, mac)
 }
 
 // FIDO2's PIN verification is just matching the first 16 bytes of the HMAC
@@ -34,27 +35,88 @@ where
     H: Hash256 + HashBlockSize64Bytes,
 {
     let expected_mac = hmac_256::<H>(key, contents);
-    bool::from(array_ref![expected_mac, 0, 16].ct_eq(pin))
+    bytes_eq(&expected_mac[..16], pin)
 }
 
 pub fn hmac_256<H>(key: &[u8], contents: &[u8]) -> [u8; HASH_SIZE]
 where
     H: Hash256 + HashBlockSize64Bytes,
 {
-    let mut ipad: [u8; BLOCK_SIZE] = [0x36; BLOCK_SIZE];
-    let mut opad: [u8; BLOCK_SIZE] = [0x5c; BLOCK_SIZE];
-    if key.len() <= BLOCK_SIZE {
-        xor_pads(&mut ipad, &mut opad, key);
+    let mut hmac = Hmac256::<H>::new(key);
+    hmac.update(contents);
+    hmac.finalize()
+}
+
+// Streaming HMAC, for contents that are not contiguous in memory. Consecutive updates are
+// equivalent to a single update with the concatenation of their contents.
+pub struct Hmac256<H> {
+    ihasher: H,
+    opad: [u8; BLOCK_SIZE],
+}
+
+impl<H> Drop for Hmac256<H> {
+    fn drop(&mut self) {
+        self.opad.zeroize();
+    }
+}
+
+impl<H> Hmac256<H>
+where
+    H: Hash256 + HashBlockSize64Bytes,
+{
+    pub fn new(key: &[u8]) -> Hmac256<H> {
+        let mut ipad: [u8; BLOCK_SIZE] = [0x36; BLOCK_SIZE];
+        let mut opad: [u8; BLOCK_SIZE] = [0x5c; BLOCK_SIZE];
+        if key.len() <= BLOCK_SIZE {
+            xor_pads(&mut ipad, &mut opad, key);
+        } else {
+            xor_pads(&mut ipad, &mut opad, &H::hash(key));
+        }
+
+        let mut ihasher = H::new();
+        ihasher.update(&ipad);
+        ipad.zeroize();
+        Hmac256 { ihasher, opad }
+    }
+
+    pub fn update(&mut self, contents: &[u8]) {
+        self.ihasher.update(contents);
+    }
+
+    pub fn finalize(mut self) -> [u8; HASH_SIZE] {
+        // The inner hasher can't be moved out of a type with a destructor.
+        let ihasher = core::mem::replace(&mut self.ihasher, H::new());
+        let mut ohasher = H::new();
+        ohasher.update(&self.opad);
+        ohasher.update(&ihasher.finalize());
+        ohasher.finalize()
+    }
+}
+
+// HMAC with SHA-384, used to derive deterministic P-384 signatures. SHA-384 works on 128-byte
+// blocks, so it doesn't share the pad logic above.
+pub fn hmac_384(key: &[u8], contents: &[u8]) -> [u8; 48] {
+    const BLOCK_SIZE_384: usize = 128;
+    let mut ipad: [u8; BLOCK_SIZE_384] = [0x36; BLOCK_SIZE_384];
+    let mut opad: [u8; BLOCK_SIZE_384] = [0x5c; BLOCK_SIZE_384];
+    let hashed_key;
+    let key = if key.len() <= BLOCK_SIZE_384 {
+        key
     } else {
-        xor_pads(&mut ipad, &mut opad, &H::hash(key));
+        hashed_key = Sha384::hash(key);
+        &hashed_key[..]
+    };
+    for (i, k) in key.iter().enumerate() {
+        ipad[i] ^= k;
+        opad[i] ^= k;
     }
 
-    let mut ihasher = H::new();
+    let mut ihasher = Sha384::new();
     ihasher.update(&ipad);
     ihasher.update(contents);
     let ihash = ihasher.finalize();
 
-    let mut ohasher = H::new();
+    let mut ohasher = Sha384::new();
     ohasher.update(&opad);
     ohasher.update(&ihash);
 
@@ -125,6 +187,20 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_hmac_sha256_streaming() {
+        let key = [0x41; 100];
+        let contents = [0x42; 200];
+        let expected = hmac_256::<Sha256>(&key, &contents);
+        for split in [0, 1, 63, 64, 65, 199, 200].iter() {
+            let mut hmac = Hmac256::<Sha256>::new(&key);
+            hmac.update(&contents[..*split]);
+            hmac.update(&[]);
+            hmac.update(&contents[*split..]);
+            assert_eq!(hmac.finalize(), expected);
+        }
+    }
+
     #[test]
     fn test_hash_sha256_for_various_lengths() {
         // This test makes sure that the key hashing and hash padding are implemented properly.
@@ -277,5 +353,26 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_hmac_sha384_examples() {
+        assert_eq!(
+            hmac_384(b"key", b"The quick brown fox jumps over the lazy dog")[..],
+            hex::decode(
+                "d7f4727e2c0b39ae0f1e40cc96f60242d5b7801841cea6fc592c5d3e1ae50700\
+                 582a96cf35e1e554995fe4e03381c237"
+            )
+            .unwrap()[..]
+        );
+        // Keys longer than a block are hashed first.
+        assert_eq!(
+            hmac_384(&[0x0b; 200], b"Hi There")[..],
+            hex::decode(
+                "f7b5870e1d6cd9338884376a64a2b9ef6c463ed643262e66cb5a4cd4d9d8dd33\
+                 db241306d6a125e24e3bf8280ee41197"
+            )
+            .unwrap()[..]
+        );
+    }
+
     // TODO: more tests
 }
@@ -17,19 +17,33 @@
 
 extern crate alloc;
 
+pub mod accelerator;
 pub mod aes256;
 pub mod cbc;
+pub mod constant_time;
 mod ec;
 pub mod ecdh;
 pub mod ecdsa;
+pub mod ecdsa_p384;
+pub mod ed25519;
+pub mod entropy;
+pub mod gcm;
+pub mod hkdf;
 pub mod hmac;
+pub mod pbkdf2;
 pub mod rng256;
+pub mod self_test;
 pub mod sha256;
+pub mod sha512;
 pub mod util;
+pub mod x25519;
+pub mod zeroize;
 
 // Trait for hash functions that returns a 256-bit hash.
 // The type must be Sized (size known at compile time) so that we can instanciate one on the stack
 // in the hash() method.
+// Contents can be streamed with consecutive calls to update() between new() and finalize(), so
+// that they don't need to be contiguous in memory.
 pub trait Hash256: Sized {
     fn new() -> Self;
     fn update(&mut self, contents: &[u8]);
@@ -0,0 +1,195 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::zeroize::Zeroize;
+use super::{Hash256, HashBlockSize64Bytes};
+use alloc::vec::Vec;
+use core::convert::Infallible;
+
+const BLOCK_SIZE: usize = 64;
+const HASH_SIZE: usize = 32;
+// The progress callback is called once per this many iterations.
+const PROGRESS_ITERATIONS: u32 = 256;
+
+// Computes the first block of PBKDF2 with HMAC as pseudorandom function, RFC 8018 section 5.2.
+//
+// The iteration count makes guessing the password expensive, and should be as high as the device
+// can afford. A zero iteration count is treated as one.
+pub fn pbkdf2_256<H>(password: &[u8], salt: &[u8], iterations: u32) -> [u8; HASH_SIZE]
+where
+    H: Hash256 + HashBlockSize64Bytes + Clone,
+{
+    match pbkdf2_256_with_progress::<H, Infallible>(password, salt, iterations, || Ok(())) {
+        Ok(output) => output,
+        Err(never) => match never {},
+    }
+}
+
+// Same as pbkdf2_256, but calls progress regularly during the computation, for example to send
+// keepalives. An error of the callback aborts the computation.
+pub fn pbkdf2_256_with_progress<H, E>(
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    mut progress: impl FnMut() -> Result<(), E>,
+) -> Result<[u8; HASH_SIZE], E>
+where
+    H: Hash256 + HashBlockSize64Bytes + Clone,
+{
+    // All iterations use the password as HMAC key. The hash states after the padded key are
+    // computed once, which halves the cost of each iteration.
+    let (ihasher, ohasher) = padded_key_hashers::<H>(password);
+    let prf = |message: &[u8]| {
+        let mut inner = ihasher.clone();
+        inner.update(message);
+        let mut outer = ohasher.clone();
+        outer.update(&inner.finalize());
+        outer.finalize()
+    };
+    // U_1 = PRF(P, S || INT(1))
+    let mut message = Vec::with_capacity(salt.len() + 4);
+    message.extend_from_slice(salt);
+    message.extend_from_slice(&1u32.to_be_bytes());
+    let mut block = prf(&message);
+    let mut output = block;
+    for i in 1..iterations {
+        if i % PROGRESS_ITERATIONS == 0 {
+            if let Err(error) = progress() {
+                block.zeroize();
+                output.zeroize();
+                return Err(error);
+            }
+        }
+        // U_i = PRF(P, U_{i-1})
+        block = prf(&block);
+        for (output_byte, block_byte) in output.iter_mut().zip(block.iter()) {
+            *output_byte ^= block_byte;
+        }
+    }
+    block.zeroize();
+    Ok(output)
+}
+
+// Returns the hashers of HMAC after the inner and outer padded key, in this order.
+fn padded_key_hashers<H>(key: &[u8]) -> (H, H)
+where
+    H: Hash256 + HashBlockSize64Bytes,
+{
+    let mut ipad = [0x36; BLOCK_SIZE];
+    let mut opad = [0x5c; BLOCK_SIZE];
+    let mut hashed_key = [0; HASH_SIZE];
+    let key = if key.len() <= BLOCK_SIZE {
+        key
+    } else {
+        hashed_key = H::hash(key);
+        &hashed_key
+    };
+    for (i, k) in key.iter().enumerate() {
+        ipad[i] ^= k;
+        opad[i] ^= k;
+    }
+    let mut ihasher = H::new();
+    ihasher.update(&ipad);
+    let mut ohasher = H::new();
+    ohasher.update(&opad);
+    ipad.zeroize();
+    opad.zeroize();
+    hashed_key.zeroize();
+    (ihasher, ohasher)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::hmac::hmac_256;
+    use super::super::sha256::Sha256;
+    use super::*;
+
+    fn hex(string: &str) -> Vec<u8> {
+        (0..string.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&string[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_pbkdf2_sha256_vectors() {
+        // The usual PBKDF2-HMAC-SHA256 vectors, truncated to one block.
+        let vectors = [
+            (
+                1,
+                "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b",
+            ),
+            (
+                2,
+                "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43",
+            ),
+            (
+                4096,
+                "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a",
+            ),
+        ];
+        for (iterations, output) in vectors.iter() {
+            assert_eq!(
+                pbkdf2_256::<Sha256>(b"password", b"salt", *iterations).to_vec(),
+                hex(output)
+            );
+        }
+    }
+
+    #[test]
+    fn test_pbkdf2_sha256_rfc7914_vector() {
+        // RFC 7914 section 11, first block of the 64 byte output.
+        assert_eq!(
+            pbkdf2_256::<Sha256>(b"passwd", b"salt", 1).to_vec(),
+            hex("55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc")
+        );
+    }
+
+    #[test]
+    fn test_pbkdf2_zero_iterations() {
+        assert_eq!(
+            pbkdf2_256::<Sha256>(b"password", b"salt", 0),
+            pbkdf2_256::<Sha256>(b"password", b"salt", 1)
+        );
+    }
+
+    #[test]
+    fn test_pbkdf2_matches_hmac() {
+        // Passwords longer than a block are hashed into the HMAC key.
+        for password in &[vec![0x55; 20], vec![0x55; 64], vec![0x55; 65]] {
+            let block1 = hmac_256::<Sha256>(password, b"salt\x00\x00\x00\x01");
+            let block2 = hmac_256::<Sha256>(password, &block1);
+            let mut expected = block1;
+            for (expected_byte, block_byte) in expected.iter_mut().zip(block2.iter()) {
+                *expected_byte ^= block_byte;
+            }
+            assert_eq!(pbkdf2_256::<Sha256>(password, b"salt", 2), expected);
+        }
+    }
+
+    #[test]
+    fn test_pbkdf2_progress() {
+        let mut calls = 0;
+        let output = pbkdf2_256_with_progress::<Sha256, ()>(b"password", b"salt", 4096, || {
+            calls += 1;
+            Ok(())
+        });
+        assert_eq!(output, Ok(pbkdf2_256::<Sha256>(b"password", b"salt", 4096)));
+        assert_eq!(calls, 4096 / PROGRESS_ITERATIONS - 1);
+
+        // Errors of the callback abort the computation.
+        let output = pbkdf2_256_with_progress::<Sha256, u8>(b"password", b"salt", 4096, || Err(7));
+        assert_eq!(output, Err(7));
+    }
+}
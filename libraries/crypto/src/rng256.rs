@@ -22,6 +22,16 @@ pub trait Rng256 {
     fn gen_uniform_u32x8(&mut self) -> [u32; 8] {
         bytes_to_u32(self.gen_uniform_u8x32())
     }
+
+    // Mixes additional input, such as a seed persisted across reboots, into generators that keep
+    // a state. Others ignore it.
+    fn add_input(&mut self, _input: &[u8]) {}
+
+    // Returns false if the entropy source failed its health tests. Generators without health tests
+    // are assumed healthy.
+    fn is_healthy(&self) -> bool {
+        true
+    }
 }
 
 // The TockOS rng driver fills a buffer of bytes, but we need 32-bit words for ECDSA.
@@ -0,0 +1,143 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Known-answer tests of the primitives, run at boot and on demand.
+//
+// Each test compares the output of a primitive with a published test vector. Primitives dispatch
+// to the hardware accelerator when there is one, so the tests cover it as well.
+
+use super::aes256;
+use super::ecdsa;
+use super::hmac::hmac_256;
+use super::sha256::Sha256;
+use super::{Decrypt16BytesBlock, Encrypt16BytesBlock, Hash256};
+
+// The tests, in the order they run. The failed test is reported with its number.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "derive_debug", derive(Debug))]
+pub enum KnownAnswerTest {
+    Sha256 = 1,
+    HmacSha256 = 2,
+    Aes256 = 3,
+    EcdsaSign = 4,
+    EcdsaVerify = 5,
+}
+
+// FIPS 180-2, appendix B.1.
+const SHA256_MESSAGE: &[u8] = b"abc";
+const SHA256_DIGEST: [u8; 32] = [
+    0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae, 0x22, 0x23,
+    0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61, 0xf2, 0x00, 0x15, 0xad,
+];
+
+// RFC 4231, test case 2.
+const HMAC_KEY: &[u8] = b"Jefe";
+const HMAC_MESSAGE: &[u8] = b"what do ya want for nothing?";
+const HMAC_TAG: [u8; 32] = [
+    0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95, 0x75, 0xc7,
+    0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9, 0x64, 0xec, 0x38, 0x43,
+];
+
+// FIPS 197, appendix C.3.
+const AES_KEY: [u8; 32] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f,
+];
+const AES_PLAINTEXT: [u8; 16] = [
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+];
+const AES_CIPHERTEXT: [u8; 16] = [
+    0x8e, 0xa2, 0xb7, 0xca, 0x51, 0x67, 0x45, 0xbf, 0xea, 0xfc, 0x49, 0x90, 0x4b, 0x49, 0x60, 0x89,
+];
+
+// RFC 6979, appendix A.2.5, with SHA-256 and the message "sample".
+const ECDSA_PRIVATE_KEY: [u8; 32] = [
+    0xc9, 0xaf, 0xa9, 0xd8, 0x45, 0xba, 0x75, 0x16, 0x6b, 0x5c, 0x21, 0x57, 0x67, 0xb1, 0xd6, 0x93,
+    0x4e, 0x50, 0xc3, 0xdb, 0x36, 0xe8, 0x9b, 0x12, 0x7b, 0x8a, 0x62, 0x2b, 0x12, 0x0f, 0x67, 0x21,
+];
+const ECDSA_MESSAGE: &[u8] = b"sample";
+// The signature (r, s), DER encoded.
+const ECDSA_SIGNATURE: [u8; 72] = [
+    0x30, 0x46, 0x02, 0x21, 0x00, 0xef, 0xd4, 0x8b, 0x2a, 0xac, 0xb6, 0xa8, 0xfd, 0x11, 0x40, 0xdd,
+    0x9c, 0xd4, 0x5e, 0x81, 0xd6, 0x9d, 0x2c, 0x87, 0x7b, 0x56, 0xaa, 0xf9, 0x91, 0xc3, 0x4d, 0x0e,
+    0xa8, 0x4e, 0xaf, 0x37, 0x16, 0x02, 0x21, 0x00, 0xf7, 0xcb, 0x1c, 0x94, 0x2d, 0x65, 0x7c, 0x41,
+    0xd4, 0x36, 0xc7, 0xa1, 0xb6, 0xe2, 0x9f, 0x65, 0xf3, 0xe9, 0x00, 0xdb, 0xb9, 0xaf, 0xf4, 0x06,
+    0x4d, 0xc4, 0xab, 0x2f, 0x84, 0x3a, 0xcd, 0xa8,
+];
+
+// Runs all tests, and returns the first that failed.
+pub fn run_known_answer_tests() -> Result<(), KnownAnswerTest> {
+    check(KnownAnswerTest::Sha256, test_sha256())?;
+    check(KnownAnswerTest::HmacSha256, test_hmac_sha256())?;
+    check(KnownAnswerTest::Aes256, test_aes256())?;
+    let sk = ecdsa::SecKey::from_bytes(&ECDSA_PRIVATE_KEY).ok_or(KnownAnswerTest::EcdsaSign)?;
+    let signature = sk.sign_rfc6979::<Sha256>(ECDSA_MESSAGE);
+    check(
+        KnownAnswerTest::EcdsaSign,
+        signature.to_asn1_der() == ECDSA_SIGNATURE[..],
+    )?;
+    // The signature must verify for the signed message only.
+    let pk = sk.genpk();
+    check(
+        KnownAnswerTest::EcdsaVerify,
+        pk.verify_vartime::<Sha256>(ECDSA_MESSAGE, &signature)
+            && !pk.verify_vartime::<Sha256>(b"test", &signature),
+    )
+}
+
+fn check(test: KnownAnswerTest, passed: bool) -> Result<(), KnownAnswerTest> {
+    if passed {
+        Ok(())
+    } else {
+        Err(test)
+    }
+}
+
+fn test_sha256() -> bool {
+    Sha256::hash(SHA256_MESSAGE) == SHA256_DIGEST
+}
+
+fn test_hmac_sha256() -> bool {
+    hmac_256::<Sha256>(HMAC_KEY, HMAC_MESSAGE) == HMAC_TAG
+}
+
+fn test_aes256() -> bool {
+    let encryption_key = aes256::EncryptionKey::new(&AES_KEY);
+    let mut block = AES_PLAINTEXT;
+    encryption_key.encrypt_block(&mut block);
+    if block != AES_CIPHERTEXT {
+        return false;
+    }
+    aes256::DecryptionKey::new(&encryption_key).decrypt_block(&mut block);
+    block == AES_PLAINTEXT
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_known_answer_tests_pass() {
+        assert!(run_known_answer_tests().is_ok());
+    }
+
+    #[test]
+    fn test_check_reports_failed_test() {
+        assert_eq!(check(KnownAnswerTest::Aes256, true), Ok(()));
+        assert_eq!(
+            check(KnownAnswerTest::Aes256, false),
+            Err(KnownAnswerTest::Aes256)
+        );
+    }
+}
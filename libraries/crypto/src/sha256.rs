@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::accelerator;
 use super::{Hash256, HashBlockSize64Bytes};
 use arrayref::{array_mut_ref, array_ref};
 use byteorder::{BigEndian, ByteOrder};
@@ -19,6 +20,7 @@ use core::num::Wrapping;
 
 const BLOCK_SIZE: usize = 64;
 
+#[derive(Clone)]
 pub struct Sha256 {
     state: [Wrapping<u32>; 8],
     block: [u8; BLOCK_SIZE],
@@ -102,6 +104,10 @@ impl HashBlockSize64Bytes for Sha256 {
 
     #[allow(clippy::many_single_char_names)]
     fn hash_block(state: &mut Self::State, block: &[u8; 64]) {
+        if accelerator::sha256_block(state, block) {
+            return;
+        }
+
         let mut w: [Wrapping<u32>; 64] = [Wrapping(0); 64];
 
         // Read the block as big-endian 32-bit words.
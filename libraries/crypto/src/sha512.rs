@@ -0,0 +1,465 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use arrayref::{array_mut_ref, array_ref};
+use byteorder::{BigEndian, ByteOrder};
+use core::num::Wrapping;
+
+const BLOCK_SIZE: usize = 128;
+
+// SHA-512 and SHA-384 are only used by Ed25519 and P-384, so they don't implement the Hash256
+// trait.
+pub struct Sha512 {
+    state: [Wrapping<u64>; 8],
+    block: [u8; BLOCK_SIZE],
+    total_len: usize,
+}
+
+impl Default for Sha512 {
+    fn default() -> Self {
+        Sha512::new()
+    }
+}
+
+impl Sha512 {
+    pub fn new() -> Self {
+        Sha512 {
+            state: Sha512::H,
+            block: [0; BLOCK_SIZE],
+            total_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut contents: &[u8]) {
+        let cursor_in_block = self.total_len % BLOCK_SIZE;
+        let left_in_block = BLOCK_SIZE - cursor_in_block;
+
+        // Increment the total length before we mutate the contents slice.
+        self.total_len += contents.len();
+
+        if contents.len() < left_in_block {
+            // The contents don't fill the current block. Simply copy the bytes.
+            self.block[cursor_in_block..(cursor_in_block + contents.len())]
+                .copy_from_slice(contents);
+        } else {
+            // First, fill and process the current block.
+            let (this_block, rest) = contents.split_at(left_in_block);
+            self.block[cursor_in_block..].copy_from_slice(this_block);
+            Sha512::hash_block(&mut self.state, &self.block);
+            contents = rest;
+
+            // Process full blocks.
+            while contents.len() >= BLOCK_SIZE {
+                let (block, rest) = contents.split_at(BLOCK_SIZE);
+                Sha512::hash_block(&mut self.state, array_ref![block, 0, BLOCK_SIZE]);
+                contents = rest;
+            }
+
+            // Copy the last block for further processing.
+            self.block[..contents.len()].copy_from_slice(contents);
+        }
+    }
+
+    pub fn finalize(mut self) -> [u8; 64] {
+        // Last block and padding.
+        let cursor_in_block = self.total_len % BLOCK_SIZE;
+        self.block[cursor_in_block] = 0x80;
+        // Clear the rest of the block.
+        for byte in self.block[(cursor_in_block + 1)..].iter_mut() {
+            *byte = 0;
+        }
+
+        if cursor_in_block >= 112 {
+            // Padding doesn't fit in this block, so we first hash this block and then hash a
+            // padding block.
+            Sha512::hash_block(&mut self.state, &self.block);
+            // Clear buffer for the padding block.
+            for byte in self.block.iter_mut() {
+                *byte = 0;
+            }
+        }
+
+        // The last 16 bytes of the last block contain the length of the contents. It must be
+        // expressed in bits, whereas `total_len` is in bytes. The high 8 bytes stay zero, since
+        // our contents are always shorter than 2^64 bits.
+        BigEndian::write_u64(
+            array_mut_ref![self.block, 120, 8],
+            self.total_len as u64 * 8,
+        );
+        Sha512::hash_block(&mut self.state, &self.block);
+
+        // Encode the state's 64-bit words into bytes, using big-endian.
+        let mut result: [u8; 64] = [0; 64];
+        for i in 0..8 {
+            BigEndian::write_u64(array_mut_ref![result, 8 * i, 8], self.state[i].0);
+        }
+        result
+    }
+
+    pub fn hash(contents: &[u8]) -> [u8; 64] {
+        let mut h = Sha512::new();
+        h.update(contents);
+        h.finalize()
+    }
+
+    #[allow(clippy::many_single_char_names)]
+    fn hash_block(state: &mut [Wrapping<u64>; 8], block: &[u8; BLOCK_SIZE]) {
+        let mut w: [Wrapping<u64>; 80] = [Wrapping(0); 80];
+
+        // Read the block as big-endian 64-bit words.
+        for (i, item) in w.iter_mut().take(16).enumerate() {
+            *item = Wrapping(BigEndian::read_u64(array_ref![block, 8 * i, 8]));
+        }
+
+        for i in 16..80 {
+            w[i] = w[i - 16] + Sha512::ssig0(w[i - 15]) + w[i - 7] + Sha512::ssig1(w[i - 2]);
+        }
+
+        let mut a = state[0];
+        let mut b = state[1];
+        let mut c = state[2];
+        let mut d = state[3];
+        let mut e = state[4];
+        let mut f = state[5];
+        let mut g = state[6];
+        let mut h = state[7];
+
+        for (i, item) in w.iter().enumerate() {
+            let tmp1 =
+                h + Sha512::bsig1(e) + Sha512::choice(e, f, g) + Wrapping(Sha512::K[i]) + *item;
+            let tmp2 = Sha512::bsig0(a) + Sha512::majority(a, b, c);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d + tmp1;
+            d = c;
+            c = b;
+            b = a;
+            a = tmp1 + tmp2;
+        }
+
+        state[0] += a;
+        state[1] += b;
+        state[2] += c;
+        state[3] += d;
+        state[4] += e;
+        state[5] += f;
+        state[6] += g;
+        state[7] += h;
+    }
+
+    // SHA-512 constants.
+    #[allow(clippy::unreadable_literal)]
+    const H: [Wrapping<u64>; 8] = [
+        Wrapping(0x6a09e667f3bcc908),
+        Wrapping(0xbb67ae8584caa73b),
+        Wrapping(0x3c6ef372fe94f82b),
+        Wrapping(0xa54ff53a5f1d36f1),
+        Wrapping(0x510e527fade682d1),
+        Wrapping(0x9b05688c2b3e6c1f),
+        Wrapping(0x1f83d9abfb41bd6b),
+        Wrapping(0x5be0cd19137e2179),
+    ];
+
+    #[allow(clippy::unreadable_literal)]
+    const K: [u64; 80] = [
+        0x428a2f98d728ae22,
+        0x7137449123ef65cd,
+        0xb5c0fbcfec4d3b2f,
+        0xe9b5dba58189dbbc,
+        0x3956c25bf348b538,
+        0x59f111f1b605d019,
+        0x923f82a4af194f9b,
+        0xab1c5ed5da6d8118,
+        0xd807aa98a3030242,
+        0x12835b0145706fbe,
+        0x243185be4ee4b28c,
+        0x550c7dc3d5ffb4e2,
+        0x72be5d74f27b896f,
+        0x80deb1fe3b1696b1,
+        0x9bdc06a725c71235,
+        0xc19bf174cf692694,
+        0xe49b69c19ef14ad2,
+        0xefbe4786384f25e3,
+        0x0fc19dc68b8cd5b5,
+        0x240ca1cc77ac9c65,
+        0x2de92c6f592b0275,
+        0x4a7484aa6ea6e483,
+        0x5cb0a9dcbd41fbd4,
+        0x76f988da831153b5,
+        0x983e5152ee66dfab,
+        0xa831c66d2db43210,
+        0xb00327c898fb213f,
+        0xbf597fc7beef0ee4,
+        0xc6e00bf33da88fc2,
+        0xd5a79147930aa725,
+        0x06ca6351e003826f,
+        0x142929670a0e6e70,
+        0x27b70a8546d22ffc,
+        0x2e1b21385c26c926,
+        0x4d2c6dfc5ac42aed,
+        0x53380d139d95b3df,
+        0x650a73548baf63de,
+        0x766a0abb3c77b2a8,
+        0x81c2c92e47edaee6,
+        0x92722c851482353b,
+        0xa2bfe8a14cf10364,
+        0xa81a664bbc423001,
+        0xc24b8b70d0f89791,
+        0xc76c51a30654be30,
+        0xd192e819d6ef5218,
+        0xd69906245565a910,
+        0xf40e35855771202a,
+        0x106aa07032bbd1b8,
+        0x19a4c116b8d2d0c8,
+        0x1e376c085141ab53,
+        0x2748774cdf8eeb99,
+        0x34b0bcb5e19b48a8,
+        0x391c0cb3c5c95a63,
+        0x4ed8aa4ae3418acb,
+        0x5b9cca4f7763e373,
+        0x682e6ff3d6b2b8a3,
+        0x748f82ee5defb2fc,
+        0x78a5636f43172f60,
+        0x84c87814a1f0ab72,
+        0x8cc702081a6439ec,
+        0x90befffa23631e28,
+        0xa4506cebde82bde9,
+        0xbef9a3f7b2c67915,
+        0xc67178f2e372532b,
+        0xca273eceea26619c,
+        0xd186b8c721c0c207,
+        0xeada7dd6cde0eb1e,
+        0xf57d4f7fee6ed178,
+        0x06f067aa72176fba,
+        0x0a637dc5a2c898a6,
+        0x113f9804bef90dae,
+        0x1b710b35131c471b,
+        0x28db77f523047d84,
+        0x32caab7b40c72493,
+        0x3c9ebe0a15c9bebc,
+        0x431d67c49c100d4c,
+        0x4cc5d4becb3e42b6,
+        0x597f299cfc657e2a,
+        0x5fcb6fab3ad6faec,
+        0x6c44198c4a475817,
+    ];
+
+    // SHA-512 helper functions.
+    #[inline(always)]
+    fn choice(e: Wrapping<u64>, f: Wrapping<u64>, g: Wrapping<u64>) -> Wrapping<u64> {
+        (e & f) ^ (!e & g)
+    }
+
+    #[inline(always)]
+    fn majority(a: Wrapping<u64>, b: Wrapping<u64>, c: Wrapping<u64>) -> Wrapping<u64> {
+        (a & b) ^ (a & c) ^ (b & c)
+    }
+
+    #[inline(always)]
+    fn bsig0(x: Wrapping<u64>) -> Wrapping<u64> {
+        x.rotate_right(28) ^ x.rotate_right(34) ^ x.rotate_right(39)
+    }
+
+    #[inline(always)]
+    fn bsig1(x: Wrapping<u64>) -> Wrapping<u64> {
+        x.rotate_right(14) ^ x.rotate_right(18) ^ x.rotate_right(41)
+    }
+
+    #[inline(always)]
+    fn ssig0(x: Wrapping<u64>) -> Wrapping<u64> {
+        x.rotate_right(1) ^ x.rotate_right(8) ^ (x >> 7)
+    }
+
+    #[inline(always)]
+    fn ssig1(x: Wrapping<u64>) -> Wrapping<u64> {
+        x.rotate_right(19) ^ x.rotate_right(61) ^ (x >> 6)
+    }
+}
+
+// SHA-384 is SHA-512 with different initial values, truncated to 384 bits.
+pub struct Sha384(Sha512);
+
+impl Default for Sha384 {
+    fn default() -> Self {
+        Sha384::new()
+    }
+}
+
+impl Sha384 {
+    pub fn new() -> Self {
+        Sha384(Sha512 {
+            state: Sha384::H,
+            block: [0; BLOCK_SIZE],
+            total_len: 0,
+        })
+    }
+
+    pub fn update(&mut self, contents: &[u8]) {
+        self.0.update(contents);
+    }
+
+    pub fn finalize(self) -> [u8; 48] {
+        let digest = self.0.finalize();
+        *array_ref![digest, 0, 48]
+    }
+
+    pub fn hash(contents: &[u8]) -> [u8; 48] {
+        let mut h = Sha384::new();
+        h.update(contents);
+        h.finalize()
+    }
+
+    #[allow(clippy::unreadable_literal)]
+    const H: [Wrapping<u64>; 8] = [
+        Wrapping(0xcbbb9d5dc1059ed8),
+        Wrapping(0x629a292a367cd507),
+        Wrapping(0x9159015a3070dd17),
+        Wrapping(0x152fecd8f70e5939),
+        Wrapping(0x67332667ffc00b31),
+        Wrapping(0x8eb44a8768581511),
+        Wrapping(0xdb0c2e0d64f98fa7),
+        Wrapping(0x47b5481dbefa4fa4),
+    ];
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash_empty() {
+        assert_eq!(
+            Sha512::hash(&[])[..],
+            hex::decode(
+                "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce\
+                 47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"
+            )
+            .unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn test_hash_abc() {
+        assert_eq!(
+            Sha512::hash(b"abc")[..],
+            hex::decode(
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                 2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+            )
+            .unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn test_update_for_various_splits() {
+        // Test vector generated with the following Python script:
+        //
+        // import hashlib
+        // print(hashlib.sha512(b'A' * 512).hexdigest())
+        //
+        let input = vec![b'A'; 512];
+        let hash = hex::decode(
+            "b62bf048526a6e3bbe816dc5b3174f1160df6fa0422565c923a73a9dc1716ad8\
+             3bfde4dba9ba44ef60a741a943caeed7b4b91ce5b21b41115661deb253411bb1",
+        )
+        .unwrap();
+
+        for i in (0..512).step_by(7) {
+            for j in (i..512).step_by(5) {
+                let mut h = Sha512::new();
+                h.update(&input[..i]);
+                h.update(&input[i..j]);
+                h.update(&input[j..]);
+                assert_eq!(h.finalize()[..], hash[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_for_various_lengths() {
+        // This test makes sure that the padding is implemented properly, including the lengths
+        // where the padding doesn't fit in the last block.
+        //
+        // Test vectors generated with the following Python script:
+        //
+        // import hashlib
+        // for n in [111, 112, 127, 128, 129, 239, 240]:
+        //     print('(%d, b"%s"),' % (n, hashlib.sha512(b'A' * n).hexdigest()))
+        //
+        let hashes: [(usize, &[u8; 128]); 7] = [
+            (111, b"ec9c8ac688af410e33264ba659dbc7bb652971831680ea2062576d394d298f58b3ccd9a1f39a3b40bc6c73609c976f71524efcd45abbf1a98b3f15dccf0b0b9f"),
+            (112, b"1a008b0480a4eb64d292db671d4f43f46fc57e077b72ad3ec0a3b0b63b320357a11418ea916038e9b659ccf39ae574ef8a8f683f1eff954788591c13022fcd81"),
+            (127, b"a9b159065acda6e2eee883409f5dc796afae042237ace277514f72152a480465ed44877cc5f8267434ce9187fcdfe8f8a4f69314a60815513a6b6c63287f5e29"),
+            (128, b"6486a74d95f54812a76071f6c6344ab6d34df3da685ec70dc78d9c5804b4ee3c449d9e68a6b52491f8275b838c2cd9102c3c223a620bbee2671edbff2611594e"),
+            (129, b"e84b03d611794516dc8ba99b3e90c08ecf1eb1debb3e25bf351fa0911e0c84adaec84e270b0123c355ca08098836448b6d6db28b8e56fff1faff9cc8eda83580"),
+            (239, b"42e1cd6686f79792b6ce1c1ba9ffe4c645f78dcc704c25b47802216228d2d279f69e94b0cac17bc35df78f35ec6869934d9868282d136dbb6cc4dabc774f6ce8"),
+            (240, b"ba7afd42d3eb5bcd4171018aa302e26839c78bbcbb8be906fa9eab77d6fb43cc436f67073eecb95a0c9ac905fc94c9c2e4c9de6ce88c3e3eb552831f6adf39ff"),
+        ];
+        for (length, hash) in hashes.iter() {
+            let input = vec![b'A'; *length];
+            assert_eq!(
+                Sha512::hash(&input)[..],
+                hex::decode(*hash as &[u8]).unwrap()[..]
+            );
+        }
+    }
+
+    #[test]
+    fn test_sha384_empty() {
+        assert_eq!(
+            Sha384::hash(&[])[..],
+            hex::decode(
+                "38b060a751ac96384cd9327eb1b1e36a21fdb71114be07434c0cc7bf63f6e1da\
+                 274edebfe76f65fbd51ad2f14898b95b"
+            )
+            .unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn test_sha384_abc() {
+        assert_eq!(
+            Sha384::hash(b"abc")[..],
+            hex::decode(
+                "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed\
+                 8086072ba1e7cc2358baeca134c825a7"
+            )
+            .unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn test_sha384_update_for_various_splits() {
+        // Test vector generated with the following Python script:
+        //
+        // import hashlib
+        // print(hashlib.sha384(b'A' * 200).hexdigest())
+        //
+        let input = [b'A'; 200];
+        let hash = hex::decode(
+            "0c2710bfa84e7bd69b22c0dbd9bde7c425435767a2af37bc1fc0cfeb8b21a5d5\
+             5cca256e17e8b8cd7d3f7f358d4fe1aa",
+        )
+        .unwrap();
+
+        for split in 0..=200 {
+            let mut h = Sha384::new();
+            h.update(&input[..split]);
+            h.update(&input[split..]);
+            assert_eq!(h.finalize()[..], hash[..]);
+        }
+    }
+}
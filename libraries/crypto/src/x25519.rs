@@ -0,0 +1,221 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// X25519 key agreement as specified in RFC 7748.
+//
+// The scalar multiplication is a constant-time Montgomery ladder on the u-coordinate, following
+// the TweetNaCl implementation.
+
+use super::ec::gf25519::Fe;
+use super::ecdh::Ecdh;
+use super::rng256::Rng256;
+use super::zeroize::Zeroize;
+
+pub const NBYTES: usize = 32;
+
+// The u-coordinate of the base point.
+const BASE_POINT: [u8; NBYTES] = [
+    9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+// (A - 2) / 4 for the curve constant A = 486662.
+const A24: Fe = Fe([0xdb41, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+pub struct SecKey {
+    scalar: [u8; NBYTES],
+}
+
+#[cfg_attr(feature = "derive_debug", derive(Clone, PartialEq, Debug))]
+pub struct PubKey {
+    u: [u8; NBYTES],
+}
+
+impl Drop for SecKey {
+    fn drop(&mut self) {
+        self.scalar.zeroize();
+    }
+}
+
+impl SecKey {
+    pub fn gensk<R>(rng: &mut R) -> SecKey
+    where
+        R: Rng256,
+    {
+        SecKey::from_bytes(&rng.gen_uniform_u8x32())
+    }
+
+    // Any 32 bytes are a valid secret key, as the scalar is clamped.
+    pub fn from_bytes(bytes: &[u8; NBYTES]) -> SecKey {
+        let mut scalar = *bytes;
+        scalar[0] &= 248;
+        scalar[31] &= 127;
+        scalar[31] |= 64;
+        SecKey { scalar }
+    }
+
+    pub fn genpk(&self) -> PubKey {
+        PubKey {
+            u: scalar_mult(&self.scalar, &BASE_POINT),
+        }
+    }
+
+    // Returns None if the shared secret is all zeros, which happens when the public key of the
+    // peer has a small order. RFC 7748, Section 6.1, requires this check.
+    pub fn exchange(&self, other: &PubKey) -> Option<[u8; NBYTES]> {
+        let shared = scalar_mult(&self.scalar, &other.u);
+        if shared.iter().fold(0, |acc, byte| acc | byte) == 0 {
+            None
+        } else {
+            Some(shared)
+        }
+    }
+}
+
+impl PubKey {
+    // Any 32 bytes are accepted, and the most significant bit is ignored.
+    pub fn from_bytes(bytes: &[u8; NBYTES]) -> PubKey {
+        PubKey { u: *bytes }
+    }
+
+    pub fn to_bytes(&self) -> [u8; NBYTES] {
+        self.u
+    }
+}
+
+pub struct X25519;
+
+impl Ecdh for X25519 {
+    type SecKey = SecKey;
+    type PubKey = PubKey;
+
+    fn gensk<R: Rng256>(rng: &mut R) -> SecKey {
+        SecKey::gensk(rng)
+    }
+
+    fn genpk(sk: &SecKey) -> PubKey {
+        sk.genpk()
+    }
+
+    fn exchange(sk: &SecKey, pk: &PubKey) -> Option<[u8; 32]> {
+        sk.exchange(pk)
+    }
+}
+
+// Computes the u-coordinate of scalar * u with the Montgomery ladder of RFC 7748, Section 5.
+fn scalar_mult(scalar: &[u8; NBYTES], u: &[u8; NBYTES]) -> [u8; NBYTES] {
+    let x = Fe::from_bytes(u);
+    let mut a = Fe::ONE;
+    let mut b = x;
+    let mut c = Fe::ZERO;
+    let mut d = Fe::ONE;
+    for i in (0..255).rev() {
+        let bit = ((scalar[i >> 3] >> (i & 7)) & 1) as i64;
+        Fe::cswap(&mut a, &mut b, bit);
+        Fe::cswap(&mut c, &mut d, bit);
+        let e = a.add(&c);
+        a = a.sub(&c);
+        c = b.add(&d);
+        b = b.sub(&d);
+        d = e.square();
+        let f = a.square();
+        a = c.mul(&a);
+        c = b.mul(&e);
+        let e = a.add(&c);
+        a = a.sub(&c);
+        b = a.square();
+        c = d.sub(&f);
+        a = c.mul(&A24).add(&d);
+        c = c.mul(&a);
+        a = d.mul(&f);
+        d = b.mul(&x);
+        b = e.square();
+        Fe::cswap(&mut a, &mut b, bit);
+        Fe::cswap(&mut c, &mut d, bit);
+    }
+    a.mul(&c.invert()).to_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::rng256::ThreadRng256;
+    use super::*;
+    use arrayref::array_ref;
+
+    fn from_hex(hex: &str) -> [u8; NBYTES] {
+        let bytes = hex::decode(hex).unwrap();
+        *array_ref!(bytes, 0, NBYTES)
+    }
+
+    /** RFC 7748 test vector, section 5.2 **/
+    #[test]
+    fn test_scalar_mult_rfc7748() {
+        let scalar = SecKey::from_bytes(&from_hex(
+            "a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4",
+        ));
+        let u = PubKey::from_bytes(&from_hex(
+            "e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c",
+        ));
+        assert_eq!(
+            scalar.exchange(&u),
+            Some(from_hex(
+                "c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552"
+            ))
+        );
+    }
+
+    /** RFC 7748 test vector, section 6.1 **/
+    #[test]
+    fn test_exchange_rfc7748() {
+        let sk_a = SecKey::from_bytes(&from_hex(
+            "77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a",
+        ));
+        let sk_b = SecKey::from_bytes(&from_hex(
+            "5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb",
+        ));
+        let pk_a = sk_a.genpk();
+        let pk_b = sk_b.genpk();
+        assert_eq!(
+            pk_a.to_bytes(),
+            from_hex("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a")
+        );
+        assert_eq!(
+            pk_b.to_bytes(),
+            from_hex("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f")
+        );
+        let shared = from_hex("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+        assert_eq!(sk_a.exchange(&pk_b), Some(shared));
+        assert_eq!(sk_b.exchange(&pk_a), Some(shared));
+    }
+
+    #[test]
+    fn test_exchange_is_symmetric() {
+        let mut rng = ThreadRng256 {};
+        let sk_a = X25519::gensk(&mut rng);
+        let sk_b = X25519::gensk(&mut rng);
+        assert_eq!(
+            X25519::exchange(&sk_a, &X25519::genpk(&sk_b)),
+            X25519::exchange(&sk_b, &X25519::genpk(&sk_a))
+        );
+    }
+
+    #[test]
+    fn test_exchange_rejects_small_order_point() {
+        let mut rng = ThreadRng256 {};
+        let sk = SecKey::gensk(&mut rng);
+        // The point of order 1, and a point of order 8.
+        assert_eq!(sk.exchange(&PubKey::from_bytes(&[0; NBYTES])), None);
+        let mut one = [0; NBYTES];
+        one[0] = 1;
+        assert_eq!(sk.exchange(&PubKey::from_bytes(&one)), None);
+    }
+}
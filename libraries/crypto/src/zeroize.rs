@@ -0,0 +1,82 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Wipes secrets from memory once they are no longer needed.
+//
+// The writes are volatile and followed by a compiler fence, so that the compiler can't remove them
+// because the memory is not read afterwards. Secret key types zeroize themselves when dropped.
+// Copies the compiler makes while moving values can't be reached, so secrets should be borrowed
+// rather than moved when possible.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+pub trait Zeroize {
+    // Overwrites the secret with zeros.
+    fn zeroize(&mut self);
+}
+
+macro_rules! impl_zeroize_for_slice {
+    ($($int: ty),*) => {
+        $(
+            impl Zeroize for [$int] {
+                fn zeroize(&mut self) {
+                    for int in self.iter_mut() {
+                        // Safety: the pointer comes from a mutable reference, so it is valid and
+                        // aligned.
+                        unsafe { core::ptr::write_volatile(int, 0) };
+                    }
+                    compiler_fence(Ordering::SeqCst);
+                }
+            }
+        )*
+    };
+}
+
+impl_zeroize_for_slice!(u8, u32, i64);
+
+// Also wipes the spare capacity, where truncated contents remain. The vector is empty afterwards.
+impl Zeroize for Vec<u8> {
+    fn zeroize(&mut self) {
+        self.resize(self.capacity(), 0);
+        self.as_mut_slice().zeroize();
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_zeroize_array() {
+        let mut bytes = [0x55u8; 32];
+        bytes.zeroize();
+        assert_eq!(bytes, [0; 32]);
+        let mut words = [0x5555_5555u32; 8];
+        words.zeroize();
+        assert_eq!(words, [0; 8]);
+    }
+
+    #[test]
+    fn test_zeroize_vec_spare_capacity() {
+        let mut bytes = vec![0x55u8; 32];
+        bytes.truncate(16);
+        bytes.zeroize();
+        assert!(bytes.is_empty());
+        // Safety: the capacity was initialized by the vector, and zeroized since.
+        let spare = unsafe { core::slice::from_raw_parts(bytes.as_ptr(), 32) };
+        assert_eq!(spare, &[0; 32][..]);
+    }
+}
@@ -43,7 +43,8 @@
 //! -   Given a key, `Remove` updates the store such that no value is associated with
 //!     the key. The values for other keys are left unchanged. Additionally, if there
 //!     was a value associated with the key, the value is wiped from the storage
-//!     (all its bits are set to 0).
+//!     (all its bits are set to 0). The wipe is part of the update and doesn't wait
+//!     for a compaction, so `Remove` can be used to securely delete secrets.
 //!
 //! The store provides the following _read-only operations_:
 //! -   `Iter` iterates through the store returning all entries exactly once. The
@@ -51,6 +52,8 @@
 //! -   `Capacity` returns how many words can be stored before the store is full.
 //! -   `Lifetime` returns how many words can be written before the storage lifetime
 //!     is consumed.
+//! -   `Wear` returns how many times each page has been erased, compared to the
+//!     maximum number of erase cycles.
 //!
 //! The store provides the following _mutable operations_:
 //! -   Given a set of independent updates, `Transaction` applies the sequence of
@@ -368,7 +371,7 @@ pub use self::driver::{
 pub use self::model::{StoreModel, StoreOperation};
 pub use self::storage::{Storage, StorageError, StorageIndex, StorageResult};
 pub use self::store::{
-    Store, StoreError, StoreHandle, StoreIter, StoreRatio, StoreResult, StoreUpdate,
+    Store, StoreError, StoreHandle, StoreIter, StoreRatio, StoreResult, StoreUpdate, StoreWear,
 };
 
 /// Internal representation of natural numbers.
@@ -114,6 +114,59 @@ impl StoreRatio {
     }
 }
 
+/// Wear of the storage.
+///
+/// This is used for the [wear] metric. Unlike the [lifetime], which is measured in words, wear is
+/// measured in erase cycles per page, which is how flash endurance is rated.
+///
+/// # Invariant
+///
+/// - The erase count of each page does not exceed the maximum: `page_erases[p] <= max_page_erases`.
+///
+/// [wear]: struct.Store.html#method.wear
+/// [lifetime]: struct.Store.html#method.lifetime
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoreWear {
+    /// How many times each page has been erased.
+    pub(crate) page_erases: Vec<usize>,
+
+    /// How many times a page can be erased at most.
+    pub(crate) max_page_erases: usize,
+
+    /// How many bytes of the lifetime have been written.
+    pub(crate) written_bytes: usize,
+}
+
+impl StoreWear {
+    /// How many times each page has been erased, indexed by page.
+    pub fn page_erases(&self) -> &[usize] {
+        &self.page_erases
+    }
+
+    /// How many times a page can be erased at most.
+    pub fn max_page_erases(&self) -> usize {
+        self.max_page_erases
+    }
+
+    /// How many erase cycles are used over all pages.
+    pub fn used_erases(&self) -> usize {
+        self.page_erases.iter().sum()
+    }
+
+    /// How many erase cycles can be used at most over all pages.
+    pub fn total_erases(&self) -> usize {
+        self.page_erases.len() * self.max_page_erases
+    }
+
+    /// How many bytes have been written.
+    ///
+    /// This counts the words used from the lifetime, including the words that the store skipped or
+    /// wrote for its own bookkeeping.
+    pub fn written_bytes(&self) -> usize {
+        self.written_bytes
+    }
+}
+
 /// Safe pointer to an entry.
 ///
 /// A store handle stays valid at least until the next mutable operation. Store operations taking a
@@ -136,6 +189,11 @@ impl StoreHandle {
         self.key as usize
     }
 
+    /// Returns the length in bytes of the value of the entry.
+    pub fn get_length(&self) -> usize {
+        self.len as usize
+    }
+
     /// Returns the value of the entry.
     ///
     /// # Errors
@@ -242,8 +300,32 @@ impl<S: Storage> Store<S> {
         Ok(StoreRatio { used, total })
     }
 
+    /// Returns the current wear of the storage.
+    ///
+    /// The erase counts are tracked by the pages themselves, so they survive reboots. Pages are
+    /// erased in a circular order, so their counts differ by at most one.
+    pub fn wear(&self) -> StoreResult<StoreWear> {
+        let num_pages = self.format.num_pages();
+        let virt_page_size = self.format.virt_page_size();
+        let mut page_erases = vec![0; num_pages as usize];
+        // The pages from the head page onwards hold consecutive virtual positions. The position of
+        // each page encodes how many times it was erased, including for the erased pages.
+        let begin = self.head()?.page_begin(&self.format);
+        for i in 0..num_pages {
+            let pos = begin + i * virt_page_size;
+            page_erases[pos.page(&self.format) as usize] = pos.cycle(&self.format) as usize;
+        }
+        Ok(StoreWear {
+            page_erases,
+            max_page_erases: self.format.max_page_erases() as usize,
+            written_bytes: (self.tail()?.get() * self.format.word_size()) as usize,
+        })
+    }
+
     /// Applies a sequence of updates as a single transaction.
     ///
+    /// The previous values of the updated keys are wiped from the storage before returning.
+    ///
     /// # Errors
     ///
     /// Returns `InvalidArgument` in the following circumstances:
@@ -301,7 +383,8 @@ impl<S: Storage> Store<S> {
 
     /// Removes multiple entries as part of a single transaction.
     ///
-    /// Entries with a key larger or equal to `min_key` are deleted.
+    /// Entries with a key larger or equal to `min_key` are deleted. Their values are wiped from the
+    /// storage before returning.
     pub fn clear(&mut self, min_key: usize) -> StoreResult<()> {
         let min_key = usize_to_nat(min_key);
         if min_key > self.format.max_key() {
@@ -332,6 +415,25 @@ impl<S: Storage> Store<S> {
         Ok(())
     }
 
+    /// Compacts one page if this makes more capacity immediately available.
+    ///
+    /// Returns whether a page was compacted. Nothing is modified if the immediate capacity is at
+    /// least `length` words, or if the store doesn't have `length` words of capacity (compaction
+    /// wouldn't reclaim enough). Each call compacts at most one page, which bounds its duration.
+    ///
+    /// Calling this function until it returns false, for example while the device is idle, makes
+    /// sure that the next operations using up to `length` words don't compact. This doesn't cost
+    /// more lifetime, since those operations would have compacted the same pages.
+    pub fn compact_step(&mut self, length: usize) -> StoreResult<bool> {
+        if self.capacity()?.remaining() < length
+            || self.immediate_capacity()? >= usize_to_nat(length)
+        {
+            return Ok(false);
+        }
+        self.compact()?;
+        Ok(true)
+    }
+
     /// Recovers a possible interrupted operation.
     ///
     /// If the storage is completely erased, it is initialized.
@@ -365,7 +467,8 @@ impl<S: Storage> Store<S> {
 
     /// Inserts an entry in the store.
     ///
-    /// If an entry for the same key is already present, it is replaced.
+    /// If an entry for the same key is already present, it is replaced and its value is wiped from
+    /// the storage before returning.
     pub fn insert(&mut self, key: usize, value: &[u8]) -> StoreResult<()> {
         // NOTE: This (and transaction) could take a position hint on the value to delete.
         let key = usize_to_nat(key);
@@ -386,7 +489,8 @@ impl<S: Storage> Store<S> {
 
     /// Removes an entry given its key.
     ///
-    /// This is not an error if there is no entry for this key.
+    /// The value is wiped from the storage (all its bits are set to 0) before returning, and not
+    /// only when its page is compacted. This is not an error if there is no entry for this key.
     pub fn remove(&mut self, key: usize) -> StoreResult<()> {
         let key = usize_to_nat(key);
         if key > self.format.max_key() {
@@ -396,6 +500,8 @@ impl<S: Storage> Store<S> {
     }
 
     /// Removes an entry given a handle.
+    ///
+    /// The value is wiped from the storage before returning, as for [`Store::remove`].
     pub fn remove_handle(&mut self, handle: &StoreHandle) -> StoreResult<()> {
         self.check_handle(handle)?;
         self.delete_pos(handle.pos, self.format.bytes_to_words(handle.len))
@@ -406,6 +512,13 @@ impl<S: Storage> Store<S> {
         self.format.max_value_len() as usize
     }
 
+    /// Returns the capacity in words used by an entry, given the length in bytes of its value.
+    ///
+    /// This is the unit of [`Store::capacity`].
+    pub fn entry_capacity(&self, length: usize) -> usize {
+        (1 + self.format.bytes_to_words(usize_to_nat(length))) as usize
+    }
+
     /// Returns the value of an entry given its handle.
     fn get_value(&self, handle: &StoreHandle) -> StoreResult<Vec<u8>> {
         self.check_handle(handle)?;
@@ -469,6 +582,10 @@ impl<S: Storage> Store<S> {
     }
 
     /// Recover a possible interrupted operation which is not a compaction.
+    ///
+    /// User entries that don't checksum are deleted, and the other entries are kept. Besides the
+    /// last entry of an interrupted operation, those are entries corrupted in the storage, for
+    /// example by bit rot.
     fn recover_operation(&mut self) -> StoreResult<()> {
         let mut pos = self.head()?;
         let mut prev_pos = pos;
@@ -497,7 +614,7 @@ impl<S: Storage> Store<S> {
                     return self.recover_wipe_partial(entry_pos, pos - entry_pos - 1);
                 }
                 ParsedEntry::PartialUser => {
-                    return self.recover_delete_user(entry_pos, pos - entry_pos - 1);
+                    self.recover_delete_user(entry_pos, pos - entry_pos - 1)?;
                 }
             }
             prev_pos = entry_pos;
@@ -639,7 +756,10 @@ impl<S: Storage> Store<S> {
     }
 
     /// Compacts one page.
-    fn compact(&mut self) -> StoreResult<()> {
+    ///
+    /// Operations already compact when they need to, so this is only useful to benchmark or test
+    /// compaction. Each call erases one page, which costs lifetime.
+    pub fn compact(&mut self) -> StoreResult<()> {
         let head = self.head()?;
         if head.cycle(&self.format) >= self.format.max_page_erases() {
             return Err(StoreError::NoLifetime);
@@ -1306,6 +1426,21 @@ mod tests {
         assert_eq!(MINIMAL.new_driver().model().format().virt_page_size(), 14);
     }
 
+    #[test]
+    fn entry_capacity_ok() {
+        let mut driver = MINIMAL.new_driver().power_on().unwrap();
+        for length in &[0, 1, 4, 5, 30] {
+            let used = driver.store().capacity().unwrap().used();
+            driver.insert(*length, &vec![0x5c; *length]).unwrap();
+            let handle = driver.store().iter().unwrap().last().unwrap().unwrap();
+            assert_eq!(handle.get_length(), *length);
+            assert_eq!(
+                driver.store().capacity().unwrap().used() - used,
+                driver.store().entry_capacity(*length)
+            );
+        }
+    }
+
     #[test]
     fn init_ok() {
         assert!(MINIMAL.new_driver().power_on().is_ok());
@@ -1386,6 +1521,71 @@ mod tests {
         driver.check().unwrap();
     }
 
+    #[test]
+    fn compact_ok() {
+        let mut driver = MINIMAL.new_driver().power_on().unwrap();
+        driver.insert(0, &[0x38; 28]).unwrap();
+        let lifetime = driver.store().lifetime().unwrap().used();
+
+        // Compact even if enough capacity is immediately available.
+        assert_eq!(driver.store().head().unwrap().get(), 0);
+        driver.store_mut().compact().unwrap();
+        driver.check().unwrap();
+        assert!(driver.store().head().unwrap().get() > 0);
+        assert!(driver.store().lifetime().unwrap().used() > lifetime);
+        assert_eq!(driver.store().find(0).unwrap(), Some(vec![0x38; 28]));
+    }
+
+    #[test]
+    fn remove_wipes_ok() {
+        // Returns whether the value is written somewhere in the storage.
+        fn contains(storage: &BufferStorage, value: &[u8]) -> bool {
+            (0..storage.num_pages()).any(|page| {
+                let index = StorageIndex { page, byte: 0 };
+                let page = storage.read_slice(index, storage.page_size()).unwrap();
+                page.windows(value.len()).any(|window| window == value)
+            })
+        }
+        fn page_erases(storage: &BufferStorage) -> usize {
+            (0..storage.num_pages())
+                .map(|page| storage.get_page_erases(page))
+                .sum()
+        }
+
+        let mut driver = MINIMAL.new_driver().power_on().unwrap();
+        let value: Vec<u8> = (1..=12).collect();
+        let erases = page_erases(driver.store().storage());
+        // Remove the entry.
+        driver.insert(0, &value).unwrap();
+        assert!(contains(driver.store().storage(), &value));
+        driver.remove(0).unwrap();
+        assert!(!contains(driver.store().storage(), &value));
+        // Replace the entry.
+        driver.insert(0, &value).unwrap();
+        driver.insert(0, &[0x5c; 12]).unwrap();
+        assert!(!contains(driver.store().storage(), &value));
+        // Remove the entry in a transaction.
+        driver.insert(1, &value).unwrap();
+        let updates = vec![
+            StoreUpdate::Remove { key: 1 },
+            StoreUpdate::Insert {
+                key: 2,
+                value: vec![0x93; 4],
+            },
+        ];
+        driver
+            .apply(StoreOperation::Transaction { updates })
+            .unwrap();
+        assert!(!contains(driver.store().storage(), &value));
+        // Clear the entry.
+        driver.insert(3, &value).unwrap();
+        driver.apply(StoreOperation::Clear { min_key: 3 }).unwrap();
+        assert!(!contains(driver.store().storage(), &value));
+        // The values were wiped without compaction.
+        assert_eq!(page_erases(driver.store().storage()), erases);
+        driver.check().unwrap();
+    }
+
     #[test]
     fn prepare_ok() {
         let mut driver = MINIMAL.new_driver().power_on().unwrap();
@@ -1421,6 +1621,113 @@ mod tests {
         assert_eq!(driver.store().capacity().unwrap().remaining(), 18);
     }
 
+    #[test]
+    fn compact_step_ok() {
+        let mut driver = MINIMAL.new_driver().power_on().unwrap();
+
+        // Fill the store and remove some entries.
+        for key in 0..4 {
+            driver.insert(key, &[0x38; 28]).unwrap();
+        }
+        driver.remove(0).unwrap();
+        driver.remove(2).unwrap();
+        driver.check().unwrap();
+        assert_eq!(driver.store().immediate_capacity().unwrap(), 7);
+        assert_eq!(driver.store().capacity().unwrap().remaining(), 18);
+
+        // Don't compact if the capacity is missing.
+        assert!(!driver.store_mut().compact_step(19).unwrap());
+        assert_eq!(driver.store().head().unwrap().get(), 0);
+
+        // Compact one page at a time until enough capacity is immediately available.
+        let mut steps = 0;
+        while driver.store_mut().compact_step(16).unwrap() {
+            driver.check().unwrap();
+            steps += 1;
+        }
+        assert!(steps > 0);
+        assert!(driver.store().immediate_capacity().unwrap() >= 16);
+        assert_eq!(driver.store().capacity().unwrap().remaining(), 18);
+
+        // Don't compact again.
+        let head = driver.store().head().unwrap();
+        assert!(!driver.store_mut().compact_step(16).unwrap());
+        assert_eq!(driver.store().head().unwrap(), head);
+    }
+
+    #[test]
+    fn wear_ok() {
+        let mut driver = MINIMAL.new_driver().power_on().unwrap();
+        let wear = driver.store().wear().unwrap();
+        assert_eq!(wear.page_erases(), &[0; 5]);
+        assert_eq!(wear.max_page_erases(), 9);
+        assert_eq!(wear.used_erases(), 0);
+        assert_eq!(wear.total_erases(), 45);
+        assert_eq!(wear.written_bytes(), 0);
+
+        // Rewrite an entry until the pages wrapped around.
+        for _ in 0..20 {
+            driver.insert(0, &[0x38; 28]).unwrap();
+        }
+        driver.check().unwrap();
+        let wear = driver.store().wear().unwrap();
+        for page in 0..5 {
+            assert_eq!(
+                wear.page_erases()[page],
+                driver.store().storage().get_page_erases(page)
+            );
+        }
+        assert!(wear.used_erases() > 5);
+        let lifetime = driver.store().lifetime().unwrap().used();
+        assert_eq!(wear.written_bytes(), 4 * lifetime);
+
+        // The erase counts are kept across reboots.
+        let driver = driver.power_off().power_on().unwrap();
+        assert_eq!(driver.store().wear().unwrap(), wear);
+    }
+
+    #[test]
+    fn recover_corrupted_ok() {
+        // Bit rot doesn't count as a word write, so the storage is not strict.
+        let options = BufferOptions {
+            word_size: MINIMAL.word_size,
+            page_size: MINIMAL.page_size,
+            max_word_writes: MINIMAL.max_word_writes,
+            max_page_erases: MINIMAL.max_page_erases,
+            strict_mode: false,
+        };
+        let storage = vec![0xff; MINIMAL.num_pages * MINIMAL.page_size].into_boxed_slice();
+        let mut store = Store::new(BufferStorage::new(storage, options))
+            .ok()
+            .unwrap();
+        for key in 0..4 {
+            store.insert(key, &[0x5c; 8]).unwrap();
+        }
+
+        // Clear a bit in the last word of the entries 1 and 2, as bit rot would.
+        let handles: Vec<StoreHandle> = (1..3)
+            .map(|key| store.find_handle(key).unwrap().unwrap())
+            .collect();
+        for handle in handles {
+            let footer = handle.pos + store.format.bytes_to_words(handle.len);
+            let mut word = store.read_word(footer).to_vec();
+            word[0] &= !0x04;
+            let index = footer.index(&store.format);
+            store.storage.write_slice(index, &word).unwrap();
+        }
+
+        // The corrupted entries are deleted on reboot, and the others are kept.
+        let store = Store::new(store.extract_storage()).ok().unwrap();
+        let keys: Vec<usize> = store
+            .iter()
+            .unwrap()
+            .map(|x| x.unwrap().get_key())
+            .collect();
+        assert_eq!(keys, vec![0, 3]);
+        assert_eq!(store.find(3).unwrap(), Some(vec![0x5c; 8]));
+        assert!(store.capacity().is_ok());
+    }
+
     #[test]
     fn reboot_ok() {
         let mut driver = MINIMAL.new_driver().power_on().unwrap();
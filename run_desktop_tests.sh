@@ -50,6 +50,8 @@ cargo check --release --target=thumbv7em-none-eabi --features panic_console
 cargo check --release --target=thumbv7em-none-eabi --features debug_allocations
 cargo check --release --target=thumbv7em-none-eabi --features verbose
 cargo check --release --target=thumbv7em-none-eabi --features debug_ctap,with_ctap1
+cargo check --release --target=thumbv7em-none-eabi --features with_secure_element
+cargo check --release --target=thumbv7em-none-eabi --features with_external_flash
 cargo check --release --target=thumbv7em-none-eabi --features debug_ctap,with_ctap1,panic_console,debug_allocations,verbose
 
 echo "Checking that examples build properly..."
@@ -128,4 +130,19 @@ then
 
   echo "Running unit tests on the desktop (debug mode + CTAP1 + CTAP2.1)..."
   cargo test --features std,with_ctap1,with_ctap2_1
+
+  echo "Running unit tests on the desktop (release mode + NFC)..."
+  cargo test --release --features std,with_nfc
+
+  echo "Running unit tests on the desktop (debug mode + NFC)..."
+  cargo test --features std,with_nfc
+
+  echo "Running unit tests on the desktop (release mode + CTAP1 + NFC)..."
+  cargo test --release --features std,with_ctap1,with_nfc
+
+  echo "Running unit tests on the desktop (debug mode + CTAP1 + NFC)..."
+  cargo test --features std,with_ctap1,with_nfc
+
+  echo "Running unit tests of the NFC driver on the desktop..."
+  cargo test --manifest-path third_party/libtock-drivers/Cargo.toml --features with_nfc
 fi
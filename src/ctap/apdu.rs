@@ -29,6 +29,8 @@ pub enum ApduStatusCode {
     SW_MEMERR = 0x65_01,
     SW_WRONG_DATA = 0x6a_80,
     SW_WRONG_LENGTH = 0x67_00,
+    /// The reader interrupted a command chain with another command.
+    SW_LAST_COMMAND_EXPECTED = 0x68_83,
     SW_COND_USE_NOT_SATISFIED = 0x69_85,
     SW_FILE_NOT_FOUND = 0x6a_82,
     SW_INCORRECT_P1P2 = 0x6a_86,
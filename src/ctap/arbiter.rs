@@ -0,0 +1,107 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::status_code::Ctap2StatusCode;
+use super::timed_permission::TimedPermission;
+use libtock_drivers::timer::{ClockValue, Duration};
+
+// The interfaces that carry CTAP transactions.
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub enum Interface {
+    Usb,
+    Nfc,
+}
+
+// CTAP specification (version 20190130) section 8.1.5.1: transactions are atomic. A transaction
+// may span several calls of the main loop, e.g. while USB packets are assembled or while the NFC
+// reader fetches a long response. In the meantime, the other interface is answered with
+// CTAP1_ERR_CHANNEL_BUSY.
+pub struct InterfaceArbiter {
+    owner: Option<Interface>,
+    // The owner loses the lock if its transaction doesn't progress before this expires.
+    lock: TimedPermission,
+}
+
+impl InterfaceArbiter {
+    // NFC readers and USB hosts send the next part of a transaction within milliseconds.
+    const LOCK_TIMEOUT_DURATION: Duration<isize> = Duration::from_ms(1000);
+
+    pub fn new() -> InterfaceArbiter {
+        InterfaceArbiter {
+            owner: None,
+            lock: TimedPermission::waiting(),
+        }
+    }
+
+    // Takes or renews the lock for a transaction of the interface, unless the other interface
+    // holds it.
+    pub fn acquire(
+        &mut self,
+        interface: Interface,
+        now: ClockValue,
+    ) -> Result<(), Ctap2StatusCode> {
+        if let Some(owner) = self.owner {
+            if owner != interface && self.lock.is_granted(now) {
+                return Err(Ctap2StatusCode::CTAP1_ERR_CHANNEL_BUSY);
+            }
+        }
+        self.owner = Some(interface);
+        self.lock = TimedPermission::granted(now, InterfaceArbiter::LOCK_TIMEOUT_DURATION);
+        Ok(())
+    }
+
+    // Releases the lock when the transaction of the interface completed or was dropped.
+    pub fn release(&mut self, interface: Interface) {
+        if self.owner == Some(interface) {
+            self.owner = None;
+            self.lock = TimedPermission::waiting();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLOCK_FREQUENCY_HZ: usize = 32768;
+    const ZERO: ClockValue = ClockValue::new(0, CLOCK_FREQUENCY_HZ);
+
+    #[test]
+    fn test_busy_until_release() {
+        let mut arbiter = InterfaceArbiter::new();
+        assert_eq!(arbiter.acquire(Interface::Nfc, ZERO), Ok(()));
+        assert_eq!(arbiter.acquire(Interface::Nfc, ZERO), Ok(()));
+        assert_eq!(
+            arbiter.acquire(Interface::Usb, ZERO),
+            Err(Ctap2StatusCode::CTAP1_ERR_CHANNEL_BUSY)
+        );
+        // Only the owner releases the lock.
+        arbiter.release(Interface::Usb);
+        assert!(arbiter.acquire(Interface::Usb, ZERO).is_err());
+        arbiter.release(Interface::Nfc);
+        assert_eq!(arbiter.acquire(Interface::Usb, ZERO), Ok(()));
+        assert!(arbiter.acquire(Interface::Nfc, ZERO).is_err());
+    }
+
+    #[test]
+    fn test_lock_timeout() {
+        let mut arbiter = InterfaceArbiter::new();
+        assert_eq!(arbiter.acquire(Interface::Usb, ZERO), Ok(()));
+        let almost_expired = ZERO.wrapping_add(Duration::from_ms(999));
+        assert!(arbiter.acquire(Interface::Nfc, almost_expired).is_err());
+        let expired = ZERO.wrapping_add(Duration::from_ms(1000));
+        assert_eq!(arbiter.acquire(Interface::Nfc, expired), Ok(()));
+    }
+}
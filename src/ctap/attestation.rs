@@ -0,0 +1,263 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::crypto_backend::{sign_attestation, CryptoBackend};
+use super::data_formats::{PackedAttestationStatement, PrivateKey, SignatureAlgorithm};
+use super::status_code::Ctap2StatusCode;
+use super::storage::PersistentStore;
+use alloc::vec::Vec;
+use crypto::rng256::Rng256;
+use crypto::zeroize::Zeroize;
+
+// How a new credential is attested in its packed attestation statement.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttestationType {
+    // The batch key signs, and its certificate chain identifies the batch of authenticators.
+    Batch,
+    // The credential key signs its own creation, without certificate.
+    SelfAttestation,
+    // The batch key signs, and the certificate identifies this authenticator to the RP.
+    Enterprise,
+}
+
+impl AttestationType {
+    // Selects the attestation of a request. Enterprise attestation is granted per request, and
+    // takes precedence over the configured choice between batch and self attestation.
+    pub fn select(use_batch_attestation: bool, ep_att: bool) -> AttestationType {
+        if ep_att {
+            AttestationType::Enterprise
+        } else if use_batch_attestation {
+            AttestationType::Batch
+        } else {
+            AttestationType::SelfAttestation
+        }
+    }
+}
+
+// Signs the attestation statement of a new credential. The signed data is the concatenation of
+// its parts, the authenticator data followed by the client data hash. The attestation key of the
+// crypto backend takes precedence over the one of the persistent store.
+pub fn packed_attestation_statement(
+    rng: &mut impl Rng256,
+    persistent_store: &PersistentStore,
+    crypto_backend: &mut impl CryptoBackend,
+    attestation_type: AttestationType,
+    credential_key: &PrivateKey,
+    signature_data: &[&[u8]],
+) -> Result<PackedAttestationStatement, Ctap2StatusCode> {
+    let (alg, sig, x5c) = match attestation_type {
+        AttestationType::Batch | AttestationType::Enterprise
+            if crypto_backend.has_attestation_key() =>
+        {
+            (
+                SignatureAlgorithm::ES256,
+                sign_attestation(crypto_backend, signature_data)?,
+                Some(certificate_chain(persistent_store)?),
+            )
+        }
+        AttestationType::Batch | AttestationType::Enterprise => {
+            let mut attestation_private_key = persistent_store
+                .attestation_private_key()?
+                .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
+            // Provisioning checks the scalar, so an invalid one was corrupted in storage.
+            let attestation_key = crypto::ecdsa::SecKey::from_bytes(&attestation_private_key);
+            attestation_private_key.zeroize();
+            let attestation_key =
+                attestation_key.ok_or(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)?;
+            (
+                SignatureAlgorithm::ES256,
+                PrivateKey::from(attestation_key).sign_and_encode(rng, signature_data)?,
+                Some(certificate_chain(persistent_store)?),
+            )
+        }
+        // Self attestation is signed with the credential key, in its own algorithm.
+        AttestationType::SelfAttestation => (
+            credential_key.signature_algorithm(),
+            credential_key.sign_and_encode(rng, signature_data)?,
+            None,
+        ),
+    };
+    Ok(PackedAttestationStatement {
+        alg: alg as i64,
+        sig,
+        x5c,
+        ecdaa_key_id: None,
+    })
+}
+
+// Returns the x5c array: the attestation certificate first, followed by the certificates that
+// signed it, in order. The root is usually omitted, since the RP needs to know it anyway.
+fn certificate_chain(persistent_store: &PersistentStore) -> Result<Vec<Vec<u8>>, Ctap2StatusCode> {
+    let attestation_certificate = persistent_store
+        .attestation_certificate()?
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
+    let mut chain = vec![attestation_certificate];
+    chain.extend(persistent_store.attestation_intermediate_certificates()?);
+    Ok(chain)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::crypto_backend::DefaultCryptoBackend;
+    use super::super::data_formats::decode_asn1_der_signature;
+    use super::super::key_material;
+    use super::*;
+    use crypto::rng256::ThreadRng256;
+
+    #[test]
+    fn test_select() {
+        assert_eq!(
+            AttestationType::select(false, false),
+            AttestationType::SelfAttestation
+        );
+        assert_eq!(AttestationType::select(true, false), AttestationType::Batch);
+        assert_eq!(
+            AttestationType::select(false, true),
+            AttestationType::Enterprise
+        );
+        assert_eq!(
+            AttestationType::select(true, true),
+            AttestationType::Enterprise
+        );
+    }
+
+    #[test]
+    fn test_self_attestation() {
+        let mut rng = ThreadRng256 {};
+        let persistent_store = PersistentStore::new(&mut rng);
+        let credential_key = PrivateKey::from(crypto::ecdsa::SecKey::gensk(&mut rng));
+
+        let statement = packed_attestation_statement(
+            &mut rng,
+            &persistent_store,
+            &mut DefaultCryptoBackend::new(),
+            AttestationType::SelfAttestation,
+            &credential_key,
+            &[&[0x55; 16], &[0x55; 16]],
+        )
+        .unwrap();
+        assert_eq!(statement.alg, SignatureAlgorithm::ES256 as i64);
+        assert_eq!(statement.x5c, None);
+        assert!(credential_key.verify_encoded(&[0x55; 32], &statement.sig));
+    }
+
+    #[test]
+    fn test_batch_attestation_chain() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_key = PrivateKey::from(crypto::ecdsa::SecKey::gensk(&mut rng));
+
+        // Batch attestation needs the attestation material.
+        assert_eq!(
+            packed_attestation_statement(
+                &mut rng,
+                &persistent_store,
+                &mut DefaultCryptoBackend::new(),
+                AttestationType::Batch,
+                &credential_key,
+                &[&[0x55; 16], &[0x55; 16]],
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
+        );
+
+        let attestation_key = crypto::ecdsa::SecKey::gensk(&mut rng);
+        let mut private_key = [0; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        attestation_key.to_bytes(&mut private_key);
+        persistent_store
+            .set_attestation_private_key(&private_key)
+            .unwrap();
+        persistent_store
+            .provision_attestation(None, None, Some(&[vec![0xdd; 20], vec![0xee; 30]]))
+            .unwrap();
+
+        for attestation_type in &[AttestationType::Batch, AttestationType::Enterprise] {
+            let statement = packed_attestation_statement(
+                &mut rng,
+                &persistent_store,
+                &mut DefaultCryptoBackend::new(),
+                *attestation_type,
+                &credential_key,
+                &[&[0x55; 16], &[0x55; 16]],
+            )
+            .unwrap();
+            assert_eq!(statement.alg, SignatureAlgorithm::ES256 as i64);
+            assert_eq!(statement.x5c, Some(vec![vec![0xdd; 20], vec![0xee; 30]]));
+            let attestation_key = PrivateKey::from(attestation_key.clone());
+            assert!(attestation_key.verify_encoded(&[0x55; 32], &statement.sig));
+            assert!(!credential_key.verify_encoded(&[0x55; 32], &statement.sig));
+        }
+    }
+
+    #[test]
+    fn test_batch_attestation_invalid_key() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_key = PrivateKey::from(crypto::ecdsa::SecKey::gensk(&mut rng));
+
+        // The zero scalar is not a valid private key.
+        persistent_store
+            .set_attestation_private_key(&[0x00; key_material::ATTESTATION_PRIVATE_KEY_LENGTH])
+            .unwrap();
+        persistent_store
+            .set_attestation_certificate(&[0xdd; 20])
+            .unwrap();
+        assert_eq!(
+            packed_attestation_statement(
+                &mut rng,
+                &persistent_store,
+                &mut DefaultCryptoBackend::new(),
+                AttestationType::Batch,
+                &credential_key,
+                &[&[0x55; 16], &[0x55; 16]],
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+    }
+
+    #[test]
+    fn test_batch_attestation_crypto_backend() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut crypto_backend = DefaultCryptoBackend::new();
+        let credential_key = PrivateKey::from(crypto::ecdsa::SecKey::gensk(&mut rng));
+
+        let stored_key = crypto::ecdsa::SecKey::gensk(&mut rng);
+        let mut private_key = [0; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        stored_key.to_bytes(&mut private_key);
+        persistent_store
+            .set_attestation_private_key(&private_key)
+            .unwrap();
+        persistent_store
+            .set_attestation_certificate(&[0xdd; 20])
+            .unwrap();
+        let public_key = crypto_backend.generate_attestation_key().unwrap();
+
+        let statement = packed_attestation_statement(
+            &mut rng,
+            &persistent_store,
+            &mut crypto_backend,
+            AttestationType::Batch,
+            &credential_key,
+            &[&[0x55; 16], &[0x55; 16]],
+        )
+        .unwrap();
+        assert_eq!(statement.alg, SignatureAlgorithm::ES256 as i64);
+        assert_eq!(statement.x5c, Some(vec![vec![0xdd; 20]]));
+        let signature = decode_asn1_der_signature(&statement.sig, 32)
+            .and_then(|bytes| crypto::ecdsa::Signature::from_bytes(&bytes))
+            .unwrap();
+        assert!(public_key.verify_vartime::<crypto::sha256::Sha256>(&[0x55; 32], &signature));
+        assert!(!PrivateKey::from(stored_key).verify_encoded(&[0x55; 32], &statement.sig));
+    }
+}
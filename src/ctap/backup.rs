@@ -0,0 +1,300 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Encrypted backups of the resident credentials, to move them to a replacement device.
+//
+// A backup is a header and a list of records. The header holds the format version, the PBKDF2
+// iteration count and the salt, from which the passphrase derives the backup key. Each record is
+// a credential in its storage representation, encrypted with AES-256-GCM under the backup key, as
+// nonce, ciphertext and tag. The header is authenticated with each record, so that records can't
+// be moved between backups.
+
+use super::data_formats::PublicKeyCredentialSource;
+use super::status_code::Ctap2StatusCode;
+use super::storage::serialize_credential;
+use alloc::vec::Vec;
+use arrayref::array_ref;
+use core::convert::TryFrom;
+use crypto::aes256;
+use crypto::gcm::{gcm_decrypt, gcm_encrypt, NONCE_SIZE, TAG_SIZE};
+use crypto::pbkdf2::pbkdf2_256_with_progress;
+use crypto::rng256::Rng256;
+use crypto::sha256::Sha256;
+use crypto::zeroize::Zeroize;
+
+const BACKUP_VERSION: u8 = 1;
+// Backups leave the device, so the key derivation must slow down offline guessing. It runs once per
+// backup or restore, and takes about a second on an nRF52840. Most of the protection comes from
+// the minimum passphrase length.
+const KDF_ITERATIONS: u32 = 10_000;
+// Restoring a backup with more iterations would block the device for too long.
+const MAX_KDF_ITERATIONS: u32 = 50_000;
+const SALT_LENGTH: usize = 16;
+const HEADER_LENGTH: usize = 1 + 4 + SALT_LENGTH;
+// New backups need a passphrase of at least this many bytes.
+pub const MIN_PASSPHRASE_LENGTH: usize = 14;
+
+// The key of a backup, together with the header it was derived from. The key is wiped when
+// dropped.
+pub struct BackupKey {
+    key: [u8; 32],
+    header: Vec<u8>,
+}
+
+impl Drop for BackupKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl BackupKey {
+    // Derives the key of a new backup, with a fresh salt. The derivation calls keepalive
+    // regularly, and aborts if it fails.
+    pub fn new(
+        rng: &mut impl Rng256,
+        passphrase: &[u8],
+        keepalive: impl FnMut() -> Result<(), Ctap2StatusCode>,
+    ) -> Result<BackupKey, Ctap2StatusCode> {
+        if passphrase.len() < MIN_PASSPHRASE_LENGTH {
+            return Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION);
+        }
+        let mut header = Vec::with_capacity(HEADER_LENGTH);
+        header.push(BACKUP_VERSION);
+        header.extend_from_slice(&KDF_ITERATIONS.to_be_bytes());
+        header.extend_from_slice(&rng.gen_uniform_u8x32()[..SALT_LENGTH]);
+        BackupKey::from_header(passphrase, &header, keepalive)
+    }
+
+    // Derives the key of an existing backup from its header, calling keepalive like new.
+    pub fn from_header(
+        passphrase: &[u8],
+        header: &[u8],
+        keepalive: impl FnMut() -> Result<(), Ctap2StatusCode>,
+    ) -> Result<BackupKey, Ctap2StatusCode> {
+        if header.len() != HEADER_LENGTH {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+        if header[0] != BACKUP_VERSION {
+            return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM);
+        }
+        let iterations = u32::from_be_bytes(*array_ref!(header, 1, 4));
+        if iterations == 0 || iterations > MAX_KDF_ITERATIONS {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+        let salt = &header[5..];
+        Ok(BackupKey {
+            key: pbkdf2_256_with_progress::<Sha256, _>(passphrase, salt, iterations, keepalive)?,
+            header: header.to_vec(),
+        })
+    }
+
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    // Encrypts a credential into a record of the backup.
+    pub fn encrypt(
+        &self,
+        rng: &mut impl Rng256,
+        credential: PublicKeyCredentialSource,
+    ) -> Result<Vec<u8>, Ctap2StatusCode> {
+        let mut plaintext = serialize_credential(credential)?;
+        let random = rng.gen_uniform_u8x32();
+        let nonce = *array_ref!(random, 0, NONCE_SIZE);
+        let mut record = Vec::with_capacity(NONCE_SIZE + plaintext.len() + TAG_SIZE);
+        record.extend_from_slice(&nonce);
+        record.extend_from_slice(&plaintext);
+        plaintext.zeroize();
+        let tag = gcm_encrypt(
+            &aes256::EncryptionKey::new(&self.key),
+            &nonce,
+            &self.header,
+            &mut record[NONCE_SIZE..],
+        );
+        record.extend_from_slice(&tag);
+        Ok(record)
+    }
+
+    // Decrypts a record of the backup. Fails with CTAP2_ERR_INTEGRITY_FAILURE if the passphrase is
+    // wrong, or the record was modified or belongs to another backup.
+    pub fn decrypt(&self, record: &[u8]) -> Result<PublicKeyCredentialSource, Ctap2StatusCode> {
+        if record.len() < NONCE_SIZE + TAG_SIZE {
+            return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
+        }
+        let nonce = array_ref![record, 0, NONCE_SIZE];
+        let tag = array_ref![record, record.len() - TAG_SIZE, TAG_SIZE];
+        let mut plaintext = record[NONCE_SIZE..record.len() - TAG_SIZE].to_vec();
+        let authentic = gcm_decrypt(
+            &aes256::EncryptionKey::new(&self.key),
+            nonce,
+            &self.header,
+            &mut plaintext,
+            tag,
+        );
+        // The plaintext holds the private key, so it is wiped whether or not it decrypted.
+        let decoded_cbor = if authentic {
+            cbor::read(&plaintext).ok()
+        } else {
+            None
+        };
+        plaintext.zeroize();
+        decoded_cbor
+            .and_then(|cbor_value| PublicKeyCredentialSource::try_from(cbor_value).ok())
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::data_formats::PublicKeyCredentialType;
+    use super::*;
+    use crypto::rng256::ThreadRng256;
+
+    const PASSPHRASE: &[u8] = b"correct horse battery staple";
+
+    fn no_keepalive() -> Result<(), Ctap2StatusCode> {
+        Ok(())
+    }
+
+    fn create_credential(rng: &mut ThreadRng256) -> PublicKeyCredentialSource {
+        PublicKeyCredentialSource {
+            key_type: PublicKeyCredentialType::PublicKey,
+            credential_id: rng.gen_uniform_u8x32().to_vec(),
+            private_key: crypto::ecdsa::SecKey::gensk(rng).into(),
+            rp_id: String::from("example.com"),
+            user_handle: vec![0x1D],
+            user_display_name: None,
+            cred_protect_policy: None,
+            creation_order: 0,
+            last_used_order: None,
+            signature_counter: None,
+            user_name: None,
+            user_icon: None,
+            large_blob_key: None,
+            cred_blob: None,
+        }
+    }
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let mut rng = ThreadRng256 {};
+        let credential = create_credential(&mut rng);
+        let backup_key = BackupKey::new(&mut rng, PASSPHRASE, no_keepalive).unwrap();
+        let record = backup_key.encrypt(&mut rng, credential.clone()).unwrap();
+
+        // The replacement device derives the same key from the header.
+        let restore_key =
+            BackupKey::from_header(PASSPHRASE, backup_key.header(), no_keepalive).unwrap();
+        assert_eq!(restore_key.decrypt(&record), Ok(credential));
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let mut rng = ThreadRng256 {};
+        let credential = create_credential(&mut rng);
+        let backup_key = BackupKey::new(&mut rng, PASSPHRASE, no_keepalive).unwrap();
+        let record = backup_key.encrypt(&mut rng, credential).unwrap();
+
+        let restore_key =
+            BackupKey::from_header(b"wrong passphrase", backup_key.header(), no_keepalive).unwrap();
+        assert_eq!(
+            restore_key.decrypt(&record),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+    }
+
+    #[test]
+    fn test_modified_record() {
+        let mut rng = ThreadRng256 {};
+        let credential = create_credential(&mut rng);
+        let backup_key = BackupKey::new(&mut rng, PASSPHRASE, no_keepalive).unwrap();
+        let mut record = backup_key.encrypt(&mut rng, credential).unwrap();
+        record[NONCE_SIZE] ^= 0x01;
+        assert_eq!(
+            backup_key.decrypt(&record),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+        assert_eq!(
+            backup_key.decrypt(&record[..NONCE_SIZE + TAG_SIZE - 1]),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+
+        // Records of another backup don't decrypt, even with the same passphrase.
+        let other_key = BackupKey::new(&mut rng, PASSPHRASE, no_keepalive).unwrap();
+        let credential = create_credential(&mut rng);
+        let other_record = other_key.encrypt(&mut rng, credential).unwrap();
+        assert_eq!(
+            backup_key.decrypt(&other_record),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+    }
+
+    #[test]
+    fn test_passphrase_too_short() {
+        let mut rng = ThreadRng256 {};
+        assert!(BackupKey::new(
+            &mut rng,
+            &PASSPHRASE[..MIN_PASSPHRASE_LENGTH - 1],
+            no_keepalive
+        )
+        .is_err());
+        assert!(
+            BackupKey::new(&mut rng, &PASSPHRASE[..MIN_PASSPHRASE_LENGTH], no_keepalive).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_keepalive() {
+        let mut rng = ThreadRng256 {};
+        let mut keepalives = 0;
+        let backup_key = BackupKey::new(&mut rng, PASSPHRASE, || {
+            keepalives += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert!(keepalives > 0);
+
+        // A cancelled keepalive aborts the key derivation.
+        let cancel = || Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
+        assert_eq!(
+            BackupKey::from_header(PASSPHRASE, backup_key.header(), cancel).err(),
+            Some(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+        );
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut rng = ThreadRng256 {};
+        let backup_key = BackupKey::new(&mut rng, PASSPHRASE, no_keepalive).unwrap();
+        let header = backup_key.header();
+        assert_eq!(
+            BackupKey::from_header(PASSPHRASE, &header[..HEADER_LENGTH - 1], no_keepalive).err(),
+            Some(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        let mut unknown_version = header.to_vec();
+        unknown_version[0] = BACKUP_VERSION + 1;
+        assert_eq!(
+            BackupKey::from_header(PASSPHRASE, &unknown_version, no_keepalive).err(),
+            Some(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM)
+        );
+
+        let mut too_many_iterations = header.to_vec();
+        too_many_iterations[1..5].copy_from_slice(&(MAX_KDF_ITERATIONS + 1).to_be_bytes());
+        assert_eq!(
+            BackupKey::from_header(PASSPHRASE, &too_many_iterations, no_keepalive).err(),
+            Some(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Timings of the cryptographic primitives and of the persistent store, measured on the device.
+//
+// Each operation is repeated, and its average duration is reported in microseconds. The clock
+// counts milliseconds, so the repetitions also make short operations measurable.
+
+use super::response::AuthenticatorVendorBenchmarkResponse;
+use super::status_code::Ctap2StatusCode;
+use super::storage::PersistentStore;
+use crypto::gcm::{gcm_encrypt, NONCE_SIZE};
+use crypto::hmac::hmac_256;
+use crypto::rng256::Rng256;
+use crypto::sha256::Sha256;
+use crypto::{aes256, ecdh, ecdsa, Hash256};
+
+// Length of the messages that are hashed, authenticated and encrypted.
+const MESSAGE_LENGTH: usize = 1024;
+const HASH_ITERATIONS: u64 = 16;
+const AES_ITERATIONS: u64 = 16;
+const PUBLIC_KEY_ITERATIONS: u64 = 4;
+const STORE_WRITE_ITERATIONS: u64 = 8;
+
+// Runs the benchmark. Store writes use a scratch entry, which is removed afterwards. The
+// compaction erases a page, so it costs some lifetime of the flash.
+pub fn run(
+    rng: &mut impl Rng256,
+    persistent_store: &mut PersistentStore,
+) -> Result<AuthenticatorVendorBenchmarkResponse, Ctap2StatusCode> {
+    let key = rng.gen_uniform_u8x32();
+    let mut message = [0x55; MESSAGE_LENGTH];
+
+    let sha256_us = measure(HASH_ITERATIONS, || {
+        Sha256::hash(&message);
+        Ok(())
+    })?;
+    let hmac_sha256_us = measure(HASH_ITERATIONS, || {
+        hmac_256::<Sha256>(&key, &message);
+        Ok(())
+    })?;
+    let aes_key = aes256::EncryptionKey::new(&key);
+    let aes256_gcm_us = measure(AES_ITERATIONS, || {
+        gcm_encrypt(&aes_key, &[0; NONCE_SIZE], &[], &mut message);
+        Ok(())
+    })?;
+
+    let signing_key = ecdsa::SecKey::gensk(rng);
+    let digest = Sha256::hash(&message);
+    let ecdsa_sign_us = measure(PUBLIC_KEY_ITERATIONS, || {
+        signing_key.sign_hedged_digest::<Sha256, _>(&digest, rng);
+        Ok(())
+    })?;
+    let agreement_key = ecdh::SecKey::gensk(rng);
+    let peer_key = ecdh::SecKey::gensk(rng).genpk();
+    let ecdh_us = measure(PUBLIC_KEY_ITERATIONS, || {
+        agreement_key.exchange_x(&peer_key);
+        Ok(())
+    })?;
+
+    let store_write_us = measure(STORE_WRITE_ITERATIONS, || {
+        persistent_store.write_benchmark_entry(&key)
+    });
+    // The scratch entry is removed even if a write failed.
+    persistent_store.remove_benchmark_entry()?;
+    let store_write_us = store_write_us?;
+    let store_compaction_us = measure(1, || persistent_store.compact_once())?;
+
+    Ok(AuthenticatorVendorBenchmarkResponse {
+        sha256_us,
+        hmac_sha256_us,
+        aes256_gcm_us,
+        ecdsa_sign_us,
+        ecdh_us,
+        store_write_us,
+        store_compaction_us,
+    })
+}
+
+// Returns the average duration of the operation in microseconds.
+#[cfg(not(feature = "std"))]
+fn measure(
+    iterations: u64,
+    mut operation: impl FnMut() -> Result<(), Ctap2StatusCode>,
+) -> Result<u64, Ctap2StatusCode> {
+    use libtock_drivers::timer;
+
+    let mut with_callback = timer::with_callback(|_, _| {});
+    let timer = with_callback
+        .init()
+        .map_err(|_| Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)?;
+    let start = timer
+        .get_current_clock()
+        .map_err(|_| Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)?;
+    for _ in 0..iterations {
+        operation()?;
+    }
+    let end = timer
+        .get_current_clock()
+        .map_err(|_| Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)?;
+    let elapsed_ms = end
+        .wrapping_sub(start)
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)?
+        .ms();
+    // A negative duration means that the clock wrapped during the measurement.
+    if elapsed_ms < 0 {
+        return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE);
+    }
+    Ok(elapsed_ms as u64 * 1000 / iterations)
+}
+
+#[cfg(feature = "std")]
+fn measure(
+    iterations: u64,
+    mut operation: impl FnMut() -> Result<(), Ctap2StatusCode>,
+) -> Result<u64, Ctap2StatusCode> {
+    let start = std::time::Instant::now();
+    for _ in 0..iterations {
+        operation()?;
+    }
+    Ok(start.elapsed().as_micros() as u64 / iterations)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::rng256::ThreadRng256;
+
+    #[test]
+    fn test_run() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let response = run(&mut rng, &mut persistent_store).unwrap();
+        // Public key operations are the slowest.
+        assert!(response.ecdsa_sign_us > response.sha256_us);
+        assert!(response.ecdh_us > response.sha256_us);
+    }
+}
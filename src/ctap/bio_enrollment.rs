@@ -0,0 +1,1030 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::command::AuthenticatorBioEnrollmentParameters;
+use super::data_formats::{ok_or_missing, BioEnrollmentSubCommand, TemplateInfo};
+use super::pin_protocol_v1::{PinPermission, PinProtocolV1, PIN_PROTOCOLS};
+use super::response::{AuthenticatorBioEnrollmentResponse, ResponseData};
+use super::status_code::Ctap2StatusCode;
+use super::storage::PersistentStore;
+use alloc::vec;
+
+// Fingerprints are the only modality of CTAP 2.1.
+const FINGERPRINT_MODALITY: u64 = 0x01;
+// The uvModality of getInfo is a FIDO registry value: USER_VERIFY_FINGERPRINT_INTERNAL.
+pub const FINGERPRINT_UV_MODALITY: u64 = 0x02;
+// The timeout of a sample capture, if the platform doesn't choose one.
+const DEFAULT_CAPTURE_TIMEOUT_MS: u64 = 30000;
+// The maximum length of a template friendly name, in bytes.
+const MAX_TEMPLATE_FRIENDLY_NAME: usize = 64;
+
+/// Outcome of a sample captured during an enrollment.
+pub struct SampleCapture {
+    /// The lastEnrollSampleStatus of CTAP 2.1.
+    pub sample_status: u8,
+    /// Number of good samples still needed to complete the enrollment.
+    pub remaining_samples: u64,
+}
+
+/// Interface of the fingerprint sensor, which stores the templates.
+///
+/// Templates are identified by a single byte, which is also their templateId in CTAP.
+pub trait FingerprintSensor {
+    /// Returns whether a sensor is connected.
+    fn is_available(&self) -> bool;
+
+    /// Returns the fingerprintKind of CTAP 2.1: 1 for touch, 2 for swipe sensors.
+    fn fingerprint_kind(&self) -> u64;
+
+    /// Returns the number of good samples needed to enroll a template.
+    fn max_capture_samples(&self) -> u64;
+
+    /// Starts the enrollment of a new template and captures its first sample.
+    fn enroll_begin(&mut self, timeout_ms: u64) -> Result<(u8, SampleCapture), Ctap2StatusCode>;
+
+    /// Captures the next sample of the template being enrolled.
+    fn enroll_capture_next(
+        &mut self,
+        template_id: u8,
+        timeout_ms: u64,
+    ) -> Result<SampleCapture, Ctap2StatusCode>;
+
+    /// Aborts the enrollment in progress, if any.
+    fn cancel_enrollment(&mut self);
+
+    fn remove_template(&mut self, template_id: u8) -> Result<(), Ctap2StatusCode>;
+
+    fn remove_all_templates(&mut self) -> Result<(), Ctap2StatusCode>;
+
+    /// Captures a finger and returns the matching template, if any.
+    fn identify(&mut self, timeout_ms: u64) -> Result<Option<u8>, Ctap2StatusCode>;
+}
+
+#[cfg(not(feature = "std"))]
+mod prod {
+    use super::{FingerprintSensor, SampleCapture};
+    use crate::ctap::status_code::Ctap2StatusCode;
+    use core::convert::TryFrom;
+    use libtock_drivers::fingerprint::{self, FingerprintError, FingerprintKind};
+
+    impl From<FingerprintError> for Ctap2StatusCode {
+        fn from(error: FingerprintError) -> Self {
+            match error {
+                FingerprintError::DriverMissing => Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND,
+                FingerprintError::Timeout => Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT,
+                FingerprintError::DatabaseFull => Ctap2StatusCode::CTAP2_ERR_FP_DATABASE_FULL,
+                FingerprintError::InvalidTemplate => Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION,
+                FingerprintError::Cancelled => Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL,
+                FingerprintError::Kernel(_) => Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE,
+            }
+        }
+    }
+
+    impl From<fingerprint::SampleCapture> for SampleCapture {
+        fn from(sample: fingerprint::SampleCapture) -> Self {
+            SampleCapture {
+                sample_status: sample.sample_status,
+                remaining_samples: sample.remaining_samples as u64,
+            }
+        }
+    }
+
+    /// The sensor capsule of the kernel.
+    pub struct TockFingerprintSensor {}
+
+    impl TockFingerprintSensor {
+        pub fn new() -> TockFingerprintSensor {
+            TockFingerprintSensor {}
+        }
+    }
+
+    impl FingerprintSensor for TockFingerprintSensor {
+        fn is_available(&self) -> bool {
+            fingerprint::is_available().is_ok()
+        }
+
+        fn fingerprint_kind(&self) -> u64 {
+            match fingerprint::fingerprint_kind() {
+                Ok(FingerprintKind::Swipe) => FingerprintKind::Swipe as u64,
+                _ => FingerprintKind::Touch as u64,
+            }
+        }
+
+        fn max_capture_samples(&self) -> u64 {
+            fingerprint::max_capture_samples().unwrap_or(0) as u64
+        }
+
+        fn enroll_begin(
+            &mut self,
+            timeout_ms: u64,
+        ) -> Result<(u8, SampleCapture), Ctap2StatusCode> {
+            let (template_id, sample) = fingerprint::enroll_begin(timeout_ms as usize)?;
+            // Template IDs are a single byte, so the sensor can't hold more than 256 templates.
+            let template_id = u8::try_from(template_id)
+                .map_err(|_| Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)?;
+            Ok((template_id, sample.into()))
+        }
+
+        fn enroll_capture_next(
+            &mut self,
+            template_id: u8,
+            timeout_ms: u64,
+        ) -> Result<SampleCapture, Ctap2StatusCode> {
+            Ok(fingerprint::enroll_capture_next(template_id as usize, timeout_ms as usize)?.into())
+        }
+
+        fn cancel_enrollment(&mut self) {
+            // There is nothing to do if the enrollment was already over.
+            fingerprint::enroll_cancel().ok();
+        }
+
+        fn remove_template(&mut self, template_id: u8) -> Result<(), Ctap2StatusCode> {
+            Ok(fingerprint::remove_template(template_id as usize)?)
+        }
+
+        fn remove_all_templates(&mut self) -> Result<(), Ctap2StatusCode> {
+            Ok(fingerprint::remove_all_templates()?)
+        }
+
+        fn identify(&mut self, timeout_ms: u64) -> Result<Option<u8>, Ctap2StatusCode> {
+            // Template IDs that don't fit a byte were never enrolled through CTAP.
+            Ok(fingerprint::identify(timeout_ms as usize)?
+                .and_then(|template_id| u8::try_from(template_id).ok()))
+        }
+    }
+}
+#[cfg(not(feature = "std"))]
+pub use self::prod::TockFingerprintSensor as DefaultFingerprintSensor;
+
+// Sensor for testing, where the fingerprint driver is missing.
+#[cfg(feature = "std")]
+mod missing {
+    use super::{FingerprintSensor, SampleCapture};
+    use crate::ctap::status_code::Ctap2StatusCode;
+
+    pub struct MissingFingerprintSensor {}
+
+    impl MissingFingerprintSensor {
+        pub fn new() -> MissingFingerprintSensor {
+            MissingFingerprintSensor {}
+        }
+    }
+
+    impl FingerprintSensor for MissingFingerprintSensor {
+        fn is_available(&self) -> bool {
+            false
+        }
+
+        fn fingerprint_kind(&self) -> u64 {
+            0
+        }
+
+        fn max_capture_samples(&self) -> u64 {
+            0
+        }
+
+        fn enroll_begin(&mut self, _: u64) -> Result<(u8, SampleCapture), Ctap2StatusCode> {
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND)
+        }
+
+        fn enroll_capture_next(&mut self, _: u8, _: u64) -> Result<SampleCapture, Ctap2StatusCode> {
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND)
+        }
+
+        fn cancel_enrollment(&mut self) {}
+
+        fn remove_template(&mut self, _: u8) -> Result<(), Ctap2StatusCode> {
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND)
+        }
+
+        fn remove_all_templates(&mut self) -> Result<(), Ctap2StatusCode> {
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND)
+        }
+
+        fn identify(&mut self, _: u64) -> Result<Option<u8>, Ctap2StatusCode> {
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND)
+        }
+    }
+}
+#[cfg(feature = "std")]
+pub use self::missing::MissingFingerprintSensor as DefaultFingerprintSensor;
+
+// Returns a response where all fields are absent.
+fn empty_response() -> AuthenticatorBioEnrollmentResponse {
+    AuthenticatorBioEnrollmentResponse {
+        modality: None,
+        fingerprint_kind: None,
+        max_capture_samples_required_for_enroll: None,
+        template_id: None,
+        last_enroll_sample_status: None,
+        remaining_samples: None,
+        template_infos: None,
+        max_template_friendly_name: None,
+    }
+}
+
+/// Implements authenticatorBioEnrollment on top of a fingerprint sensor.
+///
+/// The sensor stores the templates, while their friendly names are kept in the persistent store.
+pub struct BioEnrollment<S: FingerprintSensor> {
+    sensor: S,
+    // The template that is being enrolled, until its last sample is captured.
+    current_template_id: Option<u8>,
+}
+
+impl<S: FingerprintSensor> BioEnrollment<S> {
+    pub fn new(sensor: S) -> BioEnrollment<S> {
+        BioEnrollment {
+            sensor,
+            current_template_id: None,
+        }
+    }
+
+    /// Returns whether bio enrollment is supported, i.e. a sensor is connected.
+    pub fn is_available(&self) -> bool {
+        self.sensor.is_available()
+    }
+
+    /// Processes the AuthenticatorBioEnrollment command.
+    pub fn process_command(
+        &mut self,
+        persistent_store: &mut PersistentStore,
+        pin_protocol_v1: &mut PinProtocolV1,
+        params: AuthenticatorBioEnrollmentParameters,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        if !self.sensor.is_available() {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND);
+        }
+        let AuthenticatorBioEnrollmentParameters {
+            modality,
+            sub_command,
+            sub_command_params,
+            pin_uv_auth_protocol,
+            pin_uv_auth_param,
+            get_modality,
+        } = params;
+
+        if get_modality {
+            return Ok(ResponseData::AuthenticatorBioEnrollment(Some(
+                AuthenticatorBioEnrollmentResponse {
+                    modality: Some(FINGERPRINT_MODALITY),
+                    ..empty_response()
+                },
+            )));
+        }
+        match modality {
+            Some(FINGERPRINT_MODALITY) => (),
+            Some(_) => return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_OPTION),
+            None => return Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER),
+        }
+        let sub_command = ok_or_missing(sub_command)?;
+
+        // Reading the sensor properties and cancelling don't need user verification.
+        let needs_pin_uv_auth = match sub_command {
+            BioEnrollmentSubCommand::GetFingerprintSensorInfo
+            | BioEnrollmentSubCommand::CancelCurrentEnrollment => false,
+            _ => true,
+        };
+        if needs_pin_uv_auth {
+            let pin_uv_auth_param =
+                pin_uv_auth_param.ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)?;
+            let pin_uv_auth_protocol = match pin_uv_auth_protocol {
+                Some(protocol) if PIN_PROTOCOLS.contains(&protocol) => protocol,
+                Some(_) => return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID),
+                None => return Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER),
+            };
+            // The message is the modality, the subcommand byte and the CBOR encoded subcommand
+            // parameters, if any.
+            let mut message = vec![FINGERPRINT_MODALITY as u8, sub_command as u8];
+            if let Some(sub_command_params) = sub_command_params.clone() {
+                if !cbor::write(sub_command_params.into(), &mut message) {
+                    return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR);
+                }
+            }
+            if !pin_protocol_v1.verify_pin_auth_token(
+                &message,
+                &pin_uv_auth_param,
+                pin_uv_auth_protocol,
+            ) {
+                return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+            }
+            pin_protocol_v1.has_permission(PinPermission::BioEnrollment)?;
+        }
+
+        let timeout_ms = sub_command_params
+            .as_ref()
+            .and_then(|params| params.timeout_milliseconds)
+            .unwrap_or(DEFAULT_CAPTURE_TIMEOUT_MS);
+        let (template_id, template_friendly_name) = match sub_command_params {
+            Some(params) => (params.template_id, params.template_friendly_name),
+            None => (None, None),
+        };
+
+        let response = match sub_command {
+            BioEnrollmentSubCommand::EnrollBegin => {
+                Some(self.process_enroll_begin(persistent_store, timeout_ms)?)
+            }
+            BioEnrollmentSubCommand::EnrollCaptureNextSample => {
+                let template_id = ok_or_missing(template_id)?;
+                Some(self.process_enroll_capture_next_sample(
+                    persistent_store,
+                    &template_id,
+                    timeout_ms,
+                )?)
+            }
+            BioEnrollmentSubCommand::CancelCurrentEnrollment => {
+                if self.current_template_id.take().is_some() {
+                    self.sensor.cancel_enrollment();
+                }
+                None
+            }
+            BioEnrollmentSubCommand::EnumerateEnrollments => {
+                let template_infos = persistent_store.fingerprint_templates()?;
+                if template_infos.is_empty() {
+                    return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION);
+                }
+                Some(AuthenticatorBioEnrollmentResponse {
+                    template_infos: Some(template_infos),
+                    ..empty_response()
+                })
+            }
+            BioEnrollmentSubCommand::SetFriendlyName => {
+                let template_id = ok_or_missing(template_id)?;
+                let template_friendly_name = ok_or_missing(template_friendly_name)?;
+                if template_friendly_name.len() > MAX_TEMPLATE_FRIENDLY_NAME {
+                    return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+                }
+                find_template(persistent_store, &template_id)?;
+                persistent_store.store_fingerprint_template(TemplateInfo {
+                    template_id,
+                    template_friendly_name: Some(template_friendly_name),
+                })?;
+                None
+            }
+            BioEnrollmentSubCommand::RemoveEnrollment => {
+                let template_id = ok_or_missing(template_id)?;
+                find_template(persistent_store, &template_id)?;
+                // Stored template IDs are a single byte.
+                self.sensor.remove_template(template_id[0])?;
+                persistent_store.remove_fingerprint_template(&template_id)?;
+                None
+            }
+            BioEnrollmentSubCommand::GetFingerprintSensorInfo => {
+                Some(AuthenticatorBioEnrollmentResponse {
+                    modality: Some(FINGERPRINT_MODALITY),
+                    fingerprint_kind: Some(self.sensor.fingerprint_kind()),
+                    max_capture_samples_required_for_enroll: Some(
+                        self.sensor.max_capture_samples(),
+                    ),
+                    max_template_friendly_name: Some(MAX_TEMPLATE_FRIENDLY_NAME as u64),
+                    ..empty_response()
+                })
+            }
+        };
+        Ok(ResponseData::AuthenticatorBioEnrollment(response))
+    }
+
+    /// Processes the subcommand enrollBegin for AuthenticatorBioEnrollment.
+    fn process_enroll_begin(
+        &mut self,
+        persistent_store: &mut PersistentStore,
+        timeout_ms: u64,
+    ) -> Result<AuthenticatorBioEnrollmentResponse, Ctap2StatusCode> {
+        // A new enrollment replaces the one in progress.
+        if self.current_template_id.take().is_some() {
+            self.sensor.cancel_enrollment();
+        }
+        let (template_id, sample) = self.sensor.enroll_begin(timeout_ms)?;
+        self.update_enrollment(persistent_store, template_id, &sample)?;
+        Ok(AuthenticatorBioEnrollmentResponse {
+            template_id: Some(vec![template_id]),
+            last_enroll_sample_status: Some(sample.sample_status as u64),
+            remaining_samples: Some(sample.remaining_samples),
+            ..empty_response()
+        })
+    }
+
+    /// Processes the subcommand enrollCaptureNextSample for AuthenticatorBioEnrollment.
+    fn process_enroll_capture_next_sample(
+        &mut self,
+        persistent_store: &mut PersistentStore,
+        template_id: &[u8],
+        timeout_ms: u64,
+    ) -> Result<AuthenticatorBioEnrollmentResponse, Ctap2StatusCode> {
+        let current_template_id = match self.current_template_id {
+            Some(id) if template_id == [id] => id,
+            _ => return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION),
+        };
+        let sample = self
+            .sensor
+            .enroll_capture_next(current_template_id, timeout_ms)?;
+        self.update_enrollment(persistent_store, current_template_id, &sample)?;
+        Ok(AuthenticatorBioEnrollmentResponse {
+            last_enroll_sample_status: Some(sample.sample_status as u64),
+            remaining_samples: Some(sample.remaining_samples),
+            ..empty_response()
+        })
+    }
+
+    // Stores the template once all samples are captured, or waits for the next sample.
+    fn update_enrollment(
+        &mut self,
+        persistent_store: &mut PersistentStore,
+        template_id: u8,
+        sample: &SampleCapture,
+    ) -> Result<(), Ctap2StatusCode> {
+        if sample.remaining_samples > 0 {
+            self.current_template_id = Some(template_id);
+            return Ok(());
+        }
+        self.current_template_id = None;
+        persistent_store.store_fingerprint_template(TemplateInfo {
+            template_id: vec![template_id],
+            template_friendly_name: None,
+        })
+    }
+
+    /// Returns whether built-in user verification is configured, i.e. a finger is enrolled.
+    pub fn has_built_in_uv(
+        &self,
+        persistent_store: &PersistentStore,
+    ) -> Result<bool, Ctap2StatusCode> {
+        Ok(self.sensor.is_available() && !persistent_store.fingerprint_templates()?.is_empty())
+    }
+
+    /// Verifies the user with an enrolled finger.
+    ///
+    /// Each mismatch consumes a retry. Without retries left, built-in user verification is
+    /// blocked until the user enters the correct PIN.
+    pub fn verify_user(
+        &mut self,
+        persistent_store: &mut PersistentStore,
+    ) -> Result<(), Ctap2StatusCode> {
+        if persistent_store.uv_retries()? == 0 {
+            return Err(Ctap2StatusCode::CTAP2_ERR_UV_BLOCKED);
+        }
+        // The sensor can't identify a finger while it is enrolling one.
+        if self.current_template_id.take().is_some() {
+            self.sensor.cancel_enrollment();
+        }
+        if let Some(template_id) = self.sensor.identify(DEFAULT_CAPTURE_TIMEOUT_MS)? {
+            if find_template(persistent_store, &[template_id]).is_ok() {
+                persistent_store.reset_uv_retries()?;
+                return Ok(());
+            }
+        }
+        persistent_store.decr_uv_retries()?;
+        if persistent_store.uv_retries()? == 0 {
+            Err(Ctap2StatusCode::CTAP2_ERR_UV_BLOCKED)
+        } else {
+            Err(Ctap2StatusCode::CTAP2_ERR_UV_INVALID)
+        }
+    }
+
+    /// Verifies the user for a command that also accepts a pinUvAuthParam.
+    ///
+    /// Once built-in user verification is blocked, the platform is asked to
+    /// fall back to the PIN, if there is one.
+    pub fn verify_user_or_require_pin(
+        &mut self,
+        persistent_store: &mut PersistentStore,
+    ) -> Result<(), Ctap2StatusCode> {
+        match self.verify_user(persistent_store) {
+            Err(Ctap2StatusCode::CTAP2_ERR_UV_BLOCKED)
+                if persistent_store.pin_hash()?.is_some() =>
+            {
+                Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)
+            }
+            result => result,
+        }
+    }
+
+    /// Returns whether built-in user verification is configured and not blocked.
+    pub fn is_built_in_uv_usable(
+        &self,
+        persistent_store: &PersistentStore,
+    ) -> Result<bool, Ctap2StatusCode> {
+        Ok(self.has_built_in_uv(persistent_store)? && persistent_store.uv_retries()? > 0)
+    }
+
+    /// Removes all templates from the sensor. Their friendly names are removed with the rest of
+    /// the persistent store.
+    pub fn reset(&mut self) -> Result<(), Ctap2StatusCode> {
+        if self.current_template_id.take().is_some() {
+            self.sensor.cancel_enrollment();
+        }
+        if self.sensor.is_available() {
+            self.sensor.remove_all_templates()?;
+        }
+        Ok(())
+    }
+}
+
+// Checks that the template exists in the persistent store.
+fn find_template(
+    persistent_store: &PersistentStore,
+    template_id: &[u8],
+) -> Result<(), Ctap2StatusCode> {
+    if persistent_store
+        .fingerprint_templates()?
+        .iter()
+        .any(|t| t.template_id == template_id)
+    {
+        Ok(())
+    } else {
+        Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::data_formats::BioEnrollmentSubCommandParams;
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use crypto::hmac::hmac_256;
+    use crypto::rng256::ThreadRng256;
+    use crypto::sha256::Sha256;
+
+    const PIN_UV_AUTH_TOKEN: [u8; 32] = [0x55; 32];
+    const SAMPLES_PER_ENROLLMENT: u64 = 2;
+
+    // Simulates a sensor where every capture succeeds.
+    struct FakeFingerprintSensor {
+        templates: Vec<u8>,
+        next_template_id: u8,
+        remaining_samples: u64,
+        // The template that matches the next finger, if any.
+        presented_finger: Option<u8>,
+    }
+
+    impl FakeFingerprintSensor {
+        fn new() -> FakeFingerprintSensor {
+            FakeFingerprintSensor {
+                templates: vec![],
+                next_template_id: 0,
+                remaining_samples: 0,
+                presented_finger: None,
+            }
+        }
+    }
+
+    impl FingerprintSensor for FakeFingerprintSensor {
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        fn fingerprint_kind(&self) -> u64 {
+            1
+        }
+
+        fn max_capture_samples(&self) -> u64 {
+            SAMPLES_PER_ENROLLMENT
+        }
+
+        fn enroll_begin(&mut self, _: u64) -> Result<(u8, SampleCapture), Ctap2StatusCode> {
+            let template_id = self.next_template_id;
+            self.next_template_id += 1;
+            self.remaining_samples = SAMPLES_PER_ENROLLMENT;
+            Ok((template_id, self.enroll_capture_next(template_id, 0)?))
+        }
+
+        fn enroll_capture_next(
+            &mut self,
+            template_id: u8,
+            _: u64,
+        ) -> Result<SampleCapture, Ctap2StatusCode> {
+            self.remaining_samples -= 1;
+            if self.remaining_samples == 0 {
+                self.templates.push(template_id);
+            }
+            Ok(SampleCapture {
+                sample_status: 0x00,
+                remaining_samples: self.remaining_samples,
+            })
+        }
+
+        fn cancel_enrollment(&mut self) {
+            self.remaining_samples = 0;
+        }
+
+        fn remove_template(&mut self, template_id: u8) -> Result<(), Ctap2StatusCode> {
+            self.templates.retain(|&id| id != template_id);
+            Ok(())
+        }
+
+        fn remove_all_templates(&mut self) -> Result<(), Ctap2StatusCode> {
+            self.templates.clear();
+            Ok(())
+        }
+
+        fn identify(&mut self, _: u64) -> Result<Option<u8>, Ctap2StatusCode> {
+            Ok(self
+                .presented_finger
+                .filter(|template_id| self.templates.contains(template_id)))
+        }
+    }
+
+    fn create_pin_protocol_v1(rng: &mut ThreadRng256) -> PinProtocolV1 {
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(rng);
+        PinProtocolV1::new_test(key_agreement_key, PIN_UV_AUTH_TOKEN)
+    }
+
+    fn create_params(
+        sub_command: BioEnrollmentSubCommand,
+        sub_command_params: Option<BioEnrollmentSubCommandParams>,
+    ) -> AuthenticatorBioEnrollmentParameters {
+        let mut message = vec![FINGERPRINT_MODALITY as u8, sub_command as u8];
+        if let Some(sub_command_params) = sub_command_params.clone() {
+            assert!(cbor::write(sub_command_params.into(), &mut message));
+        }
+        let pin_uv_auth_param = hmac_256::<Sha256>(&PIN_UV_AUTH_TOKEN, &message)[..16].to_vec();
+        AuthenticatorBioEnrollmentParameters {
+            modality: Some(FINGERPRINT_MODALITY),
+            sub_command: Some(sub_command),
+            sub_command_params,
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: Some(pin_uv_auth_param),
+            get_modality: false,
+        }
+    }
+
+    fn create_template_params(
+        template_id: Vec<u8>,
+        template_friendly_name: Option<String>,
+    ) -> Option<BioEnrollmentSubCommandParams> {
+        Some(BioEnrollmentSubCommandParams {
+            template_id: Some(template_id),
+            template_friendly_name,
+            timeout_milliseconds: None,
+        })
+    }
+
+    #[test]
+    fn test_get_modality() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol_v1(&mut rng);
+        let mut bio_enrollment = BioEnrollment::new(FakeFingerprintSensor::new());
+
+        let params = AuthenticatorBioEnrollmentParameters {
+            modality: None,
+            sub_command: None,
+            sub_command_params: None,
+            pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
+            get_modality: true,
+        };
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        let expected_response = AuthenticatorBioEnrollmentResponse {
+            modality: Some(FINGERPRINT_MODALITY),
+            ..empty_response()
+        };
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorBioEnrollment(Some(
+                expected_response
+            )))
+        );
+    }
+
+    #[test]
+    fn test_missing_sensor() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol_v1(&mut rng);
+        let mut bio_enrollment = BioEnrollment::new(DefaultFingerprintSensor::new());
+        assert!(!bio_enrollment.is_available());
+
+        let params = create_params(BioEnrollmentSubCommand::GetFingerprintSensorInfo, None);
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND));
+        assert_eq!(bio_enrollment.reset(), Ok(()));
+    }
+
+    #[test]
+    fn test_get_fingerprint_sensor_info() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol_v1(&mut rng);
+        let mut bio_enrollment = BioEnrollment::new(FakeFingerprintSensor::new());
+
+        let mut params = create_params(BioEnrollmentSubCommand::GetFingerprintSensorInfo, None);
+        params.pin_uv_auth_param = None;
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        let expected_response = AuthenticatorBioEnrollmentResponse {
+            modality: Some(FINGERPRINT_MODALITY),
+            fingerprint_kind: Some(1),
+            max_capture_samples_required_for_enroll: Some(SAMPLES_PER_ENROLLMENT),
+            max_template_friendly_name: Some(MAX_TEMPLATE_FRIENDLY_NAME as u64),
+            ..empty_response()
+        };
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorBioEnrollment(Some(
+                expected_response
+            )))
+        );
+    }
+
+    #[test]
+    fn test_unsupported_modality() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol_v1(&mut rng);
+        let mut bio_enrollment = BioEnrollment::new(FakeFingerprintSensor::new());
+
+        let mut params = create_params(BioEnrollmentSubCommand::GetFingerprintSensorInfo, None);
+        params.modality = Some(0x02);
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_OPTION));
+    }
+
+    #[test]
+    fn test_enroll_requires_pin_auth() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol_v1(&mut rng);
+        let mut bio_enrollment = BioEnrollment::new(FakeFingerprintSensor::new());
+
+        let mut params = create_params(BioEnrollmentSubCommand::EnrollBegin, None);
+        params.pin_uv_auth_param = None;
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED));
+
+        let mut params = create_params(BioEnrollmentSubCommand::EnrollBegin, None);
+        params.pin_uv_auth_param = Some(vec![0x00; 16]);
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID));
+    }
+
+    #[test]
+    fn test_enroll_enumerate_rename_remove() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol_v1(&mut rng);
+        let mut bio_enrollment = BioEnrollment::new(FakeFingerprintSensor::new());
+
+        let params = create_params(BioEnrollmentSubCommand::EnrollBegin, None);
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        let expected_response = AuthenticatorBioEnrollmentResponse {
+            template_id: Some(vec![0x00]),
+            last_enroll_sample_status: Some(0x00),
+            remaining_samples: Some(1),
+            ..empty_response()
+        };
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorBioEnrollment(Some(
+                expected_response
+            )))
+        );
+        // The template is only listed once the enrollment is complete.
+        assert!(persistent_store.fingerprint_templates().unwrap().is_empty());
+
+        let params = create_params(
+            BioEnrollmentSubCommand::EnrollCaptureNextSample,
+            create_template_params(vec![0x00], None),
+        );
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        let expected_response = AuthenticatorBioEnrollmentResponse {
+            last_enroll_sample_status: Some(0x00),
+            remaining_samples: Some(0),
+            ..empty_response()
+        };
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorBioEnrollment(Some(
+                expected_response
+            )))
+        );
+
+        let template_friendly_name = String::from("Left thumb");
+        let params = create_params(
+            BioEnrollmentSubCommand::SetFriendlyName,
+            create_template_params(vec![0x00], Some(template_friendly_name.clone())),
+        );
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Ok(ResponseData::AuthenticatorBioEnrollment(None)));
+
+        let params = create_params(BioEnrollmentSubCommand::EnumerateEnrollments, None);
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        let expected_response = AuthenticatorBioEnrollmentResponse {
+            template_infos: Some(vec![TemplateInfo {
+                template_id: vec![0x00],
+                template_friendly_name: Some(template_friendly_name),
+            }]),
+            ..empty_response()
+        };
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorBioEnrollment(Some(
+                expected_response
+            )))
+        );
+
+        let params = create_params(
+            BioEnrollmentSubCommand::RemoveEnrollment,
+            create_template_params(vec![0x00], None),
+        );
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Ok(ResponseData::AuthenticatorBioEnrollment(None)));
+        assert!(bio_enrollment.sensor.templates.is_empty());
+
+        let params = create_params(BioEnrollmentSubCommand::EnumerateEnrollments, None);
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION));
+    }
+
+    #[test]
+    fn test_capture_without_enrollment() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol_v1(&mut rng);
+        let mut bio_enrollment = BioEnrollment::new(FakeFingerprintSensor::new());
+
+        let params = create_params(
+            BioEnrollmentSubCommand::EnrollCaptureNextSample,
+            create_template_params(vec![0x00], None),
+        );
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION));
+    }
+
+    #[test]
+    fn test_cancel_current_enrollment() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol_v1(&mut rng);
+        let mut bio_enrollment = BioEnrollment::new(FakeFingerprintSensor::new());
+
+        let params = create_params(BioEnrollmentSubCommand::EnrollBegin, None);
+        assert!(bio_enrollment
+            .process_command(&mut persistent_store, &mut pin_protocol_v1, params)
+            .is_ok());
+        let mut params = create_params(BioEnrollmentSubCommand::CancelCurrentEnrollment, None);
+        params.pin_uv_auth_param = None;
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Ok(ResponseData::AuthenticatorBioEnrollment(None)));
+
+        let params = create_params(
+            BioEnrollmentSubCommand::EnrollCaptureNextSample,
+            create_template_params(vec![0x00], None),
+        );
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION));
+    }
+
+    #[test]
+    fn test_set_friendly_name_unknown_template() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol_v1(&mut rng);
+        let mut bio_enrollment = BioEnrollment::new(FakeFingerprintSensor::new());
+
+        let params = create_params(
+            BioEnrollmentSubCommand::SetFriendlyName,
+            create_template_params(vec![0x07], Some(String::from("Left thumb"))),
+        );
+        let response =
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, params);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION));
+    }
+
+    #[test]
+    fn test_verify_user() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut bio_enrollment = BioEnrollment::new(FakeFingerprintSensor::new());
+        assert_eq!(bio_enrollment.has_built_in_uv(&persistent_store), Ok(false));
+
+        bio_enrollment.sensor.templates.push(0x00);
+        persistent_store
+            .store_fingerprint_template(TemplateInfo {
+                template_id: vec![0x00],
+                template_friendly_name: None,
+            })
+            .unwrap();
+        assert_eq!(bio_enrollment.has_built_in_uv(&persistent_store), Ok(true));
+
+        bio_enrollment.sensor.presented_finger = Some(0x01);
+        assert_eq!(
+            bio_enrollment.verify_user(&mut persistent_store),
+            Err(Ctap2StatusCode::CTAP2_ERR_UV_INVALID)
+        );
+        let uv_retries = persistent_store.uv_retries().unwrap();
+
+        bio_enrollment.sensor.presented_finger = Some(0x00);
+        assert_eq!(bio_enrollment.verify_user(&mut persistent_store), Ok(()));
+        assert!(persistent_store.uv_retries().unwrap() > uv_retries);
+    }
+
+    #[test]
+    fn test_verify_user_blocked() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut bio_enrollment = BioEnrollment::new(FakeFingerprintSensor::new());
+        bio_enrollment.sensor.templates.push(0x00);
+        persistent_store
+            .store_fingerprint_template(TemplateInfo {
+                template_id: vec![0x00],
+                template_friendly_name: None,
+            })
+            .unwrap();
+
+        while persistent_store.uv_retries().unwrap() > 1 {
+            assert_eq!(
+                bio_enrollment.verify_user(&mut persistent_store),
+                Err(Ctap2StatusCode::CTAP2_ERR_UV_INVALID)
+            );
+        }
+        assert_eq!(
+            bio_enrollment.verify_user(&mut persistent_store),
+            Err(Ctap2StatusCode::CTAP2_ERR_UV_BLOCKED)
+        );
+        // Even the right finger is rejected once blocked.
+        bio_enrollment.sensor.presented_finger = Some(0x00);
+        assert_eq!(
+            bio_enrollment.verify_user(&mut persistent_store),
+            Err(Ctap2StatusCode::CTAP2_ERR_UV_BLOCKED)
+        );
+    }
+
+    #[test]
+    fn test_verify_user_or_require_pin() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut bio_enrollment = BioEnrollment::new(FakeFingerprintSensor::new());
+        bio_enrollment.sensor.templates.push(0x00);
+        persistent_store
+            .store_fingerprint_template(TemplateInfo {
+                template_id: vec![0x00],
+                template_friendly_name: None,
+            })
+            .unwrap();
+        assert_eq!(
+            bio_enrollment.is_built_in_uv_usable(&persistent_store),
+            Ok(true)
+        );
+
+        while persistent_store.uv_retries().unwrap() > 0 {
+            persistent_store.decr_uv_retries().unwrap();
+        }
+        assert_eq!(
+            bio_enrollment.is_built_in_uv_usable(&persistent_store),
+            Ok(false)
+        );
+        // Without a PIN, there is nothing to fall back to.
+        bio_enrollment.sensor.presented_finger = Some(0x00);
+        assert_eq!(
+            bio_enrollment.verify_user_or_require_pin(&mut persistent_store),
+            Err(Ctap2StatusCode::CTAP2_ERR_UV_BLOCKED)
+        );
+        persistent_store.set_pin_hash(&[0x88; 16]).unwrap();
+        assert_eq!(
+            bio_enrollment.verify_user_or_require_pin(&mut persistent_store),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)
+        );
+
+        // The correct PIN unblocks built-in user verification.
+        persistent_store.reset_uv_retries().unwrap();
+        assert_eq!(
+            bio_enrollment.verify_user_or_require_pin(&mut persistent_store),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut bio_enrollment = BioEnrollment::new(FakeFingerprintSensor::new());
+        bio_enrollment.sensor.templates.push(0x00);
+        assert_eq!(bio_enrollment.reset(), Ok(()));
+        assert!(bio_enrollment.sensor.templates.is_empty());
+    }
+}
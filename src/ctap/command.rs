@@ -12,13 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::cose::CoseKey;
 use super::data_formats::{
     extract_array, extract_bool, extract_byte_string, extract_map, extract_text_string,
-    extract_unsigned, ok_or_missing, ClientPinSubCommand, CoseKey, GetAssertionExtensions,
-    GetAssertionOptions, MakeCredentialExtensions, MakeCredentialOptions,
-    PublicKeyCredentialDescriptor, PublicKeyCredentialParameter, PublicKeyCredentialRpEntity,
+    extract_unsigned, ok_or_missing, ClientPinSubCommand, CredentialProtectionPolicy,
+    GetAssertionExtensions, GetAssertionOptions, MakeCredentialExtensions, MakeCredentialOptions,
+    PublicKeyCredentialDescriptorList, PublicKeyCredentialParameter, PublicKeyCredentialRpEntity,
     PublicKeyCredentialUserEntity,
 };
+#[cfg(feature = "with_ctap2_1")]
+use super::data_formats::{
+    BioEnrollmentSubCommand, BioEnrollmentSubCommandParams, ConfigSubCommand,
+    ConfigSubCommandParams, CredentialManagementSubCommand, CredentialManagementSubCommandParams,
+    EnterpriseAttestationMode, SetMinPinLengthParams,
+};
 use super::key_material;
 use super::status_code::Ctap2StatusCode;
 use alloc::string::String;
@@ -42,10 +49,34 @@ pub enum Command {
     AuthenticatorReset,
     AuthenticatorGetNextAssertion,
     #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorBioEnrollment(AuthenticatorBioEnrollmentParameters),
+    #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorCredentialManagement(AuthenticatorCredentialManagementParameters),
+    #[cfg(feature = "with_ctap2_1")]
     AuthenticatorSelection,
+    #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorLargeBlobs(AuthenticatorLargeBlobsParameters),
+    #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorConfig(AuthenticatorConfigParameters),
     // TODO(kaczmarczyck) implement FIDO 2.1 commands (see below consts)
     // Vendor specific commands
     AuthenticatorVendorConfigure(AuthenticatorVendorConfigureParameters),
+    #[cfg(feature = "with_nfc")]
+    AuthenticatorVendorNfcStatistics,
+    #[cfg(feature = "with_nfc")]
+    AuthenticatorVendorNfcSelfTest,
+    AuthenticatorVendorProvision(AuthenticatorVendorProvisionParameters),
+    AuthenticatorVendorLockProvisioning,
+    AuthenticatorVendorRevokePinUvAuthToken,
+    AuthenticatorVendorRotateMasterKeys,
+    AuthenticatorVendorSelfTest,
+    AuthenticatorVendorBenchmark,
+    AuthenticatorVendorCompact,
+    AuthenticatorVendorWear,
+    AuthenticatorVendorBackup(AuthenticatorVendorBackupParameters),
+    AuthenticatorVendorRestore(AuthenticatorVendorRestoreParameters),
+    AuthenticatorVendorAuditLog,
+    AuthenticatorVendorDiagnostics,
 }
 
 impl From<cbor::reader::DecoderError> for Ctap2StatusCode {
@@ -71,6 +102,20 @@ impl Command {
     const AUTHENTICATOR_CONFIG: u8 = 0x0D;
     const _AUTHENTICATOR_VENDOR_FIRST: u8 = 0x40;
     const AUTHENTICATOR_VENDOR_CONFIGURE: u8 = 0x40;
+    const AUTHENTICATOR_VENDOR_NFC_STATISTICS: u8 = 0x41;
+    const AUTHENTICATOR_VENDOR_NFC_SELF_TEST: u8 = 0x42;
+    const AUTHENTICATOR_VENDOR_PROVISION: u8 = 0x43;
+    const AUTHENTICATOR_VENDOR_LOCK_PROVISIONING: u8 = 0x44;
+    const AUTHENTICATOR_VENDOR_REVOKE_PIN_UV_AUTH_TOKEN: u8 = 0x45;
+    const AUTHENTICATOR_VENDOR_ROTATE_MASTER_KEYS: u8 = 0x46;
+    const AUTHENTICATOR_VENDOR_SELF_TEST: u8 = 0x47;
+    const AUTHENTICATOR_VENDOR_BENCHMARK: u8 = 0x48;
+    const AUTHENTICATOR_VENDOR_COMPACT: u8 = 0x49;
+    const AUTHENTICATOR_VENDOR_WEAR: u8 = 0x4A;
+    const AUTHENTICATOR_VENDOR_BACKUP: u8 = 0x4B;
+    const AUTHENTICATOR_VENDOR_RESTORE: u8 = 0x4C;
+    const AUTHENTICATOR_VENDOR_AUDIT_LOG: u8 = 0x4D;
+    const AUTHENTICATOR_VENDOR_DIAGNOSTICS: u8 = 0x4E;
     const _AUTHENTICATOR_VENDOR_LAST: u8 = 0xBF;
 
     pub fn deserialize(bytes: &[u8]) -> Result<Command, Ctap2StatusCode> {
@@ -112,16 +157,108 @@ impl Command {
                 Ok(Command::AuthenticatorGetNextAssertion)
             }
             #[cfg(feature = "with_ctap2_1")]
+            Command::AUTHENTICATOR_BIO_ENROLLMENT => {
+                let decoded_cbor = cbor::read(&bytes[1..])?;
+                Ok(Command::AuthenticatorBioEnrollment(
+                    AuthenticatorBioEnrollmentParameters::try_from(decoded_cbor)?,
+                ))
+            }
+            #[cfg(feature = "with_ctap2_1")]
+            Command::AUTHENTICATOR_CREDENTIAL_MANAGEMENT => {
+                let decoded_cbor = cbor::read(&bytes[1..])?;
+                Ok(Command::AuthenticatorCredentialManagement(
+                    AuthenticatorCredentialManagementParameters::try_from(decoded_cbor)?,
+                ))
+            }
+            #[cfg(feature = "with_ctap2_1")]
             Command::AUTHENTICATOR_SELECTION => {
                 // Parameters are ignored.
                 Ok(Command::AuthenticatorSelection)
             }
+            #[cfg(feature = "with_ctap2_1")]
+            Command::AUTHENTICATOR_LARGE_BLOBS => {
+                let decoded_cbor = cbor::read(&bytes[1..])?;
+                Ok(Command::AuthenticatorLargeBlobs(
+                    AuthenticatorLargeBlobsParameters::try_from(decoded_cbor)?,
+                ))
+            }
+            #[cfg(feature = "with_ctap2_1")]
+            Command::AUTHENTICATOR_CONFIG => {
+                let decoded_cbor = cbor::read(&bytes[1..])?;
+                Ok(Command::AuthenticatorConfig(
+                    AuthenticatorConfigParameters::try_from(decoded_cbor)?,
+                ))
+            }
             Command::AUTHENTICATOR_VENDOR_CONFIGURE => {
                 let decoded_cbor = cbor::read(&bytes[1..])?;
                 Ok(Command::AuthenticatorVendorConfigure(
                     AuthenticatorVendorConfigureParameters::try_from(decoded_cbor)?,
                 ))
             }
+            #[cfg(feature = "with_nfc")]
+            Command::AUTHENTICATOR_VENDOR_NFC_STATISTICS => {
+                // Parameters are ignored.
+                Ok(Command::AuthenticatorVendorNfcStatistics)
+            }
+            #[cfg(feature = "with_nfc")]
+            Command::AUTHENTICATOR_VENDOR_NFC_SELF_TEST => {
+                // Parameters are ignored.
+                Ok(Command::AuthenticatorVendorNfcSelfTest)
+            }
+            Command::AUTHENTICATOR_VENDOR_PROVISION => {
+                let decoded_cbor = cbor::read(&bytes[1..])?;
+                Ok(Command::AuthenticatorVendorProvision(
+                    AuthenticatorVendorProvisionParameters::try_from(decoded_cbor)?,
+                ))
+            }
+            Command::AUTHENTICATOR_VENDOR_LOCK_PROVISIONING => {
+                // Parameters are ignored.
+                Ok(Command::AuthenticatorVendorLockProvisioning)
+            }
+            Command::AUTHENTICATOR_VENDOR_REVOKE_PIN_UV_AUTH_TOKEN => {
+                // Parameters are ignored.
+                Ok(Command::AuthenticatorVendorRevokePinUvAuthToken)
+            }
+            Command::AUTHENTICATOR_VENDOR_ROTATE_MASTER_KEYS => {
+                // Parameters are ignored.
+                Ok(Command::AuthenticatorVendorRotateMasterKeys)
+            }
+            Command::AUTHENTICATOR_VENDOR_SELF_TEST => {
+                // Parameters are ignored.
+                Ok(Command::AuthenticatorVendorSelfTest)
+            }
+            Command::AUTHENTICATOR_VENDOR_BENCHMARK => {
+                // Parameters are ignored.
+                Ok(Command::AuthenticatorVendorBenchmark)
+            }
+            Command::AUTHENTICATOR_VENDOR_COMPACT => {
+                // Parameters are ignored.
+                Ok(Command::AuthenticatorVendorCompact)
+            }
+            Command::AUTHENTICATOR_VENDOR_WEAR => {
+                // Parameters are ignored.
+                Ok(Command::AuthenticatorVendorWear)
+            }
+            Command::AUTHENTICATOR_VENDOR_BACKUP => {
+                let decoded_cbor = cbor::read(&bytes[1..])?;
+                Ok(Command::AuthenticatorVendorBackup(
+                    AuthenticatorVendorBackupParameters::try_from(decoded_cbor)?,
+                ))
+            }
+            Command::AUTHENTICATOR_VENDOR_RESTORE => {
+                let decoded_cbor = cbor::read(&bytes[1..])?;
+                Ok(Command::AuthenticatorVendorRestore(
+                    AuthenticatorVendorRestoreParameters::try_from(decoded_cbor)?,
+                ))
+            }
+            Command::AUTHENTICATOR_VENDOR_AUDIT_LOG => {
+                // Parameters are ignored.
+                Ok(Command::AuthenticatorVendorAuditLog)
+            }
+            Command::AUTHENTICATOR_VENDOR_DIAGNOSTICS => {
+                // Parameters are ignored.
+                Ok(Command::AuthenticatorVendorDiagnostics)
+            }
             _ => Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND),
         }
     }
@@ -133,18 +270,35 @@ pub struct AuthenticatorMakeCredentialParameters {
     pub rp: PublicKeyCredentialRpEntity,
     pub user: PublicKeyCredentialUserEntity,
     pub pub_key_cred_params: Vec<PublicKeyCredentialParameter>,
-    pub exclude_list: Option<Vec<PublicKeyCredentialDescriptor>>,
+    pub exclude_list: Option<PublicKeyCredentialDescriptorList>,
     pub extensions: Option<MakeCredentialExtensions>,
     // Even though options are optional, we can use the default if not present.
     pub options: MakeCredentialOptions,
     pub pin_uv_auth_param: Option<Vec<u8>>,
     pub pin_uv_auth_protocol: Option<u64>,
+    #[cfg(feature = "with_ctap2_1")]
+    pub enterprise_attestation: Option<EnterpriseAttestationMode>,
 }
 
 impl TryFrom<cbor::Value> for AuthenticatorMakeCredentialParameters {
     type Error = Ctap2StatusCode;
 
     fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        #[cfg(not(feature = "with_ctap2_1"))]
+        destructure_cbor_map! {
+            let {
+                1 => client_data_hash,
+                2 => rp,
+                3 => user,
+                4 => cred_param_vec,
+                5 => exclude_list,
+                6 => extensions,
+                7 => options,
+                8 => pin_uv_auth_param,
+                9 => pin_uv_auth_protocol,
+            } = extract_map(cbor_value)?;
+        }
+        #[cfg(feature = "with_ctap2_1")]
         destructure_cbor_map! {
             let {
                 1 => client_data_hash,
@@ -156,6 +310,7 @@ impl TryFrom<cbor::Value> for AuthenticatorMakeCredentialParameters {
                 7 => options,
                 8 => pin_uv_auth_param,
                 9 => pin_uv_auth_protocol,
+                10 => enterprise_attestation,
             } = extract_map(cbor_value)?;
         }
 
@@ -169,19 +324,11 @@ impl TryFrom<cbor::Value> for AuthenticatorMakeCredentialParameters {
             .map(PublicKeyCredentialParameter::try_from)
             .collect::<Result<Vec<PublicKeyCredentialParameter>, Ctap2StatusCode>>()?;
 
-        let exclude_list = match exclude_list {
-            Some(entry) => {
-                let exclude_list_vec = extract_array(entry)?;
-                let list_len = MAX_CREDENTIAL_COUNT_IN_LIST.unwrap_or(exclude_list_vec.len());
-                let exclude_list = exclude_list_vec
-                    .into_iter()
-                    .take(list_len)
-                    .map(PublicKeyCredentialDescriptor::try_from)
-                    .collect::<Result<Vec<PublicKeyCredentialDescriptor>, Ctap2StatusCode>>()?;
-                Some(exclude_list)
-            }
-            None => None,
-        };
+        let exclude_list = exclude_list
+            .map(|entry| {
+                PublicKeyCredentialDescriptorList::from_cbor(entry, MAX_CREDENTIAL_COUNT_IN_LIST)
+            })
+            .transpose()?;
 
         let extensions = extensions
             .map(MakeCredentialExtensions::try_from)
@@ -197,6 +344,12 @@ impl TryFrom<cbor::Value> for AuthenticatorMakeCredentialParameters {
 
         let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
         let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
+        #[cfg(feature = "with_ctap2_1")]
+        let enterprise_attestation = enterprise_attestation
+            .map(extract_unsigned)
+            .transpose()?
+            .map(EnterpriseAttestationMode::try_from)
+            .transpose()?;
 
         Ok(AuthenticatorMakeCredentialParameters {
             client_data_hash,
@@ -208,6 +361,8 @@ impl TryFrom<cbor::Value> for AuthenticatorMakeCredentialParameters {
             options,
             pin_uv_auth_param,
             pin_uv_auth_protocol,
+            #[cfg(feature = "with_ctap2_1")]
+            enterprise_attestation,
         })
     }
 }
@@ -216,7 +371,7 @@ impl TryFrom<cbor::Value> for AuthenticatorMakeCredentialParameters {
 pub struct AuthenticatorGetAssertionParameters {
     pub rp_id: String,
     pub client_data_hash: Vec<u8>,
-    pub allow_list: Option<Vec<PublicKeyCredentialDescriptor>>,
+    pub allow_list: Option<PublicKeyCredentialDescriptorList>,
     pub extensions: Option<GetAssertionExtensions>,
     // Even though options are optional, we can use the default if not present.
     pub options: GetAssertionOptions,
@@ -243,19 +398,11 @@ impl TryFrom<cbor::Value> for AuthenticatorGetAssertionParameters {
         let rp_id = extract_text_string(ok_or_missing(rp_id)?)?;
         let client_data_hash = extract_byte_string(ok_or_missing(client_data_hash)?)?;
 
-        let allow_list = match allow_list {
-            Some(entry) => {
-                let allow_list_vec = extract_array(entry)?;
-                let list_len = MAX_CREDENTIAL_COUNT_IN_LIST.unwrap_or(allow_list_vec.len());
-                let allow_list = allow_list_vec
-                    .into_iter()
-                    .take(list_len)
-                    .map(PublicKeyCredentialDescriptor::try_from)
-                    .collect::<Result<Vec<PublicKeyCredentialDescriptor>, Ctap2StatusCode>>()?;
-                Some(allow_list)
-            }
-            None => None,
-        };
+        let allow_list = allow_list
+            .map(|entry| {
+                PublicKeyCredentialDescriptorList::from_cbor(entry, MAX_CREDENTIAL_COUNT_IN_LIST)
+            })
+            .transpose()?;
 
         let extensions = extensions
             .map(GetAssertionExtensions::try_from)
@@ -385,6 +532,202 @@ impl TryFrom<cbor::Value> for AuthenticatorClientPinParameters {
     }
 }
 
+#[cfg(feature = "with_ctap2_1")]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct AuthenticatorLargeBlobsParameters {
+    pub get: Option<usize>,
+    pub set: Option<Vec<u8>>,
+    pub offset: usize,
+    pub length: Option<usize>,
+    pub pin_uv_auth_param: Option<Vec<u8>>,
+    pub pin_uv_auth_protocol: Option<u64>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for AuthenticatorLargeBlobsParameters {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                1 => get,
+                2 => set,
+                3 => offset,
+                4 => length,
+                5 => pin_uv_auth_param,
+                6 => pin_uv_auth_protocol,
+            } = extract_map(cbor_value)?;
+        }
+
+        // Unlike other commands, missing parameters are reported as invalid parameters here.
+        let get = get.map(extract_unsigned).transpose()?.map(|u| u as usize);
+        let set = set.map(extract_byte_string).transpose()?;
+        let offset = offset.ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+        let offset = extract_unsigned(offset)? as usize;
+        let length = length
+            .map(extract_unsigned)
+            .transpose()?
+            .map(|u| u as usize);
+        let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
+        let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
+
+        // Exactly one of get and set must be present.
+        if get.is_some() == set.is_some() {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+        if get.is_some()
+            && (length.is_some() || pin_uv_auth_param.is_some() || pin_uv_auth_protocol.is_some())
+        {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+        // The total length is announced with the first fragment only.
+        if set.is_some() && (offset == 0) != length.is_some() {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+
+        Ok(AuthenticatorLargeBlobsParameters {
+            get,
+            set,
+            offset,
+            length,
+            pin_uv_auth_param,
+            pin_uv_auth_protocol,
+        })
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct AuthenticatorBioEnrollmentParameters {
+    pub modality: Option<u64>,
+    pub sub_command: Option<BioEnrollmentSubCommand>,
+    pub sub_command_params: Option<BioEnrollmentSubCommandParams>,
+    pub pin_uv_auth_protocol: Option<u64>,
+    pub pin_uv_auth_param: Option<Vec<u8>>,
+    pub get_modality: bool,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for AuthenticatorBioEnrollmentParameters {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                0x01 => modality,
+                0x02 => sub_command,
+                0x03 => sub_command_params,
+                0x04 => pin_uv_auth_protocol,
+                0x05 => pin_uv_auth_param,
+                0x06 => get_modality,
+            } = extract_map(cbor_value)?;
+        }
+
+        let modality = modality.map(extract_unsigned).transpose()?;
+        let sub_command = sub_command
+            .map(BioEnrollmentSubCommand::try_from)
+            .transpose()?;
+        let sub_command_params = sub_command_params
+            .map(BioEnrollmentSubCommandParams::try_from)
+            .transpose()?;
+        let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
+        let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
+        let get_modality = get_modality.map(extract_bool).transpose()?.unwrap_or(false);
+
+        Ok(AuthenticatorBioEnrollmentParameters {
+            modality,
+            sub_command,
+            sub_command_params,
+            pin_uv_auth_protocol,
+            pin_uv_auth_param,
+            get_modality,
+        })
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct AuthenticatorCredentialManagementParameters {
+    pub sub_command: CredentialManagementSubCommand,
+    pub sub_command_params: Option<CredentialManagementSubCommandParams>,
+    pub pin_uv_auth_protocol: Option<u64>,
+    pub pin_uv_auth_param: Option<Vec<u8>>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for AuthenticatorCredentialManagementParameters {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                0x01 => sub_command,
+                0x02 => sub_command_params,
+                0x03 => pin_uv_auth_protocol,
+                0x04 => pin_uv_auth_param,
+            } = extract_map(cbor_value)?;
+        }
+
+        let sub_command = CredentialManagementSubCommand::try_from(ok_or_missing(sub_command)?)?;
+        let sub_command_params = sub_command_params
+            .map(CredentialManagementSubCommandParams::try_from)
+            .transpose()?;
+        let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
+        let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
+
+        Ok(AuthenticatorCredentialManagementParameters {
+            sub_command,
+            sub_command_params,
+            pin_uv_auth_protocol,
+            pin_uv_auth_param,
+        })
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct AuthenticatorConfigParameters {
+    pub sub_command: ConfigSubCommand,
+    pub sub_command_params: Option<ConfigSubCommandParams>,
+    pub pin_uv_auth_param: Option<Vec<u8>>,
+    pub pin_uv_auth_protocol: Option<u64>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for AuthenticatorConfigParameters {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                0x01 => sub_command,
+                0x02 => sub_command_params,
+                0x03 => pin_uv_auth_protocol,
+                0x04 => pin_uv_auth_param,
+            } = extract_map(cbor_value)?;
+        }
+
+        let sub_command = ConfigSubCommand::try_from(ok_or_missing(sub_command)?)?;
+        let sub_command_params = match sub_command {
+            ConfigSubCommand::SetMinPinLength => sub_command_params
+                .map(SetMinPinLengthParams::try_from)
+                .transpose()?
+                .map(ConfigSubCommandParams::SetMinPinLength),
+            // The other subcommands take no parameters.
+            _ => None,
+        };
+        let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
+        let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
+
+        Ok(AuthenticatorConfigParameters {
+            sub_command,
+            sub_command_params,
+            pin_uv_auth_param,
+            pin_uv_auth_protocol,
+        })
+    }
+}
+
 #[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
 pub struct AuthenticatorAttestationMaterial {
     pub certificate: Vec<u8>,
@@ -418,6 +761,11 @@ impl TryFrom<cbor::Value> for AuthenticatorAttestationMaterial {
 pub struct AuthenticatorVendorConfigureParameters {
     pub lockdown: bool,
     pub attestation_material: Option<AuthenticatorAttestationMaterial>,
+    pub default_cred_protect: Option<CredentialProtectionPolicy>,
+    pub touch_timeout_ms: Option<u32>,
+    pub touch_duration_ms: Option<u32>,
+    pub confirm_with_second_touch: Option<bool>,
+    pub force_pin_change: bool,
 }
 
 impl TryFrom<cbor::Value> for AuthenticatorVendorConfigureParameters {
@@ -428,82 +776,256 @@ impl TryFrom<cbor::Value> for AuthenticatorVendorConfigureParameters {
             let {
                 1 => lockdown,
                 2 => attestation_material,
+                3 => default_cred_protect,
+                4 => touch_timeout_ms,
+                5 => touch_duration_ms,
+                6 => confirm_with_second_touch,
+                7 => force_pin_change,
             } = extract_map(cbor_value)?;
         }
         let lockdown = lockdown.map_or(Ok(false), extract_bool)?;
         let attestation_material = attestation_material
             .map(AuthenticatorAttestationMaterial::try_from)
             .transpose()?;
+        let default_cred_protect = default_cred_protect
+            .map(CredentialProtectionPolicy::try_from)
+            .transpose()?;
+        let touch_timeout_ms = extract_milliseconds(touch_timeout_ms)?;
+        let touch_duration_ms = extract_milliseconds(touch_duration_ms)?;
+        let confirm_with_second_touch = confirm_with_second_touch.map(extract_bool).transpose()?;
+        let force_pin_change = force_pin_change.map_or(Ok(false), extract_bool)?;
         Ok(AuthenticatorVendorConfigureParameters {
             lockdown,
             attestation_material,
+            default_cred_protect,
+            touch_timeout_ms,
+            touch_duration_ms,
+            confirm_with_second_touch,
+            force_pin_change,
         })
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::super::data_formats::{
-        AuthenticatorTransport, PublicKeyCredentialRpEntity, PublicKeyCredentialType,
-        PublicKeyCredentialUserEntity,
-    };
-    use super::super::ES256_CRED_PARAM;
-    use super::*;
-    use alloc::collections::BTreeMap;
-    use cbor::{cbor_array, cbor_map};
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct AuthenticatorVendorProvisionParameters {
+    pub aaguid: Option<[u8; key_material::AAGUID_LENGTH]>,
+    pub private_key: Option<[u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH]>,
+    // The attestation certificate, followed by its intermediate certificates.
+    pub certificate_chain: Option<Vec<Vec<u8>>>,
+    // Generates the attestation key inside the secure element, instead of receiving it.
+    pub generate_private_key: bool,
+}
 
-    #[test]
-    fn test_from_cbor_make_credential_parameters() {
-        let cbor_value = cbor_map! {
-            1 => vec![0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F],
-            2 => cbor_map! {
-                "id" => "example.com",
-                "name" => "Example",
-                "icon" => "example.com/icon.png",
-            },
-            3 => cbor_map! {
-                "id" => vec![0x1D, 0x1D, 0x1D, 0x1D],
-                "name" => "foo",
-                "displayName" => "bar",
-                "icon" => "example.com/foo/icon.png",
-            },
-            4 => cbor_array![ES256_CRED_PARAM],
-            5 => cbor_array![],
-            8 => vec![0x12, 0x34],
-            9 => 1,
-        };
-        let returned_make_credential_parameters =
-            AuthenticatorMakeCredentialParameters::try_from(cbor_value).unwrap();
+impl TryFrom<cbor::Value> for AuthenticatorVendorProvisionParameters {
+    type Error = Ctap2StatusCode;
 
-        let client_data_hash = vec![
-            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D,
-            0x0E, 0x0F,
-        ];
-        let rp = PublicKeyCredentialRpEntity {
-            rp_id: "example.com".to_string(),
-            rp_name: Some("Example".to_string()),
-            rp_icon: Some("example.com/icon.png".to_string()),
-        };
-        let user = PublicKeyCredentialUserEntity {
-            user_id: vec![0x1D, 0x1D, 0x1D, 0x1D],
-            user_name: Some("foo".to_string()),
-            user_display_name: Some("bar".to_string()),
-            user_icon: Some("example.com/foo/icon.png".to_string()),
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                1 => aaguid,
+                2 => private_key,
+                3 => certificate_chain,
+                4 => generate_private_key,
+            } = extract_map(cbor_value)?;
+        }
+        let aaguid = aaguid.map(extract_byte_string).transpose()?;
+        let aaguid = match aaguid {
+            Some(aaguid) if aaguid.len() != key_material::AAGUID_LENGTH => {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+            }
+            Some(aaguid) => Some(*array_ref!(aaguid, 0, key_material::AAGUID_LENGTH)),
+            None => None,
         };
-        let options = MakeCredentialOptions {
-            rk: false,
-            uv: false,
+        let private_key = private_key.map(extract_byte_string).transpose()?;
+        let private_key = match private_key {
+            Some(private_key)
+                if private_key.len() != key_material::ATTESTATION_PRIVATE_KEY_LENGTH =>
+            {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+            }
+            Some(private_key) => Some(*array_ref!(
+                private_key,
+                0,
+                key_material::ATTESTATION_PRIVATE_KEY_LENGTH
+            )),
+            None => None,
         };
-        let expected_make_credential_parameters = AuthenticatorMakeCredentialParameters {
-            client_data_hash,
-            rp,
-            user,
-            pub_key_cred_params: vec![ES256_CRED_PARAM],
-            exclude_list: Some(vec![]),
+        let certificate_chain = certificate_chain
+            .map(|chain| {
+                extract_array(chain)?
+                    .into_iter()
+                    .map(extract_byte_string)
+                    .collect::<Result<Vec<_>, Ctap2StatusCode>>()
+            })
+            .transpose()?;
+        let generate_private_key = generate_private_key
+            .map(extract_bool)
+            .transpose()?
+            .unwrap_or(false);
+        Ok(AuthenticatorVendorProvisionParameters {
+            aaguid,
+            private_key,
+            certificate_chain,
+            generate_private_key,
+        })
+    }
+}
+
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct AuthenticatorVendorBackupParameters {
+    // Starts a new backup if present, continues the current one otherwise.
+    pub passphrase: Option<Vec<u8>>,
+    // Authorizes a new backup, and is required if a PIN is set. The message is 32 bytes 0xFF, the
+    // command byte and the passphrase.
+    pub pin_uv_auth_protocol: Option<u64>,
+    pub pin_uv_auth_param: Option<Vec<u8>>,
+}
+
+impl TryFrom<cbor::Value> for AuthenticatorVendorBackupParameters {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                1 => passphrase,
+                2 => pin_uv_auth_protocol,
+                3 => pin_uv_auth_param,
+            } = extract_map(cbor_value)?;
+        }
+        let passphrase = passphrase.map(extract_byte_string).transpose()?;
+        let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
+        let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
+        Ok(AuthenticatorVendorBackupParameters {
+            passphrase,
+            pin_uv_auth_protocol,
+            pin_uv_auth_param,
+        })
+    }
+}
+
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct AuthenticatorVendorRestoreParameters {
+    // Starts a new restore if present, together with the header. Continues the current one
+    // otherwise.
+    pub passphrase: Option<Vec<u8>>,
+    pub header: Option<Vec<u8>>,
+    // The encrypted credentials, as returned by the backup.
+    pub records: Vec<Vec<u8>>,
+    // Authorizes a new restore, and is required if a PIN is set. The message is 32 bytes 0xFF,
+    // the command byte, the passphrase and the header.
+    pub pin_uv_auth_protocol: Option<u64>,
+    pub pin_uv_auth_param: Option<Vec<u8>>,
+}
+
+impl TryFrom<cbor::Value> for AuthenticatorVendorRestoreParameters {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                1 => passphrase,
+                2 => header,
+                3 => records,
+                4 => pin_uv_auth_protocol,
+                5 => pin_uv_auth_param,
+            } = extract_map(cbor_value)?;
+        }
+        let passphrase = passphrase.map(extract_byte_string).transpose()?;
+        let header = header.map(extract_byte_string).transpose()?;
+        if passphrase.is_some() && header.is_none() {
+            return Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER);
+        }
+        let records = extract_array(ok_or_missing(records)?)?
+            .into_iter()
+            .map(extract_byte_string)
+            .collect::<Result<Vec<_>, Ctap2StatusCode>>()?;
+        let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
+        let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
+        Ok(AuthenticatorVendorRestoreParameters {
+            passphrase,
+            header,
+            records,
+            pin_uv_auth_protocol,
+            pin_uv_auth_param,
+        })
+    }
+}
+
+// Durations are unsigned integers of milliseconds that fit the store entries.
+fn extract_milliseconds(cbor_value: Option<cbor::Value>) -> Result<Option<u32>, Ctap2StatusCode> {
+    cbor_value
+        .map(extract_unsigned)
+        .transpose()?
+        .map(u32::try_from)
+        .transpose()
+        .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::data_formats::{
+        AuthenticatorTransport, PublicKeyCredentialDescriptor, PublicKeyCredentialRpEntity,
+        PublicKeyCredentialType, PublicKeyCredentialUserEntity,
+    };
+    use super::super::ES256_CRED_PARAM;
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use cbor::{cbor_array, cbor_map};
+
+    #[test]
+    fn test_from_cbor_make_credential_parameters() {
+        let cbor_value = cbor_map! {
+            1 => vec![0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F],
+            2 => cbor_map! {
+                "id" => "example.com",
+                "name" => "Example",
+                "icon" => "example.com/icon.png",
+            },
+            3 => cbor_map! {
+                "id" => vec![0x1D, 0x1D, 0x1D, 0x1D],
+                "name" => "foo",
+                "displayName" => "bar",
+                "icon" => "example.com/foo/icon.png",
+            },
+            4 => cbor_array![ES256_CRED_PARAM],
+            5 => cbor_array![],
+            8 => vec![0x12, 0x34],
+            9 => 1,
+        };
+        let returned_make_credential_parameters =
+            AuthenticatorMakeCredentialParameters::try_from(cbor_value).unwrap();
+
+        let client_data_hash = vec![
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D,
+            0x0E, 0x0F,
+        ];
+        let rp = PublicKeyCredentialRpEntity {
+            rp_id: "example.com".to_string(),
+            rp_name: Some("Example".to_string()),
+            rp_icon: Some("example.com/icon.png".to_string()),
+        };
+        let user = PublicKeyCredentialUserEntity {
+            user_id: vec![0x1D, 0x1D, 0x1D, 0x1D],
+            user_name: Some("foo".to_string()),
+            user_display_name: Some("bar".to_string()),
+            user_icon: Some("example.com/foo/icon.png".to_string()),
+        };
+        let options = MakeCredentialOptions {
+            rk: false,
+            uv: false,
+        };
+        let expected_make_credential_parameters = AuthenticatorMakeCredentialParameters {
+            client_data_hash,
+            rp,
+            user,
+            pub_key_cred_params: vec![ES256_CRED_PARAM],
+            exclude_list: Some(PublicKeyCredentialDescriptorList::from(vec![])),
             extensions: None,
             options,
             pin_uv_auth_param: Some(vec![0x12, 0x34]),
             pin_uv_auth_protocol: Some(1),
+            #[cfg(feature = "with_ctap2_1")]
+            enterprise_attestation: None,
         };
 
         assert_eq!(
@@ -545,7 +1067,9 @@ mod test {
         let expected_get_assertion_parameters = AuthenticatorGetAssertionParameters {
             rp_id,
             client_data_hash,
-            allow_list: Some(vec![pub_key_cred_descriptor]),
+            allow_list: Some(PublicKeyCredentialDescriptorList::from(vec![
+                pub_key_cred_descriptor,
+            ])),
             extensions: None,
             options,
             pin_uv_auth_param: Some(vec![0x12, 0x34]),
@@ -610,6 +1134,251 @@ mod test {
         );
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_from_cbor_large_blobs_parameters() {
+        let cbor_value = cbor_map! {
+            1 => 2,
+            3 => 4,
+        };
+        assert_eq!(
+            AuthenticatorLargeBlobsParameters::try_from(cbor_value),
+            Ok(AuthenticatorLargeBlobsParameters {
+                get: Some(2),
+                set: None,
+                offset: 4,
+                length: None,
+                pin_uv_auth_param: None,
+                pin_uv_auth_protocol: None,
+            })
+        );
+
+        let cbor_value = cbor_map! {
+            2 => vec![0x5E],
+            3 => 0,
+            4 => 17,
+            5 => vec![0xA9],
+            6 => 1,
+        };
+        assert_eq!(
+            AuthenticatorLargeBlobsParameters::try_from(cbor_value),
+            Ok(AuthenticatorLargeBlobsParameters {
+                get: None,
+                set: Some(vec![0x5E]),
+                offset: 0,
+                length: Some(17),
+                pin_uv_auth_param: Some(vec![0xA9]),
+                pin_uv_auth_protocol: Some(1),
+            })
+        );
+
+        // Missing offset.
+        let cbor_value = cbor_map! {
+            1 => 2,
+        };
+        assert_eq!(
+            AuthenticatorLargeBlobsParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        // Both get and set.
+        let cbor_value = cbor_map! {
+            1 => 2,
+            2 => vec![0x5E],
+            3 => 0,
+        };
+        assert_eq!(
+            AuthenticatorLargeBlobsParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        // Length with get.
+        let cbor_value = cbor_map! {
+            1 => 2,
+            3 => 0,
+            4 => 17,
+        };
+        assert_eq!(
+            AuthenticatorLargeBlobsParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        // Missing length in the first fragment.
+        let cbor_value = cbor_map! {
+            2 => vec![0x5E],
+            3 => 0,
+        };
+        assert_eq!(
+            AuthenticatorLargeBlobsParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        // Length in a later fragment.
+        let cbor_value = cbor_map! {
+            2 => vec![0x5E],
+            3 => 1,
+            4 => 17,
+        };
+        assert_eq!(
+            AuthenticatorLargeBlobsParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "with_ctap2_1")]
+    fn test_from_cbor_bio_enrollment_parameters() {
+        let cbor_value = cbor_map! {
+            0x01 => 0x01,
+            0x02 => BioEnrollmentSubCommand::EnrollBegin as u64,
+            0x03 => cbor_map! {
+                0x03 => 10000,
+            },
+            0x04 => 1,
+            0x05 => vec![0xBE; 16],
+        };
+        assert_eq!(
+            AuthenticatorBioEnrollmentParameters::try_from(cbor_value),
+            Ok(AuthenticatorBioEnrollmentParameters {
+                modality: Some(0x01),
+                sub_command: Some(BioEnrollmentSubCommand::EnrollBegin),
+                sub_command_params: Some(BioEnrollmentSubCommandParams {
+                    template_id: None,
+                    template_friendly_name: None,
+                    timeout_milliseconds: Some(10000),
+                }),
+                pin_uv_auth_protocol: Some(1),
+                pin_uv_auth_param: Some(vec![0xBE; 16]),
+                get_modality: false,
+            })
+        );
+
+        let cbor_value = cbor_map! {
+            0x06 => true,
+        };
+        assert_eq!(
+            AuthenticatorBioEnrollmentParameters::try_from(cbor_value),
+            Ok(AuthenticatorBioEnrollmentParameters {
+                modality: None,
+                sub_command: None,
+                sub_command_params: None,
+                pin_uv_auth_protocol: None,
+                pin_uv_auth_param: None,
+                get_modality: true,
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "with_ctap2_1")]
+    fn test_from_cbor_credential_management_parameters() {
+        let cbor_value = cbor_map! {
+            0x01 => CredentialManagementSubCommand::EnumerateCredentialsBegin as u64,
+            0x02 => cbor_map! {
+                0x01 => vec![0x1D; 32],
+            },
+            0x03 => 1,
+            0x04 => vec![0x9A; 16],
+        };
+        assert_eq!(
+            AuthenticatorCredentialManagementParameters::try_from(cbor_value),
+            Ok(AuthenticatorCredentialManagementParameters {
+                sub_command: CredentialManagementSubCommand::EnumerateCredentialsBegin,
+                sub_command_params: Some(CredentialManagementSubCommandParams {
+                    rp_id_hash: Some(vec![0x1D; 32]),
+                    credential_id: None,
+                    user: None,
+                }),
+                pin_uv_auth_protocol: Some(1),
+                pin_uv_auth_param: Some(vec![0x9A; 16]),
+            })
+        );
+
+        let cbor_value = cbor_map! {
+            0x03 => 1,
+        };
+        assert_eq!(
+            AuthenticatorCredentialManagementParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "with_ctap2_1")]
+    fn test_from_cbor_config_parameters() {
+        let cbor_value = cbor_map! {
+            0x01 => ConfigSubCommand::SetMinPinLength as u64,
+            0x02 => cbor_map! {
+                0x01 => 6,
+                0x03 => true,
+            },
+            0x03 => 1,
+            0x04 => vec![0x9A; 16],
+        };
+        assert_eq!(
+            AuthenticatorConfigParameters::try_from(cbor_value),
+            Ok(AuthenticatorConfigParameters {
+                sub_command: ConfigSubCommand::SetMinPinLength,
+                sub_command_params: Some(ConfigSubCommandParams::SetMinPinLength(
+                    SetMinPinLengthParams {
+                        new_min_pin_length: Some(6),
+                        min_pin_length_rp_ids: None,
+                        force_change_pin: Some(true),
+                    }
+                )),
+                pin_uv_auth_param: Some(vec![0x9A; 16]),
+                pin_uv_auth_protocol: Some(1),
+            })
+        );
+
+        // Other subcommands ignore their parameters.
+        let cbor_value = cbor_map! {
+            0x01 => ConfigSubCommand::ToggleAlwaysUv as u64,
+            0x02 => cbor_map! {
+                0x01 => 6,
+            },
+        };
+        assert_eq!(
+            AuthenticatorConfigParameters::try_from(cbor_value),
+            Ok(AuthenticatorConfigParameters {
+                sub_command: ConfigSubCommand::ToggleAlwaysUv,
+                sub_command_params: None,
+                pin_uv_auth_param: None,
+                pin_uv_auth_protocol: None,
+            })
+        );
+
+        let cbor_value = cbor_map! {
+            0x02 => cbor_map! {
+                0x01 => 6,
+            },
+        };
+        assert_eq!(
+            AuthenticatorConfigParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn test_deserialize_non_canonical_cbor() {
+        let cases = vec![
+            // Map keys 2 and 1 out of order.
+            vec![0xA2, 0x02, 0xF5, 0x01, 0xF5],
+            // Duplicate map key 1.
+            vec![0xA2, 0x01, 0xF5, 0x01, 0xF5],
+            // Map of indefinite length.
+            vec![0xBF, 0x01, 0xF5, 0xFF],
+            // Map key 1 encoded with an extra byte.
+            vec![0xA1, 0x18, 0x01, 0xF5],
+        ];
+        for cbor in cases {
+            let mut cbor_bytes = vec![Command::AUTHENTICATOR_MAKE_CREDENTIAL];
+            cbor_bytes.extend(cbor);
+            let command = Command::deserialize(&cbor_bytes);
+            assert_eq!(command, Err(Ctap2StatusCode::CTAP2_ERR_INVALID_CBOR));
+        }
+    }
+
     #[test]
     fn test_deserialize_get_info() {
         let cbor_bytes = [Command::AUTHENTICATOR_GET_INFO];
@@ -640,6 +1409,164 @@ mod test {
         assert_eq!(command, Ok(Command::AuthenticatorSelection));
     }
 
+    #[cfg(feature = "with_nfc")]
+    #[test]
+    fn test_deserialize_vendor_nfc_statistics() {
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_NFC_STATISTICS];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(command, Ok(Command::AuthenticatorVendorNfcStatistics));
+    }
+
+    #[cfg(feature = "with_nfc")]
+    #[test]
+    fn test_deserialize_vendor_nfc_self_test() {
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_NFC_SELF_TEST];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(command, Ok(Command::AuthenticatorVendorNfcSelfTest));
+    }
+
+    #[test]
+    fn test_deserialize_vendor_lock_provisioning() {
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_LOCK_PROVISIONING];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(command, Ok(Command::AuthenticatorVendorLockProvisioning));
+    }
+
+    #[test]
+    fn test_deserialize_vendor_revoke_pin_uv_auth_token() {
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_REVOKE_PIN_UV_AUTH_TOKEN];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(
+            command,
+            Ok(Command::AuthenticatorVendorRevokePinUvAuthToken)
+        );
+    }
+
+    #[test]
+    fn test_deserialize_vendor_rotate_master_keys() {
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_ROTATE_MASTER_KEYS];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(command, Ok(Command::AuthenticatorVendorRotateMasterKeys));
+    }
+
+    #[test]
+    fn test_deserialize_vendor_self_test() {
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_SELF_TEST];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(command, Ok(Command::AuthenticatorVendorSelfTest));
+    }
+
+    #[test]
+    fn test_deserialize_vendor_benchmark() {
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_BENCHMARK];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(command, Ok(Command::AuthenticatorVendorBenchmark));
+    }
+
+    #[test]
+    fn test_deserialize_vendor_compact() {
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_COMPACT];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(command, Ok(Command::AuthenticatorVendorCompact));
+    }
+
+    #[test]
+    fn test_deserialize_vendor_wear() {
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_WEAR];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(command, Ok(Command::AuthenticatorVendorWear));
+    }
+
+    #[test]
+    fn test_deserialize_vendor_backup() {
+        let mut cbor_bytes = vec![Command::AUTHENTICATOR_VENDOR_BACKUP];
+        cbor_bytes.extend(&[0xA3, 0x01, 0x42, 0x12, 0x34, 0x02, 0x01, 0x03, 0x41, 0x56]);
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(
+            command,
+            Ok(Command::AuthenticatorVendorBackup(
+                AuthenticatorVendorBackupParameters {
+                    passphrase: Some(vec![0x12, 0x34]),
+                    pin_uv_auth_protocol: Some(1),
+                    pin_uv_auth_param: Some(vec![0x56]),
+                }
+            ))
+        );
+
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_BACKUP, 0xA0];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(
+            command,
+            Ok(Command::AuthenticatorVendorBackup(
+                AuthenticatorVendorBackupParameters {
+                    passphrase: None,
+                    pin_uv_auth_protocol: None,
+                    pin_uv_auth_param: None,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_deserialize_vendor_restore() {
+        let cbor_value = cbor_map! {
+            1 => vec![0x12, 0x34],
+            2 => vec![0x01],
+            3 => cbor_array![vec![0x56]],
+            4 => 1,
+            5 => vec![0x78],
+        };
+        assert_eq!(
+            AuthenticatorVendorRestoreParameters::try_from(cbor_value),
+            Ok(AuthenticatorVendorRestoreParameters {
+                passphrase: Some(vec![0x12, 0x34]),
+                header: Some(vec![0x01]),
+                records: vec![vec![0x56]],
+                pin_uv_auth_protocol: Some(1),
+                pin_uv_auth_param: Some(vec![0x78]),
+            })
+        );
+
+        // A new restore needs the header of the backup.
+        let cbor_value = cbor_map! {
+            1 => vec![0x12, 0x34],
+            3 => cbor_array![],
+        };
+        assert_eq!(
+            AuthenticatorVendorRestoreParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+        );
+
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_RESTORE, 0xA1, 0x03, 0x80];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(
+            command,
+            Ok(Command::AuthenticatorVendorRestore(
+                AuthenticatorVendorRestoreParameters {
+                    passphrase: None,
+                    header: None,
+                    records: vec![],
+                    pin_uv_auth_protocol: None,
+                    pin_uv_auth_param: None,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_deserialize_vendor_audit_log() {
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_AUDIT_LOG];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(command, Ok(Command::AuthenticatorVendorAuditLog));
+    }
+
+    #[test]
+    fn test_deserialize_vendor_diagnostics() {
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_DIAGNOSTICS];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(command, Ok(Command::AuthenticatorVendorDiagnostics));
+    }
+
     #[test]
     fn test_vendor_configure() {
         // Incomplete command
@@ -654,7 +1581,12 @@ mod test {
             Ok(Command::AuthenticatorVendorConfigure(
                 AuthenticatorVendorConfigureParameters {
                     lockdown: true,
-                    attestation_material: None
+                    attestation_material: None,
+                    default_cred_protect: None,
+                    touch_timeout_ms: None,
+                    touch_duration_ms: None,
+                    confirm_with_second_touch: None,
+                    force_pin_change: false,
                 }
             ))
         );
@@ -705,7 +1637,8 @@ mod test {
             2 => cbor_map! {
                 1 => dummy_cert,
                 2 => dummy_pkey
-            }
+            },
+            3 => CredentialProtectionPolicy::UserVerificationRequired,
         };
         assert_eq!(
             AuthenticatorVendorConfigureParameters::try_from(cbor_value),
@@ -714,8 +1647,136 @@ mod test {
                 attestation_material: Some(AuthenticatorAttestationMaterial {
                     certificate: dummy_cert.to_vec(),
                     private_key: dummy_pkey
-                })
+                }),
+                default_cred_protect: Some(CredentialProtectionPolicy::UserVerificationRequired),
+                touch_timeout_ms: None,
+                touch_duration_ms: None,
+                confirm_with_second_touch: None,
+                force_pin_change: false,
+            })
+        );
+
+        // User presence settings
+        let cbor_value = cbor_map! {
+            4 => 10000,
+            5 => 500,
+            6 => true,
+        };
+        assert_eq!(
+            AuthenticatorVendorConfigureParameters::try_from(cbor_value),
+            Ok(AuthenticatorVendorConfigureParameters {
+                lockdown: false,
+                attestation_material: None,
+                default_cred_protect: None,
+                touch_timeout_ms: Some(10000),
+                touch_duration_ms: Some(500),
+                confirm_with_second_touch: Some(true),
+                force_pin_change: false,
+            })
+        );
+
+        let cbor_value = cbor_map! {
+            7 => true,
+        };
+        assert_eq!(
+            AuthenticatorVendorConfigureParameters::try_from(cbor_value),
+            Ok(AuthenticatorVendorConfigureParameters {
+                lockdown: false,
+                attestation_material: None,
+                default_cred_protect: None,
+                touch_timeout_ms: None,
+                touch_duration_ms: None,
+                confirm_with_second_touch: None,
+                force_pin_change: true,
+            })
+        );
+
+        // The timeout doesn't fit the store entry.
+        let cbor_value = cbor_map! {
+            4 => 0x1_0000_0000i64,
+        };
+        assert_eq!(
+            AuthenticatorVendorConfigureParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn test_vendor_provision() {
+        let dummy_aaguid = [0x33u8; key_material::AAGUID_LENGTH];
+        let dummy_pkey = [0x41u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        let dummy_cert = [0xddu8; 20];
+        let dummy_intermediate = [0xeeu8; 30];
+
+        let mut cbor_bytes = vec![Command::AUTHENTICATOR_VENDOR_PROVISION];
+        cbor_bytes.push(0xA0);
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(
+            command,
+            Ok(Command::AuthenticatorVendorProvision(
+                AuthenticatorVendorProvisionParameters {
+                    aaguid: None,
+                    private_key: None,
+                    certificate_chain: None,
+                    generate_private_key: false,
+                }
+            ))
+        );
+
+        let cbor_value = cbor_map! {
+            1 => dummy_aaguid,
+            2 => dummy_pkey,
+            3 => cbor_array![dummy_cert, dummy_intermediate],
+        };
+        assert_eq!(
+            AuthenticatorVendorProvisionParameters::try_from(cbor_value),
+            Ok(AuthenticatorVendorProvisionParameters {
+                aaguid: Some(dummy_aaguid),
+                private_key: Some(dummy_pkey),
+                certificate_chain: Some(vec![dummy_cert.to_vec(), dummy_intermediate.to_vec()]),
+                generate_private_key: false,
             })
         );
+
+        let cbor_value = cbor_map! {
+            3 => cbor_array![dummy_cert],
+            4 => true,
+        };
+        assert_eq!(
+            AuthenticatorVendorProvisionParameters::try_from(cbor_value),
+            Ok(AuthenticatorVendorProvisionParameters {
+                aaguid: None,
+                private_key: None,
+                certificate_chain: Some(vec![dummy_cert.to_vec()]),
+                generate_private_key: true,
+            })
+        );
+
+        // AAGUID is too short.
+        let cbor_value = cbor_map! {
+            1 => dummy_aaguid[..key_material::AAGUID_LENGTH - 1],
+        };
+        assert_eq!(
+            AuthenticatorVendorProvisionParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        // Attestation key is too long.
+        let cbor_value = cbor_map! {
+            2 => [0x41u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH + 1],
+        };
+        assert_eq!(
+            AuthenticatorVendorProvisionParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        // Certificates are byte strings.
+        let cbor_value = cbor_map! {
+            3 => cbor_array!["certificate"],
+        };
+        assert_eq!(
+            AuthenticatorVendorProvisionParameters::try_from(cbor_value),
+            Err(Ctap2StatusCode::CTAP2_ERR_CBOR_UNEXPECTED_TYPE)
+        );
     }
 }
@@ -0,0 +1,346 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::command::AuthenticatorConfigParameters;
+use super::data_formats::{ConfigSubCommand, ConfigSubCommandParams, SetMinPinLengthParams};
+use super::pin_protocol_v1::{PinPermission, PinProtocolV1, PIN_PROTOCOLS};
+use super::response::ResponseData;
+use super::status_code::Ctap2StatusCode;
+use super::storage::PersistentStore;
+use super::ENTERPRISE_ATTESTATION_MODE;
+use alloc::vec;
+
+/// Processes the subcommand setMinPINLength for AuthenticatorConfig.
+fn process_set_min_pin_length(
+    persistent_store: &mut PersistentStore,
+    params: SetMinPinLengthParams,
+) -> Result<(), Ctap2StatusCode> {
+    let SetMinPinLengthParams {
+        new_min_pin_length,
+        min_pin_length_rp_ids,
+        force_change_pin,
+    } = params;
+    let store_min_pin_length = persistent_store.min_pin_length()?;
+    let new_min_pin_length = new_min_pin_length.unwrap_or(store_min_pin_length);
+    if new_min_pin_length < store_min_pin_length {
+        return Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION);
+    }
+    let has_pin = persistent_store.pin_hash()?.is_some();
+    let mut force_change_pin = force_change_pin.unwrap_or(false);
+    if force_change_pin && !has_pin {
+        return Err(Ctap2StatusCode::CTAP2_ERR_PIN_NOT_SET);
+    }
+    // Only the PIN hash is stored, so we can't tell if the current PIN is still long enough.
+    if new_min_pin_length > store_min_pin_length && has_pin {
+        force_change_pin = true;
+    }
+    // A power loss must not leave a longer minimum without the forced PIN change.
+    persistent_store.transaction(|persistent_store| {
+        persistent_store.set_min_pin_length(new_min_pin_length)?;
+        if force_change_pin {
+            persistent_store.force_pin_change()?;
+        }
+        if let Some(min_pin_length_rp_ids) = min_pin_length_rp_ids {
+            persistent_store.set_min_pin_length_rp_ids(min_pin_length_rp_ids)?;
+        }
+        Ok(())
+    })
+}
+
+/// Processes the AuthenticatorConfig command.
+pub fn process_config(
+    persistent_store: &mut PersistentStore,
+    pin_protocol_v1: &mut PinProtocolV1,
+    params: AuthenticatorConfigParameters,
+) -> Result<ResponseData, Ctap2StatusCode> {
+    let AuthenticatorConfigParameters {
+        sub_command,
+        sub_command_params,
+        pin_uv_auth_param,
+        pin_uv_auth_protocol,
+    } = params;
+
+    if persistent_store.pin_hash()?.is_some() || persistent_store.has_always_uv()? {
+        let pin_uv_auth_param = pin_uv_auth_param.ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)?;
+        let pin_uv_auth_protocol = match pin_uv_auth_protocol {
+            Some(protocol) if PIN_PROTOCOLS.contains(&protocol) => protocol,
+            Some(_) => return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID),
+            None => return Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER),
+        };
+        // The message is 32 bytes 0xFF, the command byte, the subcommand byte and the CBOR
+        // encoded subcommand parameters, if any.
+        let mut message = vec![0xFF; 32];
+        message.extend(&[0x0D, sub_command as u8]);
+        if let Some(sub_command_params) = sub_command_params.clone() {
+            if !cbor::write(sub_command_params.into(), &mut message) {
+                return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR);
+            }
+        }
+        if !pin_protocol_v1.verify_pin_auth_token(
+            &message,
+            &pin_uv_auth_param,
+            pin_uv_auth_protocol,
+        ) {
+            return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+        }
+        pin_protocol_v1.has_permission(PinPermission::AuthenticatorConfiguration)?;
+    }
+
+    match sub_command {
+        ConfigSubCommand::EnableEnterpriseAttestation => {
+            if ENTERPRISE_ATTESTATION_MODE.is_none() {
+                return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_OPTION);
+            }
+            persistent_store.enable_enterprise_attestation()?
+        }
+        ConfigSubCommand::ToggleAlwaysUv => persistent_store.toggle_always_uv()?,
+        ConfigSubCommand::SetMinPinLength => {
+            let params = match sub_command_params {
+                Some(ConfigSubCommandParams::SetMinPinLength(params)) => params,
+                // Without parameters, the current settings are kept.
+                None => SetMinPinLengthParams {
+                    new_min_pin_length: None,
+                    min_pin_length_rp_ids: None,
+                    force_change_pin: None,
+                },
+            };
+            process_set_min_pin_length(persistent_store, params)?
+        }
+    }
+    Ok(ResponseData::AuthenticatorConfig)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use crypto::hmac::hmac_256;
+    use crypto::rng256::ThreadRng256;
+    use crypto::sha256::Sha256;
+
+    const PIN_UV_AUTH_TOKEN: [u8; 32] = [0x55; 32];
+
+    fn create_pin_protocol_v1(rng: &mut ThreadRng256) -> PinProtocolV1 {
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(rng);
+        PinProtocolV1::new_test(key_agreement_key, PIN_UV_AUTH_TOKEN)
+    }
+
+    fn compute_pin_uv_auth_param(
+        sub_command: ConfigSubCommand,
+        sub_command_params: Option<ConfigSubCommandParams>,
+    ) -> Vec<u8> {
+        let mut message = vec![0xFF; 32];
+        message.extend(&[0x0D, sub_command as u8]);
+        if let Some(sub_command_params) = sub_command_params {
+            assert!(cbor::write(sub_command_params.into(), &mut message));
+        }
+        hmac_256::<Sha256>(&PIN_UV_AUTH_TOKEN, &message)[..16].to_vec()
+    }
+
+    fn create_min_pin_length_params(
+        new_min_pin_length: Option<u8>,
+        min_pin_length_rp_ids: Option<Vec<String>>,
+        force_change_pin: Option<bool>,
+    ) -> Option<ConfigSubCommandParams> {
+        Some(ConfigSubCommandParams::SetMinPinLength(
+            SetMinPinLengthParams {
+                new_min_pin_length,
+                min_pin_length_rp_ids,
+                force_change_pin,
+            },
+        ))
+    }
+
+    #[test]
+    fn test_process_enable_enterprise_attestation() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol_v1(&mut rng);
+
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::EnableEnterpriseAttestation,
+            sub_command_params: None,
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let config_response =
+            process_config(&mut persistent_store, &mut pin_protocol_v1, config_params);
+        if ENTERPRISE_ATTESTATION_MODE.is_some() {
+            assert_eq!(config_response, Ok(ResponseData::AuthenticatorConfig));
+            assert!(persistent_store.enterprise_attestation().unwrap());
+        } else {
+            assert_eq!(
+                config_response,
+                Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_OPTION)
+            );
+            assert!(!persistent_store.enterprise_attestation().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_process_toggle_always_uv() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol_v1(&mut rng);
+
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::ToggleAlwaysUv,
+            sub_command_params: None,
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let config_response =
+            process_config(&mut persistent_store, &mut pin_protocol_v1, config_params);
+        assert_eq!(config_response, Ok(ResponseData::AuthenticatorConfig));
+        assert!(persistent_store.has_always_uv().unwrap());
+
+        // Once alwaysUv is enabled, toggling it back requires a PIN auth.
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::ToggleAlwaysUv,
+            sub_command_params: None,
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let config_response =
+            process_config(&mut persistent_store, &mut pin_protocol_v1, config_params);
+        assert_eq!(
+            config_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)
+        );
+
+        let pin_uv_auth_param = compute_pin_uv_auth_param(ConfigSubCommand::ToggleAlwaysUv, None);
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::ToggleAlwaysUv,
+            sub_command_params: None,
+            pin_uv_auth_param: Some(pin_uv_auth_param),
+            pin_uv_auth_protocol: Some(1),
+        };
+        let config_response =
+            process_config(&mut persistent_store, &mut pin_protocol_v1, config_params);
+        assert_eq!(config_response, Ok(ResponseData::AuthenticatorConfig));
+        assert!(!persistent_store.has_always_uv().unwrap());
+    }
+
+    #[test]
+    fn test_process_config_with_pin() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol_v1(&mut rng);
+        persistent_store.set_pin_hash(&[0x88; 16]).unwrap();
+
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::ToggleAlwaysUv,
+            sub_command_params: None,
+            pin_uv_auth_param: Some(vec![0x00; 16]),
+            pin_uv_auth_protocol: Some(1),
+        };
+        let config_response =
+            process_config(&mut persistent_store, &mut pin_protocol_v1, config_params);
+        assert_eq!(
+            config_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+
+        let pin_uv_auth_param = compute_pin_uv_auth_param(ConfigSubCommand::ToggleAlwaysUv, None);
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::ToggleAlwaysUv,
+            sub_command_params: None,
+            pin_uv_auth_param: Some(pin_uv_auth_param),
+            pin_uv_auth_protocol: Some(1),
+        };
+        let config_response =
+            process_config(&mut persistent_store, &mut pin_protocol_v1, config_params);
+        assert_eq!(config_response, Ok(ResponseData::AuthenticatorConfig));
+        assert!(persistent_store.has_always_uv().unwrap());
+    }
+
+    #[test]
+    fn test_process_set_min_pin_length() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol_v1(&mut rng);
+
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::SetMinPinLength,
+            sub_command_params: create_min_pin_length_params(
+                Some(6),
+                Some(vec![String::from("example.com")]),
+                None,
+            ),
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let config_response =
+            process_config(&mut persistent_store, &mut pin_protocol_v1, config_params);
+        assert_eq!(config_response, Ok(ResponseData::AuthenticatorConfig));
+        assert_eq!(persistent_store.min_pin_length(), Ok(6));
+        assert_eq!(
+            persistent_store.min_pin_length_rp_ids(),
+            Ok(vec![String::from("example.com")])
+        );
+        assert!(!persistent_store.has_force_pin_change().unwrap());
+
+        // The minimum PIN length can't decrease.
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::SetMinPinLength,
+            sub_command_params: create_min_pin_length_params(Some(4), None, None),
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let config_response =
+            process_config(&mut persistent_store, &mut pin_protocol_v1, config_params);
+        assert_eq!(
+            config_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
+        );
+
+        // Forcing a PIN change needs a PIN.
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::SetMinPinLength,
+            sub_command_params: create_min_pin_length_params(None, None, Some(true)),
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let config_response =
+            process_config(&mut persistent_store, &mut pin_protocol_v1, config_params);
+        assert_eq!(config_response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_NOT_SET));
+    }
+
+    #[test]
+    fn test_process_set_min_pin_length_force_pin_change() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = create_pin_protocol_v1(&mut rng);
+        persistent_store.set_pin_hash(&[0x88; 16]).unwrap();
+
+        // Increasing the minimum PIN length invalidates the current PIN.
+        let sub_command_params = create_min_pin_length_params(Some(8), None, None);
+        let pin_uv_auth_param = compute_pin_uv_auth_param(
+            ConfigSubCommand::SetMinPinLength,
+            sub_command_params.clone(),
+        );
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::SetMinPinLength,
+            sub_command_params,
+            pin_uv_auth_param: Some(pin_uv_auth_param),
+            pin_uv_auth_protocol: Some(1),
+        };
+        let config_response =
+            process_config(&mut persistent_store, &mut pin_protocol_v1, config_params);
+        assert_eq!(config_response, Ok(ResponseData::AuthenticatorConfig));
+        assert_eq!(persistent_store.min_pin_length(), Ok(8));
+        assert!(persistent_store.has_force_pin_change().unwrap());
+    }
+}
@@ -0,0 +1,642 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// COSE_Key public keys, see https://tools.ietf.org/html/rfc8152#section-13
+//
+// Requests keep COSE keys as raw CBOR maps. They are decoded into a CosePublicKey when used, which
+// checks that key type, algorithm and curve belong together and that the coordinates have the
+// size of the curve. The conversions to and from the crypto library keys build on that, so a new
+// algorithm only needs its identifiers and conversions here.
+
+use super::data_formats::{extract_byte_string, extract_integer, ok_or_missing};
+use super::status_code::Ctap2StatusCode;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use arrayref::array_ref;
+use cbor::{cbor_map_options, destructure_cbor_map};
+use core::convert::TryFrom;
+use crypto::{ecdh, ecdsa, ecdsa_p384, ed25519, x25519};
+
+// https://www.iana.org/assignments/cose/cose.xhtml#key-type
+const OKP_KEY_TYPE: i64 = 1;
+const EC2_KEY_TYPE: i64 = 2;
+// RSA keys have the modulus at label -1 and the public exponent at label -2. They are not
+// supported yet, and would become another variant of CosePublicKey.
+const RSA_KEY_TYPE: i64 = 3;
+
+// This is the algorithm specifier that is supposed to be used in a COSE key
+// map. The CTAP specification says -25 which represents ECDH-ES + HKDF-256
+// here: https://www.iana.org/assignments/cose/cose.xhtml#algorithms
+// In fact, this is just used for compatibility with older specification versions.
+const ECDH_ALGORITHM: i64 = -25;
+
+#[derive(Clone)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct CoseKey(pub BTreeMap<cbor::KeyType, cbor::Value>);
+
+impl From<CoseKey> for cbor::Value {
+    fn from(cose_key: CoseKey) -> Self {
+        cbor::Value::Map(cose_key.0)
+    }
+}
+
+// The algorithms a public key can be restricted to.
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub enum CoseAlgorithm {
+    Es256,
+    EdDsa,
+    Es384,
+    EcdhEsHkdf256,
+}
+
+impl From<CoseAlgorithm> for i64 {
+    fn from(algorithm: CoseAlgorithm) -> Self {
+        match algorithm {
+            CoseAlgorithm::Es256 => ecdsa::PubKey::ES256_ALGORITHM,
+            CoseAlgorithm::EdDsa => ed25519::PubKey::EDDSA_ALGORITHM,
+            CoseAlgorithm::Es384 => ecdsa_p384::PubKey::ES384_ALGORITHM,
+            CoseAlgorithm::EcdhEsHkdf256 => ECDH_ALGORITHM,
+        }
+    }
+}
+
+impl TryFrom<i64> for CoseAlgorithm {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(algorithm: i64) -> Result<Self, Ctap2StatusCode> {
+        match algorithm {
+            ecdsa::PubKey::ES256_ALGORITHM => Ok(CoseAlgorithm::Es256),
+            ed25519::PubKey::EDDSA_ALGORITHM => Ok(CoseAlgorithm::EdDsa),
+            ecdsa_p384::PubKey::ES384_ALGORITHM => Ok(CoseAlgorithm::Es384),
+            ECDH_ALGORITHM => Ok(CoseAlgorithm::EcdhEsHkdf256),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM),
+        }
+    }
+}
+
+// https://www.iana.org/assignments/cose/cose.xhtml#elliptic-curves
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub enum CoseCurve {
+    P256 = 1,
+    P384 = 2,
+    X25519 = 4,
+    Ed25519 = 6,
+}
+
+impl TryFrom<i64> for CoseCurve {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(curve: i64) -> Result<Self, Ctap2StatusCode> {
+        match curve {
+            1 => Ok(CoseCurve::P256),
+            2 => Ok(CoseCurve::P384),
+            4 => Ok(CoseCurve::X25519),
+            6 => Ok(CoseCurve::Ed25519),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM),
+        }
+    }
+}
+
+impl CoseCurve {
+    fn key_type(self) -> i64 {
+        match self {
+            CoseCurve::P256 | CoseCurve::P384 => EC2_KEY_TYPE,
+            CoseCurve::X25519 | CoseCurve::Ed25519 => OKP_KEY_TYPE,
+        }
+    }
+
+    // The length of each coordinate, in bytes.
+    fn coordinate_length(self) -> usize {
+        match self {
+            CoseCurve::P256 => ecdh::NBYTES,
+            CoseCurve::P384 => ecdsa_p384::NBYTES,
+            CoseCurve::X25519 | CoseCurve::Ed25519 => 32,
+        }
+    }
+
+    // Whether keys on this curve can be used with the algorithm.
+    fn supports(self, algorithm: CoseAlgorithm) -> bool {
+        matches!(
+            (self, algorithm),
+            (CoseCurve::P256, CoseAlgorithm::Es256)
+                | (CoseCurve::P256, CoseAlgorithm::EcdhEsHkdf256)
+                | (CoseCurve::P384, CoseAlgorithm::Es384)
+                | (CoseCurve::X25519, CoseAlgorithm::EcdhEsHkdf256)
+                | (CoseCurve::Ed25519, CoseAlgorithm::EdDsa)
+        )
+    }
+}
+
+// A validated COSE public key. Curve and algorithm are consistent, and all coordinates have the
+// length of the curve.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub enum CosePublicKey {
+    // A point (x, y) on a short Weierstrass curve.
+    Ec2 {
+        algorithm: CoseAlgorithm,
+        curve: CoseCurve,
+        x: Vec<u8>,
+        y: Vec<u8>,
+    },
+    // A point on a Montgomery or Edwards curve, encoded as in RFC 8032 or RFC 7748.
+    Okp {
+        algorithm: CoseAlgorithm,
+        curve: CoseCurve,
+        x: Vec<u8>,
+    },
+}
+
+// Checks the parameters shared by all key types, and returns the curve.
+fn check_curve_and_algorithm(
+    key_type: i64,
+    algorithm: CoseAlgorithm,
+    curve: Option<cbor::Value>,
+) -> Result<CoseCurve, Ctap2StatusCode> {
+    let curve = CoseCurve::try_from(extract_integer(ok_or_missing(curve)?)?)?;
+    if curve.key_type() != key_type || !curve.supports(algorithm) {
+        return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM);
+    }
+    Ok(curve)
+}
+
+fn extract_coordinate(
+    coordinate: Option<cbor::Value>,
+    curve: CoseCurve,
+) -> Result<Vec<u8>, Ctap2StatusCode> {
+    let coordinate = extract_byte_string(ok_or_missing(coordinate)?)?;
+    if coordinate.len() != curve.coordinate_length() {
+        return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+    }
+    Ok(coordinate)
+}
+
+impl TryFrom<CoseKey> for CosePublicKey {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cose_key: CoseKey) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                1 => key_type,
+                3 => algorithm,
+                -1 => curve,
+                -2 => x_bytes,
+                -3 => y_bytes,
+            } = cose_key.0;
+        }
+
+        let key_type = extract_integer(ok_or_missing(key_type)?)?;
+        let algorithm = CoseAlgorithm::try_from(extract_integer(ok_or_missing(algorithm)?)?)?;
+        match key_type {
+            EC2_KEY_TYPE => {
+                let curve = check_curve_and_algorithm(key_type, algorithm, curve)?;
+                Ok(CosePublicKey::Ec2 {
+                    algorithm,
+                    curve,
+                    x: extract_coordinate(x_bytes, curve)?,
+                    y: extract_coordinate(y_bytes, curve)?,
+                })
+            }
+            OKP_KEY_TYPE => {
+                let curve = check_curve_and_algorithm(key_type, algorithm, curve)?;
+                if y_bytes.is_some() {
+                    return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+                }
+                Ok(CosePublicKey::Okp {
+                    algorithm,
+                    curve,
+                    x: extract_coordinate(x_bytes, curve)?,
+                })
+            }
+            RSA_KEY_TYPE => Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM),
+        }
+    }
+}
+
+impl From<CosePublicKey> for CoseKey {
+    fn from(public_key: CosePublicKey) -> Self {
+        // TODO(kaczmarczyck) do not write optional parameters, spec is unclear
+        let cose_cbor_value = match public_key {
+            CosePublicKey::Ec2 {
+                algorithm,
+                curve,
+                x,
+                y,
+            } => cbor_map_options! {
+                1 => EC2_KEY_TYPE,
+                3 => i64::from(algorithm),
+                -1 => curve as i64,
+                -2 => x,
+                -3 => y,
+            },
+            CosePublicKey::Okp {
+                algorithm,
+                curve,
+                x,
+            } => cbor_map_options! {
+                1 => OKP_KEY_TYPE,
+                3 => i64::from(algorithm),
+                -1 => curve as i64,
+                -2 => x,
+            },
+        };
+        if let cbor::Value::Map(cose_map) = cose_cbor_value {
+            CoseKey(cose_map)
+        } else {
+            unreachable!();
+        }
+    }
+}
+
+impl From<ecdh::PubKey> for CoseKey {
+    fn from(pk: ecdh::PubKey) -> Self {
+        let mut x = [0; ecdh::NBYTES];
+        let mut y = [0; ecdh::NBYTES];
+        pk.to_coordinates(&mut x, &mut y);
+        CoseKey::from(CosePublicKey::Ec2 {
+            algorithm: CoseAlgorithm::EcdhEsHkdf256,
+            curve: CoseCurve::P256,
+            x: x.to_vec(),
+            y: y.to_vec(),
+        })
+    }
+}
+
+impl TryFrom<CoseKey> for ecdh::PubKey {
+    type Error = Ctap2StatusCode;
+
+    // OpenSSH uses the ES256 identifier for key agreement keys. To be compatible, we accept both
+    // ECDH-ES + HKDF-256 and ES256.
+    fn try_from(cose_key: CoseKey) -> Result<Self, Ctap2StatusCode> {
+        match CosePublicKey::try_from(cose_key)? {
+            CosePublicKey::Ec2 {
+                curve: CoseCurve::P256,
+                x,
+                y,
+                ..
+            } => ecdh::PubKey::from_coordinates(
+                array_ref![x.as_slice(), 0, ecdh::NBYTES],
+                array_ref![y.as_slice(), 0, ecdh::NBYTES],
+            )
+            .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM),
+        }
+    }
+}
+
+impl From<ecdsa::PubKey> for CoseKey {
+    fn from(pk: ecdsa::PubKey) -> Self {
+        let mut x = [0; ecdh::NBYTES];
+        let mut y = [0; ecdh::NBYTES];
+        pk.to_coordinates(&mut x, &mut y);
+        CoseKey::from(CosePublicKey::Ec2 {
+            algorithm: CoseAlgorithm::Es256,
+            curve: CoseCurve::P256,
+            x: x.to_vec(),
+            y: y.to_vec(),
+        })
+    }
+}
+
+impl TryFrom<CoseKey> for ecdsa::PubKey {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cose_key: CoseKey) -> Result<Self, Ctap2StatusCode> {
+        match CosePublicKey::try_from(cose_key)? {
+            CosePublicKey::Ec2 {
+                algorithm: CoseAlgorithm::Es256,
+                x,
+                y,
+                ..
+            } => ecdsa::PubKey::from_coordinates(
+                array_ref![x.as_slice(), 0, ecdh::NBYTES],
+                array_ref![y.as_slice(), 0, ecdh::NBYTES],
+            )
+            .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM),
+        }
+    }
+}
+
+impl From<ecdsa_p384::PubKey> for CoseKey {
+    fn from(pk: ecdsa_p384::PubKey) -> Self {
+        let mut x = [0; ecdsa_p384::NBYTES];
+        let mut y = [0; ecdsa_p384::NBYTES];
+        pk.to_coordinates(&mut x, &mut y);
+        CoseKey::from(CosePublicKey::Ec2 {
+            algorithm: CoseAlgorithm::Es384,
+            curve: CoseCurve::P384,
+            x: x.to_vec(),
+            y: y.to_vec(),
+        })
+    }
+}
+
+impl TryFrom<CoseKey> for ecdsa_p384::PubKey {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cose_key: CoseKey) -> Result<Self, Ctap2StatusCode> {
+        match CosePublicKey::try_from(cose_key)? {
+            CosePublicKey::Ec2 {
+                algorithm: CoseAlgorithm::Es384,
+                x,
+                y,
+                ..
+            } => ecdsa_p384::PubKey::from_coordinates(
+                array_ref![x.as_slice(), 0, ecdsa_p384::NBYTES],
+                array_ref![y.as_slice(), 0, ecdsa_p384::NBYTES],
+            )
+            .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM),
+        }
+    }
+}
+
+impl From<ed25519::PubKey> for CoseKey {
+    fn from(pk: ed25519::PubKey) -> Self {
+        CoseKey::from(CosePublicKey::Okp {
+            algorithm: CoseAlgorithm::EdDsa,
+            curve: CoseCurve::Ed25519,
+            x: pk.to_bytes().to_vec(),
+        })
+    }
+}
+
+impl TryFrom<CoseKey> for ed25519::PubKey {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cose_key: CoseKey) -> Result<Self, Ctap2StatusCode> {
+        match CosePublicKey::try_from(cose_key)? {
+            CosePublicKey::Okp {
+                algorithm: CoseAlgorithm::EdDsa,
+                x,
+                ..
+            } => Ok(ed25519::PubKey::from_bytes(array_ref![x.as_slice(), 0, 32])),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM),
+        }
+    }
+}
+
+impl From<x25519::PubKey> for CoseKey {
+    fn from(pk: x25519::PubKey) -> Self {
+        CoseKey::from(CosePublicKey::Okp {
+            algorithm: CoseAlgorithm::EcdhEsHkdf256,
+            curve: CoseCurve::X25519,
+            x: pk.to_bytes().to_vec(),
+        })
+    }
+}
+
+impl TryFrom<CoseKey> for x25519::PubKey {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cose_key: CoseKey) -> Result<Self, Ctap2StatusCode> {
+        match CosePublicKey::try_from(cose_key)? {
+            CosePublicKey::Okp {
+                curve: CoseCurve::X25519,
+                x,
+                ..
+            } => Ok(x25519::PubKey::from_bytes(array_ref![
+                x.as_slice(),
+                0,
+                x25519::NBYTES
+            ])),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::data_formats::extract_map;
+    use super::*;
+    use cbor::cbor_map;
+    use crypto::rng256::ThreadRng256;
+    use crypto::sha256::Sha256;
+
+    #[test]
+    fn test_from_into_cose_key() {
+        let mut rng = ThreadRng256 {};
+        let sk = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pk = sk.genpk();
+        let cose_key = CoseKey::from(pk.clone());
+        let created_pk = ecdh::PubKey::try_from(cose_key);
+        assert_eq!(created_pk, Ok(pk));
+    }
+
+    #[test]
+    fn test_ecdh_accepts_es256() {
+        let mut rng = ThreadRng256 {};
+        let pk = crypto::ecdh::SecKey::gensk(&mut rng).genpk();
+        let mut x = [0; ecdh::NBYTES];
+        let mut y = [0; ecdh::NBYTES];
+        pk.to_coordinates(&mut x, &mut y);
+        let cose_map = cbor_map! {
+            1 => EC2_KEY_TYPE,
+            3 => ecdsa::PubKey::ES256_ALGORITHM,
+            -1 => CoseCurve::P256 as i64,
+            -2 => x.to_vec(),
+            -3 => y.to_vec(),
+        };
+        let cose_key = CoseKey(extract_map(cose_map).unwrap());
+        assert_eq!(ecdh::PubKey::try_from(cose_key), Ok(pk));
+    }
+
+    #[test]
+    fn test_ecdsa_round_trip() {
+        let mut rng = ThreadRng256 {};
+        let sk = ecdsa::SecKey::gensk(&mut rng);
+        let cose_key = CoseKey::from(sk.genpk());
+        // The encoding matches the one of the crypto library.
+        let mut cose_bytes = Vec::new();
+        assert!(cbor::write(cose_key.clone().into(), &mut cose_bytes));
+        assert_eq!(Some(cose_bytes), sk.genpk().to_cose_key());
+        let pk = ecdsa::PubKey::try_from(cose_key).unwrap();
+        let signature = sk.sign_rfc6979::<Sha256>(b"message");
+        assert!(pk.verify_vartime::<Sha256>(b"message", &signature));
+    }
+
+    #[test]
+    fn test_ecdsa_p384_round_trip() {
+        let mut rng = ThreadRng256 {};
+        let sk = ecdsa_p384::SecKey::gensk(&mut rng);
+        let cose_bytes = sk.genpk().to_cose_key().unwrap();
+        let cose_map = extract_map(cbor::read(&cose_bytes).unwrap()).unwrap();
+        let pk = ecdsa_p384::PubKey::try_from(CoseKey(cose_map.clone())).unwrap();
+        let signature = sk.sign_rfc6979(b"message");
+        assert!(pk.verify_vartime(b"message", &signature));
+        assert_eq!(CoseKey::from(pk), CoseKey(cose_map));
+
+        let ecdh_pk = crypto::ecdh::SecKey::gensk(&mut rng).genpk();
+        assert_eq!(
+            ecdsa_p384::PubKey::try_from(CoseKey::from(ecdh_pk)).err(),
+            Some(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM)
+        );
+    }
+
+    #[test]
+    fn test_ed25519_round_trip() {
+        let mut rng = ThreadRng256 {};
+        let sk = ed25519::SecKey::gensk(&mut rng);
+        let cose_key = CoseKey::from(sk.genpk());
+        let mut cose_bytes = Vec::new();
+        assert!(cbor::write(cose_key.clone().into(), &mut cose_bytes));
+        assert_eq!(Some(cose_bytes), sk.genpk().to_cose_key());
+        let pk = ed25519::PubKey::try_from(cose_key).unwrap();
+        assert_eq!(pk.to_bytes(), sk.genpk().to_bytes());
+    }
+
+    #[test]
+    fn test_x25519_round_trip() {
+        let cose_map = cbor_map! {
+            1 => OKP_KEY_TYPE,
+            3 => ECDH_ALGORITHM,
+            -1 => CoseCurve::X25519 as i64,
+            -2 => vec![0x55; 32],
+        };
+        let cose_key = CoseKey(extract_map(cose_map).unwrap());
+        let public_key = CosePublicKey::try_from(cose_key.clone()).unwrap();
+        assert_eq!(
+            public_key,
+            CosePublicKey::Okp {
+                algorithm: CoseAlgorithm::EcdhEsHkdf256,
+                curve: CoseCurve::X25519,
+                x: vec![0x55; 32],
+            }
+        );
+        assert_eq!(CoseKey::from(public_key), cose_key);
+        let pk = x25519::PubKey::try_from(cose_key.clone()).unwrap();
+        assert_eq!(pk.to_bytes(), [0x55; 32]);
+        assert_eq!(CoseKey::from(pk), cose_key);
+        // There is no signature algorithm for an X25519 key.
+        assert_eq!(
+            ed25519::PubKey::try_from(cose_key).err(),
+            Some(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM)
+        );
+        // Nor is X25519 a curve for ECDH on P-256.
+        let mut rng = ThreadRng256 {};
+        let cose_key = CoseKey::from(x25519::SecKey::gensk(&mut rng).genpk());
+        assert_eq!(
+            ecdh::PubKey::try_from(cose_key).err(),
+            Some(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM)
+        );
+    }
+
+    fn decode(cose_map: cbor::Value) -> Result<CosePublicKey, Ctap2StatusCode> {
+        CosePublicKey::try_from(CoseKey(extract_map(cose_map).unwrap()))
+    }
+
+    #[test]
+    fn test_inconsistent_curve_and_algorithm() {
+        // ES256 on P-384.
+        let cose_map = cbor_map! {
+            1 => EC2_KEY_TYPE,
+            3 => ecdsa::PubKey::ES256_ALGORITHM,
+            -1 => CoseCurve::P384 as i64,
+            -2 => vec![0x55; 48],
+            -3 => vec![0x55; 48],
+        };
+        assert_eq!(
+            decode(cose_map),
+            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM)
+        );
+        // EdDSA on X25519.
+        let cose_map = cbor_map! {
+            1 => OKP_KEY_TYPE,
+            3 => ed25519::PubKey::EDDSA_ALGORITHM,
+            -1 => CoseCurve::X25519 as i64,
+            -2 => vec![0x55; 32],
+        };
+        assert_eq!(
+            decode(cose_map),
+            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM)
+        );
+        // An OKP curve in an EC2 key.
+        let cose_map = cbor_map! {
+            1 => EC2_KEY_TYPE,
+            3 => ed25519::PubKey::EDDSA_ALGORITHM,
+            -1 => CoseCurve::Ed25519 as i64,
+            -2 => vec![0x55; 32],
+            -3 => vec![0x55; 32],
+        };
+        assert_eq!(
+            decode(cose_map),
+            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM)
+        );
+    }
+
+    #[test]
+    fn test_unsupported_parameters() {
+        let cose_map = cbor_map! {
+            1 => RSA_KEY_TYPE,
+            3 => -257,
+            -1 => vec![0x55; 256],
+            -2 => vec![0x01, 0x00, 0x01],
+        };
+        assert_eq!(
+            decode(cose_map),
+            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM)
+        );
+        let cose_map = cbor_map! {
+            1 => EC2_KEY_TYPE,
+            3 => ecdsa::PubKey::ES256_ALGORITHM,
+            -1 => 3,
+            -2 => vec![0x55; 66],
+            -3 => vec![0x55; 66],
+        };
+        assert_eq!(
+            decode(cose_map),
+            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM)
+        );
+        let cose_map = cbor_map! {
+            1 => EC2_KEY_TYPE,
+            -1 => CoseCurve::P256 as i64,
+            -2 => vec![0x55; 32],
+            -3 => vec![0x55; 32],
+        };
+        assert_eq!(
+            decode(cose_map),
+            Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn test_coordinate_lengths() {
+        let cose_map = cbor_map! {
+            1 => EC2_KEY_TYPE,
+            3 => ecdsa::PubKey::ES256_ALGORITHM,
+            -1 => CoseCurve::P256 as i64,
+            -2 => vec![0x55; 32],
+            -3 => vec![0x55; 31],
+        };
+        assert_eq!(
+            decode(cose_map),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        let cose_map = cbor_map! {
+            1 => OKP_KEY_TYPE,
+            3 => ed25519::PubKey::EDDSA_ALGORITHM,
+            -1 => CoseCurve::Ed25519 as i64,
+            -2 => vec![0x55; 32],
+            -3 => vec![0x55; 32],
+        };
+        assert_eq!(
+            decode(cose_map),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+    }
+}
@@ -0,0 +1,794 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::command::AuthenticatorCredentialManagementParameters;
+use super::data_formats::{
+    CredentialManagementSubCommand, CredentialManagementSubCommandParams,
+    PublicKeyCredentialDescriptor, PublicKeyCredentialRpEntity, PublicKeyCredentialSource,
+    PublicKeyCredentialUserEntity,
+};
+use super::pin_protocol_v1::{PinPermission, PinProtocolV1, PIN_PROTOCOLS};
+use super::response::{AuthenticatorCredentialManagementResponse, ResponseData};
+use super::status_code::Ctap2StatusCode;
+use super::storage::PersistentStore;
+use super::timed_permission::TimedPermission;
+use super::{StatefulCommand, STATEFUL_COMMAND_TIMEOUT_DURATION};
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec;
+use crypto::sha256::Sha256;
+use crypto::Hash256;
+use libtock_drivers::timer::ClockValue;
+
+/// Returns the smallest RP ID of all credentials that is greater than `previous_rp_id`.
+///
+/// RPs are enumerated in lexicographic order, so that the enumeration state is only the last
+/// returned RP ID. Credentials are read one at a time from the store.
+fn next_rp_id(
+    persistent_store: &PersistentStore,
+    previous_rp_id: Option<&str>,
+) -> Result<Option<String>, Ctap2StatusCode> {
+    let mut iter_result = Ok(());
+    let iter = persistent_store.iter_credentials(&mut iter_result)?;
+    let mut next_rp_id: Option<String> = None;
+    for (_, credential) in iter {
+        if previous_rp_id.map_or(false, |previous| credential.rp_id.as_str() <= previous) {
+            continue;
+        }
+        if next_rp_id
+            .as_ref()
+            .map_or(true, |next| credential.rp_id < *next)
+        {
+            next_rp_id = Some(credential.rp_id);
+        }
+    }
+    iter_result?;
+    Ok(next_rp_id)
+}
+
+/// Returns the number of distinct RP IDs of all credentials.
+fn count_rp_ids(persistent_store: &PersistentStore) -> Result<usize, Ctap2StatusCode> {
+    let mut iter_result = Ok(());
+    let iter = persistent_store.iter_credentials(&mut iter_result)?;
+    // Only the RP IDs are kept while counting, not the credentials.
+    let rp_ids: BTreeSet<String> = iter.map(|(_, credential)| credential.rp_id).collect();
+    iter_result?;
+    Ok(rp_ids.len())
+}
+
+/// Returns the credential of the RP with the smallest storage key greater than `previous_key`.
+///
+/// Credentials are enumerated in the order of their storage keys, so that the enumeration state is
+/// only the last returned key. Also returns how many credentials of the RP remain, including the
+/// returned one.
+fn next_credential(
+    persistent_store: &PersistentStore,
+    rp_id_hash: &[u8],
+    previous_key: Option<usize>,
+) -> Result<(usize, Option<(usize, PublicKeyCredentialSource)>), Ctap2StatusCode> {
+    // The credentials are sorted by key.
+    let mut credentials = persistent_store
+        .rp_credentials(rp_id_hash)?
+        .into_iter()
+        .filter(|(key, _)| previous_key.map_or(true, |previous| *key > previous));
+    let next = credentials.next();
+    let remaining = next.iter().count() + credentials.count();
+    Ok((remaining, next))
+}
+
+/// Generates the response for subcommands enumerating RPs.
+fn enumerate_rps_response(
+    rp_id: String,
+    total_rps: Option<u64>,
+) -> AuthenticatorCredentialManagementResponse {
+    let rp_id_hash = Some(Sha256::hash(rp_id.as_bytes()).to_vec());
+    let rp = Some(PublicKeyCredentialRpEntity {
+        rp_id,
+        rp_name: None,
+        rp_icon: None,
+    });
+    AuthenticatorCredentialManagementResponse {
+        rp,
+        rp_id_hash,
+        total_rps,
+        ..Default::default()
+    }
+}
+
+/// Generates the response for subcommands enumerating credentials.
+fn enumerate_credentials_response(
+    credential: PublicKeyCredentialSource,
+    total_credentials: Option<u64>,
+) -> Result<AuthenticatorCredentialManagementResponse, Ctap2StatusCode> {
+    let PublicKeyCredentialSource {
+        key_type,
+        credential_id,
+        private_key,
+        user_handle,
+        user_display_name,
+        cred_protect_policy,
+        creation_order,
+        last_used_order,
+        user_name,
+        user_icon,
+        large_blob_key,
+        ..
+    } = credential;
+    let user = PublicKeyCredentialUserEntity {
+        user_id: user_handle,
+        user_name,
+        user_display_name,
+        user_icon,
+    };
+    let credential_id = PublicKeyCredentialDescriptor {
+        key_type,
+        key_id: credential_id,
+        transports: None,
+    };
+    Ok(AuthenticatorCredentialManagementResponse {
+        user: Some(user),
+        credential_id: Some(credential_id),
+        public_key: Some(private_key.public_key_cose()),
+        total_credentials,
+        cred_protect: cred_protect_policy,
+        large_blob_key,
+        creation_order: Some(creation_order),
+        last_used_order,
+        ..Default::default()
+    })
+}
+
+// Checks that the pinUvAuthToken may modify the credential with the given ID.
+//
+// Returns CTAP2_ERR_NO_CREDENTIALS if no credential has this ID.
+fn check_credential_permission(
+    persistent_store: &PersistentStore,
+    pin_protocol_v1: &PinProtocolV1,
+    credential_id: &[u8],
+) -> Result<(), Ctap2StatusCode> {
+    let mut iter_result = Ok(());
+    let iter = persistent_store.iter_credentials(&mut iter_result)?;
+    let rp_id = iter
+        .map(|(_, credential)| credential)
+        .find(|credential| credential.credential_id == credential_id)
+        .map(|credential| credential.rp_id);
+    iter_result?;
+    let rp_id = rp_id.ok_or(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)?;
+    pin_protocol_v1.has_no_or_rp_id_hash_permission(&Sha256::hash(rp_id.as_bytes()))
+}
+
+/// Processes the subcommand getCredsMetadata for CredentialManagement.
+fn process_get_creds_metadata(
+    persistent_store: &PersistentStore,
+    pin_protocol_v1: &PinProtocolV1,
+) -> Result<AuthenticatorCredentialManagementResponse, Ctap2StatusCode> {
+    pin_protocol_v1.has_no_permission_rp_id()?;
+    Ok(AuthenticatorCredentialManagementResponse {
+        existing_resident_credentials_count: Some(persistent_store.count_credentials()? as u64),
+        max_possible_remaining_resident_credentials_count: Some(
+            persistent_store.remaining_credentials()? as u64,
+        ),
+        ..Default::default()
+    })
+}
+
+/// Processes the subcommand enumerateRPsBegin for CredentialManagement.
+fn process_enumerate_rps_begin(
+    persistent_store: &PersistentStore,
+    pin_protocol_v1: &PinProtocolV1,
+    stateful_command_permission: &mut TimedPermission,
+    stateful_command_type: &mut Option<StatefulCommand>,
+    now: ClockValue,
+) -> Result<AuthenticatorCredentialManagementResponse, Ctap2StatusCode> {
+    pin_protocol_v1.has_no_permission_rp_id()?;
+    let total_rps = count_rp_ids(persistent_store)?;
+    let rp_id =
+        next_rp_id(persistent_store, None)?.ok_or(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)?;
+    if total_rps > 1 {
+        *stateful_command_permission =
+            TimedPermission::granted(now, STATEFUL_COMMAND_TIMEOUT_DURATION);
+        *stateful_command_type = Some(StatefulCommand::EnumerateRps(rp_id.clone()));
+    }
+    Ok(enumerate_rps_response(rp_id, Some(total_rps as u64)))
+}
+
+/// Processes the subcommand enumerateRPsGetNextRP for CredentialManagement.
+fn process_enumerate_rps_get_next_rp(
+    persistent_store: &PersistentStore,
+    stateful_command_type: &mut Option<StatefulCommand>,
+) -> Result<AuthenticatorCredentialManagementResponse, Ctap2StatusCode> {
+    let previous_rp_id = match stateful_command_type {
+        Some(StatefulCommand::EnumerateRps(previous_rp_id)) => previous_rp_id,
+        _ => return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED),
+    };
+    let rp_id = next_rp_id(persistent_store, Some(previous_rp_id.as_str()))?
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)?;
+    *previous_rp_id = rp_id.clone();
+    Ok(enumerate_rps_response(rp_id, None))
+}
+
+/// Processes the subcommand enumerateCredentialsBegin for CredentialManagement.
+fn process_enumerate_credentials_begin(
+    persistent_store: &PersistentStore,
+    pin_protocol_v1: &PinProtocolV1,
+    stateful_command_permission: &mut TimedPermission,
+    stateful_command_type: &mut Option<StatefulCommand>,
+    sub_command_params: CredentialManagementSubCommandParams,
+    now: ClockValue,
+) -> Result<AuthenticatorCredentialManagementResponse, Ctap2StatusCode> {
+    let rp_id_hash = sub_command_params
+        .rp_id_hash
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?;
+    pin_protocol_v1.has_no_or_rp_id_hash_permission(&rp_id_hash)?;
+    let (total_credentials, next) = next_credential(persistent_store, &rp_id_hash, None)?;
+    let (key, credential) = next.ok_or(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)?;
+    if total_credentials > 1 {
+        *stateful_command_permission =
+            TimedPermission::granted(now, STATEFUL_COMMAND_TIMEOUT_DURATION);
+        *stateful_command_type = Some(StatefulCommand::EnumerateCredentials(rp_id_hash, key));
+    }
+    enumerate_credentials_response(credential, Some(total_credentials as u64))
+}
+
+/// Processes the subcommand enumerateCredentialsGetNextCredential for CredentialManagement.
+fn process_enumerate_credentials_get_next_credential(
+    persistent_store: &PersistentStore,
+    stateful_command_type: &mut Option<StatefulCommand>,
+) -> Result<AuthenticatorCredentialManagementResponse, Ctap2StatusCode> {
+    let (rp_id_hash, previous_key) = match stateful_command_type {
+        Some(StatefulCommand::EnumerateCredentials(rp_id_hash, previous_key)) => {
+            (rp_id_hash, previous_key)
+        }
+        _ => return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED),
+    };
+    let (_, next) = next_credential(persistent_store, &rp_id_hash[..], Some(*previous_key))?;
+    let (key, credential) = next.ok_or(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)?;
+    *previous_key = key;
+    enumerate_credentials_response(credential, None)
+}
+
+/// Processes the subcommand deleteCredential for CredentialManagement.
+fn process_delete_credential(
+    persistent_store: &mut PersistentStore,
+    pin_protocol_v1: &PinProtocolV1,
+    sub_command_params: CredentialManagementSubCommandParams,
+) -> Result<(), Ctap2StatusCode> {
+    let credential_id = sub_command_params
+        .credential_id
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?
+        .key_id;
+    check_credential_permission(persistent_store, pin_protocol_v1, &credential_id)?;
+    persistent_store.delete_credential(&credential_id)
+}
+
+/// Processes the subcommand updateUserInformation for CredentialManagement.
+fn process_update_user_information(
+    persistent_store: &mut PersistentStore,
+    pin_protocol_v1: &PinProtocolV1,
+    sub_command_params: CredentialManagementSubCommandParams,
+) -> Result<(), Ctap2StatusCode> {
+    let credential_id = sub_command_params
+        .credential_id
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?
+        .key_id;
+    let user = sub_command_params
+        .user
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?;
+    check_credential_permission(persistent_store, pin_protocol_v1, &credential_id)?;
+    persistent_store.update_credential_user(&credential_id, user)
+}
+
+/// Processes the CredentialManagement command.
+///
+/// The caller checks that the stateful command permission is granted for the subcommands that
+/// continue an enumeration.
+pub fn process_credential_management(
+    persistent_store: &mut PersistentStore,
+    pin_protocol_v1: &mut PinProtocolV1,
+    stateful_command_permission: &mut TimedPermission,
+    stateful_command_type: &mut Option<StatefulCommand>,
+    params: AuthenticatorCredentialManagementParameters,
+    now: ClockValue,
+) -> Result<ResponseData, Ctap2StatusCode> {
+    let AuthenticatorCredentialManagementParameters {
+        sub_command,
+        sub_command_params,
+        pin_uv_auth_protocol,
+        pin_uv_auth_param,
+    } = params;
+
+    match sub_command {
+        CredentialManagementSubCommand::GetCredsMetadata
+        | CredentialManagementSubCommand::EnumerateRpsBegin
+        | CredentialManagementSubCommand::EnumerateCredentialsBegin
+        | CredentialManagementSubCommand::DeleteCredential
+        | CredentialManagementSubCommand::UpdateUserInformation => {
+            let pin_uv_auth_param =
+                pin_uv_auth_param.ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)?;
+            let pin_uv_auth_protocol = match pin_uv_auth_protocol {
+                Some(protocol) if PIN_PROTOCOLS.contains(&protocol) => protocol,
+                Some(_) => return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID),
+                None => return Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER),
+            };
+            // The message is the subcommand byte and the CBOR encoded subcommand parameters, if
+            // any.
+            let mut message = vec![sub_command as u8];
+            if let Some(sub_command_params) = sub_command_params.clone() {
+                if !cbor::write(sub_command_params.into(), &mut message) {
+                    return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR);
+                }
+            }
+            if !pin_protocol_v1.verify_pin_auth_token(
+                &message,
+                &pin_uv_auth_param,
+                pin_uv_auth_protocol,
+            ) {
+                return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+            }
+            pin_protocol_v1.has_permission(PinPermission::CredentialManagement)?;
+        }
+        CredentialManagementSubCommand::EnumerateRpsGetNextRp
+        | CredentialManagementSubCommand::EnumerateCredentialsGetNextCredential => {}
+    }
+
+    let response = match sub_command {
+        CredentialManagementSubCommand::GetCredsMetadata => Some(process_get_creds_metadata(
+            persistent_store,
+            pin_protocol_v1,
+        )?),
+        CredentialManagementSubCommand::EnumerateRpsBegin => Some(process_enumerate_rps_begin(
+            persistent_store,
+            pin_protocol_v1,
+            stateful_command_permission,
+            stateful_command_type,
+            now,
+        )?),
+        CredentialManagementSubCommand::EnumerateRpsGetNextRp => Some(
+            process_enumerate_rps_get_next_rp(persistent_store, stateful_command_type)?,
+        ),
+        CredentialManagementSubCommand::EnumerateCredentialsBegin => {
+            Some(process_enumerate_credentials_begin(
+                persistent_store,
+                pin_protocol_v1,
+                stateful_command_permission,
+                stateful_command_type,
+                sub_command_params.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
+                now,
+            )?)
+        }
+        CredentialManagementSubCommand::EnumerateCredentialsGetNextCredential => {
+            Some(process_enumerate_credentials_get_next_credential(
+                persistent_store,
+                stateful_command_type,
+            )?)
+        }
+        CredentialManagementSubCommand::DeleteCredential => {
+            process_delete_credential(
+                persistent_store,
+                pin_protocol_v1,
+                sub_command_params.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
+            )?;
+            None
+        }
+        CredentialManagementSubCommand::UpdateUserInformation => {
+            process_update_user_information(
+                persistent_store,
+                pin_protocol_v1,
+                sub_command_params.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
+            )?;
+            None
+        }
+    };
+    Ok(ResponseData::AuthenticatorCredentialManagement(response))
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::data_formats::PublicKeyCredentialType;
+    use super::*;
+    use alloc::format;
+    use alloc::vec::Vec;
+    use crypto::hmac::hmac_256;
+    use crypto::rng256::{Rng256, ThreadRng256};
+
+    const CLOCK_FREQUENCY_HZ: usize = 32768;
+    const DUMMY_CLOCK_VALUE: ClockValue = ClockValue::new(0, CLOCK_FREQUENCY_HZ);
+    const PIN_UV_AUTH_TOKEN: [u8; 32] = [0x55; 32];
+
+    fn create_pin_protocol_v1(rng: &mut ThreadRng256) -> PinProtocolV1 {
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(rng);
+        PinProtocolV1::new_test(key_agreement_key, PIN_UV_AUTH_TOKEN)
+    }
+
+    fn create_credential_source(
+        rng: &mut ThreadRng256,
+        rp_id: &str,
+        user_handle: Vec<u8>,
+    ) -> PublicKeyCredentialSource {
+        PublicKeyCredentialSource {
+            key_type: PublicKeyCredentialType::PublicKey,
+            credential_id: rng.gen_uniform_u8x32().to_vec(),
+            private_key: crypto::ecdsa::SecKey::gensk(rng).into(),
+            rp_id: String::from(rp_id),
+            user_handle,
+            user_display_name: None,
+            cred_protect_policy: None,
+            creation_order: 0,
+            last_used_order: None,
+            signature_counter: None,
+            user_name: None,
+            user_icon: None,
+            large_blob_key: None,
+            cred_blob: None,
+        }
+    }
+
+    fn create_params(
+        sub_command: CredentialManagementSubCommand,
+        sub_command_params: Option<CredentialManagementSubCommandParams>,
+    ) -> AuthenticatorCredentialManagementParameters {
+        let pin_uv_auth_param = match sub_command {
+            CredentialManagementSubCommand::EnumerateRpsGetNextRp
+            | CredentialManagementSubCommand::EnumerateCredentialsGetNextCredential => None,
+            _ => {
+                let mut message = vec![sub_command as u8];
+                if let Some(sub_command_params) = sub_command_params.clone() {
+                    assert!(cbor::write(sub_command_params.into(), &mut message));
+                }
+                Some(hmac_256::<Sha256>(&PIN_UV_AUTH_TOKEN, &message)[..16].to_vec())
+            }
+        };
+        AuthenticatorCredentialManagementParameters {
+            sub_command,
+            sub_command_params,
+            pin_uv_auth_protocol: pin_uv_auth_param.as_ref().map(|_| 1),
+            pin_uv_auth_param,
+        }
+    }
+
+    fn rp_id_hash_params(rp_id: &str) -> Option<CredentialManagementSubCommandParams> {
+        Some(CredentialManagementSubCommandParams {
+            rp_id_hash: Some(Sha256::hash(rp_id.as_bytes()).to_vec()),
+            credential_id: None,
+            user: None,
+        })
+    }
+
+    struct TestState {
+        persistent_store: PersistentStore,
+        pin_protocol_v1: PinProtocolV1,
+        stateful_command_permission: TimedPermission,
+        stateful_command_type: Option<StatefulCommand>,
+    }
+
+    impl TestState {
+        fn new(rng: &mut ThreadRng256) -> TestState {
+            TestState {
+                persistent_store: PersistentStore::new(rng),
+                pin_protocol_v1: create_pin_protocol_v1(rng),
+                stateful_command_permission: TimedPermission::waiting(),
+                stateful_command_type: None,
+            }
+        }
+
+        fn process(
+            &mut self,
+            params: AuthenticatorCredentialManagementParameters,
+        ) -> Result<Option<AuthenticatorCredentialManagementResponse>, Ctap2StatusCode> {
+            match process_credential_management(
+                &mut self.persistent_store,
+                &mut self.pin_protocol_v1,
+                &mut self.stateful_command_permission,
+                &mut self.stateful_command_type,
+                params,
+                DUMMY_CLOCK_VALUE,
+            )? {
+                ResponseData::AuthenticatorCredentialManagement(response) => Ok(response),
+                _ => panic!("Invalid response type"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_process_get_creds_metadata() {
+        let mut rng = ThreadRng256 {};
+        let mut state = TestState::new(&mut rng);
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![0x01]);
+        state
+            .persistent_store
+            .store_credential(credential_source)
+            .unwrap();
+
+        let params = create_params(CredentialManagementSubCommand::GetCredsMetadata, None);
+        let response = state.process(params).unwrap().unwrap();
+        let remaining = state.persistent_store.remaining_credentials().unwrap() as u64;
+        assert_eq!(response.existing_resident_credentials_count, Some(1));
+        assert_eq!(
+            response.max_possible_remaining_resident_credentials_count,
+            Some(remaining)
+        );
+    }
+
+    #[test]
+    fn test_process_enumerate_rps() {
+        let mut rng = ThreadRng256 {};
+        let mut state = TestState::new(&mut rng);
+        for (rp_id, user_handle) in &[
+            ("example.com", 0x01),
+            ("another.example.com", 0x01),
+            ("example.com", 0x02),
+        ] {
+            let credential_source = create_credential_source(&mut rng, rp_id, vec![*user_handle]);
+            state
+                .persistent_store
+                .store_credential(credential_source)
+                .unwrap();
+        }
+
+        let params = create_params(CredentialManagementSubCommand::EnumerateRpsBegin, None);
+        let response = state.process(params).unwrap().unwrap();
+        assert_eq!(response.total_rps, Some(2));
+        assert_eq!(response.rp.unwrap().rp_id, "another.example.com");
+        assert_eq!(
+            response.rp_id_hash,
+            Some(Sha256::hash(b"another.example.com").to_vec())
+        );
+
+        let params = create_params(CredentialManagementSubCommand::EnumerateRpsGetNextRp, None);
+        let response = state.process(params).unwrap().unwrap();
+        assert_eq!(response.total_rps, None);
+        assert_eq!(response.rp.unwrap().rp_id, "example.com");
+
+        let params = create_params(CredentialManagementSubCommand::EnumerateRpsGetNextRp, None);
+        assert_eq!(
+            state.process(params),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+    }
+
+    #[test]
+    fn test_process_enumerate_rps_many_credentials() {
+        let mut rng = ThreadRng256 {};
+        let mut state = TestState::new(&mut rng);
+        const NUM_CREDENTIALS: usize = 120;
+        let mut expected_rp_ids = BTreeSet::new();
+        for i in 0..NUM_CREDENTIALS {
+            let rp_id = format!("rp{}.example.com", i % 40);
+            let credential_source = create_credential_source(&mut rng, &rp_id, vec![i as u8]);
+            state
+                .persistent_store
+                .store_credential(credential_source)
+                .unwrap();
+            expected_rp_ids.insert(rp_id);
+        }
+
+        let params = create_params(CredentialManagementSubCommand::EnumerateRpsBegin, None);
+        let response = state.process(params).unwrap().unwrap();
+        assert_eq!(response.total_rps, Some(expected_rp_ids.len() as u64));
+        let mut rp_ids = vec![response.rp.unwrap().rp_id];
+        for _ in 1..expected_rp_ids.len() {
+            let params = create_params(CredentialManagementSubCommand::EnumerateRpsGetNextRp, None);
+            let response = state.process(params).unwrap().unwrap();
+            rp_ids.push(response.rp.unwrap().rp_id);
+        }
+        assert_eq!(rp_ids, expected_rp_ids.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_process_enumerate_credentials() {
+        let mut rng = ThreadRng256 {};
+        let mut state = TestState::new(&mut rng);
+        let mut expected_credential_ids = Vec::new();
+        for (rp_id, user_handle) in &[
+            ("example.com", 0x01),
+            ("another.example.com", 0x01),
+            ("example.com", 0x02),
+        ] {
+            let credential_source = create_credential_source(&mut rng, rp_id, vec![*user_handle]);
+            if *rp_id == "example.com" {
+                expected_credential_ids.push(credential_source.credential_id.clone());
+            }
+            state
+                .persistent_store
+                .store_credential(credential_source)
+                .unwrap();
+        }
+
+        let params = create_params(
+            CredentialManagementSubCommand::EnumerateCredentialsBegin,
+            rp_id_hash_params("example.com"),
+        );
+        let response = state.process(params).unwrap().unwrap();
+        assert_eq!(response.total_credentials, Some(2));
+        assert!(response.public_key.is_some());
+        let mut credential_ids = vec![response.credential_id.unwrap().key_id];
+
+        let params = create_params(
+            CredentialManagementSubCommand::EnumerateCredentialsGetNextCredential,
+            None,
+        );
+        let response = state.process(params).unwrap().unwrap();
+        assert_eq!(response.total_credentials, None);
+        credential_ids.push(response.credential_id.unwrap().key_id);
+
+        credential_ids.sort();
+        expected_credential_ids.sort();
+        assert_eq!(credential_ids, expected_credential_ids);
+
+        let params = create_params(
+            CredentialManagementSubCommand::EnumerateCredentialsGetNextCredential,
+            None,
+        );
+        assert_eq!(
+            state.process(params),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+    }
+
+    #[test]
+    fn test_process_enumerate_credentials_orders() {
+        let mut rng = ThreadRng256 {};
+        let mut state = TestState::new(&mut rng);
+        let mut credential_source = create_credential_source(&mut rng, "example.com", vec![0x01]);
+        credential_source.creation_order = 3;
+        credential_source.last_used_order = Some(5);
+        state
+            .persistent_store
+            .store_credential(credential_source)
+            .unwrap();
+
+        let params = create_params(
+            CredentialManagementSubCommand::EnumerateCredentialsBegin,
+            rp_id_hash_params("example.com"),
+        );
+        let response = state.process(params).unwrap().unwrap();
+        assert_eq!(response.creation_order, Some(3));
+        assert_eq!(response.last_used_order, Some(5));
+    }
+
+    #[test]
+    fn test_process_enumerate_credentials_no_credentials() {
+        let mut rng = ThreadRng256 {};
+        let mut state = TestState::new(&mut rng);
+
+        let params = create_params(
+            CredentialManagementSubCommand::EnumerateCredentialsBegin,
+            rp_id_hash_params("example.com"),
+        );
+        assert_eq!(
+            state.process(params),
+            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)
+        );
+        let params = create_params(CredentialManagementSubCommand::EnumerateRpsBegin, None);
+        assert_eq!(
+            state.process(params),
+            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)
+        );
+    }
+
+    #[test]
+    fn test_process_delete_credential() {
+        let mut rng = ThreadRng256 {};
+        let mut state = TestState::new(&mut rng);
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![0x01]);
+        let credential_id = PublicKeyCredentialDescriptor {
+            key_type: PublicKeyCredentialType::PublicKey,
+            key_id: credential_source.credential_id.clone(),
+            transports: None,
+        };
+        state
+            .persistent_store
+            .store_credential(credential_source)
+            .unwrap();
+        let sub_command_params = Some(CredentialManagementSubCommandParams {
+            rp_id_hash: None,
+            credential_id: Some(credential_id),
+            user: None,
+        });
+
+        let params = create_params(
+            CredentialManagementSubCommand::DeleteCredential,
+            sub_command_params.clone(),
+        );
+        assert_eq!(state.process(params), Ok(None));
+        assert_eq!(state.persistent_store.count_credentials(), Ok(0));
+
+        let params = create_params(
+            CredentialManagementSubCommand::DeleteCredential,
+            sub_command_params,
+        );
+        assert_eq!(
+            state.process(params),
+            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)
+        );
+    }
+
+    #[test]
+    fn test_process_update_user_information() {
+        let mut rng = ThreadRng256 {};
+        let mut state = TestState::new(&mut rng);
+        let mut credential_source = create_credential_source(&mut rng, "example.com", vec![0x01]);
+        credential_source.user_name = Some(String::from("old name"));
+        let credential_id = PublicKeyCredentialDescriptor {
+            key_type: PublicKeyCredentialType::PublicKey,
+            key_id: credential_source.credential_id.clone(),
+            transports: None,
+        };
+        state
+            .persistent_store
+            .store_credential(credential_source)
+            .unwrap();
+        let user = PublicKeyCredentialUserEntity {
+            user_id: vec![0x01],
+            user_name: Some(String::from("new name")),
+            user_display_name: Some(String::from("New Name")),
+            user_icon: None,
+        };
+        let sub_command_params = Some(CredentialManagementSubCommandParams {
+            rp_id_hash: None,
+            credential_id: Some(credential_id.clone()),
+            user: Some(user),
+        });
+
+        let params = create_params(
+            CredentialManagementSubCommand::UpdateUserInformation,
+            sub_command_params,
+        );
+        assert_eq!(state.process(params), Ok(None));
+        let stored_credential = state
+            .persistent_store
+            .find_credential("example.com", &credential_id.key_id, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored_credential.user_name, Some(String::from("new name")));
+        assert_eq!(
+            stored_credential.user_display_name,
+            Some(String::from("New Name"))
+        );
+
+        let sub_command_params = Some(CredentialManagementSubCommandParams {
+            rp_id_hash: None,
+            credential_id: Some(credential_id),
+            user: None,
+        });
+        let params = create_params(
+            CredentialManagementSubCommand::UpdateUserInformation,
+            sub_command_params,
+        );
+        assert_eq!(
+            state.process(params),
+            Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn test_process_credential_management_invalid_pin_uv_auth_param() {
+        let mut rng = ThreadRng256 {};
+        let mut state = TestState::new(&mut rng);
+
+        let mut params = create_params(CredentialManagementSubCommand::GetCredsMetadata, None);
+        params.pin_uv_auth_param = Some(vec![0x88; 16]);
+        assert_eq!(
+            state.process(params),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+
+        let mut params = create_params(CredentialManagementSubCommand::GetCredsMetadata, None);
+        params.pin_uv_auth_param = None;
+        assert_eq!(
+            state.process(params),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)
+        );
+    }
+}
@@ -0,0 +1,277 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::status_code::Ctap2StatusCode;
+use alloc::vec::Vec;
+use crypto::ecdsa;
+use crypto::sha256::Sha256;
+use crypto::Hash256;
+
+/// Interface of a secure element, which generates and uses keys that never leave it.
+///
+/// When the backend holds an attestation key, it signs batch attestations instead of the
+/// attestation private key of the persistent store.
+pub trait CryptoBackend {
+    /// Returns whether the backend holds an attestation key.
+    fn has_attestation_key(&self) -> bool;
+
+    /// Generates a new P-256 attestation key, replacing the previous one.
+    ///
+    /// The public key is returned so that the attestation certificate can be issued.
+    fn generate_attestation_key(&mut self) -> Result<ecdsa::PubKey, Ctap2StatusCode>;
+
+    /// Signs the SHA-256 digest of a message with the attestation key.
+    fn sign_attestation_digest(
+        &mut self,
+        digest: &[u8; 32],
+    ) -> Result<ecdsa::Signature, Ctap2StatusCode>;
+}
+
+// Signs the concatenation of the message parts with the attestation key of the backend, and
+// encodes the signature in ASN.1 DER.
+pub fn sign_attestation(
+    crypto_backend: &mut impl CryptoBackend,
+    message_parts: &[&[u8]],
+) -> Result<Vec<u8>, Ctap2StatusCode> {
+    Ok(crypto_backend
+        .sign_attestation_digest(&sha256(message_parts))?
+        .to_asn1_der())
+}
+
+// Returns whether the SoC has a SHA-256 engine that the kernel gives access to.
+#[cfg(feature = "with_ctap2_1")]
+pub fn has_sha256_engine() -> bool {
+    sha_engine::is_available()
+}
+
+// Hashes the concatenation of the message parts with SHA-256. The hash engine of the SoC computes
+// it if available, and the software implementation otherwise.
+pub fn sha256(message_parts: &[&[u8]]) -> [u8; 32] {
+    if let Some(digest) = sha_engine::sha256(message_parts) {
+        return digest;
+    }
+    let mut hasher = Sha256::new();
+    for part in message_parts {
+        hasher.update(part);
+    }
+    hasher.finalize()
+}
+
+#[cfg(not(feature = "std"))]
+mod sha_engine {
+    use libtock_drivers::sha;
+
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn is_available() -> bool {
+        sha::is_available()
+    }
+
+    pub fn sha256(message_parts: &[&[u8]]) -> Option<[u8; 32]> {
+        let mut digest = [0; 32];
+        if sha::sha256(message_parts, &mut digest) {
+            Some(digest)
+        } else {
+            None
+        }
+    }
+}
+
+// Desktop builds have no kernel, and therefore no hash engine.
+#[cfg(feature = "std")]
+mod sha_engine {
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn is_available() -> bool {
+        false
+    }
+
+    pub fn sha256(_message_parts: &[&[u8]]) -> Option<[u8; 32]> {
+        None
+    }
+}
+
+#[cfg(all(not(feature = "std"), feature = "with_secure_element"))]
+mod prod {
+    use super::CryptoBackend;
+    use crate::ctap::status_code::Ctap2StatusCode;
+    use arrayref::array_ref;
+    use crypto::ecdsa;
+    use libtock_drivers::secure_element;
+
+    // The slot of the secure element that holds the attestation key.
+    const ATTESTATION_KEY_SLOT: usize = 0;
+
+    /// The secure element driver of the kernel.
+    pub struct TockSecureElement {}
+
+    impl TockSecureElement {
+        pub fn new() -> TockSecureElement {
+            TockSecureElement {}
+        }
+    }
+
+    impl CryptoBackend for TockSecureElement {
+        fn has_attestation_key(&self) -> bool {
+            let mut public_key = [0; 64];
+            secure_element::is_available()
+                && secure_element::public_key(ATTESTATION_KEY_SLOT, &mut public_key)
+        }
+
+        fn generate_attestation_key(&mut self) -> Result<ecdsa::PubKey, Ctap2StatusCode> {
+            let mut public_key = [0; 64];
+            if !secure_element::generate_key(ATTESTATION_KEY_SLOT, &mut public_key) {
+                return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE);
+            }
+            ecdsa::PubKey::from_coordinates(
+                array_ref![public_key, 0, 32],
+                array_ref![public_key, 32, 32],
+            )
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)
+        }
+
+        fn sign_attestation_digest(
+            &mut self,
+            digest: &[u8; 32],
+        ) -> Result<ecdsa::Signature, Ctap2StatusCode> {
+            let mut signature = [0; 64];
+            if !secure_element::sign(ATTESTATION_KEY_SLOT, digest, &mut signature) {
+                return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE);
+            }
+            ecdsa::Signature::from_bytes(&signature)
+                .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)
+        }
+    }
+}
+#[cfg(all(not(feature = "std"), feature = "with_secure_element"))]
+pub use self::prod::TockSecureElement as DefaultCryptoBackend;
+
+// Backend of boards without secure element, where the persistent store holds the keys.
+#[cfg(all(not(feature = "std"), not(feature = "with_secure_element")))]
+mod missing {
+    use super::CryptoBackend;
+    use crate::ctap::status_code::Ctap2StatusCode;
+    use crypto::ecdsa;
+
+    pub struct MissingSecureElement {}
+
+    impl MissingSecureElement {
+        pub fn new() -> MissingSecureElement {
+            MissingSecureElement {}
+        }
+    }
+
+    impl CryptoBackend for MissingSecureElement {
+        fn has_attestation_key(&self) -> bool {
+            false
+        }
+
+        fn generate_attestation_key(&mut self) -> Result<ecdsa::PubKey, Ctap2StatusCode> {
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND)
+        }
+
+        fn sign_attestation_digest(
+            &mut self,
+            _: &[u8; 32],
+        ) -> Result<ecdsa::Signature, Ctap2StatusCode> {
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND)
+        }
+    }
+}
+#[cfg(all(not(feature = "std"), not(feature = "with_secure_element")))]
+pub use self::missing::MissingSecureElement as DefaultCryptoBackend;
+
+// Secure element for testing, which keeps its key in memory.
+#[cfg(feature = "std")]
+mod emulated {
+    use super::CryptoBackend;
+    use crate::ctap::status_code::Ctap2StatusCode;
+    use crypto::ecdsa;
+    use crypto::rng256::ThreadRng256;
+    use crypto::sha256::Sha256;
+
+    pub struct EmulatedSecureElement {
+        attestation_key: Option<ecdsa::SecKey>,
+    }
+
+    impl EmulatedSecureElement {
+        pub fn new() -> EmulatedSecureElement {
+            EmulatedSecureElement {
+                attestation_key: None,
+            }
+        }
+    }
+
+    impl CryptoBackend for EmulatedSecureElement {
+        fn has_attestation_key(&self) -> bool {
+            self.attestation_key.is_some()
+        }
+
+        fn generate_attestation_key(&mut self) -> Result<ecdsa::PubKey, Ctap2StatusCode> {
+            let attestation_key = ecdsa::SecKey::gensk(&mut ThreadRng256 {});
+            let public_key = attestation_key.genpk();
+            self.attestation_key = Some(attestation_key);
+            Ok(public_key)
+        }
+
+        fn sign_attestation_digest(
+            &mut self,
+            digest: &[u8; 32],
+        ) -> Result<ecdsa::Signature, Ctap2StatusCode> {
+            let attestation_key = self
+                .attestation_key
+                .as_ref()
+                .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)?;
+            Ok(attestation_key.sign_hedged_digest::<Sha256, _>(digest, &mut ThreadRng256 {}))
+        }
+    }
+}
+#[cfg(feature = "std")]
+pub use self::emulated::EmulatedSecureElement as DefaultCryptoBackend;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sha256_software_fallback() {
+        #[cfg(feature = "with_ctap2_1")]
+        assert!(!has_sha256_engine());
+        assert_eq!(sha256(&[b"mes", b"", b"sage"]), Sha256::hash(b"message"));
+        assert_eq!(sha256(&[]), Sha256::hash(b""));
+    }
+
+    #[test]
+    fn test_sign_attestation() {
+        let mut crypto_backend = DefaultCryptoBackend::new();
+        assert!(!crypto_backend.has_attestation_key());
+        assert_eq!(
+            sign_attestation(&mut crypto_backend, &[b"message"]),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)
+        );
+
+        let public_key = crypto_backend.generate_attestation_key().unwrap();
+        assert!(crypto_backend.has_attestation_key());
+        let signature = crypto_backend
+            .sign_attestation_digest(&Sha256::hash(b"message"))
+            .unwrap();
+        assert!(public_key.verify_vartime::<Sha256>(b"message", &signature));
+
+        // A new key replaces the previous one.
+        let new_public_key = crypto_backend.generate_attestation_key().unwrap();
+        let signature = crypto_backend
+            .sign_attestation_digest(&Sha256::hash(b"message"))
+            .unwrap();
+        assert!(new_public_key.verify_vartime::<Sha256>(b"message", &signature));
+        assert!(!public_key.verify_vartime::<Sha256>(b"message", &signature));
+    }
+}
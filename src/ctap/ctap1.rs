@@ -13,14 +13,17 @@
 // limitations under the License.
 
 use super::apdu::{ApduStatusCode, APDU};
+use super::crypto_backend::{sign_attestation, CryptoBackend};
+use super::data_formats::{PrivateKey, SignatureAlgorithm};
 use super::hid::ChannelID;
 use super::status_code::Ctap2StatusCode;
-use super::CtapState;
+use super::{CtapState, UserPresenceConfig};
 use alloc::vec::Vec;
 use arrayref::array_ref;
 use core::convert::Into;
 use core::convert::TryFrom;
 use crypto::rng256::Rng256;
+use crypto::zeroize::Zeroize;
 use libtock_drivers::timer::ClockValue;
 
 // For now, they're the same thing with apdu.rs containing the authoritative definition
@@ -188,8 +191,19 @@ impl Ctap1Command {
     ) -> Result<Vec<u8>, Ctap1StatusCode>
     where
         R: Rng256,
-        CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
+        CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
     {
+        // The crypto can't be trusted after a failed self-test.
+        if ctap_state.self_test_failure().is_some() {
+            return Err(Ctap1StatusCode::SW_INTERNAL_EXCEPTION);
+        }
+        // U2F can't verify the user, so it is disabled while alwaysUv is enabled.
+        if ctap_state
+            .has_always_uv()
+            .map_err(|_| Ctap1StatusCode::SW_INTERNAL_EXCEPTION)?
+        {
+            return Err(Ctap1StatusCode::SW_INS_INVALID);
+        }
         let command = U2fCommand::try_from(message)?;
         match command {
             U2fCommand::Register {
@@ -249,12 +263,12 @@ impl Ctap1Command {
     ) -> Result<Vec<u8>, Ctap1StatusCode>
     where
         R: Rng256,
-        CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
+        CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
     {
         let sk = crypto::ecdsa::SecKey::gensk(ctap_state.rng);
         let pk = sk.genpk();
         let key_handle = ctap_state
-            .encrypt_key_handle(sk, &application)
+            .encrypt_key_handle(sk.into(), &application, None)
             .map_err(|_| Ctap1StatusCode::SW_INTERNAL_EXCEPTION)?;
         if key_handle.len() > 0xFF {
             // This is just being defensive with unreachable code.
@@ -266,11 +280,6 @@ impl Ctap1Command {
             .attestation_certificate()
             .map_err(|_| Ctap1StatusCode::SW_MEMERR)?
             .ok_or(Ctap1StatusCode::SW_INTERNAL_EXCEPTION)?;
-        let private_key = ctap_state
-            .persistent_store
-            .attestation_private_key()
-            .map_err(|_| Ctap1StatusCode::SW_INTERNAL_EXCEPTION)?
-            .ok_or(Ctap1StatusCode::SW_INTERNAL_EXCEPTION)?;
 
         let mut response = Vec::with_capacity(105 + key_handle.len() + certificate.len());
         response.push(Ctap1Command::LEGACY_BYTE);
@@ -288,10 +297,25 @@ impl Ctap1Command {
         signature_data.extend(key_handle);
         signature_data.extend_from_slice(&user_pk);
 
+        // The attestation key of the crypto backend takes precedence, as for CTAP2.
+        if ctap_state.crypto_backend.has_attestation_key() {
+            let signature = sign_attestation(&mut ctap_state.crypto_backend, &[&signature_data])
+                .map_err(|_| Ctap1StatusCode::SW_INTERNAL_EXCEPTION)?;
+            response.extend(signature);
+            return Ok(response);
+        }
+        let mut private_key = ctap_state
+            .persistent_store
+            .attestation_private_key()
+            .map_err(|_| Ctap1StatusCode::SW_INTERNAL_EXCEPTION)?
+            .ok_or(Ctap1StatusCode::SW_INTERNAL_EXCEPTION)?;
         let attestation_key = crypto::ecdsa::SecKey::from_bytes(&private_key).unwrap();
-        let signature = attestation_key.sign_rfc6979::<crypto::sha256::Sha256>(&signature_data);
+        private_key.zeroize();
+        let signature = PrivateKey::from(attestation_key)
+            .sign_and_encode(ctap_state.rng, &[&signature_data])
+            .map_err(|_| Ctap1StatusCode::SW_INTERNAL_EXCEPTION)?;
 
-        response.extend(signature.to_asn1_der());
+        response.extend(signature);
         Ok(response)
     }
 
@@ -318,28 +342,36 @@ impl Ctap1Command {
     ) -> Result<Vec<u8>, Ctap1StatusCode>
     where
         R: Rng256,
-        CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
+        CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
     {
         let credential_source = ctap_state
             .decrypt_credential_source(key_handle, &application)
-            .map_err(|_| Ctap1StatusCode::SW_WRONG_DATA)?;
+            .map_err(|_| Ctap1StatusCode::SW_WRONG_DATA)?
+            // U2F has no user verification, so these key handles are unusable here.
+            .filter(|credential| !credential.requires_user_verification())
+            // U2F only defines ECDSA signatures.
+            .filter(|credential| {
+                credential.private_key.signature_algorithm() == SignatureAlgorithm::ES256
+            });
         if let Some(credential_source) = credential_source {
             if flags == Ctap1Flags::CheckOnly {
                 return Err(Ctap1StatusCode::SW_COND_USE_NOT_SATISFIED);
             }
-            ctap_state
-                .increment_global_signature_counter()
+            let signature_counter = ctap_state
+                .increment_signature_counter(&credential_source.credential_id)
                 .map_err(|_| Ctap1StatusCode::SW_WRONG_DATA)?;
-            let mut signature_data = ctap_state
-                .generate_auth_data(&application, Ctap1Command::USER_PRESENCE_INDICATOR_BYTE)
-                .map_err(|_| Ctap1StatusCode::SW_WRONG_DATA)?;
-            signature_data.extend(&challenge);
+            let auth_data = ctap_state.generate_auth_data(
+                &application,
+                Ctap1Command::USER_PRESENCE_INDICATOR_BYTE,
+                signature_counter,
+            );
             let signature = credential_source
                 .private_key
-                .sign_rfc6979::<crypto::sha256::Sha256>(&signature_data);
+                .sign_and_encode(ctap_state.rng, &[&auth_data, &challenge])
+                .map_err(|_| Ctap1StatusCode::SW_INTERNAL_EXCEPTION)?;
 
-            let mut response = signature_data[application.len()..application.len() + 5].to_vec();
-            response.extend(signature.to_asn1_der());
+            let mut response = auth_data[application.len()..application.len() + 5].to_vec();
+            response.extend(signature);
             Ok(response)
         } else {
             Err(Ctap1StatusCode::SW_WRONG_DATA)
@@ -349,7 +381,8 @@ impl Ctap1Command {
 
 #[cfg(test)]
 mod test {
-    use super::super::{key_material, CREDENTIAL_ID_SIZE, USE_SIGNATURE_COUNTER};
+    use super::super::self_test::SelfTestFailure;
+    use super::super::{key_material, CREDENTIAL_ID_SIZE, USE_GLOBAL_COUNTER_FOR_NON_RESIDENT};
     use super::*;
     use crypto::rng256::ThreadRng256;
     use crypto::Hash256;
@@ -399,10 +432,26 @@ mod test {
         message
     }
 
+    #[test]
+    fn test_process_command_after_self_test_failure() {
+        let mut rng = ThreadRng256 {};
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
+        let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
+
+        let message = create_register_message(&[0x0A; 32]);
+        ctap_state.self_test_failure = Some(SelfTestFailure::RngHealth);
+        let response = Ctap1Command::process_command(&message, &mut ctap_state, START_CLOCK_VALUE);
+        assert_eq!(response, Err(Ctap1StatusCode::SW_INTERNAL_EXCEPTION));
+
+        ctap_state.self_test_failure = None;
+        let response = Ctap1Command::process_command(&message, &mut ctap_state, START_CLOCK_VALUE);
+        assert_eq!(response, Err(Ctap1StatusCode::SW_COND_USE_NOT_SATISFIED));
+    }
+
     #[test]
     fn test_process_register() {
         let mut rng = ThreadRng256 {};
-        let dummy_user_presence = |_| panic!("Unexpected user presence check in CTAP1");
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
         let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
 
         let application = [0x0A; 32];
@@ -446,10 +495,26 @@ mod test {
         );
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_register_always_uv() {
+        let mut rng = ThreadRng256 {};
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
+        let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
+        ctap_state.persistent_store.toggle_always_uv().unwrap();
+
+        let application = [0x0A; 32];
+        let message = create_register_message(&application);
+        ctap_state.u2f_up_state.consume_up(START_CLOCK_VALUE);
+        ctap_state.u2f_up_state.grant_up(START_CLOCK_VALUE);
+        let response = Ctap1Command::process_command(&message, &mut ctap_state, START_CLOCK_VALUE);
+        assert_eq!(response, Err(Ctap1StatusCode::SW_INS_INVALID));
+    }
+
     #[test]
     fn test_process_register_bad_message() {
         let mut rng = ThreadRng256 {};
-        let dummy_user_presence = |_| panic!("Unexpected user presence check in CTAP1");
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
         let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
 
         let application = [0x0A; 32];
@@ -469,7 +534,7 @@ mod test {
         let message = create_register_message(&application);
 
         let mut rng = ThreadRng256 {};
-        let dummy_user_presence = |_| panic!("Unexpected user presence check in CTAP1");
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
         let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
 
         ctap_state.u2f_up_state.consume_up(START_CLOCK_VALUE);
@@ -482,13 +547,15 @@ mod test {
     #[test]
     fn test_process_authenticate_check_only() {
         let mut rng = ThreadRng256 {};
-        let dummy_user_presence = |_| panic!("Unexpected user presence check in CTAP1");
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
         let sk = crypto::ecdsa::SecKey::gensk(&mut rng);
         let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
 
         let rp_id = "example.com";
         let application = crypto::sha256::Sha256::hash(rp_id.as_bytes());
-        let key_handle = ctap_state.encrypt_key_handle(sk, &application).unwrap();
+        let key_handle = ctap_state
+            .encrypt_key_handle(sk.into(), &application, None)
+            .unwrap();
         let message = create_authenticate_message(&application, Ctap1Flags::CheckOnly, &key_handle);
 
         let response = Ctap1Command::process_command(&message, &mut ctap_state, START_CLOCK_VALUE);
@@ -498,13 +565,15 @@ mod test {
     #[test]
     fn test_process_authenticate_check_only_wrong_rp() {
         let mut rng = ThreadRng256 {};
-        let dummy_user_presence = |_| panic!("Unexpected user presence check in CTAP1");
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
         let sk = crypto::ecdsa::SecKey::gensk(&mut rng);
         let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
 
         let rp_id = "example.com";
         let application = crypto::sha256::Sha256::hash(rp_id.as_bytes());
-        let key_handle = ctap_state.encrypt_key_handle(sk, &application).unwrap();
+        let key_handle = ctap_state
+            .encrypt_key_handle(sk.into(), &application, None)
+            .unwrap();
         let application = [0x55; 32];
         let message = create_authenticate_message(&application, Ctap1Flags::CheckOnly, &key_handle);
 
@@ -512,16 +581,36 @@ mod test {
         assert_eq!(response, Err(Ctap1StatusCode::SW_WRONG_DATA));
     }
 
+    #[test]
+    fn test_process_authenticate_eddsa_key_handle() {
+        let mut rng = ThreadRng256 {};
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
+        let sk = PrivateKey::new(&mut rng, SignatureAlgorithm::EDDSA).unwrap();
+        let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
+
+        let rp_id = "example.com";
+        let application = crypto::sha256::Sha256::hash(rp_id.as_bytes());
+        let key_handle = ctap_state
+            .encrypt_key_handle(sk, &application, None)
+            .unwrap();
+        let message = create_authenticate_message(&application, Ctap1Flags::CheckOnly, &key_handle);
+
+        let response = Ctap1Command::process_command(&message, &mut ctap_state, START_CLOCK_VALUE);
+        assert_eq!(response, Err(Ctap1StatusCode::SW_WRONG_DATA));
+    }
+
     #[test]
     fn test_process_authenticate_check_only_wrong_length() {
         let mut rng = ThreadRng256 {};
-        let dummy_user_presence = |_| panic!("Unexpected user presence check in CTAP1");
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
         let sk = crypto::ecdsa::SecKey::gensk(&mut rng);
         let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
 
         let rp_id = "example.com";
         let application = crypto::sha256::Sha256::hash(rp_id.as_bytes());
-        let key_handle = ctap_state.encrypt_key_handle(sk, &application).unwrap();
+        let key_handle = ctap_state
+            .encrypt_key_handle(sk.into(), &application, None)
+            .unwrap();
         let mut message = create_authenticate_message(
             &application,
             Ctap1Flags::DontEnforceUpAndSign,
@@ -548,13 +637,15 @@ mod test {
     #[test]
     fn test_process_authenticate_check_only_wrong_cla() {
         let mut rng = ThreadRng256 {};
-        let dummy_user_presence = |_| panic!("Unexpected user presence check in CTAP1");
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
         let sk = crypto::ecdsa::SecKey::gensk(&mut rng);
         let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
 
         let rp_id = "example.com";
         let application = crypto::sha256::Sha256::hash(rp_id.as_bytes());
-        let key_handle = ctap_state.encrypt_key_handle(sk, &application).unwrap();
+        let key_handle = ctap_state
+            .encrypt_key_handle(sk.into(), &application, None)
+            .unwrap();
         let mut message =
             create_authenticate_message(&application, Ctap1Flags::CheckOnly, &key_handle);
         message[0] = 0xEE;
@@ -566,13 +657,15 @@ mod test {
     #[test]
     fn test_process_authenticate_check_only_wrong_ins() {
         let mut rng = ThreadRng256 {};
-        let dummy_user_presence = |_| panic!("Unexpected user presence check in CTAP1");
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
         let sk = crypto::ecdsa::SecKey::gensk(&mut rng);
         let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
 
         let rp_id = "example.com";
         let application = crypto::sha256::Sha256::hash(rp_id.as_bytes());
-        let key_handle = ctap_state.encrypt_key_handle(sk, &application).unwrap();
+        let key_handle = ctap_state
+            .encrypt_key_handle(sk.into(), &application, None)
+            .unwrap();
         let mut message =
             create_authenticate_message(&application, Ctap1Flags::CheckOnly, &key_handle);
         message[1] = 0xEE;
@@ -584,13 +677,15 @@ mod test {
     #[test]
     fn test_process_authenticate_check_only_wrong_flags() {
         let mut rng = ThreadRng256 {};
-        let dummy_user_presence = |_| panic!("Unexpected user presence check in CTAP1");
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
         let sk = crypto::ecdsa::SecKey::gensk(&mut rng);
         let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
 
         let rp_id = "example.com";
         let application = crypto::sha256::Sha256::hash(rp_id.as_bytes());
-        let key_handle = ctap_state.encrypt_key_handle(sk, &application).unwrap();
+        let key_handle = ctap_state
+            .encrypt_key_handle(sk.into(), &application, None)
+            .unwrap();
         let mut message =
             create_authenticate_message(&application, Ctap1Flags::CheckOnly, &key_handle);
         message[2] = 0xEE;
@@ -600,7 +695,7 @@ mod test {
     }
 
     fn check_signature_counter(response: &[u8; 4], signature_counter: u32) {
-        if USE_SIGNATURE_COUNTER {
+        if USE_GLOBAL_COUNTER_FOR_NON_RESIDENT {
             assert_eq!(u32::from_be_bytes(*response), signature_counter);
         } else {
             assert_eq!(response, &[0x00, 0x00, 0x00, 0x00]);
@@ -610,13 +705,15 @@ mod test {
     #[test]
     fn test_process_authenticate_enforce() {
         let mut rng = ThreadRng256 {};
-        let dummy_user_presence = |_| panic!("Unexpected user presence check in CTAP1");
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
         let sk = crypto::ecdsa::SecKey::gensk(&mut rng);
         let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
 
         let rp_id = "example.com";
         let application = crypto::sha256::Sha256::hash(rp_id.as_bytes());
-        let key_handle = ctap_state.encrypt_key_handle(sk, &application).unwrap();
+        let key_handle = ctap_state
+            .encrypt_key_handle(sk.into(), &application, None)
+            .unwrap();
         let message =
             create_authenticate_message(&application, Ctap1Flags::EnforceUpAndSign, &key_handle);
 
@@ -637,13 +734,15 @@ mod test {
     #[test]
     fn test_process_authenticate_dont_enforce() {
         let mut rng = ThreadRng256 {};
-        let dummy_user_presence = |_| panic!("Unexpected user presence check in CTAP1");
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
         let sk = crypto::ecdsa::SecKey::gensk(&mut rng);
         let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
 
         let rp_id = "example.com";
         let application = crypto::sha256::Sha256::hash(rp_id.as_bytes());
-        let key_handle = ctap_state.encrypt_key_handle(sk, &application).unwrap();
+        let key_handle = ctap_state
+            .encrypt_key_handle(sk.into(), &application, None)
+            .unwrap();
         let message = create_authenticate_message(
             &application,
             Ctap1Flags::DontEnforceUpAndSign,
@@ -670,7 +769,7 @@ mod test {
             create_authenticate_message(&application, Ctap1Flags::EnforceUpAndSign, &key_handle);
 
         let mut rng = ThreadRng256 {};
-        let dummy_user_presence = |_| panic!("Unexpected user presence check in CTAP1");
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
         let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
 
         ctap_state.u2f_up_state.consume_up(START_CLOCK_VALUE);
@@ -687,7 +786,7 @@ mod test {
             create_authenticate_message(&application, Ctap1Flags::EnforceUpAndSign, &key_handle);
 
         let mut rng = ThreadRng256 {};
-        let dummy_user_presence = |_| panic!("Unexpected user presence check in CTAP1");
+        let dummy_user_presence = |_, _| panic!("Unexpected user presence check in CTAP1");
         let mut ctap_state = CtapState::new(&mut rng, dummy_user_presence, START_CLOCK_VALUE);
 
         ctap_state.u2f_up_state.consume_up(START_CLOCK_VALUE);
@@ -12,14 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::cose::CoseKey;
+use super::crypto_backend;
+use super::extensions::find_extension;
 use super::status_code::Ctap2StatusCode;
 use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::vec::Vec;
 use arrayref::array_ref;
-use cbor::{cbor_array_vec, cbor_bytes_lit, cbor_map_options, destructure_cbor_map};
+use cbor::{cbor_array, cbor_array_vec, cbor_bytes, cbor_map_options, destructure_cbor_map};
 use core::convert::TryFrom;
-use crypto::{ecdh, ecdsa};
+use crypto::rng256::Rng256;
+use crypto::sha256::Sha256;
+use crypto::sha512::Sha384;
+use crypto::zeroize::Zeroize;
+use crypto::{ecdsa, ecdsa_p384, ed25519};
 #[cfg(test)]
 use enum_iterator::IntoEnumIterator;
 
@@ -55,8 +62,19 @@ impl TryFrom<cbor::Value> for PublicKeyCredentialRpEntity {
     }
 }
 
+impl From<PublicKeyCredentialRpEntity> for cbor::Value {
+    fn from(entity: PublicKeyCredentialRpEntity) -> Self {
+        cbor_map_options! {
+            "id" => entity.rp_id,
+            "icon" => entity.rp_icon,
+            "name" => entity.rp_name,
+        }
+    }
+}
+
 // https://www.w3.org/TR/webauthn/#dictdef-publickeycredentialuserentity
-#[cfg_attr(any(test, feature = "debug_ctap"), derive(Clone, Debug, PartialEq))]
+#[derive(Clone)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
 pub struct PublicKeyCredentialUserEntity {
     pub user_id: Vec<u8>,
     pub user_name: Option<String>,
@@ -170,7 +188,8 @@ impl From<PublicKeyCredentialParameter> for cbor::Value {
 }
 
 // https://www.w3.org/TR/webauthn/#enumdef-authenticatortransport
-#[cfg_attr(any(test, feature = "debug_ctap"), derive(Clone, Debug, PartialEq))]
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
 #[cfg_attr(test, derive(IntoEnumIterator))]
 pub enum AuthenticatorTransport {
     Usb,
@@ -207,7 +226,8 @@ impl TryFrom<cbor::Value> for AuthenticatorTransport {
 }
 
 // https://www.w3.org/TR/webauthn/#dictdef-publickeycredentialdescriptor
-#[cfg_attr(any(test, feature = "debug_ctap"), derive(Clone, Debug, PartialEq))]
+#[derive(Clone)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
 pub struct PublicKeyCredentialDescriptor {
     pub key_type: PublicKeyCredentialType,
     pub key_id: Vec<u8>,
@@ -258,53 +278,109 @@ impl From<PublicKeyCredentialDescriptor> for cbor::Value {
     }
 }
 
+// A list of credential descriptors, i.e. an allowList or an excludeList. Relying parties may send
+// long lists, so entries stay encoded until they are iterated. The iterator decodes one descriptor
+// at a time, and callers can stop at the first match.
+#[derive(Clone)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct PublicKeyCredentialDescriptorList {
+    entries: Vec<cbor::Value>,
+}
+
+impl PublicKeyCredentialDescriptorList {
+    // Keeps at most max_len entries of the list, each of which must be a map.
+    pub fn from_cbor(
+        cbor_value: cbor::Value,
+        max_len: Option<usize>,
+    ) -> Result<Self, Ctap2StatusCode> {
+        let mut entries = extract_array(cbor_value)?;
+        if let Some(max_len) = max_len {
+            entries.truncate(max_len);
+        }
+        for entry in &entries {
+            match entry {
+                cbor::Value::Map(_) => (),
+                _ => return Err(Ctap2StatusCode::CTAP2_ERR_CBOR_UNEXPECTED_TYPE),
+            }
+        }
+        Ok(Self { entries })
+    }
+}
+
+impl From<Vec<PublicKeyCredentialDescriptor>> for PublicKeyCredentialDescriptorList {
+    fn from(descriptors: Vec<PublicKeyCredentialDescriptor>) -> Self {
+        let entries = descriptors.into_iter().map(cbor::Value::from).collect();
+        Self { entries }
+    }
+}
+
+impl IntoIterator for PublicKeyCredentialDescriptorList {
+    type Item = Result<PublicKeyCredentialDescriptor, Ctap2StatusCode>;
+    type IntoIter = core::iter::Map<
+        alloc::vec::IntoIter<cbor::Value>,
+        fn(cbor::Value) -> Result<PublicKeyCredentialDescriptor, Ctap2StatusCode>,
+    >;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries
+            .into_iter()
+            .map(PublicKeyCredentialDescriptor::try_from as fn(_) -> _)
+    }
+}
+
+#[derive(Default)]
 #[cfg_attr(any(test, feature = "debug_ctap"), derive(Clone, Debug, PartialEq))]
 pub struct MakeCredentialExtensions {
     pub hmac_secret: bool,
     pub cred_protect: Option<CredentialProtectionPolicy>,
+    pub uvm: bool,
+    #[cfg(feature = "with_ctap2_1")]
+    pub cred_blob: Option<Vec<u8>>,
+    #[cfg(feature = "with_ctap2_1")]
+    pub large_blob_key: Option<bool>,
+    #[cfg(feature = "with_ctap2_1")]
+    pub min_pin_length: bool,
+    #[cfg(feature = "with_ctap2_1")]
+    pub hmac_secret_mc: Option<GetAssertionHmacSecretInput>,
 }
 
+// Each supported extension parses its own input. Unknown extensions are ignored.
 impl TryFrom<cbor::Value> for MakeCredentialExtensions {
     type Error = Ctap2StatusCode;
 
     fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
-        destructure_cbor_map! {
-            let {
-                "credProtect" => cred_protect,
-                "hmac-secret" => hmac_secret,
-            } = extract_map(cbor_value)?;
+        let mut extensions = MakeCredentialExtensions::default();
+        for (key, value) in extract_map(cbor_value)? {
+            if let Some(extension) = find_extension(&key) {
+                extension.parse_make_credential_input(value, &mut extensions)?;
+            }
         }
-
-        let hmac_secret = hmac_secret.map_or(Ok(false), extract_bool)?;
-        let cred_protect = cred_protect
-            .map(CredentialProtectionPolicy::try_from)
-            .transpose()?;
-        Ok(Self {
-            hmac_secret,
-            cred_protect,
-        })
+        Ok(extensions)
     }
 }
 
-#[cfg_attr(any(test, feature = "debug_ctap"), derive(Clone, Debug, PartialEq))]
+#[derive(Clone, Default)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
 pub struct GetAssertionExtensions {
     pub hmac_secret: Option<GetAssertionHmacSecretInput>,
+    pub uvm: bool,
+    #[cfg(feature = "with_ctap2_1")]
+    pub cred_blob: bool,
+    #[cfg(feature = "with_ctap2_1")]
+    pub large_blob_key: Option<bool>,
 }
 
 impl TryFrom<cbor::Value> for GetAssertionExtensions {
     type Error = Ctap2StatusCode;
 
     fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
-        destructure_cbor_map! {
-            let {
-                "hmac-secret" => hmac_secret,
-            } = extract_map(cbor_value)?;
+        let mut extensions = GetAssertionExtensions::default();
+        for (key, value) in extract_map(cbor_value)? {
+            if let Some(extension) = find_extension(&key) {
+                extension.parse_get_assertion_input(value, &mut extensions)?;
+            }
         }
-
-        let hmac_secret = hmac_secret
-            .map(GetAssertionHmacSecretInput::try_from)
-            .transpose()?;
-        Ok(Self { hmac_secret })
+        Ok(extensions)
     }
 }
 
@@ -314,12 +390,15 @@ pub struct GetAssertionHmacSecretInput {
     pub key_agreement: CoseKey,
     pub salt_enc: Vec<u8>,
     pub salt_auth: Vec<u8>,
+    #[cfg(feature = "with_ctap2_1")]
+    pub pin_uv_auth_protocol: Option<u64>,
 }
 
 impl TryFrom<cbor::Value> for GetAssertionHmacSecretInput {
     type Error = Ctap2StatusCode;
 
     fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        #[cfg(not(feature = "with_ctap2_1"))]
         destructure_cbor_map! {
             let {
                 1 => cose_key,
@@ -327,14 +406,27 @@ impl TryFrom<cbor::Value> for GetAssertionHmacSecretInput {
                 3 => salt_auth,
             } = extract_map(cbor_value)?;
         }
+        #[cfg(feature = "with_ctap2_1")]
+        destructure_cbor_map! {
+            let {
+                1 => cose_key,
+                2 => salt_enc,
+                3 => salt_auth,
+                4 => pin_uv_auth_protocol,
+            } = extract_map(cbor_value)?;
+        }
 
         let cose_key = extract_map(ok_or_missing(cose_key)?)?;
         let salt_enc = extract_byte_string(ok_or_missing(salt_enc)?)?;
         let salt_auth = extract_byte_string(ok_or_missing(salt_auth)?)?;
+        #[cfg(feature = "with_ctap2_1")]
+        let pin_uv_auth_protocol = pin_uv_auth_protocol.map(extract_unsigned).transpose()?;
         Ok(Self {
             key_agreement: CoseKey(cose_key),
             salt_enc,
             salt_auth,
+            #[cfg(feature = "with_ctap2_1")]
+            pin_uv_auth_protocol,
         })
     }
 }
@@ -429,10 +521,12 @@ impl From<PackedAttestationStatement> for cbor::Value {
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 #[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
 pub enum SignatureAlgorithm {
     ES256 = ecdsa::PubKey::ES256_ALGORITHM as isize,
+    EDDSA = ed25519::PubKey::EDDSA_ALGORITHM as isize,
+    ES384 = ecdsa_p384::PubKey::ES384_ALGORITHM as isize,
     // This is the default for all numbers not covered above.
     // Unknown types should be ignored, instead of returning errors.
     Unknown = 0,
@@ -450,11 +544,237 @@ impl TryFrom<cbor::Value> for SignatureAlgorithm {
     fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
         match extract_integer(cbor_value)? {
             ecdsa::PubKey::ES256_ALGORITHM => Ok(SignatureAlgorithm::ES256),
+            ed25519::PubKey::EDDSA_ALGORITHM => Ok(SignatureAlgorithm::EDDSA),
+            ecdsa_p384::PubKey::ES384_ALGORITHM => Ok(SignatureAlgorithm::ES384),
             _ => Ok(SignatureAlgorithm::Unknown),
         }
     }
 }
 
+// The private key of a credential, for any of the supported signature algorithms.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub enum PrivateKey {
+    Ecdsa(ecdsa::SecKey),
+    Ed25519(ed25519::SecKey),
+    Ecdsa384(ecdsa_p384::SecKey),
+}
+
+impl PrivateKey {
+    // Generates a new random key for the algorithm.
+    pub fn new(
+        rng: &mut impl Rng256,
+        alg: SignatureAlgorithm,
+    ) -> Result<PrivateKey, Ctap2StatusCode> {
+        match alg {
+            SignatureAlgorithm::ES256 => Ok(PrivateKey::Ecdsa(ecdsa::SecKey::gensk(rng))),
+            SignatureAlgorithm::EDDSA => Ok(PrivateKey::Ed25519(ed25519::SecKey::gensk(rng))),
+            SignatureAlgorithm::ES384 => Ok(PrivateKey::Ecdsa384(ecdsa_p384::SecKey::gensk(rng))),
+            SignatureAlgorithm::Unknown => Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM),
+        }
+    }
+
+    // All algorithms use 32 bytes of secret key material. For ES384, they are the seed from which
+    // the secret scalar is derived. None is returned for invalid keys.
+    pub fn from_bytes(alg: SignatureAlgorithm, bytes: &[u8; 32]) -> Option<PrivateKey> {
+        match alg {
+            SignatureAlgorithm::ES256 => ecdsa::SecKey::from_bytes(bytes).map(PrivateKey::Ecdsa),
+            SignatureAlgorithm::EDDSA => {
+                Some(PrivateKey::Ed25519(ed25519::SecKey::from_bytes(bytes)))
+            }
+            SignatureAlgorithm::ES384 => {
+                ecdsa_p384::SecKey::from_bytes(bytes).map(PrivateKey::Ecdsa384)
+            }
+            SignatureAlgorithm::Unknown => None,
+        }
+    }
+
+    // The caller owns a copy of the secret, and zeroizes it when done.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        match self {
+            PrivateKey::Ecdsa(sk) => sk.to_bytes(&mut bytes),
+            PrivateKey::Ed25519(sk) => sk.to_bytes(&mut bytes),
+            PrivateKey::Ecdsa384(sk) => sk.to_bytes(&mut bytes),
+        }
+        bytes
+    }
+
+    pub fn signature_algorithm(&self) -> SignatureAlgorithm {
+        match self {
+            PrivateKey::Ecdsa(_) => SignatureAlgorithm::ES256,
+            PrivateKey::Ed25519(_) => SignatureAlgorithm::EDDSA,
+            PrivateKey::Ecdsa384(_) => SignatureAlgorithm::ES384,
+        }
+    }
+
+    // Returns the COSE_Key of the public key.
+    pub fn public_key_cose(&self) -> CoseKey {
+        match self {
+            PrivateKey::Ecdsa(sk) => CoseKey::from(sk.genpk()),
+            PrivateKey::Ed25519(sk) => CoseKey::from(sk.genpk()),
+            PrivateKey::Ecdsa384(sk) => CoseKey::from(sk.genpk()),
+        }
+    }
+
+    // Signs the concatenation of the message parts and returns the signature in the format
+    // WebAuthn expects: ASN.1 DER for ES256 and ES384, and the raw 64 bytes for EdDSA.
+    //
+    // ECDSA hashes the parts one after the other, without concatenating them. For ES256, the hash
+    // engine of the SoC does it if available. EdDSA hashes the message twice, so it needs the
+    // concatenation.
+    //
+    // ECDSA nonces mix RFC 6979 with the RNG, so that a failing RNG can't leak the key. EdDSA is
+    // deterministic by design.
+    //
+    // Each signature is verified before it is released. A fault injected during signing, e.g. with
+    // a voltage glitch, produces a wrong signature from which the private key can be computed.
+    pub fn sign_and_encode(
+        &self,
+        rng: &mut impl Rng256,
+        message_parts: &[&[u8]],
+    ) -> Result<Vec<u8>, Ctap2StatusCode> {
+        let is_valid;
+        let signature = match self {
+            PrivateKey::Ecdsa(sk) => {
+                let digest = crypto_backend::sha256(message_parts);
+                let signature = sk.sign_hedged_digest::<Sha256, _>(&digest, rng);
+                is_valid = sk.genpk().verify_digest_vartime(&digest, &signature);
+                signature.to_asn1_der()
+            }
+            PrivateKey::Ed25519(sk) => {
+                let message = message_parts.concat();
+                let signature = sk.sign(&message);
+                is_valid = sk.genpk().verify_vartime(&message, &signature);
+                signature.to_bytes().to_vec()
+            }
+            PrivateKey::Ecdsa384(sk) => {
+                let mut hasher = Sha384::new();
+                for part in message_parts {
+                    hasher.update(part);
+                }
+                let digest = hasher.finalize();
+                let signature = sk.sign_hedged_digest(&digest, rng);
+                is_valid = sk.genpk().verify_digest_vartime(&digest, &signature);
+                signature.to_asn1_der()
+            }
+        };
+        if is_valid {
+            Ok(signature)
+        } else {
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)
+        }
+    }
+
+    // Checks a signature in the format of sign_and_encode. Signatures are not reproducible, so
+    // tests verify them instead.
+    #[cfg(test)]
+    pub fn verify_encoded(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            PrivateKey::Ecdsa(sk) => decode_asn1_der_signature(signature, 32)
+                .and_then(|bytes| ecdsa::Signature::from_bytes(&bytes))
+                .map_or(false, |signature| {
+                    sk.genpk()
+                        .verify_vartime::<crypto::sha256::Sha256>(message, &signature)
+                }),
+            PrivateKey::Ed25519(sk) => {
+                signature.len() == 64
+                    && sk.genpk().verify_vartime(
+                        message,
+                        &ed25519::Signature::from_bytes(array_ref!(signature, 0, 64)),
+                    )
+            }
+            PrivateKey::Ecdsa384(sk) => decode_asn1_der_signature(signature, ecdsa_p384::NBYTES)
+                .and_then(|bytes| {
+                    ecdsa_p384::Signature::from_bytes(array_ref!(bytes, 0, 2 * ecdsa_p384::NBYTES))
+                })
+                .map_or(false, |signature| {
+                    sk.genpk().verify_vartime(message, &signature)
+                }),
+        }
+    }
+}
+
+// Parses the ASN.1 DER SEQUENCE of the integers r and s, into their concatenation as fixed size
+// big endian integers.
+#[cfg(test)]
+pub fn decode_asn1_der_signature(der: &[u8], int_length: usize) -> Option<Vec<u8>> {
+    if der.len() < 2 || der[0] != 0x30 || der[1] as usize != der.len() - 2 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(2 * int_length);
+    let mut rest = &der[2..];
+    for _ in 0..2 {
+        if rest.len() < 2 || rest[0] != 0x02 || rest.len() < 2 + rest[1] as usize {
+            return None;
+        }
+        let (int, remaining) = rest[2..].split_at(rest[1] as usize);
+        let int = if int.len() > int_length && int[0] == 0x00 {
+            &int[1..]
+        } else {
+            int
+        };
+        if int.len() > int_length {
+            return None;
+        }
+        bytes.resize(bytes.len() + int_length - int.len(), 0x00);
+        bytes.extend_from_slice(int);
+        rest = remaining;
+    }
+    if rest.is_empty() {
+        Some(bytes)
+    } else {
+        None
+    }
+}
+
+impl From<ecdsa::SecKey> for PrivateKey {
+    fn from(sk: ecdsa::SecKey) -> Self {
+        PrivateKey::Ecdsa(sk)
+    }
+}
+
+impl From<PrivateKey> for cbor::Value {
+    fn from(private_key: PrivateKey) -> Self {
+        let bytes = private_key.to_bytes().to_vec();
+        match private_key {
+            // ECDSA keys keep the plain byte string format from before other algorithms existed.
+            PrivateKey::Ecdsa(_) => cbor_bytes!(bytes),
+            PrivateKey::Ed25519(_) | PrivateKey::Ecdsa384(_) => {
+                cbor_array![private_key.signature_algorithm(), bytes]
+            }
+        }
+    }
+}
+
+impl TryFrom<cbor::Value> for PrivateKey {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        let (alg, mut bytes) = match cbor_value {
+            cbor::Value::KeyValue(cbor::KeyType::ByteString(bytes)) => {
+                (SignatureAlgorithm::ES256, bytes)
+            }
+            cbor_value => {
+                let mut array = extract_array(cbor_value)?;
+                if array.len() != 2 {
+                    return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_CBOR);
+                }
+                let bytes = extract_byte_string(array.pop().unwrap())?;
+                let alg = SignatureAlgorithm::try_from(array.pop().unwrap())?;
+                (alg, bytes)
+            }
+        };
+        let private_key = if bytes.len() == 32 {
+            PrivateKey::from_bytes(alg, array_ref!(bytes, 0, 32))
+        } else {
+            None
+        };
+        bytes.zeroize();
+        private_key.ok_or(Ctap2StatusCode::CTAP2_ERR_INVALID_CBOR)
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, PartialOrd)]
 #[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
 #[cfg_attr(test, derive(IntoEnumIterator))]
@@ -494,14 +814,21 @@ pub struct PublicKeyCredentialSource {
     // TODO function to convert to / from Vec<u8>
     pub key_type: PublicKeyCredentialType,
     pub credential_id: Vec<u8>,
-    pub private_key: ecdsa::SecKey, // TODO(kaczmarczyck) open for other algorithms
+    pub private_key: PrivateKey,
     pub rp_id: String,
     pub user_handle: Vec<u8>, // not optional, but nullable
     pub user_display_name: Option<String>,
     pub cred_protect_policy: Option<CredentialProtectionPolicy>,
     pub creation_order: u64,
+    // Order of the last assertion made with this credential, sharing the creation order counter.
+    pub last_used_order: Option<u64>,
+    // None for credentials stored before they had their own counter. Those continue from the
+    // global signature counter.
+    pub signature_counter: Option<u32>,
     pub user_name: Option<String>,
     pub user_icon: Option<String>,
+    pub large_blob_key: Option<Vec<u8>>,
+    pub cred_blob: Option<Vec<u8>>,
 }
 
 // We serialize credentials for the persistent storage using CBOR maps. Each field of a credential
@@ -516,6 +843,10 @@ enum PublicKeyCredentialSourceField {
     CreationOrder = 7,
     UserName = 8,
     UserIcon = 9,
+    LargeBlobKey = 10,
+    CredBlob = 11,
+    LastUsedOrder = 12,
+    SignatureCounter = 13,
     // When a field is removed, its tag should be reserved and not used for new fields. We document
     // those reserved tags below.
     // Reserved tags:
@@ -530,11 +861,9 @@ impl From<PublicKeyCredentialSourceField> for cbor::KeyType {
 
 impl From<PublicKeyCredentialSource> for cbor::Value {
     fn from(credential: PublicKeyCredentialSource) -> cbor::Value {
-        let mut private_key = [0u8; 32];
-        credential.private_key.to_bytes(&mut private_key);
         cbor_map_options! {
             PublicKeyCredentialSourceField::CredentialId => Some(credential.credential_id),
-            PublicKeyCredentialSourceField::PrivateKey => Some(private_key.to_vec()),
+            PublicKeyCredentialSourceField::PrivateKey => Some(credential.private_key),
             PublicKeyCredentialSourceField::RpId => Some(credential.rp_id),
             PublicKeyCredentialSourceField::UserHandle => Some(credential.user_handle),
             PublicKeyCredentialSourceField::UserDisplayName => credential.user_display_name,
@@ -542,6 +871,10 @@ impl From<PublicKeyCredentialSource> for cbor::Value {
             PublicKeyCredentialSourceField::CreationOrder => credential.creation_order,
             PublicKeyCredentialSourceField::UserName => credential.user_name,
             PublicKeyCredentialSourceField::UserIcon => credential.user_icon,
+            PublicKeyCredentialSourceField::LargeBlobKey => credential.large_blob_key,
+            PublicKeyCredentialSourceField::CredBlob => credential.cred_blob,
+            PublicKeyCredentialSourceField::LastUsedOrder => credential.last_used_order,
+            PublicKeyCredentialSourceField::SignatureCounter => credential.signature_counter.map(u64::from),
         }
     }
 }
@@ -561,16 +894,15 @@ impl TryFrom<cbor::Value> for PublicKeyCredentialSource {
                 PublicKeyCredentialSourceField::CreationOrder => creation_order,
                 PublicKeyCredentialSourceField::UserName => user_name,
                 PublicKeyCredentialSourceField::UserIcon => user_icon,
+                PublicKeyCredentialSourceField::LargeBlobKey => large_blob_key,
+                PublicKeyCredentialSourceField::CredBlob => cred_blob,
+                PublicKeyCredentialSourceField::LastUsedOrder => last_used_order,
+                PublicKeyCredentialSourceField::SignatureCounter => signature_counter,
             } = extract_map(cbor_value)?;
         }
 
         let credential_id = extract_byte_string(ok_or_missing(credential_id)?)?;
-        let private_key = extract_byte_string(ok_or_missing(private_key)?)?;
-        if private_key.len() != 32 {
-            return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_CBOR);
-        }
-        let private_key = ecdsa::SecKey::from_bytes(array_ref!(private_key, 0, 32))
-            .ok_or(Ctap2StatusCode::CTAP2_ERR_INVALID_CBOR)?;
+        let private_key = PrivateKey::try_from(ok_or_missing(private_key)?)?;
         let rp_id = extract_text_string(ok_or_missing(rp_id)?)?;
         let user_handle = extract_byte_string(ok_or_missing(user_handle)?)?;
         let user_display_name = user_display_name.map(extract_text_string).transpose()?;
@@ -580,6 +912,15 @@ impl TryFrom<cbor::Value> for PublicKeyCredentialSource {
         let creation_order = creation_order.map(extract_unsigned).unwrap_or(Ok(0))?;
         let user_name = user_name.map(extract_text_string).transpose()?;
         let user_icon = user_icon.map(extract_text_string).transpose()?;
+        let large_blob_key = large_blob_key.map(extract_byte_string).transpose()?;
+        let cred_blob = cred_blob.map(extract_byte_string).transpose()?;
+        let last_used_order = last_used_order.map(extract_unsigned).transpose()?;
+        let signature_counter = signature_counter
+            .map(extract_unsigned)
+            .transpose()?
+            .map(u32::try_from)
+            .transpose()
+            .map_err(|_| Ctap2StatusCode::CTAP2_ERR_INVALID_CBOR)?;
         // We don't return whether there were unknown fields in the CBOR value. This means that
         // deserialization is not injective. In particular deserialization is only an inverse of
         // serialization at a given version of OpenSK. This is not a problem because:
@@ -599,8 +940,12 @@ impl TryFrom<cbor::Value> for PublicKeyCredentialSource {
             user_display_name,
             cred_protect_policy,
             creation_order,
+            last_used_order,
+            signature_counter,
             user_name,
             user_icon,
+            large_blob_key,
+            cred_blob,
         })
     }
 }
@@ -612,137 +957,408 @@ impl PublicKeyCredentialSource {
             || self.cred_protect_policy
                 == Some(CredentialProtectionPolicy::UserVerificationOptional)
     }
+
+    // Level 3 of credProtect: the credential is only usable with user verification.
+    pub fn requires_user_verification(&self) -> bool {
+        self.cred_protect_policy == Some(CredentialProtectionPolicy::UserVerificationRequired)
+    }
 }
 
-// TODO(kaczmarczyck) we could decide to split this data type up
-// It depends on the algorithm though, I think.
-// So before creating a mess, this is my workaround.
-#[derive(Clone)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Clone, Debug, PartialEq))]
+#[cfg_attr(test, derive(IntoEnumIterator))]
+pub enum ClientPinSubCommand {
+    GetPinRetries = 0x01,
+    GetKeyAgreement = 0x02,
+    SetPin = 0x03,
+    ChangePin = 0x04,
+    GetPinToken = 0x05,
+    #[cfg(feature = "with_ctap2_1")]
+    GetPinUvAuthTokenUsingUvWithPermissions = 0x06,
+    #[cfg(feature = "with_ctap2_1")]
+    GetUvRetries = 0x07,
+    #[cfg(feature = "with_ctap2_1")]
+    SetMinPinLength = 0x08,
+    #[cfg(feature = "with_ctap2_1")]
+    GetPinUvAuthTokenUsingPinWithPermissions = 0x09,
+}
+
+impl From<ClientPinSubCommand> for cbor::Value {
+    fn from(subcommand: ClientPinSubCommand) -> Self {
+        (subcommand as u64).into()
+    }
+}
+
+impl TryFrom<cbor::Value> for ClientPinSubCommand {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        let subcommand_int = extract_unsigned(cbor_value)?;
+        match subcommand_int {
+            0x01 => Ok(ClientPinSubCommand::GetPinRetries),
+            0x02 => Ok(ClientPinSubCommand::GetKeyAgreement),
+            0x03 => Ok(ClientPinSubCommand::SetPin),
+            0x04 => Ok(ClientPinSubCommand::ChangePin),
+            0x05 => Ok(ClientPinSubCommand::GetPinToken),
+            #[cfg(feature = "with_ctap2_1")]
+            0x06 => Ok(ClientPinSubCommand::GetPinUvAuthTokenUsingUvWithPermissions),
+            #[cfg(feature = "with_ctap2_1")]
+            0x07 => Ok(ClientPinSubCommand::GetUvRetries),
+            #[cfg(feature = "with_ctap2_1")]
+            0x08 => Ok(ClientPinSubCommand::SetMinPinLength),
+            #[cfg(feature = "with_ctap2_1")]
+            0x09 => Ok(ClientPinSubCommand::GetPinUvAuthTokenUsingPinWithPermissions),
+            #[cfg(feature = "with_ctap2_1")]
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND),
+            #[cfg(not(feature = "with_ctap2_1"))]
+            _ => Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER),
+        }
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub enum EnterpriseAttestationMode {
+    VendorFacilitated = 0x01,
+    PlatformManaged = 0x02,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<u64> for EnterpriseAttestationMode {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(value: u64) -> Result<Self, Ctap2StatusCode> {
+        match value {
+            0x01 => Ok(EnterpriseAttestationMode::VendorFacilitated),
+            0x02 => Ok(EnterpriseAttestationMode::PlatformManaged),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION),
+        }
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Clone, Copy)]
 #[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
-pub struct CoseKey(pub BTreeMap<cbor::KeyType, cbor::Value>);
-
-// This is the algorithm specifier that is supposed to be used in a COSE key
-// map. The CTAP specification says -25 which represents ECDH-ES + HKDF-256
-// here: https://www.iana.org/assignments/cose/cose.xhtml#algorithms
-// In fact, this is just used for compatibility with older specification versions.
-const ECDH_ALGORITHM: i64 = -25;
-// This is the identifier used by OpenSSH. To be compatible, we accept both.
-const ES256_ALGORITHM: i64 = -7;
-const EC2_KEY_TYPE: i64 = 2;
-const P_256_CURVE: i64 = 1;
-
-impl From<ecdh::PubKey> for CoseKey {
-    fn from(pk: ecdh::PubKey) -> Self {
-        let mut x_bytes = [0; ecdh::NBYTES];
-        let mut y_bytes = [0; ecdh::NBYTES];
-        pk.to_coordinates(&mut x_bytes, &mut y_bytes);
-        let x_byte_cbor: cbor::Value = cbor_bytes_lit!(&x_bytes);
-        let y_byte_cbor: cbor::Value = cbor_bytes_lit!(&y_bytes);
-        // TODO(kaczmarczyck) do not write optional parameters, spec is unclear
-        let cose_cbor_value = cbor_map_options! {
-            1 => EC2_KEY_TYPE,
-            3 => ECDH_ALGORITHM,
-            -1 => P_256_CURVE,
-            -2 => x_byte_cbor,
-            -3 => y_byte_cbor,
-        };
-        if let cbor::Value::Map(cose_map) = cose_cbor_value {
-            CoseKey(cose_map)
-        } else {
-            unreachable!();
+#[cfg_attr(test, derive(IntoEnumIterator))]
+pub enum ConfigSubCommand {
+    EnableEnterpriseAttestation = 0x01,
+    ToggleAlwaysUv = 0x02,
+    SetMinPinLength = 0x03,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl From<ConfigSubCommand> for cbor::Value {
+    fn from(subcommand: ConfigSubCommand) -> Self {
+        (subcommand as u64).into()
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for ConfigSubCommand {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        let subcommand_int = extract_unsigned(cbor_value)?;
+        match subcommand_int {
+            0x01 => Ok(ConfigSubCommand::EnableEnterpriseAttestation),
+            0x02 => Ok(ConfigSubCommand::ToggleAlwaysUv),
+            0x03 => Ok(ConfigSubCommand::SetMinPinLength),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND),
         }
     }
 }
 
-impl TryFrom<CoseKey> for ecdh::PubKey {
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Clone)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct SetMinPinLengthParams {
+    pub new_min_pin_length: Option<u8>,
+    pub min_pin_length_rp_ids: Option<Vec<String>>,
+    pub force_change_pin: Option<bool>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for SetMinPinLengthParams {
     type Error = Ctap2StatusCode;
 
-    fn try_from(cose_key: CoseKey) -> Result<Self, Ctap2StatusCode> {
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
         destructure_cbor_map! {
             let {
-                1 => key_type,
-                3 => algorithm,
-                -1 => curve,
-                -2 => x_bytes,
-                -3 => y_bytes,
-            } = cose_key.0;
+                0x01 => new_min_pin_length,
+                0x02 => min_pin_length_rp_ids,
+                0x03 => force_change_pin,
+            } = extract_map(cbor_value)?;
         }
 
-        let key_type = extract_integer(ok_or_missing(key_type)?)?;
-        if key_type != EC2_KEY_TYPE {
-            return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM);
-        }
-        let algorithm = extract_integer(ok_or_missing(algorithm)?)?;
-        if algorithm != ECDH_ALGORITHM && algorithm != ES256_ALGORITHM {
-            return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM);
+        let new_min_pin_length = new_min_pin_length
+            .map(extract_unsigned)
+            .transpose()?
+            .map(u8::try_from)
+            .transpose()
+            .map_err(|_| Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)?;
+        let min_pin_length_rp_ids = match min_pin_length_rp_ids {
+            Some(entry) => Some(
+                extract_array(entry)?
+                    .into_iter()
+                    .map(extract_text_string)
+                    .collect::<Result<Vec<String>, Ctap2StatusCode>>()?,
+            ),
+            None => None,
+        };
+        let force_change_pin = force_change_pin.map(extract_bool).transpose()?;
+
+        Ok(Self {
+            new_min_pin_length,
+            min_pin_length_rp_ids,
+            force_change_pin,
+        })
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl From<SetMinPinLengthParams> for cbor::Value {
+    fn from(params: SetMinPinLengthParams) -> Self {
+        cbor_map_options! {
+            0x01 => params.new_min_pin_length.map(|u| u as u64),
+            0x02 => params.min_pin_length_rp_ids.map(|vec| cbor_array_vec!(vec)),
+            0x03 => params.force_change_pin,
         }
-        let curve = extract_integer(ok_or_missing(curve)?)?;
-        if curve != P_256_CURVE {
-            return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM);
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Clone)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub enum ConfigSubCommandParams {
+    SetMinPinLength(SetMinPinLengthParams),
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl From<ConfigSubCommandParams> for cbor::Value {
+    fn from(params: ConfigSubCommandParams) -> Self {
+        match params {
+            ConfigSubCommandParams::SetMinPinLength(set_min_pin_length_params) => {
+                set_min_pin_length_params.into()
+            }
         }
-        let x_bytes = extract_byte_string(ok_or_missing(x_bytes)?)?;
-        if x_bytes.len() != ecdh::NBYTES {
-            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Clone, Copy)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+#[cfg_attr(test, derive(IntoEnumIterator))]
+pub enum BioEnrollmentSubCommand {
+    EnrollBegin = 0x01,
+    EnrollCaptureNextSample = 0x02,
+    CancelCurrentEnrollment = 0x03,
+    EnumerateEnrollments = 0x04,
+    SetFriendlyName = 0x05,
+    RemoveEnrollment = 0x06,
+    GetFingerprintSensorInfo = 0x07,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl From<BioEnrollmentSubCommand> for cbor::Value {
+    fn from(subcommand: BioEnrollmentSubCommand) -> Self {
+        (subcommand as u64).into()
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for BioEnrollmentSubCommand {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        let subcommand_int = extract_unsigned(cbor_value)?;
+        match subcommand_int {
+            0x01 => Ok(BioEnrollmentSubCommand::EnrollBegin),
+            0x02 => Ok(BioEnrollmentSubCommand::EnrollCaptureNextSample),
+            0x03 => Ok(BioEnrollmentSubCommand::CancelCurrentEnrollment),
+            0x04 => Ok(BioEnrollmentSubCommand::EnumerateEnrollments),
+            0x05 => Ok(BioEnrollmentSubCommand::SetFriendlyName),
+            0x06 => Ok(BioEnrollmentSubCommand::RemoveEnrollment),
+            0x07 => Ok(BioEnrollmentSubCommand::GetFingerprintSensorInfo),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND),
         }
-        let y_bytes = extract_byte_string(ok_or_missing(y_bytes)?)?;
-        if y_bytes.len() != ecdh::NBYTES {
-            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Clone)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct BioEnrollmentSubCommandParams {
+    pub template_id: Option<Vec<u8>>,
+    pub template_friendly_name: Option<String>,
+    pub timeout_milliseconds: Option<u64>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for BioEnrollmentSubCommandParams {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                0x01 => template_id,
+                0x02 => template_friendly_name,
+                0x03 => timeout_milliseconds,
+            } = extract_map(cbor_value)?;
         }
 
-        let x_array_ref = array_ref![x_bytes.as_slice(), 0, ecdh::NBYTES];
-        let y_array_ref = array_ref![y_bytes.as_slice(), 0, ecdh::NBYTES];
-        ecdh::PubKey::from_coordinates(x_array_ref, y_array_ref)
-            .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        let template_id = template_id.map(extract_byte_string).transpose()?;
+        let template_friendly_name = template_friendly_name
+            .map(extract_text_string)
+            .transpose()?;
+        let timeout_milliseconds = timeout_milliseconds.map(extract_unsigned).transpose()?;
+
+        Ok(Self {
+            template_id,
+            template_friendly_name,
+            timeout_milliseconds,
+        })
     }
 }
 
-#[cfg_attr(any(test, feature = "debug_ctap"), derive(Clone, Debug, PartialEq))]
-#[cfg_attr(test, derive(IntoEnumIterator))]
-pub enum ClientPinSubCommand {
-    GetPinRetries = 0x01,
-    GetKeyAgreement = 0x02,
-    SetPin = 0x03,
-    ChangePin = 0x04,
-    GetPinToken = 0x05,
-    #[cfg(feature = "with_ctap2_1")]
-    GetPinUvAuthTokenUsingUvWithPermissions = 0x06,
-    #[cfg(feature = "with_ctap2_1")]
-    GetUvRetries = 0x07,
-    #[cfg(feature = "with_ctap2_1")]
-    SetMinPinLength = 0x08,
-    #[cfg(feature = "with_ctap2_1")]
-    GetPinUvAuthTokenUsingPinWithPermissions = 0x09,
+#[cfg(feature = "with_ctap2_1")]
+impl From<BioEnrollmentSubCommandParams> for cbor::Value {
+    fn from(params: BioEnrollmentSubCommandParams) -> Self {
+        cbor_map_options! {
+            0x01 => params.template_id,
+            0x02 => params.template_friendly_name,
+            0x03 => params.timeout_milliseconds,
+        }
+    }
+}
+
+/// An enrolled fingerprint, as listed by enumerateEnrollments.
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Clone)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct TemplateInfo {
+    pub template_id: Vec<u8>,
+    pub template_friendly_name: Option<String>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for TemplateInfo {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                0x01 => template_id,
+                0x02 => template_friendly_name,
+            } = extract_map(cbor_value)?;
+        }
+
+        let template_id = extract_byte_string(ok_or_missing(template_id)?)?;
+        let template_friendly_name = template_friendly_name
+            .map(extract_text_string)
+            .transpose()?;
+
+        Ok(Self {
+            template_id,
+            template_friendly_name,
+        })
+    }
 }
 
-impl From<ClientPinSubCommand> for cbor::Value {
-    fn from(subcommand: ClientPinSubCommand) -> Self {
+#[cfg(feature = "with_ctap2_1")]
+impl From<TemplateInfo> for cbor::Value {
+    fn from(template_info: TemplateInfo) -> Self {
+        cbor_map_options! {
+            0x01 => template_info.template_id,
+            0x02 => template_info.template_friendly_name,
+        }
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Clone, Copy)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+#[cfg_attr(test, derive(IntoEnumIterator))]
+pub enum CredentialManagementSubCommand {
+    GetCredsMetadata = 0x01,
+    EnumerateRpsBegin = 0x02,
+    EnumerateRpsGetNextRp = 0x03,
+    EnumerateCredentialsBegin = 0x04,
+    EnumerateCredentialsGetNextCredential = 0x05,
+    DeleteCredential = 0x06,
+    UpdateUserInformation = 0x07,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl From<CredentialManagementSubCommand> for cbor::Value {
+    fn from(subcommand: CredentialManagementSubCommand) -> Self {
         (subcommand as u64).into()
     }
 }
 
-impl TryFrom<cbor::Value> for ClientPinSubCommand {
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for CredentialManagementSubCommand {
     type Error = Ctap2StatusCode;
 
     fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
         let subcommand_int = extract_unsigned(cbor_value)?;
         match subcommand_int {
-            0x01 => Ok(ClientPinSubCommand::GetPinRetries),
-            0x02 => Ok(ClientPinSubCommand::GetKeyAgreement),
-            0x03 => Ok(ClientPinSubCommand::SetPin),
-            0x04 => Ok(ClientPinSubCommand::ChangePin),
-            0x05 => Ok(ClientPinSubCommand::GetPinToken),
-            #[cfg(feature = "with_ctap2_1")]
-            0x06 => Ok(ClientPinSubCommand::GetPinUvAuthTokenUsingUvWithPermissions),
-            #[cfg(feature = "with_ctap2_1")]
-            0x07 => Ok(ClientPinSubCommand::GetUvRetries),
-            #[cfg(feature = "with_ctap2_1")]
-            0x08 => Ok(ClientPinSubCommand::SetMinPinLength),
-            #[cfg(feature = "with_ctap2_1")]
-            0x09 => Ok(ClientPinSubCommand::GetPinUvAuthTokenUsingPinWithPermissions),
-            #[cfg(feature = "with_ctap2_1")]
+            0x01 => Ok(CredentialManagementSubCommand::GetCredsMetadata),
+            0x02 => Ok(CredentialManagementSubCommand::EnumerateRpsBegin),
+            0x03 => Ok(CredentialManagementSubCommand::EnumerateRpsGetNextRp),
+            0x04 => Ok(CredentialManagementSubCommand::EnumerateCredentialsBegin),
+            0x05 => Ok(CredentialManagementSubCommand::EnumerateCredentialsGetNextCredential),
+            0x06 => Ok(CredentialManagementSubCommand::DeleteCredential),
+            0x07 => Ok(CredentialManagementSubCommand::UpdateUserInformation),
             _ => Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND),
-            #[cfg(not(feature = "with_ctap2_1"))]
-            _ => Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER),
+        }
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Clone)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct CredentialManagementSubCommandParams {
+    pub rp_id_hash: Option<Vec<u8>>,
+    pub credential_id: Option<PublicKeyCredentialDescriptor>,
+    pub user: Option<PublicKeyCredentialUserEntity>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl TryFrom<cbor::Value> for CredentialManagementSubCommandParams {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                0x01 => rp_id_hash,
+                0x02 => credential_id,
+                0x03 => user,
+            } = extract_map(cbor_value)?;
+        }
+
+        let rp_id_hash = rp_id_hash.map(extract_byte_string).transpose()?;
+        let credential_id = credential_id
+            .map(PublicKeyCredentialDescriptor::try_from)
+            .transpose()?;
+        let user = user
+            .map(PublicKeyCredentialUserEntity::try_from)
+            .transpose()?;
+
+        Ok(Self {
+            rp_id_hash,
+            credential_id,
+            user,
+        })
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl From<CredentialManagementSubCommandParams> for cbor::Value {
+    fn from(params: CredentialManagementSubCommandParams) -> Self {
+        cbor_map_options! {
+            0x01 => params.rp_id_hash,
+            0x02 => params.credential_id,
+            0x03 => params.user,
         }
     }
 }
@@ -816,8 +1432,8 @@ mod test {
     use super::*;
     use alloc::collections::BTreeMap;
     use cbor::{
-        cbor_array, cbor_bool, cbor_bytes, cbor_false, cbor_int, cbor_map, cbor_null, cbor_text,
-        cbor_unsigned,
+        cbor_array, cbor_bool, cbor_bytes, cbor_bytes_lit, cbor_false, cbor_int, cbor_map,
+        cbor_null, cbor_text, cbor_unsigned,
     };
     use crypto::rng256::{Rng256, ThreadRng256};
 
@@ -1088,19 +1704,21 @@ mod test {
     }
 
     #[test]
-    fn test_from_public_key_credential_rp_entity() {
+    fn test_from_into_public_key_credential_rp_entity() {
         let cbor_rp_entity = cbor_map! {
             "id" => "example.com",
             "name" => "Example",
             "icon" => "example.com/icon.png",
         };
-        let rp_entity = PublicKeyCredentialRpEntity::try_from(cbor_rp_entity);
+        let rp_entity = PublicKeyCredentialRpEntity::try_from(cbor_rp_entity.clone());
         let expected_rp_entity = PublicKeyCredentialRpEntity {
             rp_id: "example.com".to_string(),
             rp_name: Some("Example".to_string()),
             rp_icon: Some("example.com/icon.png".to_string()),
         };
         assert_eq!(rp_entity, Ok(expected_rp_entity));
+        let created_cbor: cbor::Value = rp_entity.unwrap().into();
+        assert_eq!(created_cbor, cbor_rp_entity);
     }
 
     #[test]
@@ -1147,12 +1765,80 @@ mod test {
         let created_cbor: cbor::Value = signature_algorithm.unwrap().into();
         assert_eq!(created_cbor, cbor_signature_algorithm);
 
+        let cbor_signature_algorithm: cbor::Value = cbor_int!(ed25519::PubKey::EDDSA_ALGORITHM);
+        let signature_algorithm = SignatureAlgorithm::try_from(cbor_signature_algorithm.clone());
+        assert_eq!(signature_algorithm, Ok(SignatureAlgorithm::EDDSA));
+        let created_cbor: cbor::Value = signature_algorithm.unwrap().into();
+        assert_eq!(created_cbor, cbor_signature_algorithm);
+
         let cbor_unknown_algorithm: cbor::Value = cbor_int!(-1);
         let unknown_algorithm = SignatureAlgorithm::try_from(cbor_unknown_algorithm);
         let expected_unknown_algorithm = SignatureAlgorithm::Unknown;
         assert_eq!(unknown_algorithm, Ok(expected_unknown_algorithm));
     }
 
+    #[test]
+    fn test_private_key_cbor_round_trip() {
+        let mut rng = ThreadRng256 {};
+        let ecdsa_key = PrivateKey::new(&mut rng, SignatureAlgorithm::ES256).unwrap();
+        let ecdsa_cbor = cbor::Value::from(ecdsa_key.clone());
+        // ECDSA keys are compatible with the format of credentials stored by older versions.
+        assert_eq!(ecdsa_cbor, cbor_bytes!(ecdsa_key.to_bytes().to_vec()));
+        assert_eq!(PrivateKey::try_from(ecdsa_cbor), Ok(ecdsa_key));
+
+        let ed25519_key = PrivateKey::new(&mut rng, SignatureAlgorithm::EDDSA).unwrap();
+        let ed25519_cbor = cbor::Value::from(ed25519_key.clone());
+        assert_eq!(PrivateKey::try_from(ed25519_cbor), Ok(ed25519_key));
+
+        let ecdsa_p384_key = PrivateKey::new(&mut rng, SignatureAlgorithm::ES384).unwrap();
+        let ecdsa_p384_cbor = cbor::Value::from(ecdsa_p384_key.clone());
+        assert_eq!(PrivateKey::try_from(ecdsa_p384_cbor), Ok(ecdsa_p384_key));
+
+        assert_eq!(
+            PrivateKey::new(&mut rng, SignatureAlgorithm::Unknown),
+            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM)
+        );
+    }
+
+    #[test]
+    fn test_private_key_sign_and_encode() {
+        let mut rng = ThreadRng256 {};
+        for algorithm in &[
+            SignatureAlgorithm::ES256,
+            SignatureAlgorithm::EDDSA,
+            SignatureAlgorithm::ES384,
+        ] {
+            let private_key = PrivateKey::new(&mut rng, *algorithm).unwrap();
+            let signature = private_key
+                .sign_and_encode(&mut rng, &[b"mes", b"", b"sage"])
+                .unwrap();
+            assert!(private_key.verify_encoded(b"message", &signature));
+            assert!(!private_key.verify_encoded(b"massage", &signature));
+            let other_key = PrivateKey::new(&mut rng, *algorithm).unwrap();
+            assert!(!other_key.verify_encoded(b"message", &signature));
+        }
+    }
+
+    #[test]
+    fn test_private_key_sign_and_encode_hedged() {
+        let mut rng = ThreadRng256 {};
+        let private_key = PrivateKey::new(&mut rng, SignatureAlgorithm::ES256).unwrap();
+        // ECDSA signatures of the same message differ, thanks to the randomness in the nonce.
+        assert_ne!(
+            private_key.sign_and_encode(&mut rng, &[b"message"]),
+            private_key.sign_and_encode(&mut rng, &[b"message"])
+        );
+    }
+
+    #[test]
+    fn test_private_key_invalid_cbor() {
+        let cbor: cbor::Value = cbor_bytes!(vec![0x55; 31]);
+        assert!(PrivateKey::try_from(cbor).is_err());
+        assert!(PrivateKey::try_from(cbor_array![-8, vec![0x55; 31]]).is_err());
+        assert!(PrivateKey::try_from(cbor_array![-8]).is_err());
+        assert!(PrivateKey::try_from(cbor_array![-1, vec![0x55; 32]]).is_err());
+    }
+
     #[test]
     fn test_cred_protection_policy_order() {
         assert!(
@@ -1204,7 +1890,7 @@ mod test {
         assert_eq!(created_cbor, cbor_authenticator_transport);
 
         for transport in AuthenticatorTransport::into_enum_iter() {
-            let created_cbor: cbor::Value = transport.clone().into();
+            let created_cbor: cbor::Value = transport.into();
             let reconstructed = AuthenticatorTransport::try_from(created_cbor).unwrap();
             assert_eq!(transport, reconstructed);
         }
@@ -1227,6 +1913,48 @@ mod test {
         assert_eq!(created_cbor, cbor_credential_parameter);
     }
 
+    #[test]
+    fn test_public_key_credential_descriptor_list() {
+        let descriptor = PublicKeyCredentialDescriptor {
+            key_type: PublicKeyCredentialType::PublicKey,
+            key_id: vec![0x2D, 0x2D, 0x2D, 0x2D],
+            transports: Some(vec![AuthenticatorTransport::Usb]),
+        };
+        let cbor_list = cbor_array![
+            cbor_map! {
+                "type" => "public-key",
+                "id" => vec![0x2D, 0x2D, 0x2D, 0x2D],
+                "transports" => cbor_array!["usb"],
+            },
+            cbor_map! {
+                "type" => "public-key",
+            },
+            cbor_map! {
+                "id" => vec![0x2D, 0x2D, 0x2D, 0x2D],
+            },
+        ];
+        let list = PublicKeyCredentialDescriptorList::from_cbor(cbor_list.clone(), None).unwrap();
+        // Invalid entries are only rejected when the iteration reaches them.
+        let mut iter = list.into_iter();
+        assert_eq!(iter.next(), Some(Ok(descriptor.clone())));
+        assert_eq!(
+            iter.next(),
+            Some(Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER))
+        );
+
+        let list = PublicKeyCredentialDescriptorList::from_cbor(cbor_list, Some(1)).unwrap();
+        assert_eq!(
+            list,
+            PublicKeyCredentialDescriptorList::from(vec![descriptor])
+        );
+
+        let cbor_list = cbor_array![cbor_array![]];
+        assert_eq!(
+            PublicKeyCredentialDescriptorList::from_cbor(cbor_list, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_CBOR_UNEXPECTED_TYPE)
+        );
+    }
+
     #[test]
     fn test_from_into_public_key_credential_descriptor() {
         let cbor_credential_descriptor = cbor_map! {
@@ -1251,13 +1979,50 @@ mod test {
         let cbor_extensions = cbor_map! {
             "hmac-secret" => true,
             "credProtect" => CredentialProtectionPolicy::UserVerificationRequired,
+            "largeBlobKey" => true,
+            "credBlob" => vec![0xCB],
+            "minPinLength" => true,
+            "uvm" => true,
         };
         let extensions = MakeCredentialExtensions::try_from(cbor_extensions);
         let expected_extensions = MakeCredentialExtensions {
             hmac_secret: true,
             cred_protect: Some(CredentialProtectionPolicy::UserVerificationRequired),
+            uvm: true,
+            #[cfg(feature = "with_ctap2_1")]
+            cred_blob: Some(vec![0xCB]),
+            #[cfg(feature = "with_ctap2_1")]
+            large_blob_key: Some(true),
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: true,
+            #[cfg(feature = "with_ctap2_1")]
+            hmac_secret_mc: None,
+        };
+        assert_eq!(extensions, Ok(expected_extensions));
+    }
+
+    #[test]
+    fn test_make_credential_extensions_ignores_unknown() {
+        let cbor_extensions = cbor_map! {
+            1 => true,
+            "hmac-secret" => true,
+            "unknownExtension" => vec![0x00],
+        };
+        let extensions = MakeCredentialExtensions::try_from(cbor_extensions);
+        let expected_extensions = MakeCredentialExtensions {
+            hmac_secret: true,
+            ..Default::default()
         };
         assert_eq!(extensions, Ok(expected_extensions));
+
+        // Supported extensions still check the type of their input.
+        let cbor_extensions = cbor_map! {
+            "hmac-secret" => vec![0x00],
+        };
+        assert_eq!(
+            MakeCredentialExtensions::try_from(cbor_extensions),
+            Err(CTAP2_ERR_CBOR_UNEXPECTED_TYPE)
+        );
     }
 
     #[test]
@@ -1271,16 +2036,27 @@ mod test {
                 1 => cbor::Value::Map(cose_key.0.clone()),
                 2 => vec![0x02; 32],
                 3 => vec![0x03; 16],
+                4 => 2,
             },
+            "largeBlobKey" => true,
+            "credBlob" => true,
+            "uvm" => true,
         };
         let extensions = GetAssertionExtensions::try_from(cbor_extensions);
         let expected_input = GetAssertionHmacSecretInput {
             key_agreement: cose_key,
             salt_enc: vec![0x02; 32],
             salt_auth: vec![0x03; 16],
+            #[cfg(feature = "with_ctap2_1")]
+            pin_uv_auth_protocol: Some(2),
         };
         let expected_extensions = GetAssertionExtensions {
             hmac_secret: Some(expected_input),
+            uvm: true,
+            #[cfg(feature = "with_ctap2_1")]
+            cred_blob: true,
+            #[cfg(feature = "with_ctap2_1")]
+            large_blob_key: Some(true),
         };
         assert_eq!(extensions, Ok(expected_extensions));
     }
@@ -1332,16 +2108,6 @@ mod test {
         assert_eq!(created_cbor, cbor_packed_attestation_statement);
     }
 
-    #[test]
-    fn test_from_into_cose_key() {
-        let mut rng = ThreadRng256 {};
-        let sk = crypto::ecdh::SecKey::gensk(&mut rng);
-        let pk = sk.genpk();
-        let cose_key = CoseKey::from(pk.clone());
-        let created_pk = ecdh::PubKey::try_from(cose_key);
-        assert_eq!(created_pk, Ok(pk));
-    }
-
     #[test]
     fn test_from_into_client_pin_sub_command() {
         let cbor_sub_command: cbor::Value = cbor_int!(0x01);
@@ -1358,20 +2124,194 @@ mod test {
         }
     }
 
+    #[test]
+    #[cfg(feature = "with_ctap2_1")]
+    fn test_from_enterprise_attestation_mode() {
+        assert_eq!(
+            EnterpriseAttestationMode::try_from(1),
+            Ok(EnterpriseAttestationMode::VendorFacilitated)
+        );
+        assert_eq!(
+            EnterpriseAttestationMode::try_from(2),
+            Ok(EnterpriseAttestationMode::PlatformManaged)
+        );
+        assert_eq!(
+            EnterpriseAttestationMode::try_from(3),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "with_ctap2_1")]
+    fn test_from_into_config_sub_command() {
+        let cbor_sub_command: cbor::Value = cbor_int!(0x01);
+        let sub_command = ConfigSubCommand::try_from(cbor_sub_command.clone());
+        let expected_sub_command = ConfigSubCommand::EnableEnterpriseAttestation;
+        assert_eq!(sub_command, Ok(expected_sub_command));
+        let created_cbor: cbor::Value = sub_command.unwrap().into();
+        assert_eq!(created_cbor, cbor_sub_command);
+
+        for command in ConfigSubCommand::into_enum_iter() {
+            let created_cbor: cbor::Value = command.into();
+            let reconstructed = ConfigSubCommand::try_from(created_cbor).unwrap();
+            assert_eq!(command, reconstructed);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "with_ctap2_1")]
+    fn test_from_into_set_min_pin_length_params() {
+        let cbor_params = cbor_map! {
+            0x01 => 6,
+            0x02 => cbor_array_vec![vec!["example.com"]],
+            0x03 => true,
+        };
+        let params = SetMinPinLengthParams::try_from(cbor_params.clone());
+        let expected_params = SetMinPinLengthParams {
+            new_min_pin_length: Some(6),
+            min_pin_length_rp_ids: Some(vec![String::from("example.com")]),
+            force_change_pin: Some(true),
+        };
+        assert_eq!(params, Ok(expected_params));
+        let created_cbor: cbor::Value = params.unwrap().into();
+        assert_eq!(created_cbor, cbor_params);
+    }
+
+    #[test]
+    #[cfg(feature = "with_ctap2_1")]
+    fn test_from_into_bio_enrollment_sub_command() {
+        let cbor_sub_command: cbor::Value = cbor_int!(0x01);
+        let sub_command = BioEnrollmentSubCommand::try_from(cbor_sub_command.clone());
+        let expected_sub_command = BioEnrollmentSubCommand::EnrollBegin;
+        assert_eq!(sub_command, Ok(expected_sub_command));
+        let created_cbor: cbor::Value = sub_command.unwrap().into();
+        assert_eq!(created_cbor, cbor_sub_command);
+
+        for command in BioEnrollmentSubCommand::into_enum_iter() {
+            let created_cbor: cbor::Value = command.into();
+            let reconstructed = BioEnrollmentSubCommand::try_from(created_cbor).unwrap();
+            assert_eq!(command, reconstructed);
+        }
+        let cbor_sub_command: cbor::Value = cbor_int!(0x08);
+        assert_eq!(
+            BioEnrollmentSubCommand::try_from(cbor_sub_command),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "with_ctap2_1")]
+    fn test_from_into_bio_enrollment_sub_command_params() {
+        let cbor_params = cbor_map! {
+            0x01 => vec![0x01],
+            0x02 => "Left thumb",
+            0x03 => 10000,
+        };
+        let params = BioEnrollmentSubCommandParams::try_from(cbor_params.clone());
+        let expected_params = BioEnrollmentSubCommandParams {
+            template_id: Some(vec![0x01]),
+            template_friendly_name: Some(String::from("Left thumb")),
+            timeout_milliseconds: Some(10000),
+        };
+        assert_eq!(params, Ok(expected_params));
+        let created_cbor: cbor::Value = params.unwrap().into();
+        assert_eq!(created_cbor, cbor_params);
+    }
+
+    #[test]
+    #[cfg(feature = "with_ctap2_1")]
+    fn test_from_into_credential_management_sub_command() {
+        let cbor_sub_command: cbor::Value = cbor_int!(0x01);
+        let sub_command = CredentialManagementSubCommand::try_from(cbor_sub_command.clone());
+        let expected_sub_command = CredentialManagementSubCommand::GetCredsMetadata;
+        assert_eq!(sub_command, Ok(expected_sub_command));
+        let created_cbor: cbor::Value = sub_command.unwrap().into();
+        assert_eq!(created_cbor, cbor_sub_command);
+
+        for command in CredentialManagementSubCommand::into_enum_iter() {
+            let created_cbor: cbor::Value = command.into();
+            let reconstructed = CredentialManagementSubCommand::try_from(created_cbor).unwrap();
+            assert_eq!(command, reconstructed);
+        }
+        let cbor_sub_command: cbor::Value = cbor_int!(0x08);
+        assert_eq!(
+            CredentialManagementSubCommand::try_from(cbor_sub_command),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "with_ctap2_1")]
+    fn test_from_into_credential_management_sub_command_params() {
+        let credential_id = PublicKeyCredentialDescriptor {
+            key_type: PublicKeyCredentialType::PublicKey,
+            key_id: vec![0x2D, 0x2D, 0x2D, 0x2D],
+            transports: None,
+        };
+        let cbor_params = cbor_map! {
+            0x01 => vec![0x1D; 32],
+            0x02 => credential_id.clone(),
+            0x03 => cbor_map! {
+                "id" => vec![0x1D],
+                "name" => "foo",
+            },
+        };
+        let params = CredentialManagementSubCommandParams::try_from(cbor_params.clone());
+        let expected_params = CredentialManagementSubCommandParams {
+            rp_id_hash: Some(vec![0x1D; 32]),
+            credential_id: Some(credential_id),
+            user: Some(PublicKeyCredentialUserEntity {
+                user_id: vec![0x1D],
+                user_name: Some(String::from("foo")),
+                user_display_name: None,
+                user_icon: None,
+            }),
+        };
+        assert_eq!(params, Ok(expected_params));
+        let created_cbor: cbor::Value = params.unwrap().into();
+        assert_eq!(created_cbor, cbor_params);
+    }
+
+    #[test]
+    #[cfg(feature = "with_ctap2_1")]
+    fn test_from_into_template_info() {
+        let cbor_template_info = cbor_map! {
+            0x01 => vec![0x01],
+            0x02 => "Left thumb",
+        };
+        let template_info = TemplateInfo::try_from(cbor_template_info.clone());
+        let expected_template_info = TemplateInfo {
+            template_id: vec![0x01],
+            template_friendly_name: Some(String::from("Left thumb")),
+        };
+        assert_eq!(template_info, Ok(expected_template_info));
+        let created_cbor: cbor::Value = template_info.unwrap().into();
+        assert_eq!(created_cbor, cbor_template_info);
+
+        assert_eq!(
+            TemplateInfo::try_from(cbor_map! { 0x02 => "Left thumb" }),
+            Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+        );
+    }
+
     #[test]
     fn test_credential_source_cbor_round_trip() {
         let mut rng = ThreadRng256 {};
         let credential = PublicKeyCredentialSource {
             key_type: PublicKeyCredentialType::PublicKey,
             credential_id: rng.gen_uniform_u8x32().to_vec(),
-            private_key: crypto::ecdsa::SecKey::gensk(&mut rng),
+            private_key: crypto::ecdsa::SecKey::gensk(&mut rng).into(),
             rp_id: "example.com".to_string(),
             user_handle: b"foo".to_vec(),
             user_display_name: None,
             cred_protect_policy: None,
             creation_order: 0,
+            last_used_order: None,
+            signature_counter: None,
             user_name: None,
             user_icon: None,
+            large_blob_key: None,
+            cred_blob: None,
         };
 
         assert_eq!(
@@ -1414,6 +2354,56 @@ mod test {
             ..credential
         };
 
+        assert_eq!(
+            PublicKeyCredentialSource::try_from(cbor::Value::from(credential.clone())),
+            Ok(credential.clone())
+        );
+
+        let credential = PublicKeyCredentialSource {
+            large_blob_key: Some(vec![0x4C; 32]),
+            ..credential
+        };
+
+        assert_eq!(
+            PublicKeyCredentialSource::try_from(cbor::Value::from(credential.clone())),
+            Ok(credential.clone())
+        );
+
+        let credential = PublicKeyCredentialSource {
+            cred_blob: Some(vec![0xCB; 32]),
+            ..credential
+        };
+
+        assert_eq!(
+            PublicKeyCredentialSource::try_from(cbor::Value::from(credential.clone())),
+            Ok(credential.clone())
+        );
+
+        let credential = PublicKeyCredentialSource {
+            last_used_order: Some(7),
+            ..credential
+        };
+
+        assert_eq!(
+            PublicKeyCredentialSource::try_from(cbor::Value::from(credential.clone())),
+            Ok(credential.clone())
+        );
+
+        let credential = PublicKeyCredentialSource {
+            signature_counter: Some(0x1234_5678),
+            ..credential
+        };
+
+        assert_eq!(
+            PublicKeyCredentialSource::try_from(cbor::Value::from(credential.clone())),
+            Ok(credential.clone())
+        );
+
+        let credential = PublicKeyCredentialSource {
+            private_key: PrivateKey::new(&mut rng, SignatureAlgorithm::EDDSA).unwrap(),
+            ..credential
+        };
+
         assert_eq!(
             PublicKeyCredentialSource::try_from(cbor::Value::from(credential.clone())),
             Ok(credential)
@@ -0,0 +1,58 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Keys derived from a hardware root that is unique to the device.
+//
+// The root never leaves the kernel or secure element that holds it, and is not in the flash. Secrets
+// wrapped with these keys are useless in a dump of the flash, or when copied to another device.
+
+use crypto::sha256::Sha256;
+use crypto::Hash256;
+
+// Returns the key for the purpose, or None if the kernel has no device key driver. The purpose
+// separates the keys of different uses.
+pub fn derive(purpose: &[u8]) -> Option<[u8; 32]> {
+    derive_label(&Sha256::hash(purpose))
+}
+
+#[cfg(not(feature = "std"))]
+mod prod {
+    use libtock_drivers::device_key;
+
+    pub fn derive_label(label: &[u8; 32]) -> Option<[u8; 32]> {
+        let mut key = [0; 32];
+        if device_key::derive(label, &mut key) {
+            Some(key)
+        } else {
+            None
+        }
+    }
+}
+#[cfg(not(feature = "std"))]
+use self::prod::derive_label;
+
+// Derivation for testing, from a fixed root.
+#[cfg(feature = "std")]
+mod test {
+    use crypto::hmac::hmac_256;
+    use crypto::sha256::Sha256;
+
+    const TEST_ROOT: [u8; 32] = [0x5A; 32];
+
+    pub fn derive_label(label: &[u8; 32]) -> Option<[u8; 32]> {
+        Some(hmac_256::<Sha256>(&TEST_ROOT, label))
+    }
+}
+#[cfg(feature = "std")]
+use self::test::derive_label;
@@ -0,0 +1,828 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(feature = "with_ctap2_1")]
+use super::data_formats::extract_byte_string;
+use super::data_formats::{
+    extract_bool, CredentialProtectionPolicy, GetAssertionExtensions, GetAssertionHmacSecretInput,
+    GetAssertionOptions, MakeCredentialExtensions, PrivateKey, PublicKeyCredentialSource,
+};
+use super::pin_protocol_v1::PinProtocolV1;
+use super::status_code::Ctap2StatusCode;
+use super::storage::PersistentStore;
+#[cfg(feature = "with_ctap2_1")]
+use super::MAX_CRED_BLOB_LENGTH;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use cbor::{cbor_array, cbor_array_vec, cbor_bool, cbor_bytes, cbor_key_text, cbor_map_btree};
+use core::convert::TryFrom;
+use crypto::hmac::hmac_256;
+use crypto::rng256::Rng256;
+use crypto::sha256::Sha256;
+use crypto::zeroize::Zeroize;
+
+// User verification methods from the FIDO registry. They are flags, so that a command can report
+// all methods it used.
+pub const USER_VERIFY_PRESENCE_INTERNAL: u32 = 0x0000_0001;
+pub const USER_VERIFY_FINGERPRINT_INTERNAL: u32 = 0x0000_0002;
+// The PIN is entered on the platform, and only its hash reaches the authenticator.
+pub const USER_VERIFY_PASSCODE_EXTERNAL: u32 = 0x0000_0800;
+// How keys and matchers are protected, also from the FIDO registry.
+const KEY_PROTECTION_HARDWARE: u64 = 0x0002;
+const MATCHER_PROTECTION_ON_CHIP: u64 = 0x0004;
+// WebAuthn limits the uvm output to this many entries.
+const MAX_UVM_ENTRIES: usize = 3;
+
+// What extensions can use while processing a MakeCredential command.
+#[cfg_attr(not(feature = "with_ctap2_1"), allow(dead_code))]
+pub struct MakeCredentialEnv<'a> {
+    pub rng: &'a mut dyn Rng256,
+    pub persistent_store: &'a PersistentStore,
+    pub rp_id: &'a str,
+    // Whether the new credential is resident.
+    pub rk: bool,
+    pub default_cred_protect: Option<CredentialProtectionPolicy>,
+}
+
+// What extensions can use while computing outputs from the key of a credential.
+pub struct CredentialEnv<'a> {
+    pub rng: &'a mut dyn Rng256,
+    pub persistent_store: &'a PersistentStore,
+    pub pin_protocol_v1: &'a PinProtocolV1,
+    pub has_uv: bool,
+    // The user verification methods of the command, for the uvm extension.
+    pub user_verification_methods: u32,
+}
+
+// Extension data that is stored with a new credential. For non-resident credentials, only the
+// credProtect policy is kept, inside the credential ID.
+#[derive(Default)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct CredentialExtensionData {
+    pub cred_protect_policy: Option<CredentialProtectionPolicy>,
+    pub cred_blob: Option<Vec<u8>>,
+    pub large_blob_key: Option<Vec<u8>>,
+}
+
+// Extension outputs of GetAssertion that are part of the response, but not of the signed
+// authenticator data.
+#[derive(Default)]
+pub struct UnsignedExtensionOutputs {
+    pub large_blob_key: Option<Vec<u8>>,
+}
+
+// An extension of MakeCredential and GetAssertion. Each hook has a default implementation that
+// ignores the extension, so extensions only implement the commands they apply to.
+pub trait Extension {
+    // The key of the extension in the input and output maps.
+    fn identifier(&self) -> &'static str;
+
+    // Parses the MakeCredential input of the extension.
+    fn parse_make_credential_input(
+        &self,
+        _input: cbor::Value,
+        _inputs: &mut MakeCredentialExtensions,
+    ) -> Result<(), Ctap2StatusCode> {
+        Ok(())
+    }
+
+    // Parses the GetAssertion input of the extension.
+    fn parse_get_assertion_input(
+        &self,
+        _input: cbor::Value,
+        _inputs: &mut GetAssertionExtensions,
+    ) -> Result<(), Ctap2StatusCode> {
+        Ok(())
+    }
+
+    // Checks the MakeCredential inputs and fills in what the new credential stores. Returns the
+    // output for the authenticator data, if any.
+    fn process_make_credential(
+        &self,
+        _env: &mut MakeCredentialEnv,
+        _inputs: &MakeCredentialExtensions,
+        _credential_data: &mut CredentialExtensionData,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        Ok(None)
+    }
+
+    // Computes the MakeCredential output that depends on the key of the new credential, once it
+    // is generated. Returns the output for the authenticator data, if any.
+    fn process_make_credential_key(
+        &self,
+        _env: &mut CredentialEnv,
+        _inputs: &MakeCredentialExtensions,
+        _private_key: &PrivateKey,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        Ok(None)
+    }
+
+    // Checks the GetAssertion inputs, before any credential is selected.
+    fn check_get_assertion(
+        &self,
+        _inputs: &GetAssertionExtensions,
+        _options: &GetAssertionOptions,
+    ) -> Result<(), Ctap2StatusCode> {
+        Ok(())
+    }
+
+    // Returns whether GetAssertion writes an output to the authenticator data, for every
+    // credential.
+    fn has_get_assertion_output(&self, _inputs: &GetAssertionExtensions) -> bool {
+        false
+    }
+
+    // Computes the GetAssertion output of the credential. Returns the output for the
+    // authenticator data, if any. Outputs outside of the authenticator data are set directly.
+    fn process_get_assertion(
+        &self,
+        _env: &mut CredentialEnv,
+        _inputs: &GetAssertionExtensions,
+        _credential: &PublicKeyCredentialSource,
+        _unsigned_outputs: &mut UnsignedExtensionOutputs,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        Ok(None)
+    }
+}
+
+// All extensions of this build. MakeCredential and GetAssertion consult each of them in turn.
+pub const EXTENSIONS: &[&dyn Extension] = &[
+    &HmacSecret,
+    #[cfg(feature = "with_ctap2_1")]
+    &HmacSecretMc,
+    &CredProtect,
+    &Uvm,
+    #[cfg(feature = "with_ctap2_1")]
+    &CredBlob,
+    #[cfg(feature = "with_ctap2_1")]
+    &LargeBlobKey,
+    #[cfg(feature = "with_ctap2_1")]
+    &MinPinLength,
+];
+
+// Returns the extension with this identifier, if it is supported.
+pub fn find_extension(key: &cbor::KeyType) -> Option<&'static dyn Extension> {
+    match key {
+        cbor::KeyType::TextString(identifier) => EXTENSIONS
+            .iter()
+            .find(|extension| extension.identifier() == identifier.as_str())
+            .copied(),
+        _ => None,
+    }
+}
+
+// Runs all extensions for MakeCredential. Returns the outputs for the authenticator data, which
+// may still grow once the key is generated.
+pub fn process_make_credential_extensions(
+    env: &mut MakeCredentialEnv,
+    inputs: &MakeCredentialExtensions,
+    credential_data: &mut CredentialExtensionData,
+) -> Result<BTreeMap<cbor::KeyType, cbor::Value>, Ctap2StatusCode> {
+    let mut outputs = BTreeMap::new();
+    for extension in EXTENSIONS {
+        if let Some(output) = extension.process_make_credential(env, inputs, credential_data)? {
+            outputs.insert(cbor_key_text!(extension.identifier()), output);
+        }
+    }
+    Ok(outputs)
+}
+
+// Runs all extensions on the key of the new credential, and adds their outputs.
+pub fn process_make_credential_key_extensions(
+    env: &mut CredentialEnv,
+    inputs: &MakeCredentialExtensions,
+    private_key: &PrivateKey,
+    outputs: &mut BTreeMap<cbor::KeyType, cbor::Value>,
+) -> Result<(), Ctap2StatusCode> {
+    for extension in EXTENSIONS {
+        if let Some(output) = extension.process_make_credential_key(env, inputs, private_key)? {
+            outputs.insert(cbor_key_text!(extension.identifier()), output);
+        }
+    }
+    Ok(())
+}
+
+// Runs the checks of all extensions for GetAssertion. Returns whether the authenticator data of
+// the assertions has extension outputs.
+pub fn check_get_assertion_extensions(
+    inputs: &GetAssertionExtensions,
+    options: &GetAssertionOptions,
+) -> Result<bool, Ctap2StatusCode> {
+    let mut has_output = false;
+    for extension in EXTENSIONS {
+        extension.check_get_assertion(inputs, options)?;
+        has_output |= extension.has_get_assertion_output(inputs);
+    }
+    Ok(has_output)
+}
+
+// Runs all extensions for the asserted credential. Returns the map of outputs for the
+// authenticator data, or None if there is no output.
+pub fn process_get_assertion_extensions(
+    env: &mut CredentialEnv,
+    inputs: &GetAssertionExtensions,
+    credential: &PublicKeyCredentialSource,
+    unsigned_outputs: &mut UnsignedExtensionOutputs,
+) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+    let mut outputs = BTreeMap::new();
+    for extension in EXTENSIONS {
+        if let Some(output) =
+            extension.process_get_assertion(env, inputs, credential, unsigned_outputs)?
+        {
+            outputs.insert(cbor_key_text!(extension.identifier()), output);
+        }
+    }
+    Ok(if outputs.is_empty() {
+        None
+    } else {
+        Some(cbor_map_btree!(outputs))
+    })
+}
+
+// Generates a different per-credential secret for each UV mode.
+// The computation is deterministic, and private_key expected to be unique.
+fn generate_cred_random(
+    persistent_store: &PersistentStore,
+    private_key: &PrivateKey,
+    has_uv: bool,
+) -> Result<[u8; 32], Ctap2StatusCode> {
+    let mut key = persistent_store.cred_random_secret(has_uv)?;
+    let mut private_key_bytes = private_key.to_bytes();
+    let cred_random = hmac_256::<Sha256>(&key, &private_key_bytes);
+    key.zeroize();
+    private_key_bytes.zeroize();
+    Ok(cred_random)
+}
+
+// Computes the encrypted hmac-secret output of the credential with this private key.
+fn process_hmac_secret_input(
+    env: &mut CredentialEnv,
+    hmac_secret_input: GetAssertionHmacSecretInput,
+    private_key: &PrivateKey,
+) -> Result<cbor::Value, Ctap2StatusCode> {
+    let cred_random = generate_cred_random(env.persistent_store, private_key, env.has_uv)?;
+    let encrypted_output =
+        env.pin_protocol_v1
+            .process_hmac_secret(env.rng, hmac_secret_input, &cred_random)?;
+    Ok(cbor_bytes!(encrypted_output))
+}
+
+pub struct HmacSecret;
+
+impl Extension for HmacSecret {
+    fn identifier(&self) -> &'static str {
+        "hmac-secret"
+    }
+
+    fn parse_make_credential_input(
+        &self,
+        input: cbor::Value,
+        inputs: &mut MakeCredentialExtensions,
+    ) -> Result<(), Ctap2StatusCode> {
+        inputs.hmac_secret = extract_bool(input)?;
+        Ok(())
+    }
+
+    fn parse_get_assertion_input(
+        &self,
+        input: cbor::Value,
+        inputs: &mut GetAssertionExtensions,
+    ) -> Result<(), Ctap2StatusCode> {
+        inputs.hmac_secret = Some(GetAssertionHmacSecretInput::try_from(input)?);
+        Ok(())
+    }
+
+    fn process_make_credential(
+        &self,
+        _env: &mut MakeCredentialEnv,
+        inputs: &MakeCredentialExtensions,
+        _credential_data: &mut CredentialExtensionData,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        Ok(if inputs.hmac_secret {
+            Some(cbor_bool!(true))
+        } else {
+            None
+        })
+    }
+
+    fn check_get_assertion(
+        &self,
+        inputs: &GetAssertionExtensions,
+        options: &GetAssertionOptions,
+    ) -> Result<(), Ctap2StatusCode> {
+        if inputs.hmac_secret.is_some() && !options.up {
+            // The secret is only available with user presence, not in silent assertions.
+            return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_OPTION);
+        }
+        Ok(())
+    }
+
+    fn has_get_assertion_output(&self, inputs: &GetAssertionExtensions) -> bool {
+        inputs.hmac_secret.is_some()
+    }
+
+    fn process_get_assertion(
+        &self,
+        env: &mut CredentialEnv,
+        inputs: &GetAssertionExtensions,
+        credential: &PublicKeyCredentialSource,
+        _unsigned_outputs: &mut UnsignedExtensionOutputs,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        match &inputs.hmac_secret {
+            Some(hmac_secret_input) => Ok(Some(process_hmac_secret_input(
+                env,
+                hmac_secret_input.clone(),
+                &credential.private_key,
+            )?)),
+            None => Ok(None),
+        }
+    }
+}
+
+// Returns the first hmac-secret output when the credential is created, so that RPs don't need an
+// assertion right away.
+#[cfg(feature = "with_ctap2_1")]
+pub struct HmacSecretMc;
+
+#[cfg(feature = "with_ctap2_1")]
+impl Extension for HmacSecretMc {
+    fn identifier(&self) -> &'static str {
+        "hmac-secret-mc"
+    }
+
+    fn parse_make_credential_input(
+        &self,
+        input: cbor::Value,
+        inputs: &mut MakeCredentialExtensions,
+    ) -> Result<(), Ctap2StatusCode> {
+        inputs.hmac_secret_mc = Some(GetAssertionHmacSecretInput::try_from(input)?);
+        Ok(())
+    }
+
+    // The input is ignored if the new credential doesn't have the hmac-secret extension.
+    fn process_make_credential_key(
+        &self,
+        env: &mut CredentialEnv,
+        inputs: &MakeCredentialExtensions,
+        private_key: &PrivateKey,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        match &inputs.hmac_secret_mc {
+            Some(hmac_secret_input) if inputs.hmac_secret => Ok(Some(process_hmac_secret_input(
+                env,
+                hmac_secret_input.clone(),
+                private_key,
+            )?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+pub struct CredProtect;
+
+impl Extension for CredProtect {
+    fn identifier(&self) -> &'static str {
+        "credProtect"
+    }
+
+    fn parse_make_credential_input(
+        &self,
+        input: cbor::Value,
+        inputs: &mut MakeCredentialExtensions,
+    ) -> Result<(), Ctap2StatusCode> {
+        inputs.cred_protect = Some(CredentialProtectionPolicy::try_from(input)?);
+        Ok(())
+    }
+
+    // The policy also applies without input, if the authenticator has a default.
+    fn process_make_credential(
+        &self,
+        env: &mut MakeCredentialEnv,
+        inputs: &MakeCredentialExtensions,
+        credential_data: &mut CredentialExtensionData,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        let mut cred_protect = inputs.cred_protect;
+        if cred_protect.unwrap_or(CredentialProtectionPolicy::UserVerificationOptional)
+            < env
+                .default_cred_protect
+                .unwrap_or(CredentialProtectionPolicy::UserVerificationOptional)
+        {
+            cred_protect = env.default_cred_protect;
+        }
+        credential_data.cred_protect_policy = cred_protect;
+        Ok(cred_protect.map(cbor::Value::from))
+    }
+}
+
+// Returns the uvm output: an entry for each method, with how keys and matchers are protected.
+fn uvm_output(user_verification_methods: u32) -> cbor::Value {
+    let entries: Vec<cbor::Value> = (0..32)
+        .map(|bit| 1u32 << bit)
+        .filter(|method| user_verification_methods & method != 0)
+        .take(MAX_UVM_ENTRIES)
+        .map(|method| {
+            cbor_array![
+                method as u64,
+                KEY_PROTECTION_HARDWARE,
+                MATCHER_PROTECTION_ON_CHIP,
+            ]
+        })
+        .collect();
+    cbor_array_vec!(entries)
+}
+
+// Reports how the user was verified, e.g. with a PIN or a fingerprint.
+pub struct Uvm;
+
+impl Extension for Uvm {
+    fn identifier(&self) -> &'static str {
+        "uvm"
+    }
+
+    fn parse_make_credential_input(
+        &self,
+        input: cbor::Value,
+        inputs: &mut MakeCredentialExtensions,
+    ) -> Result<(), Ctap2StatusCode> {
+        inputs.uvm = extract_bool(input)?;
+        Ok(())
+    }
+
+    fn parse_get_assertion_input(
+        &self,
+        input: cbor::Value,
+        inputs: &mut GetAssertionExtensions,
+    ) -> Result<(), Ctap2StatusCode> {
+        inputs.uvm = extract_bool(input)?;
+        Ok(())
+    }
+
+    fn process_make_credential_key(
+        &self,
+        env: &mut CredentialEnv,
+        inputs: &MakeCredentialExtensions,
+        _private_key: &PrivateKey,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        Ok(if inputs.uvm {
+            Some(uvm_output(env.user_verification_methods))
+        } else {
+            None
+        })
+    }
+
+    fn has_get_assertion_output(&self, inputs: &GetAssertionExtensions) -> bool {
+        inputs.uvm
+    }
+
+    fn process_get_assertion(
+        &self,
+        env: &mut CredentialEnv,
+        inputs: &GetAssertionExtensions,
+        _credential: &PublicKeyCredentialSource,
+        _unsigned_outputs: &mut UnsignedExtensionOutputs,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        Ok(if inputs.uvm {
+            Some(uvm_output(env.user_verification_methods))
+        } else {
+            None
+        })
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+pub struct CredBlob;
+
+#[cfg(feature = "with_ctap2_1")]
+impl Extension for CredBlob {
+    fn identifier(&self) -> &'static str {
+        "credBlob"
+    }
+
+    fn parse_make_credential_input(
+        &self,
+        input: cbor::Value,
+        inputs: &mut MakeCredentialExtensions,
+    ) -> Result<(), Ctap2StatusCode> {
+        inputs.cred_blob = Some(extract_byte_string(input)?);
+        Ok(())
+    }
+
+    fn parse_get_assertion_input(
+        &self,
+        input: cbor::Value,
+        inputs: &mut GetAssertionExtensions,
+    ) -> Result<(), Ctap2StatusCode> {
+        inputs.cred_blob = extract_bool(input)?;
+        Ok(())
+    }
+
+    // The blob is only stored with resident credentials, and only if it is short enough.
+    fn process_make_credential(
+        &self,
+        env: &mut MakeCredentialEnv,
+        inputs: &MakeCredentialExtensions,
+        credential_data: &mut CredentialExtensionData,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        let cred_blob = match &inputs.cred_blob {
+            Some(cred_blob) => cred_blob,
+            None => return Ok(None),
+        };
+        let is_stored = env.rk && cred_blob.len() <= MAX_CRED_BLOB_LENGTH;
+        if is_stored {
+            credential_data.cred_blob = Some(cred_blob.clone());
+        }
+        Ok(Some(cbor_bool!(is_stored)))
+    }
+
+    fn has_get_assertion_output(&self, inputs: &GetAssertionExtensions) -> bool {
+        inputs.cred_blob
+    }
+
+    // Credentials without a blob return an empty one.
+    fn process_get_assertion(
+        &self,
+        _env: &mut CredentialEnv,
+        inputs: &GetAssertionExtensions,
+        credential: &PublicKeyCredentialSource,
+        _unsigned_outputs: &mut UnsignedExtensionOutputs,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        Ok(if inputs.cred_blob {
+            Some(cbor_bytes!(credential
+                .cred_blob
+                .clone()
+                .unwrap_or_default()))
+        } else {
+            None
+        })
+    }
+}
+
+// The large blob key is only returned, never part of the authenticator data.
+#[cfg(feature = "with_ctap2_1")]
+pub struct LargeBlobKey;
+
+#[cfg(feature = "with_ctap2_1")]
+impl Extension for LargeBlobKey {
+    fn identifier(&self) -> &'static str {
+        "largeBlobKey"
+    }
+
+    fn parse_make_credential_input(
+        &self,
+        input: cbor::Value,
+        inputs: &mut MakeCredentialExtensions,
+    ) -> Result<(), Ctap2StatusCode> {
+        inputs.large_blob_key = Some(extract_bool(input)?);
+        Ok(())
+    }
+
+    fn parse_get_assertion_input(
+        &self,
+        input: cbor::Value,
+        inputs: &mut GetAssertionExtensions,
+    ) -> Result<(), Ctap2StatusCode> {
+        inputs.large_blob_key = Some(extract_bool(input)?);
+        Ok(())
+    }
+
+    fn process_make_credential(
+        &self,
+        env: &mut MakeCredentialEnv,
+        inputs: &MakeCredentialExtensions,
+        credential_data: &mut CredentialExtensionData,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        match inputs.large_blob_key {
+            // Only resident credentials have a large blob.
+            Some(true) if env.rk => {
+                credential_data.large_blob_key = Some(env.rng.gen_uniform_u8x32().to_vec());
+            }
+            Some(_) => return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION),
+            None => (),
+        }
+        Ok(None)
+    }
+
+    fn check_get_assertion(
+        &self,
+        inputs: &GetAssertionExtensions,
+        _options: &GetAssertionOptions,
+    ) -> Result<(), Ctap2StatusCode> {
+        if inputs.large_blob_key == Some(false) {
+            return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION);
+        }
+        Ok(())
+    }
+
+    fn process_get_assertion(
+        &self,
+        _env: &mut CredentialEnv,
+        inputs: &GetAssertionExtensions,
+        credential: &PublicKeyCredentialSource,
+        unsigned_outputs: &mut UnsignedExtensionOutputs,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        if inputs.large_blob_key == Some(true) {
+            unsigned_outputs.large_blob_key = credential.large_blob_key.clone();
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+pub struct MinPinLength;
+
+#[cfg(feature = "with_ctap2_1")]
+impl Extension for MinPinLength {
+    fn identifier(&self) -> &'static str {
+        "minPinLength"
+    }
+
+    fn parse_make_credential_input(
+        &self,
+        input: cbor::Value,
+        inputs: &mut MakeCredentialExtensions,
+    ) -> Result<(), Ctap2StatusCode> {
+        inputs.min_pin_length = extract_bool(input)?;
+        Ok(())
+    }
+
+    // The minimum PIN length is only disclosed to RPs on the configured list.
+    fn process_make_credential(
+        &self,
+        env: &mut MakeCredentialEnv,
+        inputs: &MakeCredentialExtensions,
+        _credential_data: &mut CredentialExtensionData,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        if inputs.min_pin_length
+            && env
+                .persistent_store
+                .min_pin_length_rp_ids()?
+                .iter()
+                .any(|rp_id| rp_id == env.rp_id)
+        {
+            Ok(Some((env.persistent_store.min_pin_length()? as u64).into()))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cbor::{cbor_map, cbor_map_btree};
+    use crypto::rng256::ThreadRng256;
+
+    fn process_minimal_make_credential(
+        inputs: &MakeCredentialExtensions,
+        rk: bool,
+        default_cred_protect: Option<CredentialProtectionPolicy>,
+    ) -> Result<
+        (
+            BTreeMap<cbor::KeyType, cbor::Value>,
+            CredentialExtensionData,
+        ),
+        Ctap2StatusCode,
+    > {
+        let mut rng = ThreadRng256 {};
+        let persistent_store = PersistentStore::new(&mut rng);
+        let mut env = MakeCredentialEnv {
+            rng: &mut rng,
+            persistent_store: &persistent_store,
+            rp_id: "example.com",
+            rk,
+            default_cred_protect,
+        };
+        let mut credential_data = CredentialExtensionData::default();
+        let outputs = process_make_credential_extensions(&mut env, inputs, &mut credential_data)?;
+        Ok((outputs, credential_data))
+    }
+
+    #[test]
+    fn test_find_extension() {
+        for extension in EXTENSIONS {
+            let key = cbor_key_text!(extension.identifier());
+            assert_eq!(
+                find_extension(&key).map(|e| e.identifier()),
+                Some(extension.identifier())
+            );
+        }
+        assert!(find_extension(&cbor_key_text!("unknown")).is_none());
+        assert!(find_extension(&cbor::KeyType::Unsigned(1)).is_none());
+    }
+
+    #[test]
+    fn test_process_make_credential_without_extensions() {
+        let inputs = MakeCredentialExtensions::default();
+        let (outputs, credential_data) =
+            process_minimal_make_credential(&inputs, true, None).unwrap();
+        assert!(outputs.is_empty());
+        assert_eq!(credential_data, CredentialExtensionData::default());
+    }
+
+    #[test]
+    fn test_process_make_credential_default_cred_protect() {
+        let inputs = MakeCredentialExtensions::default();
+        let policy = CredentialProtectionPolicy::UserVerificationRequired;
+        let (outputs, credential_data) =
+            process_minimal_make_credential(&inputs, false, Some(policy)).unwrap();
+        assert_eq!(
+            cbor_map_btree!(outputs),
+            cbor_map! { "credProtect" => policy }
+        );
+        assert_eq!(credential_data.cred_protect_policy, Some(policy));
+    }
+
+    #[test]
+    fn test_process_make_credential_outputs_are_sorted() {
+        let inputs = MakeCredentialExtensions {
+            hmac_secret: true,
+            cred_protect: Some(CredentialProtectionPolicy::UserVerificationOptional),
+            ..Default::default()
+        };
+        let (outputs, _) = process_minimal_make_credential(&inputs, false, None).unwrap();
+        let mut encoded_outputs = Vec::new();
+        assert!(cbor::write(cbor_map_btree!(outputs), &mut encoded_outputs));
+        // The shorter key "credProtect" comes first in canonical CBOR.
+        let expected_outputs = cbor_map! {
+            "credProtect" => CredentialProtectionPolicy::UserVerificationOptional,
+            "hmac-secret" => true,
+        };
+        let mut expected_encoding = Vec::new();
+        assert!(cbor::write(expected_outputs, &mut expected_encoding));
+        assert_eq!(encoded_outputs, expected_encoding);
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_make_credential_large_blob_key() {
+        let inputs = MakeCredentialExtensions {
+            large_blob_key: Some(true),
+            ..Default::default()
+        };
+        let (outputs, credential_data) =
+            process_minimal_make_credential(&inputs, true, None).unwrap();
+        // The key is returned in the response, not in the authenticator data.
+        assert!(outputs.is_empty());
+        assert_eq!(credential_data.large_blob_key.unwrap().len(), 32);
+        assert_eq!(
+            process_minimal_make_credential(&inputs, false, None).err(),
+            Some(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+        );
+    }
+
+    #[test]
+    fn test_uvm_output() {
+        assert_eq!(uvm_output(0), cbor_array![]);
+        assert_eq!(
+            uvm_output(USER_VERIFY_PRESENCE_INTERNAL | USER_VERIFY_PASSCODE_EXTERNAL),
+            cbor_array![
+                cbor_array![0x0001, 0x0002, 0x0004],
+                cbor_array![0x0800, 0x0002, 0x0004],
+            ]
+        );
+        // Only the first entries are reported.
+        assert_eq!(uvm_output(0x0F), uvm_output(0x07));
+    }
+
+    #[test]
+    fn test_check_get_assertion_extensions() {
+        let options = GetAssertionOptions {
+            up: true,
+            uv: false,
+        };
+        let inputs = GetAssertionExtensions::default();
+        assert_eq!(check_get_assertion_extensions(&inputs, &options), Ok(false));
+        let inputs = GetAssertionExtensions {
+            uvm: true,
+            ..Default::default()
+        };
+        assert_eq!(check_get_assertion_extensions(&inputs, &options), Ok(true));
+
+        #[cfg(feature = "with_ctap2_1")]
+        {
+            let inputs = GetAssertionExtensions {
+                cred_blob: true,
+                ..Default::default()
+            };
+            assert_eq!(check_get_assertion_extensions(&inputs, &options), Ok(true));
+            let inputs = GetAssertionExtensions {
+                large_blob_key: Some(false),
+                ..Default::default()
+            };
+            assert_eq!(
+                check_get_assertion_extensions(&inputs, &options),
+                Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+            );
+        }
+    }
+}
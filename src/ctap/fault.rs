@@ -0,0 +1,155 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Countermeasures against fault injection.
+//
+// Voltage and clock glitches can skip an instruction or corrupt a register. Against security keys,
+// they turned failed checks into successes and recovered signing keys from faulty signatures.
+// Signatures are verified before they are released, see PrivateKey::sign_and_encode. The flags
+// that a signature vouches for are stored redundantly, and random delays before the decisions make
+// it harder to time a glitch.
+
+use super::status_code::Ctap2StatusCode;
+use crypto::rng256::Rng256;
+
+// The offset of the flags in the authenticator data, after the RP ID hash.
+const AUTH_DATA_FLAGS_OFFSET: usize = 32;
+
+// Flags whose corruption would release a signature over wrong claims, such as the bits for user
+// presence and user verification. A second copy holds the inverted value, and all reads check that
+// both copies agree.
+#[derive(Clone, Copy)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct RedundantFlags {
+    value: u8,
+    inverted: u8,
+}
+
+impl RedundantFlags {
+    pub fn new(value: u8) -> RedundantFlags {
+        RedundantFlags {
+            value,
+            inverted: !value,
+        }
+    }
+
+    pub fn insert(&mut self, bits: u8) {
+        self.value |= bits;
+        self.inverted &= !bits;
+    }
+
+    pub fn get(&self) -> Result<u8, Ctap2StatusCode> {
+        if self.value != !self.inverted {
+            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE);
+        }
+        Ok(self.value)
+    }
+
+    // Returns whether all the bits are set. Both copies must agree.
+    pub fn contains(&self, bits: u8) -> Result<bool, Ctap2StatusCode> {
+        let is_set = self.get()? & bits == bits;
+        if is_set != (self.inverted & bits == 0) {
+            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE);
+        }
+        Ok(is_set)
+    }
+}
+
+// Checks the flags of the authenticator data against both copies of the flags, right before the
+// authenticator data is signed.
+pub fn check_auth_data_flags(
+    auth_data: &[u8],
+    flags: &RedundantFlags,
+) -> Result<(), Ctap2StatusCode> {
+    let auth_data_flags = *auth_data
+        .get(AUTH_DATA_FLAGS_OFFSET)
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
+    if auth_data_flags != flags.get()? {
+        return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE);
+    }
+    // A second comparison, inverted, in case the first one was skipped.
+    if !auth_data_flags != flags.inverted {
+        return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE);
+    }
+    Ok(())
+}
+
+// Waits for a random number of loop iterations, so that a decision doesn't happen at a fixed time
+// after the start of a command.
+pub fn random_delay(rng: &mut impl Rng256) {
+    let iterations = rng.gen_uniform_u8x32()[0];
+    let mut counter = 0u8;
+    for _ in 0..iterations {
+        let next = counter.wrapping_add(1);
+        // Safety: the pointer comes from a mutable reference, so it is valid and aligned. The write
+        // is volatile, so that the compiler doesn't remove the loop.
+        unsafe { core::ptr::write_volatile(&mut counter, next) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::rng256::ThreadRng256;
+
+    #[test]
+    fn test_redundant_flags() {
+        let mut flags = RedundantFlags::new(0x04);
+        assert_eq!(flags.get(), Ok(0x04));
+        flags.insert(0x81);
+        assert_eq!(flags.get(), Ok(0x85));
+        assert_eq!(flags.contains(0x05), Ok(true));
+        assert_eq!(flags.contains(0x40), Ok(false));
+    }
+
+    #[test]
+    fn test_redundant_flags_detect_corruption() {
+        let mut flags = RedundantFlags::new(0x00);
+        // A fault that skipped the update of the inverted copy.
+        flags.value |= 0x04;
+        assert_eq!(
+            flags.get(),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)
+        );
+        assert_eq!(
+            flags.contains(0x04),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)
+        );
+    }
+
+    #[test]
+    fn test_check_auth_data_flags() {
+        let flags = RedundantFlags::new(0x05);
+        let mut auth_data = vec![0x00; 37];
+        auth_data[AUTH_DATA_FLAGS_OFFSET] = 0x05;
+        assert_eq!(check_auth_data_flags(&auth_data, &flags), Ok(()));
+        auth_data[AUTH_DATA_FLAGS_OFFSET] = 0x01;
+        assert_eq!(
+            check_auth_data_flags(&auth_data, &flags),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)
+        );
+        assert_eq!(
+            check_auth_data_flags(&auth_data[..32], &flags),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
+        );
+    }
+
+    #[test]
+    fn test_random_delay() {
+        let mut rng = ThreadRng256 {};
+        for _ in 0..16 {
+            random_delay(&mut rng);
+        }
+    }
+}
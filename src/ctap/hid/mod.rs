@@ -17,20 +17,25 @@ pub mod send;
 
 use self::receive::MessageAssembler;
 use self::send::HidPacketIterator;
+#[cfg(feature = "with_nfc")]
+use super::arbiter::Interface;
 #[cfg(feature = "with_ctap1")]
 use super::ctap1;
+use super::data_formats::AuthenticatorTransport;
 use super::status_code::Ctap2StatusCode;
 use super::timed_permission::TimedPermission;
-use super::CtapState;
+use super::transport::Transport;
+use super::{CtapState, UserPresenceConfig};
 use alloc::vec;
 use alloc::vec::Vec;
-use arrayref::{array_ref, array_refs};
+use arrayref::{array_mut_ref, array_ref, array_refs};
 #[cfg(feature = "debug_ctap")]
 use core::fmt::Write;
 use crypto::rng256::Rng256;
 #[cfg(feature = "debug_ctap")]
 use libtock_drivers::console::Console;
 use libtock_drivers::timer::{ClockValue, Duration, Timestamp};
+use libtock_drivers::usb_ctap_hid;
 
 // CTAP specification (version 20190130) section 8.1
 // TODO: Channel allocation, section 8.1.3?
@@ -154,7 +159,7 @@ impl CtapHid {
     ) -> HidPacketIterator
     where
         R: Rng256,
-        CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
+        CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
     {
         // TODO: Send COMMAND_KEEPALIVE every 100ms?
         match self
@@ -174,6 +179,20 @@ impl CtapHid {
                 // If another command arrives, stop winking to prevent accidential button touches.
                 self.wink_permission = TimedPermission::waiting();
 
+                // Commands are processed right away, so USB only holds the interface lock while
+                // the packets of a message arrive.
+                #[cfg(feature = "with_nfc")]
+                {
+                    let arbiter = &mut ctap_state.interface_arbiter;
+                    let lock = arbiter.acquire(Interface::Usb, clock_value);
+                    arbiter.release(Interface::Usb);
+                    let is_transaction =
+                        message.cmd == CtapHid::COMMAND_MSG || message.cmd == CtapHid::COMMAND_CBOR;
+                    if is_transaction && lock.is_err() {
+                        return CtapHid::error_message(cid, CtapHid::ERR_CHANNEL_BUSY);
+                    }
+                }
+
                 match message.cmd {
                     // CTAP specification (version 20190130) section 8.1.9.1.1
                     CtapHid::COMMAND_MSG => {
@@ -294,10 +313,18 @@ impl CtapHid {
                 }
             }
             Ok(None) => {
-                // Waiting for more packets to assemble the message, nothing to send for now.
+                // Waiting for more packets to assemble the message, nothing to send for now. If
+                // NFC holds the lock, the message is rejected once complete.
+                #[cfg(feature = "with_nfc")]
+                ctap_state
+                    .interface_arbiter
+                    .acquire(Interface::Usb, clock_value)
+                    .ok();
                 HidPacketIterator::none()
             }
             Err((cid, error)) => {
+                #[cfg(feature = "with_nfc")]
+                ctap_state.interface_arbiter.release(Interface::Usb);
                 if !self.is_allocated_channel(cid)
                     && error != receive::Error::UnexpectedContinuation
                 {
@@ -325,6 +352,16 @@ impl CtapHid {
                         receive::Error::Timeout => {
                             CtapHid::error_message(cid, CtapHid::ERR_MSG_TIMEOUT)
                         }
+                        // The CTAP layer answers, so the platform sees a CTAP2 status code.
+                        receive::Error::RequestTooLarge => {
+                            // This unwrap is safe because the payload length is 1 <= 7609 bytes.
+                            CtapHid::split_message(Message {
+                                cid,
+                                cmd: CtapHid::COMMAND_CBOR,
+                                payload: vec![Ctap2StatusCode::CTAP2_ERR_REQUEST_TOO_LARGE as u8],
+                            })
+                            .unwrap()
+                        }
                     }
                 }
             }
@@ -427,6 +464,146 @@ impl CtapHid {
     }
 }
 
+// The CTAPHID transport over the USB driver.
+pub struct UsbTransport;
+
+impl UsbTransport {
+    const SEND_TIMEOUT: Duration<isize> = Duration::from_ms(1000);
+    // Keepalives are sent every 100 ms, so we don't wait longer for the host to take one.
+    const KEEPALIVE_TIMEOUT: Duration<isize> = Duration::from_ms(100);
+    // Matches the buffers of the CTAP layer rather than the 7609 bytes of a CTAPHID message.
+    const MAX_MSG_SIZE: usize = 1024;
+
+    // Returns an error if the packet, received while processing a command, cancels it. We only
+    // parse one packet, because we only care about CANCEL. Other packets are discarded.
+    fn process_cancel_packet(cid: ChannelID, pkt: &HidPacket) -> Result<(), Ctap2StatusCode> {
+        let (received_cid, processed_packet) = CtapHid::process_single_packet(pkt);
+        if received_cid != &cid {
+            #[cfg(feature = "debug_ctap")]
+            writeln!(
+                Console::new(),
+                "Received a packet on channel ID {:?} while processing a command",
+                received_cid,
+            )
+            .unwrap();
+            return Ok(());
+        }
+        match processed_packet {
+            ProcessedPacket::InitPacket { cmd, .. } => {
+                if cmd == CtapHid::COMMAND_CANCEL {
+                    // We ignore the payload, we can't answer with an error code anyway.
+                    #[cfg(feature = "debug_ctap")]
+                    writeln!(Console::new(), "Command cancelled").unwrap();
+                    return Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
+                } else {
+                    #[cfg(feature = "debug_ctap")]
+                    writeln!(
+                        Console::new(),
+                        "Discarded packet with command {} received while processing a command",
+                        cmd,
+                    )
+                    .unwrap();
+                }
+            }
+            ProcessedPacket::ContinuationPacket { .. } => {
+                #[cfg(feature = "debug_ctap")]
+                writeln!(
+                    Console::new(),
+                    "Discarded continuation packet received while processing a command",
+                )
+                .unwrap();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transport for UsbTransport {
+    fn transport_type(&self) -> AuthenticatorTransport {
+        AuthenticatorTransport::Usb
+    }
+
+    fn max_msg_size(&self) -> usize {
+        UsbTransport::MAX_MSG_SIZE
+    }
+
+    fn send_keepalive(
+        &self,
+        cid: ChannelID,
+        status: KeepaliveStatus,
+    ) -> Result<(), Ctap2StatusCode> {
+        for mut pkt in CtapHid::keepalive(cid, status) {
+            let status =
+                usb_ctap_hid::send_or_recv_with_timeout(&mut pkt, UsbTransport::KEEPALIVE_TIMEOUT);
+            match status {
+                None => {
+                    #[cfg(feature = "debug_ctap")]
+                    writeln!(Console::new(), "Sending a KEEPALIVE packet timed out").unwrap();
+                    // TODO: abort user presence test?
+                }
+                Some(usb_ctap_hid::SendOrRecvStatus::Error) => {
+                    panic!("Error sending KEEPALIVE packet")
+                }
+                Some(usb_ctap_hid::SendOrRecvStatus::Sent) => {
+                    #[cfg(feature = "debug_ctap")]
+                    writeln!(Console::new(), "Sent KEEPALIVE packet").unwrap();
+                }
+                Some(usb_ctap_hid::SendOrRecvStatus::Received) => {
+                    UsbTransport::process_cancel_packet(cid, &pkt)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn wait_for_cancel(
+        &self,
+        cid: ChannelID,
+        timeout: Duration<isize>,
+        wake_up: &dyn Fn() -> bool,
+    ) -> Result<(), Ctap2StatusCode> {
+        let mut pkt = [0; 64];
+        match usb_ctap_hid::recv_with_timeout_or_wake_up(&mut pkt, timeout, wake_up) {
+            None | Some(usb_ctap_hid::SendOrRecvStatus::Sent) => Ok(()),
+            Some(usb_ctap_hid::SendOrRecvStatus::Error) => panic!("Error receiving packet"),
+            Some(usb_ctap_hid::SendOrRecvStatus::Received) => {
+                UsbTransport::process_cancel_packet(cid, &pkt)
+            }
+        }
+    }
+
+    // Tests run without the USB driver, so commands are never cancelled.
+    #[cfg(feature = "std")]
+    fn wait_for_cancel(
+        &self,
+        _: ChannelID,
+        _: Duration<isize>,
+        _: &dyn Fn() -> bool,
+    ) -> Result<(), Ctap2StatusCode> {
+        Ok(())
+    }
+
+    // The response is a packet of a message returned by process_hid_packet.
+    fn send_response(&self, response: &mut [u8]) -> Result<(), Ctap2StatusCode> {
+        if response.len() != 64 {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH);
+        }
+        let packet = array_mut_ref!(response, 0, 64);
+        match usb_ctap_hid::send_or_recv_with_timeout(packet, UsbTransport::SEND_TIMEOUT) {
+            None => Err(Ctap2StatusCode::CTAP1_ERR_TIMEOUT),
+            Some(usb_ctap_hid::SendOrRecvStatus::Error) => panic!("Error sending packet"),
+            Some(usb_ctap_hid::SendOrRecvStatus::Sent) => Ok(()),
+            Some(usb_ctap_hid::SendOrRecvStatus::Received) => {
+                #[cfg(feature = "debug_ctap")]
+                writeln!(Console::new(), "Received an UNEXPECTED packet").unwrap();
+                // TODO: handle this unexpected packet.
+                Ok(())
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -443,7 +620,7 @@ mod test {
         request: Vec<Message>,
     ) -> Option<Vec<Message>>
     where
-        CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
+        CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
     {
         let mut result = Vec::new();
         let mut assembler_reply = MessageAssembler::new();
@@ -468,7 +645,7 @@ mod test {
         ctap_state: &mut CtapState<ThreadRng256, CheckUserPresence>,
     ) -> ChannelID
     where
-        CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
+        CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
     {
         let nonce = vec![0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0];
         let reply = process_messages(
@@ -516,10 +693,37 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_request_too_large() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_hid = CtapHid::new();
+        let cid = cid_from_init(&mut ctap_hid, &mut ctap_state);
+
+        let reply = process_messages(
+            &mut ctap_hid,
+            &mut ctap_state,
+            vec![Message {
+                cid,
+                cmd: CtapHid::COMMAND_CBOR,
+                payload: vec![0x04; UsbTransport::MAX_MSG_SIZE + 1],
+            }],
+        );
+        assert_eq!(
+            reply,
+            Some(vec![Message {
+                cid,
+                cmd: CtapHid::COMMAND_CBOR,
+                payload: vec![Ctap2StatusCode::CTAP2_ERR_REQUEST_TOO_LARGE as u8],
+            }])
+        );
+    }
+
     #[test]
     fn test_spurious_continuation_packet() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
         let mut ctap_hid = CtapHid::new();
 
@@ -540,7 +744,7 @@ mod test {
     #[test]
     fn test_command_init() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
         let mut ctap_hid = CtapHid::new();
 
@@ -585,7 +789,7 @@ mod test {
     #[test]
     fn test_command_init_for_sync() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
         let mut ctap_hid = CtapHid::new();
         let cid = cid_from_init(&mut ctap_hid, &mut ctap_state);
@@ -645,7 +849,7 @@ mod test {
     #[test]
     fn test_command_ping() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
         let mut ctap_hid = CtapHid::new();
         let cid = cid_from_init(&mut ctap_hid, &mut ctap_state);
@@ -669,4 +873,45 @@ mod test {
             }])
         );
     }
+
+    #[cfg(feature = "with_nfc")]
+    #[test]
+    fn test_busy_while_nfc_transaction() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_hid = CtapHid::new();
+        let cid = cid_from_init(&mut ctap_hid, &mut ctap_state);
+
+        ctap_state
+            .interface_arbiter
+            .acquire(Interface::Nfc, DUMMY_CLOCK_VALUE)
+            .unwrap();
+        let get_info = Message {
+            cid,
+            cmd: CtapHid::COMMAND_CBOR,
+            payload: vec![0x04],
+        };
+        let reply = process_messages(&mut ctap_hid, &mut ctap_state, vec![get_info.clone()]);
+        assert_eq!(
+            reply,
+            Some(vec![Message {
+                cid,
+                cmd: CtapHid::COMMAND_ERROR,
+                payload: vec![CtapHid::ERR_CHANNEL_BUSY]
+            }])
+        );
+        // Other commands don't start a transaction.
+        let ping = Message {
+            cid,
+            cmd: CtapHid::COMMAND_PING,
+            payload: vec![0x99],
+        };
+        let reply = process_messages(&mut ctap_hid, &mut ctap_state, vec![ping.clone()]);
+        assert_eq!(reply, Some(vec![ping]));
+
+        ctap_state.interface_arbiter.release(Interface::Nfc);
+        let reply = process_messages(&mut ctap_hid, &mut ctap_state, vec![get_info]).unwrap();
+        assert_eq!(reply[0].cmd, CtapHid::COMMAND_CBOR);
+    }
 }
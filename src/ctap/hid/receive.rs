@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{ChannelID, CtapHid, HidPacket, Message, ProcessedPacket};
+use super::{ChannelID, CtapHid, HidPacket, Message, ProcessedPacket, UsbTransport};
 use alloc::vec::Vec;
 use core::mem::swap;
 use libtock_drivers::timer::Timestamp;
@@ -47,6 +47,8 @@ pub enum Error {
     UnexpectedSeq,
     // This packet arrived after a timeout.
     Timeout,
+    // The initialization packet announced a CBOR command longer than maxMsgSize.
+    RequestTooLarge,
 }
 
 impl MessageAssembler {
@@ -107,7 +109,7 @@ impl MessageAssembler {
             // Expecting an initialization packet.
             match processed_packet {
                 ProcessedPacket::InitPacket { cmd, len, data } => {
-                    Ok(self.accept_init_packet(*cid, cmd, len, data, timestamp))
+                    self.accept_init_packet(*cid, cmd, len, data, timestamp)
                 }
                 ProcessedPacket::ContinuationPacket { .. } => {
                     // CTAP specification (version 20190130) section 8.1.5.4
@@ -129,7 +131,7 @@ impl MessageAssembler {
                 ProcessedPacket::InitPacket { cmd, len, data } => {
                     self.reset();
                     if cmd == CtapHid::COMMAND_INIT {
-                        Ok(self.accept_init_packet(*cid, cmd, len, data, timestamp))
+                        self.accept_init_packet(*cid, cmd, len, data, timestamp)
                     } else {
                         Err((*cid, Error::UnexpectedInit))
                     }
@@ -158,17 +160,22 @@ impl MessageAssembler {
         len: usize,
         data: &[u8],
         timestamp: Timestamp<isize>,
-    ) -> Option<Message> {
+    ) -> Result<Option<Message>, (ChannelID, Error)> {
         // TODO: Should invalid commands/payload lengths be rejected early, i.e. as soon as the
         // initialization packet is received, or should we build a message and then catch the
         // error?
         // The specification (version 20190130) isn't clear on this point.
+        // CBOR commands longer than maxMsgSize are rejected before buffering their payload. We
+        // stay idle, so their continuation packets are ignored as spurious.
+        if cmd == CtapHid::COMMAND_CBOR && len > UsbTransport::MAX_MSG_SIZE {
+            return Err((cid, Error::RequestTooLarge));
+        }
         self.cid = cid;
         self.last_timestamp = timestamp;
         self.cmd = cmd;
         self.seq = 0;
         self.remaining_payload_len = len;
-        self.append_payload(data)
+        Ok(self.append_payload(data))
     }
 
     fn append_payload(&mut self, data: &[u8]) -> Option<Message> {
@@ -352,6 +359,34 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_request_too_large() {
+        let mut assembler = MessageAssembler::new();
+        // A CBOR command of 1025 bytes.
+        assert_eq!(
+            assembler.parse_packet(
+                &zero_extend(&[0x12, 0x34, 0x56, 0x78, 0x90, 0x04, 0x01]),
+                DUMMY_TIMESTAMP
+            ),
+            Err(([0x12, 0x34, 0x56, 0x78], Error::RequestTooLarge))
+        );
+        assert_eq!(
+            assembler.parse_packet(
+                &zero_extend(&[0x12, 0x34, 0x56, 0x78, 0x00]),
+                DUMMY_TIMESTAMP
+            ),
+            Err(([0x12, 0x34, 0x56, 0x78], Error::UnexpectedContinuation))
+        );
+        // The limit only applies to CBOR commands.
+        assert_eq!(
+            assembler.parse_packet(
+                &zero_extend(&[0x12, 0x34, 0x56, 0x78, 0x81, 0x04, 0x01]),
+                DUMMY_TIMESTAMP
+            ),
+            Ok(None)
+        );
+    }
+
     #[test]
     fn test_multiple_messages() {
         // Check that after yielding a message, the assembler is ready to process new messages.
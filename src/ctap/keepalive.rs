@@ -0,0 +1,129 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::hid::{ChannelID, KeepaliveStatus};
+use super::status_code::Ctap2StatusCode;
+use super::transport::transport_of;
+#[cfg(not(feature = "std"))]
+use libtock_drivers::timer;
+use libtock_drivers::timer::{ClockValue, Duration};
+
+// The CTAPHID specification asks for a keepalive at least every 100 ms while processing.
+const KEEPALIVE_INTERVAL: Duration<isize> = Duration::from_ms(100);
+
+// Sends keepalives while a command is processed, at most once per interval. Slow steps, like key
+// generation, store writes that may compact pages, or large blob commits, tick before they start.
+// The user presence check sends its own keepalives with the UPNEEDED status.
+pub struct KeepaliveScheduler {
+    // When the last keepalive was sent, or when the command started.
+    last_keepalive: Option<ClockValue>,
+}
+
+impl KeepaliveScheduler {
+    pub fn new() -> KeepaliveScheduler {
+        KeepaliveScheduler {
+            last_keepalive: None,
+        }
+    }
+
+    // Starts the first interval, when a command is received.
+    pub fn start(&mut self) {
+        self.last_keepalive = current_clock();
+    }
+
+    // Sends a keepalive on the channel if the interval elapsed.
+    pub fn tick(&mut self, cid: ChannelID, status: KeepaliveStatus) -> Result<(), Ctap2StatusCode> {
+        if let Some(now) = current_clock() {
+            if self.is_due(now) {
+                transport_of(cid).send_keepalive(cid, status)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Returns whether a keepalive is due, and starts the next interval if so.
+    fn is_due(&mut self, now: ClockValue) -> bool {
+        let is_due = match self.last_keepalive.and_then(|last| now.wrapping_sub(last)) {
+            Some(elapsed) => elapsed >= KEEPALIVE_INTERVAL,
+            // Without a start or with a different clock frequency, we can't tell.
+            None => true,
+        };
+        if is_due {
+            self.last_keepalive = Some(now);
+        }
+        is_due
+    }
+}
+
+// Reads the clock of the timer driver.
+#[cfg(not(feature = "std"))]
+fn current_clock() -> Option<ClockValue> {
+    let mut with_callback = timer::with_callback(|_, _| {});
+    let timer = with_callback.init().ok()?;
+    timer.get_current_clock().ok()
+}
+
+// Tests run without the timer driver, so they never send keepalives.
+#[cfg(feature = "std")]
+fn current_clock() -> Option<ClockValue> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLOCK_FREQUENCY_HZ: usize = 32768;
+
+    fn clock_value_ms(ms: isize) -> ClockValue {
+        ClockValue::new(ms * CLOCK_FREQUENCY_HZ as isize / 1000, CLOCK_FREQUENCY_HZ)
+    }
+
+    #[test]
+    fn test_is_due() {
+        let mut keepalive = KeepaliveScheduler::new();
+        keepalive.last_keepalive = Some(clock_value_ms(1000));
+        assert!(!keepalive.is_due(clock_value_ms(1050)));
+        // Clock values are truncated to ticks, so the test stays clear of the interval's end.
+        assert!(keepalive.is_due(clock_value_ms(1110)));
+        // The interval restarts with the keepalive.
+        assert!(!keepalive.is_due(clock_value_ms(1160)));
+        assert!(keepalive.is_due(clock_value_ms(1250)));
+    }
+
+    #[test]
+    fn test_is_due_without_start() {
+        let mut keepalive = KeepaliveScheduler::new();
+        assert!(keepalive.is_due(clock_value_ms(1000)));
+        assert!(!keepalive.is_due(clock_value_ms(1000)));
+    }
+
+    #[test]
+    fn test_is_due_different_frequency() {
+        let mut keepalive = KeepaliveScheduler::new();
+        keepalive.last_keepalive = Some(clock_value_ms(1000));
+        assert!(keepalive.is_due(ClockValue::new(0, 1000)));
+    }
+
+    #[test]
+    fn test_tick_without_clock() {
+        let mut keepalive = KeepaliveScheduler::new();
+        keepalive.start();
+        assert_eq!(
+            keepalive.tick([0x12, 0x34, 0x56, 0x78], KeepaliveStatus::Processing),
+            Ok(())
+        );
+        assert!(keepalive.last_keepalive.is_none());
+    }
+}
@@ -0,0 +1,439 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::command::AuthenticatorLargeBlobsParameters;
+use super::crypto_backend;
+use super::pin_protocol_v1::{PinPermission, PinProtocolV1, PIN_PROTOCOLS};
+use super::response::{AuthenticatorLargeBlobsResponse, ResponseData};
+use super::status_code::Ctap2StatusCode;
+use super::storage::{PersistentStore, MAX_LARGE_BLOB_ARRAY_SIZE};
+use alloc::vec;
+use alloc::vec::Vec;
+use byteorder::{ByteOrder, LittleEndian};
+use crypto::sha256::Sha256;
+use crypto::Hash256;
+
+// The large blob array ends with the first 16 bytes of the SHA-256 of everything before.
+const TRUNCATED_HASH_LENGTH: usize = 16;
+// The shortest valid array is the empty CBOR array followed by its hash.
+const MIN_LARGE_BLOB_ARRAY_SIZE: usize = 1 + TRUNCATED_HASH_LENGTH;
+// Room left in a message for the CBOR overhead around a fragment.
+const FRAGMENT_OVERHEAD: usize = 64;
+
+// The array is only committed to the store once all fragments of a write are received. Until
+// then, they are buffered in RAM. The integrity hash is updated with each fragment as it arrives,
+// unless the SoC has a hash engine. The engine hashes the whole array at once, but only runs one
+// hash at a time, so it can't keep a hash open between commands.
+pub struct LargeBlobs {
+    buffer: Vec<u8>,
+    hasher: Sha256,
+    expected_length: usize,
+    expected_next_offset: usize,
+}
+
+impl LargeBlobs {
+    pub fn new() -> LargeBlobs {
+        LargeBlobs {
+            buffer: Vec::new(),
+            hasher: Sha256::new(),
+            expected_length: 0,
+            expected_next_offset: 0,
+        }
+    }
+
+    pub fn process_command(
+        &mut self,
+        persistent_store: &mut PersistentStore,
+        pin_protocol_v1: &mut PinProtocolV1,
+        large_blobs_params: AuthenticatorLargeBlobsParameters,
+        max_msg_size: usize,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        let AuthenticatorLargeBlobsParameters {
+            get,
+            set,
+            offset,
+            length,
+            pin_uv_auth_param,
+            pin_uv_auth_protocol,
+        } = large_blobs_params;
+        let max_fragment_length = max_msg_size - FRAGMENT_OVERHEAD;
+
+        if let Some(get) = get {
+            if get > max_fragment_length {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH);
+            }
+            let config = persistent_store.get_large_blob_array(offset, get)?;
+            return Ok(ResponseData::AuthenticatorLargeBlobs(Some(
+                AuthenticatorLargeBlobsResponse { config },
+            )));
+        }
+
+        // The command parameters contain either get or set.
+        let mut set = set.ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+        if set.len() > max_fragment_length {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH);
+        }
+        if offset == 0 {
+            // The command parameters contain the length for the first fragment.
+            let length = length.ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+            if length > MAX_LARGE_BLOB_ARRAY_SIZE {
+                return Err(Ctap2StatusCode::CTAP2_ERR_LARGE_BLOB_STORAGE_FULL);
+            }
+            if length < MIN_LARGE_BLOB_ARRAY_SIZE {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+            }
+            self.expected_length = length;
+            self.expected_next_offset = 0;
+        }
+        if offset != self.expected_next_offset {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_SEQ);
+        }
+        if persistent_store.pin_hash()?.is_some() {
+            let pin_uv_auth_param =
+                pin_uv_auth_param.ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)?;
+            let pin_uv_auth_protocol = match pin_uv_auth_protocol {
+                Some(protocol) if PIN_PROTOCOLS.contains(&protocol) => protocol,
+                Some(_) => return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID),
+                None => return Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER),
+            };
+            // The message is 32 bytes 0xFF, the command byte, 0x00, the offset as a little endian
+            // 32 bit integer and the hash of the fragment.
+            let mut message = vec![0xFF; 32];
+            message.extend(&[0x0C, 0x00]);
+            let mut offset_bytes = [0u8; 4];
+            LittleEndian::write_u32(&mut offset_bytes, offset as u32);
+            message.extend(&offset_bytes);
+            message.extend(&crypto_backend::sha256(&[&set]));
+            if !pin_protocol_v1.verify_pin_auth_token(
+                &message,
+                &pin_uv_auth_param,
+                pin_uv_auth_protocol,
+            ) {
+                return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+            }
+            pin_protocol_v1.has_permission(PinPermission::LargeBlobWrite)?;
+        }
+        if offset + set.len() > self.expected_length {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+        if offset == 0 {
+            self.buffer = Vec::with_capacity(self.expected_length);
+            self.hasher = Sha256::new();
+        }
+        // The length is at least MIN_LARGE_BLOB_ARRAY_SIZE.
+        let hash_index = self.expected_length - TRUNCATED_HASH_LENGTH;
+        if offset < hash_index && !crypto_backend::has_sha256_engine() {
+            let hashed_length = core::cmp::min(set.len(), hash_index - offset);
+            self.hasher.update(&set[..hashed_length]);
+        }
+        self.buffer.append(&mut set);
+        self.expected_next_offset = self.buffer.len();
+        if self.expected_next_offset == self.expected_length {
+            self.expected_length = 0;
+            self.expected_next_offset = 0;
+            let buffer = core::mem::take(&mut self.buffer);
+            let hasher = core::mem::replace(&mut self.hasher, Sha256::new());
+            let hash = if crypto_backend::has_sha256_engine() {
+                crypto_backend::sha256(&[&buffer[..hash_index]])
+            } else {
+                hasher.finalize()
+            };
+            if hash[..TRUNCATED_HASH_LENGTH] != buffer[hash_index..] {
+                return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
+            }
+            persistent_store.commit_large_blob_array(&buffer)?;
+        }
+        Ok(ResponseData::AuthenticatorLargeBlobs(None))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::hmac::hmac_256;
+    use crypto::rng256::ThreadRng256;
+
+    const MAX_MSG_SIZE: usize = 1024;
+
+    fn create_large_blob_array(length: usize) -> Vec<u8> {
+        let mut large_blob_array = vec![0xC0; length - TRUNCATED_HASH_LENGTH];
+        let hash = Sha256::hash(&large_blob_array);
+        large_blob_array.extend(&hash[..TRUNCATED_HASH_LENGTH]);
+        large_blob_array
+    }
+
+    fn create_set_params(
+        set: &[u8],
+        offset: usize,
+        length: Option<usize>,
+    ) -> AuthenticatorLargeBlobsParameters {
+        AuthenticatorLargeBlobsParameters {
+            get: None,
+            set: Some(set.to_vec()),
+            offset,
+            length,
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        }
+    }
+
+    fn create_get_params(get: usize, offset: usize) -> AuthenticatorLargeBlobsParameters {
+        AuthenticatorLargeBlobsParameters {
+            get: Some(get),
+            set: None,
+            offset,
+            length: None,
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        }
+    }
+
+    #[test]
+    fn test_process_command_get_empty() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let mut large_blobs = LargeBlobs::new();
+
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            create_get_params(MIN_LARGE_BLOB_ARRAY_SIZE, 0),
+            MAX_MSG_SIZE,
+        );
+        match response.unwrap() {
+            ResponseData::AuthenticatorLargeBlobs(Some(response)) => {
+                // The empty array is the CBOR value 0x80 followed by its truncated hash.
+                assert_eq!(response.config.len(), MIN_LARGE_BLOB_ARRAY_SIZE);
+                assert_eq!(response.config[0], 0x80);
+                assert_eq!(
+                    &response.config[1..],
+                    &Sha256::hash(&[0x80])[..TRUNCATED_HASH_LENGTH]
+                );
+            }
+            _ => panic!("Invalid response type"),
+        };
+    }
+
+    #[test]
+    fn test_process_command_commit_and_get() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let mut large_blobs = LargeBlobs::new();
+
+        const BLOB_LEN: usize = 200;
+        const DATA_LEN: usize = BLOB_LEN - TRUNCATED_HASH_LENGTH;
+        let large_blob_array = create_large_blob_array(BLOB_LEN);
+
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            create_set_params(&large_blob_array[..BLOB_LEN / 2], 0, Some(BLOB_LEN)),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Ok(ResponseData::AuthenticatorLargeBlobs(None)));
+
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            create_set_params(&large_blob_array[BLOB_LEN / 2..], BLOB_LEN / 2, None),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Ok(ResponseData::AuthenticatorLargeBlobs(None)));
+
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            create_get_params(DATA_LEN, 0),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorLargeBlobs(Some(
+                AuthenticatorLargeBlobsResponse {
+                    config: large_blob_array[..DATA_LEN].to_vec(),
+                }
+            )))
+        );
+    }
+
+    #[test]
+    fn test_process_command_commit_unexpected_offset() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let mut large_blobs = LargeBlobs::new();
+
+        const BLOB_LEN: usize = 200;
+        let large_blob_array = create_large_blob_array(BLOB_LEN);
+
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            create_set_params(&large_blob_array[..BLOB_LEN / 2], 0, Some(BLOB_LEN)),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Ok(ResponseData::AuthenticatorLargeBlobs(None)));
+
+        // The second fragment skips a byte.
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            create_set_params(
+                &large_blob_array[BLOB_LEN / 2 + 1..],
+                BLOB_LEN / 2 + 1,
+                None,
+            ),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_SEQ));
+    }
+
+    #[test]
+    fn test_process_command_commit_too_long() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let mut large_blobs = LargeBlobs::new();
+
+        const BLOB_LEN: usize = 200;
+        let large_blob_array = create_large_blob_array(BLOB_LEN);
+
+        // The announced length is shorter than the data.
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            create_set_params(&large_blob_array, 0, Some(BLOB_LEN - 1)),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER));
+
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            create_set_params(&large_blob_array, 0, Some(MAX_LARGE_BLOB_ARRAY_SIZE + 1)),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(
+            response,
+            Err(Ctap2StatusCode::CTAP2_ERR_LARGE_BLOB_STORAGE_FULL)
+        );
+
+        // The fragment doesn't fit in a message.
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            create_get_params(MAX_MSG_SIZE, 0),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH));
+    }
+
+    #[test]
+    fn test_process_command_commit_hash_mismatch() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let mut large_blobs = LargeBlobs::new();
+
+        const BLOB_LEN: usize = 200;
+        let mut large_blob_array = create_large_blob_array(BLOB_LEN);
+        large_blob_array[BLOB_LEN - 1] ^= 0x01;
+
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            create_set_params(&large_blob_array, 0, Some(BLOB_LEN)),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE));
+    }
+
+    #[test]
+    fn test_process_command_commit_fragment_inside_hash() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let mut large_blobs = LargeBlobs::new();
+
+        const BLOB_LEN: usize = 200;
+        // The second fragment starts inside the truncated hash, and isn't hashed.
+        const SPLIT: usize = BLOB_LEN - TRUNCATED_HASH_LENGTH / 2;
+        let mut large_blob_array = create_large_blob_array(BLOB_LEN);
+
+        for expected_response in &[
+            Ok(ResponseData::AuthenticatorLargeBlobs(None)),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE),
+        ] {
+            let response = large_blobs.process_command(
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                create_set_params(&large_blob_array[..SPLIT], 0, Some(BLOB_LEN)),
+                MAX_MSG_SIZE,
+            );
+            assert_eq!(response, Ok(ResponseData::AuthenticatorLargeBlobs(None)));
+            let response = large_blobs.process_command(
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                create_set_params(&large_blob_array[SPLIT..], SPLIT, None),
+                MAX_MSG_SIZE,
+            );
+            assert_eq!(response, *expected_response);
+            // The data is not covered by its hash anymore.
+            large_blob_array[0] ^= 0x01;
+        }
+    }
+
+    #[test]
+    fn test_process_command_commit_with_pin() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut large_blobs = LargeBlobs::new();
+        persistent_store.set_pin_hash(&[0x88; 16]).unwrap();
+
+        const BLOB_LEN: usize = 20;
+        let large_blob_array = create_large_blob_array(BLOB_LEN);
+
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            create_set_params(&large_blob_array, 0, Some(BLOB_LEN)),
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED));
+
+        let mut message = vec![0xFF; 32];
+        message.extend(&[0x0C, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        message.extend(&Sha256::hash(&large_blob_array));
+        let pin_uv_auth_param = hmac_256::<Sha256>(&pin_uv_auth_token, &message)[..16].to_vec();
+        let mut params = create_set_params(&large_blob_array, 0, Some(BLOB_LEN));
+        params.pin_uv_auth_param = Some(pin_uv_auth_param);
+        params.pin_uv_auth_protocol = Some(1);
+        let response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            params,
+            MAX_MSG_SIZE,
+        );
+        assert_eq!(response, Ok(ResponseData::AuthenticatorLargeBlobs(None)));
+        assert_eq!(
+            persistent_store.get_large_blob_array(0, BLOB_LEN),
+            Ok(large_blob_array)
+        );
+    }
+}
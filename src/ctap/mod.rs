@@ -13,57 +13,136 @@
 // limitations under the License.
 
 pub mod apdu;
+#[cfg(feature = "with_nfc")]
+pub mod arbiter;
+mod attestation;
+mod backup;
+mod benchmark;
+#[cfg(feature = "with_ctap2_1")]
+mod bio_enrollment;
 pub mod command;
+#[cfg(feature = "with_ctap2_1")]
+mod config_command;
+pub mod cose;
+#[cfg(feature = "with_ctap2_1")]
+mod credential_management;
+mod crypto_backend;
 #[cfg(feature = "with_ctap1")]
 mod ctap1;
 pub mod data_formats;
+mod device_key;
+mod extensions;
+mod fault;
 pub mod hid;
+mod keepalive;
 mod key_material;
+#[cfg(feature = "with_ctap2_1")]
+mod large_blobs;
+#[cfg(feature = "with_nfc")]
+pub mod nfc;
 mod pin_protocol_v1;
 pub mod response;
+pub mod self_test;
+mod signature_counter;
 pub mod status_code;
+pub mod status_leds;
 mod storage;
 mod timed_permission;
+mod token_state;
+pub mod transport;
 
+#[cfg(feature = "with_nfc")]
+use self::arbiter::InterfaceArbiter;
+use self::attestation::{packed_attestation_statement, AttestationType};
+use self::backup::BackupKey;
 #[cfg(feature = "with_ctap2_1")]
-use self::command::MAX_CREDENTIAL_COUNT_IN_LIST;
+use self::bio_enrollment::{BioEnrollment, DefaultFingerprintSensor, FINGERPRINT_UV_MODALITY};
+#[cfg(feature = "with_ctap2_1")]
+use self::command::{
+    AuthenticatorBioEnrollmentParameters, AuthenticatorConfigParameters,
+    AuthenticatorCredentialManagementParameters, AuthenticatorLargeBlobsParameters,
+    MAX_CREDENTIAL_COUNT_IN_LIST,
+};
 use self::command::{
     AuthenticatorClientPinParameters, AuthenticatorGetAssertionParameters,
-    AuthenticatorMakeCredentialParameters, AuthenticatorVendorConfigureParameters, Command,
+    AuthenticatorMakeCredentialParameters, AuthenticatorVendorBackupParameters,
+    AuthenticatorVendorConfigureParameters, AuthenticatorVendorProvisionParameters,
+    AuthenticatorVendorRestoreParameters, Command,
 };
 #[cfg(feature = "with_ctap2_1")]
-use self::data_formats::AuthenticatorTransport;
+use self::config_command::process_config;
+use self::cose::CoseKey;
+#[cfg(feature = "with_ctap2_1")]
+use self::credential_management::process_credential_management;
+use self::crypto_backend::{CryptoBackend, DefaultCryptoBackend};
 use self::data_formats::{
-    CredentialProtectionPolicy, GetAssertionHmacSecretInput, PackedAttestationStatement,
-    PublicKeyCredentialDescriptor, PublicKeyCredentialParameter, PublicKeyCredentialSource,
+    AuthenticatorTransport, ClientPinSubCommand, CredentialProtectionPolicy,
+    GetAssertionExtensions, PrivateKey, PublicKeyCredentialDescriptor,
+    PublicKeyCredentialDescriptorList, PublicKeyCredentialParameter, PublicKeyCredentialSource,
     PublicKeyCredentialType, PublicKeyCredentialUserEntity, SignatureAlgorithm,
 };
-use self::hid::ChannelID;
+#[cfg(feature = "with_ctap2_1")]
+use self::data_formats::{CredentialManagementSubCommand, EnterpriseAttestationMode};
+use self::extensions::{
+    check_get_assertion_extensions, process_get_assertion_extensions,
+    process_make_credential_extensions, process_make_credential_key_extensions, CredentialEnv,
+    CredentialExtensionData, MakeCredentialEnv, UnsignedExtensionOutputs,
+    USER_VERIFY_FINGERPRINT_INTERNAL, USER_VERIFY_PASSCODE_EXTERNAL, USER_VERIFY_PRESENCE_INTERNAL,
+};
+use self::fault::RedundantFlags;
+use self::hid::{ChannelID, KeepaliveStatus};
+use self::keepalive::KeepaliveScheduler;
+#[cfg(feature = "with_ctap2_1")]
+use self::large_blobs::LargeBlobs;
+#[cfg(feature = "with_nfc")]
+use self::nfc::{NfcStatistics, NFC_CHANNEL_ID};
 #[cfg(feature = "with_ctap2_1")]
 use self::pin_protocol_v1::PinPermission;
 use self::pin_protocol_v1::PinProtocolV1;
+use self::pin_protocol_v1::PIN_PROTOCOLS;
 use self::response::{
     AuthenticatorGetAssertionResponse, AuthenticatorGetInfoResponse,
-    AuthenticatorMakeCredentialResponse, AuthenticatorVendorResponse, ResponseData,
+    AuthenticatorMakeCredentialResponse, AuthenticatorVendorAuditLogResponse,
+    AuthenticatorVendorBackupResponse, AuthenticatorVendorCompactResponse,
+    AuthenticatorVendorDiagnosticsResponse, AuthenticatorVendorProvisionResponse,
+    AuthenticatorVendorResponse, AuthenticatorVendorRestoreResponse,
+    AuthenticatorVendorRotateMasterKeysResponse, AuthenticatorVendorSelfTestResponse,
+    AuthenticatorVendorWearResponse, ResponseData,
+};
+#[cfg(feature = "with_nfc")]
+use self::response::{
+    AuthenticatorVendorNfcSelfTestResponse, AuthenticatorVendorNfcStatisticsResponse,
 };
+use self::self_test::SelfTestFailure;
 use self::status_code::Ctap2StatusCode;
-use self::storage::PersistentStore;
+use self::status_leds::StatusLeds;
+use self::storage::{AuditEvent, AuditRecord, PersistentStore};
+#[cfg(feature = "with_ctap2_1")]
+use self::storage::{MAX_LARGE_BLOB_ARRAY_SIZE, MAX_RP_IDS_LENGTH};
 use self::timed_permission::TimedPermission;
 #[cfg(feature = "with_ctap1")]
 use self::timed_permission::U2fUserPresenceState;
+use self::transport::transport_of;
+#[cfg(feature = "with_ctap2_1")]
+use self::transport::TRANSPORTS;
 use alloc::collections::BTreeMap;
 use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
 use arrayref::array_ref;
 use byteorder::{BigEndian, ByteOrder};
-use cbor::{cbor_map, cbor_map_options};
+use cbor::cbor_map_btree;
+use core::convert::TryFrom;
 #[cfg(feature = "debug_ctap")]
 use core::fmt::Write;
-use crypto::cbc::{cbc_decrypt, cbc_encrypt};
+use crypto::cbc::cbc_decrypt;
+use crypto::constant_time::bytes_eq;
+use crypto::gcm::{gcm_decrypt, gcm_encrypt, NONCE_SIZE, TAG_SIZE};
+use crypto::hkdf::hkdf_empty_salt_256;
 use crypto::hmac::{hmac_256, verify_hmac_256};
 use crypto::rng256::Rng256;
 use crypto::sha256::Sha256;
+use crypto::zeroize::Zeroize;
 use crypto::Hash256;
 #[cfg(feature = "debug_ctap")]
 use libtock_drivers::console::Console;
@@ -74,21 +153,58 @@ use libtock_drivers::timer::{ClockValue, Duration};
 // this setting. The basic attestation uses the signing key from key_material.rs
 // as a batch key. Turn it on if you want attestation. In this case, be aware that
 // it is your responsibility to generate your own key material and keep it secret.
+// Without it, credentials are self attested, unless the request is granted enterprise
+// attestation. The x5c chain is the certificate followed by the stored intermediates.
 const USE_BATCH_ATTESTATION: bool = false;
-// The signature counter is currently implemented as a global counter, if you set
-// this flag to true. The spec strongly suggests to have per-credential-counters,
-// but it means you can't have an infinite amount of credentials anymore. Also,
-// since this is the only piece of information that needs writing often, we might
-// need a flash storage friendly way to implement this feature. The implemented
-// solution is a compromise to be compatible with U2F and not wasting storage.
+// Enterprise attestation identifies the individual authenticator to the RP. It also uses the
+// signing key from key_material.rs, so the certificate should carry the device serial number.
+// - Some(EnterpriseAttestationMode::VendorFacilitated) only attests to RPs listed in
+//   ENTERPRISE_RP_ID_LIST.
+// - Some(EnterpriseAttestationMode::PlatformManaged) additionally attests to any RP if the
+//   platform asks for it.
+// In both cases, the feature still has to be enabled through authenticatorConfig.
+#[cfg(feature = "with_ctap2_1")]
+const ENTERPRISE_ATTESTATION_MODE: Option<EnterpriseAttestationMode> = None;
+#[cfg(feature = "with_ctap2_1")]
+const ENTERPRISE_RP_ID_LIST: &[&str] = &[];
+// Signature counters are incremented on each use if you set this flag to true. Resident
+// credentials have their own counter, stored with the credential, as the spec strongly suggests.
+// A global counter would let relying parties correlate a user across sites.
 const USE_SIGNATURE_COUNTER: bool = true;
+// Non-resident credentials are not stored, so they can't have their own counter. If you set this
+// flag to true, they share a global counter, which is what U2F expects. Otherwise, their counter is
+// always 0, which tells relying parties that the credential has no counter.
+const USE_GLOBAL_COUNTER_FOR_NON_RESIDENT: bool = true;
+// If you set this flag to true, relying parties see an encrypted signature counter instead of the
+// real one. It still strictly increases for each credential, but it doesn't reveal how often the
+// authenticator was used, nor links credentials through close counter values. Set it to false if
+// your relying parties expect small counters, or increments of 1.
+const ENCRYPT_SIGNATURE_COUNTER: bool = false;
 pub const INITIAL_SIGNATURE_COUNTER: u32 = 1;
 // Our credential ID consists of
-// - 16 byte initialization vector for AES-256,
-// - 32 byte ECDSA private key for the credential,
+// - 1 byte version, that selects the format and the derivation of its key,
+// - 1 byte generation of the master keys it is wrapped with,
+// - 1 byte credProtect policy, or 0 without policy,
+// - 12 byte nonce for AES-256-GCM,
+// - 33 byte encrypted key algorithm and private key for the credential,
+// - 16 byte GCM tag.
+// The tag authenticates the version, the generation, the policy and the relying party ID hash, on
+// top of the encrypted bytes.
+pub const CREDENTIAL_ID_SIZE: usize = 64;
+const CREDENTIAL_ID_VERSION: u8 = 0x02;
+// Credential IDs of the first GCM version lack the generation, and are wrapped with the first
+// generation of master keys.
+const V1_CREDENTIAL_ID_SIZE: usize = 63;
+const V1_CREDENTIAL_ID_VERSION: u8 = 0x01;
+// Credential IDs of older versions are still accepted. They consist of
+// - 16 byte initialization vector for AES-256-CBC,
+// - 32 byte private key for the credential,
 // - 32 byte relying party ID hashed with SHA256,
+// - 16 byte block starting with the credProtect policy and the key algorithm,
 // - 32 byte HMAC-SHA256 over everything else.
-pub const CREDENTIAL_ID_SIZE: usize = 112;
+const CBC_CREDENTIAL_ID_SIZE: usize = 128;
+// The oldest credential IDs also lack the credProtect block, and have no credProtect policy.
+const LEGACY_CREDENTIAL_ID_SIZE: usize = 112;
 // Set this bit when checking user presence.
 const UP_FLAG: u8 = 0x01;
 // Set this bit when checking user verification.
@@ -98,11 +214,37 @@ const AT_FLAG: u8 = 0x40;
 // Set this bit when an extension is used.
 const ED_FLAG: u8 = 0x80;
 
+// How long a user presence check waits for a touch. This and the following user presence settings
+// suit a mechanical button. Vendors can override them per device with the vendor configure command.
 pub const TOUCH_TIMEOUT_MS: isize = 30000;
+// How long the button must be held for a touch to count. Capacitive buttons may need a longer
+// touch to ignore accidental contact, mechanical buttons debounce in hardware.
+const TOUCH_DURATION_MS: isize = 0;
+// Whether a second touch confirms destructive operations, i.e. a reset.
+const CONFIRM_WITH_SECOND_TOUCH: bool = false;
+// Platforms give up on requests after a few minutes, so longer timeouts are not useful.
+const MAX_TOUCH_TIMEOUT_MS: u32 = 120000;
+// Whether the user picks one of several discoverable credentials on the authenticator. Each touch
+// shows the next candidate on the LEDs, and waiting selects the shown one. Otherwise, the platform
+// asks the user and gets the other credentials with GetNextAssertion.
+const SELECT_CREDENTIAL_WITH_TOUCHES: bool = false;
+// Waiting this long without a touch selects the shown candidate.
+const CREDENTIAL_SELECTION_STEP_DURATION: Duration<isize> = Duration::from_ms(3000);
+// The selection times out after this many touches, e.g. if the button is stuck.
+const MAX_CREDENTIAL_SELECTION_TOUCHES: usize = 32;
+// Whether the PIN can only be set or changed over USB. All other PIN subcommands still work over
+// NFC. Set it to true for deployments that don't want new PINs entered near readers in public.
+const SET_PIN_OVER_USB_ONLY: bool = false;
 #[cfg(feature = "with_ctap1")]
 const U2F_UP_PROMPT_TIMEOUT: Duration<isize> = Duration::from_ms(10000);
+// Resets are only accepted this long after power-up, and over the transport of the first command.
 const RESET_TIMEOUT_DURATION: Duration<isize> = Duration::from_ms(10000);
 const STATEFUL_COMMAND_TIMEOUT_DURATION: Duration<isize> = Duration::from_ms(30000);
+// The number of credentials in each response of a backup. Each record is at most a few hundred
+// bytes, so that a page fits a message.
+const BACKUP_PAGE_LENGTH: usize = 4;
+// Iterations over long lists check for a CTAPHID_CANCEL before each entry, for this long.
+const CANCEL_POLL_DURATION: Duration<isize> = Duration::from_ms(1);
 
 pub const FIDO2_VERSION_STRING: &str = "FIDO_2_0";
 #[cfg(feature = "with_ctap1")]
@@ -111,16 +253,45 @@ pub const U2F_VERSION_STRING: &str = "U2F_V2";
 #[cfg(feature = "with_ctap2_1")]
 pub const FIDO2_1_VERSION_STRING: &str = "FIDO_2_1_PRE";
 
-// We currently only support one algorithm for signatures: ES256.
-// This algorithm is requested in MakeCredential and advertized in GetInfo.
+// We support three algorithms for signatures: ES256, EdDSA (with Ed25519) and ES384.
+// These algorithms are requested in MakeCredential and advertized in GetInfo.
 pub const ES256_CRED_PARAM: PublicKeyCredentialParameter = PublicKeyCredentialParameter {
     cred_type: PublicKeyCredentialType::PublicKey,
     alg: SignatureAlgorithm::ES256,
 };
+pub const EDDSA_CRED_PARAM: PublicKeyCredentialParameter = PublicKeyCredentialParameter {
+    cred_type: PublicKeyCredentialType::PublicKey,
+    alg: SignatureAlgorithm::EDDSA,
+};
+pub const ES384_CRED_PARAM: PublicKeyCredentialParameter = PublicKeyCredentialParameter {
+    cred_type: PublicKeyCredentialType::PublicKey,
+    alg: SignatureAlgorithm::ES384,
+};
+// Identifies the algorithm of the private key inside a credential ID. Credential IDs created before
+// EdDSA support decrypt to 0 here.
+const ES256_KEY_HANDLE_ALGORITHM: u8 = 0;
+const EDDSA_KEY_HANDLE_ALGORITHM: u8 = 1;
+const ES384_KEY_HANDLE_ALGORITHM: u8 = 2;
 // You can change this value to one of the following for more privacy.
 // - Some(CredentialProtectionPolicy::UserVerificationOptionalWithCredentialIdList)
 // - Some(CredentialProtectionPolicy::UserVerificationRequired)
+// Vendors can also override it per device with the vendor configure command.
 const DEFAULT_CRED_PROTECT: Option<CredentialProtectionPolicy> = None;
+// The credBlob extension stores at most this many bytes with each resident credential. CTAP 2.1
+// requires at least 32, and longer blobs make credentials use more storage.
+#[cfg(feature = "with_ctap2_1")]
+const MAX_CRED_BLOB_LENGTH: usize = 32;
+// How many times the platform should try built-in user verification before it falls back to the
+// PIN. It is only reported with a fingerprint sensor.
+#[cfg(feature = "with_ctap2_1")]
+const PREFERRED_PLATFORM_UV_ATTEMPTS: u64 = 1;
+// The certifications of the authenticator, as pairs of a name from the FIDO registry and a level,
+// for example ("FIDO", 1). Only list certifications that the shipped firmware actually received.
+#[cfg(feature = "with_ctap2_1")]
+const CERTIFICATIONS: &[(&str, u64)] = &[];
+// The vendor command IDs accepted by the vendorPrototype subcommand of authenticatorConfig.
+#[cfg(feature = "with_ctap2_1")]
+const VENDOR_PROTOTYPE_CONFIG_COMMANDS: &[u64] = &[];
 
 // This function is adapted from https://doc.rust-lang.org/nightly/src/core/str/mod.rs.html#2110
 // (as of 2020-01-20) and truncates to "max" bytes, not breaking the encoding.
@@ -136,12 +307,53 @@ fn truncate_to_char_boundary(s: &str, mut max: usize) -> &str {
     }
 }
 
+// Returns whether the PIN subcommand is allowed over the transport. Setting and changing the PIN
+// can be restricted to USB, all other subcommands work over every transport.
+fn is_pin_change_allowed(
+    sub_command: &ClientPinSubCommand,
+    transport: AuthenticatorTransport,
+    usb_only: bool,
+) -> bool {
+    match sub_command {
+        ClientPinSubCommand::SetPin | ClientPinSubCommand::ChangePin => {
+            !usb_only || transport == AuthenticatorTransport::Usb
+        }
+        _ => true,
+    }
+}
+
+// Derives the AES-256-GCM key of the credential ID version from the master encryption key. New
+// versions can change the key without breaking older credential IDs.
+fn credential_id_key(master_key: &[u8; 32], version: u8) -> crypto::aes256::EncryptionKey {
+    let mut info = b"CTAP2 credential ID ".to_vec();
+    info.push(version);
+    crypto::aes256::EncryptionKey::new(&hkdf_empty_salt_256::<Sha256>(master_key, &info))
+}
+
+// The authenticated data of a credential ID are its unencrypted header, followed by the relying
+// party ID hash.
+fn credential_id_aad(header: &[u8], rp_id_hash: &[u8]) -> Vec<u8> {
+    let mut aad = header.to_vec();
+    aad.extend_from_slice(rp_id_hash);
+    aad
+}
+
+// Returns whether a backup exports the credential. Credentials that require user verification are
+// only exported if the backup verified the user.
+fn is_exportable(credential: &PublicKeyCredentialSource, user_verified: bool) -> bool {
+    user_verified
+        || credential.cred_protect_policy
+            != Some(CredentialProtectionPolicy::UserVerificationRequired)
+}
+
 #[derive(Clone)]
 struct AssertionInput {
     client_data_hash: Vec<u8>,
-    auth_data: Vec<u8>,
-    hmac_secret_input: Option<GetAssertionHmacSecretInput>,
+    rp_id_hash: Vec<u8>,
+    flags: RedundantFlags,
+    extensions: GetAssertionExtensions,
     has_uv: bool,
+    user_verification_methods: u32,
 }
 
 struct AssertionState {
@@ -150,18 +362,43 @@ struct AssertionState {
     next_credentials: Vec<PublicKeyCredentialSource>,
 }
 
+// How the board checks for user presence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UserPresenceConfig {
+    // The check fails after this time without a touch.
+    pub timeout: Duration<isize>,
+    // The button must be held at least this long for a touch to count.
+    pub touch_duration: Duration<isize>,
+    // The candidate that the LEDs show while the user selects a credential, counting from 0.
+    pub selection: Option<usize>,
+}
+
 enum StatefulCommand {
     Reset,
     GetAssertion(AssertionState),
+    // Holds the last returned RP ID, the enumeration continues with the next greater one.
+    #[cfg(feature = "with_ctap2_1")]
+    EnumerateRps(String),
+    // Holds the RP ID hash and the storage key of the last returned credential.
+    #[cfg(feature = "with_ctap2_1")]
+    EnumerateCredentials(Vec<u8>, usize),
+    // Holds the backup key, the storage key of the last exported credential and whether the user
+    // was verified. The backup continues with the next greater key.
+    Backup(BackupKey, usize, bool),
+    // Holds the key of the backup being restored and the number of credentials restored so far.
+    Restore(BackupKey, u32),
 }
 
 // This struct currently holds all state, not only the persistent memory. The persistent members are
 // in the persistent store field.
-pub struct CtapState<'a, R: Rng256, CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>>
-{
+pub struct CtapState<
+    'a,
+    R: Rng256,
+    CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
+> {
     rng: &'a mut R,
-    // A function to check user presence, ultimately returning true if user presence was detected,
-    // false otherwise.
+    // A function to check user presence with the given settings, ultimately returning true if user
+    // presence was detected, false otherwise.
     check_user_presence: CheckUserPresence,
     persistent_store: PersistentStore,
     pin_protocol_v1: PinProtocolV1,
@@ -170,15 +407,41 @@ pub struct CtapState<'a, R: Rng256, CheckUserPresence: Fn(ChannelID) -> Result<(
     // The state initializes to Reset and its timeout, and never goes back to Reset.
     stateful_command_permission: TimedPermission,
     stateful_command_type: Option<StatefulCommand>,
+    // The transport of the first command since power-up, e.g. NFC if the field of a reader powered
+    // the authenticator.
+    power_up_transport: Option<AuthenticatorTransport>,
+    // Keeps the platform waiting during slow steps of the current command.
+    keepalive: KeepaliveScheduler,
+    // Tells the user why the last command failed, if they can do something about it.
+    pub status_leds: StatusLeds,
+    // Fragments of a large blob array write that is in progress.
+    #[cfg(feature = "with_ctap2_1")]
+    large_blobs: LargeBlobs,
+    // Enrollment of fingerprints, if the board has a sensor.
+    #[cfg(feature = "with_ctap2_1")]
+    bio_enrollment: BioEnrollment<DefaultFingerprintSensor>,
+    // Counters of the NFC transport, since boot.
+    #[cfg(feature = "with_nfc")]
+    pub nfc_statistics: NfcStatistics,
+    // Keeps USB and NFC transactions from interleaving.
+    #[cfg(feature = "with_nfc")]
+    pub interface_arbiter: InterfaceArbiter,
+    // Set when the NFC self-test took over the driver, so that the main loop issues its reception
+    // and emulation again.
+    #[cfg(feature = "with_nfc")]
+    pub nfc_driver_taken: bool,
+    // Set when the self-test requested by the vendor failed. Until it passes again, no other
+    // command is processed.
+    self_test_failure: Option<SelfTestFailure>,
+    // Secure element that holds the attestation key, if the board has one.
+    crypto_backend: DefaultCryptoBackend,
 }
 
 impl<'a, R, CheckUserPresence> CtapState<'a, R, CheckUserPresence>
 where
     R: Rng256,
-    CheckUserPresence: Fn(ChannelID) -> Result<(), Ctap2StatusCode>,
+    CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
 {
-    pub const PIN_PROTOCOL_VERSION: u64 = 1;
-
     pub fn new(
         rng: &'a mut R,
         check_user_presence: CheckUserPresence,
@@ -198,15 +461,52 @@ where
             ),
             stateful_command_permission: TimedPermission::granted(now, RESET_TIMEOUT_DURATION),
             stateful_command_type: Some(StatefulCommand::Reset),
+            power_up_transport: None,
+            keepalive: KeepaliveScheduler::new(),
+            status_leds: StatusLeds::new(),
+            #[cfg(feature = "with_ctap2_1")]
+            large_blobs: LargeBlobs::new(),
+            #[cfg(feature = "with_ctap2_1")]
+            bio_enrollment: BioEnrollment::new(DefaultFingerprintSensor::new()),
+            #[cfg(feature = "with_nfc")]
+            nfc_statistics: NfcStatistics::default(),
+            #[cfg(feature = "with_nfc")]
+            interface_arbiter: InterfaceArbiter::new(),
+            #[cfg(feature = "with_nfc")]
+            nfc_driver_taken: false,
+            self_test_failure: None,
+            crypto_backend: DefaultCryptoBackend::new(),
         }
     }
 
-    pub fn update_command_permission(&mut self, now: ClockValue) {
+    pub fn self_test_failure(&self) -> Option<SelfTestFailure> {
+        self.self_test_failure
+    }
+
+    // Compacts at most one page of the store, if the next commands might need it. The main loop
+    // calls this while idle, so that a command rarely waits for a compaction. Returns whether a
+    // page was compacted.
+    pub fn compact_store_step(&mut self) -> bool {
+        self.persistent_store.compact_step().unwrap_or(false)
+    }
+
+    pub fn update_timeouts(&mut self, now: ClockValue) {
         self.stateful_command_permission = self.stateful_command_permission.check_expiration(now);
+        self.pin_protocol_v1.update_timeouts(now);
+        self.status_leds.update_timeouts(now);
+    }
+
+    // Forgets the credentials of a GetAssertion, e.g. when the reader that started it left. The
+    // permission to Reset is kept, since it only depends on the time since boot.
+    #[cfg(feature = "with_nfc")]
+    pub fn clear_assertion_state(&mut self) {
+        if let Some(StatefulCommand::GetAssertion(_)) = self.stateful_command_type {
+            self.stateful_command_type = None;
+        }
     }
 
     fn check_command_permission(&mut self, now: ClockValue) -> Result<(), Ctap2StatusCode> {
-        self.update_command_permission(now);
+        self.update_timeouts(now);
         if self.stateful_command_permission.is_granted(now) {
             Ok(())
         } else {
@@ -214,59 +514,198 @@ where
         }
     }
 
-    pub fn increment_global_signature_counter(&mut self) -> Result<(), Ctap2StatusCode> {
-        if USE_SIGNATURE_COUNTER {
-            let increment = self.rng.gen_uniform_u32x8()[0] % 8 + 1;
-            self.persistent_store
-                .incr_global_signature_counter(increment)?;
+    /// Increments the signature counter of a credential and returns its new value.
+    ///
+    /// Also records the use of resident credentials.
+    pub fn increment_signature_counter(
+        &mut self,
+        credential_id: &[u8],
+    ) -> Result<u32, Ctap2StatusCode> {
+        let increment = if USE_SIGNATURE_COUNTER {
+            self.rng.gen_uniform_u32x8()[0] % 8 + 1
+        } else {
+            0
+        };
+        let counter = match self
+            .persistent_store
+            .record_credential_use(credential_id, increment)?
+        {
+            Some(counter) => counter,
+            None => {
+                if USE_GLOBAL_COUNTER_FOR_NON_RESIDENT {
+                    self.persistent_store
+                        .incr_global_signature_counter(increment)?;
+                }
+                self.non_resident_signature_counter()?
+            }
+        };
+        self.signature_counter_output(credential_id, counter)
+    }
+
+    // Returns the signature counter of a credential, as the relying party sees it.
+    fn signature_counter_output(
+        &self,
+        credential_id: &[u8],
+        counter: u32,
+    ) -> Result<u32, Ctap2StatusCode> {
+        if !ENCRYPT_SIGNATURE_COUNTER {
+            return Ok(counter);
+        }
+        // The master keys of the first generation live until the next reset, as the credentials.
+        let mut key = hmac_256::<Sha256>(
+            &self.persistent_store.master_keys()?.hmac,
+            b"signature counter",
+        );
+        let output = signature_counter::encrypt(&key, credential_id, counter);
+        key.zeroize();
+        Ok(output)
+    }
+
+    // Returns the current signature counter of non-resident credentials.
+    fn non_resident_signature_counter(&self) -> Result<u32, Ctap2StatusCode> {
+        if USE_GLOBAL_COUNTER_FOR_NON_RESIDENT {
+            self.persistent_store.global_signature_counter()
+        } else {
+            Ok(0)
         }
-        Ok(())
     }
 
-    // Encrypts the private key and relying party ID hash into a credential ID. Other
-    // information, such as a user name, are not stored, because encrypted credential IDs
-    // are used for credentials stored server-side. Also, we want the key handle to be
-    // compatible with U2F.
+    // Encrypts the private key and key algorithm into a credential ID, bound to the relying party
+    // ID hash and credProtect policy. Other information, such as a user name, are not stored,
+    // because encrypted credential IDs are used for credentials stored server-side. Also, we want
+    // the key handle to be compatible with U2F. The latest generation of master keys wraps it.
     pub fn encrypt_key_handle(
         &mut self,
-        private_key: crypto::ecdsa::SecKey,
+        private_key: PrivateKey,
         application: &[u8; 32],
+        cred_protect_policy: Option<CredentialProtectionPolicy>,
     ) -> Result<Vec<u8>, Ctap2StatusCode> {
-        let master_keys = self.persistent_store.master_keys()?;
-        let aes_enc_key = crypto::aes256::EncryptionKey::new(&master_keys.encryption);
-        let mut sk_bytes = [0; 32];
-        private_key.to_bytes(&mut sk_bytes);
-        let mut iv = [0; 16];
-        iv.copy_from_slice(&self.rng.gen_uniform_u8x32()[..16]);
-
-        let mut blocks = [[0u8; 16]; 4];
-        blocks[0].copy_from_slice(&sk_bytes[..16]);
-        blocks[1].copy_from_slice(&sk_bytes[16..]);
-        blocks[2].copy_from_slice(&application[..16]);
-        blocks[3].copy_from_slice(&application[16..]);
-        cbc_encrypt(&aes_enc_key, iv, &mut blocks);
-
-        let mut encrypted_id = Vec::with_capacity(0x70);
-        encrypted_id.extend(&iv);
-        for b in &blocks {
-            encrypted_id.extend(b);
-        }
-        let id_hmac = hmac_256::<Sha256>(&master_keys.hmac, &encrypted_id[..]);
-        encrypted_id.extend(&id_hmac);
+        let (generation, master_keys) = self.persistent_store.current_master_keys()?;
+        let wrapping_key = credential_id_key(&master_keys.encryption, CREDENTIAL_ID_VERSION);
+        let mut nonce = [0; NONCE_SIZE];
+        nonce.copy_from_slice(&self.rng.gen_uniform_u8x32()[..NONCE_SIZE]);
+
+        let mut encrypted_id = Vec::with_capacity(CREDENTIAL_ID_SIZE);
+        encrypted_id.push(CREDENTIAL_ID_VERSION);
+        encrypted_id.push(generation);
+        encrypted_id.push(cred_protect_policy.map_or(0, |policy| policy as u8));
+        let aad = credential_id_aad(&encrypted_id, application);
+        encrypted_id.extend(&nonce);
+
+        let mut payload = Vec::with_capacity(33);
+        payload.push(match private_key {
+            PrivateKey::Ecdsa(_) => ES256_KEY_HANDLE_ALGORITHM,
+            PrivateKey::Ed25519(_) => EDDSA_KEY_HANDLE_ALGORITHM,
+            PrivateKey::Ecdsa384(_) => ES384_KEY_HANDLE_ALGORITHM,
+        });
+        let mut private_key_bytes = private_key.to_bytes();
+        payload.extend(&private_key_bytes);
+        private_key_bytes.zeroize();
+        let tag = gcm_encrypt(&wrapping_key, &nonce, &aad, &mut payload);
+        encrypted_id.extend(payload);
+        encrypted_id.extend(&tag);
         Ok(encrypted_id)
     }
 
     // Decrypts a credential ID and writes the private key into a PublicKeyCredentialSource.
-    // None is returned if the HMAC test fails or the relying party does not match the
-    // decrypted relying party ID hash.
+    // None is returned if the authentication fails or the relying party does not match the
+    // one of the credential ID.
     pub fn decrypt_credential_source(
         &self,
         credential_id: Vec<u8>,
         rp_id_hash: &[u8],
     ) -> Result<Option<PublicKeyCredentialSource>, Ctap2StatusCode> {
-        if credential_id.len() != CREDENTIAL_ID_SIZE {
+        let unwrapped = match credential_id.len() {
+            CREDENTIAL_ID_SIZE | V1_CREDENTIAL_ID_SIZE => {
+                self.unwrap_credential_id(&credential_id, rp_id_hash)?
+            }
+            CBC_CREDENTIAL_ID_SIZE | LEGACY_CREDENTIAL_ID_SIZE => {
+                self.unwrap_cbc_credential_id(&credential_id, rp_id_hash)?
+            }
+            _ => None,
+        };
+        let (policy, algorithm, mut decrypted_sk) = match unwrapped {
+            Some(unwrapped) => unwrapped,
+            None => return Ok(None),
+        };
+        let cred_protect_policy = match policy {
+            0 => None,
+            policy => Some(
+                CredentialProtectionPolicy::try_from(cbor::Value::from(policy as i64))
+                    .map_err(|_| Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?,
+            ),
+        };
+
+        let algorithm = match algorithm {
+            ES256_KEY_HANDLE_ALGORITHM => SignatureAlgorithm::ES256,
+            EDDSA_KEY_HANDLE_ALGORITHM => SignatureAlgorithm::EDDSA,
+            ES384_KEY_HANDLE_ALGORITHM => SignatureAlgorithm::ES384,
+            _ => return Ok(None),
+        };
+
+        let sk_option = PrivateKey::from_bytes(algorithm, &decrypted_sk);
+        decrypted_sk.zeroize();
+        Ok(sk_option.map(|sk| PublicKeyCredentialSource {
+            key_type: PublicKeyCredentialType::PublicKey,
+            credential_id,
+            private_key: sk,
+            rp_id: String::from(""),
+            user_handle: vec![],
+            user_display_name: None,
+            cred_protect_policy,
+            creation_order: 0,
+            last_used_order: None,
+            signature_counter: None,
+            user_name: None,
+            user_icon: None,
+            large_blob_key: None,
+            cred_blob: None,
+        }))
+    }
+
+    // Returns the credProtect policy, key algorithm and private key of an authentic credential ID.
+    fn unwrap_credential_id(
+        &self,
+        credential_id: &[u8],
+        rp_id_hash: &[u8],
+    ) -> Result<Option<(u8, u8, [u8; 32])>, Ctap2StatusCode> {
+        let version = credential_id[0];
+        let (generation, header_size) = match (credential_id.len(), version) {
+            (CREDENTIAL_ID_SIZE, CREDENTIAL_ID_VERSION) => (credential_id[1], 3),
+            (V1_CREDENTIAL_ID_SIZE, V1_CREDENTIAL_ID_VERSION) => (0, 2),
+            // Unknown versions are rejected, so that new formats can coexist with these ones.
+            _ => return Ok(None),
+        };
+        // Generations are forgotten on reset, like the credentials they wrapped.
+        let master_keys = match self.persistent_store.generation_master_keys(generation)? {
+            Some(master_keys) => master_keys,
+            None => return Ok(None),
+        };
+        let wrapping_key = credential_id_key(&master_keys.encryption, version);
+        let header = &credential_id[..header_size];
+        let aad = credential_id_aad(header, rp_id_hash);
+        let nonce = array_ref![credential_id, header_size, NONCE_SIZE];
+        let payload_end = credential_id.len() - TAG_SIZE;
+        let mut payload = credential_id[header_size + NONCE_SIZE..payload_end].to_vec();
+        let tag = array_ref![credential_id, payload_end, TAG_SIZE];
+        if !gcm_decrypt(&wrapping_key, nonce, &aad, &mut payload, tag) {
             return Ok(None);
         }
+        let unwrapped = (
+            header[header_size - 1],
+            payload[0],
+            *array_ref![payload, 1, 32],
+        );
+        payload.zeroize();
+        Ok(Some(unwrapped))
+    }
+
+    // Same as unwrap_credential_id, for credential IDs of older versions.
+    fn unwrap_cbc_credential_id(
+        &self,
+        credential_id: &[u8],
+        rp_id_hash: &[u8],
+    ) -> Result<Option<(u8, u8, [u8; 32])>, Ctap2StatusCode> {
         let master_keys = self.persistent_store.master_keys()?;
         let payload_size = credential_id.len() - 32;
         if !verify_hmac_256::<Sha256>(
@@ -280,35 +719,27 @@ where
         let aes_dec_key = crypto::aes256::DecryptionKey::new(&aes_enc_key);
         let mut iv = [0; 16];
         iv.copy_from_slice(&credential_id[..16]);
-        let mut blocks = [[0u8; 16]; 4];
-        for i in 0..4 {
+        let num_blocks = payload_size / 16 - 1;
+        let mut blocks = [[0u8; 16]; 5];
+        for i in 0..num_blocks {
             blocks[i].copy_from_slice(&credential_id[16 * (i + 1)..16 * (i + 2)]);
         }
 
-        cbc_decrypt(&aes_dec_key, iv, &mut blocks);
+        cbc_decrypt(&aes_dec_key, iv, &mut blocks[..num_blocks]);
         let mut decrypted_sk = [0; 32];
         let mut decrypted_rp_id_hash = [0; 32];
         decrypted_sk[..16].clone_from_slice(&blocks[0]);
         decrypted_sk[16..].clone_from_slice(&blocks[1]);
         decrypted_rp_id_hash[..16].clone_from_slice(&blocks[2]);
         decrypted_rp_id_hash[16..].clone_from_slice(&blocks[3]);
+        blocks[0].zeroize();
+        blocks[1].zeroize();
         if rp_id_hash != decrypted_rp_id_hash {
+            decrypted_sk.zeroize();
             return Ok(None);
         }
-
-        let sk_option = crypto::ecdsa::SecKey::from_bytes(&decrypted_sk);
-        Ok(sk_option.map(|sk| PublicKeyCredentialSource {
-            key_type: PublicKeyCredentialType::PublicKey,
-            credential_id,
-            private_key: sk,
-            rp_id: String::from(""),
-            user_handle: vec![],
-            user_display_name: None,
-            cred_protect_policy: None,
-            creation_order: 0,
-            user_name: None,
-            user_icon: None,
-        }))
+        // Legacy credential IDs have no policy block, and decrypt to 0 like IDs without policy.
+        Ok(Some((blocks[4][0], blocks[4][1], decrypted_sk)))
     }
 
     pub fn process_command(
@@ -317,9 +748,26 @@ where
         cid: ChannelID,
         now: ClockValue,
     ) -> Vec<u8> {
+        self.keepalive.start();
+        self.status_leds.clear();
+        status_leds::show_processing();
+        if self.power_up_transport.is_none() {
+            self.power_up_transport = Some(transport_of(cid).transport_type());
+        }
+        // Transports already reject longer messages while reassembling them, when they can.
+        if command_cbor.len() > transport_of(cid).max_msg_size() {
+            return vec![Ctap2StatusCode::CTAP2_ERR_REQUEST_TOO_LARGE as u8];
+        }
         let cmd = Command::deserialize(command_cbor);
         #[cfg(feature = "debug_ctap")]
         writeln!(&mut Console::new(), "Received command: {:#?}", cmd).unwrap();
+        // The crypto can't be trusted after a failed self-test, except to run it again.
+        if self.self_test_failure.is_some() {
+            match cmd {
+                Ok(Command::AuthenticatorVendorSelfTest) => (),
+                _ => return vec![Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE as u8],
+            }
+        }
         match cmd {
             Ok(command) => {
                 // Correct behavior between CTAP1 and CTAP2 isn't defined yet. Just a guess.
@@ -336,6 +784,41 @@ where
                         Some(StatefulCommand::GetAssertion(_)),
                     ) => (),
                     (Command::AuthenticatorReset, Some(StatefulCommand::Reset)) => (),
+                    #[cfg(feature = "with_ctap2_1")]
+                    (
+                        Command::AuthenticatorCredentialManagement(
+                            AuthenticatorCredentialManagementParameters {
+                                sub_command: CredentialManagementSubCommand::EnumerateRpsGetNextRp,
+                                ..
+                            },
+                        ),
+                        Some(StatefulCommand::EnumerateRps(_)),
+                    ) => (),
+                    #[cfg(feature = "with_ctap2_1")]
+                    (
+                        Command::AuthenticatorCredentialManagement(
+                            AuthenticatorCredentialManagementParameters {
+                                sub_command:
+                                    CredentialManagementSubCommand::EnumerateCredentialsGetNextCredential,
+                                ..
+                            },
+                        ),
+                        Some(StatefulCommand::EnumerateCredentials(_, _)),
+                    ) => (),
+                    (
+                        Command::AuthenticatorVendorBackup(AuthenticatorVendorBackupParameters {
+                            passphrase: None,
+                            ..
+                        }),
+                        Some(StatefulCommand::Backup(_, _, _)),
+                    ) => (),
+                    (
+                        Command::AuthenticatorVendorRestore(AuthenticatorVendorRestoreParameters {
+                            passphrase: None,
+                            ..
+                        }),
+                        Some(StatefulCommand::Restore(_, _)),
+                    ) => (),
                     // GetInfo does not reset stateful commands.
                     (Command::AuthenticatorGetInfo, _) => (),
                     // AuthenticatorSelection does not reset stateful commands.
@@ -353,19 +836,71 @@ where
                         self.process_get_assertion(params, cid, now)
                     }
                     Command::AuthenticatorGetNextAssertion => self.process_get_next_assertion(now),
-                    Command::AuthenticatorGetInfo => self.process_get_info(),
-                    Command::AuthenticatorClientPin(params) => self.process_client_pin(params),
+                    Command::AuthenticatorGetInfo => self.process_get_info(cid),
+                    Command::AuthenticatorClientPin(params) => {
+                        self.process_client_pin(params, cid, now)
+                    }
                     Command::AuthenticatorReset => self.process_reset(cid, now),
                     #[cfg(feature = "with_ctap2_1")]
+                    Command::AuthenticatorBioEnrollment(params) => {
+                        self.process_bio_enrollment(params)
+                    }
+                    #[cfg(feature = "with_ctap2_1")]
+                    Command::AuthenticatorCredentialManagement(params) => {
+                        self.process_credential_management(params, now)
+                    }
+                    #[cfg(feature = "with_ctap2_1")]
                     Command::AuthenticatorSelection => self.process_selection(cid),
+                    #[cfg(feature = "with_ctap2_1")]
+                    Command::AuthenticatorLargeBlobs(params) => {
+                        self.process_large_blobs(params, cid)
+                    }
+                    #[cfg(feature = "with_ctap2_1")]
+                    Command::AuthenticatorConfig(params) => self.process_config(params),
                     // TODO(kaczmarczyck) implement FIDO 2.1 commands
                     // Vendor specific commands
                     Command::AuthenticatorVendorConfigure(params) => {
                         self.process_vendor_configure(params, cid)
                     }
+                    #[cfg(feature = "with_nfc")]
+                    Command::AuthenticatorVendorNfcStatistics => {
+                        self.process_vendor_nfc_statistics()
+                    }
+                    #[cfg(feature = "with_nfc")]
+                    Command::AuthenticatorVendorNfcSelfTest => {
+                        self.process_vendor_nfc_self_test(cid)
+                    }
+                    Command::AuthenticatorVendorProvision(params) => {
+                        self.process_vendor_provision(params, cid)
+                    }
+                    Command::AuthenticatorVendorLockProvisioning => {
+                        self.process_vendor_lock_provisioning(cid)
+                    }
+                    Command::AuthenticatorVendorRevokePinUvAuthToken => {
+                        self.process_vendor_revoke_pin_uv_auth_token()
+                    }
+                    Command::AuthenticatorVendorRotateMasterKeys => {
+                        self.process_vendor_rotate_master_keys(cid)
+                    }
+                    Command::AuthenticatorVendorSelfTest => self.process_vendor_self_test(),
+                    Command::AuthenticatorVendorBenchmark => self.process_vendor_benchmark(cid),
+                    Command::AuthenticatorVendorCompact => self.process_vendor_compact(),
+                    Command::AuthenticatorVendorWear => self.process_vendor_wear(),
+                    Command::AuthenticatorVendorBackup(params) => {
+                        self.process_vendor_backup(params, cid, now)
+                    }
+                    Command::AuthenticatorVendorRestore(params) => {
+                        self.process_vendor_restore(params, cid, now)
+                    }
+                    Command::AuthenticatorVendorAuditLog => self.process_vendor_audit_log(),
+                    Command::AuthenticatorVendorDiagnostics => self.process_vendor_diagnostics(),
                 };
                 #[cfg(feature = "debug_ctap")]
                 writeln!(&mut Console::new(), "Sending response: {:#?}", response).unwrap();
+                // Errors like a blocked PIN from clientPin are shown for a while, or until the next command.
+                if let Err(error_code) = &response {
+                    self.status_leds.report_error(error_code, now);
+                }
                 match response {
                     Ok(response_data) => {
                         let mut response_vec = vec![0x00];
@@ -386,6 +921,132 @@ where
         }
     }
 
+    // Performs built-in user verification if it is requested and configured. Returns whether the
+    // user was verified.
+    #[cfg_attr(not(feature = "with_ctap2_1"), allow(unused_variables))]
+    fn check_built_in_uv(&mut self, uv_requested: bool) -> Result<bool, Ctap2StatusCode> {
+        #[cfg(feature = "with_ctap2_1")]
+        {
+            if !uv_requested
+                || !self
+                    .bio_enrollment
+                    .has_built_in_uv(&self.persistent_store)?
+            {
+                return Ok(false);
+            }
+            self.bio_enrollment
+                .verify_user_or_require_pin(&mut self.persistent_store)?;
+            Ok(true)
+        }
+        #[cfg(not(feature = "with_ctap2_1"))]
+        {
+            Ok(false)
+        }
+    }
+
+    // Returns the user verification methods of a command with these flags, for the uvm extension.
+    // A pinUvAuthToken stands for the method that verified the user when it was issued.
+    fn user_verification_methods(&self, flags: u8, has_pin_uv_auth_param: bool) -> u32 {
+        let mut methods = 0;
+        if flags & UP_FLAG != 0 {
+            methods |= USER_VERIFY_PRESENCE_INTERNAL;
+        }
+        if flags & UV_FLAG != 0 {
+            methods |= if has_pin_uv_auth_param && !self.pin_protocol_v1.is_token_from_built_in_uv()
+            {
+                USER_VERIFY_PASSCODE_EXTERNAL
+            } else {
+                USER_VERIFY_FINGERPRINT_INTERNAL
+            };
+        }
+        methods
+    }
+
+    // Returns the credProtect policy for credentials whose relying party doesn't request a stronger
+    // one. The vendor setting takes precedence over DEFAULT_CRED_PROTECT.
+    fn default_cred_protect(&self) -> Result<Option<CredentialProtectionPolicy>, Ctap2StatusCode> {
+        let policy = match self.persistent_store.default_cred_protect()? {
+            Some(policy) => Some(policy),
+            None => DEFAULT_CRED_PROTECT,
+        };
+        // The lowest level is the same as having no policy.
+        Ok(policy.filter(|p| *p != CredentialProtectionPolicy::UserVerificationOptional))
+    }
+
+    // Returns the user presence settings. The vendor settings take precedence over the defaults.
+    fn user_presence_config(&self) -> Result<UserPresenceConfig, Ctap2StatusCode> {
+        let timeout_ms = match self.persistent_store.touch_timeout_ms()? {
+            Some(timeout_ms) => timeout_ms as isize,
+            None => TOUCH_TIMEOUT_MS,
+        };
+        let touch_duration_ms = match self.persistent_store.touch_duration_ms()? {
+            Some(touch_duration_ms) => touch_duration_ms as isize,
+            None => TOUCH_DURATION_MS,
+        };
+        Ok(UserPresenceConfig {
+            timeout: Duration::from_ms(timeout_ms),
+            touch_duration: Duration::from_ms(touch_duration_ms),
+            selection: None,
+        })
+    }
+
+    // Checks user presence with the current settings.
+    fn request_user_presence(&self, cid: ChannelID) -> Result<(), Ctap2StatusCode> {
+        let result = (self.check_user_presence)(cid, self.user_presence_config()?);
+        // The user presence check showed its own pattern, processing continues.
+        status_leds::show_processing();
+        result
+    }
+
+    // Lets the user step through the candidates with touches, and returns the index of the one
+    // shown when the user stopped touching.
+    fn select_credential_with_touches(
+        &self,
+        cid: ChannelID,
+        num_candidates: usize,
+    ) -> Result<usize, Ctap2StatusCode> {
+        let config = self.user_presence_config()?;
+        for touches in 0..MAX_CREDENTIAL_SELECTION_TOUCHES {
+            let selection = touches % num_candidates;
+            let step_config = UserPresenceConfig {
+                timeout: CREDENTIAL_SELECTION_STEP_DURATION,
+                selection: Some(selection),
+                ..config
+            };
+            match (self.check_user_presence)(cid, step_config) {
+                Ok(()) => (),
+                Err(Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT) => return Ok(selection),
+                Err(error) => return Err(error),
+            }
+        }
+        Err(Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT)
+    }
+
+    // Checks user presence before a destructive operation, with a second touch if configured.
+    fn request_destructive_user_presence(&self, cid: ChannelID) -> Result<(), Ctap2StatusCode> {
+        self.request_user_presence(cid)?;
+        let confirm_with_second_touch = match self.persistent_store.confirm_with_second_touch()? {
+            Some(confirm) => confirm,
+            None => CONFIRM_WITH_SECOND_TOUCH,
+        };
+        if confirm_with_second_touch {
+            self.request_user_presence(cid)?;
+        }
+        Ok(())
+    }
+
+    // Returns whether user verification is required for all credential operations.
+    fn has_always_uv(&self) -> Result<bool, Ctap2StatusCode> {
+        #[cfg(feature = "with_ctap2_1")]
+        {
+            self.persistent_store.has_always_uv()
+        }
+        #[cfg(not(feature = "with_ctap2_1"))]
+        {
+            Ok(false)
+        }
+    }
+
     fn pin_uv_auth_precheck(
         &mut self,
         pin_uv_auth_param: &Option<Vec<u8>>,
@@ -395,7 +1056,7 @@ where
         if let Some(auth_param) = &pin_uv_auth_param {
             // This case was added in FIDO 2.1.
             if auth_param.is_empty() {
-                (self.check_user_presence)(cid)?;
+                self.request_user_presence(cid)?;
                 if self.persistent_store.pin_hash()?.is_none() {
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_NOT_SET);
                 } else {
@@ -404,7 +1065,7 @@ where
             }
 
             match pin_uv_auth_protocol {
-                Some(CtapState::<R, CheckUserPresence>::PIN_PROTOCOL_VERSION) => Ok(()),
+                Some(protocol) if PIN_PROTOCOLS.contains(&protocol) => Ok(()),
                 Some(_) => Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID),
                 None => Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER),
             }
@@ -413,6 +1074,34 @@ where
         }
     }
 
+    // Checks the pinUvAuthParam of a command that signs with a credential. The check is done twice,
+    // after random delays, so that a single glitch can't skip it.
+    fn verify_pin_uv_auth_param(
+        &mut self,
+        client_data_hash: &[u8],
+        pin_uv_auth_param: &[u8],
+        pin_uv_auth_protocol: u64,
+    ) -> Result<(), Ctap2StatusCode> {
+        fault::random_delay(self.rng);
+        if !self.pin_protocol_v1.verify_pin_auth_token(
+            client_data_hash,
+            pin_uv_auth_param,
+            pin_uv_auth_protocol,
+        ) {
+            return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+        }
+        fault::random_delay(self.rng);
+        if self.pin_protocol_v1.verify_pin_auth_token(
+            client_data_hash,
+            pin_uv_auth_param,
+            pin_uv_auth_protocol,
+        ) {
+            Ok(())
+        } else {
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)
+        }
+    }
+
     fn process_make_credential(
         &mut self,
         make_credential_params: AuthenticatorMakeCredentialParameters,
@@ -428,44 +1117,86 @@ where
             options,
             pin_uv_auth_param,
             pin_uv_auth_protocol,
+            #[cfg(feature = "with_ctap2_1")]
+            enterprise_attestation,
         } = make_credential_params;
 
         self.pin_uv_auth_precheck(&pin_uv_auth_param, pin_uv_auth_protocol, cid)?;
 
-        if !pub_key_cred_params.contains(&ES256_CRED_PARAM) {
-            return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM);
-        }
+        // The relying party lists algorithms by preference, so we pick the first we support.
+        let algorithm = pub_key_cred_params
+            .iter()
+            .find(|param| {
+                **param == ES256_CRED_PARAM
+                    || **param == EDDSA_CRED_PARAM
+                    || **param == ES384_CRED_PARAM
+            })
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM)?
+            .alg;
 
-        let (use_hmac_extension, cred_protect_policy) = if let Some(extensions) = extensions {
-            let mut cred_protect = extensions.cred_protect;
-            if cred_protect.unwrap_or(CredentialProtectionPolicy::UserVerificationOptional)
-                < DEFAULT_CRED_PROTECT
-                    .unwrap_or(CredentialProtectionPolicy::UserVerificationOptional)
-            {
-                cred_protect = DEFAULT_CRED_PROTECT;
+        #[cfg(feature = "with_ctap2_1")]
+        let ep_att = if let Some(enterprise_attestation) = enterprise_attestation {
+            let authenticator_mode =
+                ENTERPRISE_ATTESTATION_MODE.ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+            if !self.persistent_store.enterprise_attestation()? {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+            }
+            match (authenticator_mode, enterprise_attestation) {
+                (
+                    EnterpriseAttestationMode::PlatformManaged,
+                    EnterpriseAttestationMode::PlatformManaged,
+                ) => true,
+                _ => ENTERPRISE_RP_ID_LIST.contains(&rp.rp_id.as_str()),
             }
-            (extensions.hmac_secret, cred_protect)
         } else {
-            (false, DEFAULT_CRED_PROTECT)
+            false
         };
-
-        let has_extension_output = use_hmac_extension || cred_protect_policy.is_some();
+        #[cfg(not(feature = "with_ctap2_1"))]
+        let ep_att = false;
+
+        // Extensions check their inputs and decide what the new credential stores.
+        let default_cred_protect = self.default_cred_protect()?;
+        let extensions = extensions.unwrap_or_default();
+        let mut credential_data = CredentialExtensionData::default();
+        let mut extensions_output = process_make_credential_extensions(
+            &mut MakeCredentialEnv {
+                rng: self.rng,
+                persistent_store: &self.persistent_store,
+                rp_id: &rp.rp_id,
+                rk: options.rk,
+                default_cred_protect,
+            },
+            &extensions,
+            &mut credential_data,
+        )?;
+        let CredentialExtensionData {
+            cred_protect_policy,
+            cred_blob,
+            large_blob_key,
+        } = credential_data;
 
         let rp_id = rp.rp_id;
         let rp_id_hash = Sha256::hash(rp_id.as_bytes());
         if let Some(exclude_list) = exclude_list {
+            let check_cred_protect = pin_uv_auth_param.is_none();
+            // Entries are decoded one at a time, and the first excluded credential ends the loop.
             for cred_desc in exclude_list {
+                self.check_cancel(cid)?;
+                let cred_desc = cred_desc?;
                 if self
                     .persistent_store
-                    .find_credential(&rp_id, &cred_desc.key_id, pin_uv_auth_param.is_none())?
+                    .find_credential(&rp_id, &cred_desc.key_id, check_cred_protect)?
                     .is_some()
                     || self
                         .decrypt_credential_source(cred_desc.key_id, &rp_id_hash)?
+                        .filter(|credential| {
+                            !check_cred_protect || !credential.requires_user_verification()
+                        })
                         .is_some()
                 {
                     // Perform this check, so bad actors can't brute force exclude_list
                     // without user interaction.
-                    (self.check_user_presence)(cid)?;
+                    self.request_user_presence(cid)?;
                     return Err(Ctap2StatusCode::CTAP2_ERR_CREDENTIAL_EXCLUDED);
                 }
             }
@@ -473,17 +1204,17 @@ where
 
         // MakeCredential always requires user presence.
         // User verification depends on the PIN auth inputs, which are checked here.
-        let ed_flag = if has_extension_output { ED_FLAG } else { 0 };
+        let has_pin_uv_auth_param = pin_uv_auth_param.is_some();
         let flags = match pin_uv_auth_param {
             Some(pin_auth) => {
                 if self.persistent_store.pin_hash()?.is_none() {
                     // Specification is unclear, could be CTAP2_ERR_INVALID_OPTION.
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_NOT_SET);
                 }
-                if !self
-                    .pin_protocol_v1
-                    .verify_pin_auth_token(&client_data_hash, &pin_auth)
-                {
+                let pin_uv_auth_protocol =
+                    pin_uv_auth_protocol.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?;
+                self.verify_pin_uv_auth_param(&client_data_hash, &pin_auth, pin_uv_auth_protocol)?;
+                if !self.pin_protocol_v1.has_user_verified_flag() {
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
                 }
                 #[cfg(feature = "with_ctap2_1")]
@@ -492,25 +1223,62 @@ where
                         .has_permission(PinPermission::MakeCredential)?;
                     self.pin_protocol_v1.has_permission_for_rp_id(&rp_id)?;
                 }
-                UP_FLAG | UV_FLAG | AT_FLAG | ed_flag
+                UP_FLAG | UV_FLAG | AT_FLAG
             }
             None => {
-                if self.persistent_store.pin_hash()?.is_some() {
-                    return Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED);
-                }
-                if options.uv {
-                    return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION);
+                let uv_requested = options.uv || self.has_always_uv()?;
+                if self.check_built_in_uv(uv_requested)? {
+                    UP_FLAG | UV_FLAG | AT_FLAG
+                } else {
+                    // Without built-in user verification, alwaysUv requires a PIN.
+                    if self.persistent_store.pin_hash()?.is_some() || self.has_always_uv()? {
+                        return Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED);
+                    }
+                    if options.uv {
+                        return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION);
+                    }
+                    UP_FLAG | AT_FLAG
                 }
-                UP_FLAG | AT_FLAG | ed_flag
             }
         };
+        let mut flags = RedundantFlags::new(flags);
+        let user_verification_methods =
+            self.user_verification_methods(flags.get()?, has_pin_uv_auth_param);
 
-        (self.check_user_presence)(cid)?;
+        // A token issued with built-in user verification may already prove user presence.
+        #[cfg(feature = "with_ctap2_1")]
+        let user_is_present = has_pin_uv_auth_param && self.pin_protocol_v1.has_user_present_flag();
+        #[cfg(not(feature = "with_ctap2_1"))]
+        let user_is_present = false;
+        if !user_is_present {
+            self.request_user_presence(cid)?;
+        }
+        // A token can't be used for more than one credential operation with user presence.
+        #[cfg(feature = "with_ctap2_1")]
+        if has_pin_uv_auth_param {
+            self.pin_protocol_v1.clear_permissions_except_lbw();
+            self.pin_protocol_v1.clear_user_flags();
+        }
 
-        let sk = crypto::ecdsa::SecKey::gensk(self.rng);
-        let pk = sk.genpk();
+        self.keepalive.tick(cid, KeepaliveStatus::Processing)?;
+        let sk = PrivateKey::new(self.rng, algorithm)?;
+        process_make_credential_key_extensions(
+            &mut CredentialEnv {
+                rng: self.rng,
+                persistent_store: &self.persistent_store,
+                pin_protocol_v1: &self.pin_protocol_v1,
+                has_uv: flags.contains(UV_FLAG)?,
+                user_verification_methods,
+            },
+            &extensions,
+            &sk,
+            &mut extensions_output,
+        )?;
+        if !extensions_output.is_empty() {
+            flags.insert(ED_FLAG);
+        }
 
-        let credential_id = if options.rk {
+        let (credential_id, signature_counter) = if options.rk {
             let random_id = self.rng.gen_uniform_u8x32().to_vec();
             let credential_source = PublicKeyCredentialSource {
                 key_type: PublicKeyCredentialType::PublicKey,
@@ -525,95 +1293,71 @@ where
                     .map(|s| truncate_to_char_boundary(&s, 64).to_string()),
                 cred_protect_policy,
                 creation_order: self.persistent_store.new_creation_order()?,
+                last_used_order: None,
+                signature_counter: Some(INITIAL_SIGNATURE_COUNTER),
                 user_name: user
                     .user_name
                     .map(|s| truncate_to_char_boundary(&s, 64).to_string()),
                 user_icon: user
                     .user_icon
                     .map(|s| truncate_to_char_boundary(&s, 64).to_string()),
+                large_blob_key: large_blob_key.clone(),
+                cred_blob,
             };
+            self.keepalive.tick(cid, KeepaliveStatus::Processing)?;
             self.persistent_store.store_credential(credential_source)?;
-            random_id
+            (random_id, INITIAL_SIGNATURE_COUNTER)
         } else {
-            self.encrypt_key_handle(sk.clone(), &rp_id_hash)?
+            (
+                self.encrypt_key_handle(sk.clone(), &rp_id_hash, cred_protect_policy)?,
+                self.non_resident_signature_counter()?,
+            )
         };
+        let signature_counter = self.signature_counter_output(&credential_id, signature_counter)?;
 
-        let mut auth_data = self.generate_auth_data(&rp_id_hash, flags)?;
+        let mut auth_data = self.generate_auth_data(&rp_id_hash, flags.get()?, signature_counter);
         auth_data.extend(&self.persistent_store.aaguid()?);
-        // The length is fixed to 0x20 or 0x70 and fits one byte.
+        // The length is fixed to 0x20 or 0x80 and fits one byte.
         if credential_id.len() > 0xFF {
             return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_TOO_LONG);
         }
         auth_data.extend(vec![0x00, credential_id.len() as u8]);
         auth_data.extend(&credential_id);
-        let cose_key = match pk.to_cose_key() {
-            Some(cose_key) => cose_key,
-            None => return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR),
-        };
-        auth_data.extend(cose_key);
-        if has_extension_output {
-            let hmac_secret_output = if use_hmac_extension { Some(true) } else { None };
-            let extensions_output = cbor_map_options! {
-                "hmac-secret" => hmac_secret_output,
-                "credProtect" => cred_protect_policy,
-            };
-            if !cbor::write(extensions_output, &mut auth_data) {
-                return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR);
-            }
+        if !cbor::write(sk.public_key_cose().into(), &mut auth_data) {
+            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR);
+        }
+        if !extensions_output.is_empty()
+            && !cbor::write(cbor_map_btree!(extensions_output), &mut auth_data)
+        {
+            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR);
         }
 
-        let mut signature_data = auth_data.clone();
-        signature_data.extend(client_data_hash);
-
-        let (signature, x5c) = if USE_BATCH_ATTESTATION {
-            let attestation_private_key = self
-                .persistent_store
-                .attestation_private_key()?
-                .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
-            let attestation_key =
-                crypto::ecdsa::SecKey::from_bytes(&attestation_private_key).unwrap();
-            let attestation_certificate = self
-                .persistent_store
-                .attestation_certificate()?
-                .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
-            (
-                attestation_key.sign_rfc6979::<crypto::sha256::Sha256>(&signature_data),
-                Some(vec![attestation_certificate]),
-            )
-        } else {
-            (
-                sk.sign_rfc6979::<crypto::sha256::Sha256>(&signature_data),
-                None,
-            )
-        };
-        let attestation_statement = PackedAttestationStatement {
-            alg: SignatureAlgorithm::ES256 as i64,
-            sig: signature.to_asn1_der(),
-            x5c,
-            ecdaa_key_id: None,
-        };
+        fault::random_delay(self.rng);
+        fault::check_auth_data_flags(&auth_data, &flags)?;
+        let attestation_type = AttestationType::select(USE_BATCH_ATTESTATION, ep_att);
+        let attestation_statement = packed_attestation_statement(
+            self.rng,
+            &self.persistent_store,
+            &mut self.crypto_backend,
+            attestation_type,
+            &sk,
+            &[&auth_data, &client_data_hash],
+        )?;
         Ok(ResponseData::AuthenticatorMakeCredential(
             AuthenticatorMakeCredentialResponse {
                 fmt: String::from("packed"),
                 auth_data,
                 att_stmt: attestation_statement,
+                ep_att: if attestation_type == AttestationType::Enterprise {
+                    Some(true)
+                } else {
+                    None
+                },
+                large_blob_key,
             },
         ))
     }
 
-    // Generates a different per-credential secret for each UV mode.
-    // The computation is deterministic, and private_key expected to be unique.
-    fn generate_cred_random(
-        &mut self,
-        private_key: &crypto::ecdsa::SecKey,
-        has_uv: bool,
-    ) -> Result<[u8; 32], Ctap2StatusCode> {
-        let mut private_key_bytes = [0u8; 32];
-        private_key.to_bytes(&mut private_key_bytes);
-        let key = self.persistent_store.cred_random_secret(has_uv)?;
-        Ok(hmac_256::<Sha256>(&key, &private_key_bytes))
-    }
-
     // Processes the input of a get_assertion operation for a given credential
     // and returns the correct Get(Next)Assertion response.
     fn assertion_response(
@@ -621,33 +1365,48 @@ where
         credential: PublicKeyCredentialSource,
         assertion_input: AssertionInput,
         number_of_credentials: Option<usize>,
+        user_selected: bool,
     ) -> Result<ResponseData, Ctap2StatusCode> {
         let AssertionInput {
             client_data_hash,
-            mut auth_data,
-            hmac_secret_input,
+            rp_id_hash,
+            flags,
+            extensions,
             has_uv,
+            user_verification_methods,
         } = assertion_input;
 
-        // Process extensions.
-        if let Some(hmac_secret_input) = hmac_secret_input {
-            let cred_random = self.generate_cred_random(&credential.private_key, has_uv)?;
-            let encrypted_output = self
-                .pin_protocol_v1
-                .process_hmac_secret(hmac_secret_input, &cred_random)?;
-            let extensions_output = cbor_map! {
-                "hmac-secret" => encrypted_output,
-            };
+        let signature_counter = self.increment_signature_counter(&credential.credential_id)?;
+        let mut auth_data = self.generate_auth_data(&rp_id_hash, flags.get()?, signature_counter);
+
+        let mut unsigned_outputs = UnsignedExtensionOutputs::default();
+        let extensions_output = process_get_assertion_extensions(
+            &mut CredentialEnv {
+                rng: self.rng,
+                persistent_store: &self.persistent_store,
+                pin_protocol_v1: &self.pin_protocol_v1,
+                has_uv,
+                user_verification_methods,
+            },
+            &extensions,
+            &credential,
+            &mut unsigned_outputs,
+        )?;
+        if let Some(extensions_output) = extensions_output {
             if !cbor::write(extensions_output, &mut auth_data) {
                 return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR);
             }
         }
 
-        let mut signature_data = auth_data.clone();
-        signature_data.extend(client_data_hash);
+        // The UV flag and has_uv are set together, and must still agree.
+        fault::random_delay(self.rng);
+        fault::check_auth_data_flags(&auth_data, &flags)?;
+        if flags.contains(UV_FLAG)? != has_uv {
+            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE);
+        }
         let signature = credential
             .private_key
-            .sign_rfc6979::<crypto::sha256::Sha256>(&signature_data);
+            .sign_and_encode(self.rng, &[&auth_data, &client_data_hash])?;
 
         let cred_desc = PublicKeyCredentialDescriptor {
             key_type: PublicKeyCredentialType::PublicKey,
@@ -668,22 +1427,32 @@ where
             AuthenticatorGetAssertionResponse {
                 credential: Some(cred_desc),
                 auth_data,
-                signature: signature.to_asn1_der(),
+                signature,
                 user,
                 number_of_credentials: number_of_credentials.map(|n| n as u64),
+                user_selected: if user_selected { Some(true) } else { None },
+                large_blob_key: unsigned_outputs.large_blob_key,
             },
         ))
     }
 
-    // Returns the first applicable credential from the allow list.
+    // Aborts a long operation if the platform cancelled the command of the channel.
+    fn check_cancel(&self, cid: ChannelID) -> Result<(), Ctap2StatusCode> {
+        transport_of(cid).wait_for_cancel(cid, CANCEL_POLL_DURATION, &|| false)
+    }
+
+    // Returns the first applicable credential from the allow list. Entries after it are not decoded.
     fn get_any_credential_from_allow_list(
         &mut self,
-        allow_list: Vec<PublicKeyCredentialDescriptor>,
+        allow_list: PublicKeyCredentialDescriptorList,
         rp_id: &str,
         rp_id_hash: &[u8],
         has_uv: bool,
+        cid: ChannelID,
     ) -> Result<Option<PublicKeyCredentialSource>, Ctap2StatusCode> {
         for allowed_credential in allow_list {
+            self.check_cancel(cid)?;
+            let allowed_credential = allowed_credential?;
             let credential = self.persistent_store.find_credential(
                 rp_id,
                 &allowed_credential.key_id,
@@ -692,8 +1461,9 @@ where
             if credential.is_some() {
                 return Ok(credential);
             }
-            let credential =
-                self.decrypt_credential_source(allowed_credential.key_id, &rp_id_hash)?;
+            let credential = self
+                .decrypt_credential_source(allowed_credential.key_id, &rp_id_hash)?
+                .filter(|credential| has_uv || !credential.requires_user_verification());
             if credential.is_some() {
                 return Ok(credential);
             }
@@ -719,25 +1489,22 @@ where
 
         self.pin_uv_auth_precheck(&pin_uv_auth_param, pin_uv_auth_protocol, cid)?;
 
-        let hmac_secret_input = extensions.map(|e| e.hmac_secret).flatten();
-        if hmac_secret_input.is_some() && !options.up {
-            // The extension is actually supported, but we need user presence.
-            return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION);
-        }
+        let extensions = extensions.unwrap_or_default();
+        let has_extension_output = check_get_assertion_extensions(&extensions, &options)?;
 
-        // The user verification bit depends on the existance of PIN auth, since we do
-        // not support internal UV. User presence is requested as an option.
-        let has_uv = pin_uv_auth_param.is_some();
-        let mut flags = match pin_uv_auth_param {
+        // The user verification bit depends on the existance of PIN auth or built-in user
+        // verification. User presence is requested as an option.
+        let has_pin_uv_auth_param = pin_uv_auth_param.is_some();
+        let flags = match pin_uv_auth_param {
             Some(pin_auth) => {
                 if self.persistent_store.pin_hash()?.is_none() {
                     // Specification is unclear, could be CTAP2_ERR_UNSUPPORTED_OPTION.
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_NOT_SET);
                 }
-                if !self
-                    .pin_protocol_v1
-                    .verify_pin_auth_token(&client_data_hash, &pin_auth)
-                {
+                let pin_uv_auth_protocol =
+                    pin_uv_auth_protocol.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?;
+                self.verify_pin_uv_auth_param(&client_data_hash, &pin_auth, pin_uv_auth_protocol)?;
+                if !self.pin_protocol_v1.has_user_verified_flag() {
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
                 }
                 #[cfg(feature = "with_ctap2_1")]
@@ -749,25 +1516,42 @@ where
                 UV_FLAG
             }
             None => {
-                if options.uv {
-                    // The specification (inconsistently) wants CTAP2_ERR_UNSUPPORTED_OPTION.
-                    return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION);
+                let uv_requested = options.uv || self.has_always_uv()?;
+                if self.check_built_in_uv(uv_requested)? {
+                    UV_FLAG
+                } else {
+                    if self.has_always_uv()? {
+                        return Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED);
+                    }
+                    if options.uv {
+                        // The specification (inconsistently) wants CTAP2_ERR_UNSUPPORTED_OPTION.
+                        return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION);
+                    }
+                    0x00
                 }
-                0x00
             }
         };
+        let mut flags = RedundantFlags::new(flags);
+        let has_uv = flags.contains(UV_FLAG)?;
         if options.up {
-            flags |= UP_FLAG;
+            flags.insert(UP_FLAG);
         }
-        if hmac_secret_input.is_some() {
-            flags |= ED_FLAG;
+        if has_extension_output {
+            flags.insert(ED_FLAG);
         }
+        let user_verification_methods =
+            self.user_verification_methods(flags.get()?, has_pin_uv_auth_param);
 
         let rp_id_hash = Sha256::hash(rp_id.as_bytes());
+        let has_allow_list = allow_list.is_some();
         let mut applicable_credentials = if let Some(allow_list) = allow_list {
-            if let Some(credential) =
-                self.get_any_credential_from_allow_list(allow_list, &rp_id, &rp_id_hash, has_uv)?
-            {
+            if let Some(credential) = self.get_any_credential_from_allow_list(
+                allow_list,
+                &rp_id,
+                &rp_id_hash,
+                has_uv,
+                cid,
+            )? {
                 vec![credential]
             } else {
                 vec![]
@@ -783,25 +1567,55 @@ where
                 credential.user_icon = None;
             }
         }
+        // Credentials are popped from the end, so the most recently created comes first.
         applicable_credentials.sort_unstable_by_key(|c| c.creation_order);
 
         // This check comes before CTAP2_ERR_NO_CREDENTIALS in CTAP 2.0.
         // For CTAP 2.1, it was moved to a later protocol step.
+        // Silent assertions skip it. The platform uses them to probe for credentials, so they don't
+        // consume the permissions of a PIN/UV auth token either.
         if options.up {
-            (self.check_user_presence)(cid)?;
+            // A token issued with built-in user verification may already prove user presence.
+            #[cfg(feature = "with_ctap2_1")]
+            let user_is_present =
+                has_pin_uv_auth_param && self.pin_protocol_v1.has_user_present_flag();
+            #[cfg(not(feature = "with_ctap2_1"))]
+            let user_is_present = false;
+            if !user_is_present {
+                self.request_user_presence(cid)?;
+            }
+            #[cfg(feature = "with_ctap2_1")]
+            if has_pin_uv_auth_param {
+                self.pin_protocol_v1.clear_permissions_except_lbw();
+                self.pin_protocol_v1.clear_user_flags();
+            }
+        }
+
+        // The touch for user presence comes first. NFC has no touches to step with.
+        let user_selected = SELECT_CREDENTIAL_WITH_TOUCHES
+            && !has_allow_list
+            && options.up
+            && applicable_credentials.len() > 1
+            && transport_of(cid).transport_type() == AuthenticatorTransport::Usb;
+        if user_selected {
+            let selection =
+                self.select_credential_with_touches(cid, applicable_credentials.len())?;
+            // Candidates are shown from the most recent, i.e. from the end.
+            let index = applicable_credentials.len() - 1 - selection;
+            applicable_credentials = vec![applicable_credentials.swap_remove(index)];
         }
 
         let credential = applicable_credentials
             .pop()
             .ok_or(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)?;
 
-        self.increment_global_signature_counter()?;
-
         let assertion_input = AssertionInput {
             client_data_hash,
-            auth_data: self.generate_auth_data(&rp_id_hash, flags)?,
-            hmac_secret_input,
+            rp_id_hash: rp_id_hash.to_vec(),
+            flags,
+            extensions,
             has_uv,
+            user_verification_methods,
         };
         let number_of_credentials = if applicable_credentials.is_empty() {
             None
@@ -815,7 +1629,12 @@ where
             }));
             number_of_credentials
         };
-        self.assertion_response(credential, assertion_input, number_of_credentials)
+        self.assertion_response(
+            credential,
+            assertion_input,
+            number_of_credentials,
+            user_selected,
+        )
     }
 
     fn process_get_next_assertion(
@@ -835,48 +1654,133 @@ where
             } else {
                 return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED);
             };
-        self.assertion_response(credential, assertion_input, None)
+        self.assertion_response(credential, assertion_input, None, false)
     }
 
-    fn process_get_info(&self) -> Result<ResponseData, Ctap2StatusCode> {
+    fn process_get_info(&self, cid: ChannelID) -> Result<ResponseData, Ctap2StatusCode> {
         let mut options_map = BTreeMap::new();
-        // TODO(kaczmarczyck) add authenticatorConfig and credProtect options
+        // TODO(kaczmarczyck) add credProtect options
         options_map.insert(String::from("rk"), true);
         options_map.insert(String::from("up"), true);
         options_map.insert(
             String::from("clientPin"),
             self.persistent_store.pin_hash()?.is_some(),
         );
+        #[cfg(feature = "with_ctap2_1")]
+        {
+            options_map.insert(String::from("credMgmt"), true);
+            options_map.insert(String::from("largeBlobs"), true);
+            options_map.insert(String::from("authnrCfg"), true);
+            options_map.insert(String::from("setMinPINLength"), true);
+            options_map.insert(String::from("alwaysUv"), self.has_always_uv()?);
+            if self.bio_enrollment.is_available() {
+                options_map.insert(
+                    String::from("bioEnroll"),
+                    !self.persistent_store.fingerprint_templates()?.is_empty(),
+                );
+                // Blocked built-in user verification is not offered, so platforms use the PIN
+                // until it unblocks the sensor.
+                options_map.insert(
+                    String::from("uv"),
+                    self.bio_enrollment
+                        .is_built_in_uv_usable(&self.persistent_store)?,
+                );
+            }
+            if ENTERPRISE_ATTESTATION_MODE.is_some() {
+                options_map.insert(
+                    String::from("ep"),
+                    self.persistent_store.enterprise_attestation()?,
+                );
+            }
+        }
+        #[cfg_attr(not(feature = "with_ctap1"), allow(unused_mut))]
+        let mut versions = vec![
+            String::from(FIDO2_VERSION_STRING),
+            #[cfg(feature = "with_ctap2_1")]
+            String::from(FIDO2_1_VERSION_STRING),
+        ];
+        // U2F has no user verification, so it is unavailable with alwaysUv.
+        #[cfg(feature = "with_ctap1")]
+        {
+            if !self.has_always_uv()? {
+                versions.insert(0, String::from(U2F_VERSION_STRING));
+            }
+        }
         Ok(ResponseData::AuthenticatorGetInfo(
             AuthenticatorGetInfoResponse {
-                versions: vec![
-                    #[cfg(feature = "with_ctap1")]
-                    String::from(U2F_VERSION_STRING),
-                    String::from(FIDO2_VERSION_STRING),
+                versions,
+                extensions: Some(vec![
+                    String::from("hmac-secret"),
+                    String::from("uvm"),
+                    #[cfg(feature = "with_ctap2_1")]
+                    String::from("credBlob"),
                     #[cfg(feature = "with_ctap2_1")]
-                    String::from(FIDO2_1_VERSION_STRING),
-                ],
-                extensions: Some(vec![String::from("hmac-secret")]),
+                    String::from("largeBlobKey"),
+                    #[cfg(feature = "with_ctap2_1")]
+                    String::from("minPinLength"),
+                    #[cfg(feature = "with_ctap2_1")]
+                    String::from("hmac-secret-mc"),
+                ]),
                 aaguid: self.persistent_store.aaguid()?,
                 options: Some(options_map),
-                max_msg_size: Some(1024),
-                pin_protocols: Some(vec![
-                    CtapState::<R, CheckUserPresence>::PIN_PROTOCOL_VERSION,
-                ]),
+                max_msg_size: Some(transport_of(cid).max_msg_size() as u64),
+                pin_protocols: Some(PIN_PROTOCOLS.to_vec()),
                 #[cfg(feature = "with_ctap2_1")]
                 max_credential_count_in_list: MAX_CREDENTIAL_COUNT_IN_LIST.map(|c| c as u64),
                 // #TODO(106) update with version 2.1 of HMAC-secret
                 #[cfg(feature = "with_ctap2_1")]
                 max_credential_id_length: Some(CREDENTIAL_ID_SIZE as u64),
                 #[cfg(feature = "with_ctap2_1")]
-                transports: Some(vec![AuthenticatorTransport::Usb]),
+                transports: Some(TRANSPORTS.iter().map(|t| t.transport_type()).collect()),
                 #[cfg(feature = "with_ctap2_1")]
-                algorithms: Some(vec![ES256_CRED_PARAM]),
-                default_cred_protect: DEFAULT_CRED_PROTECT,
+                algorithms: Some(vec![ES256_CRED_PARAM, EDDSA_CRED_PARAM, ES384_CRED_PARAM]),
+                #[cfg(feature = "with_ctap2_1")]
+                max_serialized_large_blob_array: Some(MAX_LARGE_BLOB_ARRAY_SIZE as u64),
+                #[cfg(not(feature = "with_ctap2_1"))]
+                default_cred_protect: self.default_cred_protect()?,
+                #[cfg(feature = "with_ctap2_1")]
+                force_pin_change: Some(self.persistent_store.has_force_pin_change()?),
                 #[cfg(feature = "with_ctap2_1")]
                 min_pin_length: self.persistent_store.min_pin_length()?,
                 #[cfg(feature = "with_ctap2_1")]
                 firmware_version: None,
+                #[cfg(feature = "with_ctap2_1")]
+                max_cred_blob_length: Some(MAX_CRED_BLOB_LENGTH as u64),
+                #[cfg(feature = "with_ctap2_1")]
+                max_rp_ids_for_set_min_pin_length: Some(MAX_RP_IDS_LENGTH as u64),
+                #[cfg(feature = "with_ctap2_1")]
+                preferred_platform_uv_attempts: if self.bio_enrollment.is_available() {
+                    Some(PREFERRED_PLATFORM_UV_ATTEMPTS)
+                } else {
+                    None
+                },
+                #[cfg(feature = "with_ctap2_1")]
+                uv_modality: if self.bio_enrollment.is_available() {
+                    Some(FINGERPRINT_UV_MODALITY)
+                } else {
+                    None
+                },
+                #[cfg(feature = "with_ctap2_1")]
+                certifications: if CERTIFICATIONS.is_empty() {
+                    None
+                } else {
+                    Some(
+                        CERTIFICATIONS
+                            .iter()
+                            .map(|(name, level)| (String::from(*name), *level))
+                            .collect(),
+                    )
+                },
+                #[cfg(feature = "with_ctap2_1")]
+                remaining_discoverable_credentials: Some(
+                    self.persistent_store.remaining_credentials()? as u64,
+                ),
+                #[cfg(feature = "with_ctap2_1")]
+                vendor_prototype_config_commands: if VENDOR_PROTOTYPE_CONFIG_COMMANDS.is_empty() {
+                    None
+                } else {
+                    Some(VENDOR_PROTOTYPE_CONFIG_COMMANDS.to_vec())
+                },
             },
         ))
     }
@@ -884,14 +1788,94 @@ where
     fn process_client_pin(
         &mut self,
         client_pin_params: AuthenticatorClientPinParameters,
+        cid: ChannelID,
+        now: ClockValue,
     ) -> Result<ResponseData, Ctap2StatusCode> {
+        if !is_pin_change_allowed(
+            &client_pin_params.sub_command,
+            transport_of(cid).transport_type(),
+            SET_PIN_OVER_USB_ONLY,
+        ) {
+            return Err(Ctap2StatusCode::CTAP2_ERR_OPERATION_DENIED);
+        }
+        // The bio enrollment permission is only granted with a fingerprint sensor.
+        #[cfg(feature = "with_ctap2_1")]
+        {
+            if let Some(permissions) = client_pin_params.permissions {
+                if permissions & PinPermission::BioEnrollment as u8 != 0
+                    && !self.bio_enrollment.is_available()
+                {
+                    return Err(Ctap2StatusCode::CTAP2_ERR_UNAUTHORIZED_PERMISSION);
+                }
+            }
+            match client_pin_params.sub_command {
+                ClientPinSubCommand::GetPinUvAuthTokenUsingUvWithPermissions => {
+                    return self.process_get_pin_uv_auth_token_using_uv_with_permissions(
+                        client_pin_params,
+                        now,
+                    );
+                }
+                ClientPinSubCommand::GetUvRetries => return self.process_get_uv_retries(),
+                _ => (),
+            }
+        }
         self.pin_protocol_v1.process_subcommand(
             self.rng,
             &mut self.persistent_store,
             client_pin_params,
+            now,
         )
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    fn process_get_pin_uv_auth_token_using_uv_with_permissions(
+        &mut self,
+        client_pin_params: AuthenticatorClientPinParameters,
+        now: ClockValue,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        if !self.bio_enrollment.is_available() {
+            return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND);
+        }
+        if !self
+            .bio_enrollment
+            .has_built_in_uv(&self.persistent_store)?
+        {
+            return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED);
+        }
+        let AuthenticatorClientPinParameters {
+            pin_protocol,
+            key_agreement,
+            permissions,
+            permissions_rp_id,
+            ..
+        } = client_pin_params;
+        let bio_enrollment = &mut self.bio_enrollment;
+        let persistent_store = &mut self.persistent_store;
+        let response = self
+            .pin_protocol_v1
+            .process_get_pin_uv_auth_token_using_uv_with_permissions(
+                self.rng,
+                key_agreement.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
+                pin_protocol,
+                permissions.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
+                permissions_rp_id,
+                now,
+                || bio_enrollment.verify_user(persistent_store),
+            )?;
+        Ok(ResponseData::AuthenticatorClientPin(Some(response)))
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    fn process_get_uv_retries(&self) -> Result<ResponseData, Ctap2StatusCode> {
+        if !self.bio_enrollment.is_available() {
+            return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND);
+        }
+        let response = self
+            .pin_protocol_v1
+            .process_get_uv_retries(&self.persistent_store)?;
+        Ok(ResponseData::AuthenticatorClientPin(Some(response)))
+    }
+
     fn process_reset(
         &mut self,
         cid: ChannelID,
@@ -904,10 +1888,26 @@ where
             Some(StatefulCommand::Reset) => (),
             _ => return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED),
         }
-        (self.check_user_presence)(cid)?;
+        // Another transport may have been attached later, e.g. a reader brought close to a key
+        // plugged into USB, and was not part of the power cycle.
+        match self.power_up_transport {
+            Some(transport) if transport != transport_of(cid).transport_type() => {
+                return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED);
+            }
+            _ => (),
+        }
+        // A single accidental click must not wipe all credentials, if the vendor asks for a second
+        // touch. Button callbacks only report presses, so holding the button doesn't confirm.
+        self.request_destructive_user_presence(cid)?;
 
+        self.keepalive.tick(cid, KeepaliveStatus::Processing)?;
         self.persistent_store.reset(self.rng)?;
         self.pin_protocol_v1.reset(self.rng);
+        #[cfg(feature = "with_ctap2_1")]
+        {
+            self.large_blobs = LargeBlobs::new();
+            self.bio_enrollment.reset()?;
+        }
         #[cfg(feature = "with_ctap1")]
         {
             self.u2f_up_state = U2fUserPresenceState::new(
@@ -920,43 +1920,138 @@ where
 
     #[cfg(feature = "with_ctap2_1")]
     fn process_selection(&self, cid: ChannelID) -> Result<ResponseData, Ctap2StatusCode> {
-        (self.check_user_presence)(cid)?;
+        self.request_user_presence(cid)?;
         Ok(ResponseData::AuthenticatorSelection)
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    fn process_large_blobs(
+        &mut self,
+        large_blobs_params: AuthenticatorLargeBlobsParameters,
+        cid: ChannelID,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        self.keepalive.tick(cid, KeepaliveStatus::Processing)?;
+        self.large_blobs.process_command(
+            &mut self.persistent_store,
+            &mut self.pin_protocol_v1,
+            large_blobs_params,
+            transport_of(cid).max_msg_size(),
+        )
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    fn process_bio_enrollment(
+        &mut self,
+        bio_enrollment_params: AuthenticatorBioEnrollmentParameters,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        self.bio_enrollment.process_command(
+            &mut self.persistent_store,
+            &mut self.pin_protocol_v1,
+            bio_enrollment_params,
+        )
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    fn process_credential_management(
+        &mut self,
+        cred_management_params: AuthenticatorCredentialManagementParameters,
+        now: ClockValue,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        match cred_management_params.sub_command {
+            CredentialManagementSubCommand::EnumerateRpsGetNextRp
+            | CredentialManagementSubCommand::EnumerateCredentialsGetNextCredential => {
+                self.check_command_permission(now)?
+            }
+            _ => (),
+        }
+        process_credential_management(
+            &mut self.persistent_store,
+            &mut self.pin_protocol_v1,
+            &mut self.stateful_command_permission,
+            &mut self.stateful_command_type,
+            cred_management_params,
+            now,
+        )
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    fn process_config(
+        &mut self,
+        config_params: AuthenticatorConfigParameters,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        process_config(
+            &mut self.persistent_store,
+            &mut self.pin_protocol_v1,
+            config_params,
+        )
+    }
+
     fn process_vendor_configure(
         &mut self,
         params: AuthenticatorVendorConfigureParameters,
         cid: ChannelID,
     ) -> Result<ResponseData, Ctap2StatusCode> {
-        (self.check_user_presence)(cid)?;
+        self.request_user_presence(cid)?;
+
+        // A touch must fit within the timeout, or user presence checks can't succeed anymore.
+        let current_config = self.user_presence_config()?;
+        let touch_timeout_ms = params
+            .touch_timeout_ms
+            .unwrap_or(current_config.timeout.ms() as u32);
+        let touch_duration_ms = params
+            .touch_duration_ms
+            .unwrap_or(current_config.touch_duration.ms() as u32);
+        if touch_timeout_ms == 0
+            || touch_timeout_ms > MAX_TOUCH_TIMEOUT_MS
+            || touch_duration_ms >= touch_timeout_ms
+        {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+        // Keys shipped with a default PIN can require its change before the first use.
+        if params.force_pin_change {
+            #[cfg(not(feature = "with_ctap2_1"))]
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+            #[cfg(feature = "with_ctap2_1")]
+            {
+                if self.persistent_store.pin_hash()?.is_none() {
+                    return Err(Ctap2StatusCode::CTAP2_ERR_PIN_NOT_SET);
+                }
+            }
+        }
 
         // Sanity checks
-        let current_priv_key = self.persistent_store.attestation_private_key()?;
+        let priv_key_programmed = self.persistent_store.has_attestation_private_key()?;
         let current_cert = self.persistent_store.attestation_certificate()?;
 
-        let response = match params.attestation_material {
+        let response = match &params.attestation_material {
             // Only reading values.
             None => AuthenticatorVendorResponse {
                 cert_programmed: current_cert.is_some(),
-                pkey_programmed: current_priv_key.is_some(),
+                pkey_programmed: priv_key_programmed,
             },
             // Device is already fully programmed. We don't leak information.
-            Some(_) if current_cert.is_some() && current_priv_key.is_some() => {
+            Some(_) if current_cert.is_some() && priv_key_programmed => {
                 AuthenticatorVendorResponse {
                     cert_programmed: true,
                     pkey_programmed: true,
                 }
             }
             // Device is partially or not programmed. We complete the process.
+            Some(_) if self.persistent_store.is_provisioning_locked()? => {
+                return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED);
+            }
             Some(data) => {
                 if let Some(current_cert) = &current_cert {
                     if current_cert != &data.certificate {
                         return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
                     }
                 }
-                if let Some(current_priv_key) = &current_priv_key {
-                    if current_priv_key != &data.private_key {
+                if let Some(mut current_priv_key) =
+                    self.persistent_store.attestation_private_key()?
+                {
+                    let matches = bytes_eq(&current_priv_key, &data.private_key);
+                    current_priv_key.zeroize();
+                    if !matches {
                         return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
                     }
                 }
@@ -964,7 +2059,7 @@ where
                     self.persistent_store
                         .set_attestation_certificate(&data.certificate)?;
                 }
-                if current_priv_key.is_none() {
+                if !priv_key_programmed {
                     self.persistent_store
                         .set_attestation_private_key(&data.private_key)?;
                 }
@@ -974,6 +2069,28 @@ where
                 }
             }
         };
+        // The options are written together, so the configuration is never half applied.
+        self.persistent_store.transaction(|persistent_store| {
+            if let Some(policy) = params.default_cred_protect {
+                persistent_store.set_default_cred_protect(policy)?;
+            }
+            if let Some(timeout_ms) = params.touch_timeout_ms {
+                persistent_store.set_touch_timeout_ms(timeout_ms)?;
+            }
+            if let Some(duration_ms) = params.touch_duration_ms {
+                persistent_store.set_touch_duration_ms(duration_ms)?;
+            }
+            if let Some(confirm) = params.confirm_with_second_touch {
+                persistent_store.set_confirm_with_second_touch(confirm)?;
+            }
+            #[cfg(feature = "with_ctap2_1")]
+            {
+                if params.force_pin_change {
+                    persistent_store.force_pin_change()?;
+                }
+            }
+            Ok(())
+        })?;
         if params.lockdown {
             // To avoid bricking the authenticator, we only allow lockdown
             // to happen if both values are programmed or if both U2F/CTAP1 and
@@ -982,6 +2099,8 @@ where
             let need_certificate = true;
             #[cfg(not(feature = "with_ctap1"))]
             let need_certificate = USE_BATCH_ATTESTATION;
+            #[cfg(all(not(feature = "with_ctap1"), feature = "with_ctap2_1"))]
+            let need_certificate = need_certificate || ENTERPRISE_ATTESTATION_MODE.is_some();
 
             if (need_certificate && !(response.pkey_programmed && response.cert_programmed))
                 || crp::set_protection(crp::ProtectionLevel::FullyLocked).is_err()
@@ -992,91 +2111,574 @@ where
         Ok(ResponseData::AuthenticatorVendor(response))
     }
 
-    pub fn generate_auth_data(
+    // Provisioning overwrites the attestation material, until it is locked. Unlike the lockdown,
+    // it doesn't touch the debug access protection.
+    fn process_vendor_provision(
+        &mut self,
+        params: AuthenticatorVendorProvisionParameters,
+        cid: ChannelID,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        self.request_user_presence(cid)?;
+        let AuthenticatorVendorProvisionParameters {
+            aaguid,
+            private_key,
+            certificate_chain,
+            generate_private_key,
+        } = params;
+        // A generated key replaces the given one, and an invalid scalar would fail every batch
+        // attestation later.
+        if let Some(private_key) = &private_key {
+            if generate_private_key || crypto::ecdsa::SecKey::from_bytes(private_key).is_none() {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+            }
+        }
+        // The secure element is not aware of the lock, so it is checked before generating.
+        if generate_private_key && self.persistent_store.is_provisioning_locked()? {
+            return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED);
+        }
+        let attestation_public_key = if generate_private_key {
+            Some(self.crypto_backend.generate_attestation_key()?)
+        } else {
+            None
+        };
+        self.persistent_store.provision_attestation(
+            aaguid.as_ref(),
+            private_key.as_ref(),
+            certificate_chain.as_deref(),
+        )?;
+        self.vendor_provision_response(attestation_public_key.map(CoseKey::from))
+    }
+
+    fn process_vendor_lock_provisioning(
+        &mut self,
+        cid: ChannelID,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        self.request_user_presence(cid)?;
+        // A private key without certificate, or the opposite, could never be fixed after locking.
+        let pkey_programmed = self.has_attestation_key()?;
+        let cert_programmed = self.persistent_store.attestation_certificate()?.is_some();
+        if pkey_programmed != cert_programmed {
+            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+        }
+        if !self.persistent_store.is_provisioning_locked()? {
+            self.persistent_store.lock_provisioning()?;
+        }
+        self.vendor_provision_response(None)
+    }
+
+    // Invalidates the pinUvAuthToken, for platforms that are done with it. Revoking is harmless,
+    // so it needs neither user presence nor a valid token.
+    fn process_vendor_revoke_pin_uv_auth_token(&mut self) -> Result<ResponseData, Ctap2StatusCode> {
+        self.pin_protocol_v1.stop_using_pin_uv_auth_token();
+        Ok(ResponseData::AuthenticatorVendorRevokePinUvAuthToken)
+    }
+
+    // Wraps new credential IDs with a new generation of master keys. Credential IDs of older
+    // generations stay valid, but a leaked master key no longer compromises future credentials.
+    fn process_vendor_rotate_master_keys(
+        &mut self,
+        cid: ChannelID,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        self.request_user_presence(cid)?;
+        let generation = self.persistent_store.rotate_master_keys(self.rng)?;
+        Ok(ResponseData::AuthenticatorVendorRotateMasterKeys(
+            AuthenticatorVendorRotateMasterKeysResponse {
+                generation: generation as u64,
+            },
+        ))
+    }
+
+    // Reports the provisioning state, without revealing the private key. The public key is only
+    // known right after its generation.
+    fn vendor_provision_response(
         &self,
-        rp_id_hash: &[u8],
-        flag_byte: u8,
-    ) -> Result<Vec<u8>, Ctap2StatusCode> {
-        let mut auth_data = vec![];
-        auth_data.extend(rp_id_hash);
-        auth_data.push(flag_byte);
-        // The global counter is only increased if USE_SIGNATURE_COUNTER is true.
-        // It uses a big-endian representation.
-        let mut signature_counter = [0u8; 4];
-        BigEndian::write_u32(
-            &mut signature_counter,
-            self.persistent_store.global_signature_counter()?,
-        );
-        auth_data.extend(&signature_counter);
-        Ok(auth_data)
+        attestation_public_key: Option<CoseKey>,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        let certificate_chain_length = match self.persistent_store.attestation_certificate()? {
+            Some(_) => {
+                1 + self
+                    .persistent_store
+                    .attestation_intermediate_certificates()?
+                    .len() as u64
+            }
+            None => 0,
+        };
+        Ok(ResponseData::AuthenticatorVendorProvision(
+            AuthenticatorVendorProvisionResponse {
+                aaguid: self.persistent_store.aaguid()?.to_vec(),
+                pkey_programmed: self.has_attestation_key()?,
+                certificate_chain_length,
+                locked: self.persistent_store.is_provisioning_locked()?,
+                attestation_public_key,
+            },
+        ))
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::command::AuthenticatorAttestationMaterial;
-    use super::data_formats::{
-        CoseKey, GetAssertionExtensions, GetAssertionOptions, MakeCredentialExtensions,
-        MakeCredentialOptions, PublicKeyCredentialRpEntity, PublicKeyCredentialUserEntity,
-    };
-    use super::*;
-    use cbor::cbor_array;
-    use crypto::rng256::ThreadRng256;
+    // Returns whether batch attestation has a private key, in the secure element or in the
+    // persistent store.
+    fn has_attestation_key(&self) -> Result<bool, Ctap2StatusCode> {
+        Ok(self.crypto_backend.has_attestation_key()
+            || self.persistent_store.has_attestation_private_key()?)
+    }
 
-    const CLOCK_FREQUENCY_HZ: usize = 32768;
-    const DUMMY_CLOCK_VALUE: ClockValue = ClockValue::new(0, CLOCK_FREQUENCY_HZ);
-    // The keep-alive logic in the processing of some commands needs a channel ID to send
-    // keep-alive packets to.
-    // In tests where we define a dummy user-presence check that immediately returns, the channel
-    // ID is irrelevant, so we pass this (dummy but valid) value.
-    const DUMMY_CHANNEL_ID: ChannelID = [0x12, 0x34, 0x56, 0x78];
+    fn process_vendor_self_test(&mut self) -> Result<ResponseData, Ctap2StatusCode> {
+        self.self_test_failure = self_test::run(&*self.rng).err();
+        Ok(ResponseData::AuthenticatorVendorSelfTest(
+            AuthenticatorVendorSelfTestResponse {
+                passed: self.self_test_failure.is_none(),
+                failed_test: self.self_test_failure.map(SelfTestFailure::number),
+            },
+        ))
+    }
 
-    #[test]
-    fn test_get_info() {
-        let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
-        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
-        let info_reponse = ctap_state.process_command(&[0x04], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+    // The benchmark compacts the store, so it is only run on request of the user.
+    fn process_vendor_benchmark(
+        &mut self,
+        cid: ChannelID,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        self.request_user_presence(cid)?;
+        Ok(ResponseData::AuthenticatorVendorBenchmark(benchmark::run(
+            self.rng,
+            &mut self.persistent_store,
+        )?))
+    }
 
-        #[cfg(feature = "with_ctap2_1")]
-        let mut expected_response = vec![0x00, 0xAA, 0x01];
-        #[cfg(not(feature = "with_ctap2_1"))]
-        let mut expected_response = vec![0x00, 0xA6, 0x01];
-        // The difference here is a longer array of supported versions.
-        let mut version_count = 0;
-        // CTAP 2 is always supported
-        version_count += 1;
-        #[cfg(feature = "with_ctap1")]
-        {
-            version_count += 1;
+    // Compacts the store until the next commands don't need to. Compaction only happens when
+    // these commands would have done it, so this doesn't cost lifetime of the flash.
+    fn process_vendor_compact(&mut self) -> Result<ResponseData, Ctap2StatusCode> {
+        let mut compacted_pages = 0;
+        while self.persistent_store.compact_step()? {
+            compacted_pages += 1;
         }
-        #[cfg(feature = "with_ctap2_1")]
-        {
-            version_count += 1;
+        Ok(ResponseData::AuthenticatorVendorCompact(
+            AuthenticatorVendorCompactResponse { compacted_pages },
+        ))
+    }
+
+    fn process_vendor_wear(&self) -> Result<ResponseData, Ctap2StatusCode> {
+        let wear = self.persistent_store.wear()?;
+        Ok(ResponseData::AuthenticatorVendorWear(
+            AuthenticatorVendorWearResponse {
+                used_erases: wear.used_erases() as u64,
+                total_erases: wear.total_erases() as u64,
+                max_page_erases: wear.max_page_erases() as u64,
+                page_erases: wear.page_erases().iter().map(|&e| e as u64).collect(),
+                written_bytes: wear.written_bytes() as u64,
+            },
+        ))
+    }
+
+    // Checks the pinUvAuthParam that authorizes a backup or restore. It is required if a PIN is set,
+    // with the credential management permission. The message is 32 bytes 0xFF, the command byte
+    // and the authorized parameters. Returns whether the user was verified.
+    fn check_backup_pin_uv_auth_param(
+        &mut self,
+        message: &[u8],
+        pin_uv_auth_protocol: Option<u64>,
+        pin_uv_auth_param: Option<Vec<u8>>,
+    ) -> Result<bool, Ctap2StatusCode> {
+        let pin_uv_auth_param = match pin_uv_auth_param {
+            Some(pin_uv_auth_param) => pin_uv_auth_param,
+            None if self.persistent_store.pin_hash()?.is_some() => {
+                return Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)
+            }
+            None => return Ok(false),
+        };
+        let pin_uv_auth_protocol = match pin_uv_auth_protocol {
+            Some(protocol) if PIN_PROTOCOLS.contains(&protocol) => protocol,
+            Some(_) => return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID),
+            None => return Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER),
+        };
+        if !self.pin_protocol_v1.verify_pin_auth_token(
+            message,
+            &pin_uv_auth_param,
+            pin_uv_auth_protocol,
+        ) {
+            return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
         }
-        expected_response.push(0x80 + version_count);
-        #[cfg(feature = "with_ctap1")]
-        expected_response.extend(&[0x66, 0x55, 0x32, 0x46, 0x5F, 0x56, 0x32]);
-        expected_response.extend(&[0x68, 0x46, 0x49, 0x44, 0x4F, 0x5F, 0x32, 0x5F, 0x30]);
         #[cfg(feature = "with_ctap2_1")]
-        expected_response.extend(&[
-            0x6C, 0x46, 0x49, 0x44, 0x4F, 0x5F, 0x32, 0x5F, 0x31, 0x5F, 0x50, 0x52, 0x45,
+        self.pin_protocol_v1
+            .has_permission(PinPermission::CredentialManagement)?;
+        Ok(true)
+    }
+
+    // Exports the resident credentials, encrypted under a passphrase. The first call starts the
+    // backup and needs the passphrase, a touch and the PIN if one is set. Each response holds a
+    // page of credentials, and later calls without passphrase return the next pages. Credentials
+    // that require user verification are only exported if the user was verified.
+    fn process_vendor_backup(
+        &mut self,
+        params: AuthenticatorVendorBackupParameters,
+        cid: ChannelID,
+        now: ClockValue,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        let AuthenticatorVendorBackupParameters {
+            passphrase,
+            pin_uv_auth_protocol,
+            pin_uv_auth_param,
+        } = params;
+        let (backup_key, previous_key, user_verified, total_credentials) = match passphrase {
+            Some(mut passphrase) => {
+                let mut message = vec![0xFF; 32];
+                message.push(0x4B);
+                message.extend(&passphrase);
+                let user_verified = self.check_backup_pin_uv_auth_param(
+                    &message,
+                    pin_uv_auth_protocol,
+                    pin_uv_auth_param,
+                );
+                message.zeroize();
+                let user_verified = user_verified?;
+                let keepalive = &mut self.keepalive;
+                let backup_key = BackupKey::new(self.rng, &passphrase, || {
+                    keepalive.tick(cid, KeepaliveStatus::Processing)
+                });
+                passphrase.zeroize();
+                let backup_key = backup_key?;
+                self.request_user_presence(cid)?;
+                let mut iter_result = Ok(());
+                let iter = self.persistent_store.iter_credentials(&mut iter_result)?;
+                let total_credentials = iter
+                    .filter(|(_, credential)| is_exportable(credential, user_verified))
+                    .count();
+                iter_result?;
+                self.persistent_store.append_audit_record(AuditRecord {
+                    event: AuditEvent::CredentialBackup,
+                    credential_count: total_credentials as u32,
+                })?;
+                (
+                    backup_key,
+                    None,
+                    user_verified,
+                    Some(total_credentials as u64),
+                )
+            }
+            None => {
+                self.check_command_permission(now)?;
+                match self.stateful_command_type.take() {
+                    Some(StatefulCommand::Backup(backup_key, previous_key, user_verified)) => {
+                        (backup_key, Some(previous_key), user_verified, None)
+                    }
+                    _ => return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED),
+                }
+            }
+        };
+        // Keeps the exportable credentials with the smallest keys after the previous page, and one
+        // more to know whether there is a next page. The credentials are not in key order.
+        let mut credentials: Vec<(usize, PublicKeyCredentialSource)> =
+            Vec::with_capacity(BACKUP_PAGE_LENGTH + 1);
+        let mut iter_result = Ok(());
+        let iter = self.persistent_store.iter_credentials(&mut iter_result)?;
+        for (key, credential) in iter {
+            if previous_key.map_or(false, |previous_key| key <= previous_key)
+                || !is_exportable(&credential, user_verified)
+            {
+                continue;
+            }
+            let index = credentials
+                .binary_search_by_key(&key, |(key, _)| *key)
+                .unwrap_or_else(|index| index);
+            if index <= BACKUP_PAGE_LENGTH {
+                credentials.insert(index, (key, credential));
+                credentials.truncate(BACKUP_PAGE_LENGTH + 1);
+            }
+        }
+        iter_result?;
+        // Holds the key of the last credential of this page, if there is a next page.
+        let next_page_key = credentials
+            .get(BACKUP_PAGE_LENGTH)
+            .map(|_| credentials[BACKUP_PAGE_LENGTH - 1].0);
+        credentials.truncate(BACKUP_PAGE_LENGTH);
+        let mut records = Vec::with_capacity(credentials.len());
+        for (_, credential) in credentials {
+            records.push(backup_key.encrypt(self.rng, credential)?);
+        }
+        let header = match previous_key {
+            None => Some(backup_key.header().to_vec()),
+            Some(_) => None,
+        };
+        if let Some(next_page_key) = next_page_key {
+            self.stateful_command_permission =
+                TimedPermission::granted(now, STATEFUL_COMMAND_TIMEOUT_DURATION);
+            self.stateful_command_type = Some(StatefulCommand::Backup(
+                backup_key,
+                next_page_key,
+                user_verified,
+            ));
+        }
+        Ok(ResponseData::AuthenticatorVendorBackup(
+            AuthenticatorVendorBackupResponse {
+                header,
+                records,
+                total_credentials,
+            },
+        ))
+    }
+
+    // Imports credentials from a backup. The first call starts the restore and needs the
+    // passphrase, the header of the backup, a touch and the PIN if one is set. Later calls without
+    // passphrase import more records of the same backup. Credentials of the same relying party and
+    // user are replaced.
+    fn process_vendor_restore(
+        &mut self,
+        params: AuthenticatorVendorRestoreParameters,
+        cid: ChannelID,
+        now: ClockValue,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        let AuthenticatorVendorRestoreParameters {
+            passphrase,
+            header,
+            records,
+            pin_uv_auth_protocol,
+            pin_uv_auth_param,
+        } = params;
+        let (backup_key, previously_restored) = match (passphrase, header) {
+            (Some(mut passphrase), Some(header)) => {
+                let mut message = vec![0xFF; 32];
+                message.push(0x4C);
+                message.extend(&passphrase);
+                message.extend(&header);
+                let user_verified = self.check_backup_pin_uv_auth_param(
+                    &message,
+                    pin_uv_auth_protocol,
+                    pin_uv_auth_param,
+                );
+                message.zeroize();
+                user_verified?;
+                let keepalive = &mut self.keepalive;
+                let backup_key = BackupKey::from_header(&passphrase, &header, || {
+                    keepalive.tick(cid, KeepaliveStatus::Processing)
+                });
+                passphrase.zeroize();
+                let backup_key = backup_key?;
+                self.request_user_presence(cid)?;
+                // The record is updated with the number of restored credentials after each page.
+                self.persistent_store.append_audit_record(AuditRecord {
+                    event: AuditEvent::CredentialRestore,
+                    credential_count: 0,
+                })?;
+                (backup_key, 0)
+            }
+            (Some(_), None) => return Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER),
+            (None, _) => {
+                self.check_command_permission(now)?;
+                match self.stateful_command_type.take() {
+                    Some(StatefulCommand::Restore(backup_key, previously_restored)) => {
+                        (backup_key, previously_restored)
+                    }
+                    _ => return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED),
+                }
+            }
+        };
+        // All records are decrypted first, so that a corrupted page stores nothing.
+        let credentials = records
+            .iter()
+            .map(|record| backup_key.decrypt(record))
+            .collect::<Result<Vec<_>, Ctap2StatusCode>>()?;
+        let restored_credentials = credentials.len() as u64;
+        for credential in credentials {
+            self.persistent_store.store_credential(credential)?;
+        }
+        let total_restored = previously_restored + restored_credentials as u32;
+        self.persistent_store
+            .update_last_audit_record(AuditRecord {
+                event: AuditEvent::CredentialRestore,
+                credential_count: total_restored,
+            })?;
+        self.stateful_command_permission =
+            TimedPermission::granted(now, STATEFUL_COMMAND_TIMEOUT_DURATION);
+        self.stateful_command_type = Some(StatefulCommand::Restore(backup_key, total_restored));
+        Ok(ResponseData::AuthenticatorVendorRestore(
+            AuthenticatorVendorRestoreResponse {
+                restored_credentials,
+            },
+        ))
+    }
+
+    // Returns the audit log, so that the owner can check for backups or restores they didn't do.
+    fn process_vendor_audit_log(&self) -> Result<ResponseData, Ctap2StatusCode> {
+        Ok(ResponseData::AuthenticatorVendorAuditLog(
+            AuthenticatorVendorAuditLogResponse {
+                records: self.persistent_store.audit_log()?,
+            },
+        ))
+    }
+
+    fn process_vendor_diagnostics(&self) -> Result<ResponseData, Ctap2StatusCode> {
+        Ok(ResponseData::AuthenticatorVendorDiagnostics(
+            AuthenticatorVendorDiagnosticsResponse {
+                quarantined_entries: self.persistent_store.quarantined_entries()? as u64,
+            },
+        ))
+    }
+
+    #[cfg(feature = "with_nfc")]
+    fn process_vendor_nfc_statistics(&self) -> Result<ResponseData, Ctap2StatusCode> {
+        let NfcStatistics {
+            frames_received,
+            frames_sent,
+            crc_errors,
+            wtx_requests,
+            aborts,
+        } = self.nfc_statistics;
+        Ok(ResponseData::AuthenticatorVendorNfcStatistics(
+            AuthenticatorVendorNfcStatisticsResponse {
+                frames_received,
+                frames_sent,
+                crc_errors,
+                wtx_requests,
+                aborts,
+            },
+        ))
+    }
+
+    // The self-test takes over the NFC driver, so it can't run over NFC.
+    #[cfg(feature = "with_nfc")]
+    fn process_vendor_nfc_self_test(
+        &mut self,
+        cid: ChannelID,
+    ) -> Result<ResponseData, Ctap2StatusCode> {
+        if cid == NFC_CHANNEL_ID {
+            return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED);
+        }
+        self.nfc_driver_taken = true;
+        let response = match nfc::self_test::run() {
+            Ok(()) => AuthenticatorVendorNfcSelfTestResponse {
+                passed: true,
+                failed_step: None,
+                error_code: None,
+            },
+            Err((step, error)) => AuthenticatorVendorNfcSelfTestResponse {
+                passed: false,
+                failed_step: Some(step as u64),
+                error_code: error.map(|e| e.return_code() as i64),
+            },
+        };
+        Ok(ResponseData::AuthenticatorVendorNfcSelfTest(response))
+    }
+
+    pub fn generate_auth_data(
+        &self,
+        rp_id_hash: &[u8],
+        flag_byte: u8,
+        signature_counter: u32,
+    ) -> Vec<u8> {
+        let mut auth_data = vec![];
+        auth_data.extend(rp_id_hash);
+        auth_data.push(flag_byte);
+        // The counter uses a big-endian representation.
+        let mut signature_counter_bytes = [0u8; 4];
+        BigEndian::write_u32(&mut signature_counter_bytes, signature_counter);
+        auth_data.extend(&signature_counter_bytes);
+        auth_data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::command::AuthenticatorAttestationMaterial;
+    use super::data_formats::{
+        extract_map, GetAssertionHmacSecretInput, GetAssertionOptions, MakeCredentialExtensions,
+        MakeCredentialOptions, PublicKeyCredentialRpEntity, PublicKeyCredentialUserEntity,
+    };
+    use super::storage::MasterKeys;
+    use super::*;
+    use cbor::{cbor_array, cbor_map};
+    use crypto::cbc::cbc_encrypt;
+    use crypto::rng256::ThreadRng256;
+
+    const CLOCK_FREQUENCY_HZ: usize = 32768;
+    const DUMMY_CLOCK_VALUE: ClockValue = ClockValue::new(0, CLOCK_FREQUENCY_HZ);
+    // The keep-alive logic in the processing of some commands needs a channel ID to send
+    // keep-alive packets to.
+    // In tests where we define a dummy user-presence check that immediately returns, the channel
+    // ID is irrelevant, so we pass this (dummy but valid) value.
+    const DUMMY_CHANNEL_ID: ChannelID = [0x12, 0x34, 0x56, 0x78];
+
+    #[test]
+    fn test_get_info() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let info_reponse = ctap_state.process_command(&[0x04], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+
+        #[cfg(feature = "with_ctap2_1")]
+        let mut expected_response = vec![0x00, 0xAF, 0x01];
+        #[cfg(not(feature = "with_ctap2_1"))]
+        let mut expected_response = vec![0x00, 0xA6, 0x01];
+        // The difference here is a longer array of supported versions.
+        let mut version_count = 0;
+        // CTAP 2 is always supported
+        version_count += 1;
+        #[cfg(feature = "with_ctap1")]
+        {
+            version_count += 1;
+        }
+        #[cfg(feature = "with_ctap2_1")]
+        {
+            version_count += 1;
+        }
+        expected_response.push(0x80 + version_count);
+        #[cfg(feature = "with_ctap1")]
+        expected_response.extend(&[0x66, 0x55, 0x32, 0x46, 0x5F, 0x56, 0x32]);
+        expected_response.extend(&[0x68, 0x46, 0x49, 0x44, 0x4F, 0x5F, 0x32, 0x5F, 0x30]);
+        #[cfg(feature = "with_ctap2_1")]
+        expected_response.extend(&[
+            0x6C, 0x46, 0x49, 0x44, 0x4F, 0x5F, 0x32, 0x5F, 0x31, 0x5F, 0x50, 0x52, 0x45,
+        ]);
+        #[cfg(not(feature = "with_ctap2_1"))]
+        expected_response.extend(&[0x02, 0x82]);
+        #[cfg(feature = "with_ctap2_1")]
+        expected_response.extend(&[0x02, 0x86]);
+        expected_response.extend(&[
+            0x6B, 0x68, 0x6D, 0x61, 0x63, 0x2D, 0x73, 0x65, 0x63, 0x72, 0x65, 0x74, 0x63, 0x75,
+            0x76, 0x6D,
         ]);
+        #[cfg(feature = "with_ctap2_1")]
         expected_response.extend(&[
-            0x02, 0x81, 0x6B, 0x68, 0x6D, 0x61, 0x63, 0x2D, 0x73, 0x65, 0x63, 0x72, 0x65, 0x74,
-            0x03, 0x50,
+            0x68, 0x63, 0x72, 0x65, 0x64, 0x42, 0x6C, 0x6F, 0x62, 0x6C, 0x6C, 0x61, 0x72, 0x67,
+            0x65, 0x42, 0x6C, 0x6F, 0x62, 0x4B, 0x65, 0x79, 0x6C, 0x6D, 0x69, 0x6E, 0x50, 0x69,
+            0x6E, 0x4C, 0x65, 0x6E, 0x67, 0x74, 0x68, 0x6E, 0x68, 0x6D, 0x61, 0x63, 0x2D, 0x73,
+            0x65, 0x63, 0x72, 0x65, 0x74, 0x2D, 0x6D, 0x63,
         ]);
+        expected_response.extend(&[0x03, 0x50]);
         expected_response.extend(&ctap_state.persistent_store.aaguid().unwrap());
+        #[cfg(not(feature = "with_ctap2_1"))]
         expected_response.extend(&[
             0x04, 0xA3, 0x62, 0x72, 0x6B, 0xF5, 0x62, 0x75, 0x70, 0xF5, 0x69, 0x63, 0x6C, 0x69,
-            0x65, 0x6E, 0x74, 0x50, 0x69, 0x6E, 0xF4, 0x05, 0x19, 0x04, 0x00, 0x06, 0x81, 0x01,
+            0x65, 0x6E, 0x74, 0x50, 0x69, 0x6E, 0xF4,
+        ]);
+        #[cfg(feature = "with_ctap2_1")]
+        expected_response.extend(&[
+            0x04, 0xA8, 0x62, 0x72, 0x6B, 0xF5, 0x62, 0x75, 0x70, 0xF5, 0x68, 0x61, 0x6C, 0x77,
+            0x61, 0x79, 0x73, 0x55, 0x76, 0xF4, 0x68, 0x63, 0x72, 0x65, 0x64, 0x4D, 0x67, 0x6D,
+            0x74, 0xF5, 0x69, 0x61, 0x75, 0x74, 0x68, 0x6E, 0x72, 0x43, 0x66, 0x67, 0xF5, 0x69,
+            0x63, 0x6C, 0x69, 0x65, 0x6E, 0x74, 0x50, 0x69, 0x6E, 0xF4, 0x6A, 0x6C, 0x61, 0x72,
+            0x67, 0x65, 0x42, 0x6C, 0x6F, 0x62, 0x73, 0xF5, 0x6F, 0x73, 0x65, 0x74, 0x4D, 0x69,
+            0x6E, 0x50, 0x49, 0x4E, 0x4C, 0x65, 0x6E, 0x67, 0x74, 0x68, 0xF5,
+        ]);
+        expected_response.extend(&[0x05, 0x19, 0x04, 0x00]);
+        #[cfg(not(feature = "with_ctap2_1"))]
+        expected_response.extend(&[0x06, 0x81, 0x01]);
+        #[cfg(feature = "with_ctap2_1")]
+        expected_response.extend(&[0x06, 0x82, 0x02, 0x01]);
+        #[cfg(all(feature = "with_ctap2_1", not(feature = "with_nfc")))]
+        expected_response.extend(&[0x08, 0x18, 0x40, 0x09, 0x81, 0x63, 0x75, 0x73, 0x62]);
+        // NFC is listed after USB in the transports.
+        #[cfg(all(feature = "with_ctap2_1", feature = "with_nfc"))]
+        expected_response.extend(&[
+            0x08, 0x18, 0x40, 0x09, 0x82, 0x63, 0x75, 0x73, 0x62, 0x63, 0x6E, 0x66, 0x63,
         ]);
         #[cfg(feature = "with_ctap2_1")]
         expected_response.extend(
             [
-                0x08, 0x18, 0x70, 0x09, 0x81, 0x63, 0x75, 0x73, 0x62, 0x0A, 0x81, 0xA2, 0x63, 0x61,
-                0x6C, 0x67, 0x26, 0x64, 0x74, 0x79, 0x70, 0x65, 0x6A, 0x70, 0x75, 0x62, 0x6C, 0x69,
-                0x63, 0x2D, 0x6B, 0x65, 0x79, 0x0D, 0x04,
+                0x0A, 0x83, 0xA2, 0x63, 0x61, 0x6C, 0x67, 0x26, 0x64, 0x74, 0x79, 0x70, 0x65, 0x6A,
+                0x70, 0x75, 0x62, 0x6C, 0x69, 0x63, 0x2D, 0x6B, 0x65, 0x79, 0xA2, 0x63, 0x61, 0x6C,
+                0x67, 0x27, 0x64, 0x74, 0x79, 0x70, 0x65, 0x6A, 0x70, 0x75, 0x62, 0x6C, 0x69, 0x63,
+                0x2D, 0x6B, 0x65, 0x79, 0xA2, 0x63, 0x61, 0x6C, 0x67, 0x38, 0x22, 0x64, 0x74, 0x79,
+                0x70, 0x65, 0x6A, 0x70, 0x75, 0x62, 0x6C, 0x69, 0x63, 0x2D, 0x6B, 0x65, 0x79, 0x0B,
+                0x19, 0x08, 0x00, 0x0C, 0xF4, 0x0D, 0x04, 0x0F, 0x18, 0x20, 0x10, 0x08, 0x14, 0x18,
+                0x96,
             ]
             .iter(),
         );
@@ -1084,6 +2686,74 @@ mod test {
         assert_eq!(info_reponse, expected_response);
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_get_info_always_uv() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        ctap_state.persistent_store.toggle_always_uv().unwrap();
+        match ctap_state.process_get_info(DUMMY_CHANNEL_ID).unwrap() {
+            ResponseData::AuthenticatorGetInfo(get_info_response) => {
+                #[cfg(feature = "with_ctap1")]
+                assert!(!get_info_response
+                    .versions
+                    .contains(&String::from(U2F_VERSION_STRING)));
+                assert_eq!(
+                    get_info_response.options.unwrap().get("alwaysUv"),
+                    Some(&true)
+                );
+            }
+            _ => panic!("Invalid response type"),
+        };
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_get_info_remaining_discoverable_credentials() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let remaining_discoverable_credentials = |ctap_state: &CtapState<_, _>| match ctap_state
+            .process_get_info(DUMMY_CHANNEL_ID)
+            .unwrap()
+        {
+            ResponseData::AuthenticatorGetInfo(get_info_response) => get_info_response
+                .remaining_discoverable_credentials
+                .unwrap(),
+            _ => panic!("Invalid response type"),
+        };
+        let initial_remaining = remaining_discoverable_credentials(&ctap_state);
+        assert!(initial_remaining > 0);
+
+        let make_credential_params = create_minimal_make_credential_parameters();
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+        assert_eq!(
+            remaining_discoverable_credentials(&ctap_state),
+            initial_remaining - 1
+        );
+    }
+
+    #[test]
+    fn test_process_command_too_large() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let max_msg_size = transport_of(DUMMY_CHANNEL_ID).max_msg_size();
+        let command_cbor = vec![0x04; max_msg_size + 1];
+        let response =
+            ctap_state.process_command(&command_cbor, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            response,
+            vec![Ctap2StatusCode::CTAP2_ERR_REQUEST_TOO_LARGE as u8]
+        );
+    }
+
     fn create_minimal_make_credential_parameters() -> AuthenticatorMakeCredentialParameters {
         let client_data_hash = vec![0xCD];
         let rp = PublicKeyCredentialRpEntity {
@@ -1112,6 +2782,8 @@ mod test {
             options,
             pin_uv_auth_param: None,
             pin_uv_auth_protocol: None,
+            #[cfg(feature = "with_ctap2_1")]
+            enterprise_attestation: None,
         }
     }
 
@@ -1123,7 +2795,7 @@ mod test {
             key_id: excluded_credential_id.to_vec(),
             transports: None,
         };
-        let exclude_list = Some(vec![excluded_credential_descriptor]);
+        let exclude_list = Some(vec![excluded_credential_descriptor].into());
         let mut make_credential_params = create_minimal_make_credential_parameters();
         make_credential_params.exclude_list = exclude_list;
         make_credential_params
@@ -1135,6 +2807,15 @@ mod test {
         let extensions = Some(MakeCredentialExtensions {
             hmac_secret: false,
             cred_protect: Some(policy),
+            uvm: false,
+            #[cfg(feature = "with_ctap2_1")]
+            cred_blob: None,
+            #[cfg(feature = "with_ctap2_1")]
+            large_blob_key: None,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
+            #[cfg(feature = "with_ctap2_1")]
+            hmac_secret_mc: None,
         });
         let mut make_credential_params = create_minimal_make_credential_parameters();
         make_credential_params.extensions = extensions;
@@ -1144,7 +2825,7 @@ mod test {
     #[test]
     fn test_residential_process_make_credential() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
         let make_credential_params = create_minimal_make_credential_parameters();
@@ -1157,6 +2838,7 @@ mod test {
                     fmt,
                     auth_data,
                     att_stmt,
+                    ..
                 } = make_credential_response;
                 // The expected response is split to only assert the non-random parts.
                 assert_eq!(fmt, "packed");
@@ -1181,7 +2863,7 @@ mod test {
     #[test]
     fn test_non_residential_process_make_credential() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
         let mut make_credential_params = create_minimal_make_credential_parameters();
@@ -1195,6 +2877,7 @@ mod test {
                     fmt,
                     auth_data,
                     att_stmt,
+                    ..
                 } = make_credential_response;
                 // The expected response is split to only assert the non-random parts.
                 assert_eq!(fmt, "packed");
@@ -1216,10 +2899,104 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_process_make_credential_eddsa() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // The first supported algorithm in the list of the relying party is chosen.
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.options.rk = false;
+        make_credential_params.pub_key_cred_params = vec![EDDSA_CRED_PARAM, ES256_CRED_PARAM];
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+
+        match make_credential_response.unwrap() {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                let AuthenticatorMakeCredentialResponse {
+                    auth_data,
+                    att_stmt,
+                    ..
+                } = make_credential_response;
+                assert_eq!(att_stmt.alg, SignatureAlgorithm::EDDSA as i64);
+                assert_eq!(att_stmt.sig.len(), 64);
+                // The credential public key is a COSE_Key of type OKP on Ed25519.
+                let cose_key_start = 37 + 16 + 2 + CREDENTIAL_ID_SIZE;
+                assert_eq!(
+                    auth_data[cose_key_start..cose_key_start + 10],
+                    [0xA4, 0x01, 0x01, 0x03, 0x27, 0x20, 0x06, 0x21, 0x58, 0x20]
+                );
+                let credential_id =
+                    auth_data[cose_key_start - CREDENTIAL_ID_SIZE..cose_key_start].to_vec();
+                let rp_id_hash = Sha256::hash(b"example.com");
+                let credential = ctap_state
+                    .decrypt_credential_source(credential_id, &rp_id_hash)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(
+                    credential.private_key.signature_algorithm(),
+                    SignatureAlgorithm::EDDSA
+                );
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    #[test]
+    fn test_process_make_credential_es384() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.options.rk = false;
+        make_credential_params.pub_key_cred_params = vec![ES384_CRED_PARAM];
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+
+        match make_credential_response.unwrap() {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                let AuthenticatorMakeCredentialResponse {
+                    auth_data,
+                    att_stmt,
+                    ..
+                } = make_credential_response;
+                assert_eq!(att_stmt.alg, SignatureAlgorithm::ES384 as i64);
+                let cose_key_start = 37 + 16 + 2 + CREDENTIAL_ID_SIZE;
+                let credential_id =
+                    auth_data[cose_key_start - CREDENTIAL_ID_SIZE..cose_key_start].to_vec();
+                let rp_id_hash = Sha256::hash(b"example.com");
+                let credential = ctap_state
+                    .decrypt_credential_source(credential_id, &rp_id_hash)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(
+                    credential.private_key.signature_algorithm(),
+                    SignatureAlgorithm::ES384
+                );
+                // The credential public key is a COSE_Key of type EC2 on P-384.
+                let cose_map = extract_map(cbor::read(&auth_data[cose_key_start..]).unwrap());
+                let public_key = crypto::ecdsa_p384::PubKey::try_from(CoseKey(cose_map.unwrap()));
+                assert_eq!(
+                    CoseKey::from(public_key.unwrap()),
+                    credential.private_key.public_key_cose()
+                );
+                // Self attestation is signed with the credential key.
+                let mut signature_data = auth_data;
+                signature_data.push(0xCD);
+                assert!(credential
+                    .private_key
+                    .verify_encoded(&signature_data, &att_stmt.sig));
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
     #[test]
     fn test_process_make_credential_unsupported_algorithm() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
         let mut make_credential_params = create_minimal_make_credential_parameters();
@@ -1236,8 +3013,8 @@ mod test {
     #[test]
     fn test_process_make_credential_credential_excluded() {
         let mut rng = ThreadRng256 {};
-        let excluded_private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
-        let user_immediately_present = |_| Ok(());
+        let excluded_private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
         let excluded_credential_id = vec![0x01, 0x23, 0x45, 0x67];
@@ -1252,8 +3029,12 @@ mod test {
             user_display_name: None,
             cred_protect_policy: None,
             creation_order: 0,
+            last_used_order: None,
+            signature_counter: None,
             user_name: None,
             user_icon: None,
+            large_blob_key: None,
+            cred_blob: None,
         };
         assert!(ctap_state
             .persistent_store
@@ -1271,7 +3052,7 @@ mod test {
     #[test]
     fn test_process_make_credential_credential_with_cred_protect() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
         let test_policy = CredentialProtectionPolicy::UserVerificationOptionalWithCredentialIdList;
@@ -1325,12 +3106,21 @@ mod test {
     #[test]
     fn test_process_make_credential_hmac_secret() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
         let extensions = Some(MakeCredentialExtensions {
             hmac_secret: true,
             cred_protect: None,
+            uvm: false,
+            #[cfg(feature = "with_ctap2_1")]
+            cred_blob: None,
+            #[cfg(feature = "with_ctap2_1")]
+            large_blob_key: None,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
+            #[cfg(feature = "with_ctap2_1")]
+            hmac_secret_mc: None,
         });
         let mut make_credential_params = create_minimal_make_credential_parameters();
         make_credential_params.options.rk = false;
@@ -1344,6 +3134,7 @@ mod test {
                     fmt,
                     auth_data,
                     att_stmt,
+                    ..
                 } = make_credential_response;
                 // The expected response is split to only assert the non-random parts.
                 assert_eq!(fmt, "packed");
@@ -1374,309 +3165,579 @@ mod test {
     }
 
     #[test]
-    fn test_process_make_credential_hmac_secret_resident_key() {
+    fn test_process_make_credential_uvm() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
-        let extensions = Some(MakeCredentialExtensions {
-            hmac_secret: true,
-            cred_protect: None,
-        });
         let mut make_credential_params = create_minimal_make_credential_parameters();
-        make_credential_params.extensions = extensions;
+        make_credential_params.options.rk = false;
+        make_credential_params.extensions = Some(MakeCredentialExtensions {
+            uvm: true,
+            ..Default::default()
+        });
         let make_credential_response =
             ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
 
         match make_credential_response.unwrap() {
             ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
-                let AuthenticatorMakeCredentialResponse {
-                    fmt,
-                    auth_data,
-                    att_stmt,
-                } = make_credential_response;
-                // The expected response is split to only assert the non-random parts.
-                assert_eq!(fmt, "packed");
-                let mut expected_auth_data = vec![
-                    0xA3, 0x79, 0xA6, 0xF6, 0xEE, 0xAF, 0xB9, 0xA5, 0x5E, 0x37, 0x8C, 0x11, 0x80,
-                    0x34, 0xE2, 0x75, 0x1E, 0x68, 0x2F, 0xAB, 0x9F, 0x2D, 0x30, 0xAB, 0x13, 0xD2,
-                    0x12, 0x55, 0x86, 0xCE, 0x19, 0x47, 0xC1, 0x00, 0x00, 0x00,
-                ];
-                expected_auth_data.push(INITIAL_SIGNATURE_COUNTER as u8);
-                expected_auth_data.extend(&ctap_state.persistent_store.aaguid().unwrap());
-                expected_auth_data.extend(&[0x00, 0x20]);
-                assert_eq!(
-                    auth_data[0..expected_auth_data.len()],
-                    expected_auth_data[..]
-                );
-                let expected_extension_cbor = vec![
-                    0xA1, 0x6B, 0x68, 0x6D, 0x61, 0x63, 0x2D, 0x73, 0x65, 0x63, 0x72, 0x65, 0x74,
-                    0xF5,
-                ];
+                let auth_data = make_credential_response.auth_data;
+                // Only user presence was checked.
+                let expected_extension_cbor =
+                    vec![0xA1, 0x63, 0x75, 0x76, 0x6D, 0x81, 0x83, 0x01, 0x02, 0x04];
                 assert_eq!(
                     auth_data[auth_data.len() - expected_extension_cbor.len()..auth_data.len()],
                     expected_extension_cbor[..]
                 );
-                assert_eq!(att_stmt.alg, SignatureAlgorithm::ES256 as i64);
             }
             _ => panic!("Invalid response type"),
         }
     }
 
     #[test]
-    fn test_process_make_credential_cancelled() {
+    fn test_user_verification_methods() {
         let mut rng = ThreadRng256 {};
-        let user_presence_always_cancel = |_| Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
-        let mut ctap_state =
-            CtapState::new(&mut rng, user_presence_always_cancel, DUMMY_CLOCK_VALUE);
-
-        let make_credential_params = create_minimal_make_credential_parameters();
-        let make_credential_response =
-            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        let user_immediately_present = |_, _| Ok(());
+        let ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
+        assert_eq!(ctap_state.user_verification_methods(0x00, false), 0);
         assert_eq!(
-            make_credential_response,
-            Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+            ctap_state.user_verification_methods(UP_FLAG, false),
+            USER_VERIFY_PRESENCE_INTERNAL
+        );
+        // Without a token, the user was verified by the sensor.
+        assert_eq!(
+            ctap_state.user_verification_methods(UP_FLAG | UV_FLAG, false),
+            USER_VERIFY_PRESENCE_INTERNAL | USER_VERIFY_FINGERPRINT_INTERNAL
+        );
+        assert_eq!(
+            ctap_state.user_verification_methods(UV_FLAG, true),
+            USER_VERIFY_PASSCODE_EXTERNAL
         );
     }
 
-    fn check_assertion_response_with_user(
-        response: Result<ResponseData, Ctap2StatusCode>,
-        expected_user: PublicKeyCredentialUserEntity,
-        flags: u8,
-        signature_counter: u32,
-        expected_number_of_credentials: Option<u64>,
-    ) {
-        match response.unwrap() {
-            ResponseData::AuthenticatorGetAssertion(get_assertion_response) => {
-                let AuthenticatorGetAssertionResponse {
+    #[test]
+    fn test_process_make_credential_hmac_secret_resident_key() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let extensions = Some(MakeCredentialExtensions {
+            hmac_secret: true,
+            cred_protect: None,
+            uvm: false,
+            #[cfg(feature = "with_ctap2_1")]
+            cred_blob: None,
+            #[cfg(feature = "with_ctap2_1")]
+            large_blob_key: None,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
+            #[cfg(feature = "with_ctap2_1")]
+            hmac_secret_mc: None,
+        });
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.extensions = extensions;
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+
+        match make_credential_response.unwrap() {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                let AuthenticatorMakeCredentialResponse {
+                    fmt,
                     auth_data,
-                    user,
-                    number_of_credentials,
+                    att_stmt,
                     ..
-                } = get_assertion_response;
+                } = make_credential_response;
+                // The expected response is split to only assert the non-random parts.
+                assert_eq!(fmt, "packed");
                 let mut expected_auth_data = vec![
                     0xA3, 0x79, 0xA6, 0xF6, 0xEE, 0xAF, 0xB9, 0xA5, 0x5E, 0x37, 0x8C, 0x11, 0x80,
                     0x34, 0xE2, 0x75, 0x1E, 0x68, 0x2F, 0xAB, 0x9F, 0x2D, 0x30, 0xAB, 0x13, 0xD2,
-                    0x12, 0x55, 0x86, 0xCE, 0x19, 0x47, flags, 0x00, 0x00, 0x00, 0x00,
+                    0x12, 0x55, 0x86, 0xCE, 0x19, 0x47, 0xC1, 0x00, 0x00, 0x00,
                 ];
-                let signature_counter_position = expected_auth_data.len() - 4;
-                BigEndian::write_u32(
-                    &mut expected_auth_data[signature_counter_position..],
-                    signature_counter,
+                expected_auth_data.push(INITIAL_SIGNATURE_COUNTER as u8);
+                expected_auth_data.extend(&ctap_state.persistent_store.aaguid().unwrap());
+                expected_auth_data.extend(&[0x00, 0x20]);
+                assert_eq!(
+                    auth_data[0..expected_auth_data.len()],
+                    expected_auth_data[..]
                 );
-                assert_eq!(auth_data, expected_auth_data);
-                assert_eq!(user, Some(expected_user));
-                assert_eq!(number_of_credentials, expected_number_of_credentials);
+                let expected_extension_cbor = vec![
+                    0xA1, 0x6B, 0x68, 0x6D, 0x61, 0x63, 0x2D, 0x73, 0x65, 0x63, 0x72, 0x65, 0x74,
+                    0xF5,
+                ];
+                assert_eq!(
+                    auth_data[auth_data.len() - expected_extension_cbor.len()..auth_data.len()],
+                    expected_extension_cbor[..]
+                );
+                assert_eq!(att_stmt.alg, SignatureAlgorithm::ES256 as i64);
             }
             _ => panic!("Invalid response type"),
         }
     }
 
-    fn check_assertion_response(
-        response: Result<ResponseData, Ctap2StatusCode>,
-        expected_user_id: Vec<u8>,
-        signature_counter: u32,
-        expected_number_of_credentials: Option<u64>,
-    ) {
-        let expected_user = PublicKeyCredentialUserEntity {
-            user_id: expected_user_id,
-            user_name: None,
-            user_display_name: None,
-            user_icon: None,
-        };
-        check_assertion_response_with_user(
-            response,
-            expected_user,
-            0x00,
-            signature_counter,
-            expected_number_of_credentials,
-        );
-    }
-
+    #[cfg(feature = "with_ctap2_1")]
     #[test]
-    fn test_residential_process_get_assertion() {
+    fn test_process_make_credential_large_blob_key() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
-        let make_credential_params = create_minimal_make_credential_parameters();
-        assert!(ctap_state
-            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
-            .is_ok());
-
-        let get_assertion_params = AuthenticatorGetAssertionParameters {
-            rp_id: String::from("example.com"),
-            client_data_hash: vec![0xCD],
-            allow_list: None,
-            extensions: None,
-            options: GetAssertionOptions {
-                up: false,
-                uv: false,
-            },
-            pin_uv_auth_param: None,
-            pin_uv_auth_protocol: None,
+        let extensions = Some(MakeCredentialExtensions {
+            hmac_secret: false,
+            cred_protect: None,
+            uvm: false,
+            cred_blob: None,
+            large_blob_key: Some(true),
+            min_pin_length: false,
+            hmac_secret_mc: None,
+        });
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.extensions = extensions;
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        let large_blob_key = match make_credential_response.unwrap() {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                make_credential_response.large_blob_key.unwrap()
+            }
+            _ => panic!("Invalid response type"),
         };
-        let get_assertion_response = ctap_state.process_get_assertion(
-            get_assertion_params,
-            DUMMY_CHANNEL_ID,
-            DUMMY_CLOCK_VALUE,
-        );
-        let signature_counter = ctap_state
+        assert_eq!(large_blob_key.len(), 32);
+
+        let stored_credential = ctap_state
             .persistent_store
-            .global_signature_counter()
+            .filter_credential("example.com", false)
+            .unwrap()
+            .pop()
             .unwrap();
-        check_assertion_response(get_assertion_response, vec![0x1D], signature_counter, None);
+        assert_eq!(stored_credential.large_blob_key, Some(large_blob_key));
+
+        // Non-resident credentials can't have a large blob key.
+        let extensions = Some(MakeCredentialExtensions {
+            hmac_secret: false,
+            cred_protect: None,
+            uvm: false,
+            cred_blob: None,
+            large_blob_key: Some(true),
+            min_pin_length: false,
+            hmac_secret_mc: None,
+        });
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.options.rk = false;
+        make_credential_params.extensions = extensions;
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        assert_eq!(
+            make_credential_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+        );
     }
 
+    #[cfg(feature = "with_ctap2_1")]
     #[test]
-    fn test_process_get_assertion_hmac_secret() {
+    fn test_process_make_credential_pin_protocol_two() {
         let mut rng = ThreadRng256 {};
-        let sk = crypto::ecdh::SecKey::gensk(&mut rng);
-        let user_immediately_present = |_| Ok(());
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x91; 32];
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0x88; 16])
+            .unwrap();
 
-        let make_extensions = Some(MakeCredentialExtensions {
-            hmac_secret: true,
-            cred_protect: None,
-        });
         let mut make_credential_params = create_minimal_make_credential_parameters();
-        make_credential_params.options.rk = false;
-        make_credential_params.extensions = make_extensions;
+        let pin_uv_auth_param =
+            hmac_256::<Sha256>(&pin_uv_auth_token, &make_credential_params.client_data_hash);
+        // PIN protocol two does not truncate the HMAC.
+        make_credential_params.pin_uv_auth_param = Some(pin_uv_auth_param[..16].to_vec());
+        make_credential_params.pin_uv_auth_protocol = Some(2);
         let make_credential_response =
             ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
-        assert!(make_credential_response.is_ok());
-        let credential_id = match make_credential_response.unwrap() {
+        assert_eq!(
+            make_credential_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.pin_uv_auth_param = Some(pin_uv_auth_param.to_vec());
+        make_credential_params.pin_uv_auth_protocol = Some(2);
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        match make_credential_response.unwrap() {
             ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
-                let auth_data = make_credential_response.auth_data;
-                let offset = 37 + ctap_state.persistent_store.aaguid().unwrap().len();
-                assert_eq!(auth_data[offset], 0x00);
-                assert_eq!(auth_data[offset + 1] as usize, CREDENTIAL_ID_SIZE);
-                auth_data[offset + 2..offset + 2 + CREDENTIAL_ID_SIZE].to_vec()
+                assert_eq!(make_credential_response.auth_data[32], 0x45);
             }
             _ => panic!("Invalid response type"),
         };
-
-        let pk = sk.genpk();
-        let hmac_secret_input = GetAssertionHmacSecretInput {
-            key_agreement: CoseKey::from(pk),
-            salt_enc: vec![0x02; 32],
-            salt_auth: vec![0x03; 16],
-        };
-        let get_extensions = Some(GetAssertionExtensions {
-            hmac_secret: Some(hmac_secret_input),
-        });
-
-        let cred_desc = PublicKeyCredentialDescriptor {
-            key_type: PublicKeyCredentialType::PublicKey,
-            key_id: credential_id,
-            transports: None,
-        };
-        let get_assertion_params = AuthenticatorGetAssertionParameters {
-            rp_id: String::from("example.com"),
-            client_data_hash: vec![0xCD],
-            allow_list: Some(vec![cred_desc]),
-            extensions: get_extensions,
-            options: GetAssertionOptions {
-                up: false,
-                uv: false,
-            },
-            pin_uv_auth_param: None,
-            pin_uv_auth_protocol: None,
-        };
-        let get_assertion_response = ctap_state.process_get_assertion(
-            get_assertion_params,
-            DUMMY_CHANNEL_ID,
-            DUMMY_CLOCK_VALUE,
-        );
-
-        assert_eq!(
-            get_assertion_response,
-            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION)
-        );
     }
 
+    #[cfg(feature = "with_ctap2_1")]
     #[test]
-    fn test_residential_process_get_assertion_hmac_secret() {
+    fn test_process_make_credential_clears_permissions() {
         let mut rng = ThreadRng256 {};
-        let sk = crypto::ecdh::SecKey::gensk(&mut rng);
-        let user_immediately_present = |_| Ok(());
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x91; 32];
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0x88; 16])
+            .unwrap();
 
-        let make_extensions = Some(MakeCredentialExtensions {
-            hmac_secret: true,
-            cred_protect: None,
-        });
+        let client_data_hash = create_minimal_make_credential_parameters().client_data_hash;
+        let pin_uv_auth_param =
+            hmac_256::<Sha256>(&pin_uv_auth_token, &client_data_hash)[..16].to_vec();
         let mut make_credential_params = create_minimal_make_credential_parameters();
-        make_credential_params.extensions = make_extensions;
+        make_credential_params.pin_uv_auth_param = Some(pin_uv_auth_param.clone());
+        make_credential_params.pin_uv_auth_protocol = Some(1);
         assert!(ctap_state
             .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
             .is_ok());
+        assert_eq!(
+            ctap_state
+                .pin_protocol_v1
+                .has_permission(PinPermission::LargeBlobWrite),
+            Ok(())
+        );
 
-        let pk = sk.genpk();
-        let hmac_secret_input = GetAssertionHmacSecretInput {
-            key_agreement: CoseKey::from(pk),
-            salt_enc: vec![0x02; 32],
-            salt_auth: vec![0x03; 16],
-        };
-        let get_extensions = Some(GetAssertionExtensions {
-            hmac_secret: Some(hmac_secret_input),
-        });
+        // The token lost its makeCredential permission.
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.pin_uv_auth_param = Some(pin_uv_auth_param);
+        make_credential_params.pin_uv_auth_protocol = Some(1);
+        assert_eq!(
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+    }
 
-        let get_assertion_params = AuthenticatorGetAssertionParameters {
-            rp_id: String::from("example.com"),
-            client_data_hash: vec![0xCD],
-            allow_list: None,
-            extensions: get_extensions,
-            options: GetAssertionOptions {
-                up: false,
-                uv: false,
-            },
-            pin_uv_auth_param: None,
-            pin_uv_auth_protocol: None,
+    #[test]
+    fn test_process_make_credential_expired_token() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x91; 32];
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0x88; 16])
+            .unwrap();
+        // The token was not used within its initial usage time limit.
+        ctap_state.update_timeouts(ClockValue::new(
+            31 * CLOCK_FREQUENCY_HZ as isize,
+            CLOCK_FREQUENCY_HZ,
+        ));
+
+        let client_data_hash = create_minimal_make_credential_parameters().client_data_hash;
+        let pin_uv_auth_param =
+            hmac_256::<Sha256>(&pin_uv_auth_token, &client_data_hash)[..16].to_vec();
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.pin_uv_auth_param = Some(pin_uv_auth_param);
+        make_credential_params.pin_uv_auth_protocol = Some(1);
+        assert_eq!(
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_make_credential_min_pin_length() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let extensions = MakeCredentialExtensions {
+            hmac_secret: false,
+            cred_protect: None,
+            uvm: false,
+            cred_blob: None,
+            large_blob_key: None,
+            min_pin_length: true,
+            hmac_secret_mc: None,
         };
-        let get_assertion_response = ctap_state.process_get_assertion(
-            get_assertion_params,
-            DUMMY_CHANNEL_ID,
-            DUMMY_CLOCK_VALUE,
+        // The RP is not on the list, so the extension is ignored.
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.extensions = Some(extensions.clone());
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        match make_credential_response.unwrap() {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                // The ED flag is not set.
+                assert_eq!(make_credential_response.auth_data[32], 0x41);
+            }
+            _ => panic!("Invalid response type"),
+        };
+
+        ctap_state
+            .persistent_store
+            .set_min_pin_length_rp_ids(vec![String::from("example.com")])
+            .unwrap();
+        ctap_state.persistent_store.set_min_pin_length(6).unwrap();
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.extensions = Some(extensions);
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        match make_credential_response.unwrap() {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                let auth_data = make_credential_response.auth_data;
+                assert_eq!(auth_data[32], 0xC1);
+                let expected_extension_cbor = [
+                    0xA1, 0x6C, 0x6D, 0x69, 0x6E, 0x50, 0x69, 0x6E, 0x4C, 0x65, 0x6E, 0x67, 0x74,
+                    0x68, 0x06,
+                ];
+                assert_eq!(
+                    auth_data[auth_data.len() - expected_extension_cbor.len()..],
+                    expected_extension_cbor[..]
+                );
+            }
+            _ => panic!("Invalid response type"),
+        };
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_make_credential_cred_blob() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let extensions = Some(MakeCredentialExtensions {
+            hmac_secret: false,
+            cred_protect: None,
+            uvm: false,
+            cred_blob: Some(vec![0xCB; MAX_CRED_BLOB_LENGTH]),
+            large_blob_key: None,
+            min_pin_length: false,
+            hmac_secret_mc: None,
+        });
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.extensions = extensions;
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        match make_credential_response.unwrap() {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                let auth_data = make_credential_response.auth_data;
+                // The credBlob extension output is true.
+                let expected_extension_cbor = [
+                    0xA1, 0x68, 0x63, 0x72, 0x65, 0x64, 0x42, 0x6C, 0x6F, 0x62, 0xF5,
+                ];
+                assert_eq!(
+                    auth_data[auth_data.len() - expected_extension_cbor.len()..],
+                    expected_extension_cbor[..]
+                );
+            }
+            _ => panic!("Invalid response type"),
+        };
+        let stored_credential = ctap_state
+            .persistent_store
+            .filter_credential("example.com", false)
+            .unwrap()
+            .pop()
+            .unwrap();
+        assert_eq!(
+            stored_credential.cred_blob,
+            Some(vec![0xCB; MAX_CRED_BLOB_LENGTH])
         );
 
+        // Blobs that are too long are not stored.
+        let extensions = Some(MakeCredentialExtensions {
+            hmac_secret: false,
+            cred_protect: None,
+            uvm: false,
+            cred_blob: Some(vec![0xCB; MAX_CRED_BLOB_LENGTH + 1]),
+            large_blob_key: None,
+            min_pin_length: false,
+            hmac_secret_mc: None,
+        });
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.user.user_id = vec![0x1D, 0x1D];
+        make_credential_params.extensions = extensions;
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        match make_credential_response.unwrap() {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                let auth_data = make_credential_response.auth_data;
+                // The credBlob extension output is false.
+                let expected_extension_cbor = [
+                    0xA1, 0x68, 0x63, 0x72, 0x65, 0x64, 0x42, 0x6C, 0x6F, 0x62, 0xF4,
+                ];
+                assert_eq!(
+                    auth_data[auth_data.len() - expected_extension_cbor.len()..],
+                    expected_extension_cbor[..]
+                );
+            }
+            _ => panic!("Invalid response type"),
+        };
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_selection() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
         assert_eq!(
-            get_assertion_response,
-            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION)
+            ctap_state.process_selection(DUMMY_CHANNEL_ID),
+            Ok(ResponseData::AuthenticatorSelection)
         );
     }
 
+    #[cfg(feature = "with_ctap2_1")]
     #[test]
-    fn test_residential_process_get_assertion_with_cred_protect() {
+    fn test_process_selection_user_presence_failure() {
         let mut rng = ThreadRng256 {};
-        let private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
-        let credential_id = rng.gen_uniform_u8x32().to_vec();
-        let user_immediately_present = |_| Ok(());
+        let user_presence_denied = |_, _| Err(Ctap2StatusCode::CTAP2_ERR_OPERATION_DENIED);
+        let ctap_state = CtapState::new(&mut rng, user_presence_denied, DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            ctap_state.process_selection(DUMMY_CHANNEL_ID),
+            Err(Ctap2StatusCode::CTAP2_ERR_OPERATION_DENIED)
+        );
+
+        let mut rng = ThreadRng256 {};
+        let user_presence_timeout = |_, _| Err(Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT);
+        let ctap_state = CtapState::new(&mut rng, user_presence_timeout, DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            ctap_state.process_selection(DUMMY_CHANNEL_ID),
+            Err(Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn test_process_make_credential_cancelled() {
+        let mut rng = ThreadRng256 {};
+        let user_presence_always_cancel = |_, _| Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
+        let mut ctap_state =
+            CtapState::new(&mut rng, user_presence_always_cancel, DUMMY_CLOCK_VALUE);
+
+        let make_credential_params = create_minimal_make_credential_parameters();
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+
+        assert_eq!(
+            make_credential_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_make_credential_always_uv() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
-        let cred_desc = PublicKeyCredentialDescriptor {
-            key_type: PublicKeyCredentialType::PublicKey,
-            key_id: credential_id.clone(),
-            transports: None, // You can set USB as a hint here.
-        };
-        let credential = PublicKeyCredentialSource {
-            key_type: PublicKeyCredentialType::PublicKey,
-            credential_id: credential_id.clone(),
-            private_key: private_key.clone(),
-            rp_id: String::from("example.com"),
-            user_handle: vec![0x1D],
-            user_display_name: None,
-            cred_protect_policy: Some(
-                CredentialProtectionPolicy::UserVerificationOptionalWithCredentialIdList,
-            ),
-            creation_order: 0,
+        ctap_state.persistent_store.toggle_always_uv().unwrap();
+        let make_credential_params = create_minimal_make_credential_parameters();
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        assert_eq!(
+            make_credential_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_make_credential_enterprise_attestation_disabled() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.enterprise_attestation =
+            Some(EnterpriseAttestationMode::VendorFacilitated);
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        assert_eq!(
+            make_credential_response,
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+    }
+
+    fn stored_signature_counter(persistent_store: &PersistentStore, user_handle: &[u8]) -> u32 {
+        persistent_store
+            .filter_credential("example.com", false)
+            .unwrap()
+            .into_iter()
+            .find(|credential| credential.user_handle == user_handle)
+            .unwrap()
+            .signature_counter
+            .unwrap()
+    }
+
+    fn check_assertion_response_with_user(
+        response: Result<ResponseData, Ctap2StatusCode>,
+        expected_user: PublicKeyCredentialUserEntity,
+        flags: u8,
+        signature_counter: u32,
+        expected_number_of_credentials: Option<u64>,
+    ) {
+        match response.unwrap() {
+            ResponseData::AuthenticatorGetAssertion(get_assertion_response) => {
+                let AuthenticatorGetAssertionResponse {
+                    auth_data,
+                    user,
+                    number_of_credentials,
+                    ..
+                } = get_assertion_response;
+                let mut expected_auth_data = vec![
+                    0xA3, 0x79, 0xA6, 0xF6, 0xEE, 0xAF, 0xB9, 0xA5, 0x5E, 0x37, 0x8C, 0x11, 0x80,
+                    0x34, 0xE2, 0x75, 0x1E, 0x68, 0x2F, 0xAB, 0x9F, 0x2D, 0x30, 0xAB, 0x13, 0xD2,
+                    0x12, 0x55, 0x86, 0xCE, 0x19, 0x47, flags, 0x00, 0x00, 0x00, 0x00,
+                ];
+                let signature_counter_position = expected_auth_data.len() - 4;
+                BigEndian::write_u32(
+                    &mut expected_auth_data[signature_counter_position..],
+                    signature_counter,
+                );
+                assert_eq!(auth_data, expected_auth_data);
+                assert_eq!(user, Some(expected_user));
+                assert_eq!(number_of_credentials, expected_number_of_credentials);
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    fn check_assertion_response(
+        response: Result<ResponseData, Ctap2StatusCode>,
+        expected_user_id: Vec<u8>,
+        signature_counter: u32,
+        expected_number_of_credentials: Option<u64>,
+    ) {
+        let expected_user = PublicKeyCredentialUserEntity {
+            user_id: expected_user_id,
             user_name: None,
+            user_display_name: None,
             user_icon: None,
         };
+        check_assertion_response_with_user(
+            response,
+            expected_user,
+            0x00,
+            signature_counter,
+            expected_number_of_credentials,
+        );
+    }
+
+    #[test]
+    fn test_residential_process_get_assertion() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let make_credential_params = create_minimal_make_credential_parameters();
         assert!(ctap_state
-            .persistent_store
-            .store_credential(credential)
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
             .is_ok());
 
         let get_assertion_params = AuthenticatorGetAssertionParameters {
@@ -1696,15 +3757,26 @@ mod test {
             DUMMY_CHANNEL_ID,
             DUMMY_CLOCK_VALUE,
         );
-        assert_eq!(
-            get_assertion_response,
-            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS),
-        );
+        let signature_counter = stored_signature_counter(&ctap_state.persistent_store, &[0x1D]);
+        check_assertion_response(get_assertion_response, vec![0x1D], signature_counter, None);
+    }
 
-        let get_assertion_params = AuthenticatorGetAssertionParameters {
+    #[test]
+    fn test_residential_process_get_assertion_eddsa() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.pub_key_cred_params = vec![EDDSA_CRED_PARAM];
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
             rp_id: String::from("example.com"),
             client_data_hash: vec![0xCD],
-            allow_list: Some(vec![cred_desc.clone()]),
+            allow_list: None,
             extensions: None,
             options: GetAssertionOptions {
                 up: false,
@@ -1718,33 +3790,52 @@ mod test {
             DUMMY_CHANNEL_ID,
             DUMMY_CLOCK_VALUE,
         );
-        let signature_counter = ctap_state
+        let credential = ctap_state
             .persistent_store
-            .global_signature_counter()
+            .filter_credential("example.com", false)
+            .unwrap()
+            .pop()
             .unwrap();
-        check_assertion_response(get_assertion_response, vec![0x1D], signature_counter, None);
-
-        let credential = PublicKeyCredentialSource {
-            key_type: PublicKeyCredentialType::PublicKey,
-            credential_id,
-            private_key,
-            rp_id: String::from("example.com"),
-            user_handle: vec![0x1D],
-            user_display_name: None,
-            cred_protect_policy: Some(CredentialProtectionPolicy::UserVerificationRequired),
-            creation_order: 0,
-            user_name: None,
-            user_icon: None,
+        let public_key = match credential.private_key {
+            PrivateKey::Ed25519(sk) => sk.genpk(),
+            _ => panic!("Invalid key type"),
         };
+        match get_assertion_response.unwrap() {
+            ResponseData::AuthenticatorGetAssertion(get_assertion_response) => {
+                let mut signature_data = get_assertion_response.auth_data;
+                signature_data.push(0xCD);
+                let signature = array_ref!(get_assertion_response.signature, 0, 64);
+                assert!(public_key.verify_vartime(
+                    &signature_data,
+                    &crypto::ed25519::Signature::from_bytes(signature)
+                ));
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    #[test]
+    fn test_residential_process_get_assertion_records_last_use() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let make_credential_params = create_minimal_make_credential_parameters();
         assert!(ctap_state
-            .persistent_store
-            .store_credential(credential)
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
             .is_ok());
+        let stored_credential = ctap_state
+            .persistent_store
+            .filter_credential("example.com", false)
+            .unwrap()
+            .pop()
+            .unwrap();
+        assert_eq!(stored_credential.last_used_order, None);
 
         let get_assertion_params = AuthenticatorGetAssertionParameters {
             rp_id: String::from("example.com"),
             client_data_hash: vec![0xCD],
-            allow_list: Some(vec![cred_desc]),
+            allow_list: None,
             extensions: None,
             options: GetAssertionOptions {
                 up: false,
@@ -1753,60 +3844,126 @@ mod test {
             pin_uv_auth_param: None,
             pin_uv_auth_protocol: None,
         };
-        let get_assertion_response = ctap_state.process_get_assertion(
-            get_assertion_params,
-            DUMMY_CHANNEL_ID,
-            DUMMY_CLOCK_VALUE,
-        );
+        assert!(ctap_state
+            .process_get_assertion(get_assertion_params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE)
+            .is_ok());
+        let used_credential = ctap_state
+            .persistent_store
+            .filter_credential("example.com", false)
+            .unwrap()
+            .pop()
+            .unwrap();
         assert_eq!(
-            get_assertion_response,
-            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS),
+            used_credential.creation_order,
+            stored_credential.creation_order
         );
+        assert!(used_credential.last_used_order.unwrap() > stored_credential.creation_order);
     }
 
     #[test]
-    fn test_process_get_next_assertion_two_credentials_with_uv() {
+    fn test_process_get_assertion_allow_list_stops_at_match() {
         let mut rng = ThreadRng256 {};
-        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
-        let pin_uv_auth_token = [0x88; 32];
-        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
-
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
-        ctap_state.pin_protocol_v1 = pin_protocol_v1;
 
-        let mut make_credential_params = create_minimal_make_credential_parameters();
-        let user1 = PublicKeyCredentialUserEntity {
-            user_id: vec![0x01],
-            user_name: Some("user1".to_string()),
-            user_display_name: Some("User One".to_string()),
-            user_icon: Some("icon1".to_string()),
-        };
-        make_credential_params.user = user1.clone();
+        let make_credential_params = create_minimal_make_credential_parameters();
         assert!(ctap_state
             .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
             .is_ok());
-        let mut make_credential_params = create_minimal_make_credential_parameters();
-        let user2 = PublicKeyCredentialUserEntity {
-            user_id: vec![0x02],
-            user_name: Some("user2".to_string()),
-            user_display_name: Some("User Two".to_string()),
-            user_icon: Some("icon2".to_string()),
+        let credential_id = ctap_state
+            .persistent_store
+            .filter_credential("example.com", false)
+            .unwrap()
+            .pop()
+            .unwrap()
+            .credential_id;
+        let matching_entry = cbor_map! {
+            "type" => "public-key",
+            "id" => credential_id,
         };
-        make_credential_params.user = user2.clone();
-        assert!(ctap_state
-            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
-            .is_ok());
+        let invalid_entry = cbor_map! {
+            "type" => "public-key",
+        };
+
+        let allow_lists = vec![
+            (
+                cbor_array![matching_entry.clone(), invalid_entry.clone()],
+                true,
+            ),
+            (cbor_array![invalid_entry, matching_entry], false),
+        ];
+        for (allow_list, is_ok) in allow_lists {
+            let get_assertion_params = AuthenticatorGetAssertionParameters {
+                rp_id: String::from("example.com"),
+                client_data_hash: vec![0xCD],
+                allow_list: Some(
+                    PublicKeyCredentialDescriptorList::from_cbor(allow_list, None).unwrap(),
+                ),
+                extensions: None,
+                options: GetAssertionOptions {
+                    up: false,
+                    uv: false,
+                },
+                pin_uv_auth_param: None,
+                pin_uv_auth_protocol: None,
+            };
+            let get_assertion_response = ctap_state.process_get_assertion(
+                get_assertion_params,
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE,
+            );
+            // Entries after the match are never decoded.
+            if is_ok {
+                assert!(get_assertion_response.is_ok());
+            } else {
+                assert_eq!(
+                    get_assertion_response,
+                    Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_process_get_assertion_silent() {
+        let mut rng = ThreadRng256 {};
+        let user_presence_always_cancel = |_, _| Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
+        let discoverable_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
+        let protected_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
+        let mut ctap_state =
+            CtapState::new(&mut rng, user_presence_always_cancel, DUMMY_CLOCK_VALUE);
 
+        let mut credential_source = PublicKeyCredentialSource {
+            key_type: PublicKeyCredentialType::PublicKey,
+            credential_id: vec![0x01],
+            private_key: discoverable_key,
+            rp_id: String::from("example.com"),
+            user_handle: vec![0x01],
+            user_display_name: None,
+            cred_protect_policy: None,
+            creation_order: 0,
+            last_used_order: None,
+            signature_counter: None,
+            user_name: None,
+            user_icon: None,
+            large_blob_key: None,
+            cred_blob: None,
+        };
         ctap_state
             .persistent_store
-            .set_pin_hash(&[0u8; 16])
+            .store_credential(credential_source.clone())
+            .unwrap();
+        credential_source.credential_id = vec![0x02];
+        credential_source.private_key = protected_key;
+        credential_source.user_handle = vec![0x02];
+        credential_source.cred_protect_policy =
+            Some(CredentialProtectionPolicy::UserVerificationRequired);
+        ctap_state
+            .persistent_store
+            .store_credential(credential_source)
             .unwrap();
-        let pin_uv_auth_param = Some(vec![
-            0x6F, 0x52, 0x83, 0xBF, 0x1A, 0x91, 0xEE, 0x67, 0xE9, 0xD4, 0x4C, 0x80, 0x08, 0x79,
-            0x90, 0x8D,
-        ]);
 
+        // A silent assertion doesn't wait for a touch, and leaves the UP flag unset.
         let get_assertion_params = AuthenticatorGetAssertionParameters {
             rp_id: String::from("example.com"),
             client_data_hash: vec![0xCD],
@@ -1814,74 +3971,91 @@ mod test {
             extensions: None,
             options: GetAssertionOptions {
                 up: false,
-                uv: true,
+                uv: false,
             },
-            pin_uv_auth_param,
-            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
         };
         let get_assertion_response = ctap_state.process_get_assertion(
             get_assertion_params,
             DUMMY_CHANNEL_ID,
             DUMMY_CLOCK_VALUE,
         );
-        let signature_counter = ctap_state
-            .persistent_store
-            .global_signature_counter()
-            .unwrap();
-        check_assertion_response_with_user(
-            get_assertion_response,
-            user2,
-            0x04,
-            signature_counter,
-            Some(2),
-        );
+        match get_assertion_response.unwrap() {
+            ResponseData::AuthenticatorGetAssertion(get_assertion_response) => {
+                assert_eq!(get_assertion_response.auth_data[32] & UP_FLAG, 0x00);
+                assert_eq!(get_assertion_response.user.unwrap().user_id, vec![0x01]);
+                // The credential that requires user verification is not discovered.
+                assert_eq!(get_assertion_response.number_of_credentials, None);
+            }
+            _ => panic!("Invalid response type"),
+        }
 
-        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
-        check_assertion_response_with_user(
+        // Without user verification, listing the protected credential doesn't help either.
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: Some(PublicKeyCredentialDescriptorList::from(vec![
+                PublicKeyCredentialDescriptor {
+                    key_type: PublicKeyCredentialType::PublicKey,
+                    key_id: vec![0x02],
+                    transports: None,
+                },
+            ])),
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        assert_eq!(
             get_assertion_response,
-            user1,
-            0x04,
-            signature_counter,
-            None,
+            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)
         );
 
-        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
+        // Asking for user presence waits for the touch.
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: None,
+            options: GetAssertionOptions {
+                up: true,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
         assert_eq!(
             get_assertion_response,
-            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+            Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
         );
     }
 
+    #[cfg(feature = "with_ctap2_1")]
     #[test]
-    fn test_process_get_next_assertion_three_credentials_no_uv() {
+    fn test_residential_process_get_assertion_always_uv() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
-        let mut make_credential_params = create_minimal_make_credential_parameters();
-        make_credential_params.user.user_id = vec![0x01];
-        make_credential_params.user.user_name = Some("removed".to_string());
-        make_credential_params.user.user_display_name = Some("removed".to_string());
-        make_credential_params.user.user_icon = Some("removed".to_string());
-        assert!(ctap_state
-            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
-            .is_ok());
-        let mut make_credential_params = create_minimal_make_credential_parameters();
-        make_credential_params.user.user_id = vec![0x02];
-        make_credential_params.user.user_name = Some("removed".to_string());
-        make_credential_params.user.user_display_name = Some("removed".to_string());
-        make_credential_params.user.user_icon = Some("removed".to_string());
-        assert!(ctap_state
-            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
-            .is_ok());
-        let mut make_credential_params = create_minimal_make_credential_parameters();
-        make_credential_params.user.user_id = vec![0x03];
-        make_credential_params.user.user_name = Some("removed".to_string());
-        make_credential_params.user.user_display_name = Some("removed".to_string());
-        make_credential_params.user.user_icon = Some("removed".to_string());
+        let make_credential_params = create_minimal_make_credential_parameters();
         assert!(ctap_state
             .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
             .is_ok());
+        ctap_state.persistent_store.toggle_always_uv().unwrap();
 
         let get_assertion_params = AuthenticatorGetAssertionParameters {
             rp_id: String::from("example.com"),
@@ -1900,237 +4074,2013 @@ mod test {
             DUMMY_CHANNEL_ID,
             DUMMY_CLOCK_VALUE,
         );
-        let signature_counter = ctap_state
-            .persistent_store
-            .global_signature_counter()
-            .unwrap();
-        check_assertion_response(
-            get_assertion_response,
-            vec![0x03],
-            signature_counter,
-            Some(3),
+        assert_eq!(
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED)
+        );
+    }
+
+    #[test]
+    fn test_process_get_assertion_hmac_secret() {
+        let mut rng = ThreadRng256 {};
+        let sk = crypto::ecdh::SecKey::gensk(&mut rng);
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let make_extensions = Some(MakeCredentialExtensions {
+            hmac_secret: true,
+            cred_protect: None,
+            uvm: false,
+            #[cfg(feature = "with_ctap2_1")]
+            cred_blob: None,
+            #[cfg(feature = "with_ctap2_1")]
+            large_blob_key: None,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
+            #[cfg(feature = "with_ctap2_1")]
+            hmac_secret_mc: None,
+        });
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.options.rk = false;
+        make_credential_params.extensions = make_extensions;
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        assert!(make_credential_response.is_ok());
+        let credential_id = match make_credential_response.unwrap() {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                let auth_data = make_credential_response.auth_data;
+                let offset = 37 + ctap_state.persistent_store.aaguid().unwrap().len();
+                assert_eq!(auth_data[offset], 0x00);
+                assert_eq!(auth_data[offset + 1] as usize, CREDENTIAL_ID_SIZE);
+                auth_data[offset + 2..offset + 2 + CREDENTIAL_ID_SIZE].to_vec()
+            }
+            _ => panic!("Invalid response type"),
+        };
+
+        let pk = sk.genpk();
+        let hmac_secret_input = GetAssertionHmacSecretInput {
+            key_agreement: CoseKey::from(pk),
+            salt_enc: vec![0x02; 32],
+            salt_auth: vec![0x03; 16],
+            #[cfg(feature = "with_ctap2_1")]
+            pin_uv_auth_protocol: None,
+        };
+        let get_extensions = Some(GetAssertionExtensions {
+            hmac_secret: Some(hmac_secret_input),
+            uvm: false,
+            #[cfg(feature = "with_ctap2_1")]
+            cred_blob: false,
+            #[cfg(feature = "with_ctap2_1")]
+            large_blob_key: None,
+        });
+
+        let cred_desc = PublicKeyCredentialDescriptor {
+            key_type: PublicKeyCredentialType::PublicKey,
+            key_id: credential_id,
+            transports: None,
+        };
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: Some(vec![cred_desc].into()),
+            extensions: get_extensions,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+
+        assert_eq!(
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_OPTION)
+        );
+    }
+
+    #[test]
+    fn test_residential_process_get_assertion_hmac_secret() {
+        let mut rng = ThreadRng256 {};
+        let sk = crypto::ecdh::SecKey::gensk(&mut rng);
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let make_extensions = Some(MakeCredentialExtensions {
+            hmac_secret: true,
+            cred_protect: None,
+            uvm: false,
+            #[cfg(feature = "with_ctap2_1")]
+            cred_blob: None,
+            #[cfg(feature = "with_ctap2_1")]
+            large_blob_key: None,
+            #[cfg(feature = "with_ctap2_1")]
+            min_pin_length: false,
+            #[cfg(feature = "with_ctap2_1")]
+            hmac_secret_mc: None,
+        });
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.extensions = make_extensions;
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+
+        let pk = sk.genpk();
+        let hmac_secret_input = GetAssertionHmacSecretInput {
+            key_agreement: CoseKey::from(pk),
+            salt_enc: vec![0x02; 32],
+            salt_auth: vec![0x03; 16],
+            #[cfg(feature = "with_ctap2_1")]
+            pin_uv_auth_protocol: None,
+        };
+        let get_extensions = Some(GetAssertionExtensions {
+            hmac_secret: Some(hmac_secret_input),
+            uvm: false,
+            #[cfg(feature = "with_ctap2_1")]
+            cred_blob: false,
+            #[cfg(feature = "with_ctap2_1")]
+            large_blob_key: None,
+        });
+
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: get_extensions,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+
+        assert_eq!(
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_OPTION)
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_make_credential_hmac_secret_mc() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let platform_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let shared_secret = platform_key.exchange_x_sha256(&key_agreement_key.genpk());
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, [0x91; 32]);
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+
+        // PIN protocol one encrypts the salt with a zero IV.
+        let mut salt_blocks = [[0x5A; 16]; 2];
+        let aes_enc_key = crypto::aes256::EncryptionKey::new(&shared_secret);
+        cbc_encrypt(&aes_enc_key, [0u8; 16], &mut salt_blocks);
+        let salt_enc = salt_blocks.iter().flatten().cloned().collect::<Vec<u8>>();
+        let salt_auth = hmac_256::<Sha256>(&shared_secret, &salt_enc)[..16].to_vec();
+        let hmac_secret_input = GetAssertionHmacSecretInput {
+            key_agreement: CoseKey::from(platform_key.genpk()),
+            salt_enc,
+            salt_auth,
+            pin_uv_auth_protocol: None,
+        };
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.extensions = Some(MakeCredentialExtensions {
+            hmac_secret: true,
+            hmac_secret_mc: Some(hmac_secret_input.clone()),
+            ..Default::default()
+        });
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        let make_auth_data = match make_credential_response.unwrap() {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                make_credential_response.auth_data
+            }
+            _ => panic!("Invalid response type"),
+        };
+        assert_eq!(make_auth_data[32] & ED_FLAG, ED_FLAG);
+
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: Some(GetAssertionExtensions {
+                hmac_secret: Some(hmac_secret_input),
+                ..Default::default()
+            }),
+            options: GetAssertionOptions {
+                up: true,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        let hmac_secret_output = match get_assertion_response.unwrap() {
+            ResponseData::AuthenticatorGetAssertion(get_assertion_response) => {
+                let extensions_output = cbor::read(&get_assertion_response.auth_data[37..]);
+                extract_map(extensions_output.unwrap())
+                    .unwrap()
+                    .remove(&cbor::KeyType::TextString(String::from("hmac-secret")))
+                    .unwrap()
+            }
+            _ => panic!("Invalid response type"),
+        };
+
+        // The output at creation is the same as the one of a later assertion.
+        let expected_extensions = cbor_map! {
+            "hmac-secret" => true,
+            "hmac-secret-mc" => hmac_secret_output,
+        };
+        let mut expected_encoding = Vec::new();
+        assert!(cbor::write(expected_extensions, &mut expected_encoding));
+        assert!(make_auth_data.ends_with(&expected_encoding));
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_residential_process_get_assertion_large_blob_key() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let make_extensions = Some(MakeCredentialExtensions {
+            hmac_secret: false,
+            cred_protect: None,
+            uvm: false,
+            cred_blob: None,
+            large_blob_key: Some(true),
+            min_pin_length: false,
+            hmac_secret_mc: None,
+        });
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.extensions = make_extensions;
+        let large_blob_key = match ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .unwrap()
+        {
+            ResponseData::AuthenticatorMakeCredential(make_credential_response) => {
+                make_credential_response.large_blob_key
+            }
+            _ => panic!("Invalid response type"),
+        };
+        assert!(large_blob_key.is_some());
+
+        let get_extensions = Some(GetAssertionExtensions {
+            hmac_secret: None,
+            uvm: false,
+            cred_blob: false,
+            large_blob_key: Some(true),
+        });
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: get_extensions,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        match get_assertion_response.unwrap() {
+            ResponseData::AuthenticatorGetAssertion(get_assertion_response) => {
+                assert_eq!(get_assertion_response.large_blob_key, large_blob_key);
+            }
+            _ => panic!("Invalid response type"),
+        };
+
+        // The key is only returned on request.
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        match get_assertion_response.unwrap() {
+            ResponseData::AuthenticatorGetAssertion(get_assertion_response) => {
+                assert_eq!(get_assertion_response.large_blob_key, None);
+            }
+            _ => panic!("Invalid response type"),
+        };
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_residential_process_get_assertion_cred_blob() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let make_extensions = Some(MakeCredentialExtensions {
+            hmac_secret: false,
+            cred_protect: None,
+            uvm: false,
+            cred_blob: Some(vec![0xCB; 2]),
+            large_blob_key: None,
+            min_pin_length: false,
+            hmac_secret_mc: None,
+        });
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.extensions = make_extensions;
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+
+        let get_extensions = Some(GetAssertionExtensions {
+            hmac_secret: None,
+            uvm: false,
+            cred_blob: true,
+            large_blob_key: None,
+        });
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: get_extensions,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        match get_assertion_response.unwrap() {
+            ResponseData::AuthenticatorGetAssertion(get_assertion_response) => {
+                let auth_data = get_assertion_response.auth_data;
+                assert_eq!(auth_data[32] & ED_FLAG, ED_FLAG);
+                let expected_extension_cbor = [
+                    0xA1, 0x68, 0x63, 0x72, 0x65, 0x64, 0x42, 0x6C, 0x6F, 0x62, 0x42, 0xCB, 0xCB,
+                ];
+                assert_eq!(
+                    auth_data[auth_data.len() - expected_extension_cbor.len()..],
+                    expected_extension_cbor[..]
+                );
+            }
+            _ => panic!("Invalid response type"),
+        };
+    }
+
+    #[test]
+    fn test_residential_process_get_assertion_with_cred_protect() {
+        let mut rng = ThreadRng256 {};
+        let private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
+        let credential_id = rng.gen_uniform_u8x32().to_vec();
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let cred_desc = PublicKeyCredentialDescriptor {
+            key_type: PublicKeyCredentialType::PublicKey,
+            key_id: credential_id.clone(),
+            transports: None, // You can set USB as a hint here.
+        };
+        let credential = PublicKeyCredentialSource {
+            key_type: PublicKeyCredentialType::PublicKey,
+            credential_id: credential_id.clone(),
+            private_key: private_key.clone(),
+            rp_id: String::from("example.com"),
+            user_handle: vec![0x1D],
+            user_display_name: None,
+            cred_protect_policy: Some(
+                CredentialProtectionPolicy::UserVerificationOptionalWithCredentialIdList,
+            ),
+            creation_order: 0,
+            last_used_order: None,
+            signature_counter: None,
+            user_name: None,
+            user_icon: None,
+            large_blob_key: None,
+            cred_blob: None,
+        };
+        assert!(ctap_state
+            .persistent_store
+            .store_credential(credential)
+            .is_ok());
+
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        assert_eq!(
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS),
+        );
+
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: Some(vec![cred_desc.clone()].into()),
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        let signature_counter = stored_signature_counter(&ctap_state.persistent_store, &[0x1D]);
+        check_assertion_response(get_assertion_response, vec![0x1D], signature_counter, None);
+
+        let credential = PublicKeyCredentialSource {
+            key_type: PublicKeyCredentialType::PublicKey,
+            credential_id,
+            private_key,
+            rp_id: String::from("example.com"),
+            user_handle: vec![0x1D],
+            user_display_name: None,
+            cred_protect_policy: Some(CredentialProtectionPolicy::UserVerificationRequired),
+            creation_order: 0,
+            last_used_order: None,
+            signature_counter: None,
+            user_name: None,
+            user_icon: None,
+            large_blob_key: None,
+            cred_blob: None,
+        };
+        assert!(ctap_state
+            .persistent_store
+            .store_credential(credential)
+            .is_ok());
+
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: Some(vec![cred_desc].into()),
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        assert_eq!(
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS),
+        );
+    }
+
+    #[test]
+    fn test_non_residential_process_get_assertion_with_cred_protect() {
+        let mut rng = ThreadRng256 {};
+        let private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let rp_id_hash = Sha256::hash(b"example.com");
+        let credential_id = ctap_state
+            .encrypt_key_handle(
+                private_key,
+                &rp_id_hash,
+                Some(CredentialProtectionPolicy::UserVerificationRequired),
+            )
+            .unwrap();
+        let cred_desc = PublicKeyCredentialDescriptor {
+            key_type: PublicKeyCredentialType::PublicKey,
+            key_id: credential_id,
+            transports: None,
+        };
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: Some(vec![cred_desc].into()),
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        assert_eq!(
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS),
+        );
+    }
+
+    #[test]
+    fn test_process_get_next_assertion_two_credentials_with_uv() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x88; 32];
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        let user1 = PublicKeyCredentialUserEntity {
+            user_id: vec![0x01],
+            user_name: Some("user1".to_string()),
+            user_display_name: Some("User One".to_string()),
+            user_icon: Some("icon1".to_string()),
+        };
+        make_credential_params.user = user1.clone();
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        let user2 = PublicKeyCredentialUserEntity {
+            user_id: vec![0x02],
+            user_name: Some("user2".to_string()),
+            user_display_name: Some("User Two".to_string()),
+            user_icon: Some("icon2".to_string()),
+        };
+        make_credential_params.user = user2.clone();
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0u8; 16])
+            .unwrap();
+        let pin_uv_auth_param = Some(vec![
+            0x6F, 0x52, 0x83, 0xBF, 0x1A, 0x91, 0xEE, 0x67, 0xE9, 0xD4, 0x4C, 0x80, 0x08, 0x79,
+            0x90, 0x8D,
+        ]);
+
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: true,
+            },
+            pin_uv_auth_param,
+            pin_uv_auth_protocol: Some(1),
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        let signature_counter = stored_signature_counter(&ctap_state.persistent_store, &[0x02]);
+        check_assertion_response_with_user(
+            get_assertion_response,
+            user2,
+            0x04,
+            signature_counter,
+            Some(2),
+        );
+
+        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
+        let signature_counter = stored_signature_counter(&ctap_state.persistent_store, &[0x01]);
+        check_assertion_response_with_user(
+            get_assertion_response,
+            user1,
+            0x04,
+            signature_counter,
+            None,
+        );
+
+        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+    }
+
+    #[test]
+    fn test_process_get_next_assertion_three_credentials_no_uv() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.user.user_id = vec![0x01];
+        make_credential_params.user.user_name = Some("removed".to_string());
+        make_credential_params.user.user_display_name = Some("removed".to_string());
+        make_credential_params.user.user_icon = Some("removed".to_string());
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.user.user_id = vec![0x02];
+        make_credential_params.user.user_name = Some("removed".to_string());
+        make_credential_params.user.user_display_name = Some("removed".to_string());
+        make_credential_params.user.user_icon = Some("removed".to_string());
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.user.user_id = vec![0x03];
+        make_credential_params.user.user_name = Some("removed".to_string());
+        make_credential_params.user.user_display_name = Some("removed".to_string());
+        make_credential_params.user.user_icon = Some("removed".to_string());
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        let signature_counter = stored_signature_counter(&ctap_state.persistent_store, &[0x03]);
+        check_assertion_response(
+            get_assertion_response,
+            vec![0x03],
+            signature_counter,
+            Some(3),
+        );
+
+        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
+        let signature_counter = stored_signature_counter(&ctap_state.persistent_store, &[0x02]);
+        check_assertion_response(get_assertion_response, vec![0x02], signature_counter, None);
+
+        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
+        let signature_counter = stored_signature_counter(&ctap_state.persistent_store, &[0x01]);
+        check_assertion_response(get_assertion_response, vec![0x01], signature_counter, None);
+
+        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+    }
+
+    #[test]
+    fn test_process_get_next_assertion_not_allowed() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.user.user_id = vec![0x01];
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.user.user_id = vec![0x02];
+        assert!(ctap_state
+            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+            .is_ok());
+
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: None,
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response = ctap_state.process_get_assertion(
+            get_assertion_params,
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        assert!(get_assertion_response.is_ok());
+
+        // This is a MakeCredential command.
+        let mut command_cbor = vec![0x01];
+        let cbor_value = cbor_map! {
+            1 => vec![0xCD; 16],
+            2 => cbor_map! {
+                "id" => "example.com",
+            },
+            3 => cbor_map! {
+                "id" => vec![0x1D, 0x1D, 0x1D, 0x1D],
+            },
+            4 => cbor_array![ES256_CRED_PARAM],
+        };
+        assert!(cbor::write(cbor_value, &mut command_cbor));
+        ctap_state.process_command(&command_cbor, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+
+        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+    }
+
+    #[test]
+    fn test_process_command_shows_pin_blocked() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let pk = crypto::ecdh::SecKey::gensk(&mut rng).genpk();
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0x88; 16])
+            .unwrap();
+        while ctap_state.persistent_store.pin_retries().unwrap() > 0 {
+            ctap_state.persistent_store.decr_pin_retries().unwrap();
+        }
+
+        // This is a GetPinToken command, with PIN protocol 1.
+        let mut command_cbor = vec![0x06];
+        let cbor_value = cbor_map! {
+            1 => 1,
+            2 => 5,
+            3 => cbor::Value::Map(CoseKey::from(pk).0),
+            6 => vec![0x88; 16],
+        };
+        assert!(cbor::write(cbor_value, &mut command_cbor));
+        let response =
+            ctap_state.process_command(&command_cbor, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, vec![Ctap2StatusCode::CTAP2_ERR_PIN_BLOCKED as u8]);
+        assert_eq!(ctap_state.status_leds.is_lit(DUMMY_CLOCK_VALUE), Some(true));
+
+        // The next command clears the status.
+        ctap_state.process_command(&[0x04], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(ctap_state.status_leds.is_lit(DUMMY_CLOCK_VALUE), None);
+    }
+
+    #[test]
+    fn test_process_reset() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let credential_id = vec![0x01, 0x23, 0x45, 0x67];
+        let credential_source = PublicKeyCredentialSource {
+            key_type: PublicKeyCredentialType::PublicKey,
+            credential_id,
+            private_key,
+            rp_id: String::from("example.com"),
+            user_handle: vec![],
+            user_display_name: None,
+            cred_protect_policy: None,
+            creation_order: 0,
+            last_used_order: None,
+            signature_counter: None,
+            user_name: None,
+            user_icon: None,
+            large_blob_key: None,
+            cred_blob: None,
+        };
+        assert!(ctap_state
+            .persistent_store
+            .store_credential(credential_source)
+            .is_ok());
+        assert!(ctap_state.persistent_store.count_credentials().unwrap() > 0);
+
+        let reset_reponse =
+            ctap_state.process_command(&[0x07], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        let expected_response = vec![0x00];
+        assert_eq!(reset_reponse, expected_response);
+        assert!(ctap_state.persistent_store.count_credentials().unwrap() == 0);
+    }
+
+    #[test]
+    fn test_process_reset_cancelled() {
+        let mut rng = ThreadRng256 {};
+        let user_presence_always_cancel = |_, _| Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
+        let mut ctap_state =
+            CtapState::new(&mut rng, user_presence_always_cancel, DUMMY_CLOCK_VALUE);
+
+        let reset_reponse = ctap_state.process_reset(DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+
+        assert_eq!(
+            reset_reponse,
+            Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+        );
+    }
+
+    #[test]
+    fn test_process_reset_not_first() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // This is a GetNextAssertion command.
+        ctap_state.process_command(&[0x08], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+
+        let reset_reponse = ctap_state.process_reset(DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(reset_reponse, Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED));
+    }
+
+    #[test]
+    fn test_process_reset_after_window() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let reset_reponse = ctap_state.process_reset(
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE.wrapping_add(RESET_TIMEOUT_DURATION),
+        );
+        assert_eq!(reset_reponse, Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED));
+    }
+
+    #[cfg(feature = "with_nfc")]
+    #[test]
+    fn test_process_reset_other_transport() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // This is a GetInfo command, which keeps the reset permission.
+        ctap_state.process_command(&[0x04], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+
+        let reset_reponse = ctap_state.process_command(&[0x07], NFC_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            reset_reponse,
+            vec![Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED as u8]
+        );
+        let reset_reponse =
+            ctap_state.process_command(&[0x07], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(reset_reponse, vec![0x00]);
+    }
+
+    #[cfg(feature = "with_nfc")]
+    #[test]
+    fn test_process_client_pin_over_nfc() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // This is a GetPinRetries command, with PIN protocol 1.
+        let client_pin_response = ctap_state.process_command(
+            &[0x06, 0xA2, 0x01, 0x01, 0x02, 0x01],
+            NFC_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        assert_eq!(client_pin_response, vec![0x00, 0xA1, 0x03, 0x08]);
+    }
+
+    #[test]
+    fn test_is_pin_change_allowed() {
+        for sub_command in &[ClientPinSubCommand::SetPin, ClientPinSubCommand::ChangePin] {
+            assert!(is_pin_change_allowed(
+                sub_command,
+                AuthenticatorTransport::Nfc,
+                false
+            ));
+            assert!(is_pin_change_allowed(
+                sub_command,
+                AuthenticatorTransport::Usb,
+                true
+            ));
+            assert!(!is_pin_change_allowed(
+                sub_command,
+                AuthenticatorTransport::Nfc,
+                true
+            ));
+        }
+        assert!(is_pin_change_allowed(
+            &ClientPinSubCommand::GetPinToken,
+            AuthenticatorTransport::Nfc,
+            true
+        ));
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_credential_management_get_next_rp() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        for rp_id in &["a.example.com", "b.example.com"] {
+            let mut make_credential_params = create_minimal_make_credential_parameters();
+            make_credential_params.rp.rp_id = String::from(*rp_id);
+            assert!(ctap_state
+                .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+                .is_ok());
+        }
+
+        // This is a CredentialManagement command with enumerateRPsGetNextRP.
+        let get_next_rp = [0x0A, 0xA1, 0x01, 0x03];
+        let response =
+            ctap_state.process_command(&get_next_rp, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, vec![Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED as u8]);
+
+        // Simulates an enumerateRPsBegin that returned the first RP.
+        ctap_state.stateful_command_permission =
+            TimedPermission::granted(DUMMY_CLOCK_VALUE, STATEFUL_COMMAND_TIMEOUT_DURATION);
+        ctap_state.stateful_command_type =
+            Some(StatefulCommand::EnumerateRps(String::from("a.example.com")));
+        let response =
+            ctap_state.process_command(&get_next_rp, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response[0], 0x00);
+
+        // The enumeration state expires.
+        ctap_state.stateful_command_permission =
+            TimedPermission::granted(DUMMY_CLOCK_VALUE, STATEFUL_COMMAND_TIMEOUT_DURATION);
+        ctap_state.stateful_command_type =
+            Some(StatefulCommand::EnumerateRps(String::from("a.example.com")));
+        let response = ctap_state.process_command(
+            &get_next_rp,
+            DUMMY_CHANNEL_ID,
+            ClockValue::new(31 * CLOCK_FREQUENCY_HZ as isize, CLOCK_FREQUENCY_HZ),
+        );
+        assert_eq!(response, vec![Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED as u8]);
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_bio_enrollment_without_sensor() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // This is a BioEnrollment command with getModality.
+        let bio_enrollment_response = ctap_state.process_command(
+            &[0x09, 0xA1, 0x06, 0xF5],
+            DUMMY_CHANNEL_ID,
+            DUMMY_CLOCK_VALUE,
+        );
+        assert_eq!(
+            bio_enrollment_response,
+            vec![Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND as u8]
+        );
+
+        let client_pin_params = AuthenticatorClientPinParameters {
+            pin_protocol: 1,
+            sub_command: ClientPinSubCommand::GetPinUvAuthTokenUsingPinWithPermissions,
+            key_agreement: None,
+            pin_auth: None,
+            new_pin_enc: None,
+            pin_hash_enc: None,
+            min_pin_length: None,
+            min_pin_length_rp_ids: None,
+            permissions: Some(PinPermission::BioEnrollment as u8),
+            permissions_rp_id: None,
+        };
+        let client_pin_response =
+            ctap_state.process_client_pin(client_pin_params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            client_pin_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_UNAUTHORIZED_PERMISSION)
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_built_in_uv_without_sensor() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        for sub_command in &[
+            ClientPinSubCommand::GetPinUvAuthTokenUsingUvWithPermissions,
+            ClientPinSubCommand::GetUvRetries,
+        ] {
+            let client_pin_params = AuthenticatorClientPinParameters {
+                pin_protocol: 1,
+                sub_command: sub_command.clone(),
+                key_agreement: None,
+                pin_auth: None,
+                new_pin_enc: None,
+                pin_hash_enc: None,
+                min_pin_length: None,
+                min_pin_length_rp_ids: None,
+                permissions: Some(PinPermission::MakeCredential as u8),
+                permissions_rp_id: Some(String::from("example.com")),
+            };
+            let client_pin_response = ctap_state.process_client_pin(
+                client_pin_params,
+                DUMMY_CHANNEL_ID,
+                DUMMY_CLOCK_VALUE,
+            );
+            assert_eq!(
+                client_pin_response,
+                Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND)
+            );
+        }
+
+        // Without a sensor, requesting user verification still needs a PIN.
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.options.uv = true;
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        assert_eq!(
+            make_credential_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+        );
+    }
+
+    #[test]
+    fn test_process_unknown_command() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // This command does not exist.
+        let reset_reponse =
+            ctap_state.process_command(&[0xDF], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        let expected_response = vec![Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND as u8];
+        assert_eq!(reset_reponse, expected_response);
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_credential() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // Usually, the relying party ID or its hash is provided by the client.
+        // We are not testing the correctness of our SHA256 here, only if it is checked.
+        let rp_id_hash = [0x55; 32];
+        let encrypted_id = ctap_state
+            .encrypt_key_handle(private_key.clone(), &rp_id_hash, None)
+            .unwrap();
+        let decrypted_source = ctap_state
+            .decrypt_credential_source(encrypted_id, &rp_id_hash)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(private_key, decrypted_source.private_key);
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_eddsa_credential() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let private_key = PrivateKey::new(&mut rng, SignatureAlgorithm::EDDSA).unwrap();
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let rp_id_hash = [0x55; 32];
+        let encrypted_id = ctap_state
+            .encrypt_key_handle(private_key.clone(), &rp_id_hash, None)
+            .unwrap();
+        let decrypted_source = ctap_state
+            .decrypt_credential_source(encrypted_id, &rp_id_hash)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(private_key, decrypted_source.private_key);
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_es384_credential() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let private_key = PrivateKey::new(&mut rng, SignatureAlgorithm::ES384).unwrap();
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let rp_id_hash = [0x55; 32];
+        let encrypted_id = ctap_state
+            .encrypt_key_handle(private_key.clone(), &rp_id_hash, None)
+            .unwrap();
+        let decrypted_source = ctap_state
+            .decrypt_credential_source(encrypted_id, &rp_id_hash)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(private_key, decrypted_source.private_key);
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_bad_hmac() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // Same as above.
+        let rp_id_hash = [0x55; 32];
+        let encrypted_id = ctap_state
+            .encrypt_key_handle(private_key, &rp_id_hash, None)
+            .unwrap();
+        for i in 0..encrypted_id.len() {
+            let mut modified_id = encrypted_id.clone();
+            modified_id[i] ^= 0x01;
+            assert!(ctap_state
+                .decrypt_credential_source(modified_id, &rp_id_hash)
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_cred_protect() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let rp_id_hash = [0x55; 32];
+        let cred_protect_policy = Some(CredentialProtectionPolicy::UserVerificationRequired);
+        let encrypted_id = ctap_state
+            .encrypt_key_handle(private_key.clone(), &rp_id_hash, cred_protect_policy)
+            .unwrap();
+        assert_eq!(encrypted_id.len(), CREDENTIAL_ID_SIZE);
+        let decrypted_source = ctap_state
+            .decrypt_credential_source(encrypted_id, &rp_id_hash)
+            .unwrap()
+            .unwrap();
+        assert_eq!(private_key, decrypted_source.private_key);
+        assert_eq!(decrypted_source.cred_protect_policy, cred_protect_policy);
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_wrong_rp_id() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let encrypted_id = ctap_state
+            .encrypt_key_handle(private_key, &[0x55; 32], None)
+            .unwrap();
+        assert!(ctap_state
+            .decrypt_credential_source(encrypted_id, &[0xAA; 32])
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_decrypt_unknown_version() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
+        let ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // A credential ID of a future version, that this version can't unwrap.
+        let rp_id_hash = [0x55; 32];
+        let master_keys = ctap_state.persistent_store.master_keys().unwrap();
+        let encrypted_id =
+            encrypt_gcm_credential_id(&master_keys, &[0x03, 0x00, 0x00], &private_key, &rp_id_hash);
+        assert_eq!(encrypted_id.len(), CREDENTIAL_ID_SIZE);
+
+        assert!(ctap_state
+            .decrypt_credential_source(encrypted_id, &rp_id_hash)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_decrypt_v1_credential() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let master_keys = ctap_state.persistent_store.master_keys().unwrap();
+
+        let rp_id_hash = [0x55; 32];
+        let policy = CredentialProtectionPolicy::UserVerificationRequired;
+        let header = [V1_CREDENTIAL_ID_VERSION, policy as u8];
+        let encrypted_id =
+            encrypt_gcm_credential_id(&master_keys, &header, &private_key, &rp_id_hash);
+        assert_eq!(encrypted_id.len(), V1_CREDENTIAL_ID_SIZE);
+
+        // Rotations don't affect credential IDs without generation.
+        ctap_state
+            .persistent_store
+            .rotate_master_keys(ctap_state.rng)
+            .unwrap();
+        let decrypted_source = ctap_state
+            .decrypt_credential_source(encrypted_id, &rp_id_hash)
+            .unwrap()
+            .unwrap();
+        assert_eq!(private_key, decrypted_source.private_key);
+        assert_eq!(decrypted_source.cred_protect_policy, Some(policy));
+    }
+
+    #[test]
+    fn test_decrypt_after_master_key_rotation() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let rp_id_hash = [0x55; 32];
+        let old_id = ctap_state
+            .encrypt_key_handle(private_key.clone(), &rp_id_hash, None)
+            .unwrap();
+        assert_eq!(old_id[1], 0);
+        let response = ctap_state.process_vendor_rotate_master_keys(DUMMY_CHANNEL_ID);
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorVendorRotateMasterKeys(
+                AuthenticatorVendorRotateMasterKeysResponse { generation: 1 }
+            ))
+        );
+        let new_id = ctap_state
+            .encrypt_key_handle(private_key.clone(), &rp_id_hash, None)
+            .unwrap();
+        assert_eq!(new_id[1], 1);
+
+        for encrypted_id in vec![old_id.clone(), new_id.clone()] {
+            let decrypted_source = ctap_state
+                .decrypt_credential_source(encrypted_id, &rp_id_hash)
+                .unwrap()
+                .unwrap();
+            assert_eq!(private_key, decrypted_source.private_key);
+        }
+
+        // The generation is authenticated, so a credential ID can't claim another one.
+        let mut modified_id = old_id;
+        modified_id[1] = 1;
+        assert!(ctap_state
+            .decrypt_credential_source(modified_id, &rp_id_hash)
+            .unwrap()
+            .is_none());
+        // Unknown generations are rejected.
+        let mut modified_id = new_id;
+        modified_id[1] = 2;
+        assert!(ctap_state
+            .decrypt_credential_source(modified_id, &rp_id_hash)
+            .unwrap()
+            .is_none());
+    }
+
+    // Builds a GCM credential ID with the given header, that starts with its version.
+    fn encrypt_gcm_credential_id(
+        master_keys: &MasterKeys,
+        header: &[u8],
+        private_key: &PrivateKey,
+        rp_id_hash: &[u8; 32],
+    ) -> Vec<u8> {
+        let wrapping_key = credential_id_key(&master_keys.encryption, header[0]);
+        let aad = credential_id_aad(header, rp_id_hash);
+        let nonce = [0x11; NONCE_SIZE];
+        let mut encrypted_id = header.to_vec();
+        encrypted_id.extend(&nonce);
+        let mut payload = vec![ES256_KEY_HANDLE_ALGORITHM];
+        payload.extend(&private_key.to_bytes());
+        let tag = gcm_encrypt(&wrapping_key, &nonce, &aad, &mut payload);
+        encrypted_id.extend(payload);
+        encrypted_id.extend(&tag);
+        encrypted_id
+    }
+
+    // Builds a credential ID the way older versions did. The oldest lack the policy block.
+    fn encrypt_cbc_credential_id(
+        master_keys: &MasterKeys,
+        private_key: &PrivateKey,
+        rp_id_hash: &[u8; 32],
+        policy_block: Option<[u8; 16]>,
+    ) -> Vec<u8> {
+        let aes_enc_key = crypto::aes256::EncryptionKey::new(&master_keys.encryption);
+        let sk_bytes = private_key.to_bytes();
+        let iv = [0x11; 16];
+        let mut blocks = vec![[0u8; 16]; 4];
+        blocks[0].copy_from_slice(&sk_bytes[..16]);
+        blocks[1].copy_from_slice(&sk_bytes[16..]);
+        blocks[2].copy_from_slice(&rp_id_hash[..16]);
+        blocks[3].copy_from_slice(&rp_id_hash[16..]);
+        blocks.extend(policy_block);
+        cbc_encrypt(&aes_enc_key, iv, &mut blocks);
+        let mut encrypted_id = iv.to_vec();
+        for b in &blocks {
+            encrypted_id.extend(b);
+        }
+        let id_hmac = hmac_256::<Sha256>(&master_keys.hmac, &encrypted_id[..]);
+        encrypted_id.extend(&id_hmac);
+        encrypted_id
+    }
+
+    #[test]
+    fn test_decrypt_cbc_credential() {
+        let mut rng = ThreadRng256 {};
+        let private_key = PrivateKey::new(&mut rng, SignatureAlgorithm::EDDSA).unwrap();
+        let user_immediately_present = |_, _| Ok(());
+        let ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let master_keys = ctap_state.persistent_store.master_keys().unwrap();
+
+        let rp_id_hash = [0x55; 32];
+        let mut policy_block = [0x00; 16];
+        policy_block[0] = CredentialProtectionPolicy::UserVerificationRequired as u8;
+        policy_block[1] = EDDSA_KEY_HANDLE_ALGORITHM;
+        let encrypted_id =
+            encrypt_cbc_credential_id(&master_keys, &private_key, &rp_id_hash, Some(policy_block));
+        assert_eq!(encrypted_id.len(), CBC_CREDENTIAL_ID_SIZE);
+
+        let decrypted_source = ctap_state
+            .decrypt_credential_source(encrypted_id.clone(), &rp_id_hash)
+            .unwrap()
+            .unwrap();
+        assert_eq!(private_key, decrypted_source.private_key);
+        assert_eq!(
+            decrypted_source.cred_protect_policy,
+            Some(CredentialProtectionPolicy::UserVerificationRequired)
+        );
+        assert!(ctap_state
+            .decrypt_credential_source(encrypted_id, &[0xAA; 32])
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_decrypt_legacy_credential() {
+        let mut rng = ThreadRng256 {};
+        let private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
+        let user_immediately_present = |_, _| Ok(());
+        let ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let master_keys = ctap_state.persistent_store.master_keys().unwrap();
+
+        let rp_id_hash = [0x55; 32];
+        let encrypted_id = encrypt_cbc_credential_id(&master_keys, &private_key, &rp_id_hash, None);
+        assert_eq!(encrypted_id.len(), LEGACY_CREDENTIAL_ID_SIZE);
+
+        let decrypted_source = ctap_state
+            .decrypt_credential_source(encrypted_id, &rp_id_hash)
+            .unwrap()
+            .unwrap();
+        assert_eq!(private_key, decrypted_source.private_key);
+        assert_eq!(decrypted_source.cred_protect_policy, None);
+    }
+
+    #[test]
+    fn test_signature_counter() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let mut last_counter = ctap_state
+            .persistent_store
+            .global_signature_counter()
+            .unwrap();
+        assert!(last_counter > 0);
+        // Non-resident credentials use the global counter.
+        for _ in 0..100 {
+            let next_counter = ctap_state.increment_signature_counter(&[0x55; 32]).unwrap();
+            assert!(next_counter > last_counter);
+            assert_eq!(
+                ctap_state.persistent_store.global_signature_counter(),
+                Ok(next_counter)
+            );
+            last_counter = next_counter;
+        }
+    }
+
+    #[test]
+    fn test_signature_counter_per_credential() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        for user_id in &[0x01, 0x02] {
+            let mut make_credential_params = create_minimal_make_credential_parameters();
+            make_credential_params.user.user_id = vec![*user_id];
+            assert!(ctap_state
+                .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
+                .is_ok());
+        }
+        let global_counter = ctap_state
+            .persistent_store
+            .global_signature_counter()
+            .unwrap();
+        let credentials = ctap_state
+            .persistent_store
+            .filter_credential("example.com", false)
+            .unwrap();
+        let (first_id, second_id) = (
+            credentials[0].credential_id.clone(),
+            credentials[1].credential_id.clone(),
+        );
+
+        let mut last_counter = INITIAL_SIGNATURE_COUNTER;
+        for _ in 0..10 {
+            let next_counter = ctap_state.increment_signature_counter(&first_id).unwrap();
+            assert!(next_counter > last_counter);
+            last_counter = next_counter;
+        }
+        // The second credential's counter is independent of the first one.
+        let second_counter = ctap_state.increment_signature_counter(&second_id).unwrap();
+        assert!(second_counter > INITIAL_SIGNATURE_COUNTER);
+        assert!(second_counter <= INITIAL_SIGNATURE_COUNTER + 8);
+        assert_eq!(
+            ctap_state.persistent_store.global_signature_counter(),
+            Ok(global_counter)
+        );
+    }
+
+    #[cfg(feature = "with_nfc")]
+    #[test]
+    fn test_vendor_nfc_statistics() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        ctap_state.nfc_statistics.frames_received = 3;
+        ctap_state.nfc_statistics.crc_errors = 1;
+        let response = ctap_state.process_vendor_nfc_statistics();
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorVendorNfcStatistics(
+                AuthenticatorVendorNfcStatisticsResponse {
+                    frames_received: 3,
+                    frames_sent: 0,
+                    crc_errors: 1,
+                    wtx_requests: 0,
+                    aborts: 0,
+                }
+            ))
         );
+    }
 
-        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
-        check_assertion_response(get_assertion_response, vec![0x02], signature_counter, None);
+    #[cfg(feature = "with_nfc")]
+    #[test]
+    fn test_vendor_nfc_self_test() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
-        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
-        check_assertion_response(get_assertion_response, vec![0x01], signature_counter, None);
+        let response = ctap_state.process_vendor_nfc_self_test(NFC_CHANNEL_ID);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED));
+        assert!(!ctap_state.nfc_driver_taken);
 
-        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
+        // There is no NFC driver when testing.
+        let response = ctap_state.process_vendor_nfc_self_test(DUMMY_CHANNEL_ID);
         assert_eq!(
-            get_assertion_response,
-            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+            response,
+            Ok(ResponseData::AuthenticatorVendorNfcSelfTest(
+                AuthenticatorVendorNfcSelfTestResponse {
+                    passed: false,
+                    failed_step: Some(1),
+                    error_code: Some(-11),
+                }
+            ))
         );
+        assert!(ctap_state.nfc_driver_taken);
     }
 
     #[test]
-    fn test_process_get_next_assertion_not_allowed() {
+    fn test_vendor_benchmark() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
-        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
+        let response = ctap_state.process_command(&[0x48], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response[0], 0x00);
+        let response = cbor::read(&response[1..]).unwrap();
+        assert_eq!(extract_map(response).unwrap().len(), 7);
+        // The benchmark leaves the store as it found it.
+        assert!(ctap_state.persistent_store.master_keys().is_ok());
+    }
+
+    #[test]
+    fn test_vendor_benchmark_needs_user_presence() {
+        let mut rng = ThreadRng256 {};
+        let user_cancels = |_, _| Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
+        let mut ctap_state = CtapState::new(&mut rng, user_cancels, DUMMY_CLOCK_VALUE);
+
+        let response = ctap_state.process_vendor_benchmark(DUMMY_CHANNEL_ID);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL));
+    }
+
+    #[test]
+    fn test_vendor_compact() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // A fresh store has nothing to compact.
+        let response = ctap_state.process_command(&[0x49], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, vec![0x00, 0xA1, 0x01, 0x00]);
+        assert!(!ctap_state.compact_store_step());
+    }
+
+    #[test]
+    fn test_vendor_wear() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let response = ctap_state.process_vendor_wear().unwrap();
+        match response {
+            ResponseData::AuthenticatorVendorWear(wear_response) => {
+                assert_eq!(wear_response.used_erases, 0);
+                assert_eq!(
+                    wear_response.total_erases,
+                    wear_response.max_page_erases * wear_response.page_erases.len() as u64
+                );
+                assert!(wear_response.written_bytes > 0);
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    #[test]
+    fn test_vendor_diagnostics() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let response = ctap_state.process_vendor_diagnostics();
         assert_eq!(
-            get_assertion_response,
-            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+            response,
+            Ok(ResponseData::AuthenticatorVendorDiagnostics(
+                AuthenticatorVendorDiagnosticsResponse {
+                    quarantined_entries: 0,
+                }
+            ))
         );
+    }
 
-        let mut make_credential_params = create_minimal_make_credential_parameters();
-        make_credential_params.user.user_id = vec![0x01];
-        assert!(ctap_state
-            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
-            .is_ok());
-        let mut make_credential_params = create_minimal_make_credential_parameters();
-        make_credential_params.user.user_id = vec![0x02];
-        assert!(ctap_state
-            .process_make_credential(make_credential_params, DUMMY_CHANNEL_ID)
-            .is_ok());
+    const BACKUP_PASSPHRASE: &[u8] = b"correct horse battery staple";
 
-        let get_assertion_params = AuthenticatorGetAssertionParameters {
+    fn create_backup_credential(rng: &mut ThreadRng256, user_id: u8) -> PublicKeyCredentialSource {
+        PublicKeyCredentialSource {
+            key_type: PublicKeyCredentialType::PublicKey,
+            credential_id: rng.gen_uniform_u8x32().to_vec(),
+            private_key: crypto::ecdsa::SecKey::gensk(rng).into(),
             rp_id: String::from("example.com"),
-            client_data_hash: vec![0xCD],
-            allow_list: None,
-            extensions: None,
-            options: GetAssertionOptions {
-                up: false,
-                uv: false,
-            },
+            user_handle: vec![user_id],
+            user_display_name: None,
+            cred_protect_policy: None,
+            creation_order: user_id as u64,
+            last_used_order: None,
+            signature_counter: None,
+            user_name: None,
+            user_icon: None,
+            large_blob_key: None,
+            cred_blob: None,
+        }
+    }
+
+    #[test]
+    fn test_vendor_backup_restore() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let total_credentials = BACKUP_PAGE_LENGTH + 2;
+        for user_id in 0..total_credentials as u8 {
+            let credential = create_backup_credential(ctap_state.rng, user_id);
+            ctap_state
+                .persistent_store
+                .store_credential(credential)
+                .unwrap();
+        }
+
+        let params = AuthenticatorVendorBackupParameters {
+            passphrase: Some(BACKUP_PASSPHRASE.to_vec()),
+            pin_uv_auth_protocol: None,
             pin_uv_auth_param: None,
+        };
+        let response =
+            ctap_state.process_vendor_backup(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        let (header, mut records) = match response.unwrap() {
+            ResponseData::AuthenticatorVendorBackup(backup_response) => {
+                assert_eq!(
+                    backup_response.total_credentials,
+                    Some(total_credentials as u64)
+                );
+                assert_eq!(backup_response.records.len(), BACKUP_PAGE_LENGTH);
+                (backup_response.header.unwrap(), backup_response.records)
+            }
+            _ => panic!("Invalid response type"),
+        };
+        let params = AuthenticatorVendorBackupParameters {
+            passphrase: None,
             pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
         };
-        let get_assertion_response = ctap_state.process_get_assertion(
-            get_assertion_params,
-            DUMMY_CHANNEL_ID,
-            DUMMY_CLOCK_VALUE,
+        let response =
+            ctap_state.process_vendor_backup(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        match response.unwrap() {
+            ResponseData::AuthenticatorVendorBackup(backup_response) => {
+                assert_eq!(backup_response.header, None);
+                assert_eq!(backup_response.total_credentials, None);
+                assert_eq!(backup_response.records.len(), 2);
+                records.extend(backup_response.records);
+            }
+            _ => panic!("Invalid response type"),
+        }
+        // The last page ended the backup.
+        let params = AuthenticatorVendorBackupParameters {
+            passphrase: None,
+            pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
+        };
+        let response =
+            ctap_state.process_vendor_backup(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED));
+        assert_eq!(
+            ctap_state.persistent_store.audit_log().unwrap(),
+            vec![AuditRecord {
+                event: AuditEvent::CredentialBackup,
+                credential_count: total_credentials as u32,
+            }]
         );
-        assert!(get_assertion_response.is_ok());
 
-        // This is a MakeCredential command.
-        let mut command_cbor = vec![0x01];
-        let cbor_value = cbor_map! {
-            1 => vec![0xCD; 16],
-            2 => cbor_map! {
-                "id" => "example.com",
-            },
-            3 => cbor_map! {
-                "id" => vec![0x1D, 0x1D, 0x1D, 0x1D],
-            },
-            4 => cbor_array![ES256_CRED_PARAM],
+        let mut replacement_rng = ThreadRng256 {};
+        let mut replacement_state = CtapState::new(
+            &mut replacement_rng,
+            user_immediately_present,
+            DUMMY_CLOCK_VALUE,
+        );
+        let params = AuthenticatorVendorRestoreParameters {
+            passphrase: Some(BACKUP_PASSPHRASE.to_vec()),
+            header: Some(header),
+            records: records[..BACKUP_PAGE_LENGTH].to_vec(),
+            pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
         };
-        assert!(cbor::write(cbor_value, &mut command_cbor));
-        ctap_state.process_command(&command_cbor, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        let response =
+            replacement_state.process_vendor_restore(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorVendorRestore(
+                AuthenticatorVendorRestoreResponse {
+                    restored_credentials: BACKUP_PAGE_LENGTH as u64,
+                }
+            ))
+        );
+        let params = AuthenticatorVendorRestoreParameters {
+            passphrase: None,
+            header: None,
+            records: records[BACKUP_PAGE_LENGTH..].to_vec(),
+            pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
+        };
+        let response =
+            replacement_state.process_vendor_restore(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorVendorRestore(
+                AuthenticatorVendorRestoreResponse {
+                    restored_credentials: 2,
+                }
+            ))
+        );
+        assert_eq!(
+            replacement_state.persistent_store.audit_log().unwrap(),
+            vec![AuditRecord {
+                event: AuditEvent::CredentialRestore,
+                credential_count: total_credentials as u32,
+            }]
+        );
 
-        let get_assertion_response = ctap_state.process_get_next_assertion(DUMMY_CLOCK_VALUE);
+        let mut iter_result = Ok(());
+        let iter = ctap_state
+            .persistent_store
+            .iter_credentials(&mut iter_result)
+            .unwrap();
+        for (_, credential) in iter {
+            assert_eq!(
+                replacement_state.persistent_store.find_credential(
+                    "example.com",
+                    &credential.credential_id,
+                    false
+                ),
+                Ok(Some(credential))
+            );
+        }
+        iter_result.unwrap();
         assert_eq!(
-            get_assertion_response,
-            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+            replacement_state
+                .persistent_store
+                .count_credentials()
+                .unwrap(),
+            total_credentials
         );
     }
 
     #[test]
-    fn test_process_reset() {
+    fn test_vendor_backup_skips_uv_required_credentials() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
-        let private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
-
-        let credential_id = vec![0x01, 0x23, 0x45, 0x67];
-        let credential_source = PublicKeyCredentialSource {
-            key_type: PublicKeyCredentialType::PublicKey,
-            credential_id,
-            private_key,
-            rp_id: String::from("example.com"),
-            user_handle: vec![],
-            user_display_name: None,
-            cred_protect_policy: None,
-            creation_order: 0,
-            user_name: None,
-            user_icon: None,
-        };
-        assert!(ctap_state
+        let credential = create_backup_credential(ctap_state.rng, 0);
+        ctap_state
             .persistent_store
-            .store_credential(credential_source)
-            .is_ok());
-        assert!(ctap_state.persistent_store.count_credentials().unwrap() > 0);
+            .store_credential(credential)
+            .unwrap();
+        let mut credential = create_backup_credential(ctap_state.rng, 1);
+        credential.cred_protect_policy = Some(CredentialProtectionPolicy::UserVerificationRequired);
+        ctap_state
+            .persistent_store
+            .store_credential(credential)
+            .unwrap();
 
-        let reset_reponse =
-            ctap_state.process_command(&[0x07], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
-        let expected_response = vec![0x00];
-        assert_eq!(reset_reponse, expected_response);
-        assert!(ctap_state.persistent_store.count_credentials().unwrap() == 0);
+        // Without PIN, the user is not verified.
+        let params = AuthenticatorVendorBackupParameters {
+            passphrase: Some(BACKUP_PASSPHRASE.to_vec()),
+            pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
+        };
+        let response =
+            ctap_state.process_vendor_backup(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        match response.unwrap() {
+            ResponseData::AuthenticatorVendorBackup(backup_response) => {
+                assert_eq!(backup_response.total_credentials, Some(1));
+                assert_eq!(backup_response.records.len(), 1);
+            }
+            _ => panic!("Invalid response type"),
+        }
     }
 
     #[test]
-    fn test_process_reset_cancelled() {
+    fn test_vendor_backup_restore_with_pin() {
         let mut rng = ThreadRng256 {};
-        let user_presence_always_cancel = |_| Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
-        let mut ctap_state =
-            CtapState::new(&mut rng, user_presence_always_cancel, DUMMY_CLOCK_VALUE);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x91; 32];
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
 
-        let reset_reponse = ctap_state.process_reset(DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0x88; 16])
+            .unwrap();
+        let mut credential = create_backup_credential(ctap_state.rng, 0);
+        credential.cred_protect_policy = Some(CredentialProtectionPolicy::UserVerificationRequired);
+        ctap_state
+            .persistent_store
+            .store_credential(credential)
+            .unwrap();
+
+        let params = AuthenticatorVendorBackupParameters {
+            passphrase: Some(BACKUP_PASSPHRASE.to_vec()),
+            pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
+        };
+        let response =
+            ctap_state.process_vendor_backup(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED));
+
+        let mut message = vec![0xFF; 32];
+        message.push(0x4B);
+        message.extend(BACKUP_PASSPHRASE);
+        let params = AuthenticatorVendorBackupParameters {
+            passphrase: Some(BACKUP_PASSPHRASE.to_vec()),
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: Some(vec![0x55; 16]),
+        };
+        let response =
+            ctap_state.process_vendor_backup(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID));
+
+        // A verified user also exports credentials that require user verification.
+        let params = AuthenticatorVendorBackupParameters {
+            passphrase: Some(BACKUP_PASSPHRASE.to_vec()),
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: Some(
+                hmac_256::<Sha256>(&pin_uv_auth_token, &message)[..16].to_vec(),
+            ),
+        };
+        let response =
+            ctap_state.process_vendor_backup(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        let (header, records) = match response.unwrap() {
+            ResponseData::AuthenticatorVendorBackup(backup_response) => {
+                assert_eq!(backup_response.total_credentials, Some(1));
+                (backup_response.header.unwrap(), backup_response.records)
+            }
+            _ => panic!("Invalid response type"),
+        };
 
+        // Restoring into a device with a PIN needs the PIN as well.
+        ctap_state.persistent_store.reset(ctap_state.rng).unwrap();
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0x88; 16])
+            .unwrap();
+        let params = AuthenticatorVendorRestoreParameters {
+            passphrase: Some(BACKUP_PASSPHRASE.to_vec()),
+            header: Some(header.clone()),
+            records: records.clone(),
+            pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
+        };
+        let response =
+            ctap_state.process_vendor_restore(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED));
+        assert_eq!(ctap_state.persistent_store.count_credentials().unwrap(), 0);
+
+        let mut message = vec![0xFF; 32];
+        message.push(0x4C);
+        message.extend(BACKUP_PASSPHRASE);
+        message.extend(&header);
+        let params = AuthenticatorVendorRestoreParameters {
+            passphrase: Some(BACKUP_PASSPHRASE.to_vec()),
+            header: Some(header),
+            records,
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: Some(
+                hmac_256::<Sha256>(&pin_uv_auth_token, &message)[..16].to_vec(),
+            ),
+        };
+        let response =
+            ctap_state.process_vendor_restore(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
         assert_eq!(
-            reset_reponse,
-            Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+            response,
+            Ok(ResponseData::AuthenticatorVendorRestore(
+                AuthenticatorVendorRestoreResponse {
+                    restored_credentials: 1,
+                }
+            ))
         );
     }
 
     #[test]
-    fn test_process_reset_not_first() {
+    fn test_vendor_backup_cancelled() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
-        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
-
-        // This is a GetNextAssertion command.
-        ctap_state.process_command(&[0x08], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        let user_presence_always_cancel = |_, _| Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
+        let mut ctap_state =
+            CtapState::new(&mut rng, user_presence_always_cancel, DUMMY_CLOCK_VALUE);
 
-        let reset_reponse = ctap_state.process_reset(DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
-        assert_eq!(reset_reponse, Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED));
+        let params = AuthenticatorVendorBackupParameters {
+            passphrase: Some(BACKUP_PASSPHRASE.to_vec()),
+            pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
+        };
+        let response =
+            ctap_state.process_vendor_backup(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL));
+        assert_eq!(ctap_state.persistent_store.audit_log().unwrap(), vec![]);
+
+        // Continuing needs a backup that was started.
+        let params = AuthenticatorVendorBackupParameters {
+            passphrase: None,
+            pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
+        };
+        let response =
+            ctap_state.process_vendor_backup(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED));
     }
 
     #[test]
-    fn test_process_unknown_command() {
+    fn test_vendor_restore_wrong_passphrase() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
-
-        // This command does not exist.
-        let reset_reponse =
-            ctap_state.process_command(&[0xDF], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
-        let expected_response = vec![Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND as u8];
-        assert_eq!(reset_reponse, expected_response);
+        let backup_key = BackupKey::new(ctap_state.rng, BACKUP_PASSPHRASE, || Ok(())).unwrap();
+        let credential = create_backup_credential(ctap_state.rng, 0);
+        let record = backup_key.encrypt(ctap_state.rng, credential).unwrap();
+
+        let params = AuthenticatorVendorRestoreParameters {
+            passphrase: Some(b"wrong passphrase".to_vec()),
+            header: Some(backup_key.header().to_vec()),
+            records: vec![record],
+            pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
+        };
+        let response =
+            ctap_state.process_vendor_restore(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE));
+        assert_eq!(ctap_state.persistent_store.count_credentials().unwrap(), 0);
+
+        let params = AuthenticatorVendorRestoreParameters {
+            passphrase: None,
+            header: None,
+            records: vec![],
+            pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
+        };
+        let response =
+            ctap_state.process_vendor_restore(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED));
     }
 
     #[test]
-    fn test_encrypt_decrypt_credential() {
+    fn test_vendor_audit_log() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
-        let private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
-        // Usually, the relying party ID or its hash is provided by the client.
-        // We are not testing the correctness of our SHA256 here, only if it is checked.
-        let rp_id_hash = [0x55; 32];
-        let encrypted_id = ctap_state
-            .encrypt_key_handle(private_key.clone(), &rp_id_hash)
-            .unwrap();
-        let decrypted_source = ctap_state
-            .decrypt_credential_source(encrypted_id, &rp_id_hash)
-            .unwrap()
-            .unwrap();
+        let response = ctap_state.process_command(&[0x4D], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, vec![0x00, 0xA1, 0x01, 0x80]);
 
-        assert_eq!(private_key, decrypted_source.private_key);
+        let params = AuthenticatorVendorBackupParameters {
+            passphrase: Some(BACKUP_PASSPHRASE.to_vec()),
+            pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
+        };
+        let response =
+            ctap_state.process_vendor_backup(params, DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert!(response.is_ok());
+        let response = ctap_state.process_vendor_audit_log();
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorVendorAuditLog(
+                AuthenticatorVendorAuditLogResponse {
+                    records: vec![AuditRecord {
+                        event: AuditEvent::CredentialBackup,
+                        credential_count: 0,
+                    }],
+                }
+            ))
+        );
     }
 
     #[test]
-    fn test_encrypt_decrypt_bad_hmac() {
+    fn test_vendor_self_test() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
-        let private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
-        // Same as above.
-        let rp_id_hash = [0x55; 32];
-        let encrypted_id = ctap_state
-            .encrypt_key_handle(private_key, &rp_id_hash)
-            .unwrap();
-        for i in 0..encrypted_id.len() {
-            let mut modified_id = encrypted_id.clone();
-            modified_id[i] ^= 0x01;
-            assert!(ctap_state
-                .decrypt_credential_source(modified_id, &rp_id_hash)
-                .unwrap()
-                .is_none());
-        }
-    }
-
-    #[test]
-    fn test_signature_counter() {
-        let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
-        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let response = ctap_state.process_vendor_self_test();
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorVendorSelfTest(
+                AuthenticatorVendorSelfTestResponse {
+                    passed: true,
+                    failed_test: None,
+                }
+            ))
+        );
+        assert_eq!(ctap_state.self_test_failure(), None);
 
-        let mut last_counter = ctap_state
-            .persistent_store
-            .global_signature_counter()
-            .unwrap();
-        assert!(last_counter > 0);
-        for _ in 0..100 {
-            assert!(ctap_state.increment_global_signature_counter().is_ok());
-            let next_counter = ctap_state
-                .persistent_store
-                .global_signature_counter()
-                .unwrap();
-            assert!(next_counter > last_counter);
-            last_counter = next_counter;
-        }
+        // After a failure, only the self-test is processed, until it passes.
+        ctap_state.self_test_failure = Some(SelfTestFailure::RngHealth);
+        let info_response =
+            ctap_state.process_command(&[0x04], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(
+            info_response,
+            vec![Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE as u8]
+        );
+        let self_test_response =
+            ctap_state.process_command(&[0x47], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(self_test_response[0], 0x00);
+        assert_eq!(ctap_state.self_test_failure(), None);
+        let info_response =
+            ctap_state.process_command(&[0x04], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(info_response[0], 0x00);
     }
 
     #[test]
     fn test_vendor_configure() {
         let mut rng = ThreadRng256 {};
-        let user_immediately_present = |_| Ok(());
+        let user_immediately_present = |_, _| Ok(());
         let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
 
         // Nothing should be configured at the beginning
@@ -2138,6 +6088,11 @@ mod test {
             AuthenticatorVendorConfigureParameters {
                 lockdown: false,
                 attestation_material: None,
+                default_cred_protect: None,
+                touch_timeout_ms: None,
+                touch_duration_ms: None,
+                confirm_with_second_touch: None,
+                force_pin_change: false,
             },
             DUMMY_CHANNEL_ID,
         );
@@ -2161,6 +6116,11 @@ mod test {
                     certificate: dummy_cert.to_vec(),
                     private_key: dummy_key,
                 }),
+                default_cred_protect: None,
+                touch_timeout_ms: None,
+                touch_duration_ms: None,
+                confirm_with_second_touch: None,
+                force_pin_change: false,
             },
             DUMMY_CHANNEL_ID,
         );
@@ -2199,6 +6159,11 @@ mod test {
                     certificate: dummy_cert.to_vec(),
                     private_key: other_dummy_key,
                 }),
+                default_cred_protect: None,
+                touch_timeout_ms: None,
+                touch_duration_ms: None,
+                confirm_with_second_touch: None,
+                force_pin_change: false,
             },
             DUMMY_CHANNEL_ID,
         );
@@ -2233,6 +6198,11 @@ mod test {
             AuthenticatorVendorConfigureParameters {
                 lockdown: true,
                 attestation_material: None,
+                default_cred_protect: None,
+                touch_timeout_ms: None,
+                touch_duration_ms: None,
+                confirm_with_second_touch: None,
+                force_pin_change: false,
             },
             DUMMY_CHANNEL_ID,
         );
@@ -2246,4 +6216,405 @@ mod test {
             ))
         );
     }
+
+    #[test]
+    fn test_vendor_configure_default_cred_protect() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let test_policy = CredentialProtectionPolicy::UserVerificationRequired;
+        let response = ctap_state.process_vendor_configure(
+            AuthenticatorVendorConfigureParameters {
+                lockdown: false,
+                attestation_material: None,
+                default_cred_protect: Some(test_policy),
+                touch_timeout_ms: None,
+                touch_duration_ms: None,
+                confirm_with_second_touch: None,
+                force_pin_change: false,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert!(response.is_ok());
+        assert_eq!(
+            ctap_state.persistent_store.default_cred_protect(),
+            Ok(Some(test_policy))
+        );
+
+        let make_credential_params = create_minimal_make_credential_parameters();
+        let make_credential_response =
+            ctap_state.process_make_credential(make_credential_params, DUMMY_CHANNEL_ID);
+        assert!(make_credential_response.is_ok());
+        let stored_credential = ctap_state
+            .persistent_store
+            .filter_credential("example.com", false)
+            .unwrap()
+            .pop()
+            .unwrap();
+        assert_eq!(stored_credential.cred_protect_policy, Some(test_policy));
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_vendor_configure_force_pin_change() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let force_pin_change_params = || AuthenticatorVendorConfigureParameters {
+            lockdown: false,
+            attestation_material: None,
+            default_cred_protect: None,
+            touch_timeout_ms: None,
+            touch_duration_ms: None,
+            confirm_with_second_touch: None,
+            force_pin_change: true,
+        };
+
+        // Without a PIN, there is nothing to change.
+        let response =
+            ctap_state.process_vendor_configure(force_pin_change_params(), DUMMY_CHANNEL_ID);
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_NOT_SET));
+        assert_eq!(
+            ctap_state.persistent_store.has_force_pin_change(),
+            Ok(false)
+        );
+
+        ctap_state
+            .persistent_store
+            .set_pin_hash(&[0x88; 16])
+            .unwrap();
+        let response =
+            ctap_state.process_vendor_configure(force_pin_change_params(), DUMMY_CHANNEL_ID);
+        assert!(response.is_ok());
+        assert_eq!(ctap_state.persistent_store.has_force_pin_change(), Ok(true));
+        let info_reponse = ctap_state.process_get_info(DUMMY_CHANNEL_ID).unwrap();
+        match info_reponse {
+            ResponseData::AuthenticatorGetInfo(get_info_response) => {
+                assert_eq!(get_info_response.force_pin_change, Some(true));
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    #[test]
+    fn test_vendor_configure_user_presence() {
+        let mut rng = ThreadRng256 {};
+        let up_configs = core::cell::RefCell::new(Vec::new());
+        let user_immediately_present = |_, config: UserPresenceConfig| {
+            up_configs.borrow_mut().push(config);
+            Ok(())
+        };
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // The touch must be shorter than the timeout.
+        let response = ctap_state.process_vendor_configure(
+            AuthenticatorVendorConfigureParameters {
+                lockdown: false,
+                attestation_material: None,
+                default_cred_protect: None,
+                touch_timeout_ms: None,
+                touch_duration_ms: Some(TOUCH_TIMEOUT_MS as u32),
+                confirm_with_second_touch: None,
+                force_pin_change: false,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER));
+        assert_eq!(ctap_state.persistent_store.touch_duration_ms(), Ok(None));
+
+        let response = ctap_state.process_vendor_configure(
+            AuthenticatorVendorConfigureParameters {
+                lockdown: false,
+                attestation_material: None,
+                default_cred_protect: None,
+                touch_timeout_ms: Some(10000),
+                touch_duration_ms: Some(500),
+                confirm_with_second_touch: Some(true),
+                force_pin_change: false,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert!(response.is_ok());
+        let default_config = UserPresenceConfig {
+            timeout: Duration::from_ms(TOUCH_TIMEOUT_MS),
+            touch_duration: Duration::from_ms(TOUCH_DURATION_MS),
+            selection: None,
+        };
+        assert_eq!(*up_configs.borrow(), vec![default_config; 2]);
+
+        // The reset needs two touches with the new settings.
+        up_configs.borrow_mut().clear();
+        let reset_reponse = ctap_state.process_reset(DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(reset_reponse, Ok(ResponseData::AuthenticatorReset));
+        let vendor_config = UserPresenceConfig {
+            timeout: Duration::from_ms(10000),
+            touch_duration: Duration::from_ms(500),
+            selection: None,
+        };
+        assert_eq!(*up_configs.borrow(), vec![vendor_config; 2]);
+    }
+
+    #[test]
+    fn test_select_credential_with_touches() {
+        let mut rng = ThreadRng256 {};
+        let selections = core::cell::RefCell::new(Vec::new());
+        // The user touches twice, then waits.
+        let check_user_presence = |_, config: UserPresenceConfig| {
+            selections.borrow_mut().push(config.selection);
+            if selections.borrow().len() <= 2 {
+                Ok(())
+            } else {
+                Err(Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT)
+            }
+        };
+        let ctap_state = CtapState::new(&mut rng, check_user_presence, DUMMY_CLOCK_VALUE);
+
+        assert_eq!(
+            ctap_state.select_credential_with_touches(DUMMY_CHANNEL_ID, 2),
+            Ok(0)
+        );
+        assert_eq!(*selections.borrow(), vec![Some(0), Some(1), Some(0)]);
+    }
+
+    #[test]
+    fn test_select_credential_with_touches_stuck_button() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        assert_eq!(
+            ctap_state.select_credential_with_touches(DUMMY_CHANNEL_ID, 3),
+            Err(Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn test_select_credential_with_touches_cancel() {
+        let mut rng = ThreadRng256 {};
+        let user_cancels = |_, _| Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
+        let ctap_state = CtapState::new(&mut rng, user_cancels, DUMMY_CLOCK_VALUE);
+
+        assert_eq!(
+            ctap_state.select_credential_with_touches(DUMMY_CHANNEL_ID, 3),
+            Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+        );
+    }
+
+    #[test]
+    fn test_vendor_provision() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let attestation_key = crypto::ecdsa::SecKey::gensk(&mut rng);
+        let mut private_key = [0; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        attestation_key.to_bytes(&mut private_key);
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let aaguid = [0x33u8; key_material::AAGUID_LENGTH];
+        let response = ctap_state.process_vendor_provision(
+            AuthenticatorVendorProvisionParameters {
+                aaguid: Some(aaguid),
+                private_key: Some(private_key),
+                certificate_chain: Some(vec![vec![0xdd; 20], vec![0xee; 30]]),
+                generate_private_key: false,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorVendorProvision(
+                AuthenticatorVendorProvisionResponse {
+                    aaguid: aaguid.to_vec(),
+                    pkey_programmed: true,
+                    certificate_chain_length: 2,
+                    locked: false,
+                    attestation_public_key: None,
+                }
+            ))
+        );
+
+        // Re-provisioning is allowed before locking, but the key must be a valid scalar.
+        let response = ctap_state.process_vendor_provision(
+            AuthenticatorVendorProvisionParameters {
+                aaguid: None,
+                private_key: Some([0xFF; key_material::ATTESTATION_PRIVATE_KEY_LENGTH]),
+                certificate_chain: None,
+                generate_private_key: false,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER));
+        let response = ctap_state.process_vendor_provision(
+            AuthenticatorVendorProvisionParameters {
+                aaguid: None,
+                private_key: None,
+                certificate_chain: Some(vec![vec![0xcc; 20]]),
+                generate_private_key: false,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorVendorProvision(
+                AuthenticatorVendorProvisionResponse {
+                    aaguid: aaguid.to_vec(),
+                    pkey_programmed: true,
+                    certificate_chain_length: 1,
+                    locked: false,
+                    attestation_public_key: None,
+                }
+            ))
+        );
+
+        let response = ctap_state.process_vendor_lock_provisioning(DUMMY_CHANNEL_ID);
+        assert_eq!(
+            response,
+            Ok(ResponseData::AuthenticatorVendorProvision(
+                AuthenticatorVendorProvisionResponse {
+                    aaguid: aaguid.to_vec(),
+                    pkey_programmed: true,
+                    certificate_chain_length: 1,
+                    locked: true,
+                    attestation_public_key: None,
+                }
+            ))
+        );
+
+        // After locking, the material can't be changed anymore, even after a reset.
+        ctap_state
+            .process_reset(DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE)
+            .unwrap();
+        let response = ctap_state.process_vendor_provision(
+            AuthenticatorVendorProvisionParameters {
+                aaguid: Some([0x44u8; key_material::AAGUID_LENGTH]),
+                private_key: None,
+                certificate_chain: None,
+                generate_private_key: false,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED));
+        assert_eq!(ctap_state.persistent_store.aaguid(), Ok(aaguid));
+    }
+
+    #[test]
+    fn test_vendor_provision_generate_private_key() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        // The secure element can't also receive a key.
+        let response = ctap_state.process_vendor_provision(
+            AuthenticatorVendorProvisionParameters {
+                aaguid: None,
+                private_key: Some([0x41; key_material::ATTESTATION_PRIVATE_KEY_LENGTH]),
+                certificate_chain: None,
+                generate_private_key: true,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER));
+
+        let response = ctap_state
+            .process_vendor_provision(
+                AuthenticatorVendorProvisionParameters {
+                    aaguid: None,
+                    private_key: None,
+                    certificate_chain: None,
+                    generate_private_key: true,
+                },
+                DUMMY_CHANNEL_ID,
+            )
+            .unwrap();
+        let attestation_public_key = match response {
+            ResponseData::AuthenticatorVendorProvision(AuthenticatorVendorProvisionResponse {
+                pkey_programmed: true,
+                certificate_chain_length: 0,
+                attestation_public_key: Some(attestation_public_key),
+                ..
+            }) => crypto::ecdsa::PubKey::try_from(attestation_public_key).unwrap(),
+            _ => panic!("Invalid response type"),
+        };
+        assert_eq!(
+            ctap_state.persistent_store.has_attestation_private_key(),
+            Ok(false)
+        );
+
+        // The certificate is issued for the public key, then provisioned.
+        ctap_state
+            .process_vendor_provision(
+                AuthenticatorVendorProvisionParameters {
+                    aaguid: None,
+                    private_key: None,
+                    certificate_chain: Some(vec![vec![0xdd; 20]]),
+                    generate_private_key: false,
+                },
+                DUMMY_CHANNEL_ID,
+            )
+            .unwrap();
+        let response = ctap_state.process_vendor_lock_provisioning(DUMMY_CHANNEL_ID);
+        assert!(response.is_ok());
+        let response = ctap_state.process_vendor_provision(
+            AuthenticatorVendorProvisionParameters {
+                aaguid: None,
+                private_key: None,
+                certificate_chain: None,
+                generate_private_key: true,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED));
+
+        let signature = ctap_state
+            .crypto_backend
+            .sign_attestation_digest(&Sha256::hash(b"message"))
+            .unwrap();
+        assert!(attestation_public_key.verify_vartime::<Sha256>(b"message", &signature));
+    }
+
+    #[test]
+    fn test_vendor_revoke_pin_uv_auth_token() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x91; 32];
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+
+        let response = ctap_state.process_command(&[0x45], DUMMY_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, vec![0x00]);
+        assert!(!ctap_state.pin_protocol_v1.has_user_verified_flag());
+        let pin_auth = hmac_256::<Sha256>(&pin_uv_auth_token, &[0x55; 32])[..16].to_vec();
+        assert!(!ctap_state
+            .pin_protocol_v1
+            .verify_pin_auth_token(&[0x55; 32], &pin_auth, 1));
+    }
+
+    #[test]
+    fn test_vendor_lock_provisioning_incomplete() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+
+        let response = ctap_state.process_vendor_provision(
+            AuthenticatorVendorProvisionParameters {
+                aaguid: None,
+                private_key: None,
+                certificate_chain: Some(vec![vec![0xdd; 20]]),
+                generate_private_key: false,
+            },
+            DUMMY_CHANNEL_ID,
+        );
+        assert!(response.is_ok());
+        let response = ctap_state.process_vendor_lock_provisioning(DUMMY_CHANNEL_ID);
+        assert_eq!(
+            response,
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
+        );
+        assert_eq!(
+            ctap_state.persistent_store.is_provisioning_locked(),
+            Ok(false)
+        );
+    }
 }
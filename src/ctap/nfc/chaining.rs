@@ -0,0 +1,315 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::super::apdu::{ApduHeader, ApduInstructions, ApduStatusCode, APDU};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+// ISO 7816-4, section 5.1.1: bit 5 of CLA indicates that more commands of the chain follow.
+const CLA_CHAINING_BIT: u8 = 0x10;
+// Largest command data we accept after reassembly, matching the maxMsgSize from GetInfo.
+pub const MAX_CHAINED_DATA_LEN: usize = 1024;
+
+// A structure to reassemble chained command APDUs received from the reader.
+pub struct CommandAssembler {
+    // Header of the chain being assembled, without the chaining bit.
+    header: Option<ApduHeader>,
+    // Concatenated data of all commands of the chain received so far.
+    data: Vec<u8>,
+}
+
+impl CommandAssembler {
+    pub fn new() -> CommandAssembler {
+        CommandAssembler {
+            header: None,
+            data: Vec::new(),
+        }
+    }
+
+    // Resets the assembler, e.g. when the reader is deselected.
+    pub fn reset(&mut self) {
+        self.header = None;
+        self.data.clear();
+    }
+
+    // Returns:
+    // - Ok(Some(apdu)) with the raw bytes of the full command APDU after the last command of the
+    // chain. Unchained commands are returned unchanged.
+    // - Ok(None) if this command is part of a chain and must be acknowledged with SW_SUCCESS.
+    // - An error status word otherwise. The current chain is discarded in that case.
+    pub fn push(&mut self, frame: &[u8]) -> Result<Option<Vec<u8>>, ApduStatusCode> {
+        let apdu = match APDU::try_from(frame) {
+            Ok(apdu) => apdu,
+            Err(status_code) => {
+                self.reset();
+                return Err(status_code);
+            }
+        };
+        let is_chained = apdu.header.cla & CLA_CHAINING_BIT != 0;
+        if self.header.is_none() && !is_chained {
+            return Ok(Some(frame.to_vec()));
+        }
+
+        let header = ApduHeader {
+            cla: apdu.header.cla & !CLA_CHAINING_BIT,
+            ..apdu.header
+        };
+        if let Some(chain_header) = &self.header {
+            if *chain_header != header {
+                self.reset();
+                return Err(ApduStatusCode::SW_LAST_COMMAND_EXPECTED);
+            }
+        }
+        if self.data.len() + apdu.data.len() > MAX_CHAINED_DATA_LEN {
+            self.reset();
+            return Err(ApduStatusCode::SW_WRONG_LENGTH);
+        }
+        self.data.extend_from_slice(&apdu.data);
+        if is_chained {
+            self.header = Some(header);
+            return Ok(None);
+        }
+
+        self.header = None;
+        let mut data = Vec::new();
+        core::mem::swap(&mut data, &mut self.data);
+        Ok(Some(encode_extended_apdu(&header, data, apdu.le)))
+    }
+}
+
+// Serializes a command APDU with extended length fields, so that any data length fits.
+fn encode_extended_apdu(header: &ApduHeader, data: Vec<u8>, le: u32) -> Vec<u8> {
+    let mut apdu = vec![header.cla, header.ins, header.p1, header.p2];
+    if !data.is_empty() {
+        apdu.push(0x00);
+        apdu.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        apdu.extend(data);
+    } else if le != 0 {
+        apdu.push(0x00);
+    }
+    if le != 0 {
+        // An Le of 0x0000 encodes 65536 bytes.
+        apdu.extend_from_slice(&(le as u16).to_be_bytes());
+    }
+    apdu
+}
+
+// A structure to split long responses into chunks requested with GET RESPONSE.
+pub struct ResponseSplitter {
+    // Maximum number of data bytes per response APDU, excluding the status word.
+    max_data_len: usize,
+    // Response data that was not sent yet.
+    pending: Vec<u8>,
+}
+
+impl ResponseSplitter {
+    pub fn new(max_data_len: usize) -> ResponseSplitter {
+        ResponseSplitter {
+            max_data_len,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn max_data_len(&self) -> usize {
+        self.max_data_len
+    }
+
+    // Applies to the next chunks, e.g. once the reader announced a smaller frame size.
+    pub fn set_max_data_len(&mut self, max_data_len: usize) {
+        self.max_data_len = max_data_len;
+    }
+
+    // Drops any response data that was not yet requested.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+
+    // Whether some response data was not requested yet.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    // Whether the given command APDU is a GET RESPONSE for the pending data.
+    pub fn is_get_response(&self, frame: &[u8]) -> bool {
+        frame.len() >= 2 && frame[1] == ApduInstructions::GetResponse as u8
+    }
+
+    // Returns the first response APDU for a successful command. The rest of the data, if any, is
+    // kept for subsequent GET RESPONSE commands.
+    pub fn start_response(&mut self, data: Vec<u8>) -> Vec<u8> {
+        self.pending = data;
+        self.next_chunk(self.max_data_len)
+    }
+
+    // Answers a GET RESPONSE command with the next chunk of pending data.
+    pub fn get_response(&mut self, frame: &[u8]) -> Vec<u8> {
+        let apdu = match APDU::try_from(frame) {
+            Ok(apdu) => apdu,
+            Err(status_code) => return status_word(status_code.into()),
+        };
+        if self.pending.is_empty() {
+            return status_word(ApduStatusCode::SW_COND_USE_NOT_SATISFIED.into());
+        }
+        if apdu.header.p1 != 0x00 || apdu.header.p2 != 0x00 {
+            return status_word(ApduStatusCode::SW_INCORRECT_P1P2.into());
+        }
+        // An absent Le means that the reader accepts the maximum length.
+        let requested = match apdu.le {
+            0 => self.max_data_len,
+            le => core::cmp::min(le as usize, self.max_data_len),
+        };
+        self.next_chunk(requested)
+    }
+
+    fn next_chunk(&mut self, len: usize) -> Vec<u8> {
+        let len = core::cmp::min(len, self.pending.len());
+        let mut response: Vec<u8> = self.pending.drain(..len).collect();
+        let status = match self.pending.len() {
+            0 => ApduStatusCode::SW_SUCCESS.into(),
+            // SW2 of 0x00 tells the reader that 256 or more bytes are left.
+            remaining if remaining > 0xFF => ApduStatusCode::SW_GET_RESPONSE.into(),
+            remaining => u16::from(ApduStatusCode::SW_GET_RESPONSE) | remaining as u16,
+        };
+        response.extend_from_slice(&status_word(status));
+        response
+    }
+}
+
+fn status_word(status: u16) -> Vec<u8> {
+    status.to_be_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unchained_command() {
+        let mut assembler = CommandAssembler::new();
+        let frame = [0x00, 0xa4, 0x04, 0x00, 0x02, 0xe1, 0x04];
+        assert_eq!(assembler.push(&frame), Ok(Some(frame.to_vec())));
+    }
+
+    #[test]
+    fn test_chained_command() {
+        let mut assembler = CommandAssembler::new();
+        assert_eq!(
+            assembler.push(&[0x90, 0x10, 0x00, 0x00, 0x02, 0x01, 0x02]),
+            Ok(None)
+        );
+        assert_eq!(
+            assembler.push(&[0x90, 0x10, 0x00, 0x00, 0x01, 0x03]),
+            Ok(None)
+        );
+        assert_eq!(
+            assembler.push(&[0x80, 0x10, 0x00, 0x00, 0x01, 0x04, 0x00]),
+            Ok(Some(vec![
+                0x80, 0x10, 0x00, 0x00, 0x00, 0x00, 0x04, 0x01, 0x02, 0x03, 0x04, 0x01, 0x00
+            ]))
+        );
+        // The assembler is ready for the next command.
+        let frame = [0x80, 0x10, 0x00, 0x00, 0x01, 0x04];
+        assert_eq!(assembler.push(&frame), Ok(Some(frame.to_vec())));
+    }
+
+    #[test]
+    fn test_chained_command_parses_as_extended() {
+        let mut assembler = CommandAssembler::new();
+        let mut frame = vec![0x90, 0x10, 0x00, 0x00, 0xFF];
+        frame.extend_from_slice(&[0x55; 0xFF]);
+        assert_eq!(assembler.push(&frame), Ok(None));
+        let apdu = assembler
+            .push(&[0x80, 0x10, 0x00, 0x00, 0x02, 0x55, 0x55])
+            .unwrap()
+            .unwrap();
+        let apdu = APDU::try_from(&apdu[..]).unwrap();
+        assert_eq!(apdu.header.cla, 0x80);
+        assert_eq!(apdu.data, vec![0x55; 0x101]);
+    }
+
+    #[test]
+    fn test_chained_command_header_mismatch() {
+        let mut assembler = CommandAssembler::new();
+        assert_eq!(
+            assembler.push(&[0x90, 0x10, 0x00, 0x00, 0x01, 0x01]),
+            Ok(None)
+        );
+        assert_eq!(
+            assembler.push(&[0x80, 0x11, 0x00, 0x00, 0x01, 0x02]),
+            Err(ApduStatusCode::SW_LAST_COMMAND_EXPECTED)
+        );
+        // The failed chain is discarded.
+        let frame = [0x80, 0x11, 0x00, 0x00, 0x01, 0x02];
+        assert_eq!(assembler.push(&frame), Ok(Some(frame.to_vec())));
+    }
+
+    #[test]
+    fn test_chained_command_too_long() {
+        let mut assembler = CommandAssembler::new();
+        let mut frame = vec![0x90, 0x10, 0x00, 0x00, 0xFF];
+        frame.extend_from_slice(&[0x55; 0xFF]);
+        for _ in 0..MAX_CHAINED_DATA_LEN / 0xFF {
+            assert_eq!(assembler.push(&frame), Ok(None));
+        }
+        assert_eq!(assembler.push(&frame), Err(ApduStatusCode::SW_WRONG_LENGTH));
+    }
+
+    #[test]
+    fn test_short_response() {
+        let mut splitter = ResponseSplitter::new(4);
+        assert_eq!(
+            splitter.start_response(vec![0x01, 0x02]),
+            vec![0x01, 0x02, 0x90, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_response_chaining() {
+        let mut splitter = ResponseSplitter::new(4);
+        assert_eq!(
+            splitter.start_response(vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07]),
+            vec![0x01, 0x02, 0x03, 0x04, 0x61, 0x03]
+        );
+        let get_response = [0x80, 0xc0, 0x00, 0x00, 0x00];
+        assert!(splitter.is_get_response(&get_response));
+        assert_eq!(
+            splitter.get_response(&get_response),
+            vec![0x05, 0x06, 0x07, 0x90, 0x00]
+        );
+        assert_eq!(splitter.get_response(&get_response), vec![0x69, 0x85]);
+    }
+
+    #[test]
+    fn test_response_chaining_large_remainder() {
+        let mut splitter = ResponseSplitter::new(0x100);
+        let response = splitter.start_response(vec![0x55; 0x300]);
+        assert_eq!(response.len(), 0x102);
+        assert_eq!(response[0x100..], [0x61, 0x00]);
+    }
+
+    #[test]
+    fn test_get_response_with_le() {
+        let mut splitter = ResponseSplitter::new(4);
+        splitter.start_response(vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07]);
+        assert_eq!(
+            splitter.get_response(&[0x80, 0xc0, 0x00, 0x00, 0x02]),
+            vec![0x05, 0x06, 0x61, 0x01]
+        );
+        assert_eq!(
+            splitter.get_response(&[0x80, 0xc0, 0x01, 0x00, 0x02]),
+            vec![0x6a, 0x86]
+        );
+    }
+}
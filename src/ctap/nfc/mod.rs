@@ -0,0 +1,913 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod chaining;
+mod ndef;
+pub mod self_test;
+
+use self::chaining::{CommandAssembler, ResponseSplitter, MAX_CHAINED_DATA_LEN};
+use self::ndef::NdefApplet;
+use super::apdu::{ApduInstructions, ApduStatusCode, APDU};
+use super::arbiter::Interface;
+#[cfg(feature = "with_ctap1")]
+use super::ctap1::Ctap1Command;
+use super::data_formats::AuthenticatorTransport;
+use super::hid::{ChannelID, KeepaliveStatus};
+use super::status_code::Ctap2StatusCode;
+use super::transport::Transport;
+#[cfg(not(feature = "with_ctap1"))]
+use super::FIDO2_VERSION_STRING;
+#[cfg(feature = "with_ctap1")]
+use super::U2F_VERSION_STRING;
+use super::{CtapState, UserPresenceConfig};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+#[cfg(feature = "debug_ctap")]
+use core::fmt::Write;
+use crypto::rng256::Rng256;
+#[cfg(feature = "debug_ctap")]
+use libtock_drivers::console::Console;
+use libtock_drivers::nfc::{BitRate, FrameTiming, NfcTag, CARRIER_FREQUENCY_HZ};
+use libtock_drivers::timer::{ClockValue, Duration};
+
+// CTAP over NFC has no channels. This value is passed to the CTAP layer instead, and can't collide
+// with a CTAPHID channel since it is the broadcast channel.
+pub const NFC_CHANNEL_ID: ChannelID = [0xFF, 0xFF, 0xFF, 0xFF];
+// URI read by phones that select the NDEF applet instead of the FIDO applet.
+const NDEF_URI: &str = "https://github.com/google/OpenSK";
+
+// Counters of the NFC transport, reported by a vendor command.
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug, PartialEq))]
+pub struct NfcStatistics {
+    pub frames_received: u64,
+    pub frames_sent: u64,
+    pub crc_errors: u64,
+    pub wtx_requests: u64,
+    // CTAP commands that were dropped before processing, because the reader left or moved on.
+    pub aborts: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Applet {
+    Fido,
+    Ndef,
+}
+
+// A command for the FIDO applet, whose processing waits for a waiting time extension.
+enum PendingCommand {
+    // The CBOR encoded CTAP2 command.
+    Ctap2(Vec<u8>),
+    // The whole U2F APDU.
+    #[cfg(feature = "with_ctap1")]
+    Ctap1(Vec<u8>),
+}
+
+// A structure to process ISO 14443-4 frames received from an NFC reader, and route the contained
+// APDUs to the applet selected by AID.
+pub struct CtapNfc {
+    assembler: CommandAssembler,
+    splitter: ResponseSplitter,
+    ndef: NdefApplet,
+    // The applet selected by the reader since the last DESELECT.
+    selected_applet: Option<Applet>,
+    // CTAP command waiting for the reader to accept our waiting time extension, with the PCB of the
+    // I-block that carried it.
+    wtx_command: Option<(u8, PendingCommand)>,
+    // Bit rate accepted in our last PPS response, to apply once the response is sent.
+    bit_rate_change: Option<BitRate>,
+    // ISO 14443-4 section 7.5.3.2: the block number of the tag, and the last block we sent, so
+    // that the reader can ask for a retransmission.
+    block_number: u8,
+    last_block: Option<Vec<u8>>,
+}
+
+impl CtapNfc {
+    // ISO 14443-4 section 5.6.1: request for answer to select.
+    const RATS: u8 = 0xE0;
+    /// ISO 14443-4 section 5.2.3: frames of up to 256 bytes.
+    pub const FSCI: u8 = 8;
+    // ISO 14443-4 section 7.2: frame waiting time of 4096 * 2^FWI carrier cycles, about 620 ms.
+    const FWI: u8 = 0xB;
+    // Our answer to select: bit rates up to 424 kbps, the same in both directions, no CID nor NAD
+    // support, no historical bytes.
+    const ATS: [u8; 5] = [
+        0x05,
+        0x70 | CtapNfc::FSCI,
+        0xB3,
+        CtapNfc::FWI << 4 | 0x01,
+        0x00,
+    ];
+    // ISO 14443-4 section 5.3: parameter selection request, without CID. PPS1 contains the
+    // divisor indices DSI and DRI.
+    const PPSS: u8 = 0xD0;
+    const PPS0_PPS1_PRESENT: u8 = 0x10;
+    /// Longest frame, CRC included, that the reader may send according to our ATS.
+    pub const MAX_FRAME_SIZE: usize = 256;
+    // ISO 14443-4 section 7.1.1: the PCB of I-blocks is 000x_x01x, where the last bit is the block
+    // number. We don't support CID, NAD nor block chaining.
+    const PCB_I_BLOCK_MASK: u8 = 0xFE;
+    const PCB_I_BLOCK: u8 = 0x02;
+    const PCB_S_DESELECT: u8 = 0xC2;
+    const PCB_S_WTX: u8 = 0xF2;
+    // ISO 14443-4 section 7.1.1: the PCB of R-blocks is 101x_x01x, with the NAK bit and the block
+    // number.
+    const PCB_R_BLOCK_MASK: u8 = 0xEE;
+    const PCB_R_BLOCK: u8 = 0xA2;
+    const PCB_R_NAK: u8 = 0x10;
+    const PCB_BLOCK_NUMBER: u8 = 0x01;
+    // ISO 14443-4 section 7.5.3.1, rule C: the block number of the tag starts at 1.
+    const INITIAL_BLOCK_NUMBER: u8 = 1;
+    // ISO 14443-4 section 7.3: multiplier of the frame waiting time, between 1 and 59. The CTAP
+    // layer processes commands synchronously, so we can't send S(WTX) while it is busy. Instead, we
+    // request an extension before processing each command. With the FWI of our ATS, this gives the
+    // CTAP layer about 6 seconds, which covers key generation and storage compaction.
+    const WTX_MULTIPLIER: u8 = 10;
+
+    // CTAP specification (version 20190130) section 8.2.2
+    const FIDO_AID: [u8; 8] = [0xA0, 0x00, 0x00, 0x06, 0x47, 0x2F, 0x00, 0x01];
+    const SELECT_BY_NAME: u8 = 0x04;
+    // CTAP specification (version 20190130) section 8.2.4
+    const NFCCTAP_CLA: u8 = 0x80;
+    const NFCCTAP_MSG: u8 = 0x10;
+    // FIDO U2F NFC protocol (version 20170411) section 4: U2F raw messages are sent as is.
+    #[cfg(feature = "with_ctap1")]
+    const U2F_CLA: u8 = 0x00;
+
+    // ISO 14443-4 section 5.1: the FSDI in the parameter byte of RATS codes the longest frame that
+    // the reader accepts, CRC included. Larger values of later revisions are capped at 256 bytes,
+    // which is the most we send.
+    const FSD_TABLE: [usize; 9] = [16, 24, 32, 40, 48, 64, 96, 128, 256];
+    // Our I-blocks have a PCB and a CRC around the response APDU, which ends with a status word.
+    const RESPONSE_OVERHEAD: usize = 5;
+    // Longest response data for the default FSD of 256 bytes.
+    const MAX_RESPONSE_DATA_LEN: usize = 256 - CtapNfc::RESPONSE_OVERHEAD;
+
+    /// Timing of the reader frames, as announced in our ATS.
+    pub fn frame_timing() -> FrameTiming {
+        FrameTiming::from_ats(&CtapNfc::ATS, CARRIER_FREQUENCY_HZ)
+    }
+
+    pub fn new() -> CtapNfc {
+        CtapNfc {
+            assembler: CommandAssembler::new(),
+            splitter: ResponseSplitter::new(CtapNfc::MAX_RESPONSE_DATA_LEN),
+            ndef: NdefApplet::new(NDEF_URI),
+            selected_applet: None,
+            wtx_command: None,
+            bit_rate_change: None,
+            block_number: CtapNfc::INITIAL_BLOCK_NUMBER,
+            last_block: None,
+        }
+    }
+
+    // Forgets the applet selection and any partial exchange, e.g. when the field is lost.
+    pub fn reset(&mut self) {
+        self.assembler.reset();
+        self.splitter.reset();
+        self.ndef.reset();
+        self.selected_applet = None;
+        self.wtx_command = None;
+        self.bit_rate_change = None;
+        self.splitter
+            .set_max_data_len(CtapNfc::MAX_RESPONSE_DATA_LEN);
+        self.block_number = CtapNfc::INITIAL_BLOCK_NUMBER;
+        self.last_block = None;
+    }
+
+    // Drops the exchange in progress, when the reader sends DESELECT or the field is lost.
+    pub fn abort<R, CheckUserPresence>(&mut self, ctap_state: &mut CtapState<R, CheckUserPresence>)
+    where
+        R: Rng256,
+        CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
+    {
+        if self.wtx_command.is_some() {
+            ctap_state.nfc_statistics.aborts += 1;
+        }
+        self.reset();
+        ctap_state.interface_arbiter.release(Interface::Nfc);
+        // Nothing expects a next command anymore.
+        ctap_state.clear_assertion_state();
+        // The reader that got the token left the field, or selected another application.
+        ctap_state.pin_protocol_v1.stop_using_pin_uv_auth_token();
+    }
+
+    // Returns the frame to transmit back to the reader, if any.
+    pub fn process_frame<R, CheckUserPresence>(
+        &mut self,
+        frame: &[u8],
+        clock_value: ClockValue,
+        ctap_state: &mut CtapState<R, CheckUserPresence>,
+    ) -> Option<Vec<u8>>
+    where
+        R: Rng256,
+        CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
+    {
+        let pcb = *frame.first()?;
+        if pcb == CtapNfc::RATS {
+            if let Some(param) = frame.get(1) {
+                let fsdi = (param >> 4) as usize;
+                let fsd = *CtapNfc::FSD_TABLE.get(fsdi).unwrap_or(&256);
+                // Longer responses are fetched with GET RESPONSE.
+                self.splitter
+                    .set_max_data_len(fsd - CtapNfc::RESPONSE_OVERHEAD);
+            }
+            return Some(CtapNfc::ATS.to_vec());
+        }
+        if pcb == CtapNfc::PPSS {
+            return self.process_pps(frame);
+        }
+        if pcb & CtapNfc::PCB_R_BLOCK_MASK == CtapNfc::PCB_R_BLOCK {
+            return self.process_r_block(pcb);
+        }
+        if pcb == CtapNfc::PCB_S_WTX {
+            // The reader accepted the extension, so we have time to process the command.
+            let (command_pcb, command) = self.wtx_command.take()?;
+            let mut reply = vec![command_pcb];
+            reply.extend(self.process_fido_command(command, clock_value, ctap_state));
+            self.update_interface_lock(clock_value, ctap_state);
+            return self.send_block(reply);
+        }
+        if pcb == CtapNfc::PCB_S_DESELECT {
+            self.abort(ctap_state);
+            return Some(vec![CtapNfc::PCB_S_DESELECT]);
+        }
+        // Any other frame means that the reader gave up on the extended command.
+        if self.wtx_command.take().is_some() {
+            ctap_state.nfc_statistics.aborts += 1;
+        }
+        if pcb & CtapNfc::PCB_I_BLOCK_MASK != CtapNfc::PCB_I_BLOCK {
+            #[cfg(feature = "debug_ctap")]
+            writeln!(
+                &mut Console::new(),
+                "Ignored NFC frame with PCB {:02x}",
+                pcb
+            )
+            .unwrap();
+            return None;
+        }
+        self.block_number = pcb & CtapNfc::PCB_BLOCK_NUMBER;
+        if frame.len() == 1 {
+            // ISO 14443-4 section 7.5.6.2: an empty I-block is a presence check, answered by an
+            // empty I-block.
+            return self.send_block(vec![pcb]);
+        }
+        match self.process_apdu(&frame[1..]) {
+            Some(response) => {
+                // The reply uses the same block number.
+                let mut reply = vec![pcb];
+                reply.extend(response);
+                self.update_interface_lock(clock_value, ctap_state);
+                self.send_block(reply)
+            }
+            None => {
+                if ctap_state
+                    .interface_arbiter
+                    .acquire(Interface::Nfc, clock_value)
+                    .is_err()
+                {
+                    // A USB transaction is in progress.
+                    let (_, command) = self.wtx_command.take()?;
+                    let mut reply = vec![pcb];
+                    reply.extend(self.busy_response(command));
+                    return self.send_block(reply);
+                }
+                self.wtx_command = self.wtx_command.take().map(|(_, command)| (pcb, command));
+                ctap_state.nfc_statistics.wtx_requests += 1;
+                self.send_block(vec![CtapNfc::PCB_S_WTX, CtapNfc::WTX_MULTIPLIER])
+            }
+        }
+    }
+
+    // The transaction lasts until the reader fetched the whole response.
+    fn update_interface_lock<R, CheckUserPresence>(
+        &mut self,
+        clock_value: ClockValue,
+        ctap_state: &mut CtapState<R, CheckUserPresence>,
+    ) where
+        R: Rng256,
+        CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
+    {
+        let arbiter = &mut ctap_state.interface_arbiter;
+        if !self.splitter.has_pending() {
+            arbiter.release(Interface::Nfc);
+        } else if arbiter.acquire(Interface::Nfc, clock_value).is_err() {
+            // We lost the lock after a timeout, so USB may have changed the state since.
+            self.splitter.reset();
+        }
+    }
+
+    // Returns the response APDU to a FIDO command that can't be processed while USB is busy.
+    fn busy_response(&mut self, command: PendingCommand) -> Vec<u8> {
+        match command {
+            PendingCommand::Ctap2(_) => self
+                .splitter
+                .start_response(vec![Ctap2StatusCode::CTAP1_ERR_CHANNEL_BUSY as u8]),
+            #[cfg(feature = "with_ctap1")]
+            PendingCommand::Ctap1(_) => status_word(ApduStatusCode::SW_COND_USE_NOT_SATISFIED),
+        }
+    }
+
+    // Returns the bit rate to switch to, after sending the PPS response.
+    pub fn take_bit_rate_change(&mut self) -> Option<BitRate> {
+        self.bit_rate_change.take()
+    }
+
+    // ISO 14443-4 section 5.3: we announced the same bit rate in both directions.
+    fn process_pps(&mut self, frame: &[u8]) -> Option<Vec<u8>> {
+        let pps0 = *frame.get(1)?;
+        if pps0 & CtapNfc::PPS0_PPS1_PRESENT != 0 {
+            let pps1 = *frame.get(2)?;
+            let dsi = (pps1 >> 2) & 0x03;
+            let dri = pps1 & 0x03;
+            if dsi != dri {
+                return None;
+            }
+            self.bit_rate_change = Some(BitRate::from_divisor_index(dri)?);
+        }
+        Some(vec![CtapNfc::PPSS])
+    }
+
+    // Remembers the block, in case the reader asks for a retransmission.
+    fn send_block(&mut self, block: Vec<u8>) -> Option<Vec<u8>> {
+        self.last_block = Some(block.clone());
+        Some(block)
+    }
+
+    // ISO 14443-4 section 7.5.4.3, rules 11 to 13.
+    fn process_r_block(&mut self, pcb: u8) -> Option<Vec<u8>> {
+        if pcb & CtapNfc::PCB_BLOCK_NUMBER == self.block_number {
+            // The reader didn't receive our last block.
+            return self.last_block.clone();
+        }
+        if pcb & CtapNfc::PCB_R_NAK != 0 {
+            // This is a presence check.
+            return Some(vec![CtapNfc::PCB_R_BLOCK | self.block_number]);
+        }
+        // We never chain blocks, so there is nothing to acknowledge.
+        None
+    }
+
+    // Returns the response APDU to a command of the FIDO applet, including the status word.
+    fn process_fido_command<R, CheckUserPresence>(
+        &mut self,
+        command: PendingCommand,
+        clock_value: ClockValue,
+        ctap_state: &mut CtapState<R, CheckUserPresence>,
+    ) -> Vec<u8>
+    where
+        R: Rng256,
+        CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
+    {
+        match command {
+            PendingCommand::Ctap2(cbor) => {
+                let response = ctap_state.process_command(&cbor, NFC_CHANNEL_ID, clock_value);
+                self.splitter.start_response(response)
+            }
+            #[cfg(feature = "with_ctap1")]
+            PendingCommand::Ctap1(apdu) => {
+                // Bringing the key in the field counts as user presence. It must not outlive this
+                // command, so what wasn't consumed is revoked.
+                ctap_state.u2f_up_state.grant_up_unrequested(clock_value);
+                let result = Ctap1Command::process_command(&apdu, ctap_state, clock_value);
+                ctap_state.u2f_up_state.revoke_up();
+                match result {
+                    Ok(payload) => self.splitter.start_response(payload),
+                    Err(status_code) => status_word(status_code),
+                }
+            }
+        }
+    }
+
+    // Returns the response APDU, including the status word. FIDO commands are not processed here:
+    // they are stored until the reader accepts a waiting time extension, and None is returned.
+    fn process_apdu(&mut self, frame: &[u8]) -> Option<Vec<u8>> {
+        let fido_selected = self.selected_applet == Some(Applet::Fido);
+        if fido_selected && self.splitter.is_get_response(frame) {
+            return Some(self.splitter.get_response(frame));
+        }
+        // Any other command drops the rest of a previous response.
+        self.splitter.reset();
+        let command = match self.assembler.push(frame) {
+            Ok(Some(command)) => command,
+            Ok(None) => return Some(status_word(ApduStatusCode::SW_SUCCESS)),
+            Err(status_code) => return Some(status_word(status_code)),
+        };
+        let apdu = match APDU::try_from(&command[..]) {
+            Ok(apdu) => apdu,
+            Err(status_code) => return Some(status_word(status_code)),
+        };
+
+        let is_select = apdu.header.ins == ApduInstructions::Select as u8;
+        if is_select && apdu.header.p1 == CtapNfc::SELECT_BY_NAME {
+            if apdu.data == CtapNfc::FIDO_AID {
+                self.selected_applet = Some(Applet::Fido);
+                // CTAP specification (version 20190130) section 8.2.2: authenticators that
+                // support U2F answer with its version string.
+                #[cfg(feature = "with_ctap1")]
+                let version = U2F_VERSION_STRING;
+                #[cfg(not(feature = "with_ctap1"))]
+                let version = FIDO2_VERSION_STRING;
+                return Some(self.splitter.start_response(version.as_bytes().to_vec()));
+            }
+            if apdu.data == NdefApplet::AID {
+                self.ndef.reset();
+                self.selected_applet = Some(Applet::Ndef);
+                return Some(status_word(ApduStatusCode::SW_SUCCESS));
+            }
+            self.selected_applet = None;
+            return Some(status_word(ApduStatusCode::SW_FILE_NOT_FOUND));
+        }
+        if self.selected_applet == Some(Applet::Ndef) {
+            return Some(self.ndef.process_apdu(&apdu, self.splitter.max_data_len()));
+        }
+        if is_select {
+            // The FIDO applet has no file to select.
+            self.selected_applet = None;
+            return Some(status_word(ApduStatusCode::SW_FILE_NOT_FOUND));
+        }
+        if !fido_selected {
+            return Some(status_word(ApduStatusCode::SW_COND_USE_NOT_SATISFIED));
+        }
+        #[cfg(feature = "with_ctap1")]
+        {
+            if apdu.header.cla == CtapNfc::U2F_CLA {
+                // The block number is filled in by the caller.
+                self.wtx_command = Some((0, PendingCommand::Ctap1(command)));
+                return None;
+            }
+        }
+        if apdu.header.cla != CtapNfc::NFCCTAP_CLA {
+            return Some(status_word(ApduStatusCode::SW_CLA_INVALID));
+        }
+        match apdu.header.ins {
+            CtapNfc::NFCCTAP_MSG => {
+                // The block number is filled in by the caller.
+                self.wtx_command = Some((0, PendingCommand::Ctap2(apdu.data)));
+                None
+            }
+            _ => Some(status_word(ApduStatusCode::SW_INS_INVALID)),
+        }
+    }
+}
+
+// ISO 14443-4 over the NFC driver.
+pub struct NfcTransport;
+
+impl Transport for NfcTransport {
+    fn transport_type(&self) -> AuthenticatorTransport {
+        AuthenticatorTransport::Nfc
+    }
+
+    fn max_msg_size(&self) -> usize {
+        MAX_CHAINED_DATA_LEN
+    }
+
+    // The waiting time extension requested before each command covers its processing.
+    fn send_keepalive(&self, _: ChannelID, _: KeepaliveStatus) -> Result<(), Ctap2StatusCode> {
+        Ok(())
+    }
+
+    // ISO 14443-4 has no cancellation, the reader just removes the key from the field.
+    fn wait_for_cancel(
+        &self,
+        _: ChannelID,
+        _: Duration<isize>,
+        _: &dyn Fn() -> bool,
+    ) -> Result<(), Ctap2StatusCode> {
+        Ok(())
+    }
+
+    // The response is a frame returned by process_frame.
+    fn send_response(&self, response: &mut [u8]) -> Result<(), Ctap2StatusCode> {
+        let len = response.len();
+        match NfcTag::transmit(response, len) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Ctap2StatusCode::CTAP1_ERR_OTHER),
+        }
+    }
+}
+
+fn status_word(status_code: ApduStatusCode) -> Vec<u8> {
+    let code: u16 = status_code.into();
+    code.to_be_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "with_ctap1")]
+    use super::super::key_material;
+    use super::super::pin_protocol_v1::PinProtocolV1;
+    use super::*;
+    use crypto::rng256::ThreadRng256;
+
+    const CLOCK_FREQUENCY_HZ: usize = 32768;
+    const DUMMY_CLOCK_VALUE: ClockValue = ClockValue::new(0, CLOCK_FREQUENCY_HZ);
+
+    fn select_fido<CheckUserPresence>(
+        ctap_nfc: &mut CtapNfc,
+        ctap_state: &mut CtapState<ThreadRng256, CheckUserPresence>,
+    ) where
+        CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
+    {
+        let mut select = vec![0x02, 0x00, 0xA4, 0x04, 0x00, 0x08];
+        select.extend_from_slice(&CtapNfc::FIDO_AID);
+        select.push(0x00);
+        let mut expected = vec![0x02];
+        #[cfg(feature = "with_ctap1")]
+        expected.extend_from_slice(b"U2F_V2");
+        #[cfg(not(feature = "with_ctap1"))]
+        expected.extend_from_slice(b"FIDO_2_0");
+        expected.extend_from_slice(&[0x90, 0x00]);
+        assert_eq!(
+            ctap_nfc.process_frame(&select, DUMMY_CLOCK_VALUE, ctap_state),
+            Some(expected)
+        );
+    }
+
+    // Returns the data of a reply and of the frames that follow it. They are fetched with GET
+    // RESPONSE while the status word announces more data.
+    fn read_chained_response<CheckUserPresence>(
+        ctap_nfc: &mut CtapNfc,
+        ctap_state: &mut CtapState<ThreadRng256, CheckUserPresence>,
+        mut reply: Vec<u8>,
+    ) -> Vec<u8>
+    where
+        CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
+    {
+        let mut response = Vec::new();
+        let mut pcb = reply[0];
+        while reply[reply.len() - 2] == 0x61 {
+            response.extend_from_slice(&reply[1..reply.len() - 2]);
+            pcb ^= 0x01;
+            let get_response = [pcb, 0x00, 0xC0, 0x00, 0x00, 0x00];
+            reply = ctap_nfc
+                .process_frame(&get_response, DUMMY_CLOCK_VALUE, ctap_state)
+                .unwrap();
+        }
+        assert_eq!(&reply[reply.len() - 2..], &[0x90, 0x00]);
+        response.extend_from_slice(&reply[1..reply.len() - 2]);
+        response
+    }
+
+    #[test]
+    fn test_rats() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_nfc = CtapNfc::new();
+
+        let reply = ctap_nfc.process_frame(&[0xE0, 0x80], DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(CtapNfc::ATS.to_vec()));
+    }
+
+    #[test]
+    fn test_small_frame_size() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_nfc = CtapNfc::new();
+
+        // The reader accepts frames of 32 bytes.
+        let reply = ctap_nfc.process_frame(&[0xE0, 0x20], DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(CtapNfc::ATS.to_vec()));
+        select_fido(&mut ctap_nfc, &mut ctap_state);
+        let get_info = [0x03, 0x80, 0x10, 0x00, 0x00, 0x01, 0x04, 0x00];
+        ctap_nfc.process_frame(&get_info, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        let mut reply = ctap_nfc
+            .process_frame(&[0xF2, 0x0A], DUMMY_CLOCK_VALUE, &mut ctap_state)
+            .unwrap();
+        let expected = ctap_state.process_command(&[0x04], NFC_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        let mut response = Vec::new();
+        let mut pcb = 0x03;
+        while reply[reply.len() - 2] == 0x61 {
+            // Our frames take 2 more bytes for the CRC.
+            assert_eq!(reply.len(), 30);
+            response.extend_from_slice(&reply[1..reply.len() - 2]);
+            pcb ^= 0x01;
+            let get_response = [pcb, 0x00, 0xC0, 0x00, 0x00, 0x00];
+            reply = ctap_nfc
+                .process_frame(&get_response, DUMMY_CLOCK_VALUE, &mut ctap_state)
+                .unwrap();
+        }
+        assert!(reply.len() <= 30);
+        assert_eq!(&reply[reply.len() - 2..], &[0x90, 0x00]);
+        response.extend_from_slice(&reply[1..reply.len() - 2]);
+        assert_eq!(response, expected);
+    }
+
+    #[test]
+    fn test_pps() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_nfc = CtapNfc::new();
+
+        // Without PPS1, the bit rate stays the same.
+        let reply = ctap_nfc.process_frame(&[0xD0, 0x01], DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0xD0]));
+        assert!(ctap_nfc.take_bit_rate_change().is_none());
+        let pps_424 = [0xD0, 0x11, 0x0A];
+        let reply = ctap_nfc.process_frame(&pps_424, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0xD0]));
+        assert!(ctap_nfc.take_bit_rate_change() == Some(BitRate::Kbps424));
+        assert!(ctap_nfc.take_bit_rate_change().is_none());
+        // We don't support 848 kbps, nor different bit rates in each direction.
+        for pps1 in &[0x0F, 0x04] {
+            let pps = [0xD0, 0x11, *pps1];
+            let reply = ctap_nfc.process_frame(&pps, DUMMY_CLOCK_VALUE, &mut ctap_state);
+            assert_eq!(reply, None);
+        }
+    }
+
+    #[test]
+    fn test_frame_timing() {
+        // The reader waits about 620 ms for our answers.
+        assert_eq!(CtapNfc::frame_timing().frame_delay_max(), 4096 << 11);
+    }
+
+    #[test]
+    fn test_select_unknown_applet() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_nfc = CtapNfc::new();
+
+        let select = [0x03, 0x00, 0xA4, 0x04, 0x00, 0x02, 0xE1, 0x04];
+        let reply = ctap_nfc.process_frame(&select, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0x03, 0x6A, 0x82]));
+    }
+
+    #[test]
+    fn test_select_ndef_applet() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_nfc = CtapNfc::new();
+
+        let select_applet = [
+            0x02, 0x00, 0xA4, 0x04, 0x00, 0x07, 0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01, 0x00,
+        ];
+        let reply = ctap_nfc.process_frame(&select_applet, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0x02, 0x90, 0x00]));
+        let select_ndef = [0x03, 0x00, 0xA4, 0x00, 0x0C, 0x02, 0xE1, 0x04];
+        let reply = ctap_nfc.process_frame(&select_ndef, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0x03, 0x90, 0x00]));
+        let read_len = [0x02, 0x00, 0xB0, 0x00, 0x00, 0x02];
+        let reply = ctap_nfc.process_frame(&read_len, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        let ndef_len = (NDEF_URI.len() + 5) as u8;
+        assert_eq!(reply, Some(vec![0x02, 0x00, ndef_len, 0x90, 0x00]));
+
+        // CTAP commands are refused until the FIDO applet is selected.
+        let get_info = [0x03, 0x80, 0x10, 0x00, 0x00, 0x01, 0x04, 0x00];
+        let reply = ctap_nfc.process_frame(&get_info, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0x03, 0x6D, 0x00]));
+        select_fido(&mut ctap_nfc, &mut ctap_state);
+    }
+
+    #[test]
+    fn test_get_info_requires_select() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_nfc = CtapNfc::new();
+
+        let get_info = [0x02, 0x80, 0x10, 0x00, 0x00, 0x01, 0x04, 0x00];
+        let reply = ctap_nfc.process_frame(&get_info, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0x02, 0x69, 0x85]));
+
+        select_fido(&mut ctap_nfc, &mut ctap_state);
+        let reply = ctap_nfc.process_frame(&get_info, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0xF2, 0x0A]));
+        let reply = ctap_nfc
+            .process_frame(&[0xF2, 0x0A], DUMMY_CLOCK_VALUE, &mut ctap_state)
+            .unwrap();
+        assert_eq!(reply[0], 0x02);
+        let response = read_chained_response(&mut ctap_nfc, &mut ctap_state, reply);
+        let expected = ctap_state.process_command(&[0x04], NFC_CHANNEL_ID, DUMMY_CLOCK_VALUE);
+        assert_eq!(response, expected);
+    }
+
+    #[test]
+    fn test_busy_while_usb_transaction() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_nfc = CtapNfc::new();
+
+        select_fido(&mut ctap_nfc, &mut ctap_state);
+        ctap_state
+            .interface_arbiter
+            .acquire(Interface::Usb, DUMMY_CLOCK_VALUE)
+            .unwrap();
+        let get_info = [0x02, 0x80, 0x10, 0x00, 0x00, 0x01, 0x04, 0x00];
+        let reply = ctap_nfc.process_frame(&get_info, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0x02, 0x06, 0x90, 0x00]));
+
+        ctap_state.interface_arbiter.release(Interface::Usb);
+        let reply = ctap_nfc.process_frame(&get_info, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0xF2, 0x0A]));
+        // USB waits until the last frame of the response is sent.
+        let usb_lock = ctap_state
+            .interface_arbiter
+            .acquire(Interface::Usb, DUMMY_CLOCK_VALUE);
+        assert_eq!(usb_lock, Err(Ctap2StatusCode::CTAP1_ERR_CHANNEL_BUSY));
+        let mut reply = ctap_nfc
+            .process_frame(&[0xF2, 0x0A], DUMMY_CLOCK_VALUE, &mut ctap_state)
+            .unwrap();
+        let mut pcb = 0x02;
+        while reply[reply.len() - 2] == 0x61 {
+            let usb_lock = ctap_state
+                .interface_arbiter
+                .acquire(Interface::Usb, DUMMY_CLOCK_VALUE);
+            assert_eq!(usb_lock, Err(Ctap2StatusCode::CTAP1_ERR_CHANNEL_BUSY));
+            pcb ^= 0x01;
+            let get_response = [pcb, 0x00, 0xC0, 0x00, 0x00, 0x00];
+            reply = ctap_nfc
+                .process_frame(&get_response, DUMMY_CLOCK_VALUE, &mut ctap_state)
+                .unwrap();
+        }
+        assert_eq!(&reply[reply.len() - 2..], &[0x90, 0x00]);
+        let usb_lock = ctap_state
+            .interface_arbiter
+            .acquire(Interface::Usb, DUMMY_CLOCK_VALUE);
+        assert_eq!(usb_lock, Ok(()));
+    }
+
+    #[test]
+    fn test_deselect() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_nfc = CtapNfc::new();
+
+        select_fido(&mut ctap_nfc, &mut ctap_state);
+        let reply = ctap_nfc.process_frame(&[0xC2], DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0xC2]));
+        let get_info = [0x03, 0x80, 0x10, 0x00, 0x00, 0x01, 0x04, 0x00];
+        let reply = ctap_nfc.process_frame(&get_info, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0x03, 0x69, 0x85]));
+    }
+
+    #[test]
+    fn test_deselect_stops_using_token() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, [0x91; 32]);
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        ctap_state.pin_protocol_v1 = pin_protocol_v1;
+        let mut ctap_nfc = CtapNfc::new();
+
+        select_fido(&mut ctap_nfc, &mut ctap_state);
+        assert!(ctap_state.pin_protocol_v1.has_user_verified_flag());
+        let reply = ctap_nfc.process_frame(&[0xC2], DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0xC2]));
+        assert!(!ctap_state.pin_protocol_v1.has_user_verified_flag());
+    }
+
+    #[cfg(feature = "with_ctap1")]
+    #[test]
+    fn test_u2f_version() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_nfc = CtapNfc::new();
+
+        select_fido(&mut ctap_nfc, &mut ctap_state);
+        let version = [0x03, 0x00, 0x03, 0x00, 0x00, 0x00];
+        let reply = ctap_nfc.process_frame(&version, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0xF2, 0x0A]));
+        let reply = ctap_nfc.process_frame(&[0xF2, 0x0A], DUMMY_CLOCK_VALUE, &mut ctap_state);
+        let mut expected = vec![0x03];
+        expected.extend_from_slice(b"U2F_V2");
+        expected.extend_from_slice(&[0x90, 0x00]);
+        assert_eq!(reply, Some(expected));
+    }
+
+    #[cfg(feature = "with_ctap1")]
+    #[test]
+    fn test_u2f_register_without_button() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_nfc = CtapNfc::new();
+
+        let fake_key = [0x41u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        assert!(ctap_state
+            .persistent_store
+            .set_attestation_private_key(&fake_key)
+            .is_ok());
+        let fake_cert = [0x99u8; 100];
+        assert!(ctap_state
+            .persistent_store
+            .set_attestation_certificate(&fake_cert[..])
+            .is_ok());
+
+        select_fido(&mut ctap_nfc, &mut ctap_state);
+        let mut register = vec![0x03, 0x00, 0x01, 0x00, 0x00, 0x40];
+        register.extend_from_slice(&[0x55; 64]);
+        register.push(0x00);
+        let reply = ctap_nfc.process_frame(&register, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0xF2, 0x0A]));
+        let reply = ctap_nfc
+            .process_frame(&[0xF2, 0x0A], DUMMY_CLOCK_VALUE, &mut ctap_state)
+            .unwrap();
+        // The registration doesn't fit in a frame, so it ends with 61XX.
+        assert_eq!(&reply[..2], &[0x03, 0x05]);
+        assert_eq!(reply[reply.len() - 2], 0x61);
+        // The presence granted by the tap doesn't carry over to later commands.
+        assert!(!ctap_state.u2f_up_state.is_up_needed(DUMMY_CLOCK_VALUE));
+        assert!(!ctap_state.u2f_up_state.consume_up(DUMMY_CLOCK_VALUE));
+    }
+
+    #[test]
+    fn test_unsolicited_wtx_response() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_nfc = CtapNfc::new();
+
+        let reply = ctap_nfc.process_frame(&[0xF2, 0x0A], DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, None);
+    }
+
+    #[test]
+    fn test_wtx_cancelled() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_nfc = CtapNfc::new();
+
+        select_fido(&mut ctap_nfc, &mut ctap_state);
+        let get_info = [0x03, 0x80, 0x10, 0x00, 0x00, 0x01, 0x04, 0x00];
+        let reply = ctap_nfc.process_frame(&get_info, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0xF2, 0x0A]));
+        // The reader sends a new command instead of accepting the extension.
+        select_fido(&mut ctap_nfc, &mut ctap_state);
+        let reply = ctap_nfc.process_frame(&[0xF2, 0x0A], DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, None);
+        assert_eq!(ctap_state.nfc_statistics.wtx_requests, 1);
+        assert_eq!(ctap_state.nfc_statistics.aborts, 1);
+    }
+
+    #[test]
+    fn test_presence_check() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_nfc = CtapNfc::new();
+
+        // Before any I-block, the block number of the tag is 1.
+        let reply = ctap_nfc.process_frame(&[0xB2], DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0xA3]));
+        select_fido(&mut ctap_nfc, &mut ctap_state);
+        let reply = ctap_nfc.process_frame(&[0xB3], DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0xA2]));
+        let reply = ctap_nfc.process_frame(&[0x03], DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0x03]));
+    }
+
+    #[test]
+    fn test_retransmission() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_nfc = CtapNfc::new();
+
+        let select = [0x03, 0x00, 0xA4, 0x04, 0x00, 0x02, 0xE1, 0x04];
+        let reply = ctap_nfc.process_frame(&select, DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, Some(vec![0x03, 0x6A, 0x82]));
+        for pcb in &[0xA3, 0xB3] {
+            let reply = ctap_nfc.process_frame(&[*pcb], DUMMY_CLOCK_VALUE, &mut ctap_state);
+            assert_eq!(reply, Some(vec![0x03, 0x6A, 0x82]));
+        }
+        // An acknowledgement of another block is ignored, since we don't chain blocks.
+        let reply = ctap_nfc.process_frame(&[0xA2], DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, None);
+    }
+
+    #[test]
+    fn test_unknown_frame() {
+        let mut rng = ThreadRng256 {};
+        let user_immediately_present = |_, _| Ok(());
+        let mut ctap_state = CtapState::new(&mut rng, user_immediately_present, DUMMY_CLOCK_VALUE);
+        let mut ctap_nfc = CtapNfc::new();
+
+        // WUPA has no ISO 14443-4 answer.
+        let reply = ctap_nfc.process_frame(&[0x52], DUMMY_CLOCK_VALUE, &mut ctap_state);
+        assert_eq!(reply, None);
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::super::apdu::{ApduInstructions, ApduStatusCode, APDU};
+use super::{status_word, CtapNfc};
+use alloc::vec;
+use alloc::vec::Vec;
+
+// NFC Forum Type 4 Tag section 5.1.2: select command of elementary files, by file identifier.
+const SELECT_BY_FILE_ID: u8 = 0x00;
+const SELECT_FIRST_OR_ONLY: u8 = 0x0C;
+const CC_FILE_ID: [u8; 2] = [0xE1, 0x03];
+const NDEF_FILE_ID: [u8; 2] = [0xE1, 0x04];
+// NFC Forum NDEF section 3.2: short record with the MB, ME and SR flags, well-known type.
+const NDEF_RECORD_HEADER: u8 = 0xD1;
+// NFC Forum URI RTD section 3.2.2: the URI is stored without abbreviation.
+const URI_TYPE: u8 = b'U';
+const URI_NO_PREFIX: u8 = 0x00;
+// Short records have a 1 byte payload length, which includes the prefix byte.
+const MAX_URI_LEN: usize = 254;
+
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+enum SelectedFile {
+    CapabilityContainer,
+    Ndef,
+}
+
+// A read-only NFC Forum Type 4 Tag, holding a single NDEF file with a URI record. Phones read it
+// when they tap the key outside of a WebAuthn flow.
+pub struct NdefApplet {
+    ndef_file: Vec<u8>,
+    selected_file: Option<SelectedFile>,
+}
+
+impl NdefApplet {
+    // NFC Forum Type 4 Tag section 5.4: AID of the NDEF Tag Application, version 2.0.
+    pub const AID: [u8; 7] = [0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01];
+
+    // URIs that don't fit in a short record are truncated.
+    pub fn new(uri: &str) -> NdefApplet {
+        let uri = &uri.as_bytes()[..core::cmp::min(uri.len(), MAX_URI_LEN)];
+        let record_len = 5 + uri.len();
+        // The NDEF file starts with the length of the NDEF message.
+        let mut ndef_file = (record_len as u16).to_be_bytes().to_vec();
+        ndef_file.extend_from_slice(&[
+            NDEF_RECORD_HEADER,
+            0x01,
+            uri.len() as u8 + 1,
+            URI_TYPE,
+            URI_NO_PREFIX,
+        ]);
+        ndef_file.extend_from_slice(uri);
+        NdefApplet {
+            ndef_file,
+            selected_file: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.selected_file = None;
+    }
+
+    // NFC Forum Type 4 Tag section 5.1.2: the capability container announces a single read-only
+    // NDEF file.
+    fn capability_container(&self) -> Vec<u8> {
+        let max_read_len = CtapNfc::MAX_RESPONSE_DATA_LEN as u16;
+        let ndef_file_len = self.ndef_file.len() as u16;
+        let mut cc_file = vec![0x00, 0x0F, 0x20];
+        cc_file.extend_from_slice(&max_read_len.to_be_bytes());
+        // We don't support UPDATE BINARY, so the maximum command size is irrelevant.
+        cc_file.extend_from_slice(&[0x00, 0xFF, 0x04, 0x06]);
+        cc_file.extend_from_slice(&NDEF_FILE_ID);
+        cc_file.extend_from_slice(&ndef_file_len.to_be_bytes());
+        // Read access granted, write access denied.
+        cc_file.extend_from_slice(&[0x00, 0xFF]);
+        cc_file
+    }
+
+    // Returns the response APDU, including the status word. The applet itself must be selected
+    // by the caller. Reads return at most max_read_len bytes, so that the response fits in a frame.
+    pub fn process_apdu(&mut self, apdu: &APDU, max_read_len: usize) -> Vec<u8> {
+        if apdu.header.ins == ApduInstructions::Select as u8 {
+            if apdu.header.p1 != SELECT_BY_FILE_ID || apdu.header.p2 != SELECT_FIRST_OR_ONLY {
+                return status_word(ApduStatusCode::SW_INCORRECT_P1P2);
+            }
+            self.selected_file = if apdu.data == CC_FILE_ID {
+                Some(SelectedFile::CapabilityContainer)
+            } else if apdu.data == NDEF_FILE_ID {
+                Some(SelectedFile::Ndef)
+            } else {
+                None
+            };
+            return match self.selected_file {
+                Some(_) => status_word(ApduStatusCode::SW_SUCCESS),
+                None => status_word(ApduStatusCode::SW_FILE_NOT_FOUND),
+            };
+        }
+        if apdu.header.ins != ApduInstructions::ReadBinary as u8 {
+            return status_word(ApduStatusCode::SW_INS_INVALID);
+        }
+        let file = match self.selected_file {
+            Some(SelectedFile::CapabilityContainer) => self.capability_container(),
+            Some(SelectedFile::Ndef) => self.ndef_file.clone(),
+            None => return status_word(ApduStatusCode::SW_COND_USE_NOT_SATISFIED),
+        };
+        let offset = u16::from_be_bytes([apdu.header.p1, apdu.header.p2]) as usize;
+        if offset > file.len() {
+            return status_word(ApduStatusCode::SW_INCORRECT_P1P2);
+        }
+        // An Le of 0 asks for as many bytes as possible.
+        let mut read_len = max_read_len;
+        if apdu.le != 0 {
+            read_len = core::cmp::min(read_len, apdu.le as usize);
+        }
+        let end = core::cmp::min(file.len(), offset + read_len);
+        let mut response = file[offset..end].to_vec();
+        response.extend(status_word(ApduStatusCode::SW_SUCCESS));
+        response
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::convert::TryFrom;
+
+    fn process(applet: &mut NdefApplet, command: &[u8]) -> Vec<u8> {
+        applet.process_apdu(
+            &APDU::try_from(command).unwrap(),
+            CtapNfc::MAX_RESPONSE_DATA_LEN,
+        )
+    }
+
+    #[test]
+    fn test_ndef_file() {
+        let applet = NdefApplet::new("https://a.b");
+        let expected = [
+            0x00, 0x10, 0xD1, 0x01, 0x0C, 0x55, 0x00, b'h', b't', b't', b'p', b's', b':', b'/',
+            b'/', b'a', b'.', b'b',
+        ];
+        assert_eq!(applet.ndef_file, expected.to_vec());
+    }
+
+    #[test]
+    fn test_read_capability_container() {
+        let mut applet = NdefApplet::new("https://a.b");
+        let select_cc = [0x00, 0xA4, 0x00, 0x0C, 0x02, 0xE1, 0x03];
+        assert_eq!(process(&mut applet, &select_cc), vec![0x90, 0x00]);
+        let read_cc = [0x00, 0xB0, 0x00, 0x00, 0x0F];
+        let expected = vec![
+            0x00, 0x0F, 0x20, 0x00, 0xFB, 0x00, 0xFF, 0x04, 0x06, 0xE1, 0x04, 0x00, 0x12, 0x00,
+            0xFF, 0x90, 0x00,
+        ];
+        assert_eq!(process(&mut applet, &read_cc), expected);
+    }
+
+    #[test]
+    fn test_read_ndef_with_offset() {
+        let mut applet = NdefApplet::new("https://a.b");
+        let select_ndef = [0x00, 0xA4, 0x00, 0x0C, 0x02, 0xE1, 0x04];
+        assert_eq!(process(&mut applet, &select_ndef), vec![0x90, 0x00]);
+        let read_len = [0x00, 0xB0, 0x00, 0x00, 0x02];
+        assert_eq!(
+            process(&mut applet, &read_len),
+            vec![0x00, 0x10, 0x90, 0x00]
+        );
+        let read_end = [0x00, 0xB0, 0x00, 0x10, 0x10];
+        assert_eq!(
+            process(&mut applet, &read_end),
+            vec![b'.', b'b', 0x90, 0x00]
+        );
+        let read_past_end = [0x00, 0xB0, 0x00, 0x13, 0x01];
+        assert_eq!(process(&mut applet, &read_past_end), vec![0x6A, 0x86]);
+    }
+
+    #[test]
+    fn test_read_without_select() {
+        let mut applet = NdefApplet::new("https://a.b");
+        let read = [0x00, 0xB0, 0x00, 0x00, 0x02];
+        assert_eq!(process(&mut applet, &read), vec![0x69, 0x85]);
+        let select_unknown = [0x00, 0xA4, 0x00, 0x0C, 0x02, 0xE1, 0x05];
+        assert_eq!(process(&mut applet, &select_unknown), vec![0x6A, 0x82]);
+        assert_eq!(process(&mut applet, &read), vec![0x69, 0x85]);
+    }
+}
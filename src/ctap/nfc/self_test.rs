@@ -0,0 +1,94 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use libtock_drivers::nfc::NfcError;
+
+// The steps of the self-test, in order. The failed step is reported with its number.
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+#[cfg_attr(feature = "std", allow(dead_code))]
+pub enum SelfTestStep {
+    Setup = 1,
+    Configure = 2,
+    Emulate = 3,
+    Loopback = 4,
+    Exchange = 5,
+    Compare = 6,
+}
+
+// The step that failed, and the driver error if there was one.
+pub type SelfTestFailure = (SelfTestStep, Option<NfcError>);
+
+#[cfg(not(feature = "std"))]
+mod prod {
+    use super::{SelfTestFailure, SelfTestStep};
+    use libtock_drivers::nfc::{NfcEmulationGuard, NfcTag};
+    use libtock_drivers::timer::Duration;
+
+    // The pattern is long enough to span several bytes of the frontend FIFO.
+    const PATTERN_LEN: usize = 64;
+    // The echo takes a few milliseconds at 106 kbps.
+    const ECHO_TIMEOUT: Duration<isize> = Duration::from_ms(100);
+
+    // Exercises the NFC driver end-to-end: the tag is emulated in loopback mode, and a frame is
+    // checked against its echo. The reception of the main loop is aborted first, since the driver
+    // has a single reception in flight.
+    pub fn run() -> Result<(), SelfTestFailure> {
+        NfcTag::setup().map_err(|e| (SelfTestStep::Setup, Some(e)))?;
+        NfcTag::abort().map_err(|e| (SelfTestStep::Setup, Some(e)))?;
+        NfcTag::configure(4).map_err(|e| (SelfTestStep::Configure, Some(e)))?;
+        let _emulation = NfcEmulationGuard::new().map_err(|e| (SelfTestStep::Emulate, Some(e)))?;
+        NfcTag::set_loopback(true).map_err(|e| (SelfTestStep::Loopback, Some(e)))?;
+        let result = exchange();
+        // The reader interface is restored even if the exchange failed.
+        NfcTag::set_loopback(false).map_err(|e| (SelfTestStep::Loopback, Some(e)))?;
+        result
+    }
+
+    fn exchange() -> Result<(), SelfTestFailure> {
+        let mut pattern = [0; PATTERN_LEN];
+        for (i, byte) in pattern.iter_mut().enumerate() {
+            *byte = i as u8 ^ 0xA5;
+        }
+        let mut tx_buf = pattern;
+        let mut rx_buf = [0; PATTERN_LEN];
+        let recv_op = NfcTag::echo(&mut tx_buf, &mut rx_buf, ECHO_TIMEOUT)
+            .map_err(|e| (SelfTestStep::Exchange, Some(e)))?;
+        if recv_op.result_code != 0 {
+            return Err((
+                SelfTestStep::Exchange,
+                Some((recv_op.result_code as isize).into()),
+            ));
+        }
+        if rx_buf[..recv_op.recv_amount] != pattern[..] {
+            return Err((SelfTestStep::Compare, None));
+        }
+        Ok(())
+    }
+}
+#[cfg(not(feature = "std"))]
+pub use self::prod::run;
+
+// Self-test for testing, where the NFC driver is missing.
+#[cfg(feature = "std")]
+mod test {
+    use super::{SelfTestFailure, SelfTestStep};
+    use libtock_drivers::nfc::NfcError;
+
+    pub fn run() -> Result<(), SelfTestFailure> {
+        Err((SelfTestStep::Setup, Some(NfcError::DriverMissing)))
+    }
+}
+#[cfg(feature = "std")]
+pub use self::test::run;
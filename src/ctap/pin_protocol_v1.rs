@@ -13,214 +13,420 @@
 // limitations under the License.
 
 use super::command::AuthenticatorClientPinParameters;
-use super::data_formats::{ClientPinSubCommand, CoseKey, GetAssertionHmacSecretInput};
+use super::cose::CoseKey;
+use super::data_formats::{ClientPinSubCommand, GetAssertionHmacSecretInput};
+use super::fault;
 use super::response::{AuthenticatorClientPinResponse, ResponseData};
 use super::status_code::Ctap2StatusCode;
 use super::storage::PersistentStore;
+use super::token_state::PinUvAuthTokenState;
 #[cfg(feature = "with_ctap2_1")]
 use alloc::string::String;
+#[cfg(feature = "with_ctap2_1")]
 use alloc::vec;
 use alloc::vec::Vec;
 use arrayref::array_ref;
+#[cfg(feature = "with_ctap2_1")]
+use cbor::cbor_array_vec;
 use core::convert::TryInto;
 use crypto::cbc::{cbc_decrypt, cbc_encrypt};
-use crypto::hmac::{hmac_256, verify_hmac_256_first_128bits};
+use crypto::constant_time::bytes_eq;
+use crypto::ecdh::Ecdh;
+use crypto::hkdf::hkdf_empty_salt_256;
+use crypto::hmac::{hmac_256, verify_hmac_256, verify_hmac_256_first_128bits};
 use crypto::rng256::Rng256;
 use crypto::sha256::Sha256;
+use crypto::zeroize::Zeroize;
 use crypto::Hash256;
 #[cfg(all(test, feature = "with_ctap2_1"))]
 use enum_iterator::IntoEnumIterator;
-use subtle::ConstantTimeEq;
+use libtock_drivers::timer::ClockValue;
 
 // Those constants have to be multiples of 16, the AES block size.
 pub const PIN_AUTH_LENGTH: usize = 16;
 const PIN_PADDED_LENGTH: usize = 64;
 const PIN_TOKEN_LENGTH: usize = 32;
+// PIN/UV auth protocol two authenticates with the full HMAC output.
+const PIN_AUTH_LENGTH_V2: usize = 32;
+
+// The supported PIN/UV auth protocols, in order of preference.
+#[cfg(not(feature = "with_ctap2_1"))]
+pub const PIN_PROTOCOLS: &[u64] = &[1];
+#[cfg(feature = "with_ctap2_1")]
+pub const PIN_PROTOCOLS: &[u64] = &[2, 1];
+
+/// The key agreement of both PIN/UV auth protocols, ECDH on P-256.
+///
+/// Key handling only goes through the Ecdh trait, so that another curve only
+/// needs to change this type and the conversions of its public key.
+type KeyAgreement = crypto::ecdh::P256;
+
+/// The primitives of a PIN/UV auth protocol.
+///
+/// Both protocols share the key agreement key and the pinUvAuthToken of
+/// PinProtocolV1. They differ in how keys are derived from the ECDH shared
+/// secret, and in how messages are encrypted and authenticated.
+pub trait PinUvAuthProtocol {
+    /// Derives the HMAC key and the AES key, in this order, from the raw ECDH
+    /// shared secret of the key agreement key and the platform's public key.
+    fn decapsulate(&self, z: &[u8; 32]) -> ([u8; 32], [u8; 32]);
+
+    /// Encrypts the plaintext with AES-256-CBC. The plaintext length must be a
+    /// multiple of the AES block size.
+    fn encrypt(&self, rng: &mut dyn Rng256, aes_key: &[u8; 32], plaintext: &[u8]) -> Vec<u8>;
+
+    /// Decrypts the ciphertext with AES-256-CBC. Returns None if the ciphertext
+    /// length is not valid for this protocol.
+    fn decrypt(&self, aes_key: &[u8; 32], ciphertext: &[u8]) -> Option<Vec<u8>>;
+
+    /// Checks the pinUvAuthParam of the message.
+    fn verify(&self, hmac_key: &[u8], message: &[u8], pin_auth: &[u8]) -> bool;
+}
+
+/// PIN/UV auth protocol one uses SHA-256 of the shared point's x-coordinate as
+/// both the HMAC and the AES key, encrypts with a zero IV and truncates the
+/// HMAC output to 16 bytes.
+pub struct PinUvAuthProtocolOne;
+
+impl PinUvAuthProtocol for PinUvAuthProtocolOne {
+    fn decapsulate(&self, z: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+        let shared_secret = Sha256::hash(z);
+        (shared_secret, shared_secret)
+    }
+
+    fn encrypt(&self, _rng: &mut dyn Rng256, aes_key: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
+        aes256_cbc_encrypt(aes_key, [0u8; 16], plaintext)
+    }
+
+    fn decrypt(&self, aes_key: &[u8; 32], ciphertext: &[u8]) -> Option<Vec<u8>> {
+        aes256_cbc_decrypt(aes_key, [0u8; 16], ciphertext)
+    }
+
+    fn verify(&self, hmac_key: &[u8], message: &[u8], pin_auth: &[u8]) -> bool {
+        if pin_auth.len() != PIN_AUTH_LENGTH {
+            return false;
+        }
+        verify_hmac_256_first_128bits::<Sha256>(
+            hmac_key,
+            message,
+            array_ref![pin_auth, 0, PIN_AUTH_LENGTH],
+        )
+    }
+}
+
+/// PIN/UV auth protocol two derives both keys with HKDF-SHA-256, prepends a
+/// random IV to each ciphertext and authenticates with the full HMAC output.
+pub struct PinUvAuthProtocolTwo;
+
+impl PinUvAuthProtocol for PinUvAuthProtocolTwo {
+    fn decapsulate(&self, z: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+        (
+            hkdf_empty_salt_256::<Sha256>(z, b"CTAP2 HMAC key"),
+            hkdf_empty_salt_256::<Sha256>(z, b"CTAP2 AES key"),
+        )
+    }
+
+    fn encrypt(&self, rng: &mut dyn Rng256, aes_key: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
+        let random = rng.gen_uniform_u8x32();
+        let iv = *array_ref![random, 0, 16];
+        let mut ciphertext = iv.to_vec();
+        ciphertext.extend(aes256_cbc_encrypt(aes_key, iv, plaintext));
+        ciphertext
+    }
+
+    fn decrypt(&self, aes_key: &[u8; 32], ciphertext: &[u8]) -> Option<Vec<u8>> {
+        if ciphertext.len() < 16 {
+            return None;
+        }
+        aes256_cbc_decrypt(aes_key, *array_ref![ciphertext, 0, 16], &ciphertext[16..])
+    }
+
+    fn verify(&self, hmac_key: &[u8], message: &[u8], pin_auth: &[u8]) -> bool {
+        if pin_auth.len() != PIN_AUTH_LENGTH_V2 {
+            return false;
+        }
+        verify_hmac_256::<Sha256>(
+            hmac_key,
+            message,
+            array_ref![pin_auth, 0, PIN_AUTH_LENGTH_V2],
+        )
+    }
+}
+
+/// Returns the implementation of the given PIN/UV auth protocol.
+pub fn select_pin_uv_auth_protocol(
+    pin_uv_auth_protocol: u64,
+) -> Result<&'static dyn PinUvAuthProtocol, Ctap2StatusCode> {
+    match pin_uv_auth_protocol {
+        1 => Ok(&PinUvAuthProtocolOne),
+        2 => Ok(&PinUvAuthProtocolTwo),
+        _ => Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER),
+    }
+}
+
+/// Encrypts the plaintext with AES-256-CBC. The plaintext length must be a
+/// multiple of the AES block size.
+fn aes256_cbc_encrypt(aes_key: &[u8; 32], iv: [u8; 16], plaintext: &[u8]) -> Vec<u8> {
+    let mut blocks = plaintext
+        .chunks_exact(16)
+        .map(|block| *array_ref![block, 0, 16])
+        .collect::<Vec<[u8; 16]>>();
+    let aes_enc_key = crypto::aes256::EncryptionKey::new(aes_key);
+    cbc_encrypt(&aes_enc_key, iv, &mut blocks);
+    blocks.iter().flatten().cloned().collect()
+}
+
+/// Decrypts the ciphertext with AES-256-CBC. Returns None if the ciphertext
+/// length is not a multiple of the AES block size.
+///
+/// The plaintext is copied into a buffer of the final size, and the blocks are
+/// wiped, so that no other copy of the plaintext remains.
+fn aes256_cbc_decrypt(aes_key: &[u8; 32], iv: [u8; 16], ciphertext: &[u8]) -> Option<Vec<u8>> {
+    if ciphertext.len() % 16 != 0 {
+        return None;
+    }
+    let mut blocks = ciphertext
+        .chunks_exact(16)
+        .map(|block| *array_ref![block, 0, 16])
+        .collect::<Vec<[u8; 16]>>();
+    let aes_enc_key = crypto::aes256::EncryptionKey::new(aes_key);
+    let aes_dec_key = crypto::aes256::DecryptionKey::new(&aes_enc_key);
+    cbc_decrypt(&aes_dec_key, iv, &mut blocks);
+    let mut plaintext = Vec::with_capacity(ciphertext.len());
+    for block in blocks.iter_mut() {
+        plaintext.extend_from_slice(block);
+        block.zeroize();
+    }
+    Some(plaintext)
+}
+
+/// Checks the given pin_auth against the HMAC of the given PIN/UV auth protocol.
+/// Unknown protocols never verify.
+fn verify_pin_auth(
+    hmac_key: &[u8],
+    hmac_contents: &[u8],
+    pin_auth: &[u8],
+    pin_uv_auth_protocol: u64,
+) -> bool {
+    select_pin_uv_auth_protocol(pin_uv_auth_protocol).map_or(false, |protocol| {
+        protocol.verify(hmac_key, hmac_contents, pin_auth)
+    })
+}
+
+/// The keys derived from the ECDH shared secret with the platform, for the
+/// PIN/UV auth protocol chosen by the platform.
+///
+/// The keys are wiped when the shared secret is dropped.
+struct SharedSecret {
+    protocol: &'static dyn PinUvAuthProtocol,
+    hmac_key: [u8; 32],
+    aes_key: [u8; 32],
+}
+
+impl Drop for SharedSecret {
+    fn drop(&mut self) {
+        self.hmac_key.zeroize();
+        self.aes_key.zeroize();
+    }
+}
+
+impl SharedSecret {
+    fn new(
+        key_agreement_key: &<KeyAgreement as Ecdh>::SecKey,
+        key_agreement: CoseKey,
+        pin_uv_auth_protocol: u64,
+    ) -> Result<SharedSecret, Ctap2StatusCode> {
+        let protocol = select_pin_uv_auth_protocol(pin_uv_auth_protocol)?;
+        let pk: <KeyAgreement as Ecdh>::PubKey = CoseKey::try_into(key_agreement)?;
+        let mut z = KeyAgreement::exchange(key_agreement_key, &pk)
+            .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+        let (hmac_key, aes_key) = protocol.decapsulate(&z);
+        z.zeroize();
+        Ok(SharedSecret {
+            protocol,
+            hmac_key,
+            aes_key,
+        })
+    }
+
+    fn encrypt(&self, rng: &mut dyn Rng256, plaintext: &[u8]) -> Vec<u8> {
+        self.protocol.encrypt(rng, &self.aes_key, plaintext)
+    }
 
-/// Checks the given pin_auth against the truncated output of HMAC-SHA256.
-/// Returns LEFT(HMAC(hmac_key, hmac_contents), 16) == pin_auth).
-fn verify_pin_auth(hmac_key: &[u8], hmac_contents: &[u8], pin_auth: &[u8]) -> bool {
-    if pin_auth.len() != PIN_AUTH_LENGTH {
-        return false;
-    }
-    verify_hmac_256_first_128bits::<Sha256>(
-        hmac_key,
-        hmac_contents,
-        array_ref![pin_auth, 0, PIN_AUTH_LENGTH],
-    )
+    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        self.protocol.decrypt(&self.aes_key, ciphertext)
+    }
+
+    /// Checks the given pin_auth against the HMAC of the message.
+    fn verify(&self, message: &[u8], pin_auth: &[u8]) -> bool {
+        self.protocol.verify(&self.hmac_key, message, pin_auth)
+    }
 }
 
 /// Encrypts the HMAC-secret outputs. To compute them, we first have to
 /// decrypt the HMAC secret salt(s) that were encrypted with the shared secret.
 /// The credRandom is used as a secret to HMAC those salts.
 fn encrypt_hmac_secret_output(
-    shared_secret: &[u8; 32],
+    rng: &mut dyn Rng256,
+    shared_secret: &SharedSecret,
     salt_enc: &[u8],
     cred_random: &[u8; 32],
 ) -> Result<Vec<u8>, Ctap2StatusCode> {
-    if salt_enc.len() != 32 && salt_enc.len() != 64 {
+    let mut decrypted_salts = shared_secret
+        .decrypt(salt_enc)
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION)?;
+    if decrypted_salts.len() != 32 && decrypted_salts.len() != 64 {
+        decrypted_salts.zeroize();
         return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION);
     }
-    let aes_enc_key = crypto::aes256::EncryptionKey::new(shared_secret);
-    let aes_dec_key = crypto::aes256::DecryptionKey::new(&aes_enc_key);
-    // The specification specifically asks for a zero IV.
-    let iv = [0u8; 16];
-
-    // With the if clause restriction above, block_len can only be 2 or 4.
-    let block_len = salt_enc.len() / 16;
-    let mut blocks = vec![[0u8; 16]; block_len];
-    for i in 0..block_len {
-        blocks[i].copy_from_slice(&salt_enc[16 * i..16 * (i + 1)]);
-    }
-    cbc_decrypt(&aes_dec_key, iv, &mut blocks[..block_len]);
-
-    let mut decrypted_salt1 = [0u8; 32];
-    decrypted_salt1[..16].copy_from_slice(&blocks[0]);
-    decrypted_salt1[16..].copy_from_slice(&blocks[1]);
-    let output1 = hmac_256::<Sha256>(&cred_random[..], &decrypted_salt1[..]);
-    for i in 0..2 {
-        blocks[i].copy_from_slice(&output1[16 * i..16 * (i + 1)]);
-    }
-
-    if block_len == 4 {
-        let mut decrypted_salt2 = [0u8; 32];
-        decrypted_salt2[..16].copy_from_slice(&blocks[2]);
-        decrypted_salt2[16..].copy_from_slice(&blocks[3]);
-        let output2 = hmac_256::<Sha256>(&cred_random[..], &decrypted_salt2[..]);
-        for i in 0..2 {
-            blocks[i + 2].copy_from_slice(&output2[16 * i..16 * (i + 1)]);
-        }
-    }
-
-    cbc_encrypt(&aes_enc_key, iv, &mut blocks[..block_len]);
-    let mut encrypted_output = Vec::with_capacity(salt_enc.len());
-    for b in &blocks[..block_len] {
-        encrypted_output.extend(b);
+    let mut output = Vec::with_capacity(decrypted_salts.len());
+    for salt in decrypted_salts.chunks(32) {
+        let mut salt_output = hmac_256::<Sha256>(&cred_random[..], salt);
+        output.extend(&salt_output);
+        salt_output.zeroize();
     }
-    Ok(encrypted_output)
+    decrypted_salts.zeroize();
+    let output_enc = shared_secret.encrypt(rng, &output);
+    output.zeroize();
+    Ok(output_enc)
 }
 
 /// Decrypts the new_pin_enc and outputs the found PIN.
-fn decrypt_pin(
-    aes_dec_key: &crypto::aes256::DecryptionKey,
-    new_pin_enc: Vec<u8>,
-) -> Option<Vec<u8>> {
-    if new_pin_enc.len() != PIN_PADDED_LENGTH {
+///
+/// The PIN is truncated in place, so the caller wipes the only copy, including
+/// the padding, when zeroizing it.
+fn decrypt_pin(shared_secret: &SharedSecret, new_pin_enc: Vec<u8>) -> Option<Vec<u8>> {
+    let mut decrypted_pin = shared_secret.decrypt(&new_pin_enc)?;
+    if decrypted_pin.len() != PIN_PADDED_LENGTH {
+        decrypted_pin.zeroize();
         return None;
     }
-    let iv = [0u8; 16];
-    // Assuming PIN_PADDED_LENGTH % block_size == 0 here.
-    const BLOCK_COUNT: usize = PIN_PADDED_LENGTH / 16;
-    let mut blocks = [[0u8; 16]; BLOCK_COUNT];
-    for i in 0..BLOCK_COUNT {
-        blocks[i].copy_from_slice(&new_pin_enc[i * 16..(i + 1) * 16]);
-    }
-    cbc_decrypt(aes_dec_key, iv, &mut blocks);
     // In CTAP 2.1, the specification changed. The new wording might lead to
     // different behavior when there are non-zero bytes after zero bytes.
     // This implementation consistently ignores those degenerate cases.
-    Some(
-        blocks
-            .iter()
-            .flatten()
-            .cloned()
-            .take_while(|&c| c != 0)
-            .collect::<Vec<u8>>(),
-    )
+    let pin_length = decrypted_pin
+        .iter()
+        .position(|&c| c == 0)
+        .unwrap_or(PIN_PADDED_LENGTH);
+    decrypted_pin.truncate(pin_length);
+    Some(decrypted_pin)
+}
+
+/// Returns the length of the PIN in Unicode code points.
+///
+/// PINs are UTF-8 encoded, so other byte strings are rejected.
+fn pin_code_point_length(pin: &[u8]) -> Option<usize> {
+    core::str::from_utf8(pin)
+        .ok()
+        .map(|pin| pin.chars().count())
 }
 
 /// Stores the encrypted new PIN in the persistent storage, if it satisfies the
 /// PIN policy. The PIN is decrypted and stripped from its padding. Next, the
-/// length of the PIN is checked to fulfill policy requirements. Last, the PIN
-/// is hashed, truncated to 16 bytes and persistently stored.
+/// length of the PIN in code points is checked to fulfill policy requirements.
+/// Last, the PIN is hashed, truncated to 16 bytes and persistently stored.
+/// The PIN and its hash are wiped from memory afterwards.
 fn check_and_store_new_pin(
     persistent_store: &mut PersistentStore,
-    aes_dec_key: &crypto::aes256::DecryptionKey,
+    shared_secret: &SharedSecret,
     new_pin_enc: Vec<u8>,
 ) -> Result<(), Ctap2StatusCode> {
-    let pin = decrypt_pin(aes_dec_key, new_pin_enc)
-        .ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)?;
-
     #[cfg(feature = "with_ctap2_1")]
     let min_pin_length = persistent_store.min_pin_length()? as usize;
     #[cfg(not(feature = "with_ctap2_1"))]
     let min_pin_length = 4;
-    if pin.len() < min_pin_length || pin.len() == PIN_PADDED_LENGTH {
-        // TODO(kaczmarczyck) check 4 code point minimum instead
+
+    let mut pin = decrypt_pin(shared_secret, new_pin_enc)
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)?;
+    let is_valid = pin_code_point_length(&pin).map_or(false, |length| length >= min_pin_length)
+        && pin.len() < PIN_PADDED_LENGTH;
+    if !is_valid {
+        pin.zeroize();
         return Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION);
     }
+    let mut hash = Sha256::hash(&pin[..]);
+    pin.zeroize();
     let mut pin_hash = [0u8; 16];
-    pin_hash.copy_from_slice(&Sha256::hash(&pin[..])[..16]);
-    persistent_store.set_pin_hash(&pin_hash)?;
-    Ok(())
+    pin_hash.copy_from_slice(&hash[..16]);
+    hash.zeroize();
+    let result = persistent_store.set_pin_hash(&pin_hash);
+    pin_hash.zeroize();
+    result
 }
 
 #[cfg(feature = "with_ctap2_1")]
 #[cfg_attr(test, derive(IntoEnumIterator))]
-// TODO remove when all variants are used
-#[allow(dead_code)]
 pub enum PinPermission {
     // All variants should use integers with a single bit set.
     MakeCredential = 0x01,
     GetAssertion = 0x02,
     CredentialManagement = 0x04,
     BioEnrollment = 0x08,
-    PlatformConfiguration = 0x10,
+    LargeBlobWrite = 0x10,
     AuthenticatorConfiguration = 0x20,
 }
 
 pub struct PinProtocolV1 {
-    key_agreement_key: crypto::ecdh::SecKey,
+    key_agreement_key: <KeyAgreement as Ecdh>::SecKey,
     pin_uv_auth_token: [u8; PIN_TOKEN_LENGTH],
+    pin_uv_auth_token_state: PinUvAuthTokenState,
     consecutive_pin_mismatches: u8,
-    #[cfg(feature = "with_ctap2_1")]
-    permissions: u8,
-    #[cfg(feature = "with_ctap2_1")]
-    permissions_rp_id: Option<String>,
+}
+
+// The key agreement key wipes itself when dropped.
+impl Drop for PinProtocolV1 {
+    fn drop(&mut self) {
+        self.pin_uv_auth_token.zeroize();
+    }
 }
 
 impl PinProtocolV1 {
     pub fn new(rng: &mut impl Rng256) -> PinProtocolV1 {
-        let key_agreement_key = crypto::ecdh::SecKey::gensk(rng);
+        let key_agreement_key = KeyAgreement::gensk(rng);
         let pin_uv_auth_token = rng.gen_uniform_u8x32();
         PinProtocolV1 {
             key_agreement_key,
             pin_uv_auth_token,
+            pin_uv_auth_token_state: PinUvAuthTokenState::new(),
             consecutive_pin_mismatches: 0,
-            #[cfg(feature = "with_ctap2_1")]
-            permissions: 0,
-            #[cfg(feature = "with_ctap2_1")]
-            permissions_rp_id: None,
         }
     }
 
     /// Decrypts the encrypted pin_hash and compares it to the stored pin_hash.
-    /// Resets or decreases the PIN retries, depending on success or failure.
-    /// Also, in case of failure, the key agreement key is randomly reset.
+    /// The PIN retries are decreased before the comparison, and reset on success.
+    /// Also, in case of failure, the key agreement key is randomly reset. After 3
+    /// consecutive failures, a power cycle is needed before the next attempt.
     fn verify_pin_hash_enc(
         &mut self,
         rng: &mut impl Rng256,
         persistent_store: &mut PersistentStore,
-        aes_dec_key: &crypto::aes256::DecryptionKey,
+        shared_secret: &SharedSecret,
         pin_hash_enc: Vec<u8>,
     ) -> Result<(), Ctap2StatusCode> {
         match persistent_store.pin_hash()? {
-            Some(pin_hash) => {
+            Some(mut pin_hash) => {
                 if self.consecutive_pin_mismatches >= 3 {
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_BLOCKED);
                 }
+                // Decrementing first, an attacker can't cut the power after a
+                // mismatch to keep the retries.
                 persistent_store.decr_pin_retries()?;
-                if pin_hash_enc.len() != PIN_AUTH_LENGTH {
-                    return Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID);
-                }
-
-                let iv = [0u8; 16];
-                let mut blocks = [[0u8; 16]; 1];
-                blocks[0].copy_from_slice(&pin_hash_enc);
-                cbc_decrypt(aes_dec_key, iv, &mut blocks);
+                // The comparison can't be found at a fixed time, to make it harder to glitch.
+                fault::random_delay(rng);
+                let is_match = match shared_secret.decrypt(&pin_hash_enc) {
+                    Some(mut pin_hash_dec) => {
+                        let is_match = pin_hash_dec.len() == PIN_AUTH_LENGTH
+                            && bytes_eq(&pin_hash, &pin_hash_dec);
+                        pin_hash_dec.zeroize();
+                        is_match
+                    }
+                    // An undecryptable PIN hash is a mismatch too.
+                    None => false,
+                };
+                pin_hash.zeroize();
 
-                if !bool::from(pin_hash.ct_eq(&blocks[0])) {
-                    self.key_agreement_key = crypto::ecdh::SecKey::gensk(rng);
+                if !is_match {
+                    self.regenerate(rng);
                     if persistent_store.pin_retries()? == 0 {
                         return Err(Ctap2StatusCode::CTAP2_ERR_PIN_BLOCKED);
                     }
@@ -234,29 +440,32 @@ impl PinProtocolV1 {
             // This status code is not explicitly mentioned in the specification.
             None => return Err(Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED),
         }
-        persistent_store.reset_pin_retries()?;
+        persistent_store.transaction(|persistent_store| {
+            persistent_store.reset_pin_retries()?;
+            // Entering the correct PIN also unblocks built-in user verification.
+            #[cfg(feature = "with_ctap2_1")]
+            persistent_store.reset_uv_retries()?;
+            Ok(())
+        })?;
         self.consecutive_pin_mismatches = 0;
         Ok(())
     }
 
     /// Uses the self-owned and passed halves of the key agreement to generate the
-    /// shared secret for checking pin_auth and generating a decryption key.
-    fn exchange_decryption_key(
+    /// shared secret of the given PIN protocol, and checks pin_auth with it.
+    fn exchange_shared_secret(
         &self,
         key_agreement: CoseKey,
+        pin_uv_auth_protocol: u64,
         pin_auth: &[u8],
         authenticated_message: &[u8],
-    ) -> Result<crypto::aes256::DecryptionKey, Ctap2StatusCode> {
-        let pk: crypto::ecdh::PubKey = CoseKey::try_into(key_agreement)?;
-        let shared_secret = self.key_agreement_key.exchange_x_sha256(&pk);
-
-        if !verify_pin_auth(&shared_secret, authenticated_message, pin_auth) {
+    ) -> Result<SharedSecret, Ctap2StatusCode> {
+        let shared_secret =
+            SharedSecret::new(&self.key_agreement_key, key_agreement, pin_uv_auth_protocol)?;
+        if !shared_secret.verify(authenticated_message, pin_auth) {
             return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
         }
-
-        let aes_enc_key = crypto::aes256::EncryptionKey::new(&shared_secret);
-        let aes_dec_key = crypto::aes256::DecryptionKey::new(&aes_enc_key);
-        Ok(aes_dec_key)
+        Ok(shared_secret)
     }
 
     fn process_get_pin_retries(
@@ -267,15 +476,17 @@ impl PinProtocolV1 {
             key_agreement: None,
             pin_token: None,
             retries: Some(persistent_store.pin_retries()? as u64),
+            uv_retries: None,
         })
     }
 
     fn process_get_key_agreement(&self) -> Result<AuthenticatorClientPinResponse, Ctap2StatusCode> {
-        let pk = self.key_agreement_key.genpk();
+        let pk = KeyAgreement::genpk(&self.key_agreement_key);
         Ok(AuthenticatorClientPinResponse {
             key_agreement: Some(CoseKey::from(pk)),
             pin_token: None,
             retries: None,
+            uv_retries: None,
         })
     }
 
@@ -283,15 +494,20 @@ impl PinProtocolV1 {
         &mut self,
         persistent_store: &mut PersistentStore,
         key_agreement: CoseKey,
+        pin_uv_auth_protocol: u64,
         pin_auth: Vec<u8>,
         new_pin_enc: Vec<u8>,
     ) -> Result<(), Ctap2StatusCode> {
         if persistent_store.pin_hash()?.is_some() {
             return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
         }
-        let pin_decryption_key =
-            self.exchange_decryption_key(key_agreement, &pin_auth, &new_pin_enc)?;
-        check_and_store_new_pin(persistent_store, &pin_decryption_key, new_pin_enc)?;
+        let shared_secret = self.exchange_shared_secret(
+            key_agreement,
+            pin_uv_auth_protocol,
+            &pin_auth,
+            &new_pin_enc,
+        )?;
+        check_and_store_new_pin(persistent_store, &shared_secret, new_pin_enc)?;
         persistent_store.reset_pin_retries()?;
         Ok(())
     }
@@ -301,6 +517,7 @@ impl PinProtocolV1 {
         rng: &mut impl Rng256,
         persistent_store: &mut PersistentStore,
         key_agreement: CoseKey,
+        pin_uv_auth_protocol: u64,
         pin_auth: Vec<u8>,
         new_pin_enc: Vec<u8>,
         pin_hash_enc: Vec<u8>,
@@ -310,12 +527,16 @@ impl PinProtocolV1 {
         }
         let mut auth_param_data = new_pin_enc.clone();
         auth_param_data.extend(&pin_hash_enc);
-        let pin_decryption_key =
-            self.exchange_decryption_key(key_agreement, &pin_auth, &auth_param_data)?;
-        self.verify_pin_hash_enc(rng, persistent_store, &pin_decryption_key, pin_hash_enc)?;
-
-        check_and_store_new_pin(persistent_store, &pin_decryption_key, new_pin_enc)?;
-        self.pin_uv_auth_token = rng.gen_uniform_u8x32();
+        let shared_secret = self.exchange_shared_secret(
+            key_agreement,
+            pin_uv_auth_protocol,
+            &pin_auth,
+            &auth_param_data,
+        )?;
+        self.verify_pin_hash_enc(rng, persistent_store, &shared_secret, pin_hash_enc)?;
+
+        check_and_store_new_pin(persistent_store, &shared_secret, new_pin_enc)?;
+        self.reset_pin_uv_auth_token(rng);
         Ok(())
     }
 
@@ -324,71 +545,93 @@ impl PinProtocolV1 {
         rng: &mut impl Rng256,
         persistent_store: &mut PersistentStore,
         key_agreement: CoseKey,
+        pin_uv_auth_protocol: u64,
         pin_hash_enc: Vec<u8>,
+        now: ClockValue,
     ) -> Result<AuthenticatorClientPinResponse, Ctap2StatusCode> {
         if persistent_store.pin_retries()? == 0 {
             return Err(Ctap2StatusCode::CTAP2_ERR_PIN_BLOCKED);
         }
-        let pk: crypto::ecdh::PubKey = CoseKey::try_into(key_agreement)?;
-        let shared_secret = self.key_agreement_key.exchange_x_sha256(&pk);
-
-        let token_encryption_key = crypto::aes256::EncryptionKey::new(&shared_secret);
-        let pin_decryption_key = crypto::aes256::DecryptionKey::new(&token_encryption_key);
-        self.verify_pin_hash_enc(rng, persistent_store, &pin_decryption_key, pin_hash_enc)?;
+        let shared_secret =
+            SharedSecret::new(&self.key_agreement_key, key_agreement, pin_uv_auth_protocol)?;
+        self.verify_pin_hash_enc(rng, persistent_store, &shared_secret, pin_hash_enc)?;
+        // A forced PIN change is only fulfilled by changePin, the PIN is still verified first.
+        #[cfg(feature = "with_ctap2_1")]
+        {
+            if persistent_store.has_force_pin_change()? {
+                return Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION);
+            }
+        }
 
         // Assuming PIN_TOKEN_LENGTH % block_size == 0 here.
-        let iv = [0u8; 16];
-        let mut blocks = [[0u8; 16]; PIN_TOKEN_LENGTH / 16];
-        for (i, item) in blocks.iter_mut().take(PIN_TOKEN_LENGTH / 16).enumerate() {
-            item.copy_from_slice(&self.pin_uv_auth_token[i * 16..(i + 1) * 16]);
-        }
-        cbc_encrypt(&token_encryption_key, iv, &mut blocks);
-        let pin_token: Vec<u8> = blocks.iter().flatten().cloned().collect();
+        let pin_token = shared_secret.encrypt(rng, &self.pin_uv_auth_token);
+        self.pin_uv_auth_token_state
+            .begin_using_pin_uv_auth_token(false, now);
 
         #[cfg(feature = "with_ctap2_1")]
-        {
-            self.permissions = 0x03;
-            self.permissions_rp_id = None;
-        }
+        self.pin_uv_auth_token_state.set_permissions(0x03, None);
 
         Ok(AuthenticatorClientPinResponse {
             key_agreement: None,
             pin_token: Some(pin_token),
             retries: None,
+            uv_retries: None,
         })
     }
 
+    /// Issues a pinUvAuthToken with the requested permissions after built-in user
+    /// verification. The caller owns the sensor and verifies the user in verify_user.
     #[cfg(feature = "with_ctap2_1")]
-    fn process_get_pin_uv_auth_token_using_uv_with_permissions(
-        &self,
-        // If you want to support local user verification, implement this function.
-        // Lacking a fingerprint reader, this subcommand is currently unsupported.
-        _key_agreement: CoseKey,
-        _permissions: u8,
-        _permissions_rp_id: Option<String>,
+    pub fn process_get_pin_uv_auth_token_using_uv_with_permissions(
+        &mut self,
+        rng: &mut impl Rng256,
+        key_agreement: CoseKey,
+        pin_uv_auth_protocol: u64,
+        permissions: u8,
+        permissions_rp_id: Option<String>,
+        now: ClockValue,
+        verify_user: impl FnOnce() -> Result<(), Ctap2StatusCode>,
     ) -> Result<AuthenticatorClientPinResponse, Ctap2StatusCode> {
-        // User verifications is only supported through PIN currently.
-        #[cfg(not(feature = "with_ctap2_1"))]
-        {
-            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND)
+        if !PIN_PROTOCOLS.contains(&pin_uv_auth_protocol) {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
         }
-        #[cfg(feature = "with_ctap2_1")]
-        {
-            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND)
+        if permissions == 0 {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+        if permissions & 0x03 != 0 && permissions_rp_id.is_none() {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
         }
+        let shared_secret =
+            SharedSecret::new(&self.key_agreement_key, key_agreement, pin_uv_auth_protocol)?;
+        verify_user()?;
+
+        let pin_token = shared_secret.encrypt(rng, &self.pin_uv_auth_token);
+        // Presenting a finger to the sensor also proves user presence.
+        self.pin_uv_auth_token_state
+            .begin_using_pin_uv_auth_token(true, now);
+        self.pin_uv_auth_token_state
+            .set_permissions(permissions, permissions_rp_id);
+
+        Ok(AuthenticatorClientPinResponse {
+            key_agreement: None,
+            pin_token: Some(pin_token),
+            retries: None,
+            uv_retries: None,
+        })
     }
 
+    /// Returns the number of remaining built-in user verification retries.
     #[cfg(feature = "with_ctap2_1")]
-    fn process_get_uv_retries(&self) -> Result<AuthenticatorClientPinResponse, Ctap2StatusCode> {
-        // User verifications is only supported through PIN currently.
-        #[cfg(not(feature = "with_ctap2_1"))]
-        {
-            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND)
-        }
-        #[cfg(feature = "with_ctap2_1")]
-        {
-            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND)
-        }
+    pub fn process_get_uv_retries(
+        &self,
+        persistent_store: &PersistentStore,
+    ) -> Result<AuthenticatorClientPinResponse, Ctap2StatusCode> {
+        Ok(AuthenticatorClientPinResponse {
+            key_agreement: None,
+            pin_token: None,
+            retries: None,
+            uv_retries: Some(persistent_store.uv_retries()? as u64),
+        })
     }
 
     #[cfg(feature = "with_ctap2_1")]
@@ -397,11 +640,9 @@ impl PinProtocolV1 {
         persistent_store: &mut PersistentStore,
         min_pin_length: u8,
         min_pin_length_rp_ids: Option<Vec<String>>,
+        pin_uv_auth_protocol: u64,
         pin_auth: Option<Vec<u8>>,
     ) -> Result<(), Ctap2StatusCode> {
-        if min_pin_length_rp_ids.is_some() {
-            return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION);
-        }
         if persistent_store.pin_hash()?.is_some() {
             match pin_auth {
                 Some(pin_auth) => {
@@ -416,12 +657,17 @@ impl PinProtocolV1 {
                     let mut message = vec![0xFF; 32];
                     message.extend(&[0x06, 0x08]);
                     message.extend(&[min_pin_length as u8, 0x00, 0x00, 0x00]);
-                    // TODO(kaczmarczyck) commented code is useful for the extension
-                    // https://github.com/google/OpenSK/issues/129
-                    // if !cbor::write(cbor_array_vec!(min_pin_length_rp_ids), &mut message) {
-                    //     return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR);
-                    // }
-                    if !verify_pin_auth(&self.pin_uv_auth_token, &message, &pin_auth) {
+                    if let Some(min_pin_length_rp_ids) = &min_pin_length_rp_ids {
+                        if !cbor::write(
+                            cbor_array_vec!(min_pin_length_rp_ids.clone()),
+                            &mut message,
+                        ) {
+                            return Err(
+                                Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR,
+                            );
+                        }
+                    }
+                    if !self.verify_pin_auth_token(&message, &pin_auth, pin_uv_auth_protocol) {
                         return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
                     }
                 }
@@ -431,13 +677,13 @@ impl PinProtocolV1 {
         if min_pin_length < persistent_store.min_pin_length()? {
             return Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION);
         }
-        persistent_store.set_min_pin_length(min_pin_length)?;
-        // TODO(kaczmarczyck) commented code is useful for the extension
-        // https://github.com/google/OpenSK/issues/129
-        // if let Some(min_pin_length_rp_ids) = min_pin_length_rp_ids {
-        //     persistent_store.set_min_pin_length_rp_ids(min_pin_length_rp_ids)?;
-        // }
-        Ok(())
+        persistent_store.transaction(|persistent_store| {
+            persistent_store.set_min_pin_length(min_pin_length)?;
+            if let Some(min_pin_length_rp_ids) = min_pin_length_rp_ids {
+                persistent_store.set_min_pin_length_rp_ids(min_pin_length_rp_ids)?;
+            }
+            Ok(())
+        })
     }
 
     #[cfg(feature = "with_ctap2_1")]
@@ -446,9 +692,11 @@ impl PinProtocolV1 {
         rng: &mut impl Rng256,
         persistent_store: &mut PersistentStore,
         key_agreement: CoseKey,
+        pin_uv_auth_protocol: u64,
         pin_hash_enc: Vec<u8>,
         permissions: u8,
         permissions_rp_id: Option<String>,
+        now: ClockValue,
     ) -> Result<AuthenticatorClientPinResponse, Ctap2StatusCode> {
         if permissions == 0 {
             return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
@@ -458,11 +706,17 @@ impl PinProtocolV1 {
             return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
         }
 
-        let response =
-            self.process_get_pin_token(rng, persistent_store, key_agreement, pin_hash_enc)?;
+        let response = self.process_get_pin_token(
+            rng,
+            persistent_store,
+            key_agreement,
+            pin_uv_auth_protocol,
+            pin_hash_enc,
+            now,
+        )?;
 
-        self.permissions = permissions;
-        self.permissions_rp_id = permissions_rp_id;
+        self.pin_uv_auth_token_state
+            .set_permissions(permissions, permissions_rp_id);
 
         Ok(response)
     }
@@ -472,6 +726,7 @@ impl PinProtocolV1 {
         rng: &mut impl Rng256,
         persistent_store: &mut PersistentStore,
         client_pin_params: AuthenticatorClientPinParameters,
+        now: ClockValue,
     ) -> Result<ResponseData, Ctap2StatusCode> {
         let AuthenticatorClientPinParameters {
             pin_protocol,
@@ -490,7 +745,7 @@ impl PinProtocolV1 {
             permissions_rp_id,
         } = client_pin_params;
 
-        if pin_protocol != 1 {
+        if !PIN_PROTOCOLS.contains(&pin_protocol) {
             #[cfg(not(feature = "with_ctap2_1"))]
             return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
             #[cfg(feature = "with_ctap2_1")]
@@ -506,6 +761,7 @@ impl PinProtocolV1 {
                 self.process_set_pin(
                     persistent_store,
                     key_agreement.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
+                    pin_protocol,
                     pin_auth.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                     new_pin_enc.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                 )?;
@@ -516,6 +772,7 @@ impl PinProtocolV1 {
                     rng,
                     persistent_store,
                     key_agreement.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
+                    pin_protocol,
                     pin_auth.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                     new_pin_enc.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                     pin_hash_enc.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
@@ -526,24 +783,23 @@ impl PinProtocolV1 {
                 rng,
                 persistent_store,
                 key_agreement.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
+                pin_protocol,
                 pin_hash_enc.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
+                now,
             )?),
+            // Built-in user verification needs the sensor, so the caller handles these.
             #[cfg(feature = "with_ctap2_1")]
-            ClientPinSubCommand::GetPinUvAuthTokenUsingUvWithPermissions => Some(
-                self.process_get_pin_uv_auth_token_using_uv_with_permissions(
-                    key_agreement.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
-                    permissions.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
-                    permissions_rp_id,
-                )?,
-            ),
-            #[cfg(feature = "with_ctap2_1")]
-            ClientPinSubCommand::GetUvRetries => Some(self.process_get_uv_retries()?),
+            ClientPinSubCommand::GetPinUvAuthTokenUsingUvWithPermissions
+            | ClientPinSubCommand::GetUvRetries => {
+                return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND);
+            }
             #[cfg(feature = "with_ctap2_1")]
             ClientPinSubCommand::SetMinPinLength => {
                 self.process_set_min_pin_length(
                     persistent_store,
                     min_pin_length.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                     min_pin_length_rp_ids,
+                    pin_protocol,
                     pin_auth,
                 )?;
                 None
@@ -554,32 +810,97 @@ impl PinProtocolV1 {
                     rng,
                     persistent_store,
                     key_agreement.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
+                    pin_protocol,
                     pin_hash_enc.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                     permissions.ok_or(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)?,
                     permissions_rp_id,
+                    now,
                 )?,
             ),
         };
         Ok(ResponseData::AuthenticatorClientPin(response))
     }
 
-    pub fn verify_pin_auth_token(&self, hmac_contents: &[u8], pin_auth: &[u8]) -> bool {
-        verify_pin_auth(&self.pin_uv_auth_token, &hmac_contents, &pin_auth)
+    /// Checks pin_auth against the pinUvAuthToken. Tokens that expired or
+    /// were never issued are rejected.
+    pub fn verify_pin_auth_token(
+        &mut self,
+        hmac_contents: &[u8],
+        pin_auth: &[u8],
+        pin_uv_auth_protocol: u64,
+    ) -> bool {
+        if !self.pin_uv_auth_token_state.is_in_use() {
+            return false;
+        }
+        let is_valid = verify_pin_auth(
+            &self.pin_uv_auth_token,
+            &hmac_contents,
+            &pin_auth,
+            pin_uv_auth_protocol,
+        );
+        if is_valid {
+            self.pin_uv_auth_token_state.mark_used();
+        }
+        is_valid
+    }
+
+    /// Expires the pinUvAuthToken and its user flags when their time limits are reached.
+    pub fn update_timeouts(&mut self, now: ClockValue) {
+        self.pin_uv_auth_token_state
+            .pin_uv_auth_token_usage_timer_observer(now);
     }
 
-    pub fn reset(&mut self, rng: &mut impl Rng256) {
-        self.key_agreement_key = crypto::ecdh::SecKey::gensk(rng);
+    /// Returns whether the pinUvAuthToken was issued with a recent proof of user presence.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn has_user_present_flag(&self) -> bool {
+        self.pin_uv_auth_token_state.get_user_present_flag_value()
+    }
+
+    pub fn has_user_verified_flag(&self) -> bool {
+        self.pin_uv_auth_token_state.get_user_verified_flag_value()
+    }
+
+    /// Returns whether the pinUvAuthToken was issued after built-in user verification instead of
+    /// a PIN.
+    pub fn is_token_from_built_in_uv(&self) -> bool {
+        self.pin_uv_auth_token_state.is_from_built_in_uv()
+    }
+
+    /// Clears the user flags after the token was used with user presence.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn clear_user_flags(&mut self) {
+        self.pin_uv_auth_token_state.clear_user_present_flag();
+        self.pin_uv_auth_token_state.clear_user_verified_flag();
+    }
+
+    /// Replaces the key agreement key, which invalidates the shared secrets of
+    /// all PIN/UV auth protocols.
+    fn regenerate(&mut self, rng: &mut impl Rng256) {
+        self.key_agreement_key = KeyAgreement::gensk(rng);
+    }
+
+    /// Replaces the pinUvAuthToken, and stops using the previous one.
+    fn reset_pin_uv_auth_token(&mut self, rng: &mut impl Rng256) {
         self.pin_uv_auth_token = rng.gen_uniform_u8x32();
+        self.pin_uv_auth_token_state.stop_using_pin_uv_auth_token();
+    }
+
+    pub fn reset(&mut self, rng: &mut impl Rng256) {
+        self.regenerate(rng);
+        self.reset_pin_uv_auth_token(rng);
         self.consecutive_pin_mismatches = 0;
-        #[cfg(feature = "with_ctap2_1")]
-        {
-            self.permissions = 0;
-            self.permissions_rp_id = None;
-        }
+    }
+
+    /// Invalidates the pinUvAuthToken, without replacing it.
+    ///
+    /// Used when the platform that received the token is gone, or asks to revoke it.
+    pub fn stop_using_pin_uv_auth_token(&mut self) {
+        self.pin_uv_auth_token_state.stop_using_pin_uv_auth_token();
     }
 
     pub fn process_hmac_secret(
         &self,
+        rng: &mut dyn Rng256,
         hmac_secret_input: GetAssertionHmacSecretInput,
         cred_random: &[u8; 32],
     ) -> Result<Vec<u8>, Ctap2StatusCode> {
@@ -587,52 +908,98 @@ impl PinProtocolV1 {
             key_agreement,
             salt_enc,
             salt_auth,
+            #[cfg(feature = "with_ctap2_1")]
+            pin_uv_auth_protocol,
         } = hmac_secret_input;
-        let pk: crypto::ecdh::PubKey = CoseKey::try_into(key_agreement)?;
-        let shared_secret = self.key_agreement_key.exchange_x_sha256(&pk);
-        // HMAC-secret does the same 16 byte truncated check.
-        if !verify_pin_auth(&shared_secret, &salt_enc, &salt_auth) {
+        // Platforms that don't specify a PIN protocol use the first one.
+        #[cfg(feature = "with_ctap2_1")]
+        let pin_uv_auth_protocol = pin_uv_auth_protocol.unwrap_or(1);
+        #[cfg(not(feature = "with_ctap2_1"))]
+        let pin_uv_auth_protocol = 1;
+        let shared_secret =
+            SharedSecret::new(&self.key_agreement_key, key_agreement, pin_uv_auth_protocol)?;
+        // HMAC-secret does the same check as the PIN protocol.
+        if !shared_secret.verify(&salt_enc, &salt_auth) {
             // Hard to tell what the correct error code here is.
             return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION);
         }
-        encrypt_hmac_secret_output(&shared_secret, &salt_enc[..], cred_random)
+        encrypt_hmac_secret_output(rng, &shared_secret, &salt_enc[..], cred_random)
     }
 
     #[cfg(feature = "with_ctap2_1")]
     pub fn has_permission(&self, permission: PinPermission) -> Result<(), Ctap2StatusCode> {
-        // Relies on the fact that all permissions are represented by powers of two.
-        if permission as u8 & self.permissions != 0 {
+        if self
+            .pin_uv_auth_token_state
+            .has_permission(permission as u8)
+        {
             Ok(())
         } else {
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
         }
     }
 
+    /// Removes all permissions except largeBlobWrite from the current token.
+    ///
+    /// A token used with user presence for makeCredential or getAssertion can
+    /// not be used for any further credential operation. Without largeBlobWrite,
+    /// the token is invalidated.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn clear_permissions_except_lbw(&mut self) {
+        self.pin_uv_auth_token_state
+            .clear_permissions_except(PinPermission::LargeBlobWrite as u8);
+    }
+
     #[cfg(feature = "with_ctap2_1")]
     pub fn has_permission_for_rp_id(&mut self, rp_id: &str) -> Result<(), Ctap2StatusCode> {
-        if let Some(permissions_rp_id) = &self.permissions_rp_id {
+        if let Some(permissions_rp_id) = self.pin_uv_auth_token_state.permissions_rp_id() {
             if rp_id != permissions_rp_id {
                 return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
             }
         } else {
-            self.permissions_rp_id = Some(String::from(rp_id));
+            self.pin_uv_auth_token_state
+                .set_permissions_rp_id(String::from(rp_id));
+        }
+        Ok(())
+    }
+
+    /// Checks that the token is not bound to any relying party.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn has_no_permission_rp_id(&self) -> Result<(), Ctap2StatusCode> {
+        if self.pin_uv_auth_token_state.permissions_rp_id().is_some() {
+            return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+        }
+        Ok(())
+    }
+
+    /// Checks that the token is either not bound, or bound to the relying party with this hash.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn has_no_or_rp_id_hash_permission(
+        &self,
+        rp_id_hash: &[u8],
+    ) -> Result<(), Ctap2StatusCode> {
+        if let Some(permissions_rp_id) = self.pin_uv_auth_token_state.permissions_rp_id() {
+            if rp_id_hash != Sha256::hash(permissions_rp_id.as_bytes()) {
+                return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+            }
         }
         Ok(())
     }
 
     #[cfg(test)]
     pub fn new_test(
-        key_agreement_key: crypto::ecdh::SecKey,
+        key_agreement_key: <KeyAgreement as Ecdh>::SecKey,
         pin_uv_auth_token: [u8; 32],
     ) -> PinProtocolV1 {
+        // Tests use a 32768 Hz clock that starts at zero.
+        let mut pin_uv_auth_token_state = PinUvAuthTokenState::new();
+        pin_uv_auth_token_state.begin_using_pin_uv_auth_token(false, ClockValue::new(0, 32768));
+        #[cfg(feature = "with_ctap2_1")]
+        pin_uv_auth_token_state.set_permissions(0xFF, None);
         PinProtocolV1 {
             key_agreement_key,
             pin_uv_auth_token,
+            pin_uv_auth_token_state,
             consecutive_pin_mismatches: 0,
-            #[cfg(feature = "with_ctap2_1")]
-            permissions: 0xFF,
-            #[cfg(feature = "with_ctap2_1")]
-            permissions_rp_id: None,
         }
     }
 }
@@ -642,6 +1009,9 @@ mod test {
     use super::*;
     use crypto::rng256::ThreadRng256;
 
+    const CLOCK_FREQUENCY_HZ: usize = 32768;
+    const DUMMY_CLOCK_VALUE: ClockValue = ClockValue::new(0, CLOCK_FREQUENCY_HZ);
+
     // Stores a PIN hash corresponding to the dummy PIN "1234".
     fn set_standard_pin(persistent_store: &mut PersistentStore) {
         let mut pin = [0u8; 64];
@@ -711,9 +1081,11 @@ mod test {
             0xC4, 0x12,
         ];
         persistent_store.set_pin_hash(&pin_hash).unwrap();
-        let shared_secret = [0x88; 32];
-        let aes_enc_key = crypto::aes256::EncryptionKey::new(&shared_secret);
-        let aes_dec_key = crypto::aes256::DecryptionKey::new(&aes_enc_key);
+        let shared_secret = SharedSecret {
+            protocol: &PinUvAuthProtocolOne,
+            hmac_key: [0x88; 32],
+            aes_key: [0x88; 32],
+        };
 
         let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
         let pin_hash_enc = vec![
@@ -724,7 +1096,7 @@ mod test {
             pin_protocol_v1.verify_pin_hash_enc(
                 &mut rng,
                 &mut persistent_store,
-                &aes_dec_key,
+                &shared_secret,
                 pin_hash_enc
             ),
             Ok(())
@@ -735,7 +1107,7 @@ mod test {
             pin_protocol_v1.verify_pin_hash_enc(
                 &mut rng,
                 &mut persistent_store,
-                &aes_dec_key,
+                &shared_secret,
                 pin_hash_enc
             ),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
@@ -750,7 +1122,7 @@ mod test {
             pin_protocol_v1.verify_pin_hash_enc(
                 &mut rng,
                 &mut persistent_store,
-                &aes_dec_key,
+                &shared_secret,
                 pin_hash_enc
             ),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_BLOCKED)
@@ -762,7 +1134,7 @@ mod test {
             pin_protocol_v1.verify_pin_hash_enc(
                 &mut rng,
                 &mut persistent_store,
-                &aes_dec_key,
+                &shared_secret,
                 pin_hash_enc
             ),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
@@ -773,13 +1145,90 @@ mod test {
             pin_protocol_v1.verify_pin_hash_enc(
                 &mut rng,
                 &mut persistent_store,
-                &aes_dec_key,
+                &shared_secret,
                 pin_hash_enc
             ),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
         );
     }
 
+    #[test]
+    fn test_verify_pin_hash_enc_retries() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        set_standard_pin(&mut persistent_store);
+        let shared_secret = SharedSecret {
+            protocol: &PinUvAuthProtocolOne,
+            hmac_key: [0x88; 32],
+            aes_key: [0x88; 32],
+        };
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let initial_retries = persistent_store.pin_retries().unwrap();
+
+        // Malformed PIN hashes count as mismatches.
+        for pin_hash_enc in &[vec![0xEE; 16], vec![0x77; PIN_AUTH_LENGTH - 1]] {
+            assert_eq!(
+                pin_protocol_v1.verify_pin_hash_enc(
+                    &mut rng,
+                    &mut persistent_store,
+                    &shared_secret,
+                    pin_hash_enc.clone()
+                ),
+                Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
+            );
+        }
+        assert_eq!(
+            pin_protocol_v1.verify_pin_hash_enc(
+                &mut rng,
+                &mut persistent_store,
+                &shared_secret,
+                vec![0xEE; 16]
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_BLOCKED)
+        );
+        assert_eq!(persistent_store.pin_retries(), Ok(initial_retries - 3));
+
+        // Until the power cycle, even the correct PIN is refused, without using retries.
+        assert_eq!(
+            pin_protocol_v1.verify_pin_hash_enc(
+                &mut rng,
+                &mut persistent_store,
+                &shared_secret,
+                encrypt_standard_pin_hash(&shared_secret.aes_key)
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_BLOCKED)
+        );
+        assert_eq!(persistent_store.pin_retries(), Ok(initial_retries - 3));
+
+        // After a power cycle, the correct PIN resets the retries.
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        assert_eq!(
+            pin_protocol_v1.verify_pin_hash_enc(
+                &mut rng,
+                &mut persistent_store,
+                &shared_secret,
+                encrypt_standard_pin_hash(&shared_secret.aes_key)
+            ),
+            Ok(())
+        );
+        assert_eq!(persistent_store.pin_retries(), Ok(initial_retries));
+
+        // Running out of retries blocks the PIN permanently.
+        for _ in 1..initial_retries {
+            persistent_store.decr_pin_retries().unwrap();
+        }
+        assert_eq!(
+            pin_protocol_v1.verify_pin_hash_enc(
+                &mut rng,
+                &mut persistent_store,
+                &shared_secret,
+                vec![0xEE; 16]
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_BLOCKED)
+        );
+        assert_eq!(persistent_store.pin_retries(), Ok(0));
+    }
+
     #[test]
     fn test_process_get_pin_retries() {
         let mut rng = ThreadRng256 {};
@@ -789,6 +1238,7 @@ mod test {
             key_agreement: None,
             pin_token: None,
             retries: Some(persistent_store.pin_retries().unwrap() as u64),
+            uv_retries: None,
         });
         assert_eq!(
             pin_protocol_v1.process_get_pin_retries(&persistent_store),
@@ -805,6 +1255,7 @@ mod test {
             key_agreement: Some(CoseKey::from(pk)),
             pin_token: None,
             retries: None,
+            uv_retries: None,
         });
         assert_eq!(
             pin_protocol_v1.process_get_key_agreement(),
@@ -826,6 +1277,7 @@ mod test {
             pin_protocol_v1.process_set_pin(
                 &mut persistent_store,
                 key_agreement,
+                1,
                 pin_auth,
                 new_pin_enc
             ),
@@ -852,6 +1304,7 @@ mod test {
                 &mut rng,
                 &mut persistent_store,
                 key_agreement.clone(),
+                1,
                 pin_auth.clone(),
                 new_pin_enc.clone(),
                 pin_hash_enc.clone()
@@ -865,6 +1318,7 @@ mod test {
                 &mut rng,
                 &mut persistent_store,
                 key_agreement.clone(),
+                1,
                 pin_auth.clone(),
                 new_pin_enc.clone(),
                 bad_pin_hash_enc
@@ -880,6 +1334,7 @@ mod test {
                 &mut rng,
                 &mut persistent_store,
                 key_agreement,
+                1,
                 pin_auth,
                 new_pin_enc,
                 pin_hash_enc,
@@ -903,7 +1358,9 @@ mod test {
                 &mut rng,
                 &mut persistent_store,
                 key_agreement.clone(),
-                pin_hash_enc
+                1,
+                pin_hash_enc,
+                DUMMY_CLOCK_VALUE,
             )
             .is_ok());
 
@@ -913,12 +1370,61 @@ mod test {
                 &mut rng,
                 &mut persistent_store,
                 key_agreement,
-                pin_hash_enc
+                1,
+                pin_hash_enc,
+                DUMMY_CLOCK_VALUE,
             ),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
         );
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_get_pin_token_force_pin_change() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        set_standard_pin(&mut persistent_store);
+        assert_eq!(persistent_store.force_pin_change(), Ok(()));
+        let pin_retries = persistent_store.pin_retries().unwrap();
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let pk = pin_protocol_v1.key_agreement_key.genpk();
+        let shared_secret = pin_protocol_v1.key_agreement_key.exchange_x_sha256(&pk);
+        let key_agreement = CoseKey::from(pk);
+        let pin_hash_enc = encrypt_standard_pin_hash(&shared_secret);
+        assert_eq!(
+            pin_protocol_v1.process_get_pin_token(
+                &mut rng,
+                &mut persistent_store,
+                key_agreement.clone(),
+                1,
+                pin_hash_enc.clone(),
+                DUMMY_CLOCK_VALUE,
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
+        );
+        assert_eq!(
+            pin_protocol_v1.process_get_pin_uv_auth_token_using_pin_with_permissions(
+                &mut rng,
+                &mut persistent_store,
+                key_agreement.clone(),
+                1,
+                pin_hash_enc.clone(),
+                0x03,
+                Some(String::from("example.com")),
+                DUMMY_CLOCK_VALUE,
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
+        );
+        // The correct PIN was given, so the retries are not decremented.
+        assert_eq!(persistent_store.pin_retries(), Ok(pin_retries));
+
+        // A new PIN lifts the restriction.
+        let mut pin_hash = [0u8; 16];
+        pin_hash.copy_from_slice(&Sha256::hash(b"5678")[..16]);
+        persistent_store.set_pin_hash(&pin_hash).unwrap();
+        assert!(!persistent_store.has_force_pin_change().unwrap());
+    }
+
     #[cfg(feature = "with_ctap2_1")]
     #[test]
     fn test_process_get_pin_uv_auth_token_using_pin_with_permissions() {
@@ -935,15 +1441,17 @@ mod test {
                 &mut rng,
                 &mut persistent_store,
                 key_agreement.clone(),
+                1,
                 pin_hash_enc.clone(),
                 0x03,
                 Some(String::from("example.com")),
+                DUMMY_CLOCK_VALUE,
             )
             .is_ok());
-        assert_eq!(pin_protocol_v1.permissions, 0x03);
+        assert!(pin_protocol_v1.pin_uv_auth_token_state.has_permission(0x03));
         assert_eq!(
-            pin_protocol_v1.permissions_rp_id,
-            Some(String::from("example.com"))
+            pin_protocol_v1.pin_uv_auth_token_state.permissions_rp_id(),
+            Some("example.com")
         );
 
         assert_eq!(
@@ -951,9 +1459,11 @@ mod test {
                 &mut rng,
                 &mut persistent_store,
                 key_agreement.clone(),
+                1,
                 pin_hash_enc.clone(),
                 0x00,
                 Some(String::from("example.com")),
+                DUMMY_CLOCK_VALUE,
             ),
             Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
         );
@@ -963,9 +1473,11 @@ mod test {
                 &mut rng,
                 &mut persistent_store,
                 key_agreement.clone(),
+                1,
                 pin_hash_enc.clone(),
                 0x03,
                 None,
+                DUMMY_CLOCK_VALUE,
             ),
             Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
         );
@@ -976,14 +1488,71 @@ mod test {
                 &mut rng,
                 &mut persistent_store,
                 key_agreement,
+                1,
                 pin_hash_enc,
                 0x03,
                 Some(String::from("example.com")),
+                DUMMY_CLOCK_VALUE,
             ),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
         );
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_get_pin_uv_auth_token_using_uv_with_permissions() {
+        let mut rng = ThreadRng256 {};
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let pk = pin_protocol_v1.key_agreement_key.genpk();
+        let key_agreement = CoseKey::from(pk);
+        assert!(pin_protocol_v1
+            .process_get_pin_uv_auth_token_using_uv_with_permissions(
+                &mut rng,
+                key_agreement.clone(),
+                1,
+                PinPermission::BioEnrollment as u8,
+                None,
+                DUMMY_CLOCK_VALUE,
+                || Ok(()),
+            )
+            .is_ok());
+        assert_eq!(
+            pin_protocol_v1.has_permission(PinPermission::BioEnrollment),
+            Ok(())
+        );
+        assert_eq!(
+            pin_protocol_v1.has_permission(PinPermission::MakeCredential),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        assert!(pin_protocol_v1.has_user_verified_flag());
+
+        assert_eq!(
+            pin_protocol_v1.process_get_pin_uv_auth_token_using_uv_with_permissions(
+                &mut rng,
+                key_agreement.clone(),
+                1,
+                0x00,
+                None,
+                DUMMY_CLOCK_VALUE,
+                || Ok(()),
+            ),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        assert_eq!(
+            pin_protocol_v1.process_get_pin_uv_auth_token_using_uv_with_permissions(
+                &mut rng,
+                key_agreement,
+                1,
+                0x03,
+                Some(String::from("example.com")),
+                DUMMY_CLOCK_VALUE,
+                || Err(Ctap2StatusCode::CTAP2_ERR_UV_INVALID),
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_UV_INVALID)
+        );
+    }
+
     #[cfg(feature = "with_ctap2_1")]
     #[test]
     fn test_process_set_min_pin_length() {
@@ -996,12 +1565,11 @@ mod test {
             0x94, 0x86, 0xEF, 0x4C, 0xB3, 0x84, 0x2C, 0x85, 0x72, 0x02, 0xBF, 0xE4, 0x36, 0x22,
             0xFE, 0xC9,
         ];
-        // TODO(kaczmarczyck) implement test for the min PIN length extension
-        // https://github.com/google/OpenSK/issues/129
         let response = pin_protocol_v1.process_set_min_pin_length(
             &mut persistent_store,
             min_pin_length,
             None,
+            1,
             Some(pin_auth.clone()),
         );
         assert_eq!(response, Ok(()));
@@ -1010,6 +1578,7 @@ mod test {
             &mut persistent_store,
             7,
             None,
+            1,
             Some(pin_auth),
         );
         assert_eq!(
@@ -1019,6 +1588,55 @@ mod test {
         assert_eq!(persistent_store.min_pin_length().unwrap(), min_pin_length);
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_process_set_min_pin_length_rp_ids() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let min_pin_length = 8;
+        let min_pin_length_rp_ids = vec![String::from("example.com")];
+        pin_protocol_v1.pin_uv_auth_token = [0x55; PIN_TOKEN_LENGTH];
+        pin_protocol_v1
+            .pin_uv_auth_token_state
+            .begin_using_pin_uv_auth_token(false, DUMMY_CLOCK_VALUE);
+        set_standard_pin(&mut persistent_store);
+
+        let mut message = vec![0xFF; 32];
+        message.extend(&[0x06, 0x08]);
+        message.extend(&[min_pin_length as u8, 0x00, 0x00, 0x00]);
+        assert!(cbor::write(
+            cbor_array_vec!(min_pin_length_rp_ids.clone()),
+            &mut message
+        ));
+        let pin_auth =
+            hmac_256::<Sha256>(&pin_protocol_v1.pin_uv_auth_token, &message)[..16].to_vec();
+
+        // The PIN auth computed without the RP IDs is rejected.
+        let response = pin_protocol_v1.process_set_min_pin_length(
+            &mut persistent_store,
+            min_pin_length,
+            Some(min_pin_length_rp_ids.clone()),
+            1,
+            Some(vec![0x00; 16]),
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID));
+
+        let response = pin_protocol_v1.process_set_min_pin_length(
+            &mut persistent_store,
+            min_pin_length,
+            Some(min_pin_length_rp_ids.clone()),
+            1,
+            Some(pin_auth),
+        );
+        assert_eq!(response, Ok(()));
+        assert_eq!(persistent_store.min_pin_length().unwrap(), min_pin_length);
+        assert_eq!(
+            persistent_store.min_pin_length_rp_ids().unwrap(),
+            min_pin_length_rp_ids
+        );
+    }
+
     #[test]
     fn test_process() {
         let mut rng = ThreadRng256 {};
@@ -1041,11 +1659,16 @@ mod test {
             permissions_rp_id: None,
         };
         assert!(pin_protocol_v1
-            .process_subcommand(&mut rng, &mut persistent_store, client_pin_params)
+            .process_subcommand(
+                &mut rng,
+                &mut persistent_store,
+                client_pin_params,
+                DUMMY_CLOCK_VALUE
+            )
             .is_ok());
 
         let client_pin_params = AuthenticatorClientPinParameters {
-            pin_protocol: 2,
+            pin_protocol: 3,
             sub_command: ClientPinSubCommand::GetPinRetries,
             key_agreement: None,
             pin_auth: None,
@@ -1065,16 +1688,23 @@ mod test {
         #[cfg(feature = "with_ctap2_1")]
         let error_code = Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER;
         assert_eq!(
-            pin_protocol_v1.process_subcommand(&mut rng, &mut persistent_store, client_pin_params),
+            pin_protocol_v1.process_subcommand(
+                &mut rng,
+                &mut persistent_store,
+                client_pin_params,
+                DUMMY_CLOCK_VALUE
+            ),
             Err(error_code)
         );
     }
 
     #[test]
     fn test_decrypt_pin() {
-        let shared_secret = [0x88; 32];
-        let aes_enc_key = crypto::aes256::EncryptionKey::new(&shared_secret);
-        let aes_dec_key = crypto::aes256::DecryptionKey::new(&aes_enc_key);
+        let shared_secret = SharedSecret {
+            protocol: &PinUvAuthProtocolOne,
+            hmac_key: [0x88; 32],
+            aes_key: [0x88; 32],
+        };
 
         // "1234"
         let new_pin_enc = vec![
@@ -1085,7 +1715,7 @@ mod test {
             0x18, 0x35, 0x06, 0x66, 0x97, 0x84, 0x68, 0xC2,
         ];
         assert_eq!(
-            decrypt_pin(&aes_dec_key, new_pin_enc),
+            decrypt_pin(&shared_secret, new_pin_enc),
             Some(b"1234".to_vec()),
         );
 
@@ -1098,26 +1728,28 @@ mod test {
             0x7C, 0xC7, 0x2D, 0x43, 0x74, 0x4C, 0x1D, 0x7E,
         ];
         assert_eq!(
-            decrypt_pin(&aes_dec_key, new_pin_enc),
+            decrypt_pin(&shared_secret, new_pin_enc),
             Some(b"123".to_vec()),
         );
 
         // Encrypted PIN is too short.
         let new_pin_enc = vec![0x44; 63];
-        assert_eq!(decrypt_pin(&aes_dec_key, new_pin_enc), None,);
+        assert_eq!(decrypt_pin(&shared_secret, new_pin_enc), None,);
 
         // Encrypted PIN is too long.
         let new_pin_enc = vec![0x44; 65];
-        assert_eq!(decrypt_pin(&aes_dec_key, new_pin_enc), None,);
+        assert_eq!(decrypt_pin(&shared_secret, new_pin_enc), None,);
     }
 
     #[test]
     fn test_check_and_store_new_pin() {
         let mut rng = ThreadRng256 {};
         let mut persistent_store = PersistentStore::new(&mut rng);
-        let shared_secret = [0x88; 32];
-        let aes_enc_key = crypto::aes256::EncryptionKey::new(&shared_secret);
-        let aes_dec_key = crypto::aes256::DecryptionKey::new(&aes_enc_key);
+        let shared_secret = SharedSecret {
+            protocol: &PinUvAuthProtocolOne,
+            hmac_key: [0x88; 32],
+            aes_key: [0x88; 32],
+        };
 
         let test_cases = vec![
             // Accept PIN "1234".
@@ -1137,12 +1769,27 @@ mod test {
                 vec![0x30; 64],
                 Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION),
             ),
+            // Accept a PIN of 4 euro signs, 4 code points in 12 bytes.
+            (
+                "\u{20AC}\u{20AC}\u{20AC}\u{20AC}".as_bytes().to_vec(),
+                Ok(()),
+            ),
+            // Reject a PIN of 3 accented letters, 3 code points in 6 bytes.
+            (
+                "\u{E9}\u{E9}\u{E9}".as_bytes().to_vec(),
+                Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION),
+            ),
+            // Reject PINs that are not UTF-8.
+            (
+                vec![0x31, 0x32, 0x33, 0xFF],
+                Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION),
+            ),
         ];
         for (pin, result) in test_cases {
             let old_pin_hash = persistent_store.pin_hash().unwrap();
-            let new_pin_enc = encrypt_pin(&shared_secret, pin);
+            let new_pin_enc = encrypt_pin(&shared_secret.aes_key, pin);
             assert_eq!(
-                check_and_store_new_pin(&mut persistent_store, &aes_dec_key, new_pin_enc),
+                check_and_store_new_pin(&mut persistent_store, &shared_secret, new_pin_enc),
                 result
             );
             if result.is_ok() {
@@ -1153,6 +1800,48 @@ mod test {
         }
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_check_and_store_new_pin_min_pin_length() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        persistent_store.set_min_pin_length(6).unwrap();
+        let shared_secret = SharedSecret {
+            protocol: &PinUvAuthProtocolOne,
+            hmac_key: [0x88; 32],
+            aes_key: [0x88; 32],
+        };
+
+        // 5 code points are too short, even in 10 bytes.
+        let new_pin_enc = encrypt_pin(
+            &shared_secret.aes_key,
+            "\u{E9}\u{E9}\u{E9}\u{E9}\u{E9}".as_bytes().to_vec(),
+        );
+        assert_eq!(
+            check_and_store_new_pin(&mut persistent_store, &shared_secret, new_pin_enc),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
+        );
+        let new_pin_enc = encrypt_pin(
+            &shared_secret.aes_key,
+            "\u{E9}\u{E9}\u{E9}\u{E9}\u{E9}\u{E9}".as_bytes().to_vec(),
+        );
+        assert_eq!(
+            check_and_store_new_pin(&mut persistent_store, &shared_secret, new_pin_enc),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_pin_code_point_length() {
+        assert_eq!(pin_code_point_length(b"1234"), Some(4));
+        assert_eq!(
+            pin_code_point_length("1\u{20AC}3\u{E9}".as_bytes()),
+            Some(4)
+        );
+        assert_eq!(pin_code_point_length("\u{1F511}".as_bytes()), Some(1));
+        assert_eq!(pin_code_point_length(&[0xC3]), None);
+    }
+
     #[test]
     fn test_verify_pin_auth() {
         let hmac_key = [0x88; 16];
@@ -1160,27 +1849,39 @@ mod test {
             0x88, 0x09, 0x41, 0x13, 0xF7, 0x97, 0x32, 0x0B, 0x3E, 0xD9, 0xBC, 0x76, 0x4F, 0x18,
             0x56, 0x5D,
         ];
-        assert!(verify_pin_auth(&hmac_key, &[], &pin_auth));
-        assert!(!verify_pin_auth(&hmac_key, &[0x00], &pin_auth));
+        assert!(verify_pin_auth(&hmac_key, &[], &pin_auth, 1));
+        assert!(!verify_pin_auth(&hmac_key, &[0x00], &pin_auth, 1));
+        // PIN protocol 2 needs the full HMAC output.
+        assert!(!verify_pin_auth(&hmac_key, &[], &pin_auth, 2));
+        let pin_auth = hmac_256::<Sha256>(&hmac_key, &[]);
+        assert!(verify_pin_auth(&hmac_key, &[], &pin_auth, 2));
+        assert!(!verify_pin_auth(&hmac_key, &[0x00], &pin_auth, 2));
+        assert!(!verify_pin_auth(&hmac_key, &[], &pin_auth, 1));
+        assert!(!verify_pin_auth(&hmac_key, &[], &pin_auth, 3));
     }
 
     #[test]
     fn test_encrypt_hmac_secret_output() {
-        let shared_secret = [0x55; 32];
+        let mut rng = ThreadRng256 {};
+        let shared_secret = SharedSecret {
+            protocol: &PinUvAuthProtocolOne,
+            hmac_key: [0x55; 32],
+            aes_key: [0x55; 32],
+        };
         let salt_enc = [0x5E; 32];
         let cred_random = [0xC9; 32];
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random);
+        let output = encrypt_hmac_secret_output(&mut rng, &shared_secret, &salt_enc, &cred_random);
         assert_eq!(output.unwrap().len(), 32);
 
         let salt_enc = [0x5E; 48];
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random);
+        let output = encrypt_hmac_secret_output(&mut rng, &shared_secret, &salt_enc, &cred_random);
         assert_eq!(
             output,
             Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION)
         );
 
         let salt_enc = [0x5E; 64];
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random);
+        let output = encrypt_hmac_secret_output(&mut rng, &shared_secret, &salt_enc, &cred_random);
         assert_eq!(output.unwrap().len(), 64);
 
         let mut salt_enc = [0x00; 32];
@@ -1192,58 +1893,276 @@ mod test {
         let expected_output1 = hmac_256::<Sha256>(&cred_random, &salt1);
         let expected_output2 = hmac_256::<Sha256>(&cred_random, &salt2);
 
-        let salt_enc1 = encrypt_message(&shared_secret, &salt1);
+        let salt_enc1 = encrypt_message(&shared_secret.aes_key, &salt1);
         salt_enc.copy_from_slice(salt_enc1.as_slice());
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random).unwrap();
-        let output_dec = decrypt_message(&shared_secret, &output);
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret, &salt_enc, &cred_random).unwrap();
+        let output_dec = decrypt_message(&shared_secret.aes_key, &output);
         assert_eq!(&output_dec, &expected_output1);
 
-        let salt_enc2 = &encrypt_message(&shared_secret, &salt2);
+        let salt_enc2 = &encrypt_message(&shared_secret.aes_key, &salt2);
         salt_enc.copy_from_slice(salt_enc2.as_slice());
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random).unwrap();
-        let output_dec = decrypt_message(&shared_secret, &output);
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret, &salt_enc, &cred_random).unwrap();
+        let output_dec = decrypt_message(&shared_secret.aes_key, &output);
         assert_eq!(&output_dec, &expected_output2);
 
         let mut salt_enc = [0x00; 64];
         let mut salt12 = [0x00; 64];
         salt12[..32].copy_from_slice(&salt1);
         salt12[32..].copy_from_slice(&salt2);
-        let salt_enc12 = encrypt_message(&shared_secret, &salt12);
+        let salt_enc12 = encrypt_message(&shared_secret.aes_key, &salt12);
         salt_enc.copy_from_slice(salt_enc12.as_slice());
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random).unwrap();
-        let output_dec = decrypt_message(&shared_secret, &output);
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret, &salt_enc, &cred_random).unwrap();
+        let output_dec = decrypt_message(&shared_secret.aes_key, &output);
         assert_eq!(&output_dec[..32], &expected_output1);
         assert_eq!(&output_dec[32..], &expected_output2);
 
         let mut salt_enc = [0x00; 64];
         let mut salt02 = [0x00; 64];
         salt02[32..].copy_from_slice(&salt2);
-        let salt_enc02 = encrypt_message(&shared_secret, &salt02);
+        let salt_enc02 = encrypt_message(&shared_secret.aes_key, &salt02);
         salt_enc.copy_from_slice(salt_enc02.as_slice());
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random).unwrap();
-        let output_dec = decrypt_message(&shared_secret, &output);
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret, &salt_enc, &cred_random).unwrap();
+        let output_dec = decrypt_message(&shared_secret.aes_key, &output);
         assert_eq!(&output_dec[32..], &expected_output2);
 
         let mut salt_enc = [0x00; 64];
         let mut salt10 = [0x00; 64];
         salt10[..32].copy_from_slice(&salt1);
-        let salt_enc10 = encrypt_message(&shared_secret, &salt10);
+        let salt_enc10 = encrypt_message(&shared_secret.aes_key, &salt10);
         salt_enc.copy_from_slice(salt_enc10.as_slice());
-        let output = encrypt_hmac_secret_output(&shared_secret, &salt_enc, &cred_random).unwrap();
-        let output_dec = decrypt_message(&shared_secret, &output);
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret, &salt_enc, &cred_random).unwrap();
+        let output_dec = decrypt_message(&shared_secret.aes_key, &output);
         assert_eq!(&output_dec[..32], &expected_output1);
     }
 
+    #[test]
+    fn test_pin_uv_auth_protocol_verify() {
+        let hmac_key = [0x88; 32];
+        let message = [0x55; 20];
+        let hmac = hmac_256::<Sha256>(&hmac_key, &message);
+        let pin_auths: [(u64, &[u8]); 2] = [(1, &hmac[..PIN_AUTH_LENGTH]), (2, &hmac[..])];
+        for &(version, pin_auth) in &pin_auths {
+            let protocol = select_pin_uv_auth_protocol(version).unwrap();
+            assert!(protocol.verify(&hmac_key, &message, pin_auth));
+            assert!(!protocol.verify(&hmac_key, &message[1..], pin_auth));
+            assert!(!protocol.verify(&[0x77; 32], &message, pin_auth));
+        }
+        // Each protocol only accepts its own pinUvAuthParam length.
+        assert!(!PinUvAuthProtocolOne.verify(&hmac_key, &message, &hmac));
+        assert!(!PinUvAuthProtocolTwo.verify(&hmac_key, &message, &hmac[..PIN_AUTH_LENGTH]));
+        assert!(select_pin_uv_auth_protocol(3).is_err());
+    }
+
+    #[test]
+    fn test_shared_secret_v2_key_derivation() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let platform_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let key_agreement = CoseKey::from(platform_key.genpk());
+        let shared_secret = SharedSecret::new(&key_agreement_key, key_agreement, 2).unwrap();
+
+        let z = platform_key.exchange_x(&key_agreement_key.genpk());
+        assert_eq!(
+            shared_secret.hmac_key,
+            hkdf_empty_salt_256::<Sha256>(&z, b"CTAP2 HMAC key")
+        );
+        assert_eq!(
+            shared_secret.aes_key,
+            hkdf_empty_salt_256::<Sha256>(&z, b"CTAP2 AES key")
+        );
+        assert_ne!(shared_secret.hmac_key, shared_secret.aes_key);
+    }
+
+    #[test]
+    fn test_shared_secret_unsupported_protocol() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let key_agreement = CoseKey::from(key_agreement_key.genpk());
+        assert!(SharedSecret::new(&key_agreement_key, key_agreement, 3).is_err());
+    }
+
+    #[test]
+    fn test_shared_secret_encrypt_decrypt() {
+        let mut rng = ThreadRng256 {};
+        let message = [0x5A; 32];
+        for pin_uv_auth_protocol in 1..=2 {
+            let shared_secret = SharedSecret {
+                protocol: select_pin_uv_auth_protocol(pin_uv_auth_protocol).unwrap(),
+                hmac_key: [0x88; 32],
+                aes_key: [0x99; 32],
+            };
+            let ciphertext = shared_secret.encrypt(&mut rng, &message);
+            assert_eq!(shared_secret.decrypt(&ciphertext), Some(message.to_vec()));
+            // A length that isn't a multiple of the block size is rejected.
+            assert_eq!(shared_secret.decrypt(&ciphertext[..31]), None);
+        }
+
+        let shared_secret_v1 = SharedSecret {
+            protocol: &PinUvAuthProtocolOne,
+            hmac_key: [0x88; 32],
+            aes_key: [0x99; 32],
+        };
+        let ciphertext = shared_secret_v1.encrypt(&mut rng, &message);
+        assert_eq!(ciphertext, encrypt_message(&[0x99; 32], &message));
+
+        // PIN protocol 2 prepends a random IV.
+        let shared_secret_v2 = SharedSecret {
+            protocol: &PinUvAuthProtocolTwo,
+            hmac_key: [0x88; 32],
+            aes_key: [0x99; 32],
+        };
+        let ciphertext1 = shared_secret_v2.encrypt(&mut rng, &message);
+        let ciphertext2 = shared_secret_v2.encrypt(&mut rng, &message);
+        assert_eq!(ciphertext1.len(), 48);
+        assert_ne!(ciphertext1, ciphertext2);
+        assert_eq!(shared_secret_v2.decrypt(&ciphertext1[..15]), None);
+    }
+
+    #[test]
+    fn test_process_set_pin_and_get_pin_token_v2() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let pk = pin_protocol_v1.key_agreement_key.genpk();
+        let key_agreement = CoseKey::from(pk);
+        let shared_secret =
+            SharedSecret::new(&pin_protocol_v1.key_agreement_key, key_agreement.clone(), 2)
+                .unwrap();
+
+        let mut padded_pin = [0u8; 64];
+        padded_pin[..4].copy_from_slice(b"1234");
+        let new_pin_enc = shared_secret.encrypt(&mut rng, &padded_pin);
+        // The truncated PIN auth of protocol 1 is not accepted.
+        let pin_auth = hmac_256::<Sha256>(&shared_secret.hmac_key, &new_pin_enc[..]);
+        assert_eq!(
+            pin_protocol_v1.process_set_pin(
+                &mut persistent_store,
+                key_agreement.clone(),
+                2,
+                pin_auth[..16].to_vec(),
+                new_pin_enc.clone()
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        assert_eq!(
+            pin_protocol_v1.process_set_pin(
+                &mut persistent_store,
+                key_agreement.clone(),
+                2,
+                pin_auth.to_vec(),
+                new_pin_enc
+            ),
+            Ok(())
+        );
+
+        let pin_hash = Sha256::hash(b"1234");
+        let pin_hash_enc = shared_secret.encrypt(&mut rng, &pin_hash[..16]);
+        let response = pin_protocol_v1
+            .process_get_pin_token(
+                &mut rng,
+                &mut persistent_store,
+                key_agreement,
+                2,
+                pin_hash_enc,
+                DUMMY_CLOCK_VALUE,
+            )
+            .unwrap();
+        let pin_token = shared_secret.decrypt(&response.pin_token.unwrap());
+        assert_eq!(pin_token, Some(pin_protocol_v1.pin_uv_auth_token.to_vec()));
+    }
+
+    #[test]
+    fn test_verify_pin_auth_token() {
+        let mut rng = ThreadRng256 {};
+        let pin_uv_auth_token = [0x55; PIN_TOKEN_LENGTH];
+        let mut pin_protocol_v1 =
+            PinProtocolV1::new_test(crypto::ecdh::SecKey::gensk(&mut rng), pin_uv_auth_token);
+        let message = [0xAA; 32];
+        let pin_auth = hmac_256::<Sha256>(&pin_uv_auth_token, &message);
+        assert!(pin_protocol_v1.verify_pin_auth_token(&message, &pin_auth[..16], 1));
+        assert!(!pin_protocol_v1.verify_pin_auth_token(&message, &pin_auth[..16], 2));
+        assert!(pin_protocol_v1.verify_pin_auth_token(&message, &pin_auth, 2));
+        assert!(!pin_protocol_v1.verify_pin_auth_token(&message, &pin_auth, 1));
+    }
+
+    #[test]
+    fn test_verify_pin_auth_token_expired() {
+        let mut rng = ThreadRng256 {};
+        let pin_uv_auth_token = [0x55; PIN_TOKEN_LENGTH];
+        let mut pin_protocol_v1 =
+            PinProtocolV1::new_test(crypto::ecdh::SecKey::gensk(&mut rng), pin_uv_auth_token);
+        let message = [0xAA; 32];
+        let pin_auth = hmac_256::<Sha256>(&pin_uv_auth_token, &message);
+        // The token was never used, so it expires after the initial usage time limit.
+        pin_protocol_v1.update_timeouts(ClockValue::new(
+            31 * CLOCK_FREQUENCY_HZ as isize,
+            CLOCK_FREQUENCY_HZ,
+        ));
+        assert!(!pin_protocol_v1.has_user_verified_flag());
+        assert!(!pin_protocol_v1.verify_pin_auth_token(&message, &pin_auth[..16], 1));
+    }
+
+    #[test]
+    fn test_reset_stops_using_pin_uv_auth_token() {
+        let mut rng = ThreadRng256 {};
+        let pin_uv_auth_token = [0x55; PIN_TOKEN_LENGTH];
+        let mut pin_protocol_v1 =
+            PinProtocolV1::new_test(crypto::ecdh::SecKey::gensk(&mut rng), pin_uv_auth_token);
+        assert!(pin_protocol_v1.has_user_verified_flag());
+        pin_protocol_v1.reset(&mut rng);
+        assert!(!pin_protocol_v1.has_user_verified_flag());
+    }
+
+    #[test]
+    fn test_encrypt_hmac_secret_output_v2() {
+        let mut rng = ThreadRng256 {};
+        let shared_secret = SharedSecret {
+            protocol: &PinUvAuthProtocolTwo,
+            hmac_key: [0x55; 32],
+            aes_key: [0x66; 32],
+        };
+        let cred_random = [0xC9; 32];
+        let salt1 = [0x01; 32];
+        let expected_output1 = hmac_256::<Sha256>(&cred_random, &salt1);
+
+        let salt_enc = shared_secret.encrypt(&mut rng, &salt1);
+        assert_eq!(salt_enc.len(), 48);
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret, &salt_enc, &cred_random).unwrap();
+        assert_eq!(output.len(), 48);
+        assert_eq!(
+            shared_secret.decrypt(&output),
+            Some(expected_output1.to_vec())
+        );
+
+        // Dropping the IV leaves a single block, which is not a valid salt.
+        let output =
+            encrypt_hmac_secret_output(&mut rng, &shared_secret, &salt_enc[16..], &cred_random);
+        assert_eq!(
+            output,
+            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_EXTENSION)
+        );
+    }
+
     #[cfg(feature = "with_ctap2_1")]
     #[test]
     fn test_has_permission() {
         let mut rng = ThreadRng256 {};
         let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
-        pin_protocol_v1.permissions = 0x7F;
+        pin_protocol_v1
+            .pin_uv_auth_token_state
+            .set_permissions(0x7F, None);
         for permission in PinPermission::into_enum_iter() {
             assert_eq!(pin_protocol_v1.has_permission(permission), Ok(()));
         }
-        pin_protocol_v1.permissions = 0x00;
+        pin_protocol_v1
+            .pin_uv_auth_token_state
+            .set_permissions(0x00, None);
         for permission in PinPermission::into_enum_iter() {
             assert_eq!(
                 pin_protocol_v1.has_permission(permission),
@@ -1252,6 +2171,29 @@ mod test {
         }
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_clear_permissions_except_lbw() {
+        let mut rng = ThreadRng256 {};
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        pin_protocol_v1
+            .pin_uv_auth_token_state
+            .set_permissions(0x3F, None);
+        pin_protocol_v1.clear_permissions_except_lbw();
+        assert_eq!(
+            pin_protocol_v1.has_permission(PinPermission::LargeBlobWrite),
+            Ok(())
+        );
+        assert_eq!(
+            pin_protocol_v1.has_permission(PinPermission::MakeCredential),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        assert_eq!(
+            pin_protocol_v1.has_permission(PinPermission::GetAssertion),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+    }
+
     #[cfg(feature = "with_ctap2_1")]
     #[test]
     fn test_has_permission_for_rp_id() {
@@ -1262,8 +2204,8 @@ mod test {
             Ok(())
         );
         assert_eq!(
-            pin_protocol_v1.permissions_rp_id,
-            Some(String::from("example.com"))
+            pin_protocol_v1.pin_uv_auth_token_state.permissions_rp_id(),
+            Some("example.com")
         );
         assert_eq!(
             pin_protocol_v1.has_permission_for_rp_id("example.com"),
@@ -1274,4 +2216,33 @@ mod test {
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
         );
     }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_has_no_or_rp_id_permission() {
+        let mut rng = ThreadRng256 {};
+        let mut pin_protocol_v1 = PinProtocolV1::new(&mut rng);
+        let rp_id_hash = Sha256::hash(b"example.com");
+        assert_eq!(pin_protocol_v1.has_no_permission_rp_id(), Ok(()));
+        assert_eq!(
+            pin_protocol_v1.has_no_or_rp_id_hash_permission(&rp_id_hash),
+            Ok(())
+        );
+
+        pin_protocol_v1
+            .pin_uv_auth_token_state
+            .set_permissions(0x00, Some(String::from("example.com")));
+        assert_eq!(
+            pin_protocol_v1.has_no_permission_rp_id(),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        assert_eq!(
+            pin_protocol_v1.has_no_or_rp_id_hash_permission(&rp_id_hash),
+            Ok(())
+        );
+        assert_eq!(
+            pin_protocol_v1.has_no_or_rp_id_hash_permission(&[0x4A; 32]),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+    }
 }
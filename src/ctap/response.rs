@@ -12,15 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::cose::CoseKey;
 #[cfg(feature = "with_ctap2_1")]
-use super::data_formats::{AuthenticatorTransport, PublicKeyCredentialParameter};
 use super::data_formats::{
-    CoseKey, CredentialProtectionPolicy, PackedAttestationStatement, PublicKeyCredentialDescriptor,
+    AuthenticatorTransport, PublicKeyCredentialParameter, PublicKeyCredentialRpEntity, TemplateInfo,
+};
+use super::data_formats::{
+    CredentialProtectionPolicy, PackedAttestationStatement, PublicKeyCredentialDescriptor,
     PublicKeyCredentialUserEntity,
 };
+use super::storage::AuditRecord;
 use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::vec::Vec;
+#[cfg(feature = "with_ctap2_1")]
+use cbor::cbor_unsigned;
 use cbor::{cbor_array_vec, cbor_bool, cbor_map_btree, cbor_map_options, cbor_text};
 
 #[cfg_attr(test, derive(PartialEq))]
@@ -33,8 +39,31 @@ pub enum ResponseData {
     AuthenticatorClientPin(Option<AuthenticatorClientPinResponse>),
     AuthenticatorReset,
     #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorBioEnrollment(Option<AuthenticatorBioEnrollmentResponse>),
+    #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorCredentialManagement(Option<AuthenticatorCredentialManagementResponse>),
+    #[cfg(feature = "with_ctap2_1")]
     AuthenticatorSelection,
+    #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorLargeBlobs(Option<AuthenticatorLargeBlobsResponse>),
+    #[cfg(feature = "with_ctap2_1")]
+    AuthenticatorConfig,
     AuthenticatorVendor(AuthenticatorVendorResponse),
+    #[cfg(feature = "with_nfc")]
+    AuthenticatorVendorNfcStatistics(AuthenticatorVendorNfcStatisticsResponse),
+    #[cfg(feature = "with_nfc")]
+    AuthenticatorVendorNfcSelfTest(AuthenticatorVendorNfcSelfTestResponse),
+    AuthenticatorVendorProvision(AuthenticatorVendorProvisionResponse),
+    AuthenticatorVendorRevokePinUvAuthToken,
+    AuthenticatorVendorRotateMasterKeys(AuthenticatorVendorRotateMasterKeysResponse),
+    AuthenticatorVendorSelfTest(AuthenticatorVendorSelfTestResponse),
+    AuthenticatorVendorBenchmark(AuthenticatorVendorBenchmarkResponse),
+    AuthenticatorVendorCompact(AuthenticatorVendorCompactResponse),
+    AuthenticatorVendorWear(AuthenticatorVendorWearResponse),
+    AuthenticatorVendorBackup(AuthenticatorVendorBackupResponse),
+    AuthenticatorVendorRestore(AuthenticatorVendorRestoreResponse),
+    AuthenticatorVendorAuditLog(AuthenticatorVendorAuditLogResponse),
+    AuthenticatorVendorDiagnostics(AuthenticatorVendorDiagnosticsResponse),
 }
 
 impl From<ResponseData> for Option<cbor::Value> {
@@ -48,8 +77,37 @@ impl From<ResponseData> for Option<cbor::Value> {
             ResponseData::AuthenticatorClientPin(None) => None,
             ResponseData::AuthenticatorReset => None,
             #[cfg(feature = "with_ctap2_1")]
+            ResponseData::AuthenticatorBioEnrollment(Some(data)) => Some(data.into()),
+            #[cfg(feature = "with_ctap2_1")]
+            ResponseData::AuthenticatorBioEnrollment(None) => None,
+            #[cfg(feature = "with_ctap2_1")]
+            ResponseData::AuthenticatorCredentialManagement(Some(data)) => Some(data.into()),
+            #[cfg(feature = "with_ctap2_1")]
+            ResponseData::AuthenticatorCredentialManagement(None) => None,
+            #[cfg(feature = "with_ctap2_1")]
             ResponseData::AuthenticatorSelection => None,
+            #[cfg(feature = "with_ctap2_1")]
+            ResponseData::AuthenticatorLargeBlobs(Some(data)) => Some(data.into()),
+            #[cfg(feature = "with_ctap2_1")]
+            ResponseData::AuthenticatorLargeBlobs(None) => None,
+            #[cfg(feature = "with_ctap2_1")]
+            ResponseData::AuthenticatorConfig => None,
             ResponseData::AuthenticatorVendor(data) => Some(data.into()),
+            #[cfg(feature = "with_nfc")]
+            ResponseData::AuthenticatorVendorNfcStatistics(data) => Some(data.into()),
+            #[cfg(feature = "with_nfc")]
+            ResponseData::AuthenticatorVendorNfcSelfTest(data) => Some(data.into()),
+            ResponseData::AuthenticatorVendorProvision(data) => Some(data.into()),
+            ResponseData::AuthenticatorVendorRevokePinUvAuthToken => None,
+            ResponseData::AuthenticatorVendorRotateMasterKeys(data) => Some(data.into()),
+            ResponseData::AuthenticatorVendorSelfTest(data) => Some(data.into()),
+            ResponseData::AuthenticatorVendorBenchmark(data) => Some(data.into()),
+            ResponseData::AuthenticatorVendorCompact(data) => Some(data.into()),
+            ResponseData::AuthenticatorVendorWear(data) => Some(data.into()),
+            ResponseData::AuthenticatorVendorBackup(data) => Some(data.into()),
+            ResponseData::AuthenticatorVendorRestore(data) => Some(data.into()),
+            ResponseData::AuthenticatorVendorAuditLog(data) => Some(data.into()),
+            ResponseData::AuthenticatorVendorDiagnostics(data) => Some(data.into()),
         }
     }
 }
@@ -60,6 +118,8 @@ pub struct AuthenticatorMakeCredentialResponse {
     pub fmt: String,
     pub auth_data: Vec<u8>,
     pub att_stmt: PackedAttestationStatement,
+    pub ep_att: Option<bool>,
+    pub large_blob_key: Option<Vec<u8>>,
 }
 
 impl From<AuthenticatorMakeCredentialResponse> for cbor::Value {
@@ -68,12 +128,16 @@ impl From<AuthenticatorMakeCredentialResponse> for cbor::Value {
             fmt,
             auth_data,
             att_stmt,
+            ep_att,
+            large_blob_key,
         } = make_credential_response;
 
         cbor_map_options! {
             1 => fmt,
             2 => auth_data,
             3 => att_stmt,
+            4 => ep_att,
+            5 => large_blob_key,
         }
     }
 }
@@ -86,6 +150,8 @@ pub struct AuthenticatorGetAssertionResponse {
     pub signature: Vec<u8>,
     pub user: Option<PublicKeyCredentialUserEntity>,
     pub number_of_credentials: Option<u64>,
+    pub user_selected: Option<bool>,
+    pub large_blob_key: Option<Vec<u8>>,
 }
 
 impl From<AuthenticatorGetAssertionResponse> for cbor::Value {
@@ -96,6 +162,8 @@ impl From<AuthenticatorGetAssertionResponse> for cbor::Value {
             signature,
             user,
             number_of_credentials,
+            user_selected,
+            large_blob_key,
         } = get_assertion_response;
 
         cbor_map_options! {
@@ -104,6 +172,8 @@ impl From<AuthenticatorGetAssertionResponse> for cbor::Value {
             3 => signature,
             4 => user,
             5 => number_of_credentials,
+            6 => user_selected,
+            7 => large_blob_key,
         }
     }
 }
@@ -126,11 +196,31 @@ pub struct AuthenticatorGetInfoResponse {
     pub transports: Option<Vec<AuthenticatorTransport>>,
     #[cfg(feature = "with_ctap2_1")]
     pub algorithms: Option<Vec<PublicKeyCredentialParameter>>,
+    #[cfg(feature = "with_ctap2_1")]
+    pub max_serialized_large_blob_array: Option<u64>,
+    // The CTAP 2.1 map key of defaultCredProtect is used for forcePINChange.
+    #[cfg(not(feature = "with_ctap2_1"))]
     pub default_cred_protect: Option<CredentialProtectionPolicy>,
     #[cfg(feature = "with_ctap2_1")]
+    pub force_pin_change: Option<bool>,
+    #[cfg(feature = "with_ctap2_1")]
     pub min_pin_length: u8,
     #[cfg(feature = "with_ctap2_1")]
     pub firmware_version: Option<u64>,
+    #[cfg(feature = "with_ctap2_1")]
+    pub max_cred_blob_length: Option<u64>,
+    #[cfg(feature = "with_ctap2_1")]
+    pub max_rp_ids_for_set_min_pin_length: Option<u64>,
+    #[cfg(feature = "with_ctap2_1")]
+    pub preferred_platform_uv_attempts: Option<u64>,
+    #[cfg(feature = "with_ctap2_1")]
+    pub uv_modality: Option<u64>,
+    #[cfg(feature = "with_ctap2_1")]
+    pub certifications: Option<BTreeMap<String, u64>>,
+    #[cfg(feature = "with_ctap2_1")]
+    pub remaining_discoverable_credentials: Option<u64>,
+    #[cfg(feature = "with_ctap2_1")]
+    pub vendor_prototype_config_commands: Option<Vec<u64>>,
 }
 
 impl From<AuthenticatorGetInfoResponse> for cbor::Value {
@@ -147,9 +237,17 @@ impl From<AuthenticatorGetInfoResponse> for cbor::Value {
             max_credential_id_length,
             transports,
             algorithms,
-            default_cred_protect,
+            max_serialized_large_blob_array,
+            force_pin_change,
             min_pin_length,
             firmware_version,
+            max_cred_blob_length,
+            max_rp_ids_for_set_min_pin_length,
+            preferred_platform_uv_attempts,
+            uv_modality,
+            certifications,
+            remaining_discoverable_credentials,
+            vendor_prototype_config_commands,
         } = get_info_response;
 
         let options_cbor: Option<cbor::Value> = options.map(|options| {
@@ -159,6 +257,13 @@ impl From<AuthenticatorGetInfoResponse> for cbor::Value {
                 .collect();
             cbor_map_btree!(option_map)
         });
+        let certifications_cbor: Option<cbor::Value> = certifications.map(|certifications| {
+            let certification_map: BTreeMap<_, _> = certifications
+                .into_iter()
+                .map(|(name, level)| (cbor_text!(name), cbor_unsigned!(level)))
+                .collect();
+            cbor_map_btree!(certification_map)
+        });
 
         cbor_map_options! {
             0x01 => cbor_array_vec!(versions),
@@ -171,9 +276,17 @@ impl From<AuthenticatorGetInfoResponse> for cbor::Value {
             0x08 => max_credential_id_length,
             0x09 => transports.map(|vec| cbor_array_vec!(vec)),
             0x0A => algorithms.map(|vec| cbor_array_vec!(vec)),
-            0x0C => default_cred_protect.map(|p| p as u64),
+            0x0B => max_serialized_large_blob_array,
+            0x0C => force_pin_change,
             0x0D => min_pin_length as u64,
             0x0E => firmware_version,
+            0x0F => max_cred_blob_length,
+            0x10 => max_rp_ids_for_set_min_pin_length,
+            0x11 => preferred_platform_uv_attempts,
+            0x12 => uv_modality,
+            0x13 => certifications_cbor,
+            0x14 => remaining_discoverable_credentials,
+            0x15 => vendor_prototype_config_commands.map(|vec| cbor_array_vec!(vec)),
         }
     }
 
@@ -215,6 +328,7 @@ pub struct AuthenticatorClientPinResponse {
     pub key_agreement: Option<CoseKey>,
     pub pin_token: Option<Vec<u8>>,
     pub retries: Option<u64>,
+    pub uv_retries: Option<u64>,
 }
 
 impl From<AuthenticatorClientPinResponse> for cbor::Value {
@@ -223,12 +337,14 @@ impl From<AuthenticatorClientPinResponse> for cbor::Value {
             key_agreement,
             pin_token,
             retries,
+            uv_retries,
         } = client_pin_response;
 
         cbor_map_options! {
             1 => key_agreement.map(|cose_key| cbor_map_btree!(cose_key.0)),
             2 => pin_token,
             3 => retries,
+            5 => uv_retries,
         }
     }
 }
@@ -254,13 +370,444 @@ impl From<AuthenticatorVendorResponse> for cbor::Value {
     }
 }
 
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorVendorProvisionResponse {
+    pub aaguid: Vec<u8>,
+    pub pkey_programmed: bool,
+    // The attestation certificate counts as the first certificate of the chain.
+    pub certificate_chain_length: u64,
+    pub locked: bool,
+    // The public key generated by the secure element, to be certified.
+    pub attestation_public_key: Option<CoseKey>,
+}
+
+impl From<AuthenticatorVendorProvisionResponse> for cbor::Value {
+    fn from(provision_response: AuthenticatorVendorProvisionResponse) -> Self {
+        let AuthenticatorVendorProvisionResponse {
+            aaguid,
+            pkey_programmed,
+            certificate_chain_length,
+            locked,
+            attestation_public_key,
+        } = provision_response;
+
+        cbor_map_options! {
+            1 => aaguid,
+            2 => pkey_programmed,
+            3 => certificate_chain_length,
+            4 => locked,
+            5 => attestation_public_key.map(|cose_key| cbor_map_btree!(cose_key.0)),
+        }
+    }
+}
+
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorVendorRotateMasterKeysResponse {
+    // The generation of master keys that wraps new credential IDs.
+    pub generation: u64,
+}
+
+impl From<AuthenticatorVendorRotateMasterKeysResponse> for cbor::Value {
+    fn from(rotate_master_keys_response: AuthenticatorVendorRotateMasterKeysResponse) -> Self {
+        let AuthenticatorVendorRotateMasterKeysResponse { generation } =
+            rotate_master_keys_response;
+
+        cbor_map_options! {
+            1 => generation,
+        }
+    }
+}
+
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorVendorSelfTestResponse {
+    pub passed: bool,
+    // On failure, the number of the failed test.
+    pub failed_test: Option<u64>,
+}
+
+impl From<AuthenticatorVendorSelfTestResponse> for cbor::Value {
+    fn from(self_test_response: AuthenticatorVendorSelfTestResponse) -> Self {
+        let AuthenticatorVendorSelfTestResponse {
+            passed,
+            failed_test,
+        } = self_test_response;
+
+        cbor_map_options! {
+            1 => passed,
+            2 => failed_test,
+        }
+    }
+}
+
+// Average durations in microseconds. Messages are 1024 bytes long.
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorVendorBenchmarkResponse {
+    pub sha256_us: u64,
+    pub hmac_sha256_us: u64,
+    pub aes256_gcm_us: u64,
+    pub ecdsa_sign_us: u64,
+    // The x coordinate of a P-256 key agreement.
+    pub ecdh_us: u64,
+    // Writes a 32 byte value.
+    pub store_write_us: u64,
+    // Compacts a single page.
+    pub store_compaction_us: u64,
+}
+
+impl From<AuthenticatorVendorBenchmarkResponse> for cbor::Value {
+    fn from(benchmark_response: AuthenticatorVendorBenchmarkResponse) -> Self {
+        let AuthenticatorVendorBenchmarkResponse {
+            sha256_us,
+            hmac_sha256_us,
+            aes256_gcm_us,
+            ecdsa_sign_us,
+            ecdh_us,
+            store_write_us,
+            store_compaction_us,
+        } = benchmark_response;
+
+        cbor_map_options! {
+            1 => sha256_us,
+            2 => hmac_sha256_us,
+            3 => aes256_gcm_us,
+            4 => ecdsa_sign_us,
+            5 => ecdh_us,
+            6 => store_write_us,
+            7 => store_compaction_us,
+        }
+    }
+}
+
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorVendorCompactResponse {
+    pub compacted_pages: u64,
+}
+
+impl From<AuthenticatorVendorCompactResponse> for cbor::Value {
+    fn from(compact_response: AuthenticatorVendorCompactResponse) -> Self {
+        let AuthenticatorVendorCompactResponse { compacted_pages } = compact_response;
+
+        cbor_map_options! {
+            1 => compacted_pages,
+        }
+    }
+}
+
+// Erase cycles of the flash pages of the store, and their rated endurance.
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorVendorWearResponse {
+    // Sum of the erase cycles of all pages.
+    pub used_erases: u64,
+    // Sum of the rated erase cycles of all pages.
+    pub total_erases: u64,
+    // Rated erase cycles of a single page.
+    pub max_page_erases: u64,
+    // Erase cycles of each page.
+    pub page_erases: Vec<u64>,
+    pub written_bytes: u64,
+}
+
+impl From<AuthenticatorVendorWearResponse> for cbor::Value {
+    fn from(wear_response: AuthenticatorVendorWearResponse) -> Self {
+        let AuthenticatorVendorWearResponse {
+            used_erases,
+            total_erases,
+            max_page_erases,
+            page_erases,
+            written_bytes,
+        } = wear_response;
+
+        cbor_map_options! {
+            1 => used_erases,
+            2 => total_erases,
+            3 => max_page_erases,
+            4 => cbor_array_vec!(page_erases),
+            5 => written_bytes,
+        }
+    }
+}
+
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorVendorBackupResponse {
+    // The header and number of credentials are only sent in the first response of a backup.
+    pub header: Option<Vec<u8>>,
+    pub records: Vec<Vec<u8>>,
+    pub total_credentials: Option<u64>,
+}
+
+impl From<AuthenticatorVendorBackupResponse> for cbor::Value {
+    fn from(backup_response: AuthenticatorVendorBackupResponse) -> Self {
+        let AuthenticatorVendorBackupResponse {
+            header,
+            records,
+            total_credentials,
+        } = backup_response;
+
+        cbor_map_options! {
+            1 => header,
+            2 => cbor_array_vec!(records),
+            3 => total_credentials,
+        }
+    }
+}
+
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorVendorRestoreResponse {
+    pub restored_credentials: u64,
+}
+
+impl From<AuthenticatorVendorRestoreResponse> for cbor::Value {
+    fn from(restore_response: AuthenticatorVendorRestoreResponse) -> Self {
+        let AuthenticatorVendorRestoreResponse {
+            restored_credentials,
+        } = restore_response;
+
+        cbor_map_options! {
+            1 => restored_credentials,
+        }
+    }
+}
+
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorVendorAuditLogResponse {
+    // Oldest record first.
+    pub records: Vec<AuditRecord>,
+}
+
+impl From<AuthenticatorVendorAuditLogResponse> for cbor::Value {
+    fn from(audit_log_response: AuthenticatorVendorAuditLogResponse) -> Self {
+        let AuthenticatorVendorAuditLogResponse { records } = audit_log_response;
+        let records = records
+            .into_iter()
+            .map(|record| {
+                cbor_map_options! {
+                    1 => record.event as u64,
+                    2 => record.credential_count as u64,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        cbor_map_options! {
+            1 => cbor_array_vec!(records),
+        }
+    }
+}
+
+// Health of the persistent store.
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorVendorDiagnosticsResponse {
+    // Credentials that failed their integrity check, and were set aside at boot.
+    pub quarantined_entries: u64,
+}
+
+impl From<AuthenticatorVendorDiagnosticsResponse> for cbor::Value {
+    fn from(diagnostics_response: AuthenticatorVendorDiagnosticsResponse) -> Self {
+        let AuthenticatorVendorDiagnosticsResponse {
+            quarantined_entries,
+        } = diagnostics_response;
+
+        cbor_map_options! {
+            1 => quarantined_entries,
+        }
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorBioEnrollmentResponse {
+    pub modality: Option<u64>,
+    pub fingerprint_kind: Option<u64>,
+    pub max_capture_samples_required_for_enroll: Option<u64>,
+    pub template_id: Option<Vec<u8>>,
+    pub last_enroll_sample_status: Option<u64>,
+    pub remaining_samples: Option<u64>,
+    pub template_infos: Option<Vec<TemplateInfo>>,
+    pub max_template_friendly_name: Option<u64>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl From<AuthenticatorBioEnrollmentResponse> for cbor::Value {
+    fn from(bio_enrollment_response: AuthenticatorBioEnrollmentResponse) -> Self {
+        let AuthenticatorBioEnrollmentResponse {
+            modality,
+            fingerprint_kind,
+            max_capture_samples_required_for_enroll,
+            template_id,
+            last_enroll_sample_status,
+            remaining_samples,
+            template_infos,
+            max_template_friendly_name,
+        } = bio_enrollment_response;
+
+        cbor_map_options! {
+            0x01 => modality,
+            0x02 => fingerprint_kind,
+            0x03 => max_capture_samples_required_for_enroll,
+            0x04 => template_id,
+            0x05 => last_enroll_sample_status,
+            0x06 => remaining_samples,
+            0x07 => template_infos.map(|infos| cbor_array_vec!(infos)),
+            0x08 => max_template_friendly_name,
+        }
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[derive(Default)]
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorCredentialManagementResponse {
+    pub existing_resident_credentials_count: Option<u64>,
+    pub max_possible_remaining_resident_credentials_count: Option<u64>,
+    pub rp: Option<PublicKeyCredentialRpEntity>,
+    pub rp_id_hash: Option<Vec<u8>>,
+    pub total_rps: Option<u64>,
+    pub user: Option<PublicKeyCredentialUserEntity>,
+    pub credential_id: Option<PublicKeyCredentialDescriptor>,
+    pub public_key: Option<CoseKey>,
+    pub total_credentials: Option<u64>,
+    pub cred_protect: Option<CredentialProtectionPolicy>,
+    pub large_blob_key: Option<Vec<u8>>,
+    // Vendor fields, not in the specification. They contain the credential's ordinals, so that
+    // management tools can show which credentials were created or used most recently.
+    pub creation_order: Option<u64>,
+    pub last_used_order: Option<u64>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl From<AuthenticatorCredentialManagementResponse> for cbor::Value {
+    fn from(cred_management_response: AuthenticatorCredentialManagementResponse) -> Self {
+        let AuthenticatorCredentialManagementResponse {
+            existing_resident_credentials_count,
+            max_possible_remaining_resident_credentials_count,
+            rp,
+            rp_id_hash,
+            total_rps,
+            user,
+            credential_id,
+            public_key,
+            total_credentials,
+            cred_protect,
+            large_blob_key,
+            creation_order,
+            last_used_order,
+        } = cred_management_response;
+
+        cbor_map_options! {
+            0x01 => existing_resident_credentials_count,
+            0x02 => max_possible_remaining_resident_credentials_count,
+            0x03 => rp,
+            0x04 => rp_id_hash,
+            0x05 => total_rps,
+            0x06 => user,
+            0x07 => credential_id,
+            0x08 => public_key.map(|cose_key| cbor_map_btree!(cose_key.0)),
+            0x09 => total_credentials,
+            0x0A => cred_protect,
+            0x0B => large_blob_key,
+            0x0C => creation_order,
+            0x0D => last_used_order,
+        }
+    }
+}
+
+#[cfg(feature = "with_ctap2_1")]
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorLargeBlobsResponse {
+    pub config: Vec<u8>,
+}
+
+#[cfg(feature = "with_ctap2_1")]
+impl From<AuthenticatorLargeBlobsResponse> for cbor::Value {
+    fn from(large_blobs_response: AuthenticatorLargeBlobsResponse) -> Self {
+        let AuthenticatorLargeBlobsResponse { config } = large_blobs_response;
+
+        cbor_map_options! {
+            1 => config,
+        }
+    }
+}
+
+#[cfg(feature = "with_nfc")]
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorVendorNfcStatisticsResponse {
+    pub frames_received: u64,
+    pub frames_sent: u64,
+    pub crc_errors: u64,
+    pub wtx_requests: u64,
+    pub aborts: u64,
+}
+
+#[cfg(feature = "with_nfc")]
+impl From<AuthenticatorVendorNfcStatisticsResponse> for cbor::Value {
+    fn from(nfc_statistics_response: AuthenticatorVendorNfcStatisticsResponse) -> Self {
+        let AuthenticatorVendorNfcStatisticsResponse {
+            frames_received,
+            frames_sent,
+            crc_errors,
+            wtx_requests,
+            aborts,
+        } = nfc_statistics_response;
+
+        cbor_map_options! {
+            1 => frames_received,
+            2 => frames_sent,
+            3 => crc_errors,
+            4 => wtx_requests,
+            5 => aborts,
+        }
+    }
+}
+
+#[cfg(feature = "with_nfc")]
+#[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuthenticatorVendorNfcSelfTestResponse {
+    pub passed: bool,
+    // On failure, the number of the failed step, and the kernel return code if the driver failed.
+    pub failed_step: Option<u64>,
+    pub error_code: Option<i64>,
+}
+
+#[cfg(feature = "with_nfc")]
+impl From<AuthenticatorVendorNfcSelfTestResponse> for cbor::Value {
+    fn from(nfc_self_test_response: AuthenticatorVendorNfcSelfTestResponse) -> Self {
+        let AuthenticatorVendorNfcSelfTestResponse {
+            passed,
+            failed_step,
+            error_code,
+        } = nfc_self_test_response;
+
+        cbor_map_options! {
+            1 => passed,
+            2 => failed_step,
+            3 => error_code,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::super::data_formats::PackedAttestationStatement;
+    use super::super::storage::AuditEvent;
     #[cfg(feature = "with_ctap2_1")]
     use super::super::ES256_CRED_PARAM;
     use super::*;
-    use cbor::{cbor_bytes, cbor_map};
+    use cbor::{cbor_array, cbor_bytes, cbor_map};
+    use crypto::rng256::ThreadRng256;
 
     #[test]
     fn test_make_credential_into_cbor() {
@@ -282,6 +829,8 @@ mod test {
             fmt: "packed".to_string(),
             auth_data: vec![0xAD],
             att_stmt,
+            ep_att: Some(true),
+            large_blob_key: Some(vec![0x1B]),
         };
         let response_cbor: Option<cbor::Value> =
             ResponseData::AuthenticatorMakeCredential(make_credential_response).into();
@@ -289,6 +838,8 @@ mod test {
             1 => "packed",
             2 => vec![0xAD],
             3 => cbor_packed_attestation_statement,
+            4 => true,
+            5 => vec![0x1B],
         };
         assert_eq!(response_cbor, Some(expected_cbor));
     }
@@ -301,6 +852,8 @@ mod test {
             signature: vec![0x51],
             user: None,
             number_of_credentials: None,
+            user_selected: None,
+            large_blob_key: None,
         };
         let response_cbor: Option<cbor::Value> =
             ResponseData::AuthenticatorGetAssertion(get_assertion_response).into();
@@ -329,11 +882,30 @@ mod test {
             transports: None,
             #[cfg(feature = "with_ctap2_1")]
             algorithms: None,
+            #[cfg(feature = "with_ctap2_1")]
+            max_serialized_large_blob_array: None,
+            #[cfg(not(feature = "with_ctap2_1"))]
             default_cred_protect: None,
             #[cfg(feature = "with_ctap2_1")]
+            force_pin_change: None,
+            #[cfg(feature = "with_ctap2_1")]
             min_pin_length: 4,
             #[cfg(feature = "with_ctap2_1")]
             firmware_version: None,
+            #[cfg(feature = "with_ctap2_1")]
+            max_cred_blob_length: None,
+            #[cfg(feature = "with_ctap2_1")]
+            max_rp_ids_for_set_min_pin_length: None,
+            #[cfg(feature = "with_ctap2_1")]
+            preferred_platform_uv_attempts: None,
+            #[cfg(feature = "with_ctap2_1")]
+            uv_modality: None,
+            #[cfg(feature = "with_ctap2_1")]
+            certifications: None,
+            #[cfg(feature = "with_ctap2_1")]
+            remaining_discoverable_credentials: None,
+            #[cfg(feature = "with_ctap2_1")]
+            vendor_prototype_config_commands: None,
         };
         let response_cbor: Option<cbor::Value> =
             ResponseData::AuthenticatorGetInfo(get_info_response).into();
@@ -356,6 +928,8 @@ mod test {
     fn test_get_info_optionals_into_cbor() {
         let mut options_map = BTreeMap::new();
         options_map.insert(String::from("rk"), true);
+        let mut certifications = BTreeMap::new();
+        certifications.insert(String::from("FIDO"), 1);
         let get_info_response = AuthenticatorGetInfoResponse {
             versions: vec!["FIDO_2_0".to_string()],
             extensions: Some(vec!["extension".to_string()]),
@@ -367,9 +941,17 @@ mod test {
             max_credential_id_length: Some(256),
             transports: Some(vec![AuthenticatorTransport::Usb]),
             algorithms: Some(vec![ES256_CRED_PARAM]),
-            default_cred_protect: Some(CredentialProtectionPolicy::UserVerificationRequired),
+            max_serialized_large_blob_array: Some(1024),
+            force_pin_change: Some(false),
             min_pin_length: 4,
             firmware_version: Some(0),
+            max_cred_blob_length: Some(32),
+            max_rp_ids_for_set_min_pin_length: Some(8),
+            preferred_platform_uv_attempts: Some(1),
+            uv_modality: Some(2),
+            certifications: Some(certifications),
+            remaining_discoverable_credentials: Some(150),
+            vendor_prototype_config_commands: Some(vec![0x4242]),
         };
         let response_cbor: Option<cbor::Value> =
             ResponseData::AuthenticatorGetInfo(get_info_response).into();
@@ -384,9 +966,17 @@ mod test {
             0x08 => 256,
             0x09 => cbor_array_vec![vec!["usb"]],
             0x0A => cbor_array_vec![vec![ES256_CRED_PARAM]],
-            0x0C => CredentialProtectionPolicy::UserVerificationRequired as u64,
+            0x0B => 1024,
+            0x0C => false,
             0x0D => 4,
             0x0E => 0,
+            0x0F => 32,
+            0x10 => 8,
+            0x11 => 1,
+            0x12 => 2,
+            0x13 => cbor_map! {"FIDO" => 1},
+            0x14 => 150,
+            0x15 => cbor_array_vec![vec![0x4242]],
         };
         assert_eq!(response_cbor, Some(expected_cbor));
     }
@@ -397,6 +987,7 @@ mod test {
             key_agreement: None,
             pin_token: Some(vec![70]),
             retries: None,
+            uv_retries: None,
         };
         let response_cbor: Option<cbor::Value> =
             ResponseData::AuthenticatorClientPin(Some(client_pin_response)).into();
@@ -418,6 +1009,93 @@ mod test {
         assert_eq!(response_cbor, None);
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_bio_enrollment_into_cbor() {
+        let bio_enrollment_response = AuthenticatorBioEnrollmentResponse {
+            modality: None,
+            fingerprint_kind: None,
+            max_capture_samples_required_for_enroll: None,
+            template_id: None,
+            last_enroll_sample_status: None,
+            remaining_samples: None,
+            template_infos: Some(vec![TemplateInfo {
+                template_id: vec![0x01],
+                template_friendly_name: Some(String::from("Left thumb")),
+            }]),
+            max_template_friendly_name: None,
+        };
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorBioEnrollment(Some(bio_enrollment_response)).into();
+        let expected_cbor = cbor_map! {
+            0x07 => cbor_array![cbor_map! {
+                0x01 => vec![0x01],
+                0x02 => "Left thumb",
+            }],
+        };
+        assert_eq!(response_cbor, Some(expected_cbor));
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorBioEnrollment(None).into();
+        assert_eq!(response_cbor, None);
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_credential_management_into_cbor() {
+        let cred_management_response = AuthenticatorCredentialManagementResponse {
+            existing_resident_credentials_count: Some(1),
+            max_possible_remaining_resident_credentials_count: Some(149),
+            ..Default::default()
+        };
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorCredentialManagement(Some(cred_management_response)).into();
+        let expected_cbor = cbor_map! {
+            0x01 => 1,
+            0x02 => 149,
+        };
+        assert_eq!(response_cbor, Some(expected_cbor));
+
+        let cred_management_response = AuthenticatorCredentialManagementResponse {
+            rp: Some(PublicKeyCredentialRpEntity {
+                rp_id: String::from("example.com"),
+                rp_name: None,
+                rp_icon: None,
+            }),
+            rp_id_hash: Some(vec![0x1D; 32]),
+            total_rps: Some(1),
+            ..Default::default()
+        };
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorCredentialManagement(Some(cred_management_response)).into();
+        let expected_cbor = cbor_map! {
+            0x03 => cbor_map! {
+                "id" => "example.com",
+            },
+            0x04 => vec![0x1D; 32],
+            0x05 => 1,
+        };
+        assert_eq!(response_cbor, Some(expected_cbor));
+
+        let cred_management_response = AuthenticatorCredentialManagementResponse {
+            total_credentials: Some(1),
+            creation_order: Some(3),
+            last_used_order: Some(5),
+            ..Default::default()
+        };
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorCredentialManagement(Some(cred_management_response)).into();
+        let expected_cbor = cbor_map! {
+            0x09 => 1,
+            0x0C => 3,
+            0x0D => 5,
+        };
+        assert_eq!(response_cbor, Some(expected_cbor));
+
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorCredentialManagement(None).into();
+        assert_eq!(response_cbor, None);
+    }
+
     #[cfg(feature = "with_ctap2_1")]
     #[test]
     fn test_selection_into_cbor() {
@@ -425,6 +1103,27 @@ mod test {
         assert_eq!(response_cbor, None);
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_large_blobs_into_cbor() {
+        let large_blobs_response = AuthenticatorLargeBlobsResponse { config: vec![0xC0] };
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorLargeBlobs(Some(large_blobs_response)).into();
+        let expected_cbor = cbor_map_options! {
+            1 => vec![0xC0],
+        };
+        assert_eq!(response_cbor, Some(expected_cbor));
+        let response_cbor: Option<cbor::Value> = ResponseData::AuthenticatorLargeBlobs(None).into();
+        assert_eq!(response_cbor, None);
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_config_into_cbor() {
+        let response_cbor: Option<cbor::Value> = ResponseData::AuthenticatorConfig.into();
+        assert_eq!(response_cbor, None);
+    }
+
     #[test]
     fn test_vendor_response_into_cbor() {
         let response_cbor: Option<cbor::Value> =
@@ -454,4 +1153,268 @@ mod test {
             })
         );
     }
+
+    #[test]
+    fn test_vendor_provision_into_cbor() {
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorVendorProvision(AuthenticatorVendorProvisionResponse {
+                aaguid: vec![0x33; 16],
+                pkey_programmed: true,
+                certificate_chain_length: 2,
+                locked: false,
+                attestation_public_key: None,
+            })
+            .into();
+        assert_eq!(
+            response_cbor,
+            Some(cbor_map_options! {
+                1 => vec![0x33; 16],
+                2 => true,
+                3 => 2,
+                4 => false,
+            })
+        );
+
+        let mut rng = ThreadRng256 {};
+        let public_key = crypto::ecdsa::SecKey::gensk(&mut rng).genpk();
+        let cose_key = CoseKey::from(public_key);
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorVendorProvision(AuthenticatorVendorProvisionResponse {
+                aaguid: vec![0x33; 16],
+                pkey_programmed: true,
+                certificate_chain_length: 0,
+                locked: false,
+                attestation_public_key: Some(cose_key.clone()),
+            })
+            .into();
+        assert_eq!(
+            response_cbor,
+            Some(cbor_map_options! {
+                1 => vec![0x33; 16],
+                2 => true,
+                3 => 0,
+                4 => false,
+                5 => cbor::Value::from(cose_key),
+            })
+        );
+    }
+
+    #[test]
+    fn test_vendor_rotate_master_keys_into_cbor() {
+        let response_cbor: Option<cbor::Value> = ResponseData::AuthenticatorVendorRotateMasterKeys(
+            AuthenticatorVendorRotateMasterKeysResponse { generation: 3 },
+        )
+        .into();
+        assert_eq!(
+            response_cbor,
+            Some(cbor_map_options! {
+                1 => 3,
+            })
+        );
+    }
+
+    #[test]
+    fn test_vendor_self_test_into_cbor() {
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorVendorSelfTest(AuthenticatorVendorSelfTestResponse {
+                passed: false,
+                failed_test: Some(4),
+            })
+            .into();
+        assert_eq!(
+            response_cbor,
+            Some(cbor_map_options! {
+                1 => false,
+                2 => 4,
+            })
+        );
+    }
+
+    #[test]
+    fn test_vendor_benchmark_into_cbor() {
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorVendorBenchmark(AuthenticatorVendorBenchmarkResponse {
+                sha256_us: 1,
+                hmac_sha256_us: 2,
+                aes256_gcm_us: 3,
+                ecdsa_sign_us: 4,
+                ecdh_us: 5,
+                store_write_us: 6,
+                store_compaction_us: 7,
+            })
+            .into();
+        assert_eq!(
+            response_cbor,
+            Some(cbor_map_options! {
+                1 => 1,
+                2 => 2,
+                3 => 3,
+                4 => 4,
+                5 => 5,
+                6 => 6,
+                7 => 7,
+            })
+        );
+    }
+
+    #[test]
+    fn test_vendor_wear_into_cbor() {
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorVendorWear(AuthenticatorVendorWearResponse {
+                used_erases: 3,
+                total_erases: 40,
+                max_page_erases: 10,
+                page_erases: vec![1, 1, 1, 0],
+                written_bytes: 4096,
+            })
+            .into();
+        assert_eq!(
+            response_cbor,
+            Some(cbor_map_options! {
+                1 => 3,
+                2 => 40,
+                3 => 10,
+                4 => cbor_array_vec!(vec![1, 1, 1, 0]),
+                5 => 4096,
+            })
+        );
+    }
+
+    #[test]
+    fn test_vendor_backup_into_cbor() {
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorVendorBackup(AuthenticatorVendorBackupResponse {
+                header: Some(vec![0x01]),
+                records: vec![vec![0x02], vec![0x03]],
+                total_credentials: Some(2),
+            })
+            .into();
+        assert_eq!(
+            response_cbor,
+            Some(cbor_map_options! {
+                1 => vec![0x01],
+                2 => cbor_array_vec!(vec![vec![0x02], vec![0x03]]),
+                3 => 2,
+            })
+        );
+    }
+
+    #[test]
+    fn test_vendor_restore_into_cbor() {
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorVendorRestore(AuthenticatorVendorRestoreResponse {
+                restored_credentials: 2,
+            })
+            .into();
+        assert_eq!(
+            response_cbor,
+            Some(cbor_map_options! {
+                1 => 2,
+            })
+        );
+    }
+
+    #[test]
+    fn test_vendor_audit_log_into_cbor() {
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorVendorAuditLog(AuthenticatorVendorAuditLogResponse {
+                records: vec![
+                    AuditRecord {
+                        event: AuditEvent::CredentialBackup,
+                        credential_count: 3,
+                    },
+                    AuditRecord {
+                        event: AuditEvent::CredentialRestore,
+                        credential_count: 2,
+                    },
+                ],
+            })
+            .into();
+        assert_eq!(
+            response_cbor,
+            Some(cbor_map_options! {
+                1 => cbor_array![
+                    cbor_map_options! {
+                        1 => 1,
+                        2 => 3,
+                    },
+                    cbor_map_options! {
+                        1 => 2,
+                        2 => 2,
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_vendor_diagnostics_into_cbor() {
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorVendorDiagnostics(AuthenticatorVendorDiagnosticsResponse {
+                quarantined_entries: 1,
+            })
+            .into();
+        assert_eq!(
+            response_cbor,
+            Some(cbor_map_options! {
+                1 => 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_vendor_compact_into_cbor() {
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorVendorCompact(AuthenticatorVendorCompactResponse {
+                compacted_pages: 3,
+            })
+            .into();
+        assert_eq!(
+            response_cbor,
+            Some(cbor_map_options! {
+                1 => 3,
+            })
+        );
+    }
+
+    #[cfg(feature = "with_nfc")]
+    #[test]
+    fn test_vendor_nfc_statistics_into_cbor() {
+        let response_cbor: Option<cbor::Value> = ResponseData::AuthenticatorVendorNfcStatistics(
+            AuthenticatorVendorNfcStatisticsResponse {
+                frames_received: 10,
+                frames_sent: 9,
+                crc_errors: 1,
+                wtx_requests: 2,
+                aborts: 0,
+            },
+        )
+        .into();
+        let expected_cbor = cbor_map! {
+            1 => 10,
+            2 => 9,
+            3 => 1,
+            4 => 2,
+            5 => 0,
+        };
+        assert_eq!(response_cbor, Some(expected_cbor));
+    }
+
+    #[cfg(feature = "with_nfc")]
+    #[test]
+    fn test_vendor_nfc_self_test_into_cbor() {
+        let response_cbor: Option<cbor::Value> =
+            ResponseData::AuthenticatorVendorNfcSelfTest(AuthenticatorVendorNfcSelfTestResponse {
+                passed: false,
+                failed_step: Some(5),
+                error_code: Some(-1),
+            })
+            .into();
+        let expected_cbor = cbor_map! {
+            1 => false,
+            2 => 5,
+            3 => -1,
+        };
+        assert_eq!(response_cbor, Some(expected_cbor));
+    }
 }
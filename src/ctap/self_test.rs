@@ -0,0 +1,81 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crypto::rng256::Rng256;
+use crypto::self_test::{run_known_answer_tests, KnownAnswerTest};
+
+// Follows the numbers of the known-answer tests.
+const RNG_HEALTH_TEST_NUMBER: u64 = 6;
+
+// The self-test that failed.
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub enum SelfTestFailure {
+    KnownAnswer(KnownAnswerTest),
+    RngHealth,
+}
+
+impl SelfTestFailure {
+    // The number of the failed test, as reported by the vendor command.
+    pub fn number(self) -> u64 {
+        match self {
+            SelfTestFailure::KnownAnswer(test) => test as u64,
+            SelfTestFailure::RngHealth => RNG_HEALTH_TEST_NUMBER,
+        }
+    }
+}
+
+// Runs the known-answer tests of the crypto library, then checks that the entropy source of the
+// random generator passed its health tests so far.
+pub fn run(rng: &impl Rng256) -> Result<(), SelfTestFailure> {
+    run_known_answer_tests().map_err(SelfTestFailure::KnownAnswer)?;
+    if !rng.is_healthy() {
+        return Err(SelfTestFailure::RngHealth);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::rng256::ThreadRng256;
+
+    #[test]
+    fn test_self_test_passes() {
+        assert_eq!(run(&ThreadRng256 {}), Ok(()));
+    }
+
+    #[test]
+    fn test_unhealthy_rng_fails() {
+        struct UnhealthyRng {}
+
+        impl Rng256 for UnhealthyRng {
+            fn gen_uniform_u8x32(&mut self) -> [u8; 32] {
+                [0; 32]
+            }
+
+            fn is_healthy(&self) -> bool {
+                false
+            }
+        }
+
+        let failure = run(&UnhealthyRng {}).unwrap_err();
+        assert_eq!(failure, SelfTestFailure::RngHealth);
+        assert_eq!(failure.number(), 6);
+        assert_eq!(
+            SelfTestFailure::KnownAnswer(KnownAnswerTest::EcdsaSign).number(),
+            4
+        );
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Encrypted signature counters.
+//
+// Relying parties compare the signature counter of each assertion with the last one they saw, to
+// detect cloned authenticators. The raw counter also tells them how much the authenticator was used
+// elsewhere, and equal or close values link credentials of the same authenticator. The encrypted
+// counter keeps the only property relying parties need: it strictly increases for each credential.
+//
+// Each credential starts at a secret offset, and each step of the real counter advances the
+// encrypted counter by a secret amount between 1 and 2 * STEP - 1. Both are derived with HMAC from
+// the credential ID, so that counters of different credentials look unrelated.
+
+use alloc::vec::Vec;
+use crypto::hmac::hmac_256;
+use crypto::sha256::Sha256;
+
+// The average increment of the encrypted counter for each step of the real counter. The encrypted
+// counter is strictly increasing as long as the real one stays below 2^28, and saturates after.
+const STEP: u32 = 16;
+
+// Returns the counter that the relying party sees. The key must be secret, and stable for the
+// lifetime of the credential. A real counter of 0 means that the credential has no counter, and is
+// not encrypted.
+pub fn encrypt(key: &[u8; 32], credential_id: &[u8], counter: u32) -> u32 {
+    if counter == 0 {
+        return 0;
+    }
+    let mut message = Vec::with_capacity(credential_id.len() + 4);
+    message.extend_from_slice(credential_id);
+    let offset_bytes = hmac_256::<Sha256>(key, &message);
+    let offset = u32::from_be_bytes([0, offset_bytes[0], offset_bytes[1], offset_bytes[2]]);
+    message.extend_from_slice(&counter.to_be_bytes());
+    let jitter = hmac_256::<Sha256>(key, &message)[0] as u32 % STEP;
+    offset
+        .saturating_add(counter.saturating_mul(STEP))
+        .saturating_add(jitter)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: [u8; 32] = [0x5A; 32];
+
+    #[test]
+    fn test_encrypt_is_strictly_increasing() {
+        let mut previous = 0;
+        for counter in 1..1000 {
+            let encrypted = encrypt(&KEY, b"credential", counter);
+            assert!(encrypted > previous);
+            previous = encrypted;
+        }
+    }
+
+    #[test]
+    fn test_encrypt_hides_counter() {
+        assert_eq!(encrypt(&KEY, b"credential", 0), 0);
+        assert_ne!(encrypt(&KEY, b"credential", 1), 1);
+        // The same real counter looks different for other credentials and keys.
+        assert_ne!(
+            encrypt(&KEY, b"credential", 5),
+            encrypt(&KEY, b"other credential", 5)
+        );
+        assert_ne!(
+            encrypt(&KEY, b"credential", 5),
+            encrypt(&[0xA5; 32], b"credential", 5)
+        );
+    }
+
+    #[test]
+    fn test_encrypt_saturates() {
+        assert_eq!(encrypt(&KEY, b"credential", u32::MAX), u32::MAX);
+    }
+}
@@ -35,7 +35,7 @@ pub enum Ctap2StatusCode {
     #[cfg(feature = "with_ctap2_1")]
     CTAP2_ERR_FP_DATABASE_FULL = 0x17,
     #[cfg(feature = "with_ctap2_1")]
-    CTAP2_ERR_PC_STORAGE_FULL = 0x18,
+    CTAP2_ERR_LARGE_BLOB_STORAGE_FULL = 0x18,
     CTAP2_ERR_CREDENTIAL_EXCLUDED = 0x19,
     CTAP2_ERR_PROCESSING = 0x21,
     CTAP2_ERR_INVALID_CREDENTIAL = 0x22,
@@ -64,10 +64,14 @@ pub enum Ctap2StatusCode {
     CTAP2_ERR_ACTION_TIMEOUT = 0x3A,
     CTAP2_ERR_UP_REQUIRED = 0x3B,
     CTAP2_ERR_UV_BLOCKED = 0x3C,
-    #[cfg(feature = "with_ctap2_1")]
+    // Also returned for corrupted persistent storage, with or without CTAP 2.1.
     CTAP2_ERR_INTEGRITY_FAILURE = 0x3D,
     #[cfg(feature = "with_ctap2_1")]
     CTAP2_ERR_INVALID_SUBCOMMAND = 0x3E,
+    #[cfg(feature = "with_ctap2_1")]
+    CTAP2_ERR_UV_INVALID = 0x3F,
+    #[cfg(feature = "with_ctap2_1")]
+    CTAP2_ERR_UNAUTHORIZED_PERMISSION = 0x40,
     CTAP1_ERR_OTHER = 0x7F,
     CTAP2_ERR_SPEC_LAST = 0xDF,
     CTAP2_ERR_EXTENSION_FIRST = 0xE0,
@@ -0,0 +1,239 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::status_code::Ctap2StatusCode;
+use super::timed_permission::TimedPermission;
+#[cfg(not(feature = "std"))]
+use libtock_drivers::led;
+#[cfg(not(feature = "std"))]
+use libtock_drivers::result::FlexUnwrap;
+use libtock_drivers::timer::{ClockValue, Duration};
+
+// Patterns advance by one frame per interval. The main loop and the user presence check wake up
+// at least this often.
+pub const FRAME_DURATION: Duration<isize> = Duration::from_ms(100);
+// How long the status explaining a failed command stays on, unless another command arrives.
+const ERROR_STATUS_DURATION: Duration<isize> = Duration::from_ms(3000);
+
+// What the LEDs tell the user. All LEDs show the same pattern, so that boards with a single LED
+// can show all of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LedStatus {
+    // A command is processed.
+    Processing,
+    // A touch is needed to confirm user presence.
+    AwaitingTouch,
+    // The command needs a PIN, that the platform has to ask for.
+    PinRequired,
+    // The PIN can't be used until the next power cycle, or ever again.
+    PinBlocked,
+    // Built-in user verification is blocked until the PIN is entered.
+    UvBlocked,
+    // A self-test failed, and the authenticator refuses to process commands.
+    SelfTestFailed,
+}
+
+impl LedStatus {
+    // Returns the status that explains the error to the user, if any.
+    pub fn from_error(error_code: &Ctap2StatusCode) -> Option<LedStatus> {
+        match error_code {
+            Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED => Some(LedStatus::PinRequired),
+            Ctap2StatusCode::CTAP2_ERR_PIN_BLOCKED
+            | Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_BLOCKED => Some(LedStatus::PinBlocked),
+            Ctap2StatusCode::CTAP2_ERR_UV_BLOCKED => Some(LedStatus::UvBlocked),
+            _ => None,
+        }
+    }
+
+    // One character per frame, the pattern repeats. LEDs are lit for '*'.
+    fn frames(self) -> &'static [u8] {
+        match self {
+            LedStatus::Processing => b"*",
+            LedStatus::AwaitingTouch => b"***...",
+            LedStatus::PinRequired => b"*.*.......",
+            LedStatus::PinBlocked => b"*.*.*.......",
+            LedStatus::UvBlocked => b"****.*......",
+            LedStatus::SelfTestFailed => b"*.",
+        }
+    }
+
+    // Returns whether the LEDs are lit in this frame of the pattern.
+    pub fn is_lit(self, frame: usize) -> bool {
+        let frames = self.frames();
+        frames[frame % frames.len()] == b'*'
+    }
+}
+
+// Schedules the status patterns the main loop shows between commands.
+pub struct StatusLeds {
+    // The status shown, and when its pattern started.
+    status: Option<(LedStatus, ClockValue)>,
+    expiration: TimedPermission,
+}
+
+impl StatusLeds {
+    pub fn new() -> StatusLeds {
+        StatusLeds {
+            status: None,
+            expiration: TimedPermission::waiting(),
+        }
+    }
+
+    // Shows the status for a while, starting with the first frame of its pattern.
+    pub fn show(&mut self, status: LedStatus, now: ClockValue) {
+        self.status = Some((status, now));
+        self.expiration = TimedPermission::granted(now, ERROR_STATUS_DURATION);
+    }
+
+    // Shows the status explaining the error of a command, if any.
+    pub fn report_error(&mut self, error_code: &Ctap2StatusCode, now: ClockValue) {
+        if let Some(status) = LedStatus::from_error(error_code) {
+            self.show(status, now);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.status = None;
+        self.expiration = TimedPermission::waiting();
+    }
+
+    pub fn update_timeouts(&mut self, now: ClockValue) {
+        self.expiration = self.expiration.check_expiration(now);
+        if !self.expiration.is_granted(now) {
+            self.status = None;
+        }
+    }
+
+    // Returns whether the LEDs are lit now, or None if no status is shown.
+    pub fn is_lit(&self, now: ClockValue) -> Option<bool> {
+        let (status, start) = self.status?;
+        if !self.expiration.is_granted(now) {
+            return None;
+        }
+        let elapsed = now.wrapping_sub(start)?;
+        let frame = elapsed.ms().max(0) / FRAME_DURATION.ms();
+        Some(status.is_lit(frame as usize))
+    }
+}
+
+// The main loop doesn't run while a command is processed, so the LEDs are set right away. The
+// processing pattern doesn't change over time.
+pub fn show_processing() {
+    display(LedStatus::Processing.is_lit(0));
+}
+
+// Lights all LEDs, or switches them off.
+#[cfg(not(feature = "std"))]
+pub fn display(lit: bool) {
+    for l in 0..led::count().flex_unwrap() {
+        let led = led::get(l).flex_unwrap();
+        if lit {
+            led.on().flex_unwrap();
+        } else {
+            led.off().flex_unwrap();
+        }
+    }
+}
+
+// Tests run without the LED driver.
+#[cfg(feature = "std")]
+pub fn display(_lit: bool) {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLOCK_FREQUENCY_HZ: usize = 32768;
+
+    fn clock_value_ms(ms: isize) -> ClockValue {
+        ClockValue::new(ms * CLOCK_FREQUENCY_HZ as isize / 1000, CLOCK_FREQUENCY_HZ)
+    }
+
+    #[test]
+    fn test_from_error() {
+        assert_eq!(
+            LedStatus::from_error(&Ctap2StatusCode::CTAP2_ERR_PIN_REQUIRED),
+            Some(LedStatus::PinRequired)
+        );
+        assert_eq!(
+            LedStatus::from_error(&Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_BLOCKED),
+            Some(LedStatus::PinBlocked)
+        );
+        assert_eq!(
+            LedStatus::from_error(&Ctap2StatusCode::CTAP2_ERR_UV_BLOCKED),
+            Some(LedStatus::UvBlocked)
+        );
+        assert_eq!(
+            LedStatus::from_error(&Ctap2StatusCode::CTAP2_ERR_PIN_INVALID),
+            None
+        );
+    }
+
+    #[test]
+    fn test_patterns_are_distinct() {
+        let statuses = [
+            LedStatus::Processing,
+            LedStatus::AwaitingTouch,
+            LedStatus::PinRequired,
+            LedStatus::PinBlocked,
+            LedStatus::UvBlocked,
+            LedStatus::SelfTestFailed,
+        ];
+        for (i, first) in statuses.iter().enumerate() {
+            for second in &statuses[i + 1..] {
+                assert_ne!(first.frames(), second.frames());
+            }
+        }
+    }
+
+    #[test]
+    fn test_is_lit_follows_pattern() {
+        let mut status_leds = StatusLeds::new();
+        assert_eq!(status_leds.is_lit(clock_value_ms(1000)), None);
+        status_leds.show(LedStatus::PinRequired, clock_value_ms(1000));
+        assert_eq!(status_leds.is_lit(clock_value_ms(1050)), Some(true));
+        assert_eq!(status_leds.is_lit(clock_value_ms(1150)), Some(false));
+        assert_eq!(status_leds.is_lit(clock_value_ms(1250)), Some(true));
+        assert_eq!(status_leds.is_lit(clock_value_ms(1350)), Some(false));
+        // The pattern repeats after 10 frames.
+        assert_eq!(status_leds.is_lit(clock_value_ms(2050)), Some(true));
+    }
+
+    #[test]
+    fn test_status_expires() {
+        let mut status_leds = StatusLeds::new();
+        status_leds.report_error(
+            &Ctap2StatusCode::CTAP2_ERR_PIN_BLOCKED,
+            clock_value_ms(1000),
+        );
+        status_leds.update_timeouts(clock_value_ms(3900));
+        assert!(status_leds.is_lit(clock_value_ms(3900)).is_some());
+        status_leds.update_timeouts(clock_value_ms(4100));
+        assert_eq!(status_leds.is_lit(clock_value_ms(4100)), None);
+    }
+
+    #[test]
+    fn test_report_error_ignores_other_errors() {
+        let mut status_leds = StatusLeds::new();
+        status_leds.report_error(
+            &Ctap2StatusCode::CTAP2_ERR_PIN_INVALID,
+            clock_value_ms(1000),
+        );
+        assert_eq!(status_leds.is_lit(clock_value_ms(1000)), None);
+        status_leds.report_error(&Ctap2StatusCode::CTAP2_ERR_UV_BLOCKED, clock_value_ms(1000));
+        assert_eq!(status_leds.is_lit(clock_value_ms(1000)), Some(true));
+        status_leds.clear();
+        assert_eq!(status_leds.is_lit(clock_value_ms(1000)), None);
+    }
+}
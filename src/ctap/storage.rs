@@ -15,13 +15,16 @@
 mod key;
 
 #[cfg(feature = "with_ctap2_1")]
-use crate::ctap::data_formats::{extract_array, extract_text_string};
+use crate::ctap::data_formats::{
+    extract_array, extract_text_string, PublicKeyCredentialUserEntity, TemplateInfo,
+};
 use crate::ctap::data_formats::{CredentialProtectionPolicy, PublicKeyCredentialSource};
+use crate::ctap::device_key;
 use crate::ctap::key_material;
 use crate::ctap::pin_protocol_v1::PIN_AUTH_LENGTH;
 use crate::ctap::status_code::Ctap2StatusCode;
 use crate::ctap::INITIAL_SIGNATURE_COUNTER;
-use crate::embedded_flash::{new_storage, Storage};
+use crate::embedded_flash::{new_external_storage, new_storage, Storage};
 #[cfg(feature = "with_ctap2_1")]
 use alloc::string::String;
 use alloc::vec;
@@ -29,8 +32,16 @@ use alloc::vec::Vec;
 use arrayref::array_ref;
 #[cfg(feature = "with_ctap2_1")]
 use cbor::cbor_array_vec;
+use core::convert::TryFrom;
 use core::convert::TryInto;
+use core::ops::Range;
+use crypto::gcm::{gcm_decrypt, gcm_encrypt, NONCE_SIZE, TAG_SIZE};
+use crypto::hmac::{hmac_256, Hmac256};
 use crypto::rng256::Rng256;
+use crypto::sha256::Sha256;
+use crypto::zeroize::Zeroize;
+use crypto::Hash256;
+use persistent_store::StoreUpdate;
 
 // Those constants may be modified before compilation to tune the behavior of the key.
 //
@@ -52,19 +63,128 @@ use crypto::rng256::Rng256;
 // With P=20 and K=150, we have I=2M which is enough for 500 increments per day for 10 years.
 const NUM_PAGES: usize = 20;
 const MAX_SUPPORTED_RESIDENTIAL_KEYS: usize = 150;
+// The number of pages of the external flash, on boards that have one. It holds the entries of
+// EXTERNAL_PARTITIONS, so that they don't compete with the signature counter for the erase cycles
+// of the embedded flash. The residential keys are then only limited by their key range.
+const NUM_EXTERNAL_PAGES: usize = 64;
+const MAX_EXTERNAL_RESIDENTIAL_KEYS: usize = 300;
+// The key ranges stored in the external flash, on boards that have one. Entries written to the
+// embedded flash before are moved at boot.
+#[cfg(not(feature = "with_ctap2_1"))]
+const EXTERNAL_PARTITIONS: &[Range<usize>] = &[
+    key::CREDENTIALS,
+    key::CREDENTIAL_INDEX..key::CREDENTIAL_INDEX + 1,
+];
+#[cfg(feature = "with_ctap2_1")]
+const EXTERNAL_PARTITIONS: &[Range<usize>] = &[
+    key::CREDENTIALS,
+    key::CREDENTIAL_INDEX..key::CREDENTIAL_INDEX + 1,
+    key::LARGE_BLOB_ARRAY..key::LARGE_BLOB_ARRAY + 1,
+];
+// The length of a serialized credential assumed while none is stored. Credentials with long user
+// names or a credBlob are longer, so the estimate improves as credentials are created.
+#[cfg(feature = "with_ctap2_1")]
+const TYPICAL_CREDENTIAL_LENGTH: usize = 200;
+// Credentials are stored with a CRC-32 of their serialization in big endian, to detect corruption
+// of the flash.
+const CREDENTIAL_CHECKSUM_LENGTH: usize = 4;
+// The credential index stores, for each credential, its key as 2 bytes in big endian and this many
+// bytes of its RP ID hash. Different relying parties may share a prefix, so candidates are checked
+// against the full hash. The index of MAX_EXTERNAL_RESIDENTIAL_KEYS credentials must fit an entry.
+const CREDENTIAL_INDEX_HASH_LENGTH: usize = 1;
+const CREDENTIAL_INDEX_RECORD_LENGTH: usize = 2 + CREDENTIAL_INDEX_HASH_LENGTH;
+// The key ranges whose values are encrypted with a key derived from the device key, so that a dump
+// of the flash doesn't reveal them. For example, adding `key::CREDENTIALS` hides the private keys,
+// user names and RP IDs of resident credentials. This needs a device key driver in the kernel.
+// Entries written before their range was added can't be read anymore, so adding a range that a
+// reset doesn't clear also loses its entries.
+const ENCRYPTED_PARTITIONS: &[Range<usize>] = &[];
+// The version of the layout of the entries written by this firmware. A change to the layout of
+// existing entries increments it, and appends the step that converts them to MIGRATIONS.
+const SCHEMA_VERSION: u32 = 2;
+// The migration steps, indexed by the schema version they convert from. Each step must tolerate
+// running twice: the version is only updated after the step, so a step interrupted by a power loss
+// runs again at the next boot.
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [
+    PersistentStore::wrap_legacy_attestation_private_key,
+    PersistentStore::append_credential_checksums,
+];
 
 const MAX_PIN_RETRIES: u8 = 8;
 #[cfg(feature = "with_ctap2_1")]
+const MAX_UV_RETRIES: u8 = 8;
+#[cfg(feature = "with_ctap2_1")]
 const DEFAULT_MIN_PIN_LENGTH: u8 = 4;
-// TODO(kaczmarczyck) use this for the minPinLength extension
-// https://github.com/google/OpenSK/issues/129
+// The RP IDs that always receive the minimum PIN length through the minPinLength extension.
+#[cfg(feature = "with_ctap2_1")]
+const DEFAULT_MIN_PIN_LENGTH_RP_IDS: Vec<String> = Vec::new();
+// Maximum number of RP IDs that can be stored for the minPinLength extension.
 #[cfg(feature = "with_ctap2_1")]
-const _DEFAULT_MIN_PIN_LENGTH_RP_IDS: Vec<String> = Vec::new();
-// TODO(kaczmarczyck) Check whether this constant is necessary, or replace it accordingly.
+pub const MAX_RP_IDS_LENGTH: usize = 8;
+// Maximum number of fingerprint templates. The sensor may hold fewer.
 #[cfg(feature = "with_ctap2_1")]
-const _MAX_RP_IDS_LENGTH: usize = 8;
+pub const MAX_FINGERPRINT_TEMPLATES: usize = 10;
+// The large blob array is stored in a single entry, so it must fit in a page. CTAP 2.1 requires at
+// least 1024 bytes.
+#[cfg(feature = "with_ctap2_1")]
+pub const MAX_LARGE_BLOB_ARRAY_SIZE: usize = 2048;
+// The serialized empty CBOR array, followed by the truncated SHA-256 of this one byte.
+#[cfg(feature = "with_ctap2_1")]
+const DEFAULT_LARGE_BLOB_ARRAY: [u8; 17] = [
+    0x80, 0x76, 0xBE, 0x8B, 0x52, 0x8D, 0x00, 0x75, 0xF7, 0xAA, 0xE9, 0x8D, 0x6F, 0xA5, 0x7A, 0x6D,
+    0x3C,
+];
+
+/// The purpose of the device key that wraps the attestation private key.
+const ATTESTATION_WRAPPING_PURPOSE: &[u8] = b"OpenSK attestation private key";
+/// The purpose of the device key that encrypts the entries of encrypted partitions.
+const STORE_ENCRYPTION_PURPOSE: &[u8] = b"OpenSK store encryption";
+/// The attestation private key is stored as nonce, encrypted key and tag.
+const WRAPPED_ATTESTATION_PRIVATE_KEY_LENGTH: usize =
+    NONCE_SIZE + key_material::ATTESTATION_PRIVATE_KEY_LENGTH + TAG_SIZE;
+
+/// The number of records kept in the audit log.
+const MAX_AUDIT_RECORDS: usize = 16;
+/// Audit records are stored as the event followed by the credential count in big endian.
+const AUDIT_RECORD_LENGTH: usize = 5;
+
+/// Sensitive operations recorded in the audit log.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub enum AuditEvent {
+    /// The resident credentials were exported in an encrypted backup.
+    CredentialBackup = 1,
+    /// Resident credentials were imported from an encrypted backup.
+    CredentialRestore = 2,
+}
+
+impl TryFrom<u8> for AuditEvent {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(value: u8) -> Result<Self, Ctap2StatusCode> {
+        match value {
+            1 => Ok(AuditEvent::CredentialBackup),
+            2 => Ok(AuditEvent::CredentialRestore),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE),
+        }
+    }
+}
+
+/// Record of the audit log.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct AuditRecord {
+    pub event: AuditEvent,
+    /// The number of credentials exported or imported.
+    pub credential_count: u32,
+}
+
+/// Converts the entries of a schema version to the next one.
+type Migration = fn(&mut PersistentStore) -> Result<(), Ctap2StatusCode>;
 
 /// Wrapper for master keys.
+///
+/// The keys are wiped when dropped.
 pub struct MasterKeys {
     /// Master encryption key.
     pub encryption: [u8; 32],
@@ -73,9 +193,29 @@ pub struct MasterKeys {
     pub hmac: [u8; 32],
 }
 
+impl Drop for MasterKeys {
+    fn drop(&mut self) {
+        self.encryption.zeroize();
+        self.hmac.zeroize();
+    }
+}
+
 /// CTAP persistent storage.
 pub struct PersistentStore {
     store: persistent_store::Store<Storage>,
+    /// The store of the external flash, if the board has one.
+    ///
+    /// It holds the entries of `EXTERNAL_PARTITIONS`, and `store` holds the others.
+    external_store: Option<persistent_store::Store<Storage>>,
+    /// The writes of the ongoing transaction, if any.
+    pending_updates: Option<Vec<StoreUpdate>>,
+    /// The key ranges whose values are encrypted.
+    encrypted_partitions: &'static [Range<usize>],
+    /// Whether the store was written by a firmware with a newer schema version.
+    ///
+    /// Its entries may have a layout that this firmware doesn't know, so they are neither read nor
+    /// written, until a firmware that knows the version is installed again.
+    unknown_schema: bool,
 }
 
 impl PersistentStore {
@@ -88,21 +228,123 @@ impl PersistentStore {
         let storage = new_storage(NUM_PAGES);
         let mut store = PersistentStore {
             store: persistent_store::Store::new(storage).ok().unwrap(),
+            external_store: new_external_storage(NUM_EXTERNAL_PAGES)
+                .map(|storage| persistent_store::Store::new(storage).ok().unwrap()),
+            pending_updates: None,
+            encrypted_partitions: ENCRYPTED_PARTITIONS,
+            unknown_schema: false,
         };
-        store.init(rng).unwrap();
+        store.migrate().unwrap();
+        if !store.unknown_schema {
+            store.quarantine_corrupted_credentials().unwrap();
+            store.refresh_entropy_seed(rng).unwrap();
+            store.init(rng).unwrap();
+        }
         store
     }
 
+    /// Converts the entries to the schema version of this firmware.
+    ///
+    /// The migration steps run on the first boot after an upgrade, from the stored version on. A
+    /// store of a newer or unreadable version is left untouched instead.
+    fn migrate(&mut self) -> Result<(), Ctap2StatusCode> {
+        let version = match self.store.find(key::SCHEMA_VERSION)? {
+            None => 0,
+            Some(value) if value.len() == 4 => u32::from_be_bytes(*array_ref!(&value, 0, 4)),
+            Some(_) => u32::MAX,
+        };
+        if version > SCHEMA_VERSION {
+            self.unknown_schema = true;
+            return Ok(());
+        }
+        // The steps find the entries of the external partitions in the external flash.
+        self.move_to_external_store()?;
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            migration(self)?;
+            let next_version = version as u32 + 1;
+            self.insert(key::SCHEMA_VERSION, &next_version.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Moves the entries of the external partitions from the embedded flash to the external flash.
+    ///
+    /// This happens on the first boot with an external flash. An entry is only removed from the
+    /// embedded flash once it is written to the external flash, so a power loss loses nothing.
+    fn move_to_external_store(&mut self) -> Result<(), Ctap2StatusCode> {
+        let external_store = match &mut self.external_store {
+            None => return Ok(()),
+            Some(external_store) => external_store,
+        };
+        let mut keys = Vec::new();
+        for handle in self.store.iter()? {
+            let key = handle?.get_key();
+            if is_external(key) {
+                keys.push(key);
+            }
+        }
+        for key in keys {
+            // The value is moved as is, so encrypted entries stay encrypted.
+            if let Some(mut value) = self.store.find(key)? {
+                let result = external_store.insert(key, &value);
+                value.zeroize();
+                result?;
+                self.store.remove(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the store that holds the entry of a key.
+    fn store_of(&self, key: usize) -> &persistent_store::Store<Storage> {
+        match &self.external_store {
+            Some(external_store) if is_external(key) => external_store,
+            _ => &self.store,
+        }
+    }
+
+    /// Returns the store that holds the entry of a key, for writing.
+    fn store_of_mut(&mut self, key: usize) -> &mut persistent_store::Store<Storage> {
+        match &mut self.external_store {
+            Some(external_store) if is_external(key) => external_store,
+            _ => &mut self.store,
+        }
+    }
+
+    /// Returns the store that holds the credentials.
+    fn credential_store(&self) -> &persistent_store::Store<Storage> {
+        self.store_of(key::CREDENTIALS.start)
+    }
+
+    /// Returns the maximum number of credentials.
+    fn max_credentials(&self) -> usize {
+        if self.external_store.is_some() {
+            MAX_EXTERNAL_RESIDENTIAL_KEYS
+        } else {
+            MAX_SUPPORTED_RESIDENTIAL_KEYS
+        }
+    }
+
+    /// Mixes the seed of the previous boot into the random generator, and stores the next one.
+    ///
+    /// The seed strengthens the generator if the hardware source is weak at boot. It is replaced
+    /// before the generator is otherwise used, so that no seed is mixed twice.
+    fn refresh_entropy_seed(&mut self, rng: &mut impl Rng256) -> Result<(), Ctap2StatusCode> {
+        if let Some(mut seed) = self.find(key::ENTROPY_SEED)? {
+            rng.add_input(&seed);
+            seed.zeroize();
+        }
+        let mut seed = rng.gen_uniform_u8x32();
+        self.insert(key::ENTROPY_SEED, &seed)?;
+        seed.zeroize();
+        Ok(())
+    }
+
     /// Initializes the store by creating missing objects.
     fn init(&mut self, rng: &mut impl Rng256) -> Result<(), Ctap2StatusCode> {
         // Generate and store the master keys if they are missing.
         if self.store.find_handle(key::MASTER_KEYS)?.is_none() {
-            let master_encryption_key = rng.gen_uniform_u8x32();
-            let master_hmac_key = rng.gen_uniform_u8x32();
-            let mut master_keys = Vec::with_capacity(64);
-            master_keys.extend_from_slice(&master_encryption_key);
-            master_keys.extend_from_slice(&master_hmac_key);
-            self.store.insert(key::MASTER_KEYS, &master_keys)?;
+            self.insert(key::MASTER_KEYS, &generate_master_keys(rng))?;
         }
 
         // Generate and store the CredRandom secrets if they are missing.
@@ -112,7 +354,7 @@ impl PersistentStore {
             let mut cred_random = Vec::with_capacity(64);
             cred_random.extend_from_slice(&cred_random_without_uv);
             cred_random.extend_from_slice(&cred_random_with_uv);
-            self.store.insert(key::CRED_RANDOM_SECRET, &cred_random)?;
+            self.insert(key::CRED_RANDOM_SECRET, &cred_random)?;
         }
 
         if self.store.find_handle(key::AAGUID)?.is_none() {
@@ -121,6 +363,123 @@ impl PersistentStore {
         Ok(())
     }
 
+    /// Wraps an attestation private key that older versions stored in plaintext.
+    ///
+    /// This is the migration from schema version 0. Without a device key, the plaintext key is kept
+    /// and stays usable. Otherwise, the plaintext remains in the flash until the store compacts its
+    /// page.
+    fn wrap_legacy_attestation_private_key(&mut self) -> Result<(), Ctap2StatusCode> {
+        let mut private_key = match self.find(key::ATTESTATION_PRIVATE_KEY)? {
+            Some(private_key)
+                if private_key.len() == key_material::ATTESTATION_PRIVATE_KEY_LENGTH =>
+            {
+                private_key
+            }
+            _ => return Ok(()),
+        };
+        let wrapped = wrap_attestation_private_key(array_ref![
+            private_key,
+            0,
+            key_material::ATTESTATION_PRIVATE_KEY_LENGTH
+        ]);
+        private_key.zeroize();
+        match wrapped {
+            Ok(wrapped) => self.insert(key::ATTESTATION_PRIVATE_KEY, &wrapped),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE) => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Appends the checksum to the credentials.
+    ///
+    /// This is the migration from schema version 1. Credentials that don't deserialize already have
+    /// their checksum, from an interrupted run of this step, or are corrupted. They are left as is.
+    fn append_credential_checksums(&mut self) -> Result<(), Ctap2StatusCode> {
+        let mut keys = Vec::new();
+        for handle in self.credential_store().iter()? {
+            let key = handle?.get_key();
+            if key::CREDENTIALS.contains(&key) {
+                keys.push(key);
+            }
+        }
+        for key in keys {
+            let mut value = match self.find(key) {
+                Ok(Some(value)) => value,
+                Ok(None) | Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE) => continue,
+                Err(error) => return Err(error),
+            };
+            if deserialize_credential(value.clone()).is_some() {
+                append_checksum(&mut value);
+                let result = self.insert(key, &value);
+                value.zeroize();
+                result?;
+            }
+            value.zeroize();
+        }
+        Ok(())
+    }
+
+    /// Moves the corrupted credentials to the quarantine.
+    ///
+    /// A credential is corrupted if it doesn't decrypt, checksum or deserialize. Its value is kept
+    /// as read in a free quarantine entry, or dropped if there is none.
+    fn quarantine_corrupted_credentials(&mut self) -> Result<(), Ctap2StatusCode> {
+        let store = self.credential_store();
+        let mut corrupted_keys = Vec::new();
+        for handle in store.iter()? {
+            let handle = handle?;
+            let key = handle.get_key();
+            if !key::CREDENTIALS.contains(&key) {
+                continue;
+            }
+            let value = handle.get_value(store)?;
+            if read_credential_entry(self.encrypted_partitions, key, value)?.is_none() {
+                corrupted_keys.push(key);
+            }
+        }
+        for key in corrupted_keys {
+            let mut value = match self.store_of(key).find(key)? {
+                None => continue,
+                Some(value) => value,
+            };
+            let mut quarantine_key = None;
+            if value.len() <= self.store.max_value_length() {
+                for candidate in key::QUARANTINE {
+                    if self.store.find_handle(candidate)?.is_none() {
+                        quarantine_key = Some(candidate);
+                        break;
+                    }
+                }
+            }
+            let result = match quarantine_key {
+                None => self.remove(key),
+                Some(quarantine_key) => self.apply(&[
+                    StoreUpdate::Insert {
+                        key: quarantine_key,
+                        value: value.clone(),
+                    },
+                    StoreUpdate::Remove { key },
+                ]),
+            };
+            value.zeroize();
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of quarantined entries.
+    ///
+    /// When the quarantine is full, corrupted entries are dropped and not counted.
+    pub fn quarantined_entries(&self) -> Result<usize, Ctap2StatusCode> {
+        let mut count = 0;
+        for handle in self.store.iter()? {
+            if key::QUARANTINE.contains(&handle?.get_key()) {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
     /// Returns the first matching credential.
     ///
     /// Returns `None` if no credentials are matched or if `check_cred_protect` is set and the first
@@ -131,18 +490,16 @@ impl PersistentStore {
         credential_id: &[u8],
         check_cred_protect: bool,
     ) -> Result<Option<PublicKeyCredentialSource>, Ctap2StatusCode> {
-        let mut iter_result = Ok(());
-        let iter = self.iter_credentials(&mut iter_result)?;
-        // We don't check whether there is more than one matching credential to be able to exit
-        // early.
-        let result = iter.map(|(_, credential)| credential).find(|credential| {
-            credential.rp_id == rp_id && credential.credential_id == credential_id
-        });
-        iter_result?;
+        // We don't check whether there is more than one matching credential.
+        let result = self
+            .rp_credentials(&Sha256::hash(rp_id.as_bytes()))?
+            .into_iter()
+            .map(|(_, credential)| credential)
+            .find(|credential| {
+                credential.rp_id == rp_id && credential.credential_id == credential_id
+            });
         if let Some(cred) = &result {
-            let user_verification_required = cred.cred_protect_policy
-                == Some(CredentialProtectionPolicy::UserVerificationRequired);
-            if check_cred_protect && user_verification_required {
+            if check_cred_protect && cred.requires_user_verification() {
                 return Ok(None);
             }
         }
@@ -159,46 +516,40 @@ impl PersistentStore {
         // Holds the key of the existing credential if this is an update.
         let mut old_key = None;
         let min_key = key::CREDENTIALS.start;
+        let max_credentials = self.max_credentials();
         // Holds whether a key is used (indices are shifted by min_key).
-        let mut keys = vec![false; MAX_SUPPORTED_RESIDENTIAL_KEYS];
+        let mut keys = vec![false; max_credentials];
         let mut iter_result = Ok(());
         let iter = self.iter_credentials(&mut iter_result)?;
         for (key, credential) in iter {
-            if key < min_key
-                || key - min_key >= MAX_SUPPORTED_RESIDENTIAL_KEYS
-                || keys[key - min_key]
-            {
-                return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+            if key < min_key || key - min_key >= max_credentials || keys[key - min_key] {
+                return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
             }
             keys[key - min_key] = true;
             if credential.rp_id == new_credential.rp_id
                 && credential.user_handle == new_credential.user_handle
             {
                 if old_key.is_some() {
-                    return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+                    return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
                 }
                 old_key = Some(key);
             }
         }
         iter_result?;
-        if old_key.is_none()
-            && keys.iter().filter(|&&x| x).count() >= MAX_SUPPORTED_RESIDENTIAL_KEYS
-        {
+        if old_key.is_none() && keys.iter().filter(|&&x| x).count() >= max_credentials {
             return Err(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL);
         }
         let key = match old_key {
             // This is a new credential being added, we need to allocate a free key. We choose the
             // first available key.
             None => key::CREDENTIALS
-                .take(MAX_SUPPORTED_RESIDENTIAL_KEYS)
+                .take(max_credentials)
                 .find(|key| !keys[key - min_key])
                 .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?,
             // This is an existing credential being updated, we reuse its key.
             Some(x) => x,
         };
-        let value = serialize_credential(new_credential)?;
-        self.store.insert(key, &value)?;
-        Ok(())
+        self.insert_credential(key, new_credential)
     }
 
     /// Returns the list of matching credentials.
@@ -209,24 +560,213 @@ impl PersistentStore {
         rp_id: &str,
         check_cred_protect: bool,
     ) -> Result<Vec<PublicKeyCredentialSource>, Ctap2StatusCode> {
+        Ok(self
+            .rp_credentials(&Sha256::hash(rp_id.as_bytes()))?
+            .into_iter()
+            .map(|(_, credential)| credential)
+            .filter(|credential| credential.rp_id == rp_id)
+            .filter(|cred| !check_cred_protect || cred.is_discoverable())
+            .collect())
+    }
+
+    /// Returns the credentials whose RP ID has this SHA-256 hash, with their keys in increasing
+    /// order.
+    ///
+    /// Only the credentials listed for this hash in the credential index are read. Corrupted
+    /// credentials are skipped, until they are quarantined at the next boot.
+    pub fn rp_credentials(
+        &self,
+        rp_id_hash: &[u8],
+    ) -> Result<Vec<(usize, PublicKeyCredentialSource)>, Ctap2StatusCode> {
+        let hash_prefix = match rp_id_hash.get(..CREDENTIAL_INDEX_HASH_LENGTH) {
+            Some(hash_prefix) => hash_prefix,
+            None => return Ok(Vec::new()),
+        };
+        let mut result = Vec::new();
+        for (key, prefix) in self.credential_index()?.0 {
+            if prefix != hash_prefix {
+                continue;
+            }
+            let credential = match self.read_credential(key)? {
+                None => continue,
+                Some(credential) => credential,
+            };
+            if Sha256::hash(credential.rp_id.as_bytes())[..] == rp_id_hash[..] {
+                result.push((key, credential));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns the credential at the given key.
+    ///
+    /// The key is the one returned with the credential by `iter_credentials`.
+    pub fn get_credential(&self, key: usize) -> Result<PublicKeyCredentialSource, Ctap2StatusCode> {
+        self.read_credential(key)?
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+    }
+
+    /// Reads the credential at the given key.
+    ///
+    /// Returns `None` if the credential is corrupted.
+    fn read_credential(
+        &self,
+        key: usize,
+    ) -> Result<Option<PublicKeyCredentialSource>, Ctap2StatusCode> {
+        if !key::CREDENTIALS.contains(&key) {
+            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+        }
+        self.check_schema()?;
+        let value = self
+            .store_of(key)
+            .find(key)?
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
+        read_credential_entry(self.encrypted_partitions, key, value)
+    }
+
+    /// Deletes the credential with the given credential ID.
+    ///
+    /// The store wipes the removed entry from the flash before returning, so the private key of the
+    /// credential doesn't wait for a compaction to be gone.
+    ///
+    /// Returns `CTAP2_ERR_NO_CREDENTIALS` if no credential has this ID.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn delete_credential(&mut self, credential_id: &[u8]) -> Result<(), Ctap2StatusCode> {
+        let (key, _) = self
+            .find_credential_by_id(credential_id)?
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)?;
+        let (mut index, _) = self.credential_index()?;
+        index.retain(|(indexed_key, _)| *indexed_key != key);
+        self.apply(&[StoreUpdate::Remove { key }, credential_index_update(&index)])
+    }
+
+    /// Updates the user information of the credential with the given credential ID.
+    ///
+    /// The name and display name are replaced, and removed if absent or empty. The credential is
+    /// overwritten with a single insertion, so a power loss leaves either the old or the updated
+    /// credential in the store.
+    ///
+    /// Returns `CTAP2_ERR_NO_CREDENTIALS` if no credential has this ID and
+    /// `CTAP1_ERR_INVALID_PARAMETER` if the user ID does not match.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn update_credential_user(
+        &mut self,
+        credential_id: &[u8],
+        user: PublicKeyCredentialUserEntity,
+    ) -> Result<(), Ctap2StatusCode> {
+        let (key, mut credential) = self
+            .find_credential_by_id(credential_id)?
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)?;
+        if credential.user_handle != user.user_id {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+        credential.user_name = user.user_name.filter(|name| !name.is_empty());
+        credential.user_display_name = user.user_display_name.filter(|name| !name.is_empty());
+        self.insert_credential(key, credential)
+    }
+
+    /// Records that the credential with the given ID was used.
+    ///
+    /// The signature counter of the credential is incremented and its new value returned. Returns
+    /// `None` if no stored credential has this ID, which is the case for credentials that are not
+    /// resident.
+    pub fn record_credential_use(
+        &mut self,
+        credential_id: &[u8],
+        counter_increment: u32,
+    ) -> Result<Option<u32>, Ctap2StatusCode> {
+        let last_used_order = self.new_creation_order()?;
+        let (key, mut credential) = match self.find_credential_by_id(credential_id)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let old_counter = match credential.signature_counter {
+            Some(counter) => counter,
+            None => self.global_signature_counter()?,
+        };
+        // In hopes that servers handle the wrapping gracefully.
+        let new_counter = old_counter.wrapping_add(counter_increment);
+        credential.last_used_order = Some(last_used_order);
+        credential.signature_counter = Some(new_counter);
+        self.insert_credential(key, credential)?;
+        Ok(Some(new_counter))
+    }
+
+    /// Stores the credential at the given key.
+    ///
+    /// The credential is stored with its checksum. The credential index is written in the same
+    /// transaction, unless it is up to date and already lists the credential. The serialized
+    /// credential contains its private key, so it is wiped afterwards.
+    fn insert_credential(
+        &mut self,
+        key: usize,
+        credential: PublicKeyCredentialSource,
+    ) -> Result<(), Ctap2StatusCode> {
+        let hash_prefix = credential_index_hash_prefix(&credential);
+        let (mut index, is_up_to_date) = self.credential_index()?;
+        let mut value = serialize_credential(credential)?;
+        append_checksum(&mut value);
+        let mut updates = vec![StoreUpdate::Insert { key, value }];
+        if !is_up_to_date || !index.contains(&(key, hash_prefix)) {
+            index.retain(|(indexed_key, _)| *indexed_key != key);
+            index.push((key, hash_prefix));
+            index.sort_unstable();
+            updates.push(credential_index_update(&index));
+        }
+        let result = self.apply(&updates);
+        if let StoreUpdate::Insert { value, .. } = &mut updates[0] {
+            value.zeroize();
+        }
+        result
+    }
+
+    /// Returns the key and RP ID hash prefix of each credential, in increasing key order, and
+    /// whether the stored index is up to date.
+    ///
+    /// The index is read from the store if it lists exactly the stored credentials, and rebuilt
+    /// from the credentials otherwise. Only the headers of the credentials are read to check it.
+    fn credential_index(
+        &self,
+    ) -> Result<(Vec<(usize, [u8; CREDENTIAL_INDEX_HASH_LENGTH])>, bool), Ctap2StatusCode> {
+        let mut keys = Vec::new();
+        for handle in self.credential_store().iter()? {
+            let key = handle?.get_key();
+            if key::CREDENTIALS.contains(&key) {
+                keys.push(key);
+            }
+        }
+        keys.sort_unstable();
+        if let Some(index) = self
+            .find(key::CREDENTIAL_INDEX)?
+            .and_then(|value| deserialize_credential_index(&value))
+        {
+            if index.iter().map(|(key, _)| *key).eq(keys.iter().cloned()) {
+                return Ok((index, true));
+            }
+        }
         let mut iter_result = Ok(());
         let iter = self.iter_credentials(&mut iter_result)?;
-        let result = iter
-            .filter_map(|(_, credential)| {
-                if credential.rp_id == rp_id {
-                    Some(credential)
-                } else {
-                    None
-                }
-            })
-            .filter(|cred| !check_cred_protect || cred.is_discoverable())
-            .collect();
+        let mut index = iter
+            .map(|(key, credential)| (key, credential_index_hash_prefix(&credential)))
+            .collect::<Vec<_>>();
+        iter_result?;
+        index.sort_unstable();
+        Ok((index, false))
+    }
+
+    // Returns the credential with the given credential ID and its key, if any.
+    fn find_credential_by_id(
+        &self,
+        credential_id: &[u8],
+    ) -> Result<Option<(usize, PublicKeyCredentialSource)>, Ctap2StatusCode> {
+        let mut iter_result = Ok(());
+        let mut iter = self.iter_credentials(&mut iter_result)?;
+        let result = iter.find(|(_, credential)| credential.credential_id == credential_id);
         iter_result?;
         Ok(result)
     }
 
     /// Returns the number of credentials.
-    #[cfg(test)]
     pub fn count_credentials(&self) -> Result<usize, Ctap2StatusCode> {
         let mut iter_result = Ok(());
         let iter = self.iter_credentials(&mut iter_result)?;
@@ -235,31 +775,72 @@ impl PersistentStore {
         Ok(result)
     }
 
+    /// Returns how many more credentials can be stored.
+    ///
+    /// The free space of the store is divided by the average size of the stored credentials. The
+    /// result is at most the number of free credential keys.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn remaining_credentials(&self) -> Result<usize, Ctap2StatusCode> {
+        let store = self.credential_store();
+        let mut count = 0;
+        let mut total_capacity = 0;
+        for handle in store.iter()? {
+            let handle = handle?;
+            if key::CREDENTIALS.contains(&handle.get_key()) {
+                count += 1;
+                total_capacity += store.entry_capacity(handle.get_length());
+            }
+        }
+        let average_capacity = if count == 0 {
+            store.entry_capacity(TYPICAL_CREDENTIAL_LENGTH)
+        } else {
+            (total_capacity + count - 1) / count
+        };
+        let free_capacity = store.capacity()?.remaining();
+        Ok(core::cmp::min(
+            self.max_credentials().saturating_sub(count),
+            free_capacity / average_capacity,
+        ))
+    }
+
     /// Iterates through the credentials.
     ///
-    /// If an error is encountered during iteration, it is written to `result`.
-    fn iter_credentials<'a>(
+    /// Credentials are deserialized one at a time, so callers can go through all of them without
+    /// holding them in memory. The iteration order is unspecified. If an error is encountered
+    /// during iteration, it is written to `result`.
+    pub fn iter_credentials<'a>(
         &'a self,
         result: &'a mut Result<(), Ctap2StatusCode>,
     ) -> Result<IterCredentials<'a>, Ctap2StatusCode> {
-        IterCredentials::new(&self.store, result)
+        self.check_schema()?;
+        IterCredentials::new(self.credential_store(), self.encrypted_partitions, result)
     }
 
     /// Returns the next creation order.
+    ///
+    /// Credential uses are recorded with the same ordinals, so the result is greater than all
+    /// creation and last use orders of stored credentials.
     pub fn new_creation_order(&self) -> Result<u64, Ctap2StatusCode> {
         let mut iter_result = Ok(());
         let iter = self.iter_credentials(&mut iter_result)?;
-        let max = iter.map(|(_, credential)| credential.creation_order).max();
+        let max = iter
+            .map(|(_, credential)| {
+                core::cmp::max(
+                    credential.creation_order,
+                    credential.last_used_order.unwrap_or(0),
+                )
+            })
+            .max();
         iter_result?;
         Ok(max.unwrap_or(0).wrapping_add(1))
     }
 
     /// Returns the global signature counter.
     pub fn global_signature_counter(&self) -> Result<u32, Ctap2StatusCode> {
-        match self.store.find(key::GLOBAL_SIGNATURE_COUNTER)? {
+        match self.find(key::GLOBAL_SIGNATURE_COUNTER)? {
             None => Ok(INITIAL_SIGNATURE_COUNTER),
             Some(value) if value.len() == 4 => Ok(u32::from_ne_bytes(*array_ref!(&value, 0, 4))),
-            Some(_) => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+            Some(_) => Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE),
         }
     }
 
@@ -268,34 +849,125 @@ impl PersistentStore {
         let old_value = self.global_signature_counter()?;
         // In hopes that servers handle the wrapping gracefully.
         let new_value = old_value.wrapping_add(increment);
-        self.store
-            .insert(key::GLOBAL_SIGNATURE_COUNTER, &new_value.to_ne_bytes())?;
+        self.insert(key::GLOBAL_SIGNATURE_COUNTER, &new_value.to_ne_bytes())?;
         Ok(())
     }
 
-    /// Returns the master keys.
+    /// Returns the records of the audit log, oldest first.
+    pub fn audit_log(&self) -> Result<Vec<AuditRecord>, Ctap2StatusCode> {
+        let value = self.find(key::AUDIT_LOG)?.unwrap_or_default();
+        if value.len() % AUDIT_RECORD_LENGTH != 0 {
+            return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
+        }
+        value
+            .chunks(AUDIT_RECORD_LENGTH)
+            .map(|record| {
+                Ok(AuditRecord {
+                    event: AuditEvent::try_from(record[0])?,
+                    credential_count: u32::from_be_bytes(*array_ref!(record, 1, 4)),
+                })
+            })
+            .collect()
+    }
+
+    /// Appends a record to the audit log.
+    ///
+    /// The oldest records are dropped to keep at most `MAX_AUDIT_RECORDS` records.
+    pub fn append_audit_record(&mut self, record: AuditRecord) -> Result<(), Ctap2StatusCode> {
+        let mut value = self.find(key::AUDIT_LOG)?.unwrap_or_default();
+        if value.len() % AUDIT_RECORD_LENGTH != 0 {
+            return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
+        }
+        value.push(record.event as u8);
+        value.extend_from_slice(&record.credential_count.to_be_bytes());
+        let excess = value
+            .len()
+            .saturating_sub(MAX_AUDIT_RECORDS * AUDIT_RECORD_LENGTH);
+        value.drain(..excess);
+        self.insert(key::AUDIT_LOG, &value)
+    }
+
+    /// Replaces the last record of the audit log, to update an operation in progress.
+    pub fn update_last_audit_record(&mut self, record: AuditRecord) -> Result<(), Ctap2StatusCode> {
+        let mut value = self.find(key::AUDIT_LOG)?.unwrap_or_default();
+        if value.is_empty() || value.len() % AUDIT_RECORD_LENGTH != 0 {
+            return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
+        }
+        value.truncate(value.len() - AUDIT_RECORD_LENGTH);
+        value.push(record.event as u8);
+        value.extend_from_slice(&record.credential_count.to_be_bytes());
+        self.insert(key::AUDIT_LOG, &value)
+    }
+
+    /// Returns the master keys of the first generation.
     pub fn master_keys(&self) -> Result<MasterKeys, Ctap2StatusCode> {
-        let master_keys = self
-            .store
-            .find(key::MASTER_KEYS)?
-            .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
+        self.generation_master_keys(0)?
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+    }
+
+    /// Returns the master keys of a generation, if it exists.
+    pub fn generation_master_keys(
+        &self,
+        generation: u8,
+    ) -> Result<Option<MasterKeys>, Ctap2StatusCode> {
+        let key = match master_keys_key(generation) {
+            None => return Ok(None),
+            Some(key) => key,
+        };
+        let master_keys = match self.find(key)? {
+            None => return Ok(None),
+            Some(master_keys) => master_keys,
+        };
         if master_keys.len() != 64 {
-            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+            return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
         }
-        Ok(MasterKeys {
+        Ok(Some(MasterKeys {
             encryption: *array_ref![master_keys, 0, 32],
             hmac: *array_ref![master_keys, 32, 32],
-        })
+        }))
+    }
+
+    /// Returns the latest generation of master keys.
+    pub fn master_keys_generation(&self) -> Result<u8, Ctap2StatusCode> {
+        let mut generation = 0;
+        for key in key::ROTATED_MASTER_KEYS {
+            if self.store.find_handle(key)?.is_none() {
+                break;
+            }
+            generation += 1;
+        }
+        Ok(generation)
+    }
+
+    /// Returns the latest generation of master keys, and its keys.
+    ///
+    /// New credential IDs are wrapped with these keys.
+    pub fn current_master_keys(&self) -> Result<(u8, MasterKeys), Ctap2StatusCode> {
+        let generation = self.master_keys_generation()?;
+        let master_keys = self
+            .generation_master_keys(generation)?
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)?;
+        Ok((generation, master_keys))
+    }
+
+    /// Generates the master keys of a new generation, and returns this generation.
+    ///
+    /// The keys of older generations are kept, so that their credential IDs stay valid. Fails with
+    /// `CTAP2_ERR_KEY_STORE_FULL` when all generations are used, until the next reset.
+    pub fn rotate_master_keys(&mut self, rng: &mut impl Rng256) -> Result<u8, Ctap2StatusCode> {
+        let generation = self.master_keys_generation()? + 1;
+        let key = master_keys_key(generation).ok_or(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL)?;
+        self.insert(key, &generate_master_keys(rng))?;
+        Ok(generation)
     }
 
     /// Returns the CredRandom secret.
     pub fn cred_random_secret(&self, has_uv: bool) -> Result<[u8; 32], Ctap2StatusCode> {
         let cred_random_secret = self
-            .store
             .find(key::CRED_RANDOM_SECRET)?
-            .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)?;
         if cred_random_secret.len() != 64 {
-            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+            return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
         }
         let offset = if has_uv { 32 } else { 0 };
         Ok(*array_ref![cred_random_secret, offset, 32])
@@ -303,75 +975,118 @@ impl PersistentStore {
 
     /// Returns the PIN hash if defined.
     pub fn pin_hash(&self) -> Result<Option<[u8; PIN_AUTH_LENGTH]>, Ctap2StatusCode> {
-        let pin_hash = match self.store.find(key::PIN_HASH)? {
+        let pin_hash = match self.find(key::PIN_HASH)? {
             None => return Ok(None),
             Some(pin_hash) => pin_hash,
         };
         if pin_hash.len() != PIN_AUTH_LENGTH {
-            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+            return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
         }
         Ok(Some(*array_ref![pin_hash, 0, PIN_AUTH_LENGTH]))
     }
 
     /// Sets the PIN hash.
     ///
-    /// If it was already defined, it is updated.
+    /// If it was already defined, it is updated. A new PIN fulfills a pending
+    /// forced PIN change, so both are written in a single transaction.
     pub fn set_pin_hash(
         &mut self,
         pin_hash: &[u8; PIN_AUTH_LENGTH],
     ) -> Result<(), Ctap2StatusCode> {
-        Ok(self.store.insert(key::PIN_HASH, pin_hash)?)
+        let pin_hash_update = StoreUpdate::Insert {
+            key: key::PIN_HASH,
+            value: pin_hash.to_vec(),
+        };
+        #[cfg(feature = "with_ctap2_1")]
+        let updates = [
+            pin_hash_update,
+            StoreUpdate::Remove {
+                key: key::FORCE_PIN_CHANGE,
+            },
+        ];
+        #[cfg(not(feature = "with_ctap2_1"))]
+        let updates = [pin_hash_update];
+        self.apply(&updates)
     }
 
     /// Returns the number of remaining PIN retries.
     pub fn pin_retries(&self) -> Result<u8, Ctap2StatusCode> {
-        match self.store.find(key::PIN_RETRIES)? {
+        match self.find(key::PIN_RETRIES)? {
             None => Ok(MAX_PIN_RETRIES),
             Some(value) if value.len() == 1 => Ok(value[0]),
-            _ => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE),
         }
     }
 
     /// Decrements the number of remaining PIN retries.
+    ///
+    /// The new value replaces the old one atomically, so a power loss during the
+    /// write keeps either of them, never restores the maximum.
     pub fn decr_pin_retries(&mut self) -> Result<(), Ctap2StatusCode> {
         let old_value = self.pin_retries()?;
         let new_value = old_value.saturating_sub(1);
         if new_value != old_value {
-            self.store.insert(key::PIN_RETRIES, &[new_value])?;
+            self.insert(key::PIN_RETRIES, &[new_value])?;
         }
         Ok(())
     }
 
     /// Resets the number of remaining PIN retries.
     pub fn reset_pin_retries(&mut self) -> Result<(), Ctap2StatusCode> {
-        Ok(self.store.remove(key::PIN_RETRIES)?)
+        self.remove(key::PIN_RETRIES)
+    }
+
+    /// Returns the number of remaining built-in user verification retries.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn uv_retries(&self) -> Result<u8, Ctap2StatusCode> {
+        match self.find(key::UV_RETRIES)? {
+            None => Ok(MAX_UV_RETRIES),
+            Some(value) if value.len() == 1 => Ok(value[0]),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE),
+        }
+    }
+
+    /// Decrements the number of remaining built-in user verification retries.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn decr_uv_retries(&mut self) -> Result<(), Ctap2StatusCode> {
+        let old_value = self.uv_retries()?;
+        let new_value = old_value.saturating_sub(1);
+        if new_value != old_value {
+            self.insert(key::UV_RETRIES, &[new_value])?;
+        }
+        Ok(())
+    }
+
+    /// Resets the number of remaining built-in user verification retries.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn reset_uv_retries(&mut self) -> Result<(), Ctap2StatusCode> {
+        self.remove(key::UV_RETRIES)
     }
 
     /// Returns the minimum PIN length.
     #[cfg(feature = "with_ctap2_1")]
     pub fn min_pin_length(&self) -> Result<u8, Ctap2StatusCode> {
-        match self.store.find(key::MIN_PIN_LENGTH)? {
+        match self.find(key::MIN_PIN_LENGTH)? {
             None => Ok(DEFAULT_MIN_PIN_LENGTH),
             Some(value) if value.len() == 1 => Ok(value[0]),
-            _ => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE),
         }
     }
 
     /// Sets the minimum PIN length.
     #[cfg(feature = "with_ctap2_1")]
     pub fn set_min_pin_length(&mut self, min_pin_length: u8) -> Result<(), Ctap2StatusCode> {
-        Ok(self.store.insert(key::MIN_PIN_LENGTH, &[min_pin_length])?)
+        self.insert(key::MIN_PIN_LENGTH, &[min_pin_length])
     }
 
     /// Returns the list of RP IDs that are used to check if reading the minimum PIN length is
     /// allowed.
     #[cfg(feature = "with_ctap2_1")]
-    pub fn _min_pin_length_rp_ids(&self) -> Result<Vec<String>, Ctap2StatusCode> {
+    pub fn min_pin_length_rp_ids(&self) -> Result<Vec<String>, Ctap2StatusCode> {
         let rp_ids = self
-            .store
-            .find(key::_MIN_PIN_LENGTH_RP_IDS)?
-            .map_or(Some(_DEFAULT_MIN_PIN_LENGTH_RP_IDS), |value| {
-                _deserialize_min_pin_length_rp_ids(&value)
+            .find(key::MIN_PIN_LENGTH_RP_IDS)?
+            .map_or(Some(DEFAULT_MIN_PIN_LENGTH_RP_IDS), |value| {
+                deserialize_min_pin_length_rp_ids(&value)
             });
         debug_assert!(rp_ids.is_some());
         Ok(rp_ids.unwrap_or(vec![]))
@@ -379,60 +1094,210 @@ impl PersistentStore {
 
     /// Sets the list of RP IDs that are used to check if reading the minimum PIN length is allowed.
     #[cfg(feature = "with_ctap2_1")]
-    pub fn _set_min_pin_length_rp_ids(
+    pub fn set_min_pin_length_rp_ids(
         &mut self,
         min_pin_length_rp_ids: Vec<String>,
     ) -> Result<(), Ctap2StatusCode> {
         let mut min_pin_length_rp_ids = min_pin_length_rp_ids;
-        for rp_id in _DEFAULT_MIN_PIN_LENGTH_RP_IDS {
+        for rp_id in DEFAULT_MIN_PIN_LENGTH_RP_IDS {
             if !min_pin_length_rp_ids.contains(&rp_id) {
                 min_pin_length_rp_ids.push(rp_id);
             }
         }
-        if min_pin_length_rp_ids.len() > _MAX_RP_IDS_LENGTH {
+        if min_pin_length_rp_ids.len() > MAX_RP_IDS_LENGTH {
             return Err(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL);
         }
-        Ok(self.store.insert(
-            key::_MIN_PIN_LENGTH_RP_IDS,
-            &_serialize_min_pin_length_rp_ids(min_pin_length_rp_ids)?,
-        )?)
+        self.insert(
+            key::MIN_PIN_LENGTH_RP_IDS,
+            &serialize_min_pin_length_rp_ids(min_pin_length_rp_ids)?,
+        )
     }
 
-    /// Returns the attestation private key if defined.
-    pub fn attestation_private_key(
-        &self,
-    ) -> Result<Option<[u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH]>, Ctap2StatusCode> {
-        match self.store.find(key::ATTESTATION_PRIVATE_KEY)? {
-            None => Ok(None),
-            Some(key) if key.len() == key_material::ATTESTATION_PRIVATE_KEY_LENGTH => {
-                Ok(Some(*array_ref![
-                    key,
-                    0,
-                    key_material::ATTESTATION_PRIVATE_KEY_LENGTH
-                ]))
+    /// Returns the enrolled fingerprint templates.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn fingerprint_templates(&self) -> Result<Vec<TemplateInfo>, Ctap2StatusCode> {
+        match self.find(key::FINGERPRINT_TEMPLATES)? {
+            None => Ok(vec![]),
+            Some(value) => deserialize_fingerprint_templates(&value)
+                .ok_or(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE),
+        }
+    }
+
+    /// Stores a fingerprint template, replacing the template with the same ID if any.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn store_fingerprint_template(
+        &mut self,
+        template_info: TemplateInfo,
+    ) -> Result<(), Ctap2StatusCode> {
+        let mut templates = self.fingerprint_templates()?;
+        match templates
+            .iter_mut()
+            .find(|t| t.template_id == template_info.template_id)
+        {
+            Some(template) => *template = template_info,
+            None => {
+                if templates.len() >= MAX_FINGERPRINT_TEMPLATES {
+                    return Err(Ctap2StatusCode::CTAP2_ERR_FP_DATABASE_FULL);
+                }
+                templates.push(template_info);
             }
-            Some(_) => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
         }
+        self.insert(
+            key::FINGERPRINT_TEMPLATES,
+            &serialize_fingerprint_templates(templates)?,
+        )
     }
 
-    /// Sets the attestation private key.
-    ///
-    /// If it is already defined, it is overwritten.
-    pub fn set_attestation_private_key(
+    /// Removes the fingerprint template with the given ID, if it exists.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn remove_fingerprint_template(
         &mut self,
-        attestation_private_key: &[u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH],
+        template_id: &[u8],
     ) -> Result<(), Ctap2StatusCode> {
-        match self.store.find(key::ATTESTATION_PRIVATE_KEY)? {
-            None => Ok(self
-                .store
-                .insert(key::ATTESTATION_PRIVATE_KEY, attestation_private_key)?),
-            Some(_) => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+        let mut templates = self.fingerprint_templates()?;
+        templates.retain(|t| t.template_id != template_id);
+        if templates.is_empty() {
+            self.remove(key::FINGERPRINT_TEMPLATES)
+        } else {
+            self.insert(
+                key::FINGERPRINT_TEMPLATES,
+                &serialize_fingerprint_templates(templates)?,
+            )
         }
     }
 
-    /// Returns the attestation certificate if defined.
+    /// Returns whether the PIN needs to be changed before its next use.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn has_force_pin_change(&self) -> Result<bool, Ctap2StatusCode> {
+        match self.find(key::FORCE_PIN_CHANGE)? {
+            None => Ok(false),
+            Some(value) if value.is_empty() => Ok(true),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE),
+        }
+    }
+
+    /// Marks the PIN as outdated with respect to the new PIN policy.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn force_pin_change(&mut self) -> Result<(), Ctap2StatusCode> {
+        self.insert(key::FORCE_PIN_CHANGE, &[])
+    }
+
+    /// Returns whether alwaysUv is enabled.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn has_always_uv(&self) -> Result<bool, Ctap2StatusCode> {
+        match self.find(key::ALWAYS_UV)? {
+            None => Ok(false),
+            Some(value) if value.is_empty() => Ok(true),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE),
+        }
+    }
+
+    /// Enables alwaysUv if it was disabled, and disables it otherwise.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn toggle_always_uv(&mut self) -> Result<(), Ctap2StatusCode> {
+        if self.has_always_uv()? {
+            self.remove(key::ALWAYS_UV)
+        } else {
+            self.insert(key::ALWAYS_UV, &[])
+        }
+    }
+
+    /// Returns whether enterprise attestation is enabled.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn enterprise_attestation(&self) -> Result<bool, Ctap2StatusCode> {
+        match self.find(key::ENTERPRISE_ATTESTATION)? {
+            None => Ok(false),
+            Some(value) if value.is_empty() => Ok(true),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE),
+        }
+    }
+
+    /// Enables enterprise attestation.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn enable_enterprise_attestation(&mut self) -> Result<(), Ctap2StatusCode> {
+        self.insert(key::ENTERPRISE_ATTESTATION, &[])
+    }
+
+    /// Reads a slice of the serialized large blob array.
+    ///
+    /// At most `byte_count` bytes are returned, fewer if the array ends before. The offset must
+    /// not be past the end of the array.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn get_large_blob_array(
+        &self,
+        offset: usize,
+        byte_count: usize,
+    ) -> Result<Vec<u8>, Ctap2StatusCode> {
+        let large_blob_array = self
+            .find(key::LARGE_BLOB_ARRAY)?
+            .unwrap_or_else(|| DEFAULT_LARGE_BLOB_ARRAY.to_vec());
+        if offset > large_blob_array.len() {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+        let end = core::cmp::min(large_blob_array.len(), offset.saturating_add(byte_count));
+        Ok(large_blob_array[offset..end].to_vec())
+    }
+
+    /// Replaces the serialized large blob array.
+    ///
+    /// The integrity of the array is checked by the caller.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn commit_large_blob_array(
+        &mut self,
+        large_blob_array: &[u8],
+    ) -> Result<(), Ctap2StatusCode> {
+        if large_blob_array.len() > MAX_LARGE_BLOB_ARRAY_SIZE {
+            return Err(Ctap2StatusCode::CTAP2_ERR_LARGE_BLOB_STORAGE_FULL);
+        }
+        self.insert(key::LARGE_BLOB_ARRAY, large_blob_array)
+    }
+
+    /// Returns the attestation private key if defined.
+    ///
+    /// The key is unwrapped on each call. Callers should zeroize it once they signed.
+    pub fn attestation_private_key(
+        &self,
+    ) -> Result<Option<[u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH]>, Ctap2StatusCode> {
+        match self.find(key::ATTESTATION_PRIVATE_KEY)? {
+            None => Ok(None),
+            Some(mut private_key)
+                if private_key.len() == key_material::ATTESTATION_PRIVATE_KEY_LENGTH =>
+            {
+                let legacy_key =
+                    *array_ref![private_key, 0, key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+                private_key.zeroize();
+                Ok(Some(legacy_key))
+            }
+            Some(wrapped) => Ok(Some(unwrap_attestation_private_key(&wrapped)?)),
+        }
+    }
+
+    /// Returns whether the attestation private key is defined, without unwrapping it.
+    pub fn has_attestation_private_key(&self) -> Result<bool, Ctap2StatusCode> {
+        Ok(self
+            .store
+            .find_handle(key::ATTESTATION_PRIVATE_KEY)?
+            .is_some())
+    }
+
+    /// Sets the attestation private key.
+    ///
+    /// It can't be overwritten. The key is wrapped with the device key, so this fails with
+    /// `CTAP2_ERR_VENDOR_HARDWARE_FAILURE` if the device has none.
+    pub fn set_attestation_private_key(
+        &mut self,
+        attestation_private_key: &[u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH],
+    ) -> Result<(), Ctap2StatusCode> {
+        if self.has_attestation_private_key()? {
+            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+        }
+        let wrapped = wrap_attestation_private_key(attestation_private_key)?;
+        self.insert(key::ATTESTATION_PRIVATE_KEY, &wrapped)
+    }
+
+    /// Returns the attestation certificate if defined.
     pub fn attestation_certificate(&self) -> Result<Option<Vec<u8>>, Ctap2StatusCode> {
-        Ok(self.store.find(key::ATTESTATION_CERTIFICATE)?)
+        self.find(key::ATTESTATION_CERTIFICATE)
     }
 
     /// Sets the attestation certificate.
@@ -442,22 +1307,33 @@ impl PersistentStore {
         &mut self,
         attestation_certificate: &[u8],
     ) -> Result<(), Ctap2StatusCode> {
-        match self.store.find(key::ATTESTATION_CERTIFICATE)? {
-            None => Ok(self
-                .store
-                .insert(key::ATTESTATION_CERTIFICATE, attestation_certificate)?),
+        match self.find(key::ATTESTATION_CERTIFICATE)? {
+            None => self.insert(key::ATTESTATION_CERTIFICATE, attestation_certificate),
             Some(_) => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
         }
     }
 
+    /// Returns the intermediate certificates of the attestation certificate chain.
+    ///
+    /// The chain is empty if the root signed the attestation certificate, or if it is not defined.
+    pub fn attestation_intermediate_certificates(&self) -> Result<Vec<Vec<u8>>, Ctap2StatusCode> {
+        let mut certificates = Vec::new();
+        for key in key::ATTESTATION_INTERMEDIATE_CERTIFICATES {
+            match self.find(key)? {
+                Some(certificate) => certificates.push(certificate),
+                None => break,
+            }
+        }
+        Ok(certificates)
+    }
+
     /// Returns the AAGUID.
     pub fn aaguid(&self) -> Result<[u8; key_material::AAGUID_LENGTH], Ctap2StatusCode> {
         let aaguid = self
-            .store
             .find(key::AAGUID)?
-            .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)?;
         if aaguid.len() != key_material::AAGUID_LENGTH {
-            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+            return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
         }
         Ok(*array_ref![aaguid, 0, key_material::AAGUID_LENGTH])
     }
@@ -469,19 +1345,591 @@ impl PersistentStore {
         &mut self,
         aaguid: &[u8; key_material::AAGUID_LENGTH],
     ) -> Result<(), Ctap2StatusCode> {
-        Ok(self.store.insert(key::AAGUID, aaguid)?)
+        self.insert(key::AAGUID, aaguid)
+    }
+
+    /// Provisions the attestation material, overwriting the current one.
+    ///
+    /// The certificate chain starts with the attestation certificate, followed by its
+    /// intermediate certificates. All given values are written in a single transaction. Fails if
+    /// provisioning is locked.
+    pub fn provision_attestation(
+        &mut self,
+        aaguid: Option<&[u8; key_material::AAGUID_LENGTH]>,
+        private_key: Option<&[u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH]>,
+        certificate_chain: Option<&[Vec<u8>]>,
+    ) -> Result<(), Ctap2StatusCode> {
+        if self.is_provisioning_locked()? {
+            return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED);
+        }
+        let mut updates = Vec::new();
+        if let Some(aaguid) = aaguid {
+            updates.push(StoreUpdate::Insert {
+                key: key::AAGUID,
+                value: aaguid.to_vec(),
+            });
+        }
+        if let Some(private_key) = private_key {
+            updates.push(StoreUpdate::Insert {
+                key: key::ATTESTATION_PRIVATE_KEY,
+                value: wrap_attestation_private_key(private_key)?,
+            });
+        }
+        if let Some(certificate_chain) = certificate_chain {
+            let (certificate, intermediates) = certificate_chain
+                .split_first()
+                .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+            if intermediates.len() > key::ATTESTATION_INTERMEDIATE_CERTIFICATES.len() {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+            }
+            updates.push(StoreUpdate::Insert {
+                key: key::ATTESTATION_CERTIFICATE,
+                value: certificate.clone(),
+            });
+            // Intermediate certificates of a longer previous chain are removed.
+            for (i, key) in key::ATTESTATION_INTERMEDIATE_CERTIFICATES.enumerate() {
+                updates.push(match intermediates.get(i) {
+                    Some(intermediate) => StoreUpdate::Insert {
+                        key,
+                        value: intermediate.clone(),
+                    },
+                    None => StoreUpdate::Remove { key },
+                });
+            }
+        }
+        self.apply(&updates)
+    }
+
+    /// Returns whether the attestation material is locked.
+    pub fn is_provisioning_locked(&self) -> Result<bool, Ctap2StatusCode> {
+        Ok(self.store.find_handle(key::PROVISIONING_LOCKED)?.is_some())
+    }
+
+    /// Permanently locks the attestation material.
+    pub fn lock_provisioning(&mut self) -> Result<(), Ctap2StatusCode> {
+        self.insert(key::PROVISIONING_LOCKED, &[])
+    }
+
+    /// Returns the credProtect policy configured by the vendor, if any.
+    pub fn default_cred_protect(
+        &self,
+    ) -> Result<Option<CredentialProtectionPolicy>, Ctap2StatusCode> {
+        match self.find(key::DEFAULT_CRED_PROTECT)? {
+            None => Ok(None),
+            Some(value) if value.len() == 1 => {
+                CredentialProtectionPolicy::try_from(cbor::Value::from(value[0] as i64))
+                    .map(Some)
+                    .map_err(|_| Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+            }
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE),
+        }
+    }
+
+    /// Sets the credProtect policy for credentials whose relying party doesn't request one.
+    pub fn set_default_cred_protect(
+        &mut self,
+        policy: CredentialProtectionPolicy,
+    ) -> Result<(), Ctap2StatusCode> {
+        self.insert(key::DEFAULT_CRED_PROTECT, &[policy as u8])
+    }
+
+    /// Returns the user presence timeout in milliseconds configured by the vendor, if any.
+    pub fn touch_timeout_ms(&self) -> Result<Option<u32>, Ctap2StatusCode> {
+        self.find_u32(key::TOUCH_TIMEOUT)
+    }
+
+    /// Sets how long user presence checks wait for a touch, in milliseconds.
+    pub fn set_touch_timeout_ms(&mut self, timeout_ms: u32) -> Result<(), Ctap2StatusCode> {
+        self.insert(key::TOUCH_TIMEOUT, &timeout_ms.to_ne_bytes())
+    }
+
+    /// Returns the touch duration in milliseconds configured by the vendor, if any.
+    pub fn touch_duration_ms(&self) -> Result<Option<u32>, Ctap2StatusCode> {
+        self.find_u32(key::TOUCH_DURATION)
+    }
+
+    /// Sets how long the button must be held for a touch to count, in milliseconds.
+    pub fn set_touch_duration_ms(&mut self, duration_ms: u32) -> Result<(), Ctap2StatusCode> {
+        self.insert(key::TOUCH_DURATION, &duration_ms.to_ne_bytes())
+    }
+
+    /// Returns whether destructive operations need a second touch, if configured by the vendor.
+    pub fn confirm_with_second_touch(&self) -> Result<Option<bool>, Ctap2StatusCode> {
+        match self.find(key::CONFIRM_WITH_SECOND_TOUCH)? {
+            None => Ok(None),
+            Some(value) if value.len() == 1 && value[0] <= 1 => Ok(Some(value[0] == 1)),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE),
+        }
+    }
+
+    /// Sets whether destructive operations need a second touch.
+    pub fn set_confirm_with_second_touch(&mut self, confirm: bool) -> Result<(), Ctap2StatusCode> {
+        self.insert(key::CONFIRM_WITH_SECOND_TOUCH, &[confirm as u8])
+    }
+
+    /// Writes the scratch entry of the benchmark, replacing the previous one.
+    pub fn write_benchmark_entry(&mut self, value: &[u8]) -> Result<(), Ctap2StatusCode> {
+        self.insert(key::BENCHMARK, value)
+    }
+
+    /// Removes the scratch entry of the benchmark, if present.
+    pub fn remove_benchmark_entry(&mut self) -> Result<(), Ctap2StatusCode> {
+        self.remove(key::BENCHMARK)
+    }
+
+    /// Compacts one page of the store.
+    ///
+    /// Compaction erases a page, so this costs some lifetime of the flash.
+    pub fn compact_once(&mut self) -> Result<(), Ctap2StatusCode> {
+        Ok(self.store.compact()?)
+    }
+
+    /// Compacts one page of the store if the next insertions might need it.
+    ///
+    /// Returns whether a page was compacted. Calling this function until it returns false, while
+    /// the device is idle, keeps compaction out of the commands that insert entries of up to the
+    /// maximum value length, such as credentials. The external flash, if any, is compacted once
+    /// the embedded flash doesn't need it.
+    pub fn compact_step(&mut self) -> Result<bool, Ctap2StatusCode> {
+        let length = self.store.entry_capacity(self.store.max_value_length());
+        if self.store.compact_step(length)? {
+            return Ok(true);
+        }
+        match &mut self.external_store {
+            None => Ok(false),
+            Some(external_store) => {
+                let length = external_store.entry_capacity(external_store.max_value_length());
+                Ok(external_store.compact_step(length)?)
+            }
+        }
+    }
+
+    /// Returns how worn the embedded flash of the store is.
+    pub fn wear(&self) -> Result<persistent_store::StoreWear, Ctap2StatusCode> {
+        Ok(self.store.wear()?)
+    }
+
+    /// Applies the writes of an operation atomically.
+    ///
+    /// The writes of the setters called by the operation are collected, and applied in a single
+    /// store transaction if the operation succeeds. A power loss then keeps either all or none of
+    /// them. If the operation fails, nothing is written. Reads inside the operation don't see its
+    /// writes, and transactions can't be nested.
+    pub fn transaction<T>(
+        &mut self,
+        operation: impl FnOnce(&mut Self) -> Result<T, Ctap2StatusCode>,
+    ) -> Result<T, Ctap2StatusCode> {
+        if self.pending_updates.is_some() {
+            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+        }
+        self.pending_updates = Some(Vec::new());
+        let result = operation(self);
+        let mut updates = self.pending_updates.take().unwrap_or_default();
+        let result = match result {
+            Ok(value) => self.commit(&updates).map(|()| value),
+            Err(error) => Err(error),
+        };
+        // Values may hold secrets, such as the private keys of credentials.
+        for update in &mut updates {
+            if let StoreUpdate::Insert { value, .. } = update {
+                value.zeroize();
+            }
+        }
+        result
+    }
+
+    /// Writes updates in a single store transaction per flash.
+    ///
+    /// The external flash is written first. Updates to both flashes are therefore not atomic: a
+    /// power loss in between keeps the updates of the external flash only.
+    fn commit(&mut self, updates: &[StoreUpdate]) -> Result<(), Ctap2StatusCode> {
+        if let Some(external_store) = &mut self.external_store {
+            let external_updates: Vec<StoreUpdate> = updates
+                .iter()
+                .filter(|update| is_external(update.key()))
+                .cloned()
+                .collect();
+            let updates: Vec<StoreUpdate> = updates
+                .iter()
+                .filter(|update| !is_external(update.key()))
+                .cloned()
+                .collect();
+            let mut result = Ok(());
+            if !external_updates.is_empty() {
+                result = external_store.transaction(&external_updates);
+            }
+            if result.is_ok() && !updates.is_empty() {
+                result = self.store.transaction(&updates);
+            }
+            for mut update in external_updates.into_iter().chain(updates) {
+                if let StoreUpdate::Insert { value, .. } = &mut update {
+                    value.zeroize();
+                }
+            }
+            return Ok(result?);
+        }
+        Ok(self.store.transaction(updates)?)
+    }
+
+    /// Fails if the store has a schema version unknown to this firmware.
+    fn check_schema(&self) -> Result<(), Ctap2StatusCode> {
+        if self.unknown_schema {
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the value of an entry, decrypted if its partition is encrypted.
+    fn find(&self, key: usize) -> Result<Option<Vec<u8>>, Ctap2StatusCode> {
+        self.check_schema()?;
+        match self.store_of(key).find(key)? {
+            Some(value) if is_encrypted(self.encrypted_partitions, key) => {
+                Ok(Some(decrypt_entry(key, &value)?))
+            }
+            value => Ok(value),
+        }
+    }
+
+    /// Inserts an entry, or adds it to the ongoing transaction.
+    ///
+    /// The value is encrypted if its partition is encrypted.
+    fn insert(&mut self, key: usize, value: &[u8]) -> Result<(), Ctap2StatusCode> {
+        self.check_schema()?;
+        if is_encrypted(self.encrypted_partitions, key) {
+            return self.apply(&[StoreUpdate::Insert {
+                key,
+                value: value.to_vec(),
+            }]);
+        }
+        match &mut self.pending_updates {
+            None => Ok(self.store_of_mut(key).insert(key, value)?),
+            Some(updates) => {
+                add_pending_update(
+                    updates,
+                    StoreUpdate::Insert {
+                        key,
+                        value: value.to_vec(),
+                    },
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Removes an entry, or adds its removal to the ongoing transaction.
+    fn remove(&mut self, key: usize) -> Result<(), Ctap2StatusCode> {
+        self.check_schema()?;
+        match &mut self.pending_updates {
+            None => Ok(self.store_of_mut(key).remove(key)?),
+            Some(updates) => {
+                add_pending_update(updates, StoreUpdate::Remove { key });
+                Ok(())
+            }
+        }
+    }
+
+    /// Applies updates in a single transaction, or adds them to the ongoing transaction.
+    ///
+    /// The values of encrypted partitions are encrypted, and the plaintexts wiped.
+    fn apply(&mut self, updates: &[StoreUpdate]) -> Result<(), Ctap2StatusCode> {
+        self.check_schema()?;
+        let mut encrypted_updates = Vec::with_capacity(updates.len());
+        for update in updates {
+            encrypted_updates.push(match update {
+                StoreUpdate::Insert { key, value }
+                    if is_encrypted(self.encrypted_partitions, *key) =>
+                {
+                    StoreUpdate::Insert {
+                        key: *key,
+                        value: encrypt_entry(*key, value)?,
+                    }
+                }
+                update => update.clone(),
+            });
+        }
+        let result = match &mut self.pending_updates {
+            None => self.commit(&encrypted_updates),
+            Some(pending_updates) => {
+                for update in encrypted_updates.drain(..) {
+                    add_pending_update(pending_updates, update);
+                }
+                Ok(())
+            }
+        };
+        for update in &mut encrypted_updates {
+            if let StoreUpdate::Insert { value, .. } = update {
+                value.zeroize();
+            }
+        }
+        result
+    }
+
+    /// Returns the 32 bit value of an entry, if present.
+    fn find_u32(&self, key: usize) -> Result<Option<u32>, Ctap2StatusCode> {
+        match self.find(key)? {
+            None => Ok(None),
+            Some(value) if value.len() == 4 => {
+                Ok(Some(u32::from_ne_bytes(*array_ref!(&value, 0, 4))))
+            }
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE),
+        }
     }
 
     /// Resets the store as for a CTAP reset.
     ///
-    /// In particular persistent entries are not reset.
+    /// In particular persistent entries are not reset. A store of unknown schema version is not
+    /// reset either, since its persistent entries would stay in the unknown layout. Like deletions,
+    /// the reset wipes the credentials from the flash.
     pub fn reset(&mut self, rng: &mut impl Rng256) -> Result<(), Ctap2StatusCode> {
+        self.check_schema()?;
         self.store.clear(key::NUM_PERSISTENT_KEYS)?;
+        if let Some(external_store) = &mut self.external_store {
+            external_store.clear(key::NUM_PERSISTENT_KEYS)?;
+        }
         self.init(rng)?;
         Ok(())
     }
 }
 
+/// Returns whether the entry of a key is stored in the external flash, on boards that have one.
+fn is_external(key: usize) -> bool {
+    EXTERNAL_PARTITIONS
+        .iter()
+        .any(|partition| partition.contains(&key))
+}
+
+/// Returns the storage key of a generation of master keys, if the generation fits.
+fn master_keys_key(generation: u8) -> Option<usize> {
+    match generation {
+        0 => Some(key::MASTER_KEYS),
+        _ => {
+            let key = key::ROTATED_MASTER_KEYS.start + generation as usize - 1;
+            if key < key::ROTATED_MASTER_KEYS.end {
+                Some(key)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Returns new random master keys, serialized as the encryption key followed by the hmac key.
+fn generate_master_keys(rng: &mut impl Rng256) -> Vec<u8> {
+    let mut master_keys = Vec::with_capacity(64);
+    master_keys.extend_from_slice(&rng.gen_uniform_u8x32());
+    master_keys.extend_from_slice(&rng.gen_uniform_u8x32());
+    master_keys
+}
+
+/// Returns the key that wraps the attestation private key.
+///
+/// Fails with `CTAP2_ERR_VENDOR_HARDWARE_FAILURE` if the device has no device key.
+fn attestation_wrapping_key() -> Result<[u8; 32], Ctap2StatusCode> {
+    device_key::derive(ATTESTATION_WRAPPING_PURPOSE)
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)
+}
+
+/// Returns the AES key derived from the wrapping key.
+fn attestation_encryption_key(wrapping_key: &[u8; 32]) -> crypto::aes256::EncryptionKey {
+    let mut key = hmac_256::<Sha256>(wrapping_key, b"encryption");
+    let encryption_key = crypto::aes256::EncryptionKey::new(&key);
+    key.zeroize();
+    encryption_key
+}
+
+/// Encrypts the attestation private key under the device key.
+///
+/// The nonce is derived from the private key, so that wrapping needs no random generator. A nonce
+/// only repeats for the same private key, which then wraps to the same value.
+fn wrap_attestation_private_key(
+    private_key: &[u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH],
+) -> Result<Vec<u8>, Ctap2StatusCode> {
+    let mut wrapping_key = attestation_wrapping_key()?;
+    let encryption_key = attestation_encryption_key(&wrapping_key);
+    let mut nonce_hmac = Hmac256::<Sha256>::new(&wrapping_key);
+    wrapping_key.zeroize();
+    nonce_hmac.update(b"nonce");
+    nonce_hmac.update(private_key);
+    let digest = nonce_hmac.finalize();
+    let nonce = *array_ref![digest, 0, NONCE_SIZE];
+
+    let mut wrapped = Vec::with_capacity(WRAPPED_ATTESTATION_PRIVATE_KEY_LENGTH);
+    wrapped.extend_from_slice(&nonce);
+    wrapped.extend_from_slice(private_key);
+    let tag = gcm_encrypt(
+        &encryption_key,
+        &nonce,
+        ATTESTATION_WRAPPING_PURPOSE,
+        &mut wrapped[NONCE_SIZE..],
+    );
+    wrapped.extend_from_slice(&tag);
+    Ok(wrapped)
+}
+
+/// Decrypts the attestation private key.
+///
+/// Fails with `CTAP2_ERR_INTEGRITY_FAILURE` if the key was modified, or wrapped on another device.
+fn unwrap_attestation_private_key(
+    wrapped: &[u8],
+) -> Result<[u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH], Ctap2StatusCode> {
+    if wrapped.len() != WRAPPED_ATTESTATION_PRIVATE_KEY_LENGTH {
+        return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
+    }
+    let mut wrapping_key = attestation_wrapping_key()?;
+    let encryption_key = attestation_encryption_key(&wrapping_key);
+    wrapping_key.zeroize();
+    let nonce = array_ref![wrapped, 0, NONCE_SIZE];
+    let mut private_key = *array_ref![
+        wrapped,
+        NONCE_SIZE,
+        key_material::ATTESTATION_PRIVATE_KEY_LENGTH
+    ];
+    let tag = array_ref![
+        wrapped,
+        WRAPPED_ATTESTATION_PRIVATE_KEY_LENGTH - TAG_SIZE,
+        TAG_SIZE
+    ];
+    if !gcm_decrypt(
+        &encryption_key,
+        nonce,
+        ATTESTATION_WRAPPING_PURPOSE,
+        &mut private_key,
+        tag,
+    ) {
+        return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
+    }
+    Ok(private_key)
+}
+
+/// Returns whether the value of the key is encrypted.
+fn is_encrypted(encrypted_partitions: &[Range<usize>], key: usize) -> bool {
+    encrypted_partitions
+        .iter()
+        .any(|partition| partition.contains(&key))
+}
+
+/// Returns the AES key and the nonce key of encrypted partitions.
+fn store_encryption_keys() -> Result<(crypto::aes256::EncryptionKey, [u8; 32]), Ctap2StatusCode> {
+    let mut root_key = device_key::derive(STORE_ENCRYPTION_PURPOSE)
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE)?;
+    let mut key = hmac_256::<Sha256>(&root_key, b"encryption");
+    let encryption_key = crypto::aes256::EncryptionKey::new(&key);
+    key.zeroize();
+    let nonce_key = hmac_256::<Sha256>(&root_key, b"nonce");
+    root_key.zeroize();
+    Ok((encryption_key, nonce_key))
+}
+
+/// Encrypts the value of an entry, as nonce, ciphertext and tag.
+///
+/// Like for the attestation private key, the nonce is derived from the value, so that no random
+/// generator is needed. The store key is authenticated, so that values can't be moved to another
+/// key. Encryption doesn't change how the entry is written, so it keeps the guarantees of the
+/// store in case of power loss.
+fn encrypt_entry(key: usize, value: &[u8]) -> Result<Vec<u8>, Ctap2StatusCode> {
+    let (encryption_key, mut nonce_key) = store_encryption_keys()?;
+    let associated_data = (key as u32).to_be_bytes();
+    let mut nonce_hmac = Hmac256::<Sha256>::new(&nonce_key);
+    nonce_key.zeroize();
+    nonce_hmac.update(&associated_data);
+    nonce_hmac.update(value);
+    let digest = nonce_hmac.finalize();
+    let nonce = *array_ref![digest, 0, NONCE_SIZE];
+
+    let mut encrypted = Vec::with_capacity(NONCE_SIZE + value.len() + TAG_SIZE);
+    encrypted.extend_from_slice(&nonce);
+    encrypted.extend_from_slice(value);
+    let tag = gcm_encrypt(
+        &encryption_key,
+        &nonce,
+        &associated_data,
+        &mut encrypted[NONCE_SIZE..],
+    );
+    encrypted.extend_from_slice(&tag);
+    Ok(encrypted)
+}
+
+/// Decrypts the value of an entry.
+///
+/// Fails with `CTAP2_ERR_INTEGRITY_FAILURE` if the entry was modified, written on another device,
+/// or written before its partition was encrypted.
+fn decrypt_entry(key: usize, encrypted: &[u8]) -> Result<Vec<u8>, Ctap2StatusCode> {
+    if encrypted.len() < NONCE_SIZE + TAG_SIZE {
+        return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
+    }
+    let (encryption_key, mut nonce_key) = store_encryption_keys()?;
+    nonce_key.zeroize();
+    let associated_data = (key as u32).to_be_bytes();
+    let nonce = array_ref![encrypted, 0, NONCE_SIZE];
+    let tag = array_ref![encrypted, encrypted.len() - TAG_SIZE, TAG_SIZE];
+    let mut value = encrypted[NONCE_SIZE..encrypted.len() - TAG_SIZE].to_vec();
+    if !gcm_decrypt(&encryption_key, nonce, &associated_data, &mut value, tag) {
+        value.zeroize();
+        return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
+    }
+    Ok(value)
+}
+
+/// Returns the prefix of the RP ID hash of a credential, as stored in the credential index.
+fn credential_index_hash_prefix(
+    credential: &PublicKeyCredentialSource,
+) -> [u8; CREDENTIAL_INDEX_HASH_LENGTH] {
+    let rp_id_hash = Sha256::hash(credential.rp_id.as_bytes());
+    *array_ref![rp_id_hash, 0, CREDENTIAL_INDEX_HASH_LENGTH]
+}
+
+/// Returns the store update that writes the credential index, or removes it if it is empty.
+fn credential_index_update(index: &[(usize, [u8; CREDENTIAL_INDEX_HASH_LENGTH])]) -> StoreUpdate {
+    if index.is_empty() {
+        return StoreUpdate::Remove {
+            key: key::CREDENTIAL_INDEX,
+        };
+    }
+    let mut value = Vec::with_capacity(index.len() * CREDENTIAL_INDEX_RECORD_LENGTH);
+    for (key, hash_prefix) in index {
+        value.extend_from_slice(&(*key as u16).to_be_bytes());
+        value.extend_from_slice(hash_prefix);
+    }
+    StoreUpdate::Insert {
+        key: key::CREDENTIAL_INDEX,
+        value,
+    }
+}
+
+/// Deserializes the credential index, or returns `None` if it is malformed.
+fn deserialize_credential_index(
+    value: &[u8],
+) -> Option<Vec<(usize, [u8; CREDENTIAL_INDEX_HASH_LENGTH])>> {
+    if value.len() % CREDENTIAL_INDEX_RECORD_LENGTH != 0 {
+        return None;
+    }
+    Some(
+        value
+            .chunks(CREDENTIAL_INDEX_RECORD_LENGTH)
+            .map(|record| {
+                (
+                    u16::from_be_bytes(*array_ref![record, 0, 2]) as usize,
+                    *array_ref![record, 2, CREDENTIAL_INDEX_HASH_LENGTH],
+                )
+            })
+            .collect(),
+    )
+}
+
+/// Adds an update to a transaction, replacing the previous update of the same key.
+///
+/// The store refuses transactions that update a key twice.
+fn add_pending_update(updates: &mut Vec<StoreUpdate>, update: StoreUpdate) {
+    if let Some(index) = updates.iter().position(|u| u.key() == update.key()) {
+        if let StoreUpdate::Insert { value, .. } = &mut updates[index] {
+            value.zeroize();
+        }
+        updates.remove(index);
+    }
+    updates.push(update);
+}
+
 impl From<persistent_store::StoreError> for Ctap2StatusCode {
     fn from(error: persistent_store::StoreError) -> Ctap2StatusCode {
         use persistent_store::StoreError;
@@ -495,21 +1943,24 @@ impl From<persistent_store::StoreError> for Ctap2StatusCode {
             StoreError::InvalidArgument => Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR,
             // This error is not expected. The storage has been tempered with. We could erase the
             // storage.
-            StoreError::InvalidStorage => Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE,
+            StoreError::InvalidStorage => Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE,
             // This error is not expected. The kernel is failing our syscalls.
-            StoreError::StorageError => Ctap2StatusCode::CTAP1_ERR_OTHER,
+            StoreError::StorageError => Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE,
         }
     }
 }
 
 /// Iterator for credentials.
-struct IterCredentials<'a> {
+pub struct IterCredentials<'a> {
     /// The store being iterated.
     store: &'a persistent_store::Store<Storage>,
 
     /// The store iterator.
     iter: persistent_store::StoreIter<'a, Storage>,
 
+    /// The key ranges whose values are encrypted.
+    encrypted_partitions: &'a [Range<usize>],
+
     /// The iteration result.
     ///
     /// It starts as success and gets written at most once with an error if something fails. The
@@ -521,26 +1972,31 @@ impl<'a> IterCredentials<'a> {
     /// Creates a credential iterator.
     fn new(
         store: &'a persistent_store::Store<Storage>,
+        encrypted_partitions: &'a [Range<usize>],
         result: &'a mut Result<(), Ctap2StatusCode>,
     ) -> Result<IterCredentials<'a>, Ctap2StatusCode> {
         let iter = store.iter()?;
         Ok(IterCredentials {
             store,
             iter,
+            encrypted_partitions,
             result,
         })
     }
 
-    /// Marks the iteration as failed if the content is absent.
+    /// Marks the iteration as failed if the content is an error.
     ///
     /// For convenience, the function takes and returns ownership instead of taking a shared
     /// reference and returning nothing. This permits to use it in both expressions and statements
     /// instead of statements only.
-    fn unwrap<T>(&mut self, x: Option<T>) -> Option<T> {
-        if x.is_none() {
-            *self.result = Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+    fn unwrap<T>(&mut self, x: Result<T, Ctap2StatusCode>) -> Option<T> {
+        match x {
+            Ok(x) => Some(x),
+            Err(error) => {
+                *self.result = Err(error);
+                None
+            }
         }
-        x
     }
 }
 
@@ -552,27 +2008,87 @@ impl<'a> Iterator for IterCredentials<'a> {
             return None;
         }
         while let Some(next) = self.iter.next() {
-            let handle = self.unwrap(next.ok())?;
+            let handle = self.unwrap(next.map_err(Ctap2StatusCode::from))?;
             let key = handle.get_key();
             if !key::CREDENTIALS.contains(&key) {
                 continue;
             }
-            let value = self.unwrap(handle.get_value(&self.store).ok())?;
-            let credential = self.unwrap(deserialize_credential(&value))?;
-            return Some((key, credential));
+            let value =
+                self.unwrap(handle.get_value(&self.store).map_err(Ctap2StatusCode::from))?;
+            // Corrupted credentials are skipped, until they are quarantined at the next boot.
+            if let Some(credential) =
+                self.unwrap(read_credential_entry(self.encrypted_partitions, key, value))?
+            {
+                return Some((key, credential));
+            }
         }
         None
     }
 }
 
+/// Reads the credential of an entry, as stored in the credential partition.
+///
+/// Returns `None` if the entry is corrupted: it doesn't decrypt, checksum or deserialize. Other
+/// errors, such as a missing device key, are returned. The value is wiped afterwards.
+fn read_credential_entry(
+    encrypted_partitions: &[Range<usize>],
+    key: usize,
+    value: Vec<u8>,
+) -> Result<Option<PublicKeyCredentialSource>, Ctap2StatusCode> {
+    let mut value = if is_encrypted(encrypted_partitions, key) {
+        match decrypt_entry(key, &value) {
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE) => return Ok(None),
+            result => result?,
+        }
+    } else {
+        value
+    };
+    if value.len() < CREDENTIAL_CHECKSUM_LENGTH {
+        value.zeroize();
+        return Ok(None);
+    }
+    let length = value.len() - CREDENTIAL_CHECKSUM_LENGTH;
+    let checksum = u32::from_be_bytes(*array_ref![value, length, CREDENTIAL_CHECKSUM_LENGTH]);
+    if crc32(&value[..length]) != checksum {
+        value.zeroize();
+        return Ok(None);
+    }
+    value.truncate(length);
+    Ok(deserialize_credential(value))
+}
+
+/// Appends the checksum of a serialized credential.
+fn append_checksum(value: &mut Vec<u8>) {
+    let checksum = crc32(value);
+    value.extend_from_slice(&checksum.to_be_bytes());
+}
+
+/// Returns the CRC-32 of the data, as in IEEE 802.3.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
 /// Deserializes a credential from storage representation.
-fn deserialize_credential(data: &[u8]) -> Option<PublicKeyCredentialSource> {
-    let cbor = cbor::read(data).ok()?;
-    cbor.try_into().ok()
+///
+/// The representation contains the private key, so it is wiped afterwards.
+fn deserialize_credential(mut data: Vec<u8>) -> Option<PublicKeyCredentialSource> {
+    let cbor = cbor::read(&data);
+    data.zeroize();
+    cbor.ok()?.try_into().ok()
 }
 
 /// Serializes a credential to storage representation.
-fn serialize_credential(credential: PublicKeyCredentialSource) -> Result<Vec<u8>, Ctap2StatusCode> {
+pub fn serialize_credential(
+    credential: PublicKeyCredentialSource,
+) -> Result<Vec<u8>, Ctap2StatusCode> {
     let mut data = Vec::new();
     if cbor::write(credential.into(), &mut data) {
         Ok(data)
@@ -583,7 +2099,7 @@ fn serialize_credential(credential: PublicKeyCredentialSource) -> Result<Vec<u8>
 
 /// Deserializes a list of RP IDs from storage representation.
 #[cfg(feature = "with_ctap2_1")]
-fn _deserialize_min_pin_length_rp_ids(data: &[u8]) -> Option<Vec<String>> {
+fn deserialize_min_pin_length_rp_ids(data: &[u8]) -> Option<Vec<String>> {
     let cbor = cbor::read(data).ok()?;
     extract_array(cbor)
         .ok()?
@@ -595,7 +2111,7 @@ fn _deserialize_min_pin_length_rp_ids(data: &[u8]) -> Option<Vec<String>> {
 
 /// Serializes a list of RP IDs to storage representation.
 #[cfg(feature = "with_ctap2_1")]
-fn _serialize_min_pin_length_rp_ids(rp_ids: Vec<String>) -> Result<Vec<u8>, Ctap2StatusCode> {
+fn serialize_min_pin_length_rp_ids(rp_ids: Vec<String>) -> Result<Vec<u8>, Ctap2StatusCode> {
     let mut data = Vec::new();
     if cbor::write(cbor_array_vec!(rp_ids), &mut data) {
         Ok(data)
@@ -604,18 +2120,51 @@ fn _serialize_min_pin_length_rp_ids(rp_ids: Vec<String>) -> Result<Vec<u8>, Ctap
     }
 }
 
+/// Deserializes a list of fingerprint templates from storage representation.
+#[cfg(feature = "with_ctap2_1")]
+fn deserialize_fingerprint_templates(data: &[u8]) -> Option<Vec<TemplateInfo>> {
+    let cbor = cbor::read(data).ok()?;
+    extract_array(cbor)
+        .ok()?
+        .into_iter()
+        .map(TemplateInfo::try_from)
+        .collect::<Result<Vec<TemplateInfo>, Ctap2StatusCode>>()
+        .ok()
+}
+
+/// Serializes a list of fingerprint templates to storage representation.
+#[cfg(feature = "with_ctap2_1")]
+fn serialize_fingerprint_templates(
+    templates: Vec<TemplateInfo>,
+) -> Result<Vec<u8>, Ctap2StatusCode> {
+    let mut data = Vec::new();
+    if cbor::write(cbor_array_vec!(templates), &mut data) {
+        Ok(data)
+    } else {
+        Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_RESPONSE_CANNOT_WRITE_CBOR)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::ctap::data_formats::{PublicKeyCredentialSource, PublicKeyCredentialType};
+    use crate::ctap::data_formats::{
+        PrivateKey, PublicKeyCredentialSource, PublicKeyCredentialType,
+    };
     use crypto::rng256::{Rng256, ThreadRng256};
-
+    #[cfg(feature = "with_ctap2_1")]
+    use crypto::sha256::Sha256;
+    #[cfg(feature = "with_ctap2_1")]
+    use crypto::Hash256;
+    #[cfg(feature = "with_ctap2_1")]
+    use persistent_store::{Storage as _, StorageIndex};
+
     fn create_credential_source(
         rng: &mut ThreadRng256,
         rp_id: &str,
         user_handle: Vec<u8>,
     ) -> PublicKeyCredentialSource {
-        let private_key = crypto::ecdsa::SecKey::gensk(rng);
+        let private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(rng).into();
         PublicKeyCredentialSource {
             key_type: PublicKeyCredentialType::PublicKey,
             credential_id: rng.gen_uniform_u8x32().to_vec(),
@@ -625,8 +2174,12 @@ mod test {
             user_display_name: None,
             cred_protect_policy: None,
             creation_order: 0,
+            last_used_order: None,
+            signature_counter: None,
             user_name: None,
             user_icon: None,
+            large_blob_key: None,
+            cred_blob: None,
         }
     }
 
@@ -655,6 +2208,432 @@ mod test {
         assert!(persistent_store.new_creation_order().unwrap() > current_latest_creation);
     }
 
+    #[test]
+    fn test_record_credential_use() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut credential_source = create_credential_source(&mut rng, "example.com", vec![]);
+        credential_source.creation_order = persistent_store.new_creation_order().unwrap();
+        let credential_id = credential_source.credential_id.clone();
+        assert!(persistent_store.store_credential(credential_source).is_ok());
+        let stored_credential = persistent_store
+            .find_credential("example.com", &credential_id, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored_credential.last_used_order, None);
+
+        // Credentials without their own counter continue from the global counter.
+        let global_counter = persistent_store.global_signature_counter().unwrap();
+        assert_eq!(
+            persistent_store.record_credential_use(&credential_id, 3),
+            Ok(Some(global_counter + 3))
+        );
+        let stored_credential = persistent_store
+            .find_credential("example.com", &credential_id, false)
+            .unwrap()
+            .unwrap();
+        let last_used_order = stored_credential.last_used_order.unwrap();
+        assert!(last_used_order > stored_credential.creation_order);
+        assert!(persistent_store.new_creation_order().unwrap() > last_used_order);
+        assert_eq!(
+            stored_credential.signature_counter,
+            Some(global_counter + 3)
+        );
+        assert_eq!(persistent_store.count_credentials().unwrap(), 1);
+
+        assert_eq!(
+            persistent_store.record_credential_use(&credential_id, 2),
+            Ok(Some(global_counter + 5))
+        );
+        assert_eq!(
+            persistent_store.global_signature_counter(),
+            Ok(global_counter)
+        );
+
+        // Unknown credentials are ignored.
+        assert_eq!(
+            persistent_store.record_credential_use(&[0x55; 32], 1),
+            Ok(None)
+        );
+        assert_eq!(persistent_store.count_credentials().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_get_credential() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![0x1D]);
+        let credential_id = credential_source.credential_id.clone();
+        assert!(persistent_store.store_credential(credential_source).is_ok());
+
+        let mut iter_result = Ok(());
+        let iter = persistent_store.iter_credentials(&mut iter_result).unwrap();
+        let keys: Vec<usize> = iter.map(|(key, _)| key).collect();
+        iter_result.unwrap();
+        assert_eq!(keys.len(), 1);
+        let stored_credential = persistent_store.get_credential(keys[0]).unwrap();
+        assert_eq!(stored_credential.credential_id, credential_id);
+        assert_eq!(
+            persistent_store.get_credential(key::CREDENTIALS.start - 1),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
+        );
+    }
+
+    #[test]
+    fn test_encrypted_partition() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        // A credential written before its partition is encrypted can't be read anymore, and is
+        // skipped as corrupted.
+        let old_credential = create_credential_source(&mut rng, "example.com", vec![0x1C]);
+        persistent_store.store_credential(old_credential).unwrap();
+        persistent_store.encrypted_partitions = &[key::CREDENTIALS];
+        assert_eq!(persistent_store.count_credentials(), Ok(0));
+        persistent_store.reset(&mut rng).unwrap();
+
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![0x1D]);
+        let credential_id = credential_source.credential_id.clone();
+        persistent_store
+            .store_credential(credential_source)
+            .unwrap();
+        let (key, credential) = persistent_store
+            .find_credential_by_id(&credential_id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(credential.rp_id, "example.com");
+        assert_eq!(
+            persistent_store.get_credential(key).unwrap().user_handle,
+            vec![0x1D]
+        );
+
+        // The flash holds neither the RP ID nor the user handle.
+        let raw_value = persistent_store.store.find(key).unwrap().unwrap();
+        assert!(!raw_value.windows(11).any(|w| w == b"example.com"));
+        assert_eq!(
+            decrypt_entry(key, &raw_value).unwrap().len() + NONCE_SIZE + TAG_SIZE,
+            raw_value.len()
+        );
+
+        // An entry moved to another key doesn't decrypt.
+        assert_eq!(
+            decrypt_entry(key + 1, &raw_value),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+        let mut modified_value = raw_value;
+        modified_value[NONCE_SIZE] ^= 0x01;
+        persistent_store.store.insert(key, &modified_value).unwrap();
+        assert_eq!(
+            persistent_store.get_credential(key).err(),
+            Some(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_remaining_credentials_free_space() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![0x1D]);
+        // The credential index also grows, but only the credentials are counted.
+        let credential_length = serialize_credential(credential_source.clone())
+            .unwrap()
+            .len()
+            + CREDENTIAL_CHECKSUM_LENGTH;
+        let credential_capacity = persistent_store.store.entry_capacity(credential_length);
+        persistent_store
+            .store_credential(credential_source)
+            .unwrap();
+
+        // Fill the store, until a few credentials of the same size fit. Keys above the partition
+        // are free in tests.
+        let filler = vec![0x5C; persistent_store.store.max_value_length()];
+        let filler_capacity = persistent_store.store.entry_capacity(filler.len());
+        let mut filler_key = 2048;
+        while persistent_store.store.capacity().unwrap().remaining()
+            > filler_capacity + 5 * credential_capacity
+        {
+            persistent_store.store.insert(filler_key, &filler).unwrap();
+            filler_key += 1;
+        }
+        let free_capacity = persistent_store.store.capacity().unwrap().remaining();
+        let remaining = persistent_store.remaining_credentials().unwrap();
+        assert_eq!(remaining, free_capacity / credential_capacity);
+        assert!(remaining < MAX_SUPPORTED_RESIDENTIAL_KEYS - 1);
+
+        // Deleting the filler frees space for more credentials.
+        persistent_store.store.remove(filler_key - 1).unwrap();
+        assert!(persistent_store.remaining_credentials().unwrap() > remaining);
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_delete_credential() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        assert_eq!(
+            persistent_store.remaining_credentials().unwrap(),
+            MAX_SUPPORTED_RESIDENTIAL_KEYS
+        );
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![0x1D]);
+        let credential_id = credential_source.credential_id.clone();
+        assert!(persistent_store.store_credential(credential_source).is_ok());
+        assert_eq!(
+            persistent_store.remaining_credentials().unwrap(),
+            MAX_SUPPORTED_RESIDENTIAL_KEYS - 1
+        );
+
+        assert!(persistent_store.delete_credential(&credential_id).is_ok());
+        assert_eq!(persistent_store.count_credentials().unwrap(), 0);
+        assert_eq!(
+            persistent_store.delete_credential(&credential_id),
+            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)
+        );
+    }
+
+    /// Returns whether the flash of the internal store holds the bytes anywhere.
+    #[cfg(feature = "with_ctap2_1")]
+    fn storage_contains(persistent_store: &PersistentStore, bytes: &[u8]) -> bool {
+        let storage = persistent_store.store.storage();
+        let page_size = storage.page_size();
+        let flash: Vec<u8> = (0..storage.num_pages())
+            .flat_map(|page| {
+                let index = StorageIndex { page, byte: 0 };
+                storage.read_slice(index, page_size).unwrap().to_vec()
+            })
+            .collect();
+        flash.windows(bytes.len()).any(|window| window == bytes)
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_deleted_credentials_are_wiped() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut entries = vec![];
+        for user_id in 0..3 {
+            let credential_source =
+                create_credential_source(&mut rng, "example.com", vec![user_id]);
+            let credential_id = credential_source.credential_id.clone();
+            persistent_store
+                .store_credential(credential_source)
+                .unwrap();
+            let (key, _) = persistent_store
+                .find_credential_by_id(&credential_id)
+                .unwrap()
+                .unwrap();
+            let value = persistent_store.store.find(key).unwrap().unwrap();
+            assert!(storage_contains(&persistent_store, &value));
+            entries.push((credential_id, value));
+        }
+
+        // The deleted credential is gone from the flash, without compaction.
+        let (credential_id, value) = entries.remove(0);
+        let used_erases = persistent_store.store.wear().unwrap().used_erases();
+        persistent_store.delete_credential(&credential_id).unwrap();
+        assert!(!storage_contains(&persistent_store, &value));
+        assert_eq!(
+            persistent_store.store.wear().unwrap().used_erases(),
+            used_erases
+        );
+
+        persistent_store.reset(&mut rng).unwrap();
+        for (_, value) in entries {
+            assert!(!storage_contains(&persistent_store, &value));
+        }
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_credential_index() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_source0 = create_credential_source(&mut rng, "example.com", vec![0x00]);
+        let credential_source1 = create_credential_source(&mut rng, "example.com", vec![0x01]);
+        let credential_source2 = create_credential_source(&mut rng, "other.com", vec![0x00]);
+        let id1 = credential_source1.credential_id.clone();
+        persistent_store
+            .store_credential(credential_source0)
+            .unwrap();
+        persistent_store
+            .store_credential(credential_source1)
+            .unwrap();
+        persistent_store
+            .store_credential(credential_source2)
+            .unwrap();
+        let index = persistent_store.store.find(key::CREDENTIAL_INDEX).unwrap();
+        assert_eq!(index.unwrap().len(), 3 * CREDENTIAL_INDEX_RECORD_LENGTH);
+
+        let rp_id_hash = Sha256::hash(b"example.com");
+        let credentials = persistent_store.rp_credentials(&rp_id_hash).unwrap();
+        assert_eq!(credentials.len(), 2);
+        assert!(credentials[0].0 < credentials[1].0);
+        assert!(persistent_store
+            .rp_credentials(&rp_id_hash[..CREDENTIAL_INDEX_HASH_LENGTH])
+            .unwrap()
+            .is_empty());
+
+        persistent_store.delete_credential(&id1).unwrap();
+        let index = persistent_store.store.find(key::CREDENTIAL_INDEX).unwrap();
+        assert_eq!(index.unwrap().len(), 2 * CREDENTIAL_INDEX_RECORD_LENGTH);
+        assert_eq!(
+            persistent_store
+                .filter_credential("example.com", false)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // A missing index, e.g. from an older firmware, is rebuilt.
+        persistent_store
+            .store
+            .remove(key::CREDENTIAL_INDEX)
+            .unwrap();
+        assert_eq!(
+            persistent_store
+                .filter_credential("example.com", false)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            persistent_store
+                .filter_credential("other.com", false)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // An index that doesn't list the stored credentials is ignored.
+        persistent_store
+            .store
+            .insert(key::CREDENTIAL_INDEX, &[0x06, 0xA4, 0x00, 0x00, 0x00, 0x00])
+            .unwrap();
+        assert_eq!(
+            persistent_store
+                .filter_credential("other.com", false)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    fn add_external_store(persistent_store: &mut PersistentStore) {
+        let storage = new_storage(NUM_EXTERNAL_PAGES);
+        persistent_store.external_store = Some(persistent_store::Store::new(storage).ok().unwrap());
+        persistent_store.move_to_external_store().unwrap();
+    }
+
+    #[test]
+    fn test_external_store() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![0x00]);
+        persistent_store
+            .store_credential(credential_source)
+            .unwrap();
+
+        // Credentials of the embedded flash are moved to the external flash.
+        add_external_store(&mut persistent_store);
+        assert!(
+            MAX_EXTERNAL_RESIDENTIAL_KEYS * CREDENTIAL_INDEX_RECORD_LENGTH
+                <= persistent_store.store.max_value_length()
+        );
+        let external_store = persistent_store.external_store.as_ref().unwrap();
+        let credential_key = key::CREDENTIALS.start;
+        assert!(persistent_store
+            .store
+            .find(credential_key)
+            .unwrap()
+            .is_none());
+        assert!(external_store.find(credential_key).unwrap().is_some());
+        assert!(external_store
+            .find(key::CREDENTIAL_INDEX)
+            .unwrap()
+            .is_some());
+        assert!(external_store.find(key::MASTER_KEYS).unwrap().is_none());
+        assert_eq!(
+            persistent_store
+                .filter_credential("example.com", false)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // The external flash holds more credentials than the embedded flash.
+        for user_handle in 1..=MAX_SUPPORTED_RESIDENTIAL_KEYS {
+            let credential_source = create_credential_source(
+                &mut rng,
+                "example.com",
+                (user_handle as u16).to_be_bytes().to_vec(),
+            );
+            persistent_store
+                .store_credential(credential_source)
+                .unwrap();
+        }
+        assert_eq!(
+            persistent_store.count_credentials().unwrap(),
+            MAX_SUPPORTED_RESIDENTIAL_KEYS + 1
+        );
+        assert!(persistent_store
+            .store
+            .iter()
+            .unwrap()
+            .all(|handle| !key::CREDENTIALS.contains(&handle.unwrap().get_key())));
+
+        // A reset clears both flashes.
+        persistent_store.reset(&mut rng).unwrap();
+        assert_eq!(persistent_store.count_credentials().unwrap(), 0);
+        assert!(persistent_store.master_keys().is_ok());
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_update_credential_user() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let mut credential_source = create_credential_source(&mut rng, "example.com", vec![0x1D]);
+        credential_source.user_name = Some(String::from("old name"));
+        credential_source.user_display_name = Some(String::from("Old Name"));
+        credential_source.user_icon = Some(String::from("icon"));
+        let credential_id = credential_source.credential_id.clone();
+        assert!(persistent_store
+            .store_credential(credential_source.clone())
+            .is_ok());
+
+        let user = PublicKeyCredentialUserEntity {
+            user_id: vec![0x1D],
+            user_name: Some(String::from("new name")),
+            user_display_name: Some(String::new()),
+            user_icon: None,
+        };
+        assert!(persistent_store
+            .update_credential_user(&credential_id, user.clone())
+            .is_ok());
+        let expected_credential = PublicKeyCredentialSource {
+            user_name: Some(String::from("new name")),
+            user_display_name: None,
+            ..credential_source
+        };
+        assert_eq!(
+            persistent_store.find_credential("example.com", &credential_id, false),
+            Ok(Some(expected_credential))
+        );
+        assert_eq!(persistent_store.count_credentials().unwrap(), 1);
+
+        let other_user = PublicKeyCredentialUserEntity {
+            user_id: vec![0x2D],
+            ..user.clone()
+        };
+        assert_eq!(
+            persistent_store.update_credential_user(&credential_id, other_user),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        assert_eq!(
+            persistent_store.update_credential_user(&[0x55; 32], user),
+            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS)
+        );
+    }
+
     #[test]
     #[allow(clippy::assertions_on_constants)]
     fn test_fill_store() {
@@ -771,7 +2750,7 @@ mod test {
         let mut rng = ThreadRng256 {};
         let mut persistent_store = PersistentStore::new(&mut rng);
         assert_eq!(persistent_store.count_credentials().unwrap(), 0);
-        let private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
+        let private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
         let credential = PublicKeyCredentialSource {
             key_type: PublicKeyCredentialType::PublicKey,
             credential_id: rng.gen_uniform_u8x32().to_vec(),
@@ -783,8 +2762,12 @@ mod test {
                 CredentialProtectionPolicy::UserVerificationOptionalWithCredentialIdList,
             ),
             creation_order: 0,
+            last_used_order: None,
+            signature_counter: None,
             user_name: None,
             user_icon: None,
+            large_blob_key: None,
+            cred_blob: None,
         };
         assert!(persistent_store.store_credential(credential).is_ok());
 
@@ -826,8 +2809,12 @@ mod test {
             user_display_name: None,
             cred_protect_policy: None,
             creation_order: 0,
+            last_used_order: None,
+            signature_counter: None,
             user_name: None,
             user_icon: None,
+            large_blob_key: None,
+            cred_blob: None,
         };
         assert_eq!(found_credential, Some(expected_credential));
     }
@@ -837,7 +2824,7 @@ mod test {
         let mut rng = ThreadRng256 {};
         let mut persistent_store = PersistentStore::new(&mut rng);
         assert_eq!(persistent_store.count_credentials().unwrap(), 0);
-        let private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
+        let private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
         let credential = PublicKeyCredentialSource {
             key_type: PublicKeyCredentialType::PublicKey,
             credential_id: rng.gen_uniform_u8x32().to_vec(),
@@ -847,8 +2834,12 @@ mod test {
             user_display_name: None,
             cred_protect_policy: Some(CredentialProtectionPolicy::UserVerificationRequired),
             creation_order: 0,
+            last_used_order: None,
+            signature_counter: None,
             user_name: None,
             user_icon: None,
+            large_blob_key: None,
+            cred_blob: None,
         };
         assert!(persistent_store.store_credential(credential).is_ok());
 
@@ -879,6 +2870,56 @@ mod test {
         assert!(master_keys_3.hmac != master_hmac_key.as_slice());
     }
 
+    #[test]
+    fn test_rotate_master_keys() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let first_keys = persistent_store.master_keys().unwrap();
+        assert_eq!(persistent_store.master_keys_generation(), Ok(0));
+        assert!(persistent_store
+            .generation_master_keys(1)
+            .unwrap()
+            .is_none());
+
+        assert_eq!(persistent_store.rotate_master_keys(&mut rng), Ok(1));
+        let (generation, current_keys) = persistent_store.current_master_keys().unwrap();
+        assert_eq!(generation, 1);
+        assert!(current_keys.encryption != first_keys.encryption);
+        assert!(current_keys.hmac != first_keys.hmac);
+        // Older generations stay available.
+        let old_keys = persistent_store.generation_master_keys(0).unwrap().unwrap();
+        assert_eq!(old_keys.encryption, first_keys.encryption);
+        assert_eq!(old_keys.hmac, first_keys.hmac);
+        assert_eq!(
+            persistent_store.master_keys().unwrap().encryption,
+            first_keys.encryption
+        );
+
+        // Rotations fail once all generations are used.
+        for generation in 2..=key::ROTATED_MASTER_KEYS.len() as u8 {
+            assert_eq!(
+                persistent_store.rotate_master_keys(&mut rng),
+                Ok(generation)
+            );
+        }
+        assert_eq!(
+            persistent_store.rotate_master_keys(&mut rng),
+            Err(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL)
+        );
+        assert!(persistent_store
+            .generation_master_keys(key::ROTATED_MASTER_KEYS.len() as u8 + 1)
+            .unwrap()
+            .is_none());
+
+        // A reset forgets all generations.
+        persistent_store.reset(&mut rng).unwrap();
+        assert_eq!(persistent_store.master_keys_generation(), Ok(0));
+        assert!(persistent_store
+            .generation_master_keys(1)
+            .unwrap()
+            .is_none());
+    }
+
     #[test]
     fn test_cred_random_secret() {
         let mut rng = ThreadRng256 {};
@@ -949,6 +2990,24 @@ mod test {
         assert_eq!(persistent_store.pin_retries(), Ok(MAX_PIN_RETRIES));
     }
 
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_uv_retries() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        assert_eq!(persistent_store.uv_retries(), Ok(MAX_UV_RETRIES));
+        for uv_retries in (0..MAX_UV_RETRIES).rev() {
+            persistent_store.decr_uv_retries().unwrap();
+            assert_eq!(persistent_store.uv_retries(), Ok(uv_retries));
+        }
+        persistent_store.decr_uv_retries().unwrap();
+        assert_eq!(persistent_store.uv_retries(), Ok(0));
+
+        persistent_store.reset_uv_retries().unwrap();
+        assert_eq!(persistent_store.uv_retries(), Ok(MAX_UV_RETRIES));
+    }
+
     #[test]
     fn test_persistent_keys() {
         let mut rng = ThreadRng256 {};
@@ -988,22 +3047,309 @@ mod test {
         assert_eq!(&persistent_store.aaguid().unwrap(), key_material::AAGUID);
     }
 
-    #[cfg(feature = "with_ctap2_1")]
     #[test]
-    fn test_min_pin_length() {
-        let mut rng = ThreadRng256 {};
-        let mut persistent_store = PersistentStore::new(&mut rng);
+    fn test_entropy_seed() {
+        // Records the additional input it receives.
+        struct SeedRecorder {
+            inputs: Vec<Vec<u8>>,
+        }
 
-        // The minimum PIN length is initially at the default.
-        assert_eq!(
-            persistent_store.min_pin_length().unwrap(),
-            DEFAULT_MIN_PIN_LENGTH
-        );
+        impl Rng256 for SeedRecorder {
+            fn gen_uniform_u8x32(&mut self) -> [u8; 32] {
+                ThreadRng256 {}.gen_uniform_u8x32()
+            }
 
-        // Changes by the setter are reflected by the getter..
-        let new_min_pin_length = 8;
-        persistent_store
-            .set_min_pin_length(new_min_pin_length)
+            fn add_input(&mut self, input: &[u8]) {
+                self.inputs.push(input.to_vec());
+            }
+        }
+
+        let mut rng = SeedRecorder { inputs: vec![] };
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        // There is no seed at the first boot.
+        assert!(rng.inputs.is_empty());
+        let seed = persistent_store
+            .store
+            .find(key::ENTROPY_SEED)
+            .unwrap()
+            .unwrap();
+        assert_eq!(seed.len(), 32);
+
+        // The seed survives a reset, and is consumed by the next boot.
+        persistent_store.reset(&mut rng).unwrap();
+        persistent_store.refresh_entropy_seed(&mut rng).unwrap();
+        assert_eq!(rng.inputs, vec![seed.clone()]);
+        let next_seed = persistent_store.store.find(key::ENTROPY_SEED).unwrap();
+        assert_ne!(next_seed, Some(seed));
+    }
+
+    #[test]
+    fn test_attestation_private_key_is_wrapped() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let private_key = [0x41u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        persistent_store
+            .set_attestation_private_key(&private_key)
+            .unwrap();
+        assert_eq!(persistent_store.has_attestation_private_key(), Ok(true));
+
+        // The storage doesn't contain the plaintext key.
+        let mut wrapped = persistent_store
+            .store
+            .find(key::ATTESTATION_PRIVATE_KEY)
+            .unwrap()
+            .unwrap();
+        assert_eq!(wrapped.len(), WRAPPED_ATTESTATION_PRIVATE_KEY_LENGTH);
+        assert!(!wrapped
+            .windows(private_key.len())
+            .any(|window| window == &private_key[..]));
+        assert_eq!(
+            persistent_store.attestation_private_key(),
+            Ok(Some(private_key))
+        );
+
+        // A modified key is rejected.
+        wrapped[NONCE_SIZE] ^= 0x01;
+        persistent_store
+            .store
+            .insert(key::ATTESTATION_PRIVATE_KEY, &wrapped)
+            .unwrap();
+        assert_eq!(
+            persistent_store.attestation_private_key(),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+    }
+
+    #[test]
+    fn test_legacy_attestation_private_key_is_wrapped_at_boot() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let private_key = [0x41u8; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        persistent_store
+            .store
+            .insert(key::ATTESTATION_PRIVATE_KEY, &private_key)
+            .unwrap();
+        assert_eq!(
+            persistent_store.attestation_private_key(),
+            Ok(Some(private_key))
+        );
+
+        // Older firmwares had no schema version.
+        persistent_store.store.remove(key::SCHEMA_VERSION).unwrap();
+        persistent_store.migrate().unwrap();
+        let wrapped = persistent_store
+            .store
+            .find(key::ATTESTATION_PRIVATE_KEY)
+            .unwrap()
+            .unwrap();
+        assert_eq!(wrapped.len(), WRAPPED_ATTESTATION_PRIVATE_KEY_LENGTH);
+        assert_eq!(
+            persistent_store.attestation_private_key(),
+            Ok(Some(private_key))
+        );
+    }
+
+    #[test]
+    fn test_schema_version() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let version = persistent_store.store.find(key::SCHEMA_VERSION).unwrap();
+        assert_eq!(version, Some(SCHEMA_VERSION.to_be_bytes().to_vec()));
+
+        // Migrations only run once.
+        persistent_store.migrate().unwrap();
+        persistent_store.reset(&mut rng).unwrap();
+        let version = persistent_store.store.find(key::SCHEMA_VERSION).unwrap();
+        assert_eq!(version, Some(SCHEMA_VERSION.to_be_bytes().to_vec()));
+        assert!(!persistent_store.unknown_schema);
+    }
+
+    #[test]
+    fn test_unknown_schema_version() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![]);
+        persistent_store
+            .store_credential(credential_source)
+            .unwrap();
+
+        // A newer firmware wrote the store.
+        let newer_version = (SCHEMA_VERSION + 1).to_be_bytes();
+        persistent_store
+            .store
+            .insert(key::SCHEMA_VERSION, &newer_version)
+            .unwrap();
+        persistent_store.migrate().unwrap();
+        assert!(persistent_store.unknown_schema);
+        assert_eq!(
+            persistent_store.count_credentials(),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+        assert_eq!(
+            persistent_store.pin_retries(),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+        assert_eq!(
+            persistent_store.reset(&mut rng),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+        let version = persistent_store.store.find(key::SCHEMA_VERSION).unwrap();
+        assert_eq!(version, Some(newer_version.to_vec()));
+
+        // The entries are intact for the newer firmware.
+        persistent_store.unknown_schema = false;
+        assert_eq!(persistent_store.count_credentials(), Ok(1));
+    }
+
+    #[test]
+    fn test_attestation_intermediate_certificates() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        assert_eq!(
+            persistent_store.attestation_intermediate_certificates(),
+            Ok(vec![])
+        );
+        // The attestation certificate comes first, followed by too many intermediates.
+        let too_long_chain = vec![vec![0xdd; 20]; 5];
+        assert_eq!(
+            persistent_store.provision_attestation(None, None, Some(&too_long_chain)),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        let chain = vec![vec![0xcc; 10], vec![0xdd; 20], vec![0xee; 30]];
+        persistent_store
+            .provision_attestation(None, None, Some(&chain))
+            .unwrap();
+        assert_eq!(
+            persistent_store.attestation_intermediate_certificates(),
+            Ok(chain[1..].to_vec())
+        );
+
+        // The chain survives a reset.
+        persistent_store.reset(&mut rng).unwrap();
+        assert_eq!(
+            persistent_store.attestation_intermediate_certificates(),
+            Ok(chain[1..].to_vec())
+        );
+    }
+
+    #[test]
+    fn test_provision_attestation() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        let aaguid = [0x33; key_material::AAGUID_LENGTH];
+        let private_key = [0x41; key_material::ATTESTATION_PRIVATE_KEY_LENGTH];
+        let chain = vec![vec![0xdd; 20], vec![0xee; 30], vec![0xff; 40]];
+        assert_eq!(
+            persistent_store.provision_attestation(Some(&aaguid), Some(&private_key), Some(&[])),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        persistent_store
+            .provision_attestation(Some(&aaguid), Some(&private_key), Some(&chain))
+            .unwrap();
+        assert_eq!(persistent_store.aaguid(), Ok(aaguid));
+        assert_eq!(
+            persistent_store.attestation_private_key(),
+            Ok(Some(private_key))
+        );
+        assert_eq!(
+            persistent_store.attestation_certificate(),
+            Ok(Some(chain[0].clone()))
+        );
+        assert_eq!(
+            persistent_store.attestation_intermediate_certificates(),
+            Ok(chain[1..].to_vec())
+        );
+
+        // Re-provisioning overwrites, and only touches the given values.
+        let shorter_chain = vec![vec![0xcc; 20], vec![0xbb; 30]];
+        persistent_store
+            .provision_attestation(None, None, Some(&shorter_chain))
+            .unwrap();
+        assert_eq!(persistent_store.aaguid(), Ok(aaguid));
+        assert_eq!(
+            persistent_store.attestation_certificate(),
+            Ok(Some(shorter_chain[0].clone()))
+        );
+        assert_eq!(
+            persistent_store.attestation_intermediate_certificates(),
+            Ok(shorter_chain[1..].to_vec())
+        );
+
+        // The lock is permanent, even after a reset.
+        assert_eq!(persistent_store.is_provisioning_locked(), Ok(false));
+        persistent_store.lock_provisioning().unwrap();
+        persistent_store.reset(&mut rng).unwrap();
+        assert_eq!(persistent_store.is_provisioning_locked(), Ok(true));
+        assert_eq!(
+            persistent_store.provision_attestation(Some(&[0x44; 16]), None, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+        assert_eq!(persistent_store.aaguid(), Ok(aaguid));
+    }
+
+    #[test]
+    fn test_default_cred_protect() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        assert_eq!(persistent_store.default_cred_protect(), Ok(None));
+        let policy = CredentialProtectionPolicy::UserVerificationRequired;
+        persistent_store.set_default_cred_protect(policy).unwrap();
+        assert_eq!(persistent_store.default_cred_protect(), Ok(Some(policy)));
+
+        // The vendor setting survives a reset.
+        persistent_store.reset(&mut rng).unwrap();
+        assert_eq!(persistent_store.default_cred_protect(), Ok(Some(policy)));
+    }
+
+    #[test]
+    fn test_user_presence_settings() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        assert_eq!(persistent_store.touch_timeout_ms(), Ok(None));
+        assert_eq!(persistent_store.touch_duration_ms(), Ok(None));
+        assert_eq!(persistent_store.confirm_with_second_touch(), Ok(None));
+        persistent_store.set_touch_timeout_ms(10000).unwrap();
+        persistent_store.set_touch_duration_ms(500).unwrap();
+        persistent_store
+            .set_confirm_with_second_touch(false)
+            .unwrap();
+        assert_eq!(persistent_store.touch_timeout_ms(), Ok(Some(10000)));
+        assert_eq!(persistent_store.touch_duration_ms(), Ok(Some(500)));
+        assert_eq!(
+            persistent_store.confirm_with_second_touch(),
+            Ok(Some(false))
+        );
+
+        // The vendor settings survive a reset.
+        persistent_store.reset(&mut rng).unwrap();
+        assert_eq!(persistent_store.touch_timeout_ms(), Ok(Some(10000)));
+        assert_eq!(persistent_store.touch_duration_ms(), Ok(Some(500)));
+        assert_eq!(
+            persistent_store.confirm_with_second_touch(),
+            Ok(Some(false))
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_min_pin_length() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        // The minimum PIN length is initially at the default.
+        assert_eq!(
+            persistent_store.min_pin_length().unwrap(),
+            DEFAULT_MIN_PIN_LENGTH
+        );
+
+        // Changes by the setter are reflected by the getter..
+        let new_min_pin_length = 8;
+        persistent_store
+            .set_min_pin_length(new_min_pin_length)
             .unwrap();
         assert_eq!(
             persistent_store.min_pin_length().unwrap(),
@@ -1019,22 +3365,171 @@ mod test {
 
         // The minimum PIN length RP IDs are initially at the default.
         assert_eq!(
-            persistent_store._min_pin_length_rp_ids().unwrap(),
-            _DEFAULT_MIN_PIN_LENGTH_RP_IDS
+            persistent_store.min_pin_length_rp_ids().unwrap(),
+            DEFAULT_MIN_PIN_LENGTH_RP_IDS
         );
 
         // Changes by the setter are reflected by the getter.
         let mut rp_ids = vec![String::from("example.com")];
         assert_eq!(
-            persistent_store._set_min_pin_length_rp_ids(rp_ids.clone()),
+            persistent_store.set_min_pin_length_rp_ids(rp_ids.clone()),
             Ok(())
         );
-        for rp_id in _DEFAULT_MIN_PIN_LENGTH_RP_IDS {
+        for rp_id in DEFAULT_MIN_PIN_LENGTH_RP_IDS {
             if !rp_ids.contains(&rp_id) {
                 rp_ids.push(rp_id);
             }
         }
-        assert_eq!(persistent_store._min_pin_length_rp_ids().unwrap(), rp_ids);
+        assert_eq!(persistent_store.min_pin_length_rp_ids().unwrap(), rp_ids);
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_fingerprint_templates() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        assert!(persistent_store.fingerprint_templates().unwrap().is_empty());
+
+        let template_info = TemplateInfo {
+            template_id: vec![0x01],
+            template_friendly_name: None,
+        };
+        assert!(persistent_store
+            .store_fingerprint_template(template_info.clone())
+            .is_ok());
+        assert_eq!(
+            persistent_store.fingerprint_templates().unwrap(),
+            vec![template_info]
+        );
+
+        // Storing a template with the same ID replaces it.
+        let renamed_template_info = TemplateInfo {
+            template_id: vec![0x01],
+            template_friendly_name: Some(String::from("Left thumb")),
+        };
+        assert!(persistent_store
+            .store_fingerprint_template(renamed_template_info.clone())
+            .is_ok());
+        assert_eq!(
+            persistent_store.fingerprint_templates().unwrap(),
+            vec![renamed_template_info]
+        );
+
+        assert!(persistent_store
+            .remove_fingerprint_template(&[0x01])
+            .is_ok());
+        assert!(persistent_store.fingerprint_templates().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_fingerprint_templates_full() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        for template_id in 0..MAX_FINGERPRINT_TEMPLATES {
+            let template_info = TemplateInfo {
+                template_id: vec![template_id as u8],
+                template_friendly_name: None,
+            };
+            assert!(persistent_store
+                .store_fingerprint_template(template_info)
+                .is_ok());
+        }
+        let template_info = TemplateInfo {
+            template_id: vec![MAX_FINGERPRINT_TEMPLATES as u8],
+            template_friendly_name: None,
+        };
+        assert_eq!(
+            persistent_store.store_fingerprint_template(template_info),
+            Err(Ctap2StatusCode::CTAP2_ERR_FP_DATABASE_FULL)
+        );
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_force_pin_change() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        assert!(!persistent_store.has_force_pin_change().unwrap());
+        assert_eq!(persistent_store.force_pin_change(), Ok(()));
+        assert!(persistent_store.has_force_pin_change().unwrap());
+        // Setting a new PIN clears the flag.
+        assert_eq!(
+            persistent_store.set_pin_hash(&[0x88; PIN_AUTH_LENGTH]),
+            Ok(())
+        );
+        assert!(!persistent_store.has_force_pin_change().unwrap());
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_always_uv() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        assert!(!persistent_store.has_always_uv().unwrap());
+        assert_eq!(persistent_store.toggle_always_uv(), Ok(()));
+        assert!(persistent_store.has_always_uv().unwrap());
+        assert_eq!(persistent_store.toggle_always_uv(), Ok(()));
+        assert!(!persistent_store.has_always_uv().unwrap());
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_enterprise_attestation() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        assert!(!persistent_store.enterprise_attestation().unwrap());
+        assert_eq!(persistent_store.enable_enterprise_attestation(), Ok(()));
+        assert!(persistent_store.enterprise_attestation().unwrap());
+        persistent_store.reset(&mut rng).unwrap();
+        assert!(!persistent_store.enterprise_attestation().unwrap());
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_large_blob_array() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        // The empty array is returned when nothing was committed.
+        let empty_array = persistent_store.get_large_blob_array(0, 1024).unwrap();
+        assert_eq!(empty_array, DEFAULT_LARGE_BLOB_ARRAY.to_vec());
+        let hash = Sha256::hash(&empty_array[..1]);
+        assert_eq!(&empty_array[1..], &hash[..16]);
+
+        let large_blob_array = vec![0xC0; 100];
+        assert!(persistent_store
+            .commit_large_blob_array(&large_blob_array)
+            .is_ok());
+        assert_eq!(
+            persistent_store.get_large_blob_array(0, 1024).unwrap(),
+            large_blob_array
+        );
+        assert_eq!(
+            persistent_store.get_large_blob_array(50, 10).unwrap(),
+            vec![0xC0; 10]
+        );
+        assert_eq!(persistent_store.get_large_blob_array(100, 1), Ok(vec![]));
+        assert_eq!(
+            persistent_store.get_large_blob_array(101, 1),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        // Arrays that don't fit in a store entry are rejected.
+        assert_eq!(
+            persistent_store.commit_large_blob_array(&[0xC0; MAX_LARGE_BLOB_ARRAY_SIZE + 1]),
+            Err(Ctap2StatusCode::CTAP2_ERR_LARGE_BLOB_STORAGE_FULL)
+        );
+
+        // The array is cleared by a reset.
+        persistent_store.reset(&mut rng).unwrap();
+        assert_eq!(
+            persistent_store.get_large_blob_array(0, 1024).unwrap(),
+            DEFAULT_LARGE_BLOB_ARRAY.to_vec()
+        );
     }
 
     #[test]
@@ -1059,10 +3554,317 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_audit_log() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        assert_eq!(persistent_store.audit_log().unwrap(), vec![]);
+
+        let backup_record = AuditRecord {
+            event: AuditEvent::CredentialBackup,
+            credential_count: 3,
+        };
+        persistent_store.append_audit_record(backup_record).unwrap();
+        assert_eq!(persistent_store.audit_log().unwrap(), vec![backup_record]);
+
+        // The log survives a reset.
+        persistent_store.reset(&mut rng).unwrap();
+        assert_eq!(persistent_store.audit_log().unwrap(), vec![backup_record]);
+
+        // Only the most recent records are kept.
+        for credential_count in 0..MAX_AUDIT_RECORDS as u32 {
+            let restore_record = AuditRecord {
+                event: AuditEvent::CredentialRestore,
+                credential_count,
+            };
+            persistent_store
+                .append_audit_record(restore_record)
+                .unwrap();
+        }
+        let audit_log = persistent_store.audit_log().unwrap();
+        assert_eq!(audit_log.len(), MAX_AUDIT_RECORDS);
+        assert!(audit_log
+            .iter()
+            .all(|record| record.event == AuditEvent::CredentialRestore));
+        assert_eq!(audit_log[0].credential_count, 0);
+
+        let updated_record = AuditRecord {
+            event: AuditEvent::CredentialRestore,
+            credential_count: 42,
+        };
+        persistent_store
+            .update_last_audit_record(updated_record)
+            .unwrap();
+        let audit_log = persistent_store.audit_log().unwrap();
+        assert_eq!(audit_log.len(), MAX_AUDIT_RECORDS);
+        assert_eq!(audit_log[MAX_AUDIT_RECORDS - 1], updated_record);
+    }
+
+    #[test]
+    fn test_benchmark_entry_and_compaction() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        for _ in 0..3 {
+            persistent_store.write_benchmark_entry(&[0x55; 32]).unwrap();
+        }
+        assert_eq!(
+            persistent_store.store.find(key::BENCHMARK).unwrap(),
+            Some(vec![0x55; 32])
+        );
+        persistent_store.remove_benchmark_entry().unwrap();
+        assert_eq!(persistent_store.store.find(key::BENCHMARK).unwrap(), None);
+
+        let lifetime = persistent_store.store.lifetime().unwrap().used();
+        persistent_store.compact_once().unwrap();
+        assert!(persistent_store.store.lifetime().unwrap().used() > lifetime);
+        assert_eq!(
+            persistent_store.global_signature_counter(),
+            Ok(INITIAL_SIGNATURE_COUNTER)
+        );
+    }
+
+    #[test]
+    fn test_compact_step() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        assert_eq!(persistent_store.compact_step(), Ok(false));
+
+        // Rewriting an entry fills the store with deleted values, until compaction is needed.
+        let value = vec![0x55; persistent_store.store.max_value_length()];
+        let mut compacted = false;
+        for _ in 0..1000 {
+            persistent_store.write_benchmark_entry(&value).unwrap();
+            if persistent_store.compact_step().unwrap() {
+                compacted = true;
+                break;
+            }
+        }
+        assert!(compacted);
+
+        while persistent_store.compact_step().unwrap() {}
+        assert_eq!(persistent_store.compact_step(), Ok(false));
+        assert_eq!(
+            persistent_store.store.find(key::BENCHMARK).unwrap(),
+            Some(value)
+        );
+    }
+
+    #[test]
+    fn test_wear() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let wear = persistent_store.wear().unwrap();
+        assert_eq!(wear.used_erases(), 0);
+        assert!(wear.max_page_erases() > 0);
+        let written_bytes = wear.written_bytes();
+        assert!(written_bytes > 0);
+
+        persistent_store.write_benchmark_entry(&[0x55; 32]).unwrap();
+        assert!(persistent_store.wear().unwrap().written_bytes() > written_bytes);
+        persistent_store.compact_once().unwrap();
+        assert_eq!(persistent_store.wear().unwrap().used_erases(), 1);
+    }
+
+    #[test]
+    fn test_transaction() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        // All writes are applied together.
+        persistent_store
+            .transaction(|store| {
+                store.set_touch_timeout_ms(1000)?;
+                store.set_touch_duration_ms(200)?;
+                store.set_touch_duration_ms(300)?;
+                // Writes are only visible once applied.
+                assert_eq!(store.touch_timeout_ms(), Ok(None));
+                store.set_confirm_with_second_touch(true)
+            })
+            .unwrap();
+        assert_eq!(persistent_store.touch_timeout_ms(), Ok(Some(1000)));
+        assert_eq!(persistent_store.touch_duration_ms(), Ok(Some(300)));
+        assert_eq!(persistent_store.confirm_with_second_touch(), Ok(Some(true)));
+
+        // A failing operation writes nothing.
+        let result = persistent_store.transaction(|store| {
+            store.set_touch_timeout_ms(2000)?;
+            store.reset_pin_retries()?;
+            Err::<(), _>(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
+        });
+        assert_eq!(result, Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID));
+        assert_eq!(persistent_store.touch_timeout_ms(), Ok(Some(1000)));
+
+        // Transactions can't be nested.
+        let result = persistent_store.transaction(|store| store.transaction(|_| Ok(())));
+        assert_eq!(
+            result,
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
+        );
+        assert!(persistent_store.pending_updates.is_none());
+    }
+
+    #[test]
+    fn test_corrupted_entries() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+
+        persistent_store
+            .store
+            .insert(key::GLOBAL_SIGNATURE_COUNTER, &[0x01, 0x02])
+            .unwrap();
+        assert_eq!(
+            persistent_store.global_signature_counter(),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+
+        persistent_store
+            .store
+            .insert(key::CREDENTIALS.start, &[0xFF; 10])
+            .unwrap();
+        assert_eq!(
+            persistent_store.get_credential(key::CREDENTIALS.start),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+        // Corrupted credentials are skipped.
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![0x1D]);
+        persistent_store
+            .store_credential(credential_source)
+            .unwrap();
+        let mut iter_result = Ok(());
+        let iter = persistent_store.iter_credentials(&mut iter_result).unwrap();
+        assert_eq!(iter.count(), 1);
+        assert_eq!(iter_result, Ok(()));
+    }
+
+    #[test]
+    fn test_quarantine_corrupted_credentials() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        for user_handle in 0..3 {
+            let credential_source =
+                create_credential_source(&mut rng, "example.com", vec![user_handle]);
+            persistent_store
+                .store_credential(credential_source)
+                .unwrap();
+        }
+        assert_eq!(persistent_store.quarantined_entries(), Ok(0));
+
+        // Flip a bit of a credential, as bit rot would.
+        let corrupted_key = key::CREDENTIALS.start + 1;
+        let mut value = persistent_store.store.find(corrupted_key).unwrap().unwrap();
+        value[10] ^= 0x01;
+        persistent_store
+            .store
+            .insert(corrupted_key, &value)
+            .unwrap();
+        assert_eq!(
+            persistent_store.get_credential(corrupted_key),
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE)
+        );
+        assert_eq!(
+            persistent_store
+                .filter_credential("example.com", false)
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // The next boot moves the credential to the quarantine.
+        persistent_store.quarantine_corrupted_credentials().unwrap();
+        assert_eq!(persistent_store.quarantined_entries(), Ok(1));
+        assert!(persistent_store
+            .store
+            .find(corrupted_key)
+            .unwrap()
+            .is_none());
+        let quarantined = persistent_store.store.find(key::QUARANTINE.start).unwrap();
+        assert_eq!(quarantined, Some(value));
+        assert_eq!(persistent_store.count_credentials(), Ok(2));
+
+        // When the quarantine is full, corrupted credentials are dropped.
+        for quarantine_key in key::QUARANTINE {
+            persistent_store.store.insert(quarantine_key, &[]).unwrap();
+        }
+        persistent_store
+            .store
+            .insert(key::CREDENTIALS.start, &[0xFF; 10])
+            .unwrap();
+        persistent_store.quarantine_corrupted_credentials().unwrap();
+        assert_eq!(persistent_store.count_credentials(), Ok(1));
+        assert_eq!(
+            persistent_store.quarantined_entries(),
+            Ok(key::QUARANTINE.len())
+        );
+
+        // A reset clears the quarantine.
+        persistent_store.reset(&mut rng).unwrap();
+        assert_eq!(persistent_store.quarantined_entries(), Ok(0));
+    }
+
+    #[test]
+    fn test_append_credential_checksums() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_source = create_credential_source(&mut rng, "example.com", vec![0x1D]);
+        let credential_key = key::CREDENTIALS.start;
+        let legacy_value = serialize_credential(credential_source.clone()).unwrap();
+        persistent_store
+            .store
+            .insert(credential_key, &legacy_value)
+            .unwrap();
+        assert!(persistent_store.get_credential(credential_key).is_err());
+
+        // The migration from version 1 appends the checksum, and tolerates running twice.
+        persistent_store
+            .store
+            .insert(key::SCHEMA_VERSION, &1u32.to_be_bytes())
+            .unwrap();
+        persistent_store.migrate().unwrap();
+        persistent_store.append_credential_checksums().unwrap();
+        assert_eq!(
+            persistent_store.get_credential(credential_key),
+            Ok(credential_source)
+        );
+        let version = persistent_store.store.find(key::SCHEMA_VERSION).unwrap();
+        assert_eq!(version, Some(SCHEMA_VERSION.to_be_bytes().to_vec()));
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_store_error_status_code() {
+        use persistent_store::StoreError;
+        assert_eq!(
+            Ctap2StatusCode::from(StoreError::NoCapacity),
+            Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL
+        );
+        assert_eq!(
+            Ctap2StatusCode::from(StoreError::NoLifetime),
+            Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL
+        );
+        assert_eq!(
+            Ctap2StatusCode::from(StoreError::InvalidArgument),
+            Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR
+        );
+        assert_eq!(
+            Ctap2StatusCode::from(StoreError::InvalidStorage),
+            Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE
+        );
+        assert_eq!(
+            Ctap2StatusCode::from(StoreError::StorageError),
+            Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE
+        );
+    }
+
     #[test]
     fn test_serialize_deserialize_credential() {
         let mut rng = ThreadRng256 {};
-        let private_key = crypto::ecdsa::SecKey::gensk(&mut rng);
+        let private_key: PrivateKey = crypto::ecdsa::SecKey::gensk(&mut rng).into();
         let credential = PublicKeyCredentialSource {
             key_type: PublicKeyCredentialType::PublicKey,
             credential_id: rng.gen_uniform_u8x32().to_vec(),
@@ -1072,11 +3874,15 @@ mod test {
             user_display_name: None,
             cred_protect_policy: None,
             creation_order: 0,
+            last_used_order: None,
+            signature_counter: None,
             user_name: None,
             user_icon: None,
+            large_blob_key: None,
+            cred_blob: None,
         };
         let serialized = serialize_credential(credential.clone()).unwrap();
-        let reconstructed = deserialize_credential(&serialized).unwrap();
+        let reconstructed = deserialize_credential(serialized).unwrap();
         assert_eq!(credential, reconstructed);
     }
 
@@ -1084,8 +3890,8 @@ mod test {
     #[test]
     fn test_serialize_deserialize_min_pin_length_rp_ids() {
         let rp_ids = vec![String::from("example.com")];
-        let serialized = _serialize_min_pin_length_rp_ids(rp_ids.clone()).unwrap();
-        let reconstructed = _deserialize_min_pin_length_rp_ids(&serialized).unwrap();
+        let serialized = serialize_min_pin_length_rp_ids(rp_ids.clone()).unwrap();
+        let reconstructed = deserialize_min_pin_length_rp_ids(&serialized).unwrap();
         assert_eq!(rp_ids, reconstructed);
     }
 }
@@ -70,6 +70,61 @@ make_partition! {
     /// The aaguid.
     AAGUID = 3;
 
+    /// The credProtect policy for credentials whose relying party doesn't request one.
+    ///
+    /// If the entry is absent, `DEFAULT_CRED_PROTECT` applies. The vendor sets this entry, and it
+    /// survives resets.
+    DEFAULT_CRED_PROTECT = 4;
+
+    /// How long a user presence check waits for a touch, in milliseconds.
+    ///
+    /// If the entry is absent, `TOUCH_TIMEOUT_MS` applies. The vendor sets this entry, and it
+    /// survives resets.
+    TOUCH_TIMEOUT = 5;
+
+    /// How long the button must be held for a touch to count, in milliseconds.
+    ///
+    /// If the entry is absent, `TOUCH_DURATION_MS` applies. The vendor sets this entry, and it
+    /// survives resets.
+    TOUCH_DURATION = 6;
+
+    /// Whether destructive operations need a second touch.
+    ///
+    /// If the entry is absent, `CONFIRM_WITH_SECOND_TOUCH` applies. The vendor sets this entry,
+    /// and it survives resets.
+    CONFIRM_WITH_SECOND_TOUCH = 7;
+
+    /// The certificates between the attestation certificate and the root.
+    ///
+    /// The first entry signed the attestation certificate, and each entry was signed by the next.
+    /// Only a prefix of those keys is used, depending on the length of the chain.
+    ATTESTATION_INTERMEDIATE_CERTIFICATES = 8..11;
+
+    /// Whether the attestation material is locked.
+    ///
+    /// The attestation private key, certificate, intermediate certificates and AAGUID form the
+    /// provisioning partition. The vendor writes it at manufacturing time, until this entry is
+    /// present. It is never removed, so the lock is permanent.
+    PROVISIONING_LOCKED = 11;
+
+    /// The seed of the entropy pool for the next boot.
+    ///
+    /// It is mixed into the random generator at boot, and replaced right away. It survives resets,
+    /// since it doesn't identify the device.
+    ENTROPY_SEED = 12;
+
+    /// The audit log of sensitive vendor operations.
+    ///
+    /// It holds the last `MAX_AUDIT_RECORDS` records, oldest first. It survives resets, so that a
+    /// reset doesn't hide an export of the credentials.
+    AUDIT_LOG = 13;
+
+    /// The schema version of the store.
+    ///
+    /// If the entry is absent, the store was written before schema versioning, which is version
+    /// 0. It survives resets, since persistent entries keep their layout.
+    SCHEMA_VERSION = 14;
+
     // This is the persistent key limit:
     // - When adding a (persistent) key above this message, make sure its value is smaller than
     //   NUM_PERSISTENT_KEYS.
@@ -88,12 +143,73 @@ make_partition! {
     /// board may configure `MAX_SUPPORTED_RESIDENTIAL_KEYS` depending on the storage size.
     CREDENTIALS = 1700..2000;
 
+    /// Corrupted entries, moved there at boot so that they don't fail the operations that read
+    /// them.
+    ///
+    /// The values are kept as they were read, for analysis.
+    QUARANTINE = 2000..2016;
+
+    /// The RP ID hash prefix of each credential, to find the credentials of a relying party without
+    /// reading all of them.
+    ///
+    /// If the entry is absent or doesn't list the keys of the stored credentials, it is rebuilt
+    /// from the credentials. When `CREDENTIALS` is encrypted, this entry should be as well.
+    CREDENTIAL_INDEX = 2017;
+
+    /// Scratch entry of the benchmark vendor command.
+    ///
+    /// It is removed once the store writes are measured.
+    BENCHMARK = 2018;
+
+    /// The master keys of later generations.
+    ///
+    /// The first entry holds generation 1, and each entry holds the next generation. Generation 0
+    /// is `MASTER_KEYS`. Only a prefix of those keys is used, depending on the number of rotations.
+    ROTATED_MASTER_KEYS = 2019..2035;
+
+    /// The number of built-in user verification retries.
+    ///
+    /// If the entry is absent, the number of retries is `MAX_UV_RETRIES`.
+    #[cfg(feature = "with_ctap2_1")]
+    UV_RETRIES = 2035;
+
+    /// The enrolled fingerprint templates.
+    ///
+    /// The templates themselves are stored by the sensor. If the entry is absent, there is no
+    /// enrolled template.
+    #[cfg(feature = "with_ctap2_1")]
+    FINGERPRINT_TEMPLATES = 2036;
+
+    /// Whether the PIN must be changed before it can be used again.
+    ///
+    /// If the entry is absent, there is no pending PIN change.
+    #[cfg(feature = "with_ctap2_1")]
+    FORCE_PIN_CHANGE = 2037;
+
+    /// Whether user verification is required for all credential operations.
+    ///
+    /// If the entry is absent, alwaysUv is disabled.
+    #[cfg(feature = "with_ctap2_1")]
+    ALWAYS_UV = 2038;
+
+    /// Whether enterprise attestation is enabled.
+    ///
+    /// If the entry is absent, enterprise attestation is disabled.
+    #[cfg(feature = "with_ctap2_1")]
+    ENTERPRISE_ATTESTATION = 2039;
+
+    /// The serialized large blob array.
+    ///
+    /// If the entry is absent, the array is empty.
+    #[cfg(feature = "with_ctap2_1")]
+    LARGE_BLOB_ARRAY = 2040;
+
     /// The secret of the CredRandom feature.
     CRED_RANDOM_SECRET = 2041;
 
     /// List of RP IDs allowed to read the minimum PIN length.
     #[cfg(feature = "with_ctap2_1")]
-    _MIN_PIN_LENGTH_RP_IDS = 2042;
+    MIN_PIN_LENGTH_RP_IDS = 2042;
 
     /// The minimum PIN length.
     ///
@@ -111,7 +227,7 @@ make_partition! {
     /// If the entry is absent, there is no PIN set.
     PIN_HASH = 2045;
 
-    /// The encryption and hmac keys.
+    /// The encryption and hmac keys of the first generation.
     ///
     /// This entry is always present. It is generated at startup if absent.
     MASTER_KEYS = 2046;
@@ -128,8 +244,9 @@ mod test {
 
     #[test]
     fn enough_credentials() {
-        use super::super::MAX_SUPPORTED_RESIDENTIAL_KEYS;
+        use super::super::{MAX_EXTERNAL_RESIDENTIAL_KEYS, MAX_SUPPORTED_RESIDENTIAL_KEYS};
         assert!(MAX_SUPPORTED_RESIDENTIAL_KEYS <= CREDENTIALS.end - CREDENTIALS.start);
+        assert!(MAX_EXTERNAL_RESIDENTIAL_KEYS <= CREDENTIALS.end - CREDENTIALS.start);
     }
 
     #[test]
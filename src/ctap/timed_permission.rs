@@ -89,6 +89,20 @@ impl U2fUserPresenceState {
         }
     }
 
+    // Granting user presence without a request, when using the transport proves presence.
+    #[cfg(feature = "with_nfc")]
+    pub fn grant_up_unrequested(&mut self, now: ClockValue) {
+        self.needs_up = TimedPermission::Waiting;
+        self.has_up = TimedPermission::granted(now, self.presence_duration);
+    }
+
+    // Forgets both requested and granted user presence.
+    #[cfg(feature = "with_nfc")]
+    pub fn revoke_up(&mut self) {
+        self.needs_up = TimedPermission::Waiting;
+        self.has_up = TimedPermission::Waiting;
+    }
+
     // This marks user presence as needed or uses it up if already granted. Also cleans up.
     pub fn consume_up(&mut self, now: ClockValue) -> bool {
         self.check_expiration(now);
@@ -185,4 +199,24 @@ mod test {
         assert!(!u2f_state.is_up_needed(ZERO));
         assert!(!u2f_state.consume_up(ZERO));
     }
+
+    #[cfg(feature = "with_nfc")]
+    #[test]
+    fn test_grant_up_unrequested() {
+        let mut u2f_state = U2fUserPresenceState::new(REQUEST_DURATION, PRESENCE_DURATION);
+        u2f_state.grant_up_unrequested(ZERO);
+        assert!(!u2f_state.is_up_needed(ZERO));
+        assert!(u2f_state.consume_up(ZERO));
+        assert!(!u2f_state.consume_up(ZERO));
+    }
+
+    #[cfg(feature = "with_nfc")]
+    #[test]
+    fn test_revoke_up() {
+        let mut u2f_state = U2fUserPresenceState::new(REQUEST_DURATION, PRESENCE_DURATION);
+        u2f_state.grant_up_unrequested(ZERO);
+        u2f_state.revoke_up();
+        assert!(!u2f_state.is_up_needed(ZERO));
+        assert!(!u2f_state.consume_up(ZERO));
+    }
 }
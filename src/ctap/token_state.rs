@@ -0,0 +1,309 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::timed_permission::TimedPermission;
+#[cfg(feature = "with_ctap2_1")]
+use alloc::string::String;
+use libtock_drivers::timer::{ClockValue, Duration};
+
+// A token has to be used for the first time within this duration after it was issued.
+const INITIAL_USAGE_TIME_LIMIT: Duration<isize> = Duration::from_ms(30000);
+// A token expires after this duration, even if it is still in use.
+const MAX_USAGE_TIME_PERIOD: Duration<isize> = Duration::from_ms(600000);
+// The user present flag of a token is cleared after this duration.
+const USER_PRESENT_TIME_LIMIT: Duration<isize> = Duration::from_ms(30000);
+
+/// Tracks the lifetime of the pinUvAuthToken and the user flags it carries.
+///
+/// The timers are checked by the usage timer observer, which the main loop
+/// calls regularly. Expired tokens are no longer in use and fail verification.
+///
+/// All invalidation goes through `stop_using_pin_uv_auth_token`, which also
+/// drops the permissions, so handlers can't keep a stale grant around.
+pub struct PinUvAuthTokenState {
+    in_use: bool,
+    used: bool,
+    initial_usage_timer: TimedPermission,
+    max_usage_timer: TimedPermission,
+    user_present: bool,
+    user_present_timer: TimedPermission,
+    user_verified: bool,
+    // Whether the token was issued after built-in user verification, as opposed to a PIN.
+    built_in_uv: bool,
+    #[cfg(feature = "with_ctap2_1")]
+    permissions: u8,
+    #[cfg(feature = "with_ctap2_1")]
+    permissions_rp_id: Option<String>,
+}
+
+impl PinUvAuthTokenState {
+    /// Creates a state for a token that is not in use.
+    pub fn new() -> PinUvAuthTokenState {
+        PinUvAuthTokenState {
+            in_use: false,
+            used: false,
+            initial_usage_timer: TimedPermission::waiting(),
+            max_usage_timer: TimedPermission::waiting(),
+            user_present: false,
+            user_present_timer: TimedPermission::waiting(),
+            user_verified: false,
+            built_in_uv: false,
+            #[cfg(feature = "with_ctap2_1")]
+            permissions: 0,
+            #[cfg(feature = "with_ctap2_1")]
+            permissions_rp_id: None,
+        }
+    }
+
+    /// Starts the timers for a newly issued token.
+    ///
+    /// Issuing a token always verifies the user. User presence depends on the
+    /// method of user verification: only built-in user verification proves it.
+    pub fn begin_using_pin_uv_auth_token(&mut self, user_is_present: bool, now: ClockValue) {
+        self.in_use = true;
+        self.used = false;
+        self.initial_usage_timer = TimedPermission::granted(now, INITIAL_USAGE_TIME_LIMIT);
+        self.max_usage_timer = TimedPermission::granted(now, MAX_USAGE_TIME_PERIOD);
+        self.user_present = user_is_present;
+        self.user_present_timer = TimedPermission::granted(now, USER_PRESENT_TIME_LIMIT);
+        self.user_verified = true;
+        self.built_in_uv = user_is_present;
+        #[cfg(feature = "with_ctap2_1")]
+        {
+            self.permissions = 0;
+            self.permissions_rp_id = None;
+        }
+    }
+
+    /// Expires the token and its user present flag when their time limits are reached.
+    pub fn pin_uv_auth_token_usage_timer_observer(&mut self, now: ClockValue) {
+        if !self.in_use {
+            return;
+        }
+        if self.user_present && !self.user_present_timer.is_granted(now) {
+            self.user_present = false;
+        }
+        if !self.max_usage_timer.is_granted(now)
+            || (!self.used && !self.initial_usage_timer.is_granted(now))
+        {
+            self.stop_using_pin_uv_auth_token();
+        }
+    }
+
+    /// Returns whether the token can be used.
+    pub fn is_in_use(&self) -> bool {
+        self.in_use
+    }
+
+    /// Records a successful use, so that the initial usage time limit no longer applies.
+    pub fn mark_used(&mut self) {
+        self.used = true;
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn get_user_present_flag_value(&self) -> bool {
+        self.in_use && self.user_present
+    }
+
+    pub fn get_user_verified_flag_value(&self) -> bool {
+        self.in_use && self.user_verified
+    }
+
+    /// Returns whether the token in use was issued after built-in user verification.
+    pub fn is_from_built_in_uv(&self) -> bool {
+        self.in_use && self.built_in_uv
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn clear_user_present_flag(&mut self) {
+        self.user_present = false;
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn clear_user_verified_flag(&mut self) {
+        self.user_verified = false;
+    }
+
+    /// Grants permissions to the token in use, optionally bound to a relying party.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn set_permissions(&mut self, permissions: u8, permissions_rp_id: Option<String>) {
+        self.permissions = permissions;
+        self.permissions_rp_id = permissions_rp_id;
+    }
+
+    /// Returns whether all bits of the permission are granted.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn has_permission(&self, permission: u8) -> bool {
+        self.permissions & permission == permission
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn permissions_rp_id(&self) -> Option<&str> {
+        self.permissions_rp_id.as_deref()
+    }
+
+    /// Binds the token to a relying party, if it is not bound yet.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn set_permissions_rp_id(&mut self, rp_id: String) {
+        self.permissions_rp_id.get_or_insert(rp_id);
+    }
+
+    /// Removes all permissions except the kept ones.
+    ///
+    /// A token without any permission left can't be used for anything, so it
+    /// is invalidated.
+    #[cfg(feature = "with_ctap2_1")]
+    pub fn clear_permissions_except(&mut self, kept_permissions: u8) {
+        self.permissions &= kept_permissions;
+        if self.permissions == 0 {
+            self.stop_using_pin_uv_auth_token();
+        }
+    }
+
+    /// Invalidates the token and clears all its flags and permissions.
+    pub fn stop_using_pin_uv_auth_token(&mut self) {
+        *self = PinUvAuthTokenState::new();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLOCK_FREQUENCY_HZ: usize = 32768;
+    const START_CLOCK_VALUE: ClockValue = ClockValue::new(0, CLOCK_FREQUENCY_HZ);
+
+    fn clock_value_after_ms(ms: isize) -> ClockValue {
+        ClockValue::new(ms * CLOCK_FREQUENCY_HZ as isize / 1000, CLOCK_FREQUENCY_HZ)
+    }
+
+    #[test]
+    fn test_begin_using_pin_uv_auth_token() {
+        let mut token_state = PinUvAuthTokenState::new();
+        assert!(!token_state.is_in_use());
+        assert!(!token_state.get_user_verified_flag_value());
+        token_state.begin_using_pin_uv_auth_token(false, START_CLOCK_VALUE);
+        assert!(token_state.is_in_use());
+        assert!(token_state.get_user_verified_flag_value());
+        assert!(!token_state.user_present);
+        assert!(!token_state.is_from_built_in_uv());
+        token_state.begin_using_pin_uv_auth_token(true, START_CLOCK_VALUE);
+        assert!(token_state.user_present);
+        assert!(token_state.is_from_built_in_uv());
+    }
+
+    #[test]
+    fn test_initial_usage_time_limit() {
+        let mut token_state = PinUvAuthTokenState::new();
+        token_state.begin_using_pin_uv_auth_token(false, START_CLOCK_VALUE);
+        token_state.pin_uv_auth_token_usage_timer_observer(clock_value_after_ms(29000));
+        assert!(token_state.is_in_use());
+        token_state.pin_uv_auth_token_usage_timer_observer(clock_value_after_ms(31000));
+        assert!(!token_state.is_in_use());
+        assert!(!token_state.get_user_verified_flag_value());
+    }
+
+    #[test]
+    fn test_max_usage_time_period() {
+        let mut token_state = PinUvAuthTokenState::new();
+        token_state.begin_using_pin_uv_auth_token(false, START_CLOCK_VALUE);
+        token_state.mark_used();
+        token_state.pin_uv_auth_token_usage_timer_observer(clock_value_after_ms(31000));
+        assert!(token_state.is_in_use());
+        token_state.pin_uv_auth_token_usage_timer_observer(clock_value_after_ms(599000));
+        assert!(token_state.is_in_use());
+        token_state.pin_uv_auth_token_usage_timer_observer(clock_value_after_ms(601000));
+        assert!(!token_state.is_in_use());
+    }
+
+    #[test]
+    fn test_user_present_time_limit() {
+        let mut token_state = PinUvAuthTokenState::new();
+        token_state.begin_using_pin_uv_auth_token(true, START_CLOCK_VALUE);
+        token_state.mark_used();
+        token_state.pin_uv_auth_token_usage_timer_observer(clock_value_after_ms(29000));
+        assert!(token_state.user_present);
+        token_state.pin_uv_auth_token_usage_timer_observer(clock_value_after_ms(31000));
+        assert!(!token_state.user_present);
+        assert!(token_state.get_user_verified_flag_value());
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_clear_user_flags() {
+        let mut token_state = PinUvAuthTokenState::new();
+        token_state.begin_using_pin_uv_auth_token(true, START_CLOCK_VALUE);
+        assert!(token_state.get_user_present_flag_value());
+        token_state.clear_user_present_flag();
+        assert!(!token_state.get_user_present_flag_value());
+        assert!(token_state.get_user_verified_flag_value());
+        token_state.clear_user_verified_flag();
+        assert!(!token_state.get_user_verified_flag_value());
+        assert!(token_state.is_in_use());
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_permissions() {
+        let mut token_state = PinUvAuthTokenState::new();
+        token_state.begin_using_pin_uv_auth_token(false, START_CLOCK_VALUE);
+        token_state.set_permissions(0x03, None);
+        assert!(token_state.has_permission(0x01));
+        assert!(token_state.has_permission(0x03));
+        assert!(!token_state.has_permission(0x04));
+        assert_eq!(token_state.permissions_rp_id(), None);
+        token_state.set_permissions_rp_id(String::from("example.com"));
+        token_state.set_permissions_rp_id(String::from("counter-example.com"));
+        assert_eq!(token_state.permissions_rp_id(), Some("example.com"));
+
+        // A new token starts without permissions.
+        token_state.begin_using_pin_uv_auth_token(false, START_CLOCK_VALUE);
+        assert!(!token_state.has_permission(0x01));
+        assert_eq!(token_state.permissions_rp_id(), None);
+    }
+
+    #[cfg(feature = "with_ctap2_1")]
+    #[test]
+    fn test_clear_permissions_except() {
+        let mut token_state = PinUvAuthTokenState::new();
+        token_state.begin_using_pin_uv_auth_token(false, START_CLOCK_VALUE);
+        token_state.set_permissions(0x13, None);
+        token_state.clear_permissions_except(0x10);
+        assert!(token_state.is_in_use());
+        assert!(token_state.has_permission(0x10));
+        assert!(!token_state.has_permission(0x01));
+
+        // Exhausting all permissions invalidates the token.
+        token_state.clear_permissions_except(0x20);
+        assert!(!token_state.is_in_use());
+        assert!(!token_state.get_user_verified_flag_value());
+    }
+
+    #[test]
+    fn test_stop_using_pin_uv_auth_token() {
+        let mut token_state = PinUvAuthTokenState::new();
+        token_state.begin_using_pin_uv_auth_token(true, START_CLOCK_VALUE);
+        #[cfg(feature = "with_ctap2_1")]
+        token_state.set_permissions(0xFF, Some(String::from("example.com")));
+        token_state.stop_using_pin_uv_auth_token();
+        assert!(!token_state.is_in_use());
+        assert!(!token_state.user_present);
+        assert!(!token_state.get_user_verified_flag_value());
+        #[cfg(feature = "with_ctap2_1")]
+        {
+            assert!(!token_state.has_permission(0x01));
+            assert_eq!(token_state.permissions_rp_id(), None);
+        }
+    }
+}
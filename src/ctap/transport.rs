@@ -0,0 +1,87 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::data_formats::AuthenticatorTransport;
+use super::hid::{ChannelID, KeepaliveStatus, UsbTransport};
+#[cfg(feature = "with_nfc")]
+use super::nfc::{NfcTransport, NFC_CHANNEL_ID};
+use super::status_code::Ctap2StatusCode;
+use libtock_drivers::timer::Duration;
+
+// What the CTAP layer needs to know about the transport that carries a command. Each transport
+// frames messages its own way, e.g. CTAPHID packets or ISO 14443-4 frames.
+pub trait Transport {
+    // The transport, as listed in GetInfo since CTAP 2.1.
+    fn transport_type(&self) -> AuthenticatorTransport;
+
+    // Longest CTAP message that the transport reassembles, reported as maxMsgSize in GetInfo when
+    // it carries the GetInfo command. Longer commands fail with CTAP2_ERR_REQUEST_TOO_LARGE.
+    fn max_msg_size(&self) -> usize;
+
+    // Tells the platform that the command of the channel is still being processed. An error means
+    // that the platform cancelled the command.
+    fn send_keepalive(
+        &self,
+        cid: ChannelID,
+        status: KeepaliveStatus,
+    ) -> Result<(), Ctap2StatusCode>;
+
+    // Waits for the platform to cancel the command of the channel, until the timeout elapses or the
+    // wake-up condition holds. An error means that the platform cancelled the command.
+    fn wait_for_cancel(
+        &self,
+        cid: ChannelID,
+        timeout: Duration<isize>,
+        wake_up: &dyn Fn() -> bool,
+    ) -> Result<(), Ctap2StatusCode>;
+
+    // Sends a unit of the reply built by the transport layer, i.e. a packet or a frame.
+    fn send_response(&self, response: &mut [u8]) -> Result<(), Ctap2StatusCode>;
+}
+
+// All transports of this build, starting with USB.
+#[cfg(feature = "with_ctap2_1")]
+pub const TRANSPORTS: &[&dyn Transport] = &[
+    &UsbTransport,
+    #[cfg(feature = "with_nfc")]
+    &NfcTransport,
+];
+
+// Returns the transport that carried a command of the channel.
+#[cfg_attr(not(feature = "with_nfc"), allow(unused_variables))]
+pub fn transport_of(cid: ChannelID) -> &'static dyn Transport {
+    #[cfg(feature = "with_nfc")]
+    {
+        if cid == NFC_CHANNEL_ID {
+            return &NfcTransport;
+        }
+    }
+    &UsbTransport
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transport_of() {
+        let usb = transport_of([0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(usb.transport_type(), AuthenticatorTransport::Usb);
+        #[cfg(feature = "with_nfc")]
+        assert_eq!(
+            transport_of(NFC_CHANNEL_ID).transport_type(),
+            AuthenticatorTransport::Nfc
+        );
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use libtock_drivers::external_flash::{self, get_info_nr};
+use persistent_store::{Storage, StorageError, StorageIndex, StorageResult};
+
+fn get_info(nr: usize) -> StorageResult<usize> {
+    external_flash::get_info(nr).ok_or(StorageError::CustomError)
+}
+
+pub struct ExternalStorage {
+    word_size: usize,
+    page_size: usize,
+    num_pages: usize,
+    max_word_writes: usize,
+    max_page_erases: usize,
+    storage: &'static [u8],
+}
+
+impl ExternalStorage {
+    /// Provides access to the first pages of the external flash if available.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CustomError` if the kernel has no external flash driver, or if any of the
+    /// following conditions do not hold:
+    /// - The word size is a power of two.
+    /// - The page size is a power of two.
+    /// - The page size is a multiple of the word size.
+    ///
+    /// Returns `OutOfBounds` if the number of pages does not fit in the flash.
+    pub fn new(num_pages: usize) -> StorageResult<ExternalStorage> {
+        if !external_flash::is_available() {
+            return Err(StorageError::CustomError);
+        }
+        let mut storage = ExternalStorage {
+            word_size: get_info(get_info_nr::WORD_SIZE)?,
+            page_size: get_info(get_info_nr::PAGE_SIZE)?,
+            num_pages,
+            max_word_writes: get_info(get_info_nr::MAX_WORD_WRITES)?,
+            max_page_erases: get_info(get_info_nr::MAX_PAGE_ERASES)?,
+            storage: &[],
+        };
+        if !storage.word_size.is_power_of_two()
+            || !storage.page_size.is_power_of_two()
+            || storage.page_size & (storage.word_size - 1) != 0
+        {
+            return Err(StorageError::CustomError);
+        }
+        let mapping = external_flash::mapping().ok_or(StorageError::CustomError)?;
+        storage.storage = mapping
+            .get(..num_pages * storage.page_size)
+            .ok_or(StorageError::OutOfBounds)?;
+        Ok(storage)
+    }
+}
+
+impl Storage for ExternalStorage {
+    fn word_size(&self) -> usize {
+        self.word_size
+    }
+
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn num_pages(&self) -> usize {
+        self.num_pages
+    }
+
+    fn max_word_writes(&self) -> usize {
+        self.max_word_writes
+    }
+
+    fn max_page_erases(&self) -> usize {
+        self.max_page_erases
+    }
+
+    fn read_slice(&self, index: StorageIndex, length: usize) -> StorageResult<&[u8]> {
+        Ok(&self.storage[index.range(length, self)?])
+    }
+
+    fn write_slice(&mut self, index: StorageIndex, value: &[u8]) -> StorageResult<()> {
+        if index.byte & (self.word_size - 1) != 0 || value.len() & (self.word_size - 1) != 0 {
+            return Err(StorageError::NotAligned);
+        }
+        let range = index.range(value.len(), self)?;
+        if !external_flash::write_slice(range.start, value) {
+            return Err(StorageError::CustomError);
+        }
+        Ok(())
+    }
+
+    fn erase_page(&mut self, page: usize) -> StorageResult<()> {
+        let index = StorageIndex { page, byte: 0 };
+        let range = index.range(self.page_size, self)?;
+        if !external_flash::erase_page(range.start, self.page_size) {
+            return Err(StorageError::CustomError);
+        }
+        Ok(())
+    }
+}
@@ -12,23 +12,100 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(all(not(feature = "std"), feature = "with_external_flash"))]
+mod external;
 #[cfg(not(feature = "std"))]
 mod syscall;
 
+#[cfg(all(not(feature = "std"), feature = "with_external_flash"))]
+pub use self::external::ExternalStorage;
 #[cfg(not(feature = "std"))]
 pub use self::syscall::SyscallStorage;
 
 /// Storage definition for production.
-#[cfg(not(feature = "std"))]
+#[cfg(all(not(feature = "std"), not(feature = "with_external_flash")))]
 mod prod {
     pub type Storage = super::SyscallStorage;
 
     pub fn new_storage(num_pages: usize) -> Storage {
         Storage::new(num_pages).unwrap()
     }
+
+    pub fn new_external_storage(_num_pages: usize) -> Option<Storage> {
+        None
+    }
 }
-#[cfg(not(feature = "std"))]
-pub use self::prod::{new_storage, Storage};
+#[cfg(all(not(feature = "std"), not(feature = "with_external_flash")))]
+pub use self::prod::{new_external_storage, new_storage, Storage};
+
+/// Storage definition for production with an external flash.
+///
+/// Both flashes have the same storage type, so that the persistent store handles their stores
+/// alike.
+#[cfg(all(not(feature = "std"), feature = "with_external_flash"))]
+mod prod {
+    use super::{ExternalStorage, SyscallStorage};
+    use persistent_store::{Storage as _, StorageIndex, StorageResult};
+
+    pub enum Storage {
+        Embedded(SyscallStorage),
+        External(ExternalStorage),
+    }
+
+    pub fn new_storage(num_pages: usize) -> Storage {
+        Storage::Embedded(SyscallStorage::new(num_pages).unwrap())
+    }
+
+    /// Returns the storage of the external flash, or `None` if the board has none.
+    pub fn new_external_storage(num_pages: usize) -> Option<Storage> {
+        ExternalStorage::new(num_pages).ok().map(Storage::External)
+    }
+
+    macro_rules! delegate {
+        ($storage: expr, $inner: ident => $call: expr) => {
+            match $storage {
+                Storage::Embedded($inner) => $call,
+                Storage::External($inner) => $call,
+            }
+        };
+    }
+
+    impl persistent_store::Storage for Storage {
+        fn word_size(&self) -> usize {
+            delegate!(self, storage => storage.word_size())
+        }
+
+        fn page_size(&self) -> usize {
+            delegate!(self, storage => storage.page_size())
+        }
+
+        fn num_pages(&self) -> usize {
+            delegate!(self, storage => storage.num_pages())
+        }
+
+        fn max_word_writes(&self) -> usize {
+            delegate!(self, storage => storage.max_word_writes())
+        }
+
+        fn max_page_erases(&self) -> usize {
+            delegate!(self, storage => storage.max_page_erases())
+        }
+
+        fn read_slice(&self, index: StorageIndex, length: usize) -> StorageResult<&[u8]> {
+            delegate!(self, storage => storage.read_slice(index, length))
+        }
+
+        fn write_slice(&mut self, index: StorageIndex, value: &[u8]) -> StorageResult<()> {
+            delegate!(self, storage => storage.write_slice(index, value))
+        }
+
+        fn erase_page(&mut self, page: usize) -> StorageResult<()> {
+            delegate!(self, storage => storage.erase_page(page))
+        }
+    }
+}
+#[cfg(all(not(feature = "std"), feature = "with_external_flash"))]
+pub use self::prod::{new_external_storage, new_storage, Storage};
 
 /// Storage definition for testing.
 #[cfg(feature = "std")]
@@ -47,6 +124,11 @@ mod test {
         };
         Storage::new(store, options)
     }
+
+    // Tests add an external flash to the persistent store explicitly.
+    pub fn new_external_storage(_num_pages: usize) -> Option<Storage> {
+        None
+    }
 }
 #[cfg(feature = "std")]
-pub use self::test::{new_storage, Storage};
+pub use self::test::{new_external_storage, new_storage, Storage};
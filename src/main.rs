@@ -28,17 +28,29 @@ pub mod embedded_flash;
 use core::cell::Cell;
 #[cfg(feature = "debug_ctap")]
 use core::fmt::Write;
-use crypto::rng256::TockRng256;
-use ctap::hid::{ChannelID, CtapHid, KeepaliveStatus, ProcessedPacket};
+use crypto::entropy::{EntropyPool, TockEntropySource};
+use ctap::hid::{ChannelID, CtapHid, KeepaliveStatus, UsbTransport};
+#[cfg(feature = "with_nfc")]
+use ctap::nfc::{CtapNfc, NfcTransport, NFC_CHANNEL_ID};
+use ctap::self_test;
 use ctap::status_code::Ctap2StatusCode;
-use ctap::CtapState;
-use libtock_core::result::{CommandError, EALREADY};
+use ctap::status_leds;
+use ctap::status_leds::LedStatus;
+use ctap::transport::{transport_of, Transport};
+use ctap::{CtapState, UserPresenceConfig};
 use libtock_drivers::buttons;
 use libtock_drivers::buttons::ButtonState;
 #[cfg(feature = "debug_ctap")]
 use libtock_drivers::console::Console;
 use libtock_drivers::led;
-use libtock_drivers::result::{FlexUnwrap, TockError};
+#[cfg(feature = "with_nfc")]
+use libtock_drivers::nfc;
+#[cfg(feature = "with_nfc")]
+use libtock_drivers::nfc::{
+    FieldState, NfcEmulationGuard, NfcError, NfcOperation, NfcTag, PendingRecv, RecvCallback,
+    RecvOp, TagConfig, UidLength, UidMode,
+};
+use libtock_drivers::result::FlexUnwrap;
 use libtock_drivers::timer;
 use libtock_drivers::timer::Duration;
 #[cfg(feature = "debug_ctap")]
@@ -49,7 +61,34 @@ use libtock_drivers::usb_ctap_hid;
 
 const KEEPALIVE_DELAY_MS: isize = 100;
 const KEEPALIVE_DELAY: Duration<isize> = Duration::from_ms(KEEPALIVE_DELAY_MS);
-const SEND_TIMEOUT: Duration<isize> = Duration::from_ms(1000);
+// Pressing this button denies user presence instead of confirming it. All other buttons confirm.
+// Boards with a single button can't deny, so denial is disabled by default.
+const DENY_BUTTON: Option<usize> = None;
+// Type 4 Tag, as required to exchange ISO 7816 APDUs.
+#[cfg(feature = "with_nfc")]
+const NFC_TAG_TYPE: u8 = 4;
+// This flag enables a random UID, drawn again each time the key enters a field, so that readers
+// can't track it. Turn it off if your deployment identifies keys by their UID.
+#[cfg(feature = "with_nfc")]
+const NFC_USE_RANDOM_UID: bool = true;
+// ISO 14443-3 section 6.5.2 and 6.5.3.4: a random 4 byte UID or the 7 byte UID of the chip, and a
+// SAK announcing ISO 14443-4 support. The FSCI must match the ATS sent by CtapNfc.
+#[cfg(feature = "with_nfc")]
+const NFC_TAG_CONFIG: TagConfig = TagConfig {
+    uid_length: if NFC_USE_RANDOM_UID {
+        UidLength::Single
+    } else {
+        UidLength::Double
+    },
+    atqa: if NFC_USE_RANDOM_UID {
+        [0x04, 0x00]
+    } else {
+        [0x44, 0x00]
+    },
+    sak: 0x20,
+    fsci: CtapNfc::FSCI,
+    historical_bytes: &[],
+};
 
 fn main() {
     // Setup the timer with a dummy callback (we only care about reading the current time, but the
@@ -63,9 +102,55 @@ fn main() {
     }
 
     let boot_time = timer.get_current_clock().flex_unwrap();
-    let mut rng = TockRng256 {};
+    let mut rng = EntropyPool::new(TockEntropySource {});
+    // Nothing is processed with faulty crypto, not even to report which test failed.
+    if let Err(_failure) = self_test::run(&rng) {
+        #[cfg(feature = "debug_ctap")]
+        writeln!(Console::new(), "Self-test failed: {:?}", _failure).unwrap();
+        halt_on_self_test_failure();
+    }
     let mut ctap_state = CtapState::new(&mut rng, check_user_presence, boot_time);
     let mut ctap_hid = CtapHid::new();
+    #[cfg(feature = "with_nfc")]
+    let mut ctap_nfc = CtapNfc::new();
+
+    // Setup NFC driver, if present. USB remains the main transport.
+    #[cfg(feature = "with_nfc")]
+    let nfc_available = NfcTag::setup().is_ok();
+    #[cfg(feature = "with_nfc")]
+    let mut nfc_emulation = None;
+    #[cfg(feature = "with_nfc")]
+    let nfc_reception = NfcOperation::new();
+    #[cfg(feature = "with_nfc")]
+    let mut nfc_callback = RecvCallback::new(&nfc_reception);
+    #[cfg(feature = "with_nfc")]
+    let mut nfc_frame = [0; CtapNfc::MAX_FRAME_SIZE];
+    #[cfg(feature = "with_nfc")]
+    let mut nfc_pending: Option<PendingRecv> = None;
+    // Field events arrive before the reader selects the tag, which leaves time to enable the
+    // emulation.
+    #[cfg(feature = "with_nfc")]
+    let nfc_field = Cell::new(None);
+    #[cfg(feature = "with_nfc")]
+    let mut nfc_field_callback = nfc::with_field_callback(|state| nfc_field.set(Some(state)));
+    #[cfg(feature = "with_nfc")]
+    let _nfc_field_events = if nfc_available {
+        nfc_field_callback.init().ok()
+    } else {
+        None
+    };
+    // Receptions are refused until a reader selects the tag, so we issue the next one when it
+    // does, instead of at the next timeout.
+    #[cfg(feature = "with_nfc")]
+    let nfc_selected = Cell::new(false);
+    #[cfg(feature = "with_nfc")]
+    let mut nfc_select_callback = nfc::with_select_callback(|| nfc_selected.set(true));
+    #[cfg(feature = "with_nfc")]
+    let _nfc_select_events = if nfc_available {
+        nfc_select_callback.init().ok()
+    } else {
+        None
+    };
 
     let mut led_counter = 0;
     let mut last_led_increment = boot_time;
@@ -92,8 +177,66 @@ fn main() {
             button.enable().flex_unwrap();
         }
 
+        // Issue an NFC reception if none is pending. Its callback is executed while we wait for USB
+        // packets below.
+        #[cfg(feature = "with_nfc")]
+        {
+            nfc_selected.set(false);
+            match nfc_field.take() {
+                Some(FieldState::On) => {
+                    if nfc_emulation.is_none() {
+                        nfc_emulation = start_nfc_emulation();
+                    }
+                }
+                Some(FieldState::Off) => {
+                    // Abort the in-flight exchange, the next reader starts from scratch.
+                    if let Some(pending) = nfc_pending.take() {
+                        pending.abort().ok();
+                    }
+                    ctap_nfc.abort(&mut ctap_state);
+                    nfc_emulation = None;
+                }
+                None => (),
+            }
+            if nfc_available && nfc_pending.is_none() {
+                nfc_pending = match NfcTag::receive_start(
+                    &mut nfc_frame,
+                    CtapNfc::MAX_FRAME_SIZE,
+                    &mut nfc_callback,
+                ) {
+                    Ok(pending) => Some(pending),
+                    Err(e) => {
+                        process_nfc_error(&mut ctap_nfc, &mut nfc_emulation, e, &mut ctap_state);
+                        None
+                    }
+                };
+            }
+        }
+
+        // Any of these events ends the wait for a USB packet, so that we sleep until there is
+        // something to do. The timeout keeps the LEDs blinking.
+        let wake_up = || {
+            #[cfg(feature = "with_ctap1")]
+            {
+                if button_touched.get() {
+                    return true;
+                }
+            }
+            #[cfg(feature = "with_nfc")]
+            {
+                if nfc_reception.poll().is_some() || nfc_field.get().is_some() || nfc_selected.get()
+                {
+                    return true;
+                }
+            }
+            false
+        };
         let mut pkt_request = [0; 64];
-        let has_packet = match usb_ctap_hid::recv_with_timeout(&mut pkt_request, KEEPALIVE_DELAY) {
+        let has_packet = match usb_ctap_hid::recv_with_timeout_or_wake_up(
+            &mut pkt_request,
+            KEEPALIVE_DELAY,
+            wake_up,
+        ) {
             Some(usb_ctap_hid::SendOrRecvStatus::Received) => {
                 #[cfg(feature = "debug_ctap")]
                 print_packet_notice("Received packet", &timer);
@@ -121,39 +264,66 @@ fn main() {
 
         // These calls are making sure that even for long inactivity, wrapping clock values
         // never randomly wink or grant user presence for U2F.
-        ctap_state.update_command_permission(now);
+        ctap_state.update_timeouts(now);
         ctap_hid.wink_permission = ctap_hid.wink_permission.check_expiration(now);
 
         if has_packet {
             let reply = ctap_hid.process_hid_packet(&pkt_request, now, &mut ctap_state);
             // This block handles sending packets.
             for mut pkt_reply in reply {
-                let status = usb_ctap_hid::send_or_recv_with_timeout(&mut pkt_reply, SEND_TIMEOUT);
-                match status {
-                    None => {
-                        #[cfg(feature = "debug_ctap")]
-                        print_packet_notice("Sending packet timed out", &timer);
-                        // TODO: reset the ctap_hid state.
-                        // Since sending the packet timed out, we cancel this reply.
-                        break;
-                    }
-                    Some(usb_ctap_hid::SendOrRecvStatus::Error) => panic!("Error sending packet"),
-                    Some(usb_ctap_hid::SendOrRecvStatus::Sent) => {
-                        #[cfg(feature = "debug_ctap")]
-                        print_packet_notice("Sent packet", &timer);
+                if UsbTransport.send_response(&mut pkt_reply).is_err() {
+                    #[cfg(feature = "debug_ctap")]
+                    print_packet_notice("Sending packet timed out", &timer);
+                    // TODO: reset the ctap_hid state.
+                    // Since sending the packet timed out, we cancel this reply.
+                    break;
+                }
+                #[cfg(feature = "debug_ctap")]
+                print_packet_notice("Sent packet", &timer);
+            }
+        }
+
+        // The NFC self-test aborted our reception, and left the emulation disabled.
+        #[cfg(feature = "with_nfc")]
+        {
+            if ctap_state.nfc_driver_taken {
+                ctap_state.nfc_driver_taken = false;
+                nfc_pending = None;
+                ctap_nfc.abort(&mut ctap_state);
+                nfc_emulation = None;
+            }
+        }
+
+        #[cfg(feature = "with_nfc")]
+        {
+            if let Some(result) = nfc_pending.as_ref().and_then(PendingRecv::poll) {
+                // Unshare the buffer with the driver before reading the frame.
+                nfc_pending = None;
+                match result {
+                    Ok(recv_op) => {
+                        let now = timer.get_current_clock().flex_unwrap();
+                        process_nfc_frame(&mut ctap_nfc, recv_op, &nfc_frame, now, &mut ctap_state);
                     }
-                    Some(usb_ctap_hid::SendOrRecvStatus::Received) => {
-                        #[cfg(feature = "debug_ctap")]
-                        print_packet_notice("Received an UNEXPECTED packet", &timer);
-                        // TODO: handle this unexpected packet.
+                    Err(e) => {
+                        process_nfc_error(&mut ctap_nfc, &mut nfc_emulation, e, &mut ctap_state)
                     }
                 }
             }
         }
 
+        // Compacts the store while the device is idle, so that commands rarely wait for it. A step
+        // compacts at most one page, which delays the next packet by a bounded amount of time.
+        #[cfg(feature = "with_nfc")]
+        let nfc_idle = nfc_emulation.is_none();
+        #[cfg(not(feature = "with_nfc"))]
+        let nfc_idle = true;
+        if !has_packet && nfc_idle {
+            ctap_state.compact_store_step();
+        }
+
         let now = timer.get_current_clock().flex_unwrap();
         if let Some(wait_duration) = now.wrapping_sub(last_led_increment) {
-            if wait_duration > KEEPALIVE_DELAY {
+            if wait_duration > status_leds::FRAME_DURATION {
                 // Loops quickly when waiting for U2F user presence, so the next LED blink
                 // state is only set if enough time has elapsed.
                 led_counter += 1;
@@ -165,22 +335,108 @@ fn main() {
             last_led_increment = now;
         }
 
-        if ctap_hid.wink_permission.is_granted(now) {
+        #[cfg(feature = "with_ctap1")]
+        let u2f_up_needed = ctap_state.u2f_up_state.is_up_needed(now);
+        #[cfg(not(feature = "with_ctap1"))]
+        let u2f_up_needed = false;
+        if ctap_state.self_test_failure().is_some() {
+            status_leds::display(LedStatus::SelfTestFailed.is_lit(led_counter));
+        } else if ctap_hid.wink_permission.is_granted(now) {
             wink_leds(led_counter);
+        } else if u2f_up_needed {
+            // Flash the LEDs with an almost regular pattern. The inaccuracy comes from
+            // delay caused by processing and sending of packets.
+            status_leds::display(LedStatus::AwaitingTouch.is_lit(led_counter));
+        } else if let Some(lit) = ctap_state.status_leds.is_lit(now) {
+            // Tells the user why the last command failed.
+            status_leds::display(lit);
         } else {
-            #[cfg(not(feature = "with_ctap1"))]
             switch_off_leds();
-            #[cfg(feature = "with_ctap1")]
-            {
-                if ctap_state.u2f_up_state.is_up_needed(now) {
-                    // Flash the LEDs with an almost regular pattern. The inaccuracy comes from
-                    // delay caused by processing and sending of packets.
-                    blink_leds(led_counter);
-                } else {
-                    switch_off_leds();
-                }
+        }
+    }
+}
+
+// Answers the frame received from the NFC reader, if it was received correctly.
+#[cfg(feature = "with_nfc")]
+fn process_nfc_frame<R, CheckUserPresence>(
+    ctap_nfc: &mut CtapNfc,
+    recv_op: RecvOp,
+    frame: &[u8],
+    now: timer::ClockValue,
+    ctap_state: &mut CtapState<R, CheckUserPresence>,
+) where
+    R: crypto::rng256::Rng256,
+    CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
+{
+    ctap_state.nfc_statistics.frames_received += 1;
+    // Frames with an invalid CRC are dropped, the reader retransmits.
+    if recv_op.result_code != 0 {
+        #[cfg(feature = "debug_ctap")]
+        writeln!(Console::new(), "Dropped NFC frame").unwrap();
+        ctap_state.nfc_statistics.crc_errors += 1;
+        return;
+    }
+    let frame = &frame[..recv_op.recv_amount];
+    if let Some(mut reply) = ctap_nfc.process_frame(frame, now, ctap_state) {
+        if NfcTransport.send_response(&mut reply).is_ok() {
+            ctap_state.nfc_statistics.frames_sent += 1;
+            // The PPS response is sent at the previous bit rate.
+            if let Some(bit_rate) = ctap_nfc.take_bit_rate_change() {
+                NfcTag::set_bit_rate(bit_rate, CtapNfc::frame_timing()).ok();
             }
+        } else {
+            #[cfg(feature = "debug_ctap")]
+            writeln!(Console::new(), "Error sending NFC frame").unwrap();
+            ctap_nfc.reset();
+        }
+    }
+}
+
+// Enables the tag emulation until the returned guard is dropped.
+#[cfg(feature = "with_nfc")]
+fn start_nfc_emulation() -> Option<NfcEmulationGuard> {
+    // The identity must be set before the reader activates the tag.
+    NfcTag::set_tag_config(&NFC_TAG_CONFIG).ok();
+    let uid_mode = if NFC_USE_RANDOM_UID {
+        UidMode::Random
+    } else {
+        UidMode::Fixed
+    };
+    NfcTag::set_uid_mode(uid_mode).ok();
+    let guard = NfcEmulationGuard::new().ok()?;
+    NfcTag::configure(NFC_TAG_TYPE).ok();
+    NfcTag::set_frame_timing(CtapNfc::frame_timing()).ok();
+    Some(guard)
+}
+
+// Updates the emulation state when the driver refuses a reception.
+#[cfg(feature = "with_nfc")]
+fn process_nfc_error<R, CheckUserPresence>(
+    ctap_nfc: &mut CtapNfc,
+    nfc_emulation: &mut Option<NfcEmulationGuard>,
+    error: NfcError,
+    ctap_state: &mut CtapState<R, CheckUserPresence>,
+) where
+    R: crypto::rng256::Rng256,
+    CheckUserPresence: Fn(ChannelID, UserPresenceConfig) -> Result<(), Ctap2StatusCode>,
+{
+    match error {
+        NfcError::EmulationDisabled => {
+            // The tag is not emulated yet. On failure, we try again at the next reception. A stale
+            // guard is dropped first, so that it doesn't disable the new emulation.
+            *nfc_emulation = None;
+            *nfc_emulation = start_nfc_emulation();
         }
+        NfcError::FieldLost => {
+            // The field was lost, the next reader starts from scratch.
+            #[cfg(feature = "debug_ctap")]
+            writeln!(Console::new(), "NFC field lost").unwrap();
+            ctap_nfc.abort(ctap_state);
+            *nfc_emulation = None;
+        }
+        // A busy driver means that no reader selected the tag yet. Aborted receptions are issued
+        // again.
+        _ => (),
     }
 }
 
@@ -198,80 +454,6 @@ fn print_packet_notice(notice_text: &str, timer: &Timer) {
     .unwrap();
 }
 
-// Returns whether the keepalive was sent, or false if cancelled.
-fn send_keepalive_up_needed(
-    cid: ChannelID,
-    timeout: Duration<isize>,
-) -> Result<(), Ctap2StatusCode> {
-    let keepalive_msg = CtapHid::keepalive(cid, KeepaliveStatus::UpNeeded);
-    for mut pkt in keepalive_msg {
-        let status = usb_ctap_hid::send_or_recv_with_timeout(&mut pkt, timeout);
-        match status {
-            None => {
-                #[cfg(feature = "debug_ctap")]
-                writeln!(Console::new(), "Sending a KEEPALIVE packet timed out").unwrap();
-                // TODO: abort user presence test?
-            }
-            Some(usb_ctap_hid::SendOrRecvStatus::Error) => panic!("Error sending KEEPALIVE packet"),
-            Some(usb_ctap_hid::SendOrRecvStatus::Sent) => {
-                #[cfg(feature = "debug_ctap")]
-                writeln!(Console::new(), "Sent KEEPALIVE packet").unwrap();
-            }
-            Some(usb_ctap_hid::SendOrRecvStatus::Received) => {
-                // We only parse one packet, because we only care about CANCEL.
-                let (received_cid, processed_packet) = CtapHid::process_single_packet(&pkt);
-                if received_cid != &cid {
-                    #[cfg(feature = "debug_ctap")]
-                    writeln!(
-                        Console::new(),
-                        "Received a packet on channel ID {:?} while sending a KEEPALIVE packet",
-                        received_cid,
-                    )
-                    .unwrap();
-                    return Ok(());
-                }
-                match processed_packet {
-                    ProcessedPacket::InitPacket { cmd, .. } => {
-                        if cmd == CtapHid::COMMAND_CANCEL {
-                            // We ignore the payload, we can't answer with an error code anyway.
-                            #[cfg(feature = "debug_ctap")]
-                            writeln!(Console::new(), "User presence check cancelled").unwrap();
-                            return Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
-                        } else {
-                            #[cfg(feature = "debug_ctap")]
-                            writeln!(
-                                Console::new(),
-                                "Discarded packet with command {} received while sending a KEEPALIVE packet",
-                                cmd,
-                            )
-                            .unwrap();
-                        }
-                    }
-                    ProcessedPacket::ContinuationPacket { .. } => {
-                        #[cfg(feature = "debug_ctap")]
-                        writeln!(
-                            Console::new(),
-                            "Discarded continuation packet received while sending a KEEPALIVE packet",
-                        )
-                        .unwrap();
-                    }
-                }
-            }
-        }
-    }
-    Ok(())
-}
-
-fn blink_leds(pattern_seed: usize) {
-    for l in 0..led::count().flex_unwrap() {
-        if (pattern_seed ^ l).count_ones() & 1 != 0 {
-            led::get(l).flex_unwrap().on().flex_unwrap();
-        } else {
-            led::get(l).flex_unwrap().off().flex_unwrap();
-        }
-    }
-}
-
 fn wink_leds(pattern_seed: usize) {
     // This generates a "snake" pattern circling through the LEDs.
     // Fox example with 4 LEDs the sequence of lit LEDs will be the following.
@@ -305,71 +487,101 @@ fn wink_leds(pattern_seed: usize) {
     }
 }
 
+fn selection_leds(selection: usize, pattern_seed: usize) {
+    // Shows the number of the candidate, counting from 1, in binary. It blinks, so that each
+    // candidate has a distinct pattern from the usual user presence blinking.
+    for l in 0..led::count().flex_unwrap() {
+        if pattern_seed % 2 == 0 && ((selection + 1) >> l) & 1 != 0 {
+            led::get(l).flex_unwrap().on().flex_unwrap();
+        } else {
+            led::get(l).flex_unwrap().off().flex_unwrap();
+        }
+    }
+}
+
+// Blinks the failure pattern forever.
+fn halt_on_self_test_failure() -> ! {
+    let mut frame = 0;
+    loop {
+        status_leds::display(LedStatus::SelfTestFailed.is_lit(frame));
+        timer::sleep(status_leds::FRAME_DURATION).flex_unwrap();
+        frame += 1;
+    }
+}
+
 fn switch_off_leds() {
     for l in 0..led::count().flex_unwrap() {
         led::get(l).flex_unwrap().off().flex_unwrap();
     }
 }
 
-fn check_user_presence(cid: ChannelID) -> Result<(), Ctap2StatusCode> {
+fn check_user_presence(cid: ChannelID, config: UserPresenceConfig) -> Result<(), Ctap2StatusCode> {
+    // Over NFC, tapping the key is the user presence test.
+    #[cfg(feature = "with_nfc")]
+    {
+        if cid == NFC_CHANNEL_ID {
+            return Ok(());
+        }
+    }
+
     // The timeout is N times the keepalive delay.
-    const TIMEOUT_ITERATIONS: usize = ctap::TOUCH_TIMEOUT_MS as usize / KEEPALIVE_DELAY_MS as usize;
+    let timeout_iterations = (config.timeout.ms() / KEEPALIVE_DELAY_MS) as usize;
+    // A touch counts after the button is held for N keepalive delays, rounded up.
+    let touch_iterations =
+        ((config.touch_duration.ms() + KEEPALIVE_DELAY_MS - 1) / KEEPALIVE_DELAY_MS) as usize;
 
     // First, send a keep-alive packet to notify that the keep-alive status has changed.
-    send_keepalive_up_needed(cid, KEEPALIVE_DELAY)?;
+    let transport = transport_of(cid);
+    transport.send_keepalive(cid, KeepaliveStatus::UpNeeded)?;
 
     // Listen to the button presses.
     let button_touched = Cell::new(false);
-    let mut buttons_callback = buttons::with_callback(|_button_num, state| {
+    let button_held = Cell::new(false);
+    let button_denied = Cell::new(false);
+    let mut buttons_callback = buttons::with_callback(|button_num, state| {
         match state {
-            ButtonState::Pressed => button_touched.set(true),
-            ButtonState::Released => (),
+            ButtonState::Pressed if DENY_BUTTON == Some(button_num) => button_denied.set(true),
+            ButtonState::Pressed if touch_iterations == 0 => button_touched.set(true),
+            ButtonState::Pressed => button_held.set(true),
+            ButtonState::Released => button_held.set(false),
         };
     });
     let mut buttons = buttons_callback.init().flex_unwrap();
-    // At the moment, all buttons are accepted. You can customize your setup here.
+    // At the moment, all buttons are enabled. You can customize your setup here.
     for mut button in &mut buttons {
         button.enable().flex_unwrap();
     }
 
     let mut keepalive_response = Ok(());
-    for i in 0..TIMEOUT_ITERATIONS {
-        blink_leds(i);
+    let mut held_iterations = 0;
+    for i in 0..timeout_iterations {
+        match config.selection {
+            Some(selection) => selection_leds(selection, i),
+            None => status_leds::display(LedStatus::AwaitingTouch.is_lit(i)),
+        }
 
-        // Setup a keep-alive callback.
-        let keepalive_expired = Cell::new(false);
-        let mut keepalive_callback = timer::with_callback(|_, _| {
-            keepalive_expired.set(true);
+        // Wait for a button touch, a cancellation, or the time of the next keep-alive. A packet
+        // that doesn't cancel ends the wait early, which only shortens this iteration.
+        // Do not return immediately on errors, because we must clean up still.
+        keepalive_response = transport.wait_for_cancel(cid, KEEPALIVE_DELAY, &|| {
+            button_touched.get() || button_denied.get()
         });
-        let mut keepalive = keepalive_callback.init().flex_unwrap();
-        let keepalive_alarm = keepalive.set_alarm(KEEPALIVE_DELAY).flex_unwrap();
-
-        // Wait for a button touch or an alarm.
-        libtock_drivers::util::yieldk_for(|| button_touched.get() || keepalive_expired.get());
-
-        // Cleanup alarm callback.
-        match keepalive.stop_alarm(keepalive_alarm) {
-            Ok(()) => (),
-            Err(TockError::Command(CommandError {
-                return_code: EALREADY,
-                ..
-            })) => assert!(keepalive_expired.get()),
-            Err(_e) => {
-                #[cfg(feature = "debug_ctap")]
-                panic!("Unexpected error when stopping alarm: {:?}", _e);
-                #[cfg(not(feature = "debug_ctap"))]
-                panic!("Unexpected error when stopping alarm: <error is only visible with the debug_ctap feature>");
+        if button_held.get() {
+            held_iterations += 1;
+            if held_iterations >= touch_iterations {
+                button_touched.set(true);
             }
+        } else {
+            held_iterations = 0;
+        }
+        if button_touched.get() || button_denied.get() || keepalive_response.is_err() {
+            break;
         }
 
         // TODO: this may take arbitrary time. The keepalive_delay should be adjusted accordingly,
         // so that LEDs blink with a consistent pattern.
-        if keepalive_expired.get() {
-            // Do not return immediately, because we must clean up still.
-            keepalive_response = send_keepalive_up_needed(cid, KEEPALIVE_DELAY);
-        }
-
-        if button_touched.get() || keepalive_response.is_err() {
+        keepalive_response = transport.send_keepalive(cid, KeepaliveStatus::UpNeeded);
+        if keepalive_response.is_err() {
             break;
         }
     }
@@ -384,6 +596,8 @@ fn check_user_presence(cid: ChannelID) -> Result<(), Ctap2StatusCode> {
     // Returns whether the user was present.
     if keepalive_response.is_err() {
         keepalive_response
+    } else if button_denied.get() {
+        Err(Ctap2StatusCode::CTAP2_ERR_OPERATION_DENIED)
     } else if button_touched.get() {
         Ok(())
     } else {
@@ -0,0 +1,108 @@
+use crate::util;
+use core::cell::Cell;
+use libtock_core::result::SUCCESS;
+use libtock_core::{callback, syscalls};
+
+// Driver of a hardware crypto accelerator, such as the CryptoCell 310 of the nRF52840.
+const DRIVER_NUMBER: usize = 0x40002;
+
+mod command_nr {
+    pub const CHECK: usize = 0;
+    pub const SHA256_BLOCK: usize = 1;
+    pub const AES256_ENCRYPT_BLOCK: usize = 2;
+    pub const AES256_DECRYPT_BLOCK: usize = 3;
+    pub const P256_BASE_POINT_MUL: usize = 4;
+    pub const P256_POINT_MUL: usize = 5;
+}
+
+mod subscribe_nr {
+    pub const DONE: usize = 0;
+}
+
+mod allow_nr {
+    // Keys and scalars, only read by the kernel.
+    pub const KEY: usize = 0;
+    // The input of the operation, overwritten in place with the output.
+    pub const DATA: usize = 1;
+}
+
+pub fn is_available() -> bool {
+    syscalls::command(DRIVER_NUMBER, command_nr::CHECK, 0, 0).is_ok()
+}
+
+// Applies the SHA-256 compression function to the state, in place.
+pub fn sha256_compress(state: &mut [u32; 8], block: &[u8; 64]) -> bool {
+    // The kernel reads the state as big endian words, followed by the block.
+    let mut data = [0; 96];
+    for (chunk, word) in data[..32].chunks_mut(4).zip(state.iter()) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    data[32..].copy_from_slice(block);
+    if !run(command_nr::SHA256_BLOCK, None, &mut data) {
+        return false;
+    }
+    for (word, chunk) in state.iter_mut().zip(data[..32].chunks(4)) {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(chunk);
+        *word = u32::from_be_bytes(bytes);
+    }
+    true
+}
+
+pub fn aes256_encrypt_block(key: &[u8; 32], block: &mut [u8; 16]) -> bool {
+    let mut key = *key;
+    run(command_nr::AES256_ENCRYPT_BLOCK, Some(&mut key), block)
+}
+
+pub fn aes256_decrypt_block(key: &[u8; 32], block: &mut [u8; 16]) -> bool {
+    let mut key = *key;
+    run(command_nr::AES256_DECRYPT_BLOCK, Some(&mut key), block)
+}
+
+// Multiplies the generator by the big endian scalar. The point is written as its big endian
+// affine coordinates x and y.
+pub fn p256_base_point_mul(scalar: &[u8; 32], point: &mut [u8; 64]) -> bool {
+    let mut scalar = *scalar;
+    run(command_nr::P256_BASE_POINT_MUL, Some(&mut scalar), point)
+}
+
+// Multiplies the point by the big endian scalar, in place. The point is encoded as for
+// p256_base_point_mul.
+pub fn p256_point_mul(scalar: &[u8; 32], point: &mut [u8; 64]) -> bool {
+    let mut scalar = *scalar;
+    run(command_nr::P256_POINT_MUL, Some(&mut scalar), point)
+}
+
+// Runs a command until its completion. Returns whether the kernel reported a success.
+fn run(command: usize, key: Option<&mut [u8]>, data: &mut [u8]) -> bool {
+    let _key_share = match key {
+        Some(key) => match syscalls::allow(DRIVER_NUMBER, allow_nr::KEY, key) {
+            Ok(share) => Some(share),
+            Err(_) => return false,
+        },
+        None => None,
+    };
+    let data_share = syscalls::allow(DRIVER_NUMBER, allow_nr::DATA, data);
+    if data_share.is_err() {
+        return false;
+    }
+
+    let return_code = Cell::new(None);
+    let mut done_alarm = |code: usize| return_code.set(Some(code as isize));
+    let subscription = syscalls::subscribe::<callback::Identity1Consumer, _>(
+        DRIVER_NUMBER,
+        subscribe_nr::DONE,
+        &mut done_alarm,
+    );
+    if subscription.is_err() {
+        return false;
+    }
+
+    let result_code = syscalls::command(DRIVER_NUMBER, command, 0, 0);
+    if result_code.is_err() {
+        return false;
+    }
+
+    util::yieldk_for(|| return_code.get().is_some());
+    return_code.get() == Some(SUCCESS)
+}
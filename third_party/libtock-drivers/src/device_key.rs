@@ -0,0 +1,62 @@
+use crate::util;
+use core::cell::Cell;
+use libtock_core::result::SUCCESS;
+use libtock_core::{callback, syscalls};
+
+// Driver that derives keys from a secret unique to the device. The kernel holds the secret, for
+// example derived from the FICR and UICR of the nRF52840 or stored in a secure element, and never
+// shares it with applications.
+const DRIVER_NUMBER: usize = 0x40003;
+
+mod command_nr {
+    pub const CHECK: usize = 0;
+    pub const DERIVE: usize = 1;
+}
+
+mod subscribe_nr {
+    pub const DONE: usize = 0;
+}
+
+mod allow_nr {
+    // The label of the derived key, only read by the kernel.
+    pub const LABEL: usize = 0;
+    // The derived key, written by the kernel.
+    pub const KEY: usize = 1;
+}
+
+pub fn is_available() -> bool {
+    syscalls::command(DRIVER_NUMBER, command_nr::CHECK, 0, 0).is_ok()
+}
+
+// Writes the key for the label. Different labels derive independent keys, and the same label
+// always derives the same key on a given device.
+pub fn derive(label: &[u8; 32], key: &mut [u8; 32]) -> bool {
+    let mut label = *label;
+    let label_share = syscalls::allow(DRIVER_NUMBER, allow_nr::LABEL, &mut label);
+    if label_share.is_err() {
+        return false;
+    }
+    let key_share = syscalls::allow(DRIVER_NUMBER, allow_nr::KEY, key);
+    if key_share.is_err() {
+        return false;
+    }
+
+    let return_code = Cell::new(None);
+    let mut done_alarm = |code: usize| return_code.set(Some(code as isize));
+    let subscription = syscalls::subscribe::<callback::Identity1Consumer, _>(
+        DRIVER_NUMBER,
+        subscribe_nr::DONE,
+        &mut done_alarm,
+    );
+    if subscription.is_err() {
+        return false;
+    }
+
+    let result_code = syscalls::command(DRIVER_NUMBER, command_nr::DERIVE, 0, 0);
+    if result_code.is_err() {
+        return false;
+    }
+
+    util::yieldk_for(|| return_code.get().is_some());
+    return_code.get() == Some(SUCCESS)
+}
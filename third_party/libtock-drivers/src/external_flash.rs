@@ -0,0 +1,95 @@
+use crate::util;
+use core::cell::Cell;
+use libtock_core::result::SUCCESS;
+use libtock_core::{callback, syscalls};
+
+// Driver of a NOR flash on the SPI bus. The kernel maps the flash read-only in the address space of
+// the application, as the QSPI peripheral of the nRF52840 does, so that it is read like the
+// embedded flash. Writes and erasures go through commands, since they take several milliseconds.
+const DRIVER_NUMBER: usize = 0x50004;
+
+mod command_nr {
+    pub const CHECK: usize = 0;
+    pub const GET_INFO: usize = 1;
+    pub const WRITE_SLICE: usize = 2;
+    pub const ERASE_PAGE: usize = 3;
+}
+
+pub mod get_info_nr {
+    pub const WORD_SIZE: usize = 0;
+    pub const PAGE_SIZE: usize = 1;
+    pub const MAX_WORD_WRITES: usize = 2;
+    pub const MAX_PAGE_ERASES: usize = 3;
+    // The address and length in bytes of the mapping of the flash.
+    pub const ADDRESS: usize = 4;
+    pub const LENGTH: usize = 5;
+}
+
+mod subscribe_nr {
+    pub const DONE: usize = 0;
+}
+
+mod allow_nr {
+    // The words to write, only read by the kernel.
+    pub const WRITE_SLICE: usize = 0;
+}
+
+pub fn is_available() -> bool {
+    syscalls::command(DRIVER_NUMBER, command_nr::CHECK, 0, 0).is_ok()
+}
+
+// Returns a property of the flash, numbered as in get_info_nr.
+pub fn get_info(nr: usize) -> Option<usize> {
+    syscalls::command(DRIVER_NUMBER, command_nr::GET_INFO, nr, 0).ok()
+}
+
+// Returns the mapping of the flash, which changes when the flash is written or erased.
+pub fn mapping() -> Option<&'static [u8]> {
+    let address = get_info(get_info_nr::ADDRESS)?;
+    let length = get_info(get_info_nr::LENGTH)?;
+    Some(unsafe { core::slice::from_raw_parts(address as *const u8, length) })
+}
+
+// Writes the words at the byte offset in the flash. The offset and length must be word aligned.
+pub fn write_slice(offset: usize, value: &[u8]) -> bool {
+    let code = unsafe {
+        syscalls::raw::allow(
+            DRIVER_NUMBER,
+            allow_nr::WRITE_SLICE,
+            // We rely on the driver not writing to the slice, as for the embedded flash.
+            value.as_ptr() as *mut u8,
+            value.len(),
+        )
+    };
+    if code < 0 {
+        return false;
+    }
+    run(command_nr::WRITE_SLICE, offset, value.len())
+}
+
+// Erases the page at the byte offset in the flash. The offset must be page aligned.
+pub fn erase_page(offset: usize, length: usize) -> bool {
+    run(command_nr::ERASE_PAGE, offset, length)
+}
+
+// Runs a command until its completion. Returns whether the kernel reported a success.
+fn run(command: usize, offset: usize, length: usize) -> bool {
+    let return_code = Cell::new(None);
+    let mut done_alarm = |code: usize| return_code.set(Some(code as isize));
+    let subscription = syscalls::subscribe::<callback::Identity1Consumer, _>(
+        DRIVER_NUMBER,
+        subscribe_nr::DONE,
+        &mut done_alarm,
+    );
+    if subscription.is_err() {
+        return false;
+    }
+
+    let result_code = syscalls::command(DRIVER_NUMBER, command, offset, length);
+    if result_code.is_err() {
+        return false;
+    }
+
+    util::yieldk_for(|| return_code.get().is_some());
+    return_code.get() == Some(SUCCESS)
+}
@@ -0,0 +1,242 @@
+use crate::result::TockError;
+use crate::util;
+use core::cell::Cell;
+use libtock_core::callback::Consumer;
+use libtock_core::result::{
+    AllowError, CommandError, SubscribeError, ECANCEL, EINVAL, ENODEVICE, ENOMEM, FAIL, SUCCESS,
+};
+use libtock_core::syscalls;
+
+const DRIVER_NUMBER: usize = 0x30004;
+
+mod command_nr {
+    pub const CHECK: usize = 0;
+    pub const FINGERPRINT_KIND: usize = 1;
+    pub const MAX_CAPTURE_SAMPLES: usize = 2;
+    pub const ENROLL_BEGIN: usize = 3;
+    pub const ENROLL_CAPTURE_NEXT: usize = 4;
+    pub const ENROLL_CANCEL: usize = 5;
+    pub const REMOVE_TEMPLATE: usize = 6;
+    pub const REMOVE_ALL_TEMPLATES: usize = 7;
+    pub const IDENTIFY: usize = 8;
+}
+
+mod subscribe_nr {
+    pub const CAPTURE: usize = 0;
+    pub const IDENTIFY: usize = 1;
+}
+
+/// Errors of the fingerprint driver, decoded from the kernel return codes.
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub enum FingerprintError {
+    /// The kernel has no fingerprint driver.
+    DriverMissing,
+    /// No finger touched the sensor before the timeout.
+    Timeout,
+    /// The sensor has no space left for another template.
+    DatabaseFull,
+    /// The template or enrollment doesn't exist.
+    InvalidTemplate,
+    /// The capture was cancelled by `enroll_cancel`.
+    Cancelled,
+    /// Any other return code of the kernel.
+    Kernel(isize),
+}
+
+impl From<isize> for FingerprintError {
+    // The capsule reports timeouts of a capture as FAIL.
+    fn from(return_code: isize) -> Self {
+        match return_code {
+            ENODEVICE => FingerprintError::DriverMissing,
+            FAIL => FingerprintError::Timeout,
+            ENOMEM => FingerprintError::DatabaseFull,
+            EINVAL => FingerprintError::InvalidTemplate,
+            ECANCEL => FingerprintError::Cancelled,
+            _ => FingerprintError::Kernel(return_code),
+        }
+    }
+}
+
+impl From<TockError> for FingerprintError {
+    fn from(error: TockError) -> Self {
+        match error {
+            TockError::Command(CommandError { return_code, .. })
+            | TockError::Subscribe(SubscribeError { return_code, .. })
+            | TockError::Allow(AllowError { return_code, .. }) => return_code.into(),
+            TockError::Format | TockError::Other(_) => FingerprintError::Kernel(FAIL),
+        }
+    }
+}
+
+impl From<CommandError> for FingerprintError {
+    fn from(error: CommandError) -> Self {
+        error.return_code.into()
+    }
+}
+
+impl From<SubscribeError> for FingerprintError {
+    fn from(error: SubscribeError) -> Self {
+        error.return_code.into()
+    }
+}
+
+/// How the user presents the finger to the sensor.
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub enum FingerprintKind {
+    Touch = 1,
+    Swipe = 2,
+}
+
+/// Outcome of a sample captured during an enrollment.
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub struct SampleCapture {
+    /// The lastEnrollSampleStatus of CTAP 2.1, as reported by the sensor.
+    pub sample_status: u8,
+    /// Number of good samples still needed to complete the enrollment.
+    pub remaining_samples: usize,
+}
+
+// The capsule calls back with the return code, the sample status and the remaining samples.
+struct CaptureCallback<'a> {
+    capture: &'a Cell<Option<Result<SampleCapture, FingerprintError>>>,
+}
+
+struct CaptureConsumer;
+
+impl Consumer<CaptureCallback<'_>> for CaptureConsumer {
+    fn consume(data: &mut CaptureCallback, return_code: usize, status: usize, remaining: usize) {
+        let return_code = return_code as isize;
+        let outcome = if return_code == SUCCESS {
+            Ok(SampleCapture {
+                sample_status: status as u8,
+                remaining_samples: remaining,
+            })
+        } else {
+            Err(return_code.into())
+        };
+        data.capture.set(Some(outcome));
+    }
+}
+
+// The capsule calls back with the return code, whether the finger matched and the template.
+struct IdentifyCallback<'a> {
+    identified: &'a Cell<Option<Result<Option<usize>, FingerprintError>>>,
+}
+
+struct IdentifyConsumer;
+
+impl Consumer<IdentifyCallback<'_>> for IdentifyConsumer {
+    fn consume(
+        data: &mut IdentifyCallback,
+        return_code: usize,
+        matched: usize,
+        template_id: usize,
+    ) {
+        let return_code = return_code as isize;
+        let outcome = if return_code == SUCCESS {
+            Ok(if matched != 0 {
+                Some(template_id)
+            } else {
+                None
+            })
+        } else {
+            Err(return_code.into())
+        };
+        data.identified.set(Some(outcome));
+    }
+}
+
+pub fn is_available() -> Result<(), FingerprintError> {
+    syscalls::command(DRIVER_NUMBER, command_nr::CHECK, 0, 0)?;
+    Ok(())
+}
+
+pub fn fingerprint_kind() -> Result<FingerprintKind, FingerprintError> {
+    match syscalls::command(DRIVER_NUMBER, command_nr::FINGERPRINT_KIND, 0, 0)? {
+        2 => Ok(FingerprintKind::Swipe),
+        _ => Ok(FingerprintKind::Touch),
+    }
+}
+
+/// Returns the number of good samples the sensor needs for an enrollment.
+pub fn max_capture_samples() -> Result<usize, FingerprintError> {
+    Ok(syscalls::command(
+        DRIVER_NUMBER,
+        command_nr::MAX_CAPTURE_SAMPLES,
+        0,
+        0,
+    )?)
+}
+
+// Issues a capture command and blocks until the capsule reports the sample.
+fn capture(
+    command_number: usize,
+    arg1: usize,
+    arg2: usize,
+) -> Result<SampleCapture, FingerprintError> {
+    let capture = Cell::new(None);
+    let mut callback = CaptureCallback { capture: &capture };
+    let _subscription = syscalls::subscribe::<CaptureConsumer, _>(
+        DRIVER_NUMBER,
+        subscribe_nr::CAPTURE,
+        &mut callback,
+    )?;
+    syscalls::command(DRIVER_NUMBER, command_number, arg1, arg2)?;
+    util::yieldk_for(|| capture.get().is_some());
+    capture.get().unwrap()
+}
+
+/// Starts the enrollment of a new template, and captures its first sample.
+///
+/// Returns the identifier of the template, which is only kept by the sensor once the enrollment
+/// completes.
+pub fn enroll_begin(timeout_ms: usize) -> Result<(usize, SampleCapture), FingerprintError> {
+    let template_id = syscalls::command(DRIVER_NUMBER, command_nr::ENROLL_BEGIN, 0, 0)?;
+    let sample = capture(command_nr::ENROLL_CAPTURE_NEXT, template_id, timeout_ms)?;
+    Ok((template_id, sample))
+}
+
+/// Captures the next sample of the enrollment in progress.
+pub fn enroll_capture_next(
+    template_id: usize,
+    timeout_ms: usize,
+) -> Result<SampleCapture, FingerprintError> {
+    capture(command_nr::ENROLL_CAPTURE_NEXT, template_id, timeout_ms)
+}
+
+/// Aborts the enrollment in progress, if any.
+pub fn enroll_cancel() -> Result<(), FingerprintError> {
+    syscalls::command(DRIVER_NUMBER, command_nr::ENROLL_CANCEL, 0, 0)?;
+    Ok(())
+}
+
+pub fn remove_template(template_id: usize) -> Result<(), FingerprintError> {
+    syscalls::command(DRIVER_NUMBER, command_nr::REMOVE_TEMPLATE, template_id, 0)?;
+    Ok(())
+}
+
+pub fn remove_all_templates() -> Result<(), FingerprintError> {
+    syscalls::command(DRIVER_NUMBER, command_nr::REMOVE_ALL_TEMPLATES, 0, 0)?;
+    Ok(())
+}
+
+/// Captures a finger and matches it against the enrolled templates.
+///
+/// Returns the matching template, or `None` if the finger matches none of them.
+pub fn identify(timeout_ms: usize) -> Result<Option<usize>, FingerprintError> {
+    let identified = Cell::new(None);
+    let mut callback = IdentifyCallback {
+        identified: &identified,
+    };
+    let _subscription = syscalls::subscribe::<IdentifyConsumer, _>(
+        DRIVER_NUMBER,
+        subscribe_nr::IDENTIFY,
+        &mut callback,
+    )?;
+    syscalls::command(DRIVER_NUMBER, command_nr::IDENTIFY, timeout_ms, 0)?;
+    util::yieldk_for(|| identified.get().is_some());
+    identified.get().unwrap()
+}
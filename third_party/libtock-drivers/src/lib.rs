@@ -3,11 +3,19 @@
 pub mod buttons;
 pub mod console;
 pub mod crp;
+pub mod crypto_cell;
+pub mod device_key;
+#[cfg(feature = "with_external_flash")]
+pub mod external_flash;
+pub mod fingerprint;
 pub mod led;
 #[cfg(feature = "with_nfc")]
 pub mod nfc;
 pub mod result;
 pub mod rng;
+#[cfg(feature = "with_secure_element")]
+pub mod secure_element;
+pub mod sha;
 pub mod timer;
 pub mod usb_ctap_hid;
 pub mod util;
@@ -1,8 +1,16 @@
-use crate::result::TockResult;
+use crate::result::TockError;
+use crate::timer;
+use crate::timer::Duration;
 use crate::util;
 use core::cell::Cell;
 use core::mem;
-use libtock_core::{callback, syscalls};
+use libtock_core::callback::{CallbackSubscription, Consumer};
+use libtock_core::result::{
+    AllowError, CommandError, SubscribeError, EALREADY, EBUSY, ECANCEL, EINVAL, ENODEVICE, ENOMEM,
+    EOFF, ESIZE, FAIL,
+};
+use libtock_core::shared_memory::SharedMemory;
+use libtock_core::syscalls;
 
 const DRIVER_NUMBER: usize = 0x30003;
 
@@ -12,92 +20,931 @@ mod command_nr {
     pub const RECEIVE: usize = 2;
     pub const EMULATE: usize = 3;
     pub const CONFIGURE: usize = 4;
+    pub const FRAMEDELAYMAX: usize = 5;
+    pub const TAG_CONFIG: usize = 6;
+    pub const UID_MODE: usize = 7;
+    pub const BIT_RATE: usize = 8;
+    pub const ABORT: usize = 9;
+    pub const LOOPBACK: usize = 10;
 }
 
 mod subscribe_nr {
     pub const TRANSMIT: usize = 1;
     pub const RECEIVE: usize = 2;
+    pub const SELECT: usize = 3;
+    pub const FIELD: usize = 4;
 }
 
 mod allow_nr {
     pub const TRANSMIT: usize = 1;
     pub const RECEIVE: usize = 2;
+    pub const TAG_CONFIG: usize = 3;
 }
 
-#[allow(dead_code)]
+/// Errors of the NFC driver, decoded from the kernel return codes.
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub enum NfcError {
+    /// The kernel has no NFC driver.
+    DriverMissing,
+    /// The driver is processing another request, or the tag is not selected yet.
+    Busy,
+    /// Tag emulation is disabled.
+    EmulationDisabled,
+    /// The reader removed the field during the operation.
+    FieldLost,
+    /// The frame doesn't fit in the shared buffer.
+    BufferTooSmall,
+    /// The operation didn't complete before the timeout.
+    Timeout,
+    /// The driver refused the arguments, for example an invalid tag configuration.
+    InvalidArgument,
+    /// The operation was cancelled by `NfcTag::abort`.
+    Aborted,
+    /// Any other return code of the kernel.
+    Kernel(isize),
+}
+
+impl From<isize> for NfcError {
+    fn from(return_code: isize) -> Self {
+        match return_code {
+            ENODEVICE => NfcError::DriverMissing,
+            EBUSY => NfcError::Busy,
+            EOFF => NfcError::EmulationDisabled,
+            ECANCEL => NfcError::FieldLost,
+            ENOMEM | ESIZE => NfcError::BufferTooSmall,
+            EINVAL => NfcError::InvalidArgument,
+            _ => NfcError::Kernel(return_code),
+        }
+    }
+}
+
+impl NfcError {
+    /// The kernel return code of the error. Timeouts are detected by the app, and reported as
+    /// FAIL.
+    pub fn return_code(&self) -> isize {
+        match self {
+            NfcError::DriverMissing => ENODEVICE,
+            NfcError::Busy => EBUSY,
+            NfcError::EmulationDisabled => EOFF,
+            NfcError::FieldLost | NfcError::Aborted => ECANCEL,
+            NfcError::BufferTooSmall => ESIZE,
+            NfcError::Timeout => FAIL,
+            NfcError::InvalidArgument => EINVAL,
+            NfcError::Kernel(return_code) => *return_code,
+        }
+    }
+}
+
+impl From<TockError> for NfcError {
+    fn from(error: TockError) -> Self {
+        match error {
+            TockError::Command(CommandError { return_code, .. })
+            | TockError::Subscribe(SubscribeError { return_code, .. })
+            | TockError::Allow(AllowError { return_code, .. }) => return_code.into(),
+            TockError::Format | TockError::Other(_) => NfcError::Kernel(FAIL),
+        }
+    }
+}
+
+impl From<CommandError> for NfcError {
+    fn from(error: CommandError) -> Self {
+        error.return_code.into()
+    }
+}
+
+impl From<SubscribeError> for NfcError {
+    fn from(error: SubscribeError) -> Self {
+        error.return_code.into()
+    }
+}
+
+impl From<AllowError> for NfcError {
+    fn from(error: AllowError) -> Self {
+        error.return_code.into()
+    }
+}
+
+/// Number of bytes of the tag UID, as announced in the ATQA.
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "debug_ctap", derive(Debug))]
+pub enum UidLength {
+    Single = 4,
+    Double = 7,
+    Triple = 10,
+}
+
+/// ISO 14443-4 section 5.3: bit rates of the tag, negotiated by the reader with PPS.
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "debug_ctap", derive(Debug))]
+pub enum BitRate {
+    Kbps106 = 0,
+    Kbps212 = 1,
+    Kbps424 = 2,
+}
+
+impl BitRate {
+    /// Decodes the DSI or DRI field of a PPS request.
+    pub fn from_divisor_index(index: u8) -> Option<BitRate> {
+        match index {
+            0 => Some(BitRate::Kbps106),
+            1 => Some(BitRate::Kbps212),
+            2 => Some(BitRate::Kbps424),
+            _ => None,
+        }
+    }
+}
+
+/// Source of the UID sent by the tag during anticollision.
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "debug_ctap", derive(Debug))]
+pub enum UidMode {
+    /// The UID programmed in the chip, with the length of the tag configuration.
+    Fixed = 0,
+    /// ISO 14443-3 section 6.4.4: a random 4 byte UID, starting with 0x08. The driver draws a new
+    /// one at each field activation, so that readers can't track the tag.
+    Random = 1,
+}
+
+/// Frequency of the carrier generated by the reader, in Hz.
+///
+/// The frame delay counter of the nRF52840 runs on the carrier.
+pub const CARRIER_FREQUENCY_HZ: u32 = 13_560_000;
+
+/// Timing of the frames of the reader, which gives the maximum frame delay of the driver.
 #[derive(Clone, Copy)]
+pub struct FrameTiming {
+    fwi: u8,
+    clock_frequency_hz: u32,
+}
+
+impl FrameTiming {
+    // ISO 14443-4 section 5.2.5: the FWI defaults to 4 when the ATS has no TB byte. The value 15
+    // is reserved.
+    const DEFAULT_FWI: u8 = 4;
+    const MAX_FWI: u8 = 14;
+    const T0_TA_PRESENT: u8 = 0x10;
+    const T0_TB_PRESENT: u8 = 0x20;
+
+    /// Reads the frame waiting time integer announced in the ATS. The frame delay is counted by a
+    /// clock of the given frequency, for example the CPU clock or the carrier.
+    pub fn from_ats(ats: &[u8], clock_frequency_hz: u32) -> FrameTiming {
+        let mut fwi = FrameTiming::DEFAULT_FWI;
+        if let Some(&t0) = ats.get(1) {
+            let tb_index = if t0 & FrameTiming::T0_TA_PRESENT != 0 {
+                3
+            } else {
+                2
+            };
+            if t0 & FrameTiming::T0_TB_PRESENT != 0 {
+                if let Some(&tb) = ats.get(tb_index) {
+                    fwi = tb >> 4;
+                }
+            }
+        }
+        FrameTiming {
+            fwi: core::cmp::min(fwi, FrameTiming::MAX_FWI),
+            clock_frequency_hz,
+        }
+    }
+
+    /// ISO 14443-4 section 7.2: the frame waiting time is 256 * 16 * 2^FWI carrier cycles. It
+    /// doesn't depend on the bit rate. Returns it in ticks of the clock, rounded up.
+    pub fn frame_delay_max(&self) -> u32 {
+        let carrier_cycles = (256 * 16u64) << self.fwi;
+        let frequency = CARRIER_FREQUENCY_HZ as u64;
+        let ticks = (carrier_cycles * self.clock_frequency_hz as u64 + frequency - 1) / frequency;
+        core::cmp::min(ticks, u32::MAX as u64) as u32
+    }
+}
+
+/// Identity of the emulated tag, as seen by readers during anticollision and activation.
+///
+/// The ATS is only built by the driver when it answers RATS itself. Apps answering RATS must send
+/// the same FSCI and historical bytes.
+pub struct TagConfig<'a> {
+    pub uid_length: UidLength,
+    /// ATQA, in the order the bytes are sent on the air.
+    pub atqa: [u8; 2],
+    pub sak: u8,
+    /// ISO 14443-4 section 5.2.3: 0 to 8, for frames of 16 to 256 bytes.
+    pub fsci: u8,
+    pub historical_bytes: &'a [u8],
+}
+
+impl TagConfig<'_> {
+    const MAX_FSCI: u8 = 8;
+    // The ATS is at most 20 bytes: TL, T0, TA, TB, TC and 15 historical bytes.
+    const MAX_HISTORICAL_BYTES: usize = 15;
+    const HEADER_LEN: usize = 5;
+    const MAX_ENCODED_LEN: usize = TagConfig::HEADER_LEN + TagConfig::MAX_HISTORICAL_BYTES;
+
+    // Encoding shared with the driver: UID length, ATQA, SAK, FSCI, then the historical bytes.
+    fn encode(&self, buf: &mut [u8; TagConfig::MAX_ENCODED_LEN]) -> Result<usize, NfcError> {
+        if self.fsci > TagConfig::MAX_FSCI
+            || self.historical_bytes.len() > TagConfig::MAX_HISTORICAL_BYTES
+        {
+            return Err(NfcError::InvalidArgument);
+        }
+        buf[0] = self.uid_length as u8;
+        buf[1..3].copy_from_slice(&self.atqa);
+        buf[3] = self.sak;
+        buf[4] = self.fsci;
+        let len = TagConfig::HEADER_LEN + self.historical_bytes.len();
+        buf[TagConfig::HEADER_LEN..len].copy_from_slice(self.historical_bytes);
+        Ok(len)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
 pub struct RecvOp {
     pub result_code: usize,
     pub recv_amount: usize,
 }
 
+/// Outcome of a half-duplex exchange with the reader.
+#[derive(Clone, Copy)]
+pub struct TransceiveOp {
+    pub recv_op: RecvOp,
+    /// Result code of the transmission, or None if no reply was sent.
+    pub transmit_result: Option<usize>,
+}
+
+/// An event of the driver, decoded from the arguments of its callback.
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub enum NfcEvent {
+    /// A transmission completed, with its result code.
+    Transmitted(usize),
+    /// A reception completed.
+    Received(RecvOp),
+    /// A reader selected the tag.
+    Selected,
+    /// The reader field appeared or disappeared.
+    Field(FieldState),
+}
+
+impl NfcEvent {
+    // Each subscription has its own callback arguments.
+    fn decode(subscribe_number: usize, arg1: usize, arg2: usize) -> NfcEvent {
+        match subscribe_number {
+            subscribe_nr::TRANSMIT => NfcEvent::Transmitted(arg1),
+            subscribe_nr::RECEIVE => NfcEvent::Received(RecvOp {
+                result_code: arg1,
+                recv_amount: arg2,
+            }),
+            subscribe_nr::SELECT => NfcEvent::Selected,
+            _ => NfcEvent::Field(arg1.into()),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum OperationState {
+    Idle,
+    Pending,
+    Completed(Result<NfcEvent, NfcError>),
+}
+
+/// Progress of an operation issued to the driver, updated by the events of its callback.
+///
+/// The operation completes with the first event after it started. Cancelled operations complete
+/// with `NfcError::Aborted`, and a field off event completes them with `NfcError::FieldLost`.
+/// Later events are ignored until the operation starts again.
+pub struct NfcOperation {
+    state: Cell<OperationState>,
+}
+
+impl NfcOperation {
+    pub fn new() -> NfcOperation {
+        NfcOperation {
+            state: Cell::new(OperationState::Idle),
+        }
+    }
+
+    // Callbacks are only delivered while the app yields, so the operation can start once its
+    // request was issued.
+    fn start(&self) {
+        self.state.set(OperationState::Pending);
+    }
+
+    /// Updates the operation with an event of the driver.
+    pub fn handle(&self, event: NfcEvent) {
+        if !self.is_pending() {
+            return;
+        }
+        let outcome = match event {
+            NfcEvent::Field(FieldState::On) => return,
+            NfcEvent::Field(FieldState::Off) => Err(NfcError::FieldLost),
+            NfcEvent::Transmitted(result_code) | NfcEvent::Received(RecvOp { result_code, .. })
+                if is_aborted(result_code) =>
+            {
+                Err(NfcError::Aborted)
+            }
+            _ => Ok(event),
+        };
+        self.state.set(OperationState::Completed(outcome));
+    }
+
+    /// Whether the operation started and didn't complete yet.
+    pub fn is_pending(&self) -> bool {
+        matches!(self.state.get(), OperationState::Pending)
+    }
+
+    /// Returns the outcome of the operation if it completed, without blocking.
+    pub fn poll(&self) -> Option<Result<NfcEvent, NfcError>> {
+        match self.state.get() {
+            OperationState::Completed(outcome) => Some(outcome),
+            OperationState::Idle | OperationState::Pending => None,
+        }
+    }
+
+    fn wait(&self) -> Result<NfcEvent, NfcError> {
+        util::yieldk_for(|| self.poll().is_some());
+        self.poll().unwrap()
+    }
+}
+
+// Forwards the events of a subscription to an operation.
+struct EventCallback<'a> {
+    subscribe_number: usize,
+    operation: &'a NfcOperation,
+}
+
+struct EventConsumer;
+
+impl Consumer<EventCallback<'_>> for EventConsumer {
+    fn consume(data: &mut EventCallback, arg1: usize, arg2: usize, _: usize) {
+        let event = NfcEvent::decode(data.subscribe_number, arg1, arg2);
+        data.operation.handle(event);
+    }
+}
+
+impl<'a> EventCallback<'a> {
+    fn new(subscribe_number: usize, operation: &'a NfcOperation) -> EventCallback<'a> {
+        EventCallback {
+            subscribe_number,
+            operation,
+        }
+    }
+
+    fn subscribe(&mut self) -> Result<CallbackSubscription<'_>, NfcError> {
+        let subscribe_number = self.subscribe_number;
+        Ok(syscalls::subscribe::<EventConsumer, _>(
+            DRIVER_NUMBER,
+            subscribe_number,
+            self,
+        )?)
+    }
+}
+
+/// Subscription of a non-blocking reception to the driver.
+///
+/// The operation must outlive the `PendingRecv` returned by `NfcTag::receive_start`.
+pub struct RecvCallback<'a> {
+    callback: EventCallback<'a>,
+}
+
+impl<'a> RecvCallback<'a> {
+    pub fn new(operation: &'a NfcOperation) -> RecvCallback<'a> {
+        RecvCallback {
+            callback: EventCallback::new(subscribe_nr::RECEIVE, operation),
+        }
+    }
+}
+
+/// A reception that was issued to the driver but may not have completed yet.
+///
+/// The receive buffer stays shared with the driver until this value is dropped. Dropping it
+/// before completion unshares the buffer, so a late frame is discarded by the driver.
+pub struct PendingRecv<'a> {
+    operation: &'a NfcOperation,
+    max_frame_size: usize,
+    #[allow(dead_code)] // Used in drop
+    subscription: CallbackSubscription<'a>,
+    #[allow(dead_code)] // Used in drop
+    shared_buffer: SharedMemory<'a>,
+}
+
+impl<'a> PendingRecv<'a> {
+    /// Returns the result of the reception if it completed, without blocking.
+    ///
+    /// Callbacks are only delivered while the app yields, so the caller must yield (for example
+    /// while waiting on another driver) between two polls.
+    pub fn poll(&self) -> Option<Result<RecvOp, NfcError>> {
+        self.operation.poll().map(|outcome| self.check(outcome))
+    }
+
+    /// Blocks until the reception completes.
+    pub fn wait(self) -> Result<RecvOp, NfcError> {
+        self.check(self.operation.wait())
+    }
+
+    /// Cancels the reception, and waits for the driver to complete it.
+    ///
+    /// Once this returns, the driver has no reception outstanding, so the next one can be issued
+    /// right away. A frame received before the cancellation is discarded.
+    pub fn abort(self) -> Result<(), NfcError> {
+        if self.operation.is_pending() {
+            NfcTag::abort()?;
+            util::yieldk_for(|| !self.operation.is_pending());
+        }
+        Ok(())
+    }
+
+    // The driver reports the length of the frame, which is more than it wrote if the frame didn't
+    // fit in the shared buffer.
+    fn check(&self, outcome: Result<NfcEvent, NfcError>) -> Result<RecvOp, NfcError> {
+        match outcome? {
+            NfcEvent::Received(recv_op) if recv_op.recv_amount > self.max_frame_size => {
+                Err(NfcError::BufferTooSmall)
+            }
+            NfcEvent::Received(recv_op) => Ok(recv_op),
+            // The subscription only delivers reception events.
+            _ => Err(NfcError::Kernel(FAIL)),
+        }
+    }
+}
+
+/// Presence of a reader field, as reported by the field-detect events.
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(any(test, feature = "debug_ctap"), derive(Debug))]
+pub enum FieldState {
+    Off,
+    On,
+}
+
+impl From<usize> for FieldState {
+    fn from(state: usize) -> FieldState {
+        match state {
+            0 => FieldState::Off,
+            _ => FieldState::On,
+        }
+    }
+}
+
+pub fn with_field_callback<CB>(callback: CB) -> FieldCallback<CB> {
+    FieldCallback { callback }
+}
+
+pub struct FieldCallback<CB> {
+    callback: CB,
+}
+
+struct FieldConsumer;
+
+impl<CB: FnMut(FieldState)> Consumer<FieldCallback<CB>> for FieldConsumer {
+    fn consume(data: &mut FieldCallback<CB>, state: usize, _: usize, _: usize) {
+        (data.callback)(state.into());
+    }
+}
+
+impl<CB: FnMut(FieldState)> FieldCallback<CB> {
+    /// Subscribe to the field on and off events. They are delivered until the returned value is
+    /// dropped.
+    pub fn init(&mut self) -> Result<FieldEvents<'_>, NfcError> {
+        let subscription =
+            syscalls::subscribe::<FieldConsumer, _>(DRIVER_NUMBER, subscribe_nr::FIELD, self)?;
+        Ok(FieldEvents { subscription })
+    }
+}
+
+pub struct FieldEvents<'a> {
+    #[allow(dead_code)] // Used in drop
+    subscription: CallbackSubscription<'a>,
+}
+
+pub fn with_select_callback<CB>(callback: CB) -> SelectCallback<CB> {
+    SelectCallback { callback }
+}
+
+pub struct SelectCallback<CB> {
+    callback: CB,
+}
+
+struct SelectConsumer;
+
+impl<CB: FnMut()> Consumer<SelectCallback<CB>> for SelectConsumer {
+    fn consume(data: &mut SelectCallback<CB>, _: usize, _: usize, _: usize) {
+        (data.callback)();
+    }
+}
+
+impl<CB: FnMut()> SelectCallback<CB> {
+    /// Subscribe to the events of a reader selecting the tag, without blocking. They are delivered
+    /// until the returned value is dropped, and replace the ones of `NfcTag::selected`.
+    pub fn init(&mut self) -> Result<SelectEvents<'_>, NfcError> {
+        let subscription =
+            syscalls::subscribe::<SelectConsumer, _>(DRIVER_NUMBER, subscribe_nr::SELECT, self)?;
+        Ok(SelectEvents { subscription })
+    }
+}
+
+pub struct SelectEvents<'a> {
+    #[allow(dead_code)] // Used in drop
+    subscription: CallbackSubscription<'a>,
+}
+
+/// Keeps the tag emulated while it is alive.
+///
+/// Emulation is enabled on creation and disabled on drop, so that no error path can leave the tag
+/// emulated after the transport stopped using it.
+pub struct NfcEmulationGuard {
+    _private: (),
+}
+
+impl NfcEmulationGuard {
+    pub fn new() -> Result<NfcEmulationGuard, NfcError> {
+        NfcTag::enable_emulation()?;
+        Ok(NfcEmulationGuard { _private: () })
+    }
+}
+
+impl Drop for NfcEmulationGuard {
+    fn drop(&mut self) {
+        // Nothing to do on failure, the driver stops the emulation when the field is lost anyway.
+        NfcTag::disable_emulation().ok();
+    }
+}
+
 pub struct NfcTag {}
 
 impl NfcTag {
     /// Check the existence of an NFC driver.
-    pub fn setup() -> bool {
-        syscalls::command(DRIVER_NUMBER, command_nr::CHECK, 0, 0).is_ok()
+    pub fn setup() -> Result<(), NfcError> {
+        match syscalls::command(DRIVER_NUMBER, command_nr::CHECK, 0, 0) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(NfcError::DriverMissing),
+        }
     }
 
-    pub fn enable_emulation() -> bool {
+    fn enable_emulation() -> Result<(), NfcError> {
         NfcTag::emulate(true)
     }
 
-    pub fn disable_emulation() -> bool {
+    fn disable_emulation() -> Result<(), NfcError> {
         NfcTag::emulate(false)
     }
 
-    fn emulate(enabled: bool) -> bool {
-        syscalls::command(DRIVER_NUMBER, command_nr::EMULATE, enabled as usize, 0).is_ok()
+    fn emulate(enabled: bool) -> Result<(), NfcError> {
+        syscalls::command(DRIVER_NUMBER, command_nr::EMULATE, enabled as usize, 0)?;
+        Ok(())
     }
 
     /// Configure the tag type command.
-    pub fn configure(tag_type: u8) -> bool {
-        syscalls::command(DRIVER_NUMBER, command_nr::CONFIGURE, tag_type as usize, 0).is_ok()
+    pub fn configure(tag_type: u8) -> Result<(), NfcError> {
+        syscalls::command(DRIVER_NUMBER, command_nr::CONFIGURE, tag_type as usize, 0)?;
+        Ok(())
+    }
+
+    /// Set the identity of the tag. It applies from the next activation by a reader.
+    pub fn set_tag_config(config: &TagConfig) -> Result<(), NfcError> {
+        let mut buf = [0; TagConfig::MAX_ENCODED_LEN];
+        let len = config.encode(&mut buf)?;
+        let shared_buffer = syscalls::allow(DRIVER_NUMBER, allow_nr::TAG_CONFIG, &mut buf[..len])?;
+        // The driver copies the configuration, so the buffer can be unshared right away.
+        let result = syscalls::command(DRIVER_NUMBER, command_nr::TAG_CONFIG, len, 0);
+        mem::drop(shared_buffer);
+        result?;
+        Ok(())
+    }
+
+    /// Choose between a fixed and a random UID. Random UIDs need a tag configuration with a single
+    /// UID length.
+    pub fn set_uid_mode(mode: UidMode) -> Result<(), NfcError> {
+        syscalls::command(DRIVER_NUMBER, command_nr::UID_MODE, mode as usize, 0)?;
+        Ok(())
+    }
+
+    /// Switch both directions to the bit rate accepted in a PPS response, once it was sent.
+    ///
+    /// The driver goes back to 106 kbps when the field is lost. Changing the bit rate resets the
+    /// maximum frame delay, so it is set again from the frame timing.
+    pub fn set_bit_rate(bit_rate: BitRate, timing: FrameTiming) -> Result<(), NfcError> {
+        syscalls::command(DRIVER_NUMBER, command_nr::BIT_RATE, bit_rate as usize, 0)?;
+        NfcTag::set_frame_timing(timing)
+    }
+
+    /// Set the maximum frame delay time, in ticks of the frame delay clock.
+    pub fn set_framedelaymax(delay: u32) -> Result<(), NfcError> {
+        syscalls::command(DRIVER_NUMBER, command_nr::FRAMEDELAYMAX, delay as usize, 0)?;
+        Ok(())
+    }
+
+    /// Set the maximum frame delay time from the frame timing announced to the reader.
+    pub fn set_frame_timing(timing: FrameTiming) -> Result<(), NfcError> {
+        NfcTag::set_framedelaymax(timing.frame_delay_max())
+    }
+
+    /// Cancel the reception and transmission in flight, if any.
+    ///
+    /// Their callbacks complete with a cancellation code, reported as `NfcError::Aborted`.
+    pub fn abort() -> Result<(), NfcError> {
+        match syscalls::command(DRIVER_NUMBER, command_nr::ABORT, 0, 0) {
+            // Nothing was in flight.
+            Ok(_)
+            | Err(CommandError {
+                return_code: EALREADY,
+                ..
+            }) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// In loopback mode, the frontend receives the frames it transmits instead of sending them to a
+    /// reader. This is meant for production tests of the antenna and frontend.
+    pub fn set_loopback(enabled: bool) -> Result<(), NfcError> {
+        syscalls::command(DRIVER_NUMBER, command_nr::LOOPBACK, enabled as usize, 0)?;
+        Ok(())
+    }
+
+    /// In loopback mode, transmits a frame and receives its echo, or gives up after the timeout.
+    pub fn echo(
+        tx_buf: &mut [u8],
+        rx_buf: &mut [u8],
+        timeout_delay: Duration<isize>,
+    ) -> Result<RecvOp, NfcError> {
+        let operation = NfcOperation::new();
+        let mut callback = RecvCallback::new(&operation);
+        let max_frame_size = rx_buf.len();
+        // The reception is issued first, so that the echo can't be missed.
+        let pending = NfcTag::receive_start(rx_buf, max_frame_size, &mut callback)?;
+        let amount = tx_buf.len();
+        NfcTag::transmit(tx_buf, amount)?;
+        match yieldk_for_with_timeout(|| pending.poll().is_some(), timeout_delay) {
+            Ok(()) => pending.wait(),
+            Err(e) => {
+                pending.abort()?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Block until a reader selects the tag.
+    pub fn selected() -> Result<(), NfcError> {
+        let operation = NfcOperation::new();
+        let mut callback = EventCallback::new(subscribe_nr::SELECT, &operation);
+        let subscription = callback.subscribe()?;
+        operation.start();
+        let outcome = operation.wait();
+        mem::drop(subscription);
+        outcome?;
+        Ok(())
+    }
+
+    /// Same as selected, but gives up after the timeout.
+    pub fn selected_within(timeout_delay: Duration<isize>) -> Result<(), NfcError> {
+        let operation = NfcOperation::new();
+        let mut callback = EventCallback::new(subscribe_nr::SELECT, &operation);
+        let subscription = callback.subscribe()?;
+        operation.start();
+        let result = yieldk_for_with_timeout(|| operation.poll().is_some(), timeout_delay);
+        mem::drop(subscription);
+        result?;
+        operation.wait()?;
+        Ok(())
     }
 
-    /// 1. Share with the driver a buffer.
+    /// 1. Share with the driver the first `max_frame_size` bytes of a buffer.
     /// 2. Subscribe to having a successful receive callback.
     /// 3. Issue the request for reception.
-    pub fn receive(buf: &mut [u8; 256]) -> TockResult<RecvOp> {
-        let result = syscalls::allow(DRIVER_NUMBER, allow_nr::RECEIVE, buf)?;
-        // set callback with 2 arguments, to receive ReturnCode and RX Amount
-        let recv_data = Cell::new(None);
-        let mut callback = |result, amount| {
-            recv_data.set(Some(RecvOp {
-                result_code: result,
-                recv_amount: amount,
-            }))
-        };
-        let subscription = syscalls::subscribe::<callback::Identity2Consumer, _>(
-            DRIVER_NUMBER,
-            subscribe_nr::RECEIVE,
-            &mut callback,
-        )?;
+    ///
+    /// The maximum frame size is the one negotiated with the reader. The returned `recv_amount` is
+    /// the number of bytes written to the buffer.
+    pub fn receive(buf: &mut [u8], max_frame_size: usize) -> Result<RecvOp, NfcError> {
+        let operation = NfcOperation::new();
+        let mut callback = RecvCallback::new(&operation);
+        let pending = NfcTag::receive_start(buf, max_frame_size, &mut callback)?;
+        pending.wait()
+    }
+
+    /// Same as receive, but gives up after the timeout.
+    ///
+    /// The buffer is unshared on timeout, so a frame arriving later is dropped by the driver.
+    pub fn receive_with_timeout(
+        buf: &mut [u8],
+        max_frame_size: usize,
+        timeout_delay: Duration<isize>,
+    ) -> Result<RecvOp, NfcError> {
+        let operation = NfcOperation::new();
+        let mut callback = RecvCallback::new(&operation);
+        let pending = NfcTag::receive_start(buf, max_frame_size, &mut callback)?;
+        yieldk_for_with_timeout(|| pending.poll().is_some(), timeout_delay)?;
+        pending.wait()
+    }
+
+    /// Same as receive, but returns as soon as the request is issued.
+    ///
+    /// The returned `PendingRecv` is polled for completion, which lets the caller service other
+    /// drivers while waiting for a frame from the reader.
+    pub fn receive_start<'a>(
+        buf: &'a mut [u8],
+        max_frame_size: usize,
+        callback: &'a mut RecvCallback,
+    ) -> Result<PendingRecv<'a>, NfcError> {
+        if max_frame_size > buf.len() {
+            return Err(NfcError::BufferTooSmall);
+        }
+        let shared_buffer =
+            syscalls::allow(DRIVER_NUMBER, allow_nr::RECEIVE, &mut buf[..max_frame_size])?;
+        // The callback receives 2 arguments: the ReturnCode and the RX amount.
+        let operation = callback.callback.operation;
+        let subscription = callback.callback.subscribe()?;
         syscalls::command(DRIVER_NUMBER, command_nr::RECEIVE, 0, 0)?;
-        util::yieldk_for(|| recv_data.get().is_some());
-        mem::drop(subscription);
-        mem::drop(result);
-        Ok(recv_data.get().unwrap())
+        operation.start();
+        Ok(PendingRecv {
+            operation,
+            max_frame_size,
+            subscription,
+            shared_buffer,
+        })
+    }
+
+    /// Receives a frame, then transmits the reply built from it.
+    ///
+    /// The builder gets the received frame and writes the reply to the transmit buffer. It returns
+    /// the length of the reply, or None if the frame must not be answered. Frames received with an
+    /// error, for example an invalid CRC, are not given to the builder. The receive buffer is
+    /// unshared before calling the builder, so the driver can't modify the frame while it is read.
+    pub fn transceive<F>(
+        rx_buf: &mut [u8],
+        max_frame_size: usize,
+        tx_buf: &mut [u8],
+        tx_builder: F,
+    ) -> Result<TransceiveOp, NfcError>
+    where
+        F: FnOnce(&[u8], &mut [u8]) -> Option<usize>,
+    {
+        let recv_op = NfcTag::receive(rx_buf, max_frame_size)?;
+        let mut transmit_result = None;
+        if recv_op.result_code == 0 {
+            if let Some(amount) = tx_builder(&rx_buf[..recv_op.recv_amount], tx_buf) {
+                if amount > tx_buf.len() {
+                    return Err(NfcError::BufferTooSmall);
+                }
+                transmit_result = Some(NfcTag::transmit(tx_buf, amount)?);
+            }
+        }
+        Ok(TransceiveOp {
+            recv_op,
+            transmit_result,
+        })
     }
 
     /// 1. Share with the driver a buffer containing the app's reply.
     /// 2. Subscribe to having a successful transmission callback.
     /// 3. Issue the request for transmitting.
-    pub fn transmit(buf: &mut [u8], amount: usize) -> TockResult<usize> {
-        let result = syscalls::allow(DRIVER_NUMBER, allow_nr::TRANSMIT, buf)?;
-        // set callback with 1 argument, to receive ReturnCode
-        let result_code = Cell::new(None);
-        let mut callback = |result| result_code.set(Some(result));
-        let subscription = syscalls::subscribe::<callback::Identity1Consumer, _>(
-            DRIVER_NUMBER,
-            subscribe_nr::TRANSMIT,
-            &mut callback,
-        )?;
+    pub fn transmit(buf: &mut [u8], amount: usize) -> Result<usize, NfcError> {
+        let shared_buffer = syscalls::allow(DRIVER_NUMBER, allow_nr::TRANSMIT, buf)?;
+        // The callback receives 1 argument: the ReturnCode.
+        let operation = NfcOperation::new();
+        let mut callback = EventCallback::new(subscribe_nr::TRANSMIT, &operation);
+        let subscription = callback.subscribe()?;
         syscalls::command(DRIVER_NUMBER, command_nr::TRANSMIT, amount, 0)?;
-        util::yieldk_for(|| result_code.get().is_some());
+        operation.start();
+        let outcome = operation.wait();
         mem::drop(subscription);
-        mem::drop(result);
-        Ok(result_code.get().unwrap())
+        mem::drop(shared_buffer);
+        match outcome? {
+            NfcEvent::Transmitted(result_code) => Ok(result_code),
+            // The subscription only delivers transmission events.
+            _ => Err(NfcError::Kernel(FAIL)),
+        }
+    }
+}
+
+// Callbacks carry the return code of the operation as an unsigned value.
+fn is_aborted(result_code: usize) -> bool {
+    result_code as isize == ECANCEL
+}
+
+// Yields until the condition holds, or returns a timeout error once the delay elapsed.
+fn yieldk_for_with_timeout<F: Fn() -> bool>(
+    cond: F,
+    timeout_delay: Duration<isize>,
+) -> Result<(), NfcError> {
+    let timeout_expired = Cell::new(false);
+    let mut timeout_callback = timer::with_callback(|_, _| {
+        timeout_expired.set(true);
+    });
+    let mut timeout = timeout_callback.init()?;
+    let timeout_alarm = timeout.set_alarm(timeout_delay)?;
+
+    util::yieldk_for(|| cond() || timeout_expired.get());
+
+    // Cleanup alarm callback.
+    match timeout.stop_alarm(timeout_alarm) {
+        Ok(())
+        | Err(TockError::Command(CommandError {
+            return_code: EALREADY,
+            ..
+        })) => (),
+        Err(e) => return Err(e.into()),
+    }
+    if cond() {
+        Ok(())
+    } else {
+        Err(NfcError::Timeout)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RECEIVED: NfcEvent = NfcEvent::Received(RecvOp {
+        result_code: 0,
+        recv_amount: 5,
+    });
+
+    #[test]
+    fn test_frame_timing() {
+        // TA, TB and TC are present, with an FWI of 11.
+        let ats = [0x05, 0x78, 0xB3, 0xB1, 0x00];
+        let timing = FrameTiming::from_ats(&ats, CARRIER_FREQUENCY_HZ);
+        assert_eq!(timing.frame_delay_max(), 4096 << 11);
+        // The same delay, about 620 ms, counted by a 64 MHz CPU clock.
+        let timing = FrameTiming::from_ats(&ats, 64_000_000);
+        assert_eq!(timing.frame_delay_max(), 39_592_251);
+        // Only TB is present.
+        let timing = FrameTiming::from_ats(&[0x03, 0x28, 0x20], CARRIER_FREQUENCY_HZ);
+        assert_eq!(timing.frame_delay_max(), 4096 << 2);
+        // Without TB, the FWI defaults to 4.
+        let timing = FrameTiming::from_ats(&[0x02, 0x58], CARRIER_FREQUENCY_HZ);
+        assert_eq!(timing.frame_delay_max(), 4096 << 4);
+        let timing = FrameTiming::from_ats(&[0x05, 0x78, 0xB3, 0xF1, 0x00], CARRIER_FREQUENCY_HZ);
+        assert_eq!(timing.frame_delay_max(), 4096 << 14);
+    }
+
+    #[test]
+    fn test_decode_event() {
+        assert_eq!(
+            NfcEvent::decode(subscribe_nr::TRANSMIT, 0, 0),
+            NfcEvent::Transmitted(0)
+        );
+        assert_eq!(NfcEvent::decode(subscribe_nr::RECEIVE, 0, 5), RECEIVED);
+        assert_eq!(
+            NfcEvent::decode(subscribe_nr::SELECT, 0, 0),
+            NfcEvent::Selected
+        );
+        assert_eq!(
+            NfcEvent::decode(subscribe_nr::FIELD, 0, 0),
+            NfcEvent::Field(FieldState::Off)
+        );
+        assert_eq!(
+            NfcEvent::decode(subscribe_nr::FIELD, 1, 0),
+            NfcEvent::Field(FieldState::On)
+        );
+    }
+
+    #[test]
+    fn test_operation_completes_once() {
+        let operation = NfcOperation::new();
+        // Events before the start belong to a previous operation.
+        operation.handle(RECEIVED);
+        assert_eq!(operation.poll(), None);
+        operation.start();
+        assert!(operation.is_pending());
+        operation.handle(NfcEvent::Field(FieldState::On));
+        assert_eq!(operation.poll(), None);
+        operation.handle(RECEIVED);
+        assert!(!operation.is_pending());
+        operation.handle(NfcEvent::Field(FieldState::Off));
+        assert_eq!(operation.poll(), Some(Ok(RECEIVED)));
+    }
+
+    #[test]
+    fn test_operation_field_lost() {
+        let operation = NfcOperation::new();
+        operation.start();
+        operation.handle(NfcEvent::Field(FieldState::Off));
+        assert_eq!(operation.poll(), Some(Err(NfcError::FieldLost)));
+        // The operation can start again.
+        operation.start();
+        assert_eq!(operation.poll(), None);
+    }
+
+    #[test]
+    fn test_operation_aborted() {
+        let operation = NfcOperation::new();
+        operation.start();
+        operation.handle(NfcEvent::Transmitted(ECANCEL as usize));
+        assert_eq!(operation.poll(), Some(Err(NfcError::Aborted)));
+        operation.start();
+        operation.handle(NfcEvent::Received(RecvOp {
+            result_code: ECANCEL as usize,
+            recv_amount: 0,
+        }));
+        assert_eq!(operation.poll(), Some(Err(NfcError::Aborted)));
+        // Other errors are reported with the event.
+        let crc_error = NfcEvent::Received(RecvOp {
+            result_code: FAIL as usize,
+            recv_amount: 3,
+        });
+        operation.start();
+        operation.handle(crc_error);
+        assert_eq!(operation.poll(), Some(Ok(crc_error)));
     }
 }
@@ -0,0 +1,82 @@
+use crate::util;
+use core::cell::Cell;
+use libtock_core::result::SUCCESS;
+use libtock_core::{callback, syscalls};
+
+// Driver of a secure element on the I2C bus, such as the ATECC608 or the SE050. Private keys are
+// generated inside the secure element, in numbered slots, and never leave it.
+const DRIVER_NUMBER: usize = 0x40004;
+
+mod command_nr {
+    pub const CHECK: usize = 0;
+    pub const GENERATE_KEY: usize = 1;
+    pub const PUBLIC_KEY: usize = 2;
+    pub const SIGN: usize = 3;
+}
+
+mod subscribe_nr {
+    pub const DONE: usize = 0;
+}
+
+mod allow_nr {
+    // The digest to sign, only read by the kernel.
+    pub const DIGEST: usize = 0;
+    // The public key or signature, written by the kernel.
+    pub const OUTPUT: usize = 1;
+}
+
+pub fn is_available() -> bool {
+    syscalls::command(DRIVER_NUMBER, command_nr::CHECK, 0, 0).is_ok()
+}
+
+// Generates a new P-256 key pair in the slot, replacing its previous key. The public key is
+// written as its big endian affine coordinates x and y.
+pub fn generate_key(slot: usize, public_key: &mut [u8; 64]) -> bool {
+    run(command_nr::GENERATE_KEY, slot, None, public_key)
+}
+
+// Writes the public key of the slot, encoded as for generate_key. Fails if the slot is empty.
+pub fn public_key(slot: usize, public_key: &mut [u8; 64]) -> bool {
+    run(command_nr::PUBLIC_KEY, slot, None, public_key)
+}
+
+// Signs the SHA-256 digest with the private key of the slot. The signature is written as its big
+// endian integers r and s.
+pub fn sign(slot: usize, digest: &[u8; 32], signature: &mut [u8; 64]) -> bool {
+    let mut digest = *digest;
+    run(command_nr::SIGN, slot, Some(&mut digest), signature)
+}
+
+// Runs a command on a slot until its completion. Returns whether the kernel reported a success.
+fn run(command: usize, slot: usize, digest: Option<&mut [u8]>, output: &mut [u8]) -> bool {
+    let _digest_share = match digest {
+        Some(digest) => match syscalls::allow(DRIVER_NUMBER, allow_nr::DIGEST, digest) {
+            Ok(share) => Some(share),
+            Err(_) => return false,
+        },
+        None => None,
+    };
+    let output_share = syscalls::allow(DRIVER_NUMBER, allow_nr::OUTPUT, output);
+    if output_share.is_err() {
+        return false;
+    }
+
+    let return_code = Cell::new(None);
+    let mut done_alarm = |code: usize| return_code.set(Some(code as isize));
+    let subscription = syscalls::subscribe::<callback::Identity1Consumer, _>(
+        DRIVER_NUMBER,
+        subscribe_nr::DONE,
+        &mut done_alarm,
+    );
+    if subscription.is_err() {
+        return false;
+    }
+
+    let result_code = syscalls::command(DRIVER_NUMBER, command, slot, 0);
+    if result_code.is_err() {
+        return false;
+    }
+
+    util::yieldk_for(|| return_code.get().is_some());
+    return_code.get() == Some(SUCCESS)
+}
@@ -0,0 +1,76 @@
+use crate::util;
+use core::cell::Cell;
+use libtock_core::result::SUCCESS;
+use libtock_core::{callback, syscalls};
+
+// Driver of the SHA-256 engine of the SoC, which hashes whole messages with DMA instead of one
+// block per command. The engine runs one hash at a time, so a hash is computed within one call.
+const DRIVER_NUMBER: usize = 0x40005;
+
+mod command_nr {
+    pub const CHECK: usize = 0;
+    pub const INIT: usize = 1;
+    // The argument is the number of bytes to hash from the shared data.
+    pub const UPDATE: usize = 2;
+    pub const FINISH: usize = 3;
+}
+
+mod subscribe_nr {
+    pub const DONE: usize = 0;
+}
+
+mod allow_nr {
+    // The next chunk of the message, only read by the kernel.
+    pub const DATA: usize = 0;
+    // The digest, written by the kernel.
+    pub const DIGEST: usize = 1;
+}
+
+// The message is copied to the kernel in chunks of this size, because shared buffers must be
+// mutable.
+const CHUNK_SIZE: usize = 256;
+
+pub fn is_available() -> bool {
+    syscalls::command(DRIVER_NUMBER, command_nr::CHECK, 0, 0).is_ok()
+}
+
+// Writes the SHA-256 of the concatenation of the message parts.
+pub fn sha256(message_parts: &[&[u8]], digest: &mut [u8; 32]) -> bool {
+    if !run(command_nr::INIT, 0) {
+        return false;
+    }
+    let mut chunk = [0; CHUNK_SIZE];
+    for part in message_parts {
+        for piece in part.chunks(CHUNK_SIZE) {
+            chunk[..piece.len()].copy_from_slice(piece);
+            let data_share = syscalls::allow(DRIVER_NUMBER, allow_nr::DATA, &mut chunk);
+            if data_share.is_err() || !run(command_nr::UPDATE, piece.len()) {
+                return false;
+            }
+        }
+    }
+    let digest_share = syscalls::allow(DRIVER_NUMBER, allow_nr::DIGEST, digest);
+    digest_share.is_ok() && run(command_nr::FINISH, 0)
+}
+
+// Runs a command until its completion. Returns whether the kernel reported a success.
+fn run(command: usize, argument: usize) -> bool {
+    let return_code = Cell::new(None);
+    let mut done_alarm = |code: usize| return_code.set(Some(code as isize));
+    let subscription = syscalls::subscribe::<callback::Identity1Consumer, _>(
+        DRIVER_NUMBER,
+        subscribe_nr::DONE,
+        &mut done_alarm,
+    );
+    if subscription.is_err() {
+        return false;
+    }
+
+    let result_code = syscalls::command(DRIVER_NUMBER, command, argument, 0);
+    if result_code.is_err() {
+        return false;
+    }
+
+    util::yieldk_for(|| return_code.get().is_some());
+    return_code.get() == Some(SUCCESS)
+}
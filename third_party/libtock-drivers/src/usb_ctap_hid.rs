@@ -186,7 +186,36 @@ pub fn recv_with_timeout(
     )
     .unwrap();
 
-    let result = recv_with_timeout_detail(buf, timeout_delay);
+    let result = recv_with_timeout_detail(buf, timeout_delay, || false);
+
+    #[cfg(feature = "verbose_usb")]
+    {
+        if let Some(SendOrRecvStatus::Received) = result {
+            writeln!(Console::new(), "Received packet = {:02x?}", buf as &[u8]).unwrap();
+        }
+    }
+
+    result
+}
+
+// Same as recv_with_timeout, but also returns None as soon as the wake-up condition holds.
+// The condition is checked whenever a callback is executed, so that callbacks of other drivers
+// (e.g. NFC or buttons) wake the app up from the same yield.
+#[allow(clippy::let_and_return)]
+pub fn recv_with_timeout_or_wake_up<W: Fn() -> bool>(
+    buf: &mut [u8; 64],
+    timeout_delay: Duration<isize>,
+    wake_up: W,
+) -> Option<SendOrRecvStatus> {
+    #[cfg(feature = "verbose_usb")]
+    writeln!(
+        Console::new(),
+        "Receiving packet with timeout of {}ms or until woken up",
+        timeout_delay.ms(),
+    )
+    .unwrap();
+
+    let result = recv_with_timeout_detail(buf, timeout_delay, wake_up);
 
     #[cfg(feature = "verbose_usb")]
     {
@@ -226,9 +255,10 @@ pub fn send_or_recv_with_timeout(
     result
 }
 
-fn recv_with_timeout_detail(
+fn recv_with_timeout_detail<W: Fn() -> bool>(
     buf: &mut [u8; 64],
     timeout_delay: Duration<isize>,
+    wake_up: W,
 ) -> Option<SendOrRecvStatus> {
     let result = syscalls::allow(DRIVER_NUMBER, allow_nr::RECEIVE, buf);
     if result.is_err() {
@@ -273,7 +303,7 @@ fn recv_with_timeout_detail(
         return Some(SendOrRecvStatus::Error);
     }
 
-    util::yieldk_for(|| status.get().is_some() || timeout_expired.get());
+    util::yieldk_for(|| status.get().is_some() || timeout_expired.get() || wake_up());
 
     // Cleanup alarm callback.
     match timeout.stop_alarm(timeout_alarm) {
@@ -302,7 +332,11 @@ fn recv_with_timeout_detail(
     // Cancel USB transaction if necessary.
     if status.get().is_none() {
         #[cfg(feature = "verbose_usb")]
-        writeln!(Console::new(), "Cancelling USB receive due to timeout").unwrap();
+        writeln!(
+            Console::new(),
+            "Cancelling USB receive due to timeout or wake-up"
+        )
+        .unwrap();
         let result_code =
             unsafe { syscalls::raw::command(DRIVER_NUMBER, command_nr::CANCEL, 0, 0) };
         match result_code {
@@ -21,6 +21,8 @@ from __future__ import print_function
 import argparse
 import getpass
 import datetime
+import hashlib
+import hmac
 import sys
 import uuid
 
@@ -30,13 +32,31 @@ from tqdm.auto import tqdm
 from cryptography import x509
 from cryptography.hazmat.primitives import serialization
 from cryptography.hazmat.primitives.asymmetric import ec
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
 
+from fido2 import cbor
 from fido2 import ctap
 from fido2 import ctap2
 from fido2 import hid
 
 OPENSK_VID_PID = (0x1915, 0x521F)
 OPENSK_VENDOR_CONFIGURE = 0x40
+OPENSK_VENDOR_PROVISION = 0x43
+OPENSK_VENDOR_LOCK_PROVISIONING = 0x44
+OPENSK_VENDOR_ROTATE_MASTER_KEYS = 0x46
+OPENSK_VENDOR_SELF_TEST = 0x47
+OPENSK_VENDOR_BENCHMARK = 0x48
+OPENSK_VENDOR_COMPACT = 0x49
+OPENSK_VENDOR_WEAR = 0x4A
+OPENSK_VENDOR_BACKUP = 0x4B
+OPENSK_VENDOR_RESTORE = 0x4C
+OPENSK_VENDOR_AUDIT_LOG = 0x4D
+OPENSK_VENDOR_DIAGNOSTICS = 0x4E
+CTAP_CLIENT_PIN = 0x06
+# The credential management permission of PIN tokens.
+PERMISSION_CREDENTIAL_MANAGEMENT = 0x04
+# Number of credentials in each restore command, as in each backup response.
+BACKUP_PAGE_LENGTH = 4
 
 
 def fatal(msg):
@@ -86,13 +106,296 @@ def get_private_key(data, password=None):
     return get_private_key(data, password=password.encode(sys.stdin.encoding))
 
 
+def provision(authenticator, provision_data, lock):
+  try:
+    if provision_data is not None:
+      result = authenticator.send_cbor(
+          OPENSK_VENDOR_PROVISION,
+          data=provision_data,
+      )
+    if lock:
+      if provision_data is not None:
+        info("Please touch the device again to lock provisioning...")
+      result = authenticator.send_cbor(OPENSK_VENDOR_LOCK_PROVISIONING)
+    info("AAGUID: {}".format(uuid.UUID(bytes=result[1])))
+    info("Private Key: {}".format("Present" if result[2] else "Missing"))
+    info("Certificate chain length: {}".format(result[3]))
+    if 5 in result:
+      pub_key = ec.EllipticCurvePublicNumbers(
+          int.from_bytes(result[5][-2], byteorder="big"),
+          int.from_bytes(result[5][-3], byteorder="big"),
+          ec.SECP256R1()).public_key()
+      info("Generated attestation public key, to be certified:\n{}".format(
+          pub_key.public_bytes(
+              serialization.Encoding.PEM,
+              serialization.PublicFormat.SubjectPublicKeyInfo).decode()))
+    if result[4]:
+      info("Provisioning is now locked!")
+  except ctap.CtapError as ex:
+    if ex.code.value == ctap.CtapError.ERR.INVALID_COMMAND:
+      error("Failed to provision OpenSK (unsupported command).")
+    elif ex.code.value == ctap.CtapError.ERR.NOT_ALLOWED:
+      error("Failed to provision OpenSK (provisioning is locked).")
+    elif ex.code.value == 0xF2:  # VENDOR_INTERNAL_ERROR
+      error(("Failed to lock OpenSK provisioning (certificate and private key "
+             "must be both programmed or both missing)."))
+    elif ex.code.value == ctap.CtapError.ERR.INVALID_PARAMETER:
+      error("Failed to provision OpenSK (invalid key or certificate chain).")
+    else:
+      error("Failed to provision OpenSK (unknown error: {}".format(ex))
+
+
+def rotate_master_keys(authenticator):
+  try:
+    result = authenticator.send_cbor(OPENSK_VENDOR_ROTATE_MASTER_KEYS)
+    info("New credentials use master key generation {}.".format(result[1]))
+  except ctap.CtapError as ex:
+    if ex.code.value == ctap.CtapError.ERR.INVALID_COMMAND:
+      error("Failed to rotate master keys (unsupported command).")
+    elif ex.code.value == ctap.CtapError.ERR.KEY_STORE_FULL:
+      error(("Failed to rotate master keys (all generations are used until "
+             "the next reset)."))
+    else:
+      error("Failed to rotate master keys (unknown error: {}".format(ex))
+
+
+def get_passphrase(confirm):
+  if not sys.stdin.isatty():
+    return sys.stdin.readline().rstrip().encode(sys.stdin.encoding)
+  passphrase = getpass.getpass(prompt="Backup passphrase: ")
+  if confirm and getpass.getpass(prompt="Confirm passphrase: ") != passphrase:
+    fatal("Passphrases don't match.")
+  return passphrase.encode(sys.stdin.encoding)
+
+
+def get_pin(authenticator):
+  if not authenticator.get_info().options.get("clientPin"):
+    return None
+  if not sys.stdin.isatty():
+    return sys.stdin.readline().rstrip()
+  return getpass.getpass(prompt="PIN: ")
+
+
+def get_pin_uv_auth_token(authenticator, pin):
+  # PIN protocol one, with the key agreement and shared secret of CTAP 2.1.
+  result = authenticator.send_cbor(CTAP_CLIENT_PIN, data={1: 1, 2: 0x02})
+  peer_key = ec.EllipticCurvePublicNumbers(
+      int.from_bytes(result[1][-2], "big"), int.from_bytes(result[1][-3], "big"),
+      ec.SECP256R1()).public_key()
+  private_key = ec.generate_private_key(ec.SECP256R1())
+  shared_secret = hashlib.sha256(private_key.exchange(ec.ECDH(),
+                                                      peer_key)).digest()
+  public_numbers = private_key.public_key().public_numbers()
+  key_agreement = {
+      1: 2,
+      3: -25,
+      -1: 1,
+      -2: public_numbers.x.to_bytes(32, "big"),
+      -3: public_numbers.y.to_bytes(32, "big"),
+  }
+  cipher = Cipher(algorithms.AES(shared_secret), modes.CBC(bytes(16)))
+  encryptor = cipher.encryptor()
+  pin_hash = hashlib.sha256(pin.encode("utf-8")).digest()[:16]
+  pin_hash_enc = encryptor.update(pin_hash) + encryptor.finalize()
+  result = authenticator.send_cbor(
+      CTAP_CLIENT_PIN,
+      data={
+          1: 1,
+          2: 0x09,
+          3: key_agreement,
+          6: pin_hash_enc,
+          9: PERMISSION_CREDENTIAL_MANAGEMENT,
+      },
+  )
+  decryptor = cipher.decryptor()
+  return decryptor.update(result[2]) + decryptor.finalize()
+
+
+def add_pin_uv_auth_param(authenticator, pin, command, message, data,
+                          protocol_key):
+  # The message is 32 bytes 0xFF, the command byte and the authorized
+  # parameters.
+  if pin is None:
+    return
+  token = get_pin_uv_auth_token(authenticator, pin)
+  message = b"\xff" * 32 + bytes([command]) + message
+  data[protocol_key] = 1
+  data[protocol_key + 1] = hmac.new(token, message,
+                                    hashlib.sha256).digest()[:16]
+
+
+def backup_error(action, ex):
+  if ex.code.value == ctap.CtapError.ERR.INVALID_COMMAND:
+    error("Failed to {} credentials (unsupported command).".format(action))
+  elif ex.code.value == ctap.CtapError.ERR.PIN_POLICY_VIOLATION:
+    error(("Failed to {} credentials (the passphrase needs at least 14 "
+           "bytes).").format(action))
+  elif ex.code.value == ctap.CtapError.ERR.INTEGRITY_FAILURE:
+    error(("Failed to {} credentials (wrong passphrase or corrupted "
+           "backup).").format(action))
+  elif ex.code.value == ctap.CtapError.ERR.KEY_STORE_FULL:
+    error("Failed to {} credentials (no space left).".format(action))
+  elif ex.code.value in (ctap.CtapError.ERR.PIN_INVALID,
+                         ctap.CtapError.ERR.PIN_AUTH_INVALID):
+    error("Failed to {} credentials (wrong PIN).".format(action))
+  else:
+    error("Failed to {} credentials (unknown error: {}".format(action, ex))
+
+
+def backup(authenticator, backup_file, passphrase, pin):
+  try:
+    data = {1: passphrase}
+    add_pin_uv_auth_param(authenticator, pin, OPENSK_VENDOR_BACKUP, passphrase,
+                          data, 2)
+    result = authenticator.send_cbor(OPENSK_VENDOR_BACKUP, data=data)
+    header, records, total_credentials = result[1], result[2], result[3]
+    while len(records) < total_credentials:
+      result = authenticator.send_cbor(OPENSK_VENDOR_BACKUP, data={})
+      records.extend(result[2])
+    backup_file.write(cbor.encode({1: header, 2: records}))
+    info("Exported {} credentials.".format(len(records)))
+  except ctap.CtapError as ex:
+    backup_error("export", ex)
+
+
+def restore(authenticator, backup_data, passphrase, pin):
+  header, records = backup_data[1], backup_data[2]
+  try:
+    restored = 0
+    for start in range(0, max(len(records), 1), BACKUP_PAGE_LENGTH):
+      data = {3: records[start:start + BACKUP_PAGE_LENGTH]}
+      if start == 0:
+        data[1] = passphrase
+        data[2] = header
+        add_pin_uv_auth_param(authenticator, pin, OPENSK_VENDOR_RESTORE,
+                              passphrase + header, data, 4)
+      result = authenticator.send_cbor(OPENSK_VENDOR_RESTORE, data=data)
+      restored += result[1]
+    info("Imported {} credentials.".format(restored))
+  except ctap.CtapError as ex:
+    backup_error("import", ex)
+
+
+def self_test(authenticator):
+  try:
+    result = authenticator.send_cbor(OPENSK_VENDOR_SELF_TEST)
+    if result[1]:
+      info("All self-tests passed.")
+    else:
+      error(("Self-test {} failed. The device refuses all other commands "
+             "until the self-tests pass.").format(result[2]))
+  except ctap.CtapError as ex:
+    if ex.code.value == ctap.CtapError.ERR.INVALID_COMMAND:
+      error("Failed to run the self-tests (unsupported command).")
+    else:
+      error("Failed to run the self-tests (unknown error: {}".format(ex))
+
+
+def benchmark(authenticator):
+  names = [
+      "SHA-256 (1 KiB)",
+      "HMAC-SHA256 (1 KiB)",
+      "AES-256-GCM (1 KiB)",
+      "ECDSA P-256 sign",
+      "ECDH P-256",
+      "Store write (32 bytes)",
+      "Store page compaction",
+  ]
+  try:
+    result = authenticator.send_cbor(OPENSK_VENDOR_BENCHMARK)
+    for number, name in enumerate(names, start=1):
+      info("{}: {} us".format(name, result[number]))
+  except ctap.CtapError as ex:
+    if ex.code.value == ctap.CtapError.ERR.INVALID_COMMAND:
+      error("Failed to run the benchmark (unsupported command).")
+    else:
+      error("Failed to run the benchmark (unknown error: {}".format(ex))
+
+
+def compact(authenticator):
+  try:
+    result = authenticator.send_cbor(OPENSK_VENDOR_COMPACT)
+    info("Compacted {} pages of the store.".format(result[1]))
+  except ctap.CtapError as ex:
+    if ex.code.value == ctap.CtapError.ERR.INVALID_COMMAND:
+      error("Failed to compact the store (unsupported command).")
+    else:
+      error("Failed to compact the store (unknown error: {}".format(ex))
+
+
+def wear(authenticator):
+  try:
+    result = authenticator.send_cbor(OPENSK_VENDOR_WEAR)
+    info("Erase cycles: {} of {} ({:.2f}% of the rated endurance).".format(
+        result[1], result[2], 100 * result[1] / result[2]))
+    info("Most erased page: {} of {} cycles.".format(
+        max(result[4]), result[3]))
+    info("Bytes written: {}".format(result[5]))
+  except ctap.CtapError as ex:
+    if ex.code.value == ctap.CtapError.ERR.INVALID_COMMAND:
+      error("Failed to read the flash wear (unsupported command).")
+    else:
+      error("Failed to read the flash wear (unknown error: {}".format(ex))
+
+
+def audit_log(authenticator):
+  events = {1: "Backup", 2: "Restore"}
+  try:
+    result = authenticator.send_cbor(OPENSK_VENDOR_AUDIT_LOG)
+    if not result[1]:
+      info("The audit log is empty.")
+    for record in result[1]:
+      info("{} of {} credentials.".format(
+          events.get(record[1], "Unknown event"), record[2]))
+  except ctap.CtapError as ex:
+    if ex.code.value == ctap.CtapError.ERR.INVALID_COMMAND:
+      error("Failed to read the audit log (unsupported command).")
+    else:
+      error("Failed to read the audit log (unknown error: {}".format(ex))
+
+
+def diagnostics(authenticator):
+  try:
+    result = authenticator.send_cbor(OPENSK_VENDOR_DIAGNOSTICS)
+    info("Quarantined entries: {}".format(result[1]))
+  except ctap.CtapError as ex:
+    if ex.code.value == ctap.CtapError.ERR.INVALID_COMMAND:
+      error("Failed to read the diagnostics (unsupported command).")
+    else:
+      error("Failed to read the diagnostics (unknown error: {}".format(ex))
+
+
 def main(args):
   colorama.init()
-  # We need either both the certificate and the key or none
-  if bool(args.priv_key) ^ bool(args.certificate):
+  # We need either both the certificate and the key or none, unless the secure
+  # element holds the key.
+  if bool(args.priv_key) ^ bool(args.certificate) and not args.provision:
     fatal("Certificate and private key must be set together or both omitted.")
+  if args.generate_priv_key and (args.priv_key or not args.provision):
+    fatal("Only provisioning can generate the private key, instead of "
+          "receiving it.")
+  if args.intermediates and not args.certificate:
+    fatal("Intermediate certificates need the attestation certificate.")
+  if (args.intermediates or args.aaguid) and not args.provision:
+    fatal("AAGUID and intermediate certificates can only be provisioned.")
+  if args.backup and args.restore:
+    fatal("A backup and a restore can't be done at the same time.")
+  passphrase = None
+  backup_data = None
+  if args.backup:
+    passphrase = get_passphrase(confirm=True)
+  if args.restore:
+    backup_data = cbor.decode(args.restore.read())
+    passphrase = get_passphrase(confirm=False)
 
   cbor_data = {1: args.lock}
+  provision_data = {}
+
+  if args.aaguid:
+    provision_data[1] = args.aaguid.bytes
+
+  if args.generate_priv_key:
+    provision_data[4] = True
 
   if args.priv_key:
     cbor_data[1] = args.lock
@@ -123,23 +426,84 @@ def main(args):
       fatal("Certificate public doesn't match with the private key.")
     info("Certificate is valid.")
 
-    cbor_data[2] = {
-        1:
-            cert.public_bytes(serialization.Encoding.DER),
-        2:
-            priv_key.private_numbers().private_value.to_bytes(
-                length=32, byteorder='big', signed=False)
-    }
+    cert_der = cert.public_bytes(serialization.Encoding.DER)
+    priv_key_bytes = priv_key.private_numbers().private_value.to_bytes(
+        length=32, byteorder='big', signed=False)
+    if args.provision:
+      chain = [cert_der]
+      for intermediate in args.intermediates:
+        chain.append(
+            x509.load_pem_x509_certificate(intermediate.read()).public_bytes(
+                serialization.Encoding.DER))
+      provision_data[2] = priv_key_bytes
+      provision_data[3] = chain
+    else:
+      cbor_data[2] = {1: cert_der, 2: priv_key_bytes}
+  elif args.certificate:
+    # The certificate of a key generated by the secure element.
+    chain = [
+        x509.load_pem_x509_certificate(certificate.read()).public_bytes(
+            serialization.Encoding.DER)
+        for certificate in [args.certificate] + args.intermediates
+    ]
+    provision_data[3] = chain
+
+  if args.default_cred_protect:
+    cbor_data[3] = args.default_cred_protect
+
+  if args.touch_timeout is not None:
+    cbor_data[4] = args.touch_timeout
+
+  if args.touch_duration is not None:
+    cbor_data[5] = args.touch_duration
+
+  if args.second_touch is not None:
+    cbor_data[6] = args.second_touch == "on"
+
+  if args.force_pin_change:
+    cbor_data[7] = True
 
   for authenticator in tqdm(get_opensk_devices(args.batch)):
     # If the device supports it, wink to show which device
     # we're going to program.
     if authenticator.device.capabilities & hid.CAPABILITY.WINK:
       authenticator.device.wink()
+    # GetInfo is refused after a failed self-test.
+    if args.self_test:
+      self_test(authenticator)
+      continue
+    if args.compact:
+      compact(authenticator)
+      continue
+    if args.wear:
+      wear(authenticator)
+      continue
+    if args.audit_log:
+      audit_log(authenticator)
+      continue
+    if args.diagnostics:
+      diagnostics(authenticator)
+      continue
     aaguid = uuid.UUID(bytes=authenticator.get_info().aaguid)
     info("Programming OpenSK device AAGUID {} ({}).".format(
         aaguid, authenticator.device))
     info("Please touch the device to confirm...")
+    if args.benchmark:
+      benchmark(authenticator)
+      continue
+    if args.rotate_master_keys:
+      rotate_master_keys(authenticator)
+      continue
+    if args.backup:
+      backup(authenticator, args.backup, passphrase, get_pin(authenticator))
+      continue
+    if args.restore:
+      restore(authenticator, backup_data, passphrase, get_pin(authenticator))
+      continue
+    if args.provision or args.lock_provisioning:
+      provision(authenticator, provision_data if args.provision else None,
+                args.lock_provisioning)
+      continue
     try:
       result = authenticator.send_cbor(
           OPENSK_VENDOR_CONFIGURE,
@@ -155,6 +519,8 @@ def main(args):
       elif ex.code.value == 0xF2:  # VENDOR_INTERNAL_ERROR
         error(("Failed to configure OpenSK (lockdown conditions not met "
                "or hardware error)."))
+      elif ex.code.value == ctap.CtapError.ERR.PIN_NOT_SET:
+        error("Failed to configure OpenSK (forcing a PIN change needs a PIN).")
       elif ex.code.value == ctap.CtapError.ERR.INVALID_PARAMETER:
         error(
             ("Failed to configure OpenSK (device is partially programmed but "
@@ -201,4 +567,168 @@ if __name__ == "__main__":
             "This command can fail if the certificate or the private key "
             "haven't been both programmed yet."),
   )
+  parser.add_argument(
+      "--default-cred-protect",
+      type=int,
+      choices=[1, 2, 3],
+      default=None,
+      dest="default_cred_protect",
+      help=("Sets the credProtect level applied to new credentials when the "
+            "relying party doesn't request a stronger one."),
+  )
+  parser.add_argument(
+      "--touch-timeout",
+      type=int,
+      default=None,
+      metavar="MILLISECONDS",
+      dest="touch_timeout",
+      help=("Sets how long the device waits for a touch when it asks for "
+            "user presence."),
+  )
+  parser.add_argument(
+      "--touch-duration",
+      type=int,
+      default=None,
+      metavar="MILLISECONDS",
+      dest="touch_duration",
+      help=("Sets how long the button must be held for a touch to count. "
+            "Capacitive buttons may need a longer touch than mechanical "
+            "ones."),
+  )
+  parser.add_argument(
+      "--second-touch",
+      choices=["on", "off"],
+      default=None,
+      dest="second_touch",
+      help="Sets whether a reset needs a second touch to be confirmed.",
+  )
+  parser.add_argument(
+      "--force-pin-change",
+      default=False,
+      action="store_true",
+      dest="force_pin_change",
+      help=("Requires a change of the current PIN before it can be used "
+            "again, e.g. when shipping devices with a default PIN."),
+  )
+  parser.add_argument(
+      "--provision",
+      default=False,
+      action="store_true",
+      dest="provision",
+      help=("Provisions the attestation material instead of configuring it. "
+            "Provisioning overwrites the current material, until it is "
+            "locked."),
+  )
+  parser.add_argument(
+      "--generate-private-key",
+      default=False,
+      action="store_true",
+      dest="generate_priv_key",
+      help=("Generates the attestation private key inside the secure element "
+            "and prints its public key. Provision its certificate afterwards, "
+            "without private key."),
+  )
+  parser.add_argument(
+      "--aaguid",
+      type=uuid.UUID,
+      default=None,
+      metavar="UUID",
+      dest="aaguid",
+      help="Provisions the AAGUID of the OpenSK authenticator.",
+  )
+  parser.add_argument(
+      "--intermediate-certificate",
+      type=argparse.FileType("rb"),
+      default=[],
+      action="append",
+      metavar="PEM_FILE",
+      dest="intermediates",
+      help=("PEM file containing an intermediate certificate of the chain. "
+            "Repeat it in order, starting with the issuer of the "
+            "attestation certificate."),
+  )
+  parser.add_argument(
+      "--lock-provisioning",
+      default=False,
+      action="store_true",
+      dest="lock_provisioning",
+      help=("Permanently prevents provisioning new attestation material. "
+            "This command fails if only one of the certificate and the "
+            "private key is programmed."),
+  )
+  parser.add_argument(
+      "--rotate-master-keys",
+      default=False,
+      action="store_true",
+      dest="rotate_master_keys",
+      help=("Wraps new non-resident credentials with a new master key. "
+            "Existing credentials remain usable."),
+  )
+  parser.add_argument(
+      "--backup",
+      type=argparse.FileType("wb"),
+      default=None,
+      metavar="BACKUP_FILE",
+      dest="backup",
+      help=("Exports the resident credentials to the file, encrypted under a "
+            "passphrase. The device needs a touch to confirm, and its PIN if "
+            "one is set."),
+  )
+  parser.add_argument(
+      "--restore",
+      type=argparse.FileType("rb"),
+      default=None,
+      metavar="BACKUP_FILE",
+      dest="restore",
+      help=("Imports the resident credentials of a backup file. The device "
+            "needs a touch to confirm, and its PIN if one is set."),
+  )
+  parser.add_argument(
+      "--audit-log",
+      default=False,
+      action="store_true",
+      dest="audit_log",
+      help=("Lists the backups and restores of resident credentials, oldest "
+            "first."),
+  )
+  parser.add_argument(
+      "--benchmark",
+      default=False,
+      action="store_true",
+      dest="benchmark",
+      help=("Times cryptographic operations and store writes on the device. "
+            "This compacts a page of the store."),
+  )
+  parser.add_argument(
+      "--compact",
+      default=False,
+      action="store_true",
+      dest="compact",
+      help=("Compacts the store ahead of time, so that the next commands "
+            "don't wait for it. The device also does this when idle."),
+  )
+  parser.add_argument(
+      "--wear",
+      default=False,
+      action="store_true",
+      dest="wear",
+      help=("Reports how many erase cycles the flash of the store has used, "
+            "compared to its rated endurance."),
+  )
+  parser.add_argument(
+      "--diagnostics",
+      default=False,
+      action="store_true",
+      dest="diagnostics",
+      help=("Reports how many corrupted credentials the device set aside "
+            "when it booted."),
+  )
+  parser.add_argument(
+      "--self-test",
+      default=False,
+      action="store_true",
+      dest="self_test",
+      help=("Runs the cryptographic self-tests of the device, and reports "
+            "the first test that failed."),
+  )
   main(parser.parse_args())